@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+
+use ::bitcask::storage::bitcask::entry::{DataFileEntry, DataFileValue, Decode, Encode};
+
+/// A data file entry that is serialized with `bincode`, which was the on-disk layout before the
+/// layout was documented.
+#[derive(Serialize, Deserialize)]
+struct BincodeEntry {
+    tstamp: i64,
+    key: Bytes,
+    value: Option<Bytes>,
+    expiry: Option<i64>,
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut g = c.benchmark_group("decode");
+    for val_size in [64, 1024, 16 * 1024] {
+        let entry = DataFileEntry {
+            tstamp: 1,
+            key: vec![b'k'; 64].into(),
+            value: Some(vec![b'v'; val_size].into()),
+            expiry: Some(2),
+        };
+        let mut layout = Vec::new();
+        entry.write_to(&mut layout).unwrap();
+        let bincode = bincode::serialize(&BincodeEntry {
+            tstamp: entry.tstamp,
+            key: entry.key.clone(),
+            value: entry.value.clone(),
+            expiry: entry.expiry,
+        })
+        .unwrap();
+
+        g.throughput(Throughput::Bytes(entry.encoded_len()));
+        g.bench_with_input(BenchmarkId::new("layout", val_size), &layout, |b, buf| {
+            b.iter(|| DataFileEntry::read_from(&mut black_box(buf.as_slice())).unwrap())
+        });
+        g.bench_with_input(
+            BenchmarkId::new("layout_value", val_size),
+            &layout,
+            |b, buf| b.iter(|| DataFileValue::read_from(&mut black_box(buf.as_slice())).unwrap()),
+        );
+        g.bench_with_input(BenchmarkId::new("bincode", val_size), &bincode, |b, buf| {
+            b.iter(|| bincode::deserialize::<BincodeEntry>(black_box(buf)).unwrap())
+        });
+    }
+    g.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(5));
+    targets = bench_decode,
+);
+criterion_main!(benches);
@@ -0,0 +1,266 @@
+//! Concurrent mixed workloads where writer threads and reader threads access the same keyspace.
+//! Keys are chosen from a Zipfian distribution, so a few hot keys get most of the accesses, which
+//! is closer to what a cache sees than uniformly random keys. The same workloads run against
+//! bitcask, sled, and a DashMap to give a baseline for the writer lock and the readers pool.
+
+use std::{sync::Arc, thread, time::Duration};
+
+use ::bitcask::storage::{bitcask, KeyValueStorage};
+use bytes::Bytes;
+use criterion::{
+    black_box, criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, BenchmarkId,
+    Criterion, SamplingMode, Throughput,
+};
+use dashmap::DashMap;
+use pprof::criterion::{Output, PProfProfiler};
+use rand::{distributions::Standard, prelude::*};
+use tempfile::TempDir;
+
+const KEYS: usize = 10000;
+const KEY_SIZE: usize = 32;
+const VAL_SIZE: usize = 256;
+/// The number of operations done by all threads in one iteration.
+const OPS: usize = 100000;
+/// The exponent of the Zipfian distribution, larger values make the hot keys hotter.
+const ZIPF_EXPONENT: f64 = 0.99;
+
+/// The number of writer and reader threads, and the fraction of the operations that are reads.
+struct Workload {
+    writers: usize,
+    readers: usize,
+    read_ratio: f64,
+}
+
+const WORKLOADS: [Workload; 4] = [
+    Workload {
+        writers: 1,
+        readers: 4,
+        read_ratio: 0.9,
+    },
+    Workload {
+        writers: 1,
+        readers: 8,
+        read_ratio: 0.99,
+    },
+    Workload {
+        writers: 4,
+        readers: 4,
+        read_ratio: 0.5,
+    },
+    Workload {
+        writers: 8,
+        readers: 2,
+        read_ratio: 0.1,
+    },
+];
+
+impl Workload {
+    fn name(&self) -> String {
+        format!(
+            "{}w_{}r_{:.0}pct_reads",
+            self.writers,
+            self.readers,
+            self.read_ratio * 100.0
+        )
+    }
+}
+
+/// A Zipfian distribution over `0..n` that is sampled by a binary search over its CDF.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, exponent: f64) -> Self {
+        let mut cdf: Vec<f64> = (1..=n)
+            .scan(0.0, |sum, rank| {
+                *sum += 1.0 / (rank as f64).powf(exponent);
+                Some(*sum)
+            })
+            .collect();
+        let total = cdf[n - 1];
+        cdf.iter_mut().for_each(|p| *p /= total);
+        Self { cdf }
+    }
+}
+
+impl Distribution<usize> for Zipf {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        let p: f64 = rng.gen();
+        self.cdf.partition_point(|&c| c < p).min(self.cdf.len() - 1)
+    }
+}
+
+/// The indices of the keys that each writer and each reader accesses, generated up front so the
+/// sampling isn't measured.
+struct Plan {
+    writes: Vec<Vec<usize>>,
+    reads: Vec<Vec<usize>>,
+}
+
+impl Plan {
+    fn new<R: Rng>(rng: &mut R, zipf: &Zipf, workload: &Workload) -> Self {
+        let reads = (OPS as f64 * workload.read_ratio) as usize;
+        let writes = OPS - reads;
+        let mut samples = |threads: usize, ops: usize| -> Vec<Vec<usize>> {
+            (0..threads)
+                .map(|_| zipf.sample_iter(&mut *rng).take(ops / threads).collect())
+                .collect()
+        };
+        Self {
+            writes: samples(workload.writers, writes),
+            reads: samples(workload.readers, reads),
+        }
+    }
+}
+
+/// The operations used by the benchmark, so engines that don't implement [`KeyValueStorage`] can
+/// be compared.
+trait Engine: Send + Sized {
+    /// Return a copy of the engine for a single thread.
+    fn for_thread(&self) -> Self;
+
+    fn set(&self, key: Bytes, value: Bytes);
+
+    fn get(&self, key: Bytes) -> Option<Bytes>;
+}
+
+impl Engine for bitcask::Handle {
+    fn for_thread(&self) -> Self {
+        self.for_client()
+    }
+
+    fn set(&self, key: Bytes, value: Bytes) {
+        KeyValueStorage::set(self, key, value).unwrap();
+    }
+
+    fn get(&self, key: Bytes) -> Option<Bytes> {
+        KeyValueStorage::get(self, key).unwrap()
+    }
+}
+
+impl Engine for sled::Db {
+    fn for_thread(&self) -> Self {
+        self.clone()
+    }
+
+    fn set(&self, key: Bytes, value: Bytes) {
+        self.insert(key.as_ref(), value.as_ref()).unwrap();
+    }
+
+    fn get(&self, key: Bytes) -> Option<Bytes> {
+        sled::Tree::get(self, key.as_ref())
+            .unwrap()
+            .map(|v| Bytes::copy_from_slice(&v))
+    }
+}
+
+impl Engine for Arc<DashMap<Bytes, Bytes>> {
+    fn for_thread(&self) -> Self {
+        self.clone()
+    }
+
+    fn set(&self, key: Bytes, value: Bytes) {
+        self.insert(key, value);
+    }
+
+    fn get(&self, key: Bytes) -> Option<Bytes> {
+        DashMap::get(self, &key).map(|v| v.value().clone())
+    }
+}
+
+fn run<E>(engine: &E, keys: &[Bytes], value: &Bytes, plan: &Plan)
+where
+    E: Engine,
+{
+    thread::scope(|s| {
+        for samples in &plan.writes {
+            let engine = engine.for_thread();
+            s.spawn(move || {
+                for &i in samples {
+                    engine.set(keys[i].clone(), value.clone());
+                }
+            });
+        }
+        for samples in &plan.reads {
+            let engine = engine.for_thread();
+            s.spawn(move || {
+                for &i in samples {
+                    black_box(engine.get(keys[i].clone()));
+                }
+            });
+        }
+    });
+}
+
+fn bench_engine<E>(
+    g: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    engine: E,
+    keys: &[Bytes],
+    value: &Bytes,
+    plans: &[Plan],
+) where
+    E: Engine,
+{
+    for key in keys {
+        engine.set(key.clone(), value.clone());
+    }
+    for (workload, plan) in WORKLOADS.iter().zip(plans) {
+        g.bench_with_input(BenchmarkId::new(name, workload.name()), plan, |b, plan| {
+            b.iter(|| run(&engine, keys, value, plan))
+        });
+    }
+}
+
+fn bench_mixed(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64((1 << 7) + 1);
+    let keys: Vec<Bytes> = (0..KEYS)
+        .map(|_| (&mut rng).sample_iter(Standard).take(KEY_SIZE).collect())
+        .collect();
+    let value: Bytes = (&mut rng).sample_iter(Standard).take(VAL_SIZE).collect();
+    let zipf = Zipf::new(KEYS, ZIPF_EXPONENT);
+    let plans: Vec<Plan> = WORKLOADS
+        .iter()
+        .map(|workload| Plan::new(&mut rng, &zipf, workload))
+        .collect();
+
+    let mut g = c.benchmark_group("mixed");
+    g.sampling_mode(SamplingMode::Flat);
+    g.throughput(Throughput::Elements(OPS as u64));
+
+    let tmpdir = TempDir::new().unwrap();
+    let bitcask = bitcask::Config::default()
+        .path(tmpdir.path())
+        .to_owned()
+        .open()
+        .unwrap();
+    bench_engine(
+        &mut g,
+        "bitcask",
+        bitcask.get_handle(),
+        &keys,
+        &value,
+        &plans,
+    );
+    drop(bitcask);
+
+    let tmpdir = TempDir::new().unwrap();
+    let sled = sled::open(tmpdir.path()).unwrap();
+    bench_engine(&mut g, "sled", sled, &keys, &value, &plans);
+
+    let dashmap = Arc::new(DashMap::new());
+    bench_engine(&mut g, "dashmap", dashmap, &keys, &value, &plans);
+    g.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .with_profiler(PProfProfiler::new(500, Output::Flamegraph(None)))
+        .sample_size(20)
+        .warm_up_time(Duration::from_secs(3))
+        .measurement_time(Duration::from_secs(10));
+    targets = bench_mixed,
+);
+criterion_main!(benches);
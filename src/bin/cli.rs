@@ -1,10 +1,10 @@
-use std::net::Ipv4Addr;
+use std::{net::Ipv4Addr, ops::Bound};
 
 use clap::{Parser, Subcommand};
 
 use bitcask::{
     net::Client,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, LogFormat},
 };
 
 /// A minimal Redis client.
@@ -44,12 +44,30 @@ enum Commands {
         #[clap(name = "KEY")]
         keys: Vec<String>,
     },
+
+    /// Get keys within a range in lexicographic order.
+    Scanrange {
+        /// The inclusive lower bound of the range.
+        #[clap(long)]
+        start: Option<String>,
+        /// The exclusive upper bound of the range.
+        #[clap(long)]
+        end: Option<String>,
+        /// The max number of keys to return.
+        #[clap(long)]
+        count: Option<u64>,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), anyhow::Error> {
     // Setup global `tracing` subscriber
-    let subscriber = get_subscriber("bitcask".into(), "info".into(), std::io::stdout);
+    let subscriber = get_subscriber(
+        "bitcask".into(),
+        "info".into(),
+        LogFormat::Json,
+        std::io::stdout,
+    );
     init_subscriber(subscriber);
 
     let cli = Cli::parse();
@@ -71,6 +89,20 @@ async fn main() -> Result<(), anyhow::Error> {
             let n_deleted = client.del(keys).await?;
             println!("(integer) {n_deleted}");
         }
+        Commands::Scanrange { start, end, count } => {
+            let start = start.map_or(Bound::Unbounded, |s| Bound::Included(s.into()));
+            let end = end.map_or(Bound::Unbounded, |s| Bound::Excluded(s.into()));
+            let keys = client.scan_range(start, end, count).await?;
+            if keys.is_empty() {
+                println!("(empty array)");
+            }
+            for (i, key) in keys.iter().enumerate() {
+                match std::str::from_utf8(key) {
+                    Ok(s) => println!("{}) \"{s}\"", i + 1),
+                    Err(_) => println!("{}) {key:?}", i + 1),
+                }
+            }
+        }
     }
 
     Ok(())
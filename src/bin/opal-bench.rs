@@ -0,0 +1,279 @@
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use bytes::Bytes;
+use clap::Parser;
+use rand::{distributions::WeightedIndex, prelude::*};
+use tokio::{net::TcpStream, task::JoinSet};
+
+use bitcask::net::{connection::Connection, frame::Frame};
+
+/// A load generator for the RESP server, similar to redis-benchmark.
+#[derive(Parser)]
+#[clap(name = "opal-bench", version, author, long_about = None)]
+struct Cli {
+    /// The host address of the server.
+    #[clap(long, default_value = "127.0.0.1")]
+    host: Ipv4Addr,
+
+    /// The port number of the server.
+    #[clap(long, default_value_t = 6379)]
+    port: u16,
+
+    /// The number of concurrent connections.
+    #[clap(short, long, default_value_t = 50)]
+    clients: usize,
+
+    /// The total number of requests.
+    #[clap(short = 'n', long, default_value_t = 100000)]
+    requests: u64,
+
+    /// The number of requests sent on a connection before waiting for their replies.
+    #[clap(short = 'P', long, default_value_t = 1)]
+    pipeline: u64,
+
+    /// The size in bytes of the values of SET requests.
+    #[clap(short = 'd', long, default_value_t = 64)]
+    data_size: usize,
+
+    /// The number of distinct keys, which are chosen uniformly at random.
+    #[clap(short = 'r', long, default_value_t = 10000)]
+    keyspace: u64,
+
+    /// The commands to send and their relative weights, out of get, set, and del.
+    #[clap(long, default_value = "get=1,set=1")]
+    mix: Mix,
+}
+
+/// A command sent by the benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Get,
+    Set,
+    Del,
+}
+
+impl Op {
+    const ALL: [Op; 3] = [Op::Get, Op::Set, Op::Del];
+
+    fn request(self, key: u64, value: &Bytes) -> Frame {
+        let bulk = |s: &'static str| Frame::BulkString(Bytes::from_static(s.as_bytes()));
+        match self {
+            Op::Get => Frame::Array(vec![bulk("GET"), key_frame(key)]),
+            Op::Set => Frame::Array(vec![
+                bulk("SET"),
+                key_frame(key),
+                Frame::BulkString(value.clone()),
+            ]),
+            Op::Del => Frame::Array(vec![bulk("DEL"), key_frame(key)]),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Op::Get => "get",
+            Op::Set => "set",
+            Op::Del => "del",
+        };
+        f.write_str(name)
+    }
+}
+
+fn key_frame(key: u64) -> Frame {
+    Frame::BulkString(Bytes::from(format!("key:{key:012}")))
+}
+
+/// The commands to send and their relative weights, parsed from a list such as "get=9,set=1".
+#[derive(Debug, Clone)]
+struct Mix(Vec<(Op, u32)>);
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ops = Vec::new();
+        for item in s.split(',') {
+            let (name, weight) = item.split_once('=').unwrap_or((item, "1"));
+            let op = Op::ALL
+                .into_iter()
+                .find(|op| op.to_string().eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| format!("unknown command '{name}'"))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight '{weight}'"))?;
+            ops.push((op, weight));
+        }
+        if ops.iter().all(|(_, weight)| *weight == 0) {
+            return Err("at least one command must have a positive weight".into());
+        }
+        Ok(Self(ops))
+    }
+}
+
+impl fmt::Display for Mix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (op, weight)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{op}={weight}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a single connection observed.
+#[derive(Default)]
+struct Report {
+    /// The round-trip time of each request. Pipelined requests share the round-trip time of
+    /// their batch.
+    latencies: Vec<Duration>,
+    /// The number of requests sent for each command, in the order of [`Op::ALL`].
+    counts: [u64; Op::ALL.len()],
+    /// The number of error replies.
+    errors: u64,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.latencies.extend(other.latencies);
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.errors += other.errors;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Arc::new(Cli::parse());
+    if cli.pipeline == 0 {
+        bail!("the pipeline must have at least one request");
+    }
+    if cli.clients == 0 {
+        bail!("there must be at least one client");
+    }
+    if cli.keyspace == 0 {
+        bail!("the keyspace must have at least one key");
+    }
+
+    // All connections are established before the clock starts
+    let mut conns = Vec::with_capacity(cli.clients);
+    for _ in 0..cli.clients {
+        let stream = TcpStream::connect((cli.host, cli.port)).await?;
+        stream.set_nodelay(true)?;
+        conns.push(Connection::new(stream));
+    }
+
+    // Requests are handed out to the connections in batches of the pipeline size
+    let start = Instant::now();
+    let issued = Arc::new(AtomicU64::new(0));
+    let mut clients = JoinSet::new();
+    for conn in conns {
+        clients.spawn(run_client(conn, Arc::clone(&cli), Arc::clone(&issued)));
+    }
+    let mut report = Report::default();
+    while let Some(result) = clients.join_next().await {
+        report.merge(result??);
+    }
+    let elapsed = start.elapsed();
+
+    print_report(&cli, report, elapsed);
+    Ok(())
+}
+
+async fn run_client(
+    mut conn: Connection,
+    cli: Arc<Cli>,
+    issued: Arc<AtomicU64>,
+) -> Result<Report, anyhow::Error> {
+    let mut rng = StdRng::from_entropy();
+    let ops = WeightedIndex::new(cli.mix.0.iter().map(|(_, weight)| weight))?;
+    let value = Bytes::from(vec![b'x'; cli.data_size]);
+
+    let mut report = Report::default();
+    let mut frames = Vec::with_capacity(cli.pipeline as usize);
+    loop {
+        let first = issued.fetch_add(cli.pipeline, Ordering::Relaxed);
+        if first >= cli.requests {
+            break;
+        }
+        let batch = cli.pipeline.min(cli.requests - first);
+
+        frames.clear();
+        for _ in 0..batch {
+            let (op, _) = cli.mix.0[ops.sample(&mut rng)];
+            let key = rng.gen_range(0..cli.keyspace);
+            frames.push(op.request(key, &value));
+            report.counts[op as usize] += 1;
+        }
+
+        let sent = Instant::now();
+        conn.write_frames(&frames).await?;
+        for _ in 0..batch {
+            match conn.read_frame().await? {
+                Some(Frame::Error(_)) => report.errors += 1,
+                Some(_) => {}
+                None => return Err(anyhow!("connection closed by the server")),
+            }
+        }
+        let latency = sent.elapsed();
+        report
+            .latencies
+            .extend(std::iter::repeat_n(latency, batch as usize));
+    }
+    Ok(report)
+}
+
+fn print_report(cli: &Cli, mut report: Report, elapsed: Duration) {
+    let completed = report.latencies.len();
+    println!("====== {} ======", cli.mix);
+    println!(
+        "  {completed} requests completed in {:.2} seconds",
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "  {} parallel clients, pipeline of {}, {} bytes payload, {} keys",
+        cli.clients, cli.pipeline, cli.data_size, cli.keyspace
+    );
+    for (op, count) in Op::ALL.iter().zip(report.counts) {
+        if count > 0 {
+            println!("  {op}: {count} requests");
+        }
+    }
+    println!("  {} error replies", report.errors);
+    println!();
+    println!(
+        "throughput: {:.2} requests per second",
+        completed as f64 / elapsed.as_secs_f64()
+    );
+    if completed == 0 {
+        return;
+    }
+
+    report.latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let rank = ((p / 100.0) * completed as f64).ceil() as usize;
+        report.latencies[rank.clamp(1, completed) - 1]
+    };
+    println!("latency (msec):");
+    for p in [50.0, 90.0, 99.0, 99.9] {
+        println!("  p{p:<5} {:.3}", percentile(p).as_secs_f64() * 1000.0);
+    }
+    println!(
+        "  max    {:.3}",
+        report.latencies[completed - 1].as_secs_f64() * 1000.0
+    );
+}
@@ -2,10 +2,17 @@ use std::fs;
 
 use clap::Parser;
 use tokio::signal;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 use bitcask::{
     conf::Configuration,
-    telemetry::{get_subscriber, init_subscriber},
+    net::LimitsHandle,
+    storage::{
+        bitcask::Handle,
+        databases::{self, Databases},
+    },
+    telemetry::{self, FilterHandle},
 };
 
 /// A minimal Redis server.
@@ -19,20 +26,132 @@ struct Cli {
 
 #[tokio::main]
 pub async fn main() -> Result<(), anyhow::Error> {
-    // Setup global `tracing` subscriber
-    let subscriber = get_subscriber("bitcaskd".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-
     let cli = Cli::parse();
     let conf = Configuration::get(&cli.config)?;
 
+    // Setup global `tracing` subscriber
+    let filter = telemetry::init("bitcaskd", &conf.log.level, conf.log.format);
+
+    for (name, tenant_conf) in &conf.databases {
+        if databases::is_default(name.as_bytes()) {
+            anyhow::bail!("the database named {name} is configured by storage");
+        }
+        if tenant_conf.path == conf.storage.path {
+            anyhow::bail!("the database named {name} is in the directory of the storage");
+        }
+    }
     fs::create_dir_all(&conf.storage.path)?;
 
     let storage = conf.storage.open()?;
+    // The additional databases are kept open for as long as the server runs
+    let mut databases = Databases::new(storage.get_handle());
+    let mut tenants = Vec::with_capacity(conf.databases.len());
+    for (name, tenant_conf) in conf.databases {
+        fs::create_dir_all(&tenant_conf.path)?;
+        let tenant = tenant_conf.open()?;
+        databases = databases.with_database(name.clone(), tenant.get_handle());
+        tenants.push((name, tenant));
+    }
     let server = conf
         .net
-        .async_server(storage.get_handle(), signal::ctrl_c())
+        .async_server(databases.clone(), signal::ctrl_c())
         .await?;
-    server.run().await;
+    // The gateway's clients are paused, rate limited, and audited together with the server's
+    let gateway = match conf.gateway {
+        Some(gateway) => Some(
+            gateway
+                .async_server_alongside(databases, signal::ctrl_c(), &server.state())
+                .await?,
+        ),
+        None => None,
+    };
+
+    #[cfg(unix)]
+    {
+        let reloader = Reloader {
+            config: cli.config,
+            filter,
+            storage: storage.get_handle(),
+            tenants: tenants
+                .iter()
+                .map(|(name, tenant)| (name.clone(), tenant.get_handle()))
+                .collect(),
+            limits: server.limits_handle(),
+            gateway_limits: gateway.as_ref().map(|g| g.limits_handle()),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = reloader.reload_on_hangup().await {
+                error!(cause = %err, "stopped listening for SIGHUP");
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    drop(filter);
+
+    match gateway {
+        Some(gateway) => {
+            tokio::join!(server.run(), gateway.run());
+        }
+        None => server.run().await,
+    }
     Ok(())
 }
+
+/// Reloads the settings that can be changed while the server is running.
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Reloader {
+    config: String,
+    filter: FilterHandle,
+    storage: Handle,
+    /// The handles of the additional databases by name.
+    tenants: Vec<(String, Handle)>,
+    limits: LimitsHandle,
+    gateway_limits: Option<LimitsHandle>,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl Reloader {
+    /// Reload the configuration file every time the process receives SIGHUP.
+    #[cfg(unix)]
+    async fn reload_on_hangup(self) -> Result<(), anyhow::Error> {
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            match self.reload() {
+                Ok(()) => info!("reloaded configuration"),
+                Err(err) => error!(cause = %err, "invalid configuration, keeping the current one"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the configuration file and apply the log filter, the server limits, and the merge
+    /// settings of every database. Every setting is validated before any of them is applied, so
+    /// an invalid file leaves the running configuration untouched. A gateway or a database can't
+    /// be added or removed without restarting, so the databases that the server doesn't host are
+    /// ignored, and the databases that are missing from the file keep their settings.
+    fn reload(&self) -> Result<(), anyhow::Error> {
+        let conf = Configuration::get(&self.config)?;
+        let env_filter = EnvFilter::try_new(&conf.log.level)?;
+        conf.net.validate()?;
+        if let Some(gateway) = &conf.gateway {
+            gateway.validate()?;
+        }
+        conf.storage.validate()?;
+        for tenant_conf in conf.databases.values() {
+            tenant_conf.validate()?;
+        }
+
+        self.filter.reload(env_filter)?;
+        self.limits.reload(&conf.net)?;
+        if let (Some(limits), Some(gateway)) = (&self.gateway_limits, &conf.gateway) {
+            limits.reload(gateway)?;
+        }
+        self.storage.reload(&conf.storage)?;
+        for (name, tenant) in &self.tenants {
+            if let Some(tenant_conf) = conf.databases.get(name) {
+                tenant.reload(tenant_conf)?;
+            }
+        }
+        Ok(())
+    }
+}
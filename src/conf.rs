@@ -1,17 +1,50 @@
 //! Configuration for the server binary
 
+use std::collections::BTreeMap;
+
 use config::Config;
 use serde::Deserialize;
 
-use super::storage::bitcask;
+use super::{storage::bitcask, telemetry::LogFormat};
 
 /// All configuration
 #[derive(Deserialize)]
 pub struct Configuration {
+    /// Logging configuration.
+    #[serde(default)]
+    pub log: LogConfiguration,
     /// Server configuration.
     pub net: crate::net::Config,
+    /// Configuration of an additional listener, usually one that speaks HTTP, that shares the
+    /// storage with the server. No additional listener is started if this is not given.
+    #[serde(default)]
+    pub gateway: Option<crate::net::Config>,
     /// Bitcask storage configuration.
     pub storage: bitcask::Config,
+    /// Configurations of the additional databases that clients can switch to with SELECT, by
+    /// name. Each database has its own directory and its own quotas.
+    #[serde(default)]
+    pub databases: BTreeMap<String, bitcask::Config>,
+}
+
+/// Logging configuration
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfiguration {
+    /// The filter directives for the logs, e.g. `info` or `bitcask=debug`. This is overridden by
+    /// the `RUST_LOG` environment variable when the server starts.
+    pub level: String,
+    /// The format of the logs, either `json` or `text`.
+    pub format: LogFormat,
+}
+
+impl Default for LogConfiguration {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+        }
+    }
 }
 
 impl Configuration {
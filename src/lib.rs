@@ -3,12 +3,19 @@
 //! for interacting with the key-value storage through network connections, these implement
 //! Redis serialization protocol (RESP) to communicate with each other. A minimal set of Redis's
 //! commands is supported.
+//!
+//! The network layer, the configuration loader, and the telemetry setup are behind the `server`
+//! feature, which is enabled by default. Turning off the default features leaves only the storage
+//! engine, so it can be embedded without the server's dependencies.
 
 #![deny(rust_2018_idioms, rust_2021_compatibility)]
 #![warn(missing_docs)]
 
+#[cfg(feature = "server")]
 pub mod conf;
+#[cfg(feature = "server")]
 pub mod net;
 pub mod shutdown;
 pub mod storage;
+#[cfg(feature = "server")]
 pub mod telemetry;
@@ -1,12 +1,43 @@
 //! This module contains the implementation for Redis serialization protocol (RESP),
-//! along with a client and a server that supports a minimal set of commands from Redis
+//! along with a client and a server that supports a minimal set of commands from Redis. The
+//! server can also speak the memcached text protocol or HTTP to clients that can't use RESP.
 
+pub mod audit;
 mod client;
 pub mod command;
 mod config;
 pub mod connection;
 mod error;
 pub mod frame;
+mod lanes;
+mod pause;
+pub mod protocol;
+mod proxy;
+mod pubsub;
+mod ratelimit;
+mod renames;
 mod server;
+mod state;
 
-pub use self::{client::Client, config::Config, error::Error, server::Server};
+pub use self::{
+    client::Client,
+    config::{Config, ListenerErrorPolicy},
+    error::Error,
+    lanes::{LaneStats, LanesStats},
+    pause::PauseMode,
+    ratelimit::RateLimits,
+    server::{LimitsHandle, Server},
+    state::State,
+};
+
+/// Run a blocking call to the storage on the blocking thread pool, inside the span of the caller.
+/// The spans of the storage are then nested under the command that made the call, so a request
+/// shows up as one trace from the connection down to the storage.
+pub(crate) fn spawn_blocking<F, T>(f: F) -> tokio::task::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f))
+}
@@ -0,0 +1,223 @@
+//! An append-only log of the writes that clients make, kept apart from the data files for
+//! deployments that must be able to tell who changed a key and when. Each record is a line of
+//! JSON, so the log can be read by other tools. The log is rotated once it grows past a size
+//! limit, `audit.log` is renamed to `audit.log.1`, `audit.log.1` to `audit.log.2`, and so on, and
+//! the oldest file is dropped.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// A write that was made by a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The Unix timestamp in milliseconds at which the command was received.
+    pub time_ms: u64,
+    /// The address of the client.
+    pub client: String,
+    /// The name of the command.
+    pub command: String,
+    /// The keys that the command writes to. Keys that aren't valid UTF-8 are written lossily.
+    pub keys: Vec<String>,
+}
+
+impl AuditRecord {
+    /// Create a record of a command that is received now.
+    pub fn new<'a, K>(client: &str, command: &str, keys: K) -> Self
+    where
+        K: IntoIterator<Item = &'a [u8]>,
+    {
+        let time_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or_default();
+        Self {
+            time_ms,
+            client: client.to_string(),
+            command: command.to_string(),
+            keys: keys
+                .into_iter()
+                .map(|k| String::from_utf8_lossy(k).into_owned())
+                .collect(),
+        }
+    }
+}
+
+/// The audit log of a server.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    active: Mutex<ActiveFile>,
+}
+
+/// The file that records are appended to.
+#[derive(Debug)]
+struct ActiveFile {
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Open the audit log at the given path, appending to it if it exists. The log is rotated
+    /// when it grows past `max_size` bytes, and at most `max_files` rotated files are kept.
+    pub fn open<P>(path: P, max_size: u64, max_files: usize) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            active: Mutex::new(ActiveFile { file, size }),
+        })
+    }
+
+    /// Append the record to the log. The record is written to the file before this returns, so
+    /// it isn't lost if the server crashes.
+    pub fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut active = self.active.lock();
+        active.file.write_all(&line)?;
+        active.size += line.len() as u64;
+        if active.size >= self.max_size {
+            self.rotate()?;
+            *active = ActiveFile {
+                file: open_append(&self.path)?,
+                size: 0,
+            };
+        }
+        Ok(())
+    }
+
+    /// Return at most `count` of the most recent records, newest first, optionally only those
+    /// that wrote to the given key.
+    pub fn query(&self, key: Option<&str>, count: usize) -> io::Result<Vec<AuditRecord>> {
+        // Holding the lock stops the files from being rotated while they are read
+        let _active = self.active.lock();
+        let mut records = Vec::new();
+        for n in 0..=self.max_files {
+            let file = match File::open(self.rotated_path(n)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e),
+            };
+            let mut lines = Vec::new();
+            for line in BufReader::new(file).lines() {
+                lines.push(line?);
+            }
+            for line in lines.iter().rev() {
+                let record: AuditRecord = serde_json::from_str(line)?;
+                if key.is_some_and(|key| !record.keys.iter().any(|k| k == key)) {
+                    continue;
+                }
+                records.push(record);
+                if records.len() >= count {
+                    return Ok(records);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Shift every rotated file up by one, dropping the oldest, and rotate the active file.
+    fn rotate(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..self.max_files).rev() {
+            match fs::rename(self.rotated_path(n), self.rotated_path(n + 1)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    /// Get the path of the file that was rotated `n` times, where 0 is the active file.
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        if n == 0 {
+            return self.path.clone();
+        }
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+}
+
+fn open_append<P>(path: P) -> io::Result<File>
+where
+    P: AsRef<Path>,
+{
+    File::options().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(command: &str, key: &str) -> AuditRecord {
+        AuditRecord::new("127.0.0.1:1234", command, [key.as_bytes()])
+    }
+
+    #[test]
+    fn query_returns_the_newest_records_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::open(dir.path().join("audit.log"), u64::MAX, 4).unwrap();
+        log.append(&record("SET", "a")).unwrap();
+        log.append(&record("SET", "b")).unwrap();
+        log.append(&record("DEL", "a")).unwrap();
+
+        let records = log.query(None, 2).unwrap();
+        let commands: Vec<_> = records.iter().map(|r| r.command.as_str()).collect();
+        assert_eq!(vec!["DEL", "SET"], commands);
+        assert_eq!(vec!["b".to_string()], records[1].keys);
+
+        let records = log.query(Some("a"), 10).unwrap();
+        let commands: Vec<_> = records.iter().map(|r| r.command.as_str()).collect();
+        assert_eq!(vec!["DEL", "SET"], commands);
+    }
+
+    #[test]
+    fn log_is_rotated_and_oldest_files_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        // Every record goes to its own file
+        let log = AuditLog::open(&path, 1, 2).unwrap();
+        for key in ["a", "b", "c", "d"] {
+            log.append(&record("SET", key)).unwrap();
+        }
+        assert!(dir.path().join("audit.log.2").is_file());
+        assert!(!dir.path().join("audit.log.3").exists());
+
+        let keys: Vec<_> = log
+            .query(None, 10)
+            .unwrap()
+            .into_iter()
+            .flat_map(|r| r.keys)
+            .collect();
+        assert_eq!(vec!["d".to_string(), "c".to_string()], keys);
+
+        // Records are appended to the existing log when it's reopened
+        drop(log);
+        let log = AuditLog::open(&path, u64::MAX, 2).unwrap();
+        log.append(&record("SET", "e")).unwrap();
+        assert_eq!(3, log.query(None, 10).unwrap().len());
+    }
+}
@@ -1,9 +1,11 @@
+use std::ops::Bound;
+
 use bytes::Bytes;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tracing::debug;
 
 use super::{
-    command::{self, Del, Get, Set, Utf8Bytes},
+    command::{self, Del, Get, ScanRange, Set, Utf8Bytes},
     connection::Connection,
     frame::Frame,
 };
@@ -70,18 +72,48 @@ impl Client {
         }
     }
 
+    /// Get the keys within the given range in lexicographic order.
+    ///
+    /// Returns at most `count` keys if it is given.
+    #[tracing::instrument(skip(self))]
+    pub async fn scan_range(
+        &mut self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: Option<u64>,
+    ) -> Result<Vec<Bytes>, super::Error> {
+        let cmd = ScanRange::new(start, end, count);
+        let frame: Frame = cmd.into();
+        debug!(request = ?frame);
+
+        self.conn.write_frame(&frame).await?;
+
+        // Wait for the response from the server
+        match self.read_response().await? {
+            Frame::Array(frames) => frames
+                .into_iter()
+                .map(|f| match f {
+                    Frame::BulkString(key) => Ok(key),
+                    f => Err(command::Error::BadFrame(f).into()),
+                })
+                .collect(),
+            f => Err(command::Error::BadFrame(f).into()),
+        }
+    }
+
     /// Set the value of the key, overwritting the value that is currently held by
     /// the key, regardless of its type.
     ///
-    /// The SET command supports a set of options that modify its behavior:
-    /// - (unsupported) EX seconds -- Set the specified expire time, in seconds.
-    /// - (unsupported) PX milliseconds -- Set the specified expire time, in milliseconds.
-    /// - (unsupported) EXAT timestamp-seconds -- Set the specified Unix time at which the key will expire, in seconds.
-    /// - (unsupported) PXAT timestamp-milliseconds -- Set the specified Unix time at which the key will expire, in milliseconds.
-    /// - (unsupported) NX -- Only set the key if it does not already exist.
-    /// - (unsupported) XX -- Only set the key if it already exist.
-    /// - (unsupported) KEEPTTL -- Retain the time to live associated with the key.
-    /// - (unsupported) GET -- Return the old string stored at key, or nil if key did not exist. An error is returned and SET aborted if the value stored at key is not a string.
+    /// The SET command supports a set of options that modify its behavior, which are not exposed
+    /// through this method:
+    /// - EX seconds -- Set the specified expire time, in seconds.
+    /// - PX milliseconds -- Set the specified expire time, in milliseconds.
+    /// - EXAT timestamp-seconds -- Set the specified Unix time at which the key will expire, in seconds.
+    /// - PXAT timestamp-milliseconds -- Set the specified Unix time at which the key will expire, in milliseconds.
+    /// - NX -- Only set the key if it does not already exist.
+    /// - XX -- Only set the key if it already exist.
+    /// - KEEPTTL -- Retain the time to live associated with the key.
+    /// - GET -- Return the old string stored at key, or nil if key did not exist. An error is returned and SET aborted if the value stored at key is not a string.
     #[tracing::instrument(skip(self))]
     pub async fn set(&mut self, key: String, value: Bytes) -> Result<(), super::Error> {
         let cmd = Set::new(key.into(), value);
@@ -1,16 +1,110 @@
 //! Implementations for a small set of commands as supported by Redis
 
+mod audit;
+mod batch;
+mod bgpause;
+mod bpop;
+mod client;
+mod copy;
+mod datafiles;
 mod del;
+mod delprefix;
+#[cfg(feature = "scripting")]
+mod eval;
+mod expiry;
+mod geo;
+mod geoadd;
+mod geosearch;
 mod get;
+mod getex;
+mod info;
+mod jsonget;
+mod jsonpath;
+mod jsonset;
+mod linsert;
+mod list;
+mod llen;
+mod lpos;
+mod lrem;
+mod lset;
+mod ltrim;
+mod mpop;
+mod object;
+mod pop;
+mod publish;
+mod push;
+mod registry;
+mod rename;
+mod sadd;
+mod scanrange;
+mod select;
+mod serverinfo;
+mod session;
 mod set;
+mod sintercard;
+pub(super) mod stream;
+mod unlink;
+pub(super) mod value;
+mod xadd;
+mod xrange;
+mod xread;
+mod zset;
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, sync::Arc, time::Duration};
 
 use bytes::Bytes;
 use thiserror::Error;
 
-pub use self::{del::Del, get::Get, set::Set};
-use super::{connection::Connection, frame::Frame};
+#[cfg(feature = "scripting")]
+pub use self::eval::{Eval, Script};
+pub use self::{
+    audit::Audit,
+    batch::{Batch, BatchOp},
+    bgpause::BgPause,
+    bpop::BlockingPop,
+    client::ClientCommand,
+    copy::Copy,
+    datafiles::DataFiles,
+    del::Del,
+    delprefix::DelPrefix,
+    expiry::Expiry,
+    geo::{GeoPosition, GeoShape, GeoUnit},
+    geoadd::Geoadd,
+    geosearch::{GeoOrder, GeoOrigin, Geosearch},
+    get::Get,
+    getex::GetEx,
+    info::CommandInfo,
+    jsonget::JsonGet,
+    jsonset::{JsonSet, JsonSetCondition},
+    linsert::Linsert,
+    list::ListEnd,
+    llen::Llen,
+    lpos::Lpos,
+    lrem::Lrem,
+    lset::Lset,
+    ltrim::Ltrim,
+    mpop::{Lmpop, Zmpop},
+    object::{ObjectFreq, ObjectIdleTime},
+    pop::Pop,
+    publish::Publish,
+    push::Push,
+    registry::{CommandSpec, KeySpec},
+    rename::Rename,
+    sadd::Sadd,
+    scanrange::ScanRange,
+    select::Select,
+    serverinfo::ServerInfo,
+    session::SessionCommand,
+    set::{Set, SetCondition},
+    sintercard::Sintercard,
+    stream::{StreamId, XaddId},
+    unlink::Unlink,
+    xadd::Xadd,
+    xrange::Xrange,
+    xread::Xread,
+    zset::ZsetEnd,
+};
+use super::{connection::Connection, frame::Frame, PauseMode, State};
 use crate::{shutdown::Shutdown, storage::KeyValueStorage};
 
 /// Error from parsing command from frame
@@ -33,16 +127,115 @@ pub enum Error {
     NotUtf8(#[from] std::str::Utf8Error),
 }
 
+/// Whether a command only reads from the storage or also writes to it. Reads and writes are
+/// limited and scheduled separately, so a flood of writes can't delay the reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandClass {
+    /// The command only reads.
+    Read,
+    /// The command writes to at least one key.
+    Write,
+}
+
 /// Enumeration of all the supported Redis commands. Each commands
 /// will have an associated struct that contains its arguments' data
 #[derive(Debug, PartialEq, Eq)]
 pub enum Command {
+    /// AUDIT [KEY key] [COUNT count]
+    Audit(Audit),
+    /// BATCH SET key value | DEL key [SET key value | DEL key ...]
+    Batch(Batch),
+    /// BGPAUSE [ON | OFF]
+    BgPause(BgPause),
+    /// BLPOP key [key ...] timeout
+    /// BRPOP key [key ...] timeout
+    BlockingPop(BlockingPop),
+    /// CLIENT PAUSE timeout [WRITE | ALL]
+    /// CLIENT UNPAUSE
+    Client(ClientCommand),
+    /// COMMAND [COUNT | LIST | INFO [command-name ...] | GETKEYS command [arg ...]]
+    Info(CommandInfo),
+    /// COPY source destination [REPLACE]
+    Copy(Copy),
+    /// DATAFILES
+    DataFiles(DataFiles),
     /// DEL key [key ...]
     Del(Del),
+    /// DELPREFIX prefix
+    DelPrefix(DelPrefix),
+    /// EVAL script numkeys [key [key ...]] [arg [arg ...]]
+    /// EVALSHA sha1 numkeys [key [key ...]] [arg [arg ...]]
+    #[cfg(feature = "scripting")]
+    Eval(Eval),
+    /// GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]
+    Geoadd(Geoadd),
+    /// GEOSEARCH key <FROMMEMBER member | FROMLONLAT longitude latitude>
+    ///   <BYRADIUS radius <M | KM | FT | MI> | BYBOX width height <M | KM | FT | MI>>
+    ///   [ASC | DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+    Geosearch(Geosearch),
     /// GET key
     Get(Get),
-    /// SET key value
+    /// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    ///   PXAT unix-time-milliseconds | PERSIST]
+    GetEx(GetEx),
+    /// INFO [section [section ...]]
+    ServerInfo(ServerInfo),
+    /// JSON.GET key [path]
+    JsonGet(JsonGet),
+    /// JSON.SET key path value [NX | XX]
+    JsonSet(JsonSet),
+    /// LINSERT key BEFORE | AFTER pivot element
+    Linsert(Linsert),
+    /// LLEN key
+    Llen(Llen),
+    /// LMPOP numkeys key [key ...] LEFT | RIGHT [COUNT count]
+    Lmpop(Lmpop),
+    /// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+    Lpos(Lpos),
+    /// LREM key count element
+    Lrem(Lrem),
+    /// LSET key index element
+    Lset(Lset),
+    /// LTRIM key start stop
+    Ltrim(Ltrim),
+    /// OBJECT IDLETIME key
+    ObjectIdleTime(ObjectIdleTime),
+    /// OBJECT FREQ key
+    ObjectFreq(ObjectFreq),
+    /// LPOP key [count]
+    /// RPOP key [count]
+    Pop(Pop),
+    /// PUBLISH channel message
+    Publish(Publish),
+    /// LPUSH key element [element ...]
+    /// RPUSH key element [element ...]
+    Push(Push),
+    /// RENAME key newkey
+    /// RENAMENX key newkey
+    Rename(Rename),
+    /// SADD key member [member ...]
+    Sadd(Sadd),
+    /// SCANRANGE min max [COUNT count]
+    ScanRange(ScanRange),
+    /// SELECT name
+    Select(Select),
+    /// MULTI, EXEC, DISCARD, SUBSCRIBE, UNSUBSCRIBE, MONITOR, and RESET
+    Session(SessionCommand),
+    /// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+    ///   EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL] [SYNC]
     Set(Set),
+    /// SINTERCARD numkeys key [key ...] [LIMIT limit]
+    Sintercard(Sintercard),
+    /// UNLINK key [key ...]
+    Unlink(Unlink),
+    /// XADD key <* | id> field value [field value ...]
+    Xadd(Xadd),
+    /// XRANGE key start end [COUNT count]
+    Xrange(Xrange),
+    /// XREAD [COUNT count] STREAMS key [key ...] id [id ...]
+    Xread(Xread),
+    /// ZMPOP numkeys key [key ...] MIN | MAX [COUNT count]
+    Zmpop(Zmpop),
 }
 
 impl Command {
@@ -54,16 +247,120 @@ impl Command {
     pub async fn apply<KV>(
         self,
         storage: KV,
+        state: &Arc<State>,
         connection: &mut Connection,
-        _shutdown: &mut Shutdown,
+        shutdown: &mut Shutdown,
     ) -> Result<(), super::Error>
     where
         KV: KeyValueStorage,
     {
         match self {
+            Command::Audit(cmd) => cmd.apply(state, connection).await,
+            Command::BlockingPop(cmd) => cmd.apply(storage, state, connection, shutdown).await,
+            Command::Batch(cmd) => cmd.apply(storage, connection).await,
+            Command::BgPause(cmd) => cmd.apply(storage, connection).await,
+            Command::Client(cmd) => cmd.apply(state, connection).await,
+            Command::Info(cmd) => cmd.apply(connection).await,
+            Command::Copy(cmd) => cmd.apply(storage, connection).await,
+            Command::DataFiles(cmd) => cmd.apply(storage, connection).await,
             Command::Del(cmd) => cmd.apply(storage, connection).await,
+            Command::DelPrefix(cmd) => cmd.apply(storage, connection).await,
+            #[cfg(feature = "scripting")]
+            Command::Eval(cmd) => cmd.apply(storage, state.clone(), connection).await,
+            Command::Geoadd(cmd) => cmd.apply(storage, connection).await,
+            Command::Geosearch(cmd) => cmd.apply(storage, connection).await,
             Command::Get(cmd) => cmd.apply(storage, connection).await,
+            Command::GetEx(cmd) => cmd.apply(storage, connection).await,
+            Command::ServerInfo(cmd) => cmd.apply(storage, state, connection).await,
+            Command::JsonGet(cmd) => cmd.apply(storage, connection).await,
+            Command::JsonSet(cmd) => cmd.apply(storage, connection).await,
+            Command::Linsert(cmd) => cmd.apply(storage, connection).await,
+            Command::Llen(cmd) => cmd.apply(storage, connection).await,
+            Command::Lmpop(cmd) => cmd.apply(storage, connection).await,
+            Command::Lpos(cmd) => cmd.apply(storage, connection).await,
+            Command::Lrem(cmd) => cmd.apply(storage, connection).await,
+            Command::Lset(cmd) => cmd.apply(storage, connection).await,
+            Command::Ltrim(cmd) => cmd.apply(storage, connection).await,
+            Command::ObjectIdleTime(cmd) => cmd.apply(storage, connection).await,
+            Command::ObjectFreq(cmd) => cmd.apply(storage, connection).await,
+            Command::Pop(cmd) => cmd.apply(storage, connection).await,
+            Command::Publish(cmd) => cmd.apply(state, connection).await,
+            Command::Push(cmd) => cmd.apply(storage, state, connection).await,
+            Command::Rename(cmd) => cmd.apply(storage, connection).await,
+            Command::Sadd(cmd) => cmd.apply(storage, connection).await,
+            Command::ScanRange(cmd) => cmd.apply(storage, connection).await,
+            Command::Select(cmd) => cmd.apply(storage, connection).await,
+            // The state of the connection decides what these commands do, so the connection
+            // handles them instead of applying them
+            Command::Session(_) => {
+                let response = Frame::Error("ERR command is only allowed on a connection".into());
+                connection.write_frame(&response).await
+            }
             Command::Set(cmd) => cmd.apply(storage, connection).await,
+            Command::Sintercard(cmd) => cmd.apply(storage, connection).await,
+            Command::Unlink(cmd) => cmd.apply(storage, connection).await,
+            Command::Xadd(cmd) => cmd.apply(storage, connection).await,
+            Command::Xrange(cmd) => cmd.apply(storage, connection).await,
+            Command::Xread(cmd) => cmd.apply(storage, connection).await,
+            Command::Zmpop(cmd) => cmd.apply(storage, connection).await,
+        }
+    }
+
+    /// Get the class of the command.
+    pub fn class(&self) -> CommandClass {
+        match self.writes() {
+            Some(_) => CommandClass::Write,
+            None => CommandClass::Read,
+        }
+    }
+
+    /// Get the name of the command and the keys that it writes to, or `None` if the command
+    /// doesn't write to the storage.
+    pub fn writes(&self) -> Option<(&'static str, Vec<&Utf8Bytes>)> {
+        match self {
+            Command::Batch(cmd) => Some(cmd.writes()),
+            Command::BlockingPop(cmd) => Some(cmd.writes()),
+            Command::Copy(cmd) => Some(cmd.writes()),
+            Command::Del(cmd) => Some(cmd.writes()),
+            Command::DelPrefix(cmd) => Some(cmd.writes()),
+            #[cfg(feature = "scripting")]
+            Command::Eval(cmd) => Some(cmd.writes()),
+            Command::Geoadd(cmd) => Some(cmd.writes()),
+            Command::GetEx(cmd) => cmd.writes(),
+            Command::JsonSet(cmd) => Some(cmd.writes()),
+            Command::Linsert(cmd) => Some(cmd.writes()),
+            Command::Lmpop(cmd) => Some(cmd.writes()),
+            Command::Lrem(cmd) => Some(cmd.writes()),
+            Command::Lset(cmd) => Some(cmd.writes()),
+            Command::Ltrim(cmd) => Some(cmd.writes()),
+            Command::Pop(cmd) => Some(cmd.writes()),
+            Command::Push(cmd) => Some(cmd.writes()),
+            Command::Rename(cmd) => Some(cmd.writes()),
+            Command::Sadd(cmd) => Some(cmd.writes()),
+            Command::Set(cmd) => Some(cmd.writes()),
+            Command::Unlink(cmd) => Some(cmd.writes()),
+            Command::Xadd(cmd) => Some(cmd.writes()),
+            Command::Zmpop(cmd) => Some(cmd.writes()),
+            Command::Audit(_)
+            | Command::BgPause(_)
+            | Command::Client(_)
+            | Command::DataFiles(_)
+            | Command::Geosearch(_)
+            | Command::Get(_)
+            | Command::Info(_)
+            | Command::JsonGet(_)
+            | Command::Llen(_)
+            | Command::Lpos(_)
+            | Command::ObjectIdleTime(_)
+            | Command::ObjectFreq(_)
+            | Command::Publish(_)
+            | Command::ScanRange(_)
+            | Command::Select(_)
+            | Command::ServerInfo(_)
+            | Command::Session(_)
+            | Command::Sintercard(_)
+            | Command::Xrange(_)
+            | Command::Xread(_) => None,
         }
     }
 }
@@ -74,10 +371,10 @@ impl TryFrom<Frame> for Command {
     fn try_from(frame: Frame) -> Result<Self, Self::Error> {
         let mut parser = Parser::new(frame)?;
         match parser.get_bytes()? {
-            Some(b) if "DEL" == b => Ok(Command::Del(parser.try_into()?)),
-            Some(b) if "GET" == b => Ok(Command::Get(parser.try_into()?)),
-            Some(b) if "SET" == b => Ok(Command::Set(parser.try_into()?)),
-            Some(b) => Err(Error::BadCommand(String::from_utf8_lossy(&b).into())),
+            Some(b) => match CommandSpec::lookup(&b) {
+                Some(spec) => spec.parse(parser),
+                None => Err(Error::BadCommand(String::from_utf8_lossy(&b).into())),
+            },
             None => Err(Error::BadCommand("".into())),
         }
     }
@@ -127,12 +424,206 @@ impl Parser {
         }
     }
 
+    /// Parses the next value in the frame as an unsigned integer.
+    ///
+    /// Returns an integer if the next value is a bulk string containing its decimal
+    /// representation. Otherwise returns an error. Returns `None` if there's no value left.
+    fn get_integer(&mut self) -> Result<Option<u64>, Error> {
+        match self.get_string()? {
+            Some(s) => {
+                let s = std::str::from_utf8(s.as_ref())?;
+                let n = s
+                    .parse()
+                    .map_err(|_| Error::BadArguments("Value is not an integer or out of range"))?;
+                Ok(Some(n))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the next value in the frame as a signed integer.
+    ///
+    /// Returns an integer if the next value is a bulk string containing its decimal
+    /// representation. Otherwise returns an error. Returns `None` if there's no value left.
+    fn get_signed_integer(&mut self) -> Result<Option<i64>, Error> {
+        match self.get_string()? {
+            Some(s) => {
+                let n = s
+                    .as_str()
+                    .parse()
+                    .map_err(|_| Error::BadArguments("Value is not an integer or out of range"))?;
+                Ok(Some(n))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the next value in the frame as a floating point number.
+    ///
+    /// Returns a number if the next value is a bulk string containing its decimal
+    /// representation. Otherwise returns an error. Returns `None` if there's no value left.
+    fn get_float(&mut self) -> Result<Option<f64>, Error> {
+        match self.get_string()? {
+            Some(s) => Ok(Some(parse_float(s.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
     /// Ensure there are no more values
     fn finish(&mut self) -> bool {
         self.frames.next().is_none()
     }
 }
 
+impl TryFrom<Parser> for Audit {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let mut key = None;
+        let mut count = None;
+        while let Some(opt) = parser.get_string()? {
+            if opt.as_ref().eq_ignore_ascii_case(b"KEY") {
+                key = Some(
+                    parser
+                        .get_string()?
+                        .ok_or(Error::BadArguments("Key is not given"))?,
+                );
+            } else if opt.as_ref().eq_ignore_ascii_case(b"COUNT") {
+                count = Some(
+                    parser
+                        .get_integer()?
+                        .ok_or(Error::BadArguments("Count is not given"))?,
+                );
+            } else {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+        }
+        Ok(Self::new(key, count))
+    }
+}
+
+fn parse_bpop(end: ListEnd, mut parser: Parser) -> Result<BlockingPop, Error> {
+    let mut args = Vec::new();
+    while let Some(arg) = parser.get_string()? {
+        args.push(arg);
+    }
+    let timeout = args
+        .pop()
+        .ok_or(Error::BadArguments("Timeout is not given"))?;
+    let timeout = timeout
+        .as_str()
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or(Error::BadArguments(
+            "Timeout is not a float or out of range",
+        ))?;
+    if args.is_empty() {
+        return Err(Error::BadArguments("Keys are empty"));
+    }
+    Ok(BlockingPop::new(args, end, timeout))
+}
+
+impl TryFrom<Parser> for Batch {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let mut ops = Vec::new();
+        while let Some(op) = parser.get_string()? {
+            let op = op.as_ref();
+            let key = parser
+                .get_string()?
+                .ok_or(Error::BadArguments("Key is not given"))?;
+            if op.eq_ignore_ascii_case(b"SET") {
+                let value = parser
+                    .get_bytes()?
+                    .ok_or(Error::BadArguments("Value is not given"))?;
+                ops.push(BatchOp::Set(key, value));
+            } else if op.eq_ignore_ascii_case(b"DEL") {
+                ops.push(BatchOp::Del(key));
+            } else {
+                return Err(Error::BadArguments("Batch only supports SET and DEL"));
+            }
+        }
+        if ops.is_empty() {
+            return Err(Error::BadArguments("Batch is empty"));
+        }
+        Ok(Self::new(ops))
+    }
+}
+
+impl TryFrom<Parser> for CommandInfo {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let subcommand = match parser.get_string()? {
+            Some(subcommand) => subcommand,
+            None => return Ok(Self::All),
+        };
+        if subcommand.as_ref().eq_ignore_ascii_case(b"COUNT") {
+            if !parser.finish() {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+            Ok(Self::Count)
+        } else if subcommand.as_ref().eq_ignore_ascii_case(b"LIST") {
+            if !parser.finish() {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+            Ok(Self::List)
+        } else if subcommand.as_ref().eq_ignore_ascii_case(b"INFO") {
+            let mut names = Vec::new();
+            while let Some(name) = parser.get_string()? {
+                names.push(name);
+            }
+            Ok(Self::Info(names))
+        } else if subcommand.as_ref().eq_ignore_ascii_case(b"GETKEYS") {
+            let mut args = Vec::new();
+            while let Some(arg) = parser.get_bytes()? {
+                args.push(arg);
+            }
+            if args.is_empty() {
+                return Err(Error::BadArguments("Command is not given"));
+            }
+            Ok(Self::GetKeys(args))
+        } else {
+            Err(Error::BadArguments("Unknown subcommand"))
+        }
+    }
+}
+
+impl TryFrom<Parser> for Copy {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let src = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Source is not given"))?;
+        let dst = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Destination is not given"))?;
+        let replace = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"REPLACE") => true,
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => false,
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(src, dst, replace))
+    }
+}
+
+impl TryFrom<Parser> for DataFiles {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self)
+    }
+}
+
 impl TryFrom<Parser> for Del {
     type Error = Error;
 
@@ -148,6 +639,54 @@ impl TryFrom<Parser> for Del {
     }
 }
 
+impl TryFrom<Parser> for Unlink {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = parser.get_string()? {
+            keys.push(key)
+        }
+        if keys.is_empty() {
+            return Err(Error::BadArguments("Keys are empty"));
+        }
+        Ok(Self::new(keys))
+    }
+}
+
+impl TryFrom<Parser> for DelPrefix {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let prefix = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Prefix is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(prefix))
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn parse_eval(script: Script, mut parser: Parser) -> Result<Eval, Error> {
+    let numkeys = parser
+        .get_integer()?
+        .ok_or(Error::BadArguments("Number of keys is not given"))?;
+    let mut keys = Vec::new();
+    for _ in 0..numkeys {
+        let key = parser.get_string()?.ok_or(Error::BadArguments(
+            "Number of keys can't be greater than number of args",
+        ))?;
+        keys.push(key);
+    }
+    let mut args = Vec::new();
+    while let Some(arg) = parser.get_bytes()? {
+        args.push(arg);
+    }
+    Ok(Eval::new(script, keys, args))
+}
+
 impl TryFrom<Parser> for Get {
     type Error = Error;
 
@@ -162,179 +701,1799 @@ impl TryFrom<Parser> for Get {
     }
 }
 
-impl TryFrom<Parser> for Set {
+impl TryFrom<Parser> for JsonGet {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let path = parser.get_string()?.unwrap_or_else(|| "$".into());
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, path))
+    }
+}
+
+impl TryFrom<Parser> for JsonSet {
     type Error = Error;
 
     fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
         let key = parser
             .get_string()?
             .ok_or(Error::BadArguments("Key is not given"))?;
+        let path = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Path is not given"))?;
         let value = parser
             .get_bytes()?
             .ok_or(Error::BadArguments("Value is not given"))?;
+        let condition = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"NX") => Some(JsonSetCondition::Nx),
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"XX") => Some(JsonSetCondition::Xx),
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => None,
+        };
         if !parser.finish() {
             return Err(Error::BadArguments("Frame contains extra data"));
         }
-        Ok(Self::new(key, value))
+        Ok(Self::new(key, path, value, condition))
     }
 }
 
-/// A wrapper around [`bytes::Bytes`] that check if the underlying bytes represent a UTF8-encoded
-/// string. We use this to provide checks for UTF8 without having to copy from Bytes to String.
-///
-/// [`bytes::Bytes`]: bytes::Bytes
-#[derive(Debug, PartialEq, Eq)]
-pub struct Utf8Bytes(bytes::Bytes);
+impl TryFrom<Parser> for Geoadd {
+    type Error = Error;
 
-impl AsRef<Bytes> for Utf8Bytes {
-    fn as_ref(&self) -> &Bytes {
-        &self.0
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let mut condition = None;
+        let mut changed = false;
+        let mut members = Vec::new();
+        while let Some(arg) = parser.get_string()? {
+            let is_nx = arg.as_ref().eq_ignore_ascii_case(b"NX");
+            if (is_nx || arg.as_ref().eq_ignore_ascii_case(b"XX")) && members.is_empty() {
+                let c = if is_nx {
+                    SetCondition::Nx
+                } else {
+                    SetCondition::Xx
+                };
+                if condition.is_some_and(|prev| prev != c) {
+                    return Err(Error::BadArguments(
+                        "XX and NX options at the same time are not compatible",
+                    ));
+                }
+                condition = Some(c);
+            } else if arg.as_ref().eq_ignore_ascii_case(b"CH") && members.is_empty() {
+                changed = true;
+            } else {
+                let lon = parse_float(arg.as_str())?;
+                let lat = parser
+                    .get_float()?
+                    .ok_or(Error::BadArguments("Latitude is not given"))?;
+                let member = parser
+                    .get_bytes()?
+                    .ok_or(Error::BadArguments("Member is not given"))?;
+                let position = GeoPosition::new(lon, lat)
+                    .ok_or(Error::BadArguments("Invalid longitude,latitude pair"))?;
+                members.push((position, member));
+            }
+        }
+        if members.is_empty() {
+            return Err(Error::BadArguments("Members are not given"));
+        }
+        let mut geoadd = Self::new(key, members);
+        if let Some(condition) = condition {
+            geoadd = geoadd.with_condition(condition);
+        }
+        if changed {
+            geoadd = geoadd.with_changed();
+        }
+        Ok(geoadd)
     }
 }
 
-impl AsMut<Bytes> for Utf8Bytes {
-    fn as_mut(&mut self) -> &mut Bytes {
-        &mut self.0
+impl TryFrom<Parser> for Geosearch {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let mut origin = None;
+        let mut shape = None;
+        let mut unit = None;
+        let mut order = None;
+        let mut count = None;
+        let mut with_coord = false;
+        let mut with_dist = false;
+        let mut with_hash = false;
+        while let Some(opt) = parser.get_string()? {
+            let opt = opt.as_ref();
+            if opt.eq_ignore_ascii_case(b"FROMMEMBER") && origin.is_none() {
+                let member = parser
+                    .get_bytes()?
+                    .ok_or(Error::BadArguments("Member is not given"))?;
+                origin = Some(GeoOrigin::Member(member));
+            } else if opt.eq_ignore_ascii_case(b"FROMLONLAT") && origin.is_none() {
+                let lon = parser
+                    .get_float()?
+                    .ok_or(Error::BadArguments("Longitude is not given"))?;
+                let lat = parser
+                    .get_float()?
+                    .ok_or(Error::BadArguments("Latitude is not given"))?;
+                let position = GeoPosition::new(lon, lat)
+                    .ok_or(Error::BadArguments("Invalid longitude,latitude pair"))?;
+                origin = Some(GeoOrigin::Position(position));
+            } else if opt.eq_ignore_ascii_case(b"BYRADIUS") && shape.is_none() {
+                let radius = parse_geo_size(&mut parser)?;
+                shape = Some(GeoShape::Radius(radius));
+                unit = Some(parse_geo_unit(&mut parser)?);
+            } else if opt.eq_ignore_ascii_case(b"BYBOX") && shape.is_none() {
+                let width = parse_geo_size(&mut parser)?;
+                let height = parse_geo_size(&mut parser)?;
+                shape = Some(GeoShape::Box(width, height));
+                unit = Some(parse_geo_unit(&mut parser)?);
+            } else if opt.eq_ignore_ascii_case(b"ASC") {
+                order = Some(GeoOrder::Asc);
+            } else if opt.eq_ignore_ascii_case(b"DESC") {
+                order = Some(GeoOrder::Desc);
+            } else if opt.eq_ignore_ascii_case(b"COUNT") {
+                let n = parser
+                    .get_integer()?
+                    .ok_or(Error::BadArguments("Count is not given"))?;
+                if n == 0 {
+                    return Err(Error::BadArguments("Count must be positive"));
+                }
+                count = Some((n, false));
+            } else if opt.eq_ignore_ascii_case(b"ANY") {
+                match &mut count {
+                    Some((_, any)) => *any = true,
+                    None => return Err(Error::BadArguments("ANY requires COUNT")),
+                }
+            } else if opt.eq_ignore_ascii_case(b"WITHCOORD") {
+                with_coord = true;
+            } else if opt.eq_ignore_ascii_case(b"WITHDIST") {
+                with_dist = true;
+            } else if opt.eq_ignore_ascii_case(b"WITHHASH") {
+                with_hash = true;
+            } else {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+        }
+        let origin = origin.ok_or(Error::BadArguments(
+            "Exactly one of FROMMEMBER or FROMLONLAT must be given",
+        ))?;
+        let (shape, unit) = shape.zip(unit).ok_or(Error::BadArguments(
+            "Exactly one of BYRADIUS or BYBOX must be given",
+        ))?;
+        let mut geosearch = Self::new(key, origin, shape, unit);
+        if let Some(order) = order {
+            geosearch = geosearch.with_order(order);
+        }
+        if let Some((count, any)) = count {
+            geosearch = geosearch.with_count(count, any);
+        }
+        if with_coord {
+            geosearch = geosearch.with_coord();
+        }
+        if with_dist {
+            geosearch = geosearch.with_dist();
+        }
+        if with_hash {
+            geosearch = geosearch.with_hash();
+        }
+        Ok(geosearch)
     }
 }
 
-impl From<&str> for Utf8Bytes {
-    fn from(value: &str) -> Self {
-        Self(Bytes::from(value.to_string()))
+/// Parse the keys, the end, and the count of LMPOP and ZMPOP.
+fn parse_mpop(parser: &mut Parser) -> Result<(Vec<Utf8Bytes>, Utf8Bytes, Option<u64>), Error> {
+    let numkeys = parser
+        .get_integer()?
+        .ok_or(Error::BadArguments("Number of keys is not given"))?;
+    if numkeys == 0 {
+        return Err(Error::BadArguments("Number of keys can't be zero"));
     }
+    let mut keys = Vec::new();
+    for _ in 0..numkeys {
+        let key = parser.get_string()?.ok_or(Error::BadArguments(
+            "Number of keys can't be greater than number of args",
+        ))?;
+        keys.push(key);
+    }
+    let end = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("End is not given"))?;
+    let count = match parser.get_string()? {
+        Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"COUNT") => {
+            let count = parser
+                .get_integer()?
+                .ok_or(Error::BadArguments("Count is not given"))?;
+            if count == 0 {
+                return Err(Error::BadArguments("Count can't be zero"));
+            }
+            Some(count)
+        }
+        Some(_) => return Err(Error::BadArguments("Syntax error")),
+        None => None,
+    };
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    Ok((keys, end, count))
 }
 
-impl From<String> for Utf8Bytes {
-    fn from(value: String) -> Self {
-        Self(Bytes::from(value))
+/// Parse a floating point number that is not NaN.
+fn parse_float(s: &str) -> Result<f64, Error> {
+    match s.parse::<f64>() {
+        Ok(n) if !n.is_nan() => Ok(n),
+        _ => Err(Error::BadArguments("Value is not a valid float")),
     }
 }
 
-impl TryFrom<Bytes> for Utf8Bytes {
-    type Error = Error;
-
-    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
-        std::str::from_utf8(&value)?;
-        Ok(Self(value))
+/// Parse the radius, the width, or the height of a GEOSEARCH shape.
+fn parse_geo_size(parser: &mut Parser) -> Result<f64, Error> {
+    let size = parser
+        .get_float()?
+        .ok_or(Error::BadArguments("Size is not given"))?;
+    if size < 0.0 {
+        return Err(Error::BadArguments("Size can't be negative"));
     }
+    Ok(size)
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::net::frame::Frame;
+/// Parse the unit of a GEOSEARCH shape.
+fn parse_geo_unit(parser: &mut Parser) -> Result<GeoUnit, Error> {
+    let unit = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Unit is not given"))?;
+    GeoUnit::parse(unit.as_str()).ok_or(Error::BadArguments(
+        "Unsupported unit provided. please use M, KM, FT, MI",
+    ))
+}
 
-    use super::*;
+impl TryFrom<Parser> for Linsert {
+    type Error = Error;
 
-    #[test]
-    fn parse_get_ok() {
-        assert_command(
-            Frame::Array(vec![
-                Frame::BulkString("GET".into()),
-                Frame::BulkString("hello".into()),
-            ]),
-            Command::Get(Get::new("hello".into())),
-        )
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let before = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"BEFORE") => true,
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"AFTER") => false,
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => return Err(Error::BadArguments("Position is not given")),
+        };
+        let pivot = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Pivot is not given"))?;
+        let element = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Element is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, before, pivot, element))
     }
+}
 
-    #[test]
-    fn parse_get_no_key() {
-        assert_error(
-            Frame::Array(vec![Frame::BulkString("GET".into())]),
-            Error::BadArguments("Key is not given"),
-        )
-    }
+impl TryFrom<Parser> for Llen {
+    type Error = Error;
 
-    #[test]
-    fn parse_get_extra_keys() {
-        assert_error(
-            Frame::Array(vec![
-                Frame::BulkString("GET".into()),
-                Frame::BulkString("hello".into()),
-                Frame::BulkString("hello".into()),
-                Frame::BulkString("hello".into()),
-            ]),
-            Error::BadArguments("Frame contains extra data"),
-        )
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key))
     }
+}
 
-    #[test]
-    fn parse_set_ok() {
-        assert_command(
-            Frame::Array(vec![
-                Frame::BulkString("SET".into()),
-                Frame::BulkString("hello".into()),
-                Frame::BulkString("world".into()),
-            ]),
-            Command::Set(Set::new("hello".into(), "world".into())),
-        )
+impl TryFrom<Parser> for Lmpop {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let (keys, end, count) = parse_mpop(&mut parser)?;
+        let end = if end.as_ref().eq_ignore_ascii_case(b"LEFT") {
+            ListEnd::Left
+        } else if end.as_ref().eq_ignore_ascii_case(b"RIGHT") {
+            ListEnd::Right
+        } else {
+            return Err(Error::BadArguments("Syntax error"));
+        };
+        Ok(Self::new(keys, end, count))
     }
+}
 
-    #[test]
-    fn parse_set_no_key() {
-        assert_error(
-            Frame::Array(vec![Frame::BulkString("SET".into())]),
-            Error::BadArguments("Key is not given"),
-        )
+impl TryFrom<Parser> for Lpos {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let element = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Element is not given"))?;
+        let mut rank = None;
+        let mut count = None;
+        let mut maxlen = None;
+        while let Some(opt) = parser.get_string()? {
+            if opt.as_ref().eq_ignore_ascii_case(b"RANK") {
+                let r = parser
+                    .get_signed_integer()?
+                    .ok_or(Error::BadArguments("Rank is not given"))?;
+                if r == 0 {
+                    return Err(Error::BadArguments("Rank can't be zero"));
+                }
+                rank = Some(r);
+            } else if opt.as_ref().eq_ignore_ascii_case(b"COUNT") {
+                count = Some(
+                    parser
+                        .get_integer()?
+                        .ok_or(Error::BadArguments("Count is not given"))?,
+                );
+            } else if opt.as_ref().eq_ignore_ascii_case(b"MAXLEN") {
+                maxlen = Some(
+                    parser
+                        .get_integer()?
+                        .ok_or(Error::BadArguments("Max length is not given"))?,
+                );
+            } else {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+        }
+        Ok(Self::new(key, element, rank, count, maxlen))
+    }
+}
+
+impl TryFrom<Parser> for Lrem {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let count = parser
+            .get_signed_integer()?
+            .ok_or(Error::BadArguments("Count is not given"))?;
+        let element = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Element is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, count, element))
+    }
+}
+
+impl TryFrom<Parser> for Lset {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let index = parser
+            .get_signed_integer()?
+            .ok_or(Error::BadArguments("Index is not given"))?;
+        let element = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Element is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, index, element))
+    }
+}
+
+impl TryFrom<Parser> for Ltrim {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let start = parser
+            .get_signed_integer()?
+            .ok_or(Error::BadArguments("Start is not given"))?;
+        let stop = parser
+            .get_signed_integer()?
+            .ok_or(Error::BadArguments("Stop is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, start, stop))
+    }
+}
+
+fn parse_pop(end: ListEnd, mut parser: Parser) -> Result<Pop, Error> {
+    let key = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Key is not given"))?;
+    let count = parser.get_integer()?;
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    Ok(Pop::new(key, end, count))
+}
+
+fn parse_push(end: ListEnd, mut parser: Parser) -> Result<Push, Error> {
+    let key = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Key is not given"))?;
+    let mut elements = Vec::new();
+    while let Some(element) = parser.get_bytes()? {
+        elements.push(element);
+    }
+    if elements.is_empty() {
+        return Err(Error::BadArguments("Elements are empty"));
+    }
+    Ok(Push::new(key, end, elements))
+}
+
+fn parse_client(mut parser: Parser) -> Result<Command, Error> {
+    let subcommand = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Subcommand is not given"))?;
+    let is_subcommand = |name: &[u8]| subcommand.as_ref().eq_ignore_ascii_case(name);
+    let cmd = if is_subcommand(b"PAUSE") {
+        let timeout_ms = parser
+            .get_integer()?
+            .ok_or(Error::BadArguments("Timeout is not given"))?;
+        let mode = match parser.get_string()? {
+            None => PauseMode::All,
+            Some(s) if s.as_ref().eq_ignore_ascii_case(b"ALL") => PauseMode::All,
+            Some(s) if s.as_ref().eq_ignore_ascii_case(b"WRITE") => PauseMode::Write,
+            Some(_) => return Err(Error::BadArguments("Pause mode must be WRITE or ALL")),
+        };
+        ClientCommand::Pause { timeout_ms, mode }
+    } else if is_subcommand(b"UNPAUSE") {
+        ClientCommand::Unpause
+    } else {
+        return Err(Error::BadArguments(
+            "CLIENT only supports PAUSE and UNPAUSE",
+        ));
+    };
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    Ok(Command::Client(cmd))
+}
+
+fn parse_object(mut parser: Parser) -> Result<Command, Error> {
+    let subcommand = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Subcommand is not given"))?;
+    let is_subcommand = |name: &[u8]| subcommand.as_ref().eq_ignore_ascii_case(name);
+    if !is_subcommand(b"IDLETIME") && !is_subcommand(b"FREQ") {
+        return Err(Error::BadArguments(
+            "OBJECT only supports IDLETIME and FREQ",
+        ));
+    }
+    let key = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Key is not given"))?;
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    if is_subcommand(b"FREQ") {
+        Ok(Command::ObjectFreq(ObjectFreq::new(key)))
+    } else {
+        Ok(Command::ObjectIdleTime(ObjectIdleTime::new(key)))
+    }
+}
+
+impl TryFrom<Parser> for Publish {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let channel = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Channel is not given"))?;
+        let message = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Message is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(channel, message))
+    }
+}
+
+fn parse_rename(replace: bool, mut parser: Parser) -> Result<Rename, Error> {
+    let src = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("Key is not given"))?;
+    let dst = parser
+        .get_string()?
+        .ok_or(Error::BadArguments("New key is not given"))?;
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    Ok(Rename::new(src, dst, replace))
+}
+
+fn parse_session(cmd: SessionCommand, mut parser: Parser) -> Result<SessionCommand, Error> {
+    if !parser.finish() {
+        return Err(Error::BadArguments("Frame contains extra data"));
+    }
+    Ok(cmd)
+}
+
+fn parse_channels(mut parser: Parser) -> Result<Vec<Bytes>, Error> {
+    let mut channels = Vec::new();
+    while let Some(channel) = parser.get_bytes()? {
+        channels.push(channel);
+    }
+    Ok(channels)
+}
+
+impl TryFrom<Parser> for ServerInfo {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let mut sections = Vec::new();
+        while let Some(section) = parser.get_string()? {
+            sections.push(section);
+        }
+        Ok(Self::new(sections))
+    }
+}
+
+impl TryFrom<Parser> for ScanRange {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let start = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Min is not given"))?;
+        let end = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Max is not given"))?;
+        let start = scanrange::parse_bound(start, b"-")?;
+        let end = scanrange::parse_bound(end, b"+")?;
+        let count = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"COUNT") => Some(
+                parser
+                    .get_integer()?
+                    .ok_or(Error::BadArguments("Count is not given"))?,
+            ),
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => None,
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(start, end, count))
+    }
+}
+
+impl TryFrom<Parser> for Sadd {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let mut members = Vec::new();
+        while let Some(member) = parser.get_bytes()? {
+            members.push(member);
+        }
+        if members.is_empty() {
+            return Err(Error::BadArguments("Members are not given"));
+        }
+        Ok(Self::new(key, members))
+    }
+}
+
+impl TryFrom<Parser> for Sintercard {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let numkeys = parser
+            .get_integer()?
+            .ok_or(Error::BadArguments("Number of keys is not given"))?;
+        if numkeys == 0 {
+            return Err(Error::BadArguments("Number of keys can't be zero"));
+        }
+        let mut keys = Vec::new();
+        for _ in 0..numkeys {
+            let key = parser.get_string()?.ok_or(Error::BadArguments(
+                "Number of keys can't be greater than number of args",
+            ))?;
+            keys.push(key);
+        }
+        let limit = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"LIMIT") => Some(
+                parser
+                    .get_integer()?
+                    .ok_or(Error::BadArguments("Limit is not given"))?,
+            ),
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => None,
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(keys, limit))
+    }
+}
+
+impl TryFrom<Parser> for BgPause {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let paused = match parser.get_string()? {
+            None => true,
+            Some(s) if s.as_ref().eq_ignore_ascii_case(b"ON") => true,
+            Some(s) if s.as_ref().eq_ignore_ascii_case(b"OFF") => false,
+            Some(_) => return Err(Error::BadArguments("BGPAUSE takes ON or OFF")),
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(paused))
+    }
+}
+
+impl TryFrom<Parser> for Select {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let name = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Database is not given"))?;
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(name))
+    }
+}
+
+impl TryFrom<Parser> for Set {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let value = parser
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Value is not given"))?;
+        let mut set = Self::new(key, value);
+        let mut has_condition = false;
+        let mut has_expiry = false;
+        let mut has_get = false;
+        let mut has_sync = false;
+        while let Some(opt) = parser.get_string()? {
+            let opt = opt.as_ref();
+            if opt.eq_ignore_ascii_case(b"NX") && !has_condition {
+                has_condition = true;
+                set = set.with_condition(SetCondition::Nx);
+            } else if opt.eq_ignore_ascii_case(b"XX") && !has_condition {
+                has_condition = true;
+                set = set.with_condition(SetCondition::Xx);
+            } else if opt.eq_ignore_ascii_case(b"GET") && !has_get {
+                has_get = true;
+                set = set.with_get();
+            } else if opt.eq_ignore_ascii_case(b"SYNC") && !has_sync {
+                has_sync = true;
+                set = set.with_sync();
+            } else if opt.eq_ignore_ascii_case(b"KEEPTTL") && !has_expiry {
+                has_expiry = true;
+                set = set.with_expiry(Expiry::KeepTtl);
+            } else if let (Some(expiry), false) = (parse_expiry(opt, &mut parser)?, has_expiry) {
+                has_expiry = true;
+                set = set.with_expiry(expiry);
+            } else {
+                return Err(Error::BadArguments("Syntax error"));
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl TryFrom<Parser> for GetEx {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let expiry = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"PERSIST") => Some(Expiry::Persist),
+            Some(opt) => Some(
+                parse_expiry(opt.as_ref(), &mut parser)?
+                    .ok_or(Error::BadArguments("Syntax error"))?,
+            ),
+            None => None,
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, expiry))
+    }
+}
+
+/// Parse an expiry option that takes a time argument (EX, PX, EXAT, or PXAT). Returns `None` if
+/// the option is not one of them.
+fn parse_expiry(opt: &[u8], parser: &mut Parser) -> Result<Option<Expiry>, Error> {
+    let expiry: fn(u64) -> Expiry = if opt.eq_ignore_ascii_case(b"EX") {
+        Expiry::Ex
+    } else if opt.eq_ignore_ascii_case(b"PX") {
+        Expiry::Px
+    } else if opt.eq_ignore_ascii_case(b"EXAT") {
+        Expiry::ExAt
+    } else if opt.eq_ignore_ascii_case(b"PXAT") {
+        Expiry::PxAt
+    } else {
+        return Ok(None);
+    };
+    match parser.get_integer()? {
+        Some(0) => Err(Error::BadArguments("Invalid expire time")),
+        Some(n) => Ok(Some(expiry(n))),
+        None => Err(Error::BadArguments("Syntax error")),
+    }
+}
+
+impl TryFrom<Parser> for Xadd {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let id = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("ID is not given"))?;
+        let id = match id.as_str() {
+            "*" => XaddId::Auto,
+            id => match id.strip_suffix("-*") {
+                Some(ms) => XaddId::AutoSeq(ms.parse().map_err(|_| INVALID_STREAM_ID)?),
+                None => XaddId::Explicit(id.parse().map_err(|_| INVALID_STREAM_ID)?),
+            },
+        };
+        let mut fields = Vec::new();
+        while let Some(field) = parser.get_bytes()? {
+            let value = parser
+                .get_bytes()?
+                .ok_or(Error::BadArguments("Field is given without a value"))?;
+            fields.push((field, value));
+        }
+        if fields.is_empty() {
+            return Err(Error::BadArguments("Fields are empty"));
+        }
+        Ok(Self::new(key, id, fields))
+    }
+}
+
+impl TryFrom<Parser> for Xrange {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let key = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Key is not given"))?;
+        let start = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("Start is not given"))?;
+        let end = parser
+            .get_string()?
+            .ok_or(Error::BadArguments("End is not given"))?;
+        let start = xrange::parse_bound(start.as_str(), "-", 0).ok_or(INVALID_STREAM_ID)?;
+        let end = xrange::parse_bound(end.as_str(), "+", u64::MAX).ok_or(INVALID_STREAM_ID)?;
+        let count = match parser.get_string()? {
+            Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"COUNT") => Some(
+                parser
+                    .get_integer()?
+                    .ok_or(Error::BadArguments("Count is not given"))?,
+            ),
+            Some(_) => return Err(Error::BadArguments("Syntax error")),
+            None => None,
+        };
+        if !parser.finish() {
+            return Err(Error::BadArguments("Frame contains extra data"));
+        }
+        Ok(Self::new(key, start, end, count))
+    }
+}
+
+impl TryFrom<Parser> for Xread {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let mut count = None;
+        loop {
+            match parser.get_string()? {
+                Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"COUNT") => {
+                    count = Some(
+                        parser
+                            .get_integer()?
+                            .ok_or(Error::BadArguments("Count is not given"))?,
+                    );
+                }
+                Some(opt) if opt.as_ref().eq_ignore_ascii_case(b"STREAMS") => break,
+                _ => return Err(Error::BadArguments("Syntax error")),
+            }
+        }
+        let mut args = Vec::new();
+        while let Some(arg) = parser.get_string()? {
+            args.push(arg);
+        }
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(Error::BadArguments("Unbalanced list of streams and IDs"));
+        }
+        let ids = args.split_off(args.len() / 2);
+        let streams = args
+            .into_iter()
+            .zip(ids)
+            .map(|(key, id)| match id.as_str() {
+                "$" => Ok((key, None)),
+                id => Ok((key, Some(id.parse().map_err(|_| INVALID_STREAM_ID)?))),
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self::new(count, streams))
+    }
+}
+
+impl TryFrom<Parser> for Zmpop {
+    type Error = Error;
+
+    fn try_from(mut parser: Parser) -> Result<Self, Self::Error> {
+        let (keys, end, count) = parse_mpop(&mut parser)?;
+        let end = if end.as_ref().eq_ignore_ascii_case(b"MIN") {
+            ZsetEnd::Min
+        } else if end.as_ref().eq_ignore_ascii_case(b"MAX") {
+            ZsetEnd::Max
+        } else {
+            return Err(Error::BadArguments("Syntax error"));
+        };
+        Ok(Self::new(keys, end, count))
+    }
+}
+
+/// The error returned when a stream ID can't be parsed.
+const INVALID_STREAM_ID: Error = Error::BadArguments("Invalid stream ID");
+
+/// A wrapper around [`bytes::Bytes`] that check if the underlying bytes represent a UTF8-encoded
+/// string. We use this to provide checks for UTF8 without having to copy from Bytes to String.
+///
+/// [`bytes::Bytes`]: bytes::Bytes
+#[derive(Debug, PartialEq, Eq)]
+pub struct Utf8Bytes(bytes::Bytes);
+
+impl Utf8Bytes {
+    /// Get the underlying bytes as a string slice.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("bytes are checked to be UTF-8")
+    }
+}
+
+impl AsRef<Bytes> for Utf8Bytes {
+    fn as_ref(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl AsMut<Bytes> for Utf8Bytes {
+    fn as_mut(&mut self) -> &mut Bytes {
+        &mut self.0
+    }
+}
+
+impl From<&str> for Utf8Bytes {
+    fn from(value: &str) -> Self {
+        Self(Bytes::from(value.to_string()))
+    }
+}
+
+impl From<String> for Utf8Bytes {
+    fn from(value: String) -> Self {
+        Self(Bytes::from(value))
+    }
+}
+
+impl TryFrom<Bytes> for Utf8Bytes {
+    type Error = Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        std::str::from_utf8(&value)?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::net::frame::Frame;
+
+    use super::*;
+
+    #[test]
+    fn parse_get_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("GET".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Command::Get(Get::new("hello".into())),
+        )
+    }
+
+    #[test]
+    fn parse_get_no_key() {
+        assert_error(
+            Frame::Array(vec![Frame::BulkString("GET".into())]),
+            Error::BadArguments("Key is not given"),
+        )
+    }
+
+    #[test]
+    fn parse_get_extra_keys() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("GET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Error::BadArguments("Frame contains extra data"),
+        )
+    }
+
+    #[test]
+    fn parse_set_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+            ]),
+            Command::Set(Set::new("hello".into(), "world".into())),
+        )
+    }
+
+    #[test]
+    fn parse_set_no_key() {
+        assert_error(
+            Frame::Array(vec![Frame::BulkString("SET".into())]),
+            Error::BadArguments("Key is not given"),
+        )
+    }
+
+    #[test]
+    fn parse_set_no_value() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Error::BadArguments("Value is not given"),
+        )
+    }
+
+    #[test]
+    fn parse_set_extra_data() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Error::BadArguments("Syntax error"),
+        )
+    }
+
+    #[test]
+    fn parse_del_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("DEL".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Command::Del(Del::new(vec!["hello".into()])),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("DEL".into()),
+                Frame::BulkString("hello1".into()),
+                Frame::BulkString("hello2".into()),
+                Frame::BulkString("hello3".into()),
+                Frame::BulkString("hello4".into()),
+                Frame::BulkString("hello5".into()),
+            ]),
+            Command::Del(Del::new(vec![
+                "hello1".into(),
+                "hello2".into(),
+                "hello3".into(),
+                "hello4".into(),
+                "hello5".into(),
+            ])),
+        );
+    }
+
+    #[test]
+    fn parse_unlink_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("UNLINK".into()),
+                Frame::BulkString("hello1".into()),
+                Frame::BulkString("hello2".into()),
+            ]),
+            Command::Unlink(Unlink::new(vec!["hello1".into(), "hello2".into()])),
+        );
+    }
+
+    #[test]
+    fn parse_delprefix_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("DELPREFIX".into()),
+                Frame::BulkString("user:".into()),
+            ]),
+            Command::DelPrefix(DelPrefix::new("user:".into())),
+        )
+    }
+
+    #[test]
+    fn parse_del_no_key() {
+        assert_error(
+            Frame::Array(vec![Frame::BulkString("DEL".into())]),
+            Error::BadArguments("Keys are empty"),
+        )
+    }
+
+    #[test]
+    fn parse_scanrange_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SCANRANGE".into()),
+                Frame::BulkString("[a".into()),
+                Frame::BulkString("(c".into()),
+            ]),
+            Command::ScanRange(ScanRange::new(
+                Bound::Included("a".into()),
+                Bound::Excluded("c".into()),
+                None,
+            )),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SCANRANGE".into()),
+                Frame::BulkString("-".into()),
+                Frame::BulkString("+".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("10".into()),
+            ]),
+            Command::ScanRange(ScanRange::new(Bound::Unbounded, Bound::Unbounded, Some(10))),
+        );
+    }
+
+    #[test]
+    fn parse_scanrange_bad_range_item() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SCANRANGE".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("+".into()),
+            ]),
+            Error::BadArguments("Range item is not valid"),
+        )
+    }
+
+    #[test]
+    fn parse_json_get_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("JSON.GET".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Command::JsonGet(JsonGet::new("hello".into(), "$".into())),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("JSON.GET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("$.a".into()),
+            ]),
+            Command::JsonGet(JsonGet::new("hello".into(), "$.a".into())),
+        );
+    }
+
+    #[test]
+    fn parse_json_set_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("JSON.SET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("$".into()),
+                Frame::BulkString("{}".into()),
+                Frame::BulkString("NX".into()),
+            ]),
+            Command::JsonSet(JsonSet::new(
+                "hello".into(),
+                "$".into(),
+                "{}".into(),
+                Some(JsonSetCondition::Nx),
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_json_set_no_value() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("JSON.SET".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("$".into()),
+            ]),
+            Error::BadArguments("Value is not given"),
+        )
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn parse_eval_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("EVAL".into()),
+                Frame::BulkString("return 1".into()),
+                Frame::BulkString("1".into()),
+                Frame::BulkString("key".into()),
+                Frame::BulkString("arg".into()),
+            ]),
+            Command::Eval(Eval::new(
+                Script::Source("return 1".into()),
+                vec!["key".into()],
+                vec!["arg".into()],
+            )),
+        );
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn parse_eval_too_many_keys() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("EVALSHA".into()),
+                Frame::BulkString("abc".into()),
+                Frame::BulkString("2".into()),
+                Frame::BulkString("key".into()),
+            ]),
+            Error::BadArguments("Number of keys can't be greater than number of args"),
+        );
+    }
+
+    #[test]
+    fn parse_xadd_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("XADD".into()),
+                Frame::BulkString("stream".into()),
+                Frame::BulkString("5-*".into()),
+                Frame::BulkString("field".into()),
+                Frame::BulkString("value".into()),
+            ]),
+            Command::Xadd(Xadd::new(
+                "stream".into(),
+                XaddId::AutoSeq(5),
+                vec![("field".into(), "value".into())],
+            )),
+        )
+    }
+
+    #[test]
+    fn parse_xadd_unbalanced_fields() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("XADD".into()),
+                Frame::BulkString("stream".into()),
+                Frame::BulkString("*".into()),
+                Frame::BulkString("field".into()),
+            ]),
+            Error::BadArguments("Field is given without a value"),
+        )
+    }
+
+    #[test]
+    fn parse_xrange_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("XRANGE".into()),
+                Frame::BulkString("stream".into()),
+                Frame::BulkString("(1-2".into()),
+                Frame::BulkString("3".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("10".into()),
+            ]),
+            Command::Xrange(Xrange::new(
+                "stream".into(),
+                Bound::Excluded(StreamId::new(1, 2)),
+                Bound::Included(StreamId::new(3, u64::MAX)),
+                Some(10),
+            )),
+        )
+    }
+
+    #[test]
+    fn parse_xread_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("XREAD".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("2".into()),
+                Frame::BulkString("STREAMS".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("0-1".into()),
+                Frame::BulkString("$".into()),
+            ]),
+            Command::Xread(Xread::new(
+                Some(2),
+                vec![("a".into(), Some(StreamId::new(0, 1))), ("b".into(), None)],
+            )),
+        )
+    }
+
+    #[test]
+    fn parse_xread_unbalanced_streams() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("XREAD".into()),
+                Frame::BulkString("STREAMS".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("0".into()),
+            ]),
+            Error::BadArguments("Unbalanced list of streams and IDs"),
+        )
+    }
+
+    #[test]
+    fn parse_list_commands_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("LPOS".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("RANK".into()),
+                Frame::BulkString("-2".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("0".into()),
+            ]),
+            Command::Lpos(Lpos::new(
+                "list".into(),
+                "a".into(),
+                Some(-2),
+                Some(0),
+                None,
+            )),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("LINSERT".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("after".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+            ]),
+            Command::Linsert(Linsert::new("list".into(), false, "a".into(), "b".into())),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("LTRIM".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("1".into()),
+                Frame::BulkString("-1".into()),
+            ]),
+            Command::Ltrim(Ltrim::new("list".into(), 1, -1)),
+        );
+    }
+
+    #[test]
+    fn parse_lpos_zero_rank() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("LPOS".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("RANK".into()),
+                Frame::BulkString("0".into()),
+            ]),
+            Error::BadArguments("Rank can't be zero"),
+        );
+    }
+
+    #[test]
+    fn parse_mpop_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("LMPOP".into()),
+                Frame::BulkString("2".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("right".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("3".into()),
+            ]),
+            Command::Lmpop(Lmpop::new(
+                vec!["a".into(), "b".into()],
+                ListEnd::Right,
+                Some(3),
+            )),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("ZMPOP".into()),
+                Frame::BulkString("1".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("MIN".into()),
+            ]),
+            Command::Zmpop(Zmpop::new(vec!["a".into()], ZsetEnd::Min, None)),
+        );
+    }
+
+    #[test]
+    fn parse_sintercard() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SINTERCARD".into()),
+                Frame::BulkString("2".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("limit".into()),
+                Frame::BulkString("5".into()),
+            ]),
+            Command::Sintercard(Sintercard::new(vec!["a".into(), "b".into()], Some(5))),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SINTERCARD".into()),
+                Frame::BulkString("3".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+            ]),
+            Error::BadArguments("Number of keys can't be greater than number of args"),
+        );
+    }
+
+    #[test]
+    fn parse_mpop_invalid() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("LMPOP".into()),
+                Frame::BulkString("0".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("LEFT".into()),
+            ]),
+            Error::BadArguments("Number of keys can't be zero"),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("ZMPOP".into()),
+                Frame::BulkString("3".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("MAX".into()),
+            ]),
+            Error::BadArguments("Number of keys can't be greater than number of args"),
+        );
     }
 
     #[test]
-    fn parse_set_no_value() {
+    fn parse_geo_commands_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("GEOADD".into()),
+                Frame::BulkString("Sicily".into()),
+                Frame::BulkString("XX".into()),
+                Frame::BulkString("CH".into()),
+                Frame::BulkString("13.361389".into()),
+                Frame::BulkString("38.115556".into()),
+                Frame::BulkString("Palermo".into()),
+            ]),
+            Command::Geoadd(
+                Geoadd::new(
+                    "Sicily".into(),
+                    vec![(
+                        GeoPosition::new(13.361389, 38.115556).unwrap(),
+                        "Palermo".into(),
+                    )],
+                )
+                .with_condition(SetCondition::Xx)
+                .with_changed(),
+            ),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("GEOSEARCH".into()),
+                Frame::BulkString("Sicily".into()),
+                Frame::BulkString("FROMLONLAT".into()),
+                Frame::BulkString("15".into()),
+                Frame::BulkString("37".into()),
+                Frame::BulkString("BYBOX".into()),
+                Frame::BulkString("400".into()),
+                Frame::BulkString("300".into()),
+                Frame::BulkString("km".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("2".into()),
+                Frame::BulkString("ANY".into()),
+                Frame::BulkString("WITHDIST".into()),
+            ]),
+            Command::Geosearch(
+                Geosearch::new(
+                    "Sicily".into(),
+                    GeoOrigin::Position(GeoPosition::new(15.0, 37.0).unwrap()),
+                    GeoShape::Box(400.0, 300.0),
+                    GeoUnit::Km,
+                )
+                .with_count(2, true)
+                .with_dist(),
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_geo_commands_invalid() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("GEOADD".into()),
+                Frame::BulkString("Sicily".into()),
+                Frame::BulkString("181".into()),
+                Frame::BulkString("38".into()),
+                Frame::BulkString("Palermo".into()),
+            ]),
+            Error::BadArguments("Invalid longitude,latitude pair"),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("GEOSEARCH".into()),
+                Frame::BulkString("Sicily".into()),
+                Frame::BulkString("FROMMEMBER".into()),
+                Frame::BulkString("Palermo".into()),
+                Frame::BulkString("BYRADIUS".into()),
+                Frame::BulkString("10".into()),
+                Frame::BulkString("yd".into()),
+            ]),
+            Error::BadArguments("Unsupported unit provided. please use M, KM, FT, MI"),
+        );
+    }
+
+    #[test]
+    fn parse_push_and_pop_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("RPUSH".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+            ]),
+            Command::Push(Push::new(
+                "list".into(),
+                ListEnd::Right,
+                vec!["a".into(), "b".into()],
+            )),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("LPOP".into()),
+                Frame::BulkString("list".into()),
+                Frame::BulkString("2".into()),
+            ]),
+            Command::Pop(Pop::new("list".into(), ListEnd::Left, Some(2))),
+        );
+    }
+
+    #[test]
+    fn parse_bpop_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("BRPOP".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("0.5".into()),
+            ]),
+            Command::BlockingPop(BlockingPop::new(
+                vec!["a".into(), "b".into()],
+                ListEnd::Right,
+                Duration::from_millis(500),
+            )),
+        )
+    }
+
+    #[test]
+    fn parse_bpop_negative_timeout() {
         assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("BLPOP".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("-1".into()),
+            ]),
+            Error::BadArguments("Timeout is not a float or out of range"),
+        )
+    }
+
+    #[test]
+    fn parse_set_with_options_ok() {
+        assert_command(
             Frame::Array(vec![
                 Frame::BulkString("SET".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+                Frame::BulkString("nx".into()),
+                Frame::BulkString("GET".into()),
+                Frame::BulkString("PX".into()),
+                Frame::BulkString("100".into()),
             ]),
-            Error::BadArguments("Value is not given"),
+            Command::Set(
+                Set::new("hello".into(), "world".into())
+                    .with_condition(SetCondition::Nx)
+                    .with_get()
+                    .with_expiry(Expiry::Px(100)),
+            ),
         )
     }
 
     #[test]
-    fn parse_set_extra_data() {
-        assert_error(
+    fn parse_set_with_sync_ok() {
+        assert_command(
             Frame::Array(vec![
                 Frame::BulkString("SET".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+                Frame::BulkString("sync".into()),
+            ]),
+            Command::Set(Set::new("hello".into(), "world".into()).with_sync()),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+                Frame::BulkString("SYNC".into()),
+                Frame::BulkString("SYNC".into()),
+            ]),
+            Error::BadArguments("Syntax error"),
+        );
+    }
+
+    #[test]
+    fn parse_set_conflicting_options() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+                Frame::BulkString("EX".into()),
+                Frame::BulkString("10".into()),
+                Frame::BulkString("KEEPTTL".into()),
+            ]),
+            Error::BadArguments("Syntax error"),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("SET".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("world".into()),
+                Frame::BulkString("NX".into()),
+                Frame::BulkString("XX".into()),
             ]),
-            Error::BadArguments("Frame contains extra data"),
-        )
+            Error::BadArguments("Syntax error"),
+        );
     }
 
     #[test]
-    fn parse_del_ok() {
+    fn parse_getex_ok() {
         assert_command(
             Frame::Array(vec![
-                Frame::BulkString("DEL".into()),
+                Frame::BulkString("GETEX".into()),
                 Frame::BulkString("hello".into()),
+                Frame::BulkString("PERSIST".into()),
             ]),
-            Command::Del(Del::new(vec!["hello".into()])),
+            Command::GetEx(GetEx::new("hello".into(), Some(Expiry::Persist))),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("GETEX".into()),
+                Frame::BulkString("hello".into()),
+                Frame::BulkString("EX".into()),
+                Frame::BulkString("0".into()),
+            ]),
+            Error::BadArguments("Invalid expire time"),
         );
+    }
+
+    #[test]
+    fn parse_batch_ok() {
         assert_command(
             Frame::Array(vec![
-                Frame::BulkString("DEL".into()),
-                Frame::BulkString("hello1".into()),
-                Frame::BulkString("hello2".into()),
-                Frame::BulkString("hello3".into()),
-                Frame::BulkString("hello4".into()),
-                Frame::BulkString("hello5".into()),
+                Frame::BulkString("BATCH".into()),
+                Frame::BulkString("SET".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("1".into()),
+                Frame::BulkString("del".into()),
+                Frame::BulkString("b".into()),
             ]),
-            Command::Del(Del::new(vec![
-                "hello1".into(),
-                "hello2".into(),
-                "hello3".into(),
-                "hello4".into(),
-                "hello5".into(),
+            Command::Batch(Batch::new(vec![
+                BatchOp::Set("a".into(), "1".into()),
+                BatchOp::Del("b".into()),
             ])),
+        )
+    }
+
+    #[test]
+    fn parse_batch_unsupported_op() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("BATCH".into()),
+                Frame::BulkString("GET".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Error::BadArguments("Batch only supports SET and DEL"),
+        )
+    }
+
+    #[test]
+    fn parse_copy_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("COPY".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+                Frame::BulkString("REPLACE".into()),
+            ]),
+            Command::Copy(Copy::new("a".into(), "b".into(), true)),
+        )
+    }
+
+    #[test]
+    fn parse_renamenx_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("RENAMENX".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+            ]),
+            Command::Rename(Rename::new("a".into(), "b".into(), false)),
+        )
+    }
+
+    #[test]
+    fn parse_rename_no_new_key() {
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("RENAME".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Error::BadArguments("New key is not given"),
+        )
+    }
+
+    #[test]
+    fn parse_object_idletime_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("OBJECT".into()),
+                Frame::BulkString("idletime".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Command::ObjectIdleTime(ObjectIdleTime::new("a".into())),
+        )
+    }
+
+    #[test]
+    fn parse_info_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("INFO".into())]),
+            Command::ServerInfo(ServerInfo::new(vec![])),
         );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("INFO".into()),
+                Frame::BulkString("hotkeys".into()),
+            ]),
+            Command::ServerInfo(ServerInfo::new(vec!["hotkeys".into()])),
+        )
     }
 
     #[test]
-    fn parse_del_no_key() {
+    fn parse_bgpause_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("BGPAUSE".into())]),
+            Command::BgPause(BgPause::new(true)),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("BGPAUSE".into()),
+                Frame::BulkString("off".into()),
+            ]),
+            Command::BgPause(BgPause::new(false)),
+        )
+    }
+
+    #[test]
+    fn parse_datafiles_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("DATAFILES".into())]),
+            Command::DataFiles(DataFiles),
+        );
+    }
+
+    #[test]
+    fn parse_select_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SELECT".into()),
+                Frame::BulkString("tenant".into()),
+            ]),
+            Command::Select(Select::new("tenant".into())),
+        )
+    }
+
+    #[test]
+    fn parse_object_freq_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("OBJECT".into()),
+                Frame::BulkString("freq".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Command::ObjectFreq(ObjectFreq::new("a".into())),
+        )
+    }
+
+    #[test]
+    fn parse_client_pause_ok() {
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("CLIENT".into()),
+                Frame::BulkString("pause".into()),
+                Frame::BulkString("500".into()),
+                Frame::BulkString("write".into()),
+            ]),
+            Command::Client(ClientCommand::Pause {
+                timeout_ms: 500,
+                mode: PauseMode::Write,
+            }),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("CLIENT".into()),
+                Frame::BulkString("PAUSE".into()),
+                Frame::BulkString("500".into()),
+            ]),
+            Command::Client(ClientCommand::Pause {
+                timeout_ms: 500,
+                mode: PauseMode::All,
+            }),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("CLIENT".into()),
+                Frame::BulkString("UNPAUSE".into()),
+            ]),
+            Command::Client(ClientCommand::Unpause),
+        );
+    }
+
+    #[test]
+    fn parse_object_unsupported_subcommand() {
         assert_error(
-            Frame::Array(vec![Frame::BulkString("DEL".into())]),
-            Error::BadArguments("Keys are empty"),
+            Frame::Array(vec![
+                Frame::BulkString("OBJECT".into()),
+                Frame::BulkString("ENCODING".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Error::BadArguments("OBJECT only supports IDLETIME and FREQ"),
         )
     }
 
@@ -346,6 +2505,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_audit_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("AUDIT".into())]),
+            Command::Audit(Audit::new(None, None)),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("AUDIT".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("5".into()),
+                Frame::BulkString("key".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            Command::Audit(Audit::new(Some("hello".into()), Some(5))),
+        );
+    }
+
+    #[test]
+    fn parse_session_commands_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("RESET".into())]),
+            Command::Session(SessionCommand::Reset),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("SUBSCRIBE".into()),
+                Frame::BulkString("a".into()),
+                Frame::BulkString("b".into()),
+            ]),
+            Command::Session(SessionCommand::Subscribe(vec!["a".into(), "b".into()])),
+        );
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("UNSUBSCRIBE".into())]),
+            Command::Session(SessionCommand::Unsubscribe(Vec::new())),
+        );
+        assert_error(
+            Frame::Array(vec![Frame::BulkString("SUBSCRIBE".into())]),
+            Error::BadArguments("Channels are empty"),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("MULTI".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Error::BadArguments("Frame contains extra data"),
+        );
+    }
+
+    #[test]
+    fn parse_command_ok() {
+        assert_command(
+            Frame::Array(vec![Frame::BulkString("COMMAND".into())]),
+            Command::Info(CommandInfo::All),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("command".into()),
+                Frame::BulkString("info".into()),
+                Frame::BulkString("get".into()),
+                Frame::BulkString("set".into()),
+            ]),
+            Command::Info(CommandInfo::Info(vec!["get".into(), "set".into()])),
+        );
+        assert_command(
+            Frame::Array(vec![
+                Frame::BulkString("COMMAND".into()),
+                Frame::BulkString("GETKEYS".into()),
+                Frame::BulkString("DEL".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Command::Info(CommandInfo::GetKeys(vec!["DEL".into(), "a".into()])),
+        );
+        assert_error(
+            Frame::Array(vec![
+                Frame::BulkString("COMMAND".into()),
+                Frame::BulkString("COUNT".into()),
+                Frame::BulkString("a".into()),
+            ]),
+            Error::BadArguments("Syntax error"),
+        );
+    }
+
+    #[test]
+    fn writes_of_commands() {
+        let cmd = Command::try_from(Frame::Array(vec![
+            Frame::BulkString("RENAMENX".into()),
+            Frame::BulkString("a".into()),
+            Frame::BulkString("b".into()),
+        ]))
+        .unwrap();
+        let (name, keys) = cmd.writes().unwrap();
+        assert_eq!("RENAMENX", name);
+        assert_eq!(
+            vec!["a", "b"],
+            keys.iter().map(|k| k.as_str()).collect::<Vec<_>>()
+        );
+
+        let cmd = Command::Get(Get::new("hello".into()));
+        assert_eq!(None, cmd.writes());
+        let cmd = Command::GetEx(GetEx::new("hello".into(), None));
+        assert_eq!(None, cmd.writes());
+    }
+
     fn assert_command(frame: Frame, cmd: Command) {
         let parsed = Command::try_from(frame).unwrap();
         assert_eq!(parsed, cmd);
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::net::{self, audit::AuditRecord, connection::Connection, frame::Frame, State};
+
+use super::Utf8Bytes;
+
+/// The number of records that are sent back when COUNT isn't given.
+const DEFAULT_COUNT: u64 = 10;
+
+/// Arguments for AUDIT command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Audit {
+    /// Only return the records of the writes to this key
+    key: Option<Utf8Bytes>,
+    /// The max number of records to return
+    count: Option<u64>,
+}
+
+impl Audit {
+    /// Creates a new set of arguments
+    pub fn new(key: Option<Utf8Bytes>, count: Option<u64>) -> Self {
+        Self { key, count }
+    }
+
+    /// Send back the most recent records of the server's audit log, newest first. Each record is
+    /// an array of the Unix time in milliseconds, the client address, the command name, and the
+    /// keys that were written.
+    #[tracing::instrument(skip(self, state, connection))]
+    pub async fn apply(
+        self,
+        state: &Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error> {
+        let response = match state.audit_log() {
+            Some(audit_log) => {
+                let audit_log = Arc::clone(audit_log);
+                let count = self.count.unwrap_or(DEFAULT_COUNT) as usize;
+                let records = net::spawn_blocking(move || {
+                    audit_log.query(self.key.as_ref().map(Utf8Bytes::as_str), count)
+                })
+                .await??;
+                Frame::Array(records.into_iter().map(record_frame).collect())
+            }
+            None => Frame::Error("ERR audit log is disabled".to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+fn record_frame(record: AuditRecord) -> Frame {
+    Frame::Array(vec![
+        Frame::Integer(i64::try_from(record.time_ms).unwrap_or(i64::MAX)),
+        Frame::BulkString(record.client.into()),
+        Frame::BulkString(record.command.into()),
+        Frame::Array(
+            record
+                .keys
+                .into_iter()
+                .map(|k| Frame::BulkString(k.into()))
+                .collect(),
+        ),
+    ])
+}
+
+impl From<Audit> for Frame {
+    fn from(cmd: Audit) -> Self {
+        let mut cmd_data = vec![Self::BulkString("AUDIT".into())];
+        if let Some(key) = cmd.key {
+            cmd_data.push(Self::BulkString("KEY".into()));
+            cmd_data.push(Self::BulkString(key.as_ref().clone()));
+        }
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,109 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{stream, Utf8Bytes};
+
+/// Arguments for BATCH command, a non-standard command that applies a list of writes atomically.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+/// A write within a batch.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Set the value of the key, clearing its expiry.
+    Set(Utf8Bytes, Bytes),
+    /// Delete the key.
+    Del(Utf8Bytes),
+}
+
+impl Batch {
+    /// Creates a new set of arguments.
+    ///
+    /// BATCH requires that the list of writes must have at least 1 element
+    pub fn new(ops: Vec<BatchOp>) -> Self {
+        Self { ops }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let keys = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Set(key, _) | BatchOp::Del(key) => key,
+            })
+            .collect();
+        ("BATCH", keys)
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Apply all writes within a single atomic operation, collecting the status of each one.
+        // If any write fails, none are made and the client only gets the error.
+        let statuses = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let mut statuses = Vec::with_capacity(self.ops.len());
+                for op in self.ops {
+                    match op {
+                        BatchOp::Set(key, value) => {
+                            let key = key.as_ref().clone();
+                            stream::delete_chunks(txn, key.clone())?;
+                            txn.set(key, value)?;
+                            statuses.push(Frame::SimpleString("OK".to_string()));
+                        }
+                        BatchOp::Del(key) => {
+                            let key = key.as_ref().clone();
+                            stream::delete_chunks(txn, key.clone())?;
+                            let deleted = txn.del(key)?;
+                            statuses.push(Frame::Integer(i64::from(deleted)));
+                        }
+                    }
+                }
+                Ok(statuses)
+            })
+        })
+        .await?
+        .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+
+        // Responding with the status of each write in the order they were given
+        let response = Frame::Array(statuses);
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Batch> for Frame {
+    fn from(cmd: Batch) -> Self {
+        let mut cmd_data = vec![Self::BulkString("BATCH".into())];
+        for op in cmd.ops {
+            match op {
+                BatchOp::Set(key, value) => {
+                    cmd_data.push(Self::BulkString("SET".into()));
+                    cmd_data.push(Self::BulkString(key.as_ref().clone()));
+                    cmd_data.push(Self::BulkString(value));
+                }
+                BatchOp::Del(key) => {
+                    cmd_data.push(Self::BulkString("DEL".into()));
+                    cmd_data.push(Self::BulkString(key.as_ref().clone()));
+                }
+            }
+        }
+        Self::Array(cmd_data)
+    }
+}
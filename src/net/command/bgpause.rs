@@ -0,0 +1,50 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+/// Arguments for BGPAUSE command
+#[derive(Debug, PartialEq, Eq)]
+pub struct BgPause {
+    /// Whether the background maintenance is paused or resumed
+    paused: bool,
+}
+
+impl BgPause {
+    /// Creates a new set of arguments
+    pub fn new(paused: bool) -> Self {
+        Self { paused }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Suspend or resume the merges and the disk synchronizations that run in the background
+        net::spawn_blocking(move || storage.pause_maintenance(self.paused))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+        let response = Frame::SimpleString("OK".to_string());
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<BgPause> for Frame {
+    fn from(cmd: BgPause) -> Self {
+        let state = if cmd.paused { "ON" } else { "OFF" };
+        Self::Array(vec![
+            Self::BulkString("BGPAUSE".into()),
+            Self::BulkString(state.into()),
+        ])
+    }
+}
@@ -0,0 +1,146 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use tokio::time::{self, Instant};
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame, State},
+    shutdown::Shutdown,
+    storage::{KeyValueStorage, Update},
+};
+
+use super::{
+    list::{self, ListEnd},
+    Utf8Bytes,
+};
+
+/// Arguments for BLPOP and BRPOP commands
+#[derive(Debug, PartialEq, Eq)]
+pub struct BlockingPop {
+    /// The keys of the lists, checked in the given order
+    keys: Vec<Utf8Bytes>,
+    /// The end of the list from which the element is popped
+    end: ListEnd,
+    /// The max duration to block for, or zero to block indefinitely
+    timeout: Duration,
+}
+
+impl BlockingPop {
+    /// Creates a new set of arguments.
+    ///
+    /// BLPOP and BRPOP require that the list of keys must have at least 1 element
+    pub fn new(keys: Vec<Utf8Bytes>, end: ListEnd, timeout: Duration) -> Self {
+        Self { keys, end, timeout }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let name = match self.end {
+            ListEnd::Left => "BLPOP",
+            ListEnd::Right => "BRPOP",
+        };
+        (name, self.keys.iter().collect())
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance. When all the lists are
+    /// empty, the connection waits until an element is pushed to one of the lists, the timeout
+    /// elapses, or the server shuts down.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, state, connection, shutdown))]
+    pub async fn apply<KV>(
+        self,
+        storage: KV,
+        state: &Arc<State>,
+        connection: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        let keys: Vec<Bytes> = self.keys.iter().map(|k| k.as_ref().clone()).collect();
+        let database = storage.selected_database();
+        let deadline = (!self.timeout.is_zero()).then(|| Instant::now() + self.timeout);
+        let response = loop {
+            // The waiter is registered before checking the lists, so pushes that happen after
+            // the check are not missed.
+            let waiter = state.register_waiter(&database, &keys);
+            if let Some(response) = pop_first(storage.clone(), keys.clone(), self.end).await? {
+                break response;
+            }
+            let timeout = async {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = waiter.wait() => continue,
+                _ = timeout => break Frame::Null,
+                _ = shutdown.recv() => return Ok(()),
+            }
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Pop an element from the first non-empty list. Returns `None` if all the lists are empty.
+async fn pop_first<KV>(
+    storage: KV,
+    keys: Vec<Bytes>,
+    end: ListEnd,
+) -> Result<Option<Frame>, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            for key in keys {
+                let value = txn.get(key.clone())?;
+                let (update, result) = list::pop(value, end, 1);
+                match update {
+                    Update::Keep => {}
+                    Update::Set(value) => txn.set(key.clone(), value)?,
+                    Update::Delete => {
+                        txn.del(key.clone())?;
+                    }
+                }
+                match result {
+                    Ok(mut elements) if !elements.is_empty() => {
+                        return Ok(Some(Frame::Array(vec![
+                            Frame::BulkString(key),
+                            Frame::BulkString(elements.remove(0)),
+                        ])));
+                    }
+                    Ok(_) => continue,
+                    Err(e) => return Ok(Some(Frame::Error(e.to_string()))),
+                }
+            }
+            Ok(None)
+        })
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))
+}
+
+impl From<BlockingPop> for Frame {
+    fn from(cmd: BlockingPop) -> Self {
+        let name = match cmd.end {
+            ListEnd::Left => "BLPOP",
+            ListEnd::Right => "BRPOP",
+        };
+        let mut cmd_data = vec![Self::BulkString(name.into())];
+        for key in cmd.keys {
+            cmd_data.push(Self::BulkString(key.as_ref().clone()));
+        }
+        cmd_data.push(Self::BulkString(
+            cmd.timeout.as_secs_f64().to_string().into(),
+        ));
+        Self::Array(cmd_data)
+    }
+}
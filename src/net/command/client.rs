@@ -0,0 +1,64 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::net::{self, connection::Connection, frame::Frame, PauseMode, State};
+
+/// Arguments for CLIENT command
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientCommand {
+    /// CLIENT PAUSE timeout [WRITE | ALL]
+    Pause {
+        /// The number of milliseconds that the clients are paused for
+        timeout_ms: u64,
+        /// The commands that are held
+        mode: PauseMode,
+    },
+    /// CLIENT UNPAUSE
+    Unpause,
+}
+
+impl ClientCommand {
+    /// Pause or unpause the server's clients. Held commands wait until the pause ends and are
+    /// then applied in the order they were sent.
+    #[tracing::instrument(skip(self, state, connection))]
+    pub async fn apply(
+        self,
+        state: &Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error> {
+        match self {
+            ClientCommand::Pause { timeout_ms, mode } => {
+                let until = Instant::now() + Duration::from_millis(timeout_ms);
+                state.pause().pause(until, mode);
+            }
+            ClientCommand::Unpause => state.pause().unpause(),
+        }
+        let response = Frame::SimpleString("OK".to_string());
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<ClientCommand> for Frame {
+    fn from(cmd: ClientCommand) -> Self {
+        let mut cmd_data = vec![Self::BulkString("CLIENT".into())];
+        match cmd {
+            ClientCommand::Pause { timeout_ms, mode } => {
+                cmd_data.push(Self::BulkString("PAUSE".into()));
+                cmd_data.push(Self::BulkString(timeout_ms.to_string().into()));
+                let mode = match mode {
+                    PauseMode::Write => "WRITE",
+                    PauseMode::All => "ALL",
+                };
+                cmd_data.push(Self::BulkString(mode.into()));
+            }
+            ClientCommand::Unpause => cmd_data.push(Self::BulkString("UNPAUSE".into())),
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,96 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{stream, value::Value, Utf8Bytes};
+
+/// Arguments for COPY command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Copy {
+    /// The key to copy from
+    src: Utf8Bytes,
+    /// The key to copy to
+    dst: Utf8Bytes,
+    /// Whether the destination key is replaced if it exists
+    replace: bool,
+}
+
+impl Copy {
+    /// Creates a new set of arguments
+    pub fn new(src: Utf8Bytes, dst: Utf8Bytes, replace: bool) -> Self {
+        Self { src, dst, replace }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("COPY", vec![&self.dst])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        if self.src == self.dst {
+            let response = Frame::Error("ERR source and destination objects are the same".into());
+            connection.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        // Copy the value within a single atomic operation
+        let response = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let src = self.src.as_ref().clone();
+                let dst = self.dst.as_ref().clone();
+                let Some(value) = txn.get(src.clone())?.map(Value::decode) else {
+                    return Ok(Frame::Integer(0));
+                };
+                if txn.get(dst.clone())?.is_some() {
+                    if !self.replace {
+                        return Ok(Frame::Integer(0));
+                    }
+                    stream::delete_chunks(txn, dst.clone())?;
+                }
+                match value {
+                    // Streams are deep-copied, so the copy doesn't share chunks with the source
+                    Value::Stream(s) => {
+                        let copy = s.duplicate(txn)?;
+                        let expires_at = txn.get_expiry(src)?;
+                        txn.set_with_expiry(dst, Value::Stream(copy).encode(), expires_at)?;
+                    }
+                    _ => {
+                        txn.copy(src, dst, true)?;
+                    }
+                }
+                Ok(Frame::Integer(1))
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Copy> for Frame {
+    fn from(cmd: Copy) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("COPY".into()),
+            Self::BulkString(cmd.src.as_ref().clone()),
+            Self::BulkString(cmd.dst.as_ref().clone()),
+        ];
+        if cmd.replace {
+            cmd_data.push(Self::BulkString("REPLACE".into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
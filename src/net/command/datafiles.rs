@@ -0,0 +1,69 @@
+use std::time::UNIX_EPOCH;
+
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{FileStats, KeyValueStorage},
+};
+
+/// Arguments for DATAFILES command
+#[derive(Debug, PartialEq, Eq)]
+pub struct DataFiles;
+
+impl DataFiles {
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        let files = net::spawn_blocking(move || storage.file_stats())
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+        let response = Frame::Array(files.iter().map(describe).collect());
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Describe a data file by an array of field names followed by their values. The fragmentation
+/// is given as a string, since RESP2 has no floating point numbers, and the modification time in
+/// milliseconds since the Unix epoch.
+fn describe(file: &FileStats) -> Frame {
+    let modified = file
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let fields = [
+        ("id", Frame::Integer(file.fileid as i64)),
+        ("size", Frame::Integer(file.size as i64)),
+        ("live_keys", Frame::Integer(file.live_keys as i64)),
+        ("dead_keys", Frame::Integer(file.dead_keys as i64)),
+        ("dead_bytes", Frame::Integer(file.dead_bytes as i64)),
+        (
+            "fragmentation",
+            Frame::BulkString(format!("{:.4}", file.fragmentation).into()),
+        ),
+        ("modified", Frame::Integer(modified)),
+        ("hint", Frame::Integer(i64::from(file.has_hintfile))),
+    ];
+    Frame::Array(
+        fields
+            .into_iter()
+            .flat_map(|(name, value)| [Frame::BulkString(name.into()), value])
+            .collect(),
+    )
+}
+
+impl From<DataFiles> for Frame {
+    fn from(_: DataFiles) -> Self {
+        Self::Array(vec![Self::BulkString("DATAFILES".into())])
+    }
+}
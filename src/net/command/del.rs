@@ -5,7 +5,7 @@ use crate::{
     storage::KeyValueStorage,
 };
 
-use super::Utf8Bytes;
+use super::{stream, Utf8Bytes};
 
 /// Arguments for DEL command
 #[derive(Debug, PartialEq, Eq)]
@@ -21,6 +21,11 @@ impl Del {
         Self { keys }
     }
 
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("DEL", self.keys.iter().collect())
+    }
+
     /// Apply the command to the specified [`StorageEngine`] instance.
     ///
     /// [`StorageEngine`]: crate::StorageEngine;
@@ -29,17 +34,20 @@ impl Del {
     where
         KV: KeyValueStorage,
     {
-        // Delete the keys and count the number of deletions
-        let count = tokio::task::spawn_blocking(move || {
-            let mut count = 0;
-            for key in self.keys {
-                match storage.del(key.as_ref().clone()) {
-                    Ok(true) => count += 1,
-                    Ok(false) => continue,
-                    Err(e) => return Err(e),
-                };
-            }
-            Ok(count)
+        // Delete the keys and count the number of deletions. Values that are made of multiple
+        // entries in the storage are removed together with their entries.
+        let count = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let mut count = 0;
+                for key in self.keys {
+                    let key = key.as_ref().clone();
+                    stream::delete_chunks(txn, key.clone())?;
+                    if txn.del(key)? {
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            })
         })
         .await?
         .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
@@ -0,0 +1,100 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{stream, Utf8Bytes};
+
+/// The number of keys that are deleted in each transaction, so other writes aren't held for the
+/// whole deletion.
+const BATCH_SIZE: usize = 1024;
+
+/// Arguments for DELPREFIX command
+#[derive(Debug, PartialEq, Eq)]
+pub struct DelPrefix {
+    prefix: Utf8Bytes,
+}
+
+impl DelPrefix {
+    /// Creates a new set of arguments
+    pub fn new(prefix: Utf8Bytes) -> Self {
+        Self { prefix }
+    }
+
+    /// Get the name of the command and the keys that it writes to. The prefix stands for the keys
+    /// that it covers.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("DELPREFIX", vec![&self.prefix])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // An empty prefix covers every key, which is refused so a mistake can't wipe the storage
+        let prefix = self.prefix.as_ref().clone();
+        let response = if prefix.is_empty() {
+            Frame::Error("ERR prefix must not be empty".to_string())
+        } else {
+            let count = net::spawn_blocking(move || delete_prefix(&storage, prefix))
+                .await?
+                .map_err(|e| net::Error::Storage(e.into()))?;
+            Frame::Integer(count)
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Delete the keys starting with the prefix in batches and return the number of deleted keys.
+/// Values that are made of multiple entries in the storage are removed together with their
+/// entries, like with DEL.
+fn delete_prefix<KV>(storage: &KV, prefix: Bytes) -> Result<i64, KV::Error>
+where
+    KV: KeyValueStorage,
+{
+    let mut start = Bound::Included(prefix.clone());
+    let mut count = 0;
+    loop {
+        let keys: Vec<_> = storage
+            .scan_range(start, Bound::Unbounded, BATCH_SIZE)?
+            .into_iter()
+            .take_while(|key| key.starts_with(&prefix))
+            .collect();
+        let Some(last) = keys.last().cloned() else {
+            return Ok(count);
+        };
+        count += storage.atomically(move |txn| {
+            let mut count = 0;
+            for key in keys {
+                stream::delete_chunks(txn, key.clone())?;
+                if txn.del(key)? {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })?;
+        start = Bound::Excluded(last);
+    }
+}
+
+impl From<DelPrefix> for Frame {
+    fn from(cmd: DelPrefix) -> Self {
+        Self::Array(vec![
+            Self::BulkString("DELPREFIX".into()),
+            Self::BulkString(cmd.prefix.as_ref().clone()),
+        ])
+    }
+}
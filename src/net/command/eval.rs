@@ -0,0 +1,395 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Value, Variadic};
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame, State},
+    storage::{KeyValueStorage, Transaction},
+};
+
+use super::{stream, Utf8Bytes};
+
+/// Arguments for EVAL and EVALSHA commands
+#[derive(Debug, PartialEq, Eq)]
+pub struct Eval {
+    /// The script's source when run with EVAL, or its SHA1 digest when run with EVALSHA
+    script: Script,
+    /// Names of the keys accessed by the script
+    keys: Vec<Utf8Bytes>,
+    /// Additional arguments given to the script
+    args: Vec<Bytes>,
+}
+
+/// A script given to EVAL or EVALSHA.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Script {
+    /// The script's source.
+    Source(Bytes),
+    /// The SHA1 digest of a previously run script.
+    Sha(Utf8Bytes),
+}
+
+impl Eval {
+    /// Creates a new set of arguments
+    pub fn new(script: Script, keys: Vec<Utf8Bytes>, args: Vec<Bytes>) -> Self {
+        Self { script, keys, args }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let name = match self.script {
+            Script::Source(_) => "EVAL",
+            Script::Sha(_) => "EVALSHA",
+        };
+        (name, self.keys.iter().collect())
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance. The script is run while
+    /// holding exclusive write access to the storage, so it's executed atomically, and it's
+    /// aborted once it runs for longer than the server's script time limit.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, state, connection))]
+    pub async fn apply<KV>(
+        self,
+        storage: KV,
+        state: Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        let source = match self.script {
+            Script::Source(source) => {
+                state.cache_script(source.clone());
+                source
+            }
+            Script::Sha(sha) => match state.get_script(sha.as_str()) {
+                Some(source) => source,
+                None => {
+                    let response =
+                        Frame::Error("NOSCRIPT No matching script. Please use EVAL.".into());
+                    connection.write_frame(&response).await?;
+                    return Ok(());
+                }
+            },
+        };
+
+        // Run the script
+        let keys = self.keys;
+        let args = self.args;
+        let time_limit = state.script_time_limit();
+        let response = net::spawn_blocking(move || {
+            storage.atomically(move |txn| Ok(run(txn, &source, keys, args, time_limit)))
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Number of instructions that a script runs between checks of its time limit.
+const INSTRUCTIONS_PER_TIME_CHECK: u32 = 1000;
+
+/// Run the script with the `KEYS` and `ARGV` tables and `redis.call` bound to
+/// the given transaction, then convert the result into a frame. The script is
+/// aborted with an error once it runs for longer than `time_limit`.
+fn run<E>(
+    txn: &mut dyn Transaction<Error = E>,
+    source: &[u8],
+    keys: Vec<Utf8Bytes>,
+    args: Vec<Bytes>,
+    time_limit: Duration,
+) -> Frame
+where
+    E: std::error::Error,
+{
+    let lua = match sandbox(time_limit) {
+        Ok(lua) => lua,
+        Err(e) => return Frame::Error(format!("ERR Error running script - {e}")),
+    };
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+        let keys = keys
+            .iter()
+            .map(|k| lua.create_string(k.as_ref()))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let args = args
+            .iter()
+            .map(|a| lua.create_string(a))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        globals.set("KEYS", lua.create_sequence_from(keys)?)?;
+        globals.set("ARGV", lua.create_sequence_from(args)?)?;
+
+        let call = scope
+            .create_function_mut(|lua, args: Variadic<mlua::String<'_>>| call(txn, lua, args))?;
+        let redis = lua.create_table()?;
+        redis.set("call", call)?;
+        globals.set("redis", redis)?;
+
+        let value: Value<'_> = lua.load(source).eval()?;
+        Ok(into_frame(value))
+    });
+    match result {
+        Ok(frame) => frame,
+        Err(e) => Frame::Error(format!("ERR Error running script - {e}")),
+    }
+}
+
+/// Create a Lua state that can only use the string, table, and math libraries
+/// on top of the basic functions, without the functions that load code from
+/// strings or files. Scripts that run for longer than `time_limit` are aborted.
+fn sandbox(time_limit: Duration) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    for name in ["load", "loadfile", "dofile", "require"] {
+        lua.globals().set(name, Value::Nil)?;
+    }
+    let deadline = Instant::now() + time_limit;
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_TIME_CHECK),
+        move |_, _| {
+            if Instant::now() >= deadline {
+                return Err(mlua::Error::runtime(
+                    "Script killed after running for longer than the time limit",
+                ));
+            }
+            Ok(())
+        },
+    );
+    Ok(lua)
+}
+
+/// Execute a command that is made through `redis.call`.
+fn call<'lua, E>(
+    txn: &mut dyn Transaction<Error = E>,
+    lua: &'lua Lua,
+    args: Variadic<mlua::String<'lua>>,
+) -> mlua::Result<MultiValue<'lua>>
+where
+    E: std::error::Error,
+{
+    let mut args = args
+        .iter()
+        .map(|a| Bytes::copy_from_slice(a.as_bytes()))
+        .collect::<Vec<_>>()
+        .into_iter();
+    let name = args.next().ok_or_else(|| {
+        mlua::Error::runtime("Please specify at least one argument for this call")
+    })?;
+    let storage_error = |e: E| mlua::Error::runtime(e.to_string());
+    let wrong_args = || mlua::Error::runtime("Wrong number of args calling command from script");
+    let value = if name.eq_ignore_ascii_case(b"GET") {
+        let (Some(key), None) = (args.next(), args.next()) else {
+            return Err(wrong_args());
+        };
+        match txn.get(key).map_err(storage_error)? {
+            Some(value) => Value::String(lua.create_string(&value)?),
+            None => Value::Boolean(false),
+        }
+    } else if name.eq_ignore_ascii_case(b"SET") {
+        let (Some(key), Some(value), None) = (args.next(), args.next(), args.next()) else {
+            return Err(wrong_args());
+        };
+        stream::delete_chunks(txn, key.clone()).map_err(storage_error)?;
+        txn.set(key, value).map_err(storage_error)?;
+        let ok = lua.create_table()?;
+        ok.set("ok", "OK")?;
+        Value::Table(ok)
+    } else if name.eq_ignore_ascii_case(b"DEL") {
+        let mut count = 0;
+        for key in args {
+            stream::delete_chunks(txn, key.clone()).map_err(storage_error)?;
+            if txn.del(key).map_err(storage_error)? {
+                count += 1;
+            }
+        }
+        Value::Integer(count)
+    } else {
+        return Err(mlua::Error::runtime(format!(
+            "Unknown command called from script - {}",
+            String::from_utf8_lossy(&name)
+        )));
+    };
+    Ok(MultiValue::from_vec(vec![value]))
+}
+
+/// Convert a Lua value into a frame following the conversion rules of Redis.
+fn into_frame(value: Value<'_>) -> Frame {
+    match value {
+        Value::Nil | Value::Boolean(false) => Frame::Null,
+        Value::Boolean(true) => Frame::Integer(1),
+        Value::Integer(n) => Frame::Integer(n),
+        Value::Number(n) => Frame::Integer(n as i64),
+        Value::String(s) => Frame::BulkString(Bytes::copy_from_slice(s.as_bytes())),
+        Value::Table(t) => {
+            if let Ok(mlua::Value::String(s)) = t.raw_get("ok") {
+                return Frame::SimpleString(s.to_string_lossy().into_owned());
+            }
+            if let Ok(mlua::Value::String(s)) = t.raw_get("err") {
+                return Frame::Error(s.to_string_lossy().into_owned());
+            }
+            // Arrays are truncated at the first nil, like Redis does
+            let items = t
+                .sequence_values::<Value<'_>>()
+                .map_while(Result::ok)
+                .map(into_frame)
+                .collect();
+            Frame::Array(items)
+        }
+        _ => Frame::Null,
+    }
+}
+
+impl From<Eval> for Frame {
+    fn from(cmd: Eval) -> Self {
+        let mut cmd_data = match cmd.script {
+            Script::Source(source) => {
+                vec![Self::BulkString("EVAL".into()), Self::BulkString(source)]
+            }
+            Script::Sha(sha) => vec![
+                Self::BulkString("EVALSHA".into()),
+                Self::BulkString(sha.as_ref().clone()),
+            ],
+        };
+        cmd_data.push(Self::BulkString(cmd.keys.len().to_string().into()));
+        for key in cmd.keys {
+            cmd_data.push(Self::BulkString(key.as_ref().clone()));
+        }
+        for arg in cmd.args {
+            cmd_data.push(Self::BulkString(arg));
+        }
+        Self::Array(cmd_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const TIME_LIMIT: Duration = Duration::from_secs(5);
+
+    #[derive(Default)]
+    struct MemoryTransaction(HashMap<Bytes, Bytes>);
+
+    impl Transaction for MemoryTransaction {
+        type Error = std::io::Error;
+
+        fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+            self.0.insert(key, value);
+            Ok(())
+        }
+
+        fn set_with_expiry(
+            &mut self,
+            key: Bytes,
+            value: Bytes,
+            _: Option<std::time::SystemTime>,
+        ) -> Result<(), Self::Error> {
+            self.set(key, value)
+        }
+
+        fn get(&mut self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+            Ok(self.0.get(&key).cloned())
+        }
+
+        fn get_expiry(&mut self, _: Bytes) -> Result<Option<std::time::SystemTime>, Self::Error> {
+            Ok(None)
+        }
+
+        fn del(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+            Ok(self.0.remove(&key).is_some())
+        }
+    }
+
+    #[test]
+    fn run_script_with_keys_and_args() {
+        let mut txn = MemoryTransaction::default();
+        let script = br#"
+            redis.call('SET', KEYS[1], ARGV[1])
+            redis.call('SET', KEYS[2], ARGV[2])
+            return {redis.call('GET', KEYS[1]), redis.call('DEL', KEYS[1], KEYS[2], 'x')}
+        "#;
+        let frame = run(
+            &mut txn,
+            script,
+            vec!["a".into(), "b".into()],
+            vec!["1".into(), "2".into()],
+            TIME_LIMIT,
+        );
+        assert_eq!(
+            Frame::Array(vec![Frame::BulkString("1".into()), Frame::Integer(2)]),
+            frame
+        );
+        assert!(txn.0.is_empty());
+    }
+
+    #[test]
+    fn run_script_reports_errors() {
+        let mut txn = MemoryTransaction::default();
+        let frame = run(
+            &mut txn,
+            b"return redis.call('FOO')",
+            vec![],
+            vec![],
+            TIME_LIMIT,
+        );
+        assert!(matches!(frame, Frame::Error(_)));
+        let frame = run(&mut txn, b"return {err='boom'}", vec![], vec![], TIME_LIMIT);
+        assert_eq!(Frame::Error("boom".into()), frame);
+        let frame = run(
+            &mut txn,
+            b"return redis.call('GET', 'x')",
+            vec![],
+            vec![],
+            TIME_LIMIT,
+        );
+        assert_eq!(Frame::Null, frame);
+    }
+
+    #[test]
+    fn run_script_without_os_io_or_loaders() {
+        let mut txn = MemoryTransaction::default();
+        let script = br#"
+            return {os == nil, io == nil, load == nil, loadfile == nil, dofile == nil,
+                    require == nil, string.upper('ok')}
+        "#;
+        let frame = run(&mut txn, script, vec![], vec![], TIME_LIMIT);
+        let mut items = vec![Frame::Integer(1); 6];
+        items.push(Frame::BulkString("OK".into()));
+        assert_eq!(Frame::Array(items), frame);
+        let frame = run(&mut txn, b"os.execute('true')", vec![], vec![], TIME_LIMIT);
+        assert!(matches!(frame, Frame::Error(_)));
+    }
+
+    #[test]
+    fn run_script_aborts_runaway_loops() {
+        let mut txn = MemoryTransaction::default();
+        let started = Instant::now();
+        let frame = run(
+            &mut txn,
+            b"while true do end",
+            vec![],
+            vec![],
+            Duration::from_millis(100),
+        );
+        let Frame::Error(e) = frame else {
+            panic!("expected an error, got {frame:?}");
+        };
+        assert!(e.contains("time limit"), "{e}");
+        assert!(started.elapsed() < TIME_LIMIT);
+    }
+}
@@ -0,0 +1,48 @@
+//! Expiry options that are shared by SET and GETEX.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+/// An option that changes the expiry of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// EX seconds -- Expire after the given number of seconds.
+    Ex(u64),
+    /// PX milliseconds -- Expire after the given number of milliseconds.
+    Px(u64),
+    /// EXAT timestamp-seconds -- Expire at the given Unix time, in seconds.
+    ExAt(u64),
+    /// PXAT timestamp-milliseconds -- Expire at the given Unix time, in milliseconds.
+    PxAt(u64),
+    /// KEEPTTL -- Retain the expiry of the key. Only accepted by SET.
+    KeepTtl,
+    /// PERSIST -- Remove the expiry of the key. Only accepted by GETEX.
+    Persist,
+}
+
+impl Expiry {
+    /// Get the name of the option and its argument as they are sent in a command.
+    pub(super) fn to_args(self) -> Vec<Bytes> {
+        match self {
+            Self::Ex(n) => vec!["EX".into(), n.to_string().into()],
+            Self::Px(n) => vec!["PX".into(), n.to_string().into()],
+            Self::ExAt(n) => vec!["EXAT".into(), n.to_string().into()],
+            Self::PxAt(n) => vec!["PXAT".into(), n.to_string().into()],
+            Self::KeepTtl => vec!["KEEPTTL".into()],
+            Self::Persist => vec!["PERSIST".into()],
+        }
+    }
+
+    /// Get the time at which the key expires when the option is applied at `now`. Returns `None`
+    /// for the options that don't set a new expiry.
+    pub(super) fn expires_at(self, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Ex(secs) => Some(now + Duration::from_secs(secs)),
+            Self::Px(millis) => Some(now + Duration::from_millis(millis)),
+            Self::ExAt(secs) => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            Self::PxAt(millis) => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+            Self::KeepTtl | Self::Persist => None,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Geospatial indexes are sorted sets whose scores are the 52-bit geohashes of the members'
+//! positions, encoded the same way as Redis so the scores can be compared with the ones given by
+//! Redis. Since a sorted set is read as a whole, searches check the distance to every member
+//! instead of narrowing the search to the geohash ranges around the center.
+
+use bytes::Bytes;
+
+use super::zset::SortedSet;
+
+/// The number of bits used for each of the longitude and the latitude.
+const STEP: u32 = 26;
+
+/// The min and max longitudes that can be indexed.
+const LON_RANGE: (f64, f64) = (-180.0, 180.0);
+
+/// The min and max latitudes that can be indexed, the same as EPSG:900913 / EPSG:3785.
+const LAT_RANGE: (f64, f64) = (-85.05112878, 85.05112878);
+
+/// The radius of the Earth in meters, as used by Redis.
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// A position given by its longitude and latitude in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPosition {
+    lon: f64,
+    lat: f64,
+}
+
+impl GeoPosition {
+    /// Create a position, returning `None` if it can't be indexed.
+    pub fn new(lon: f64, lat: f64) -> Option<Self> {
+        let valid = (LON_RANGE.0..=LON_RANGE.1).contains(&lon)
+            && (LAT_RANGE.0..=LAT_RANGE.1).contains(&lat);
+        valid.then_some(Self { lon, lat })
+    }
+
+    /// Get the longitude in degrees.
+    pub fn lon(&self) -> f64 {
+        self.lon
+    }
+
+    /// Get the latitude in degrees.
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    /// Encode the position as a 52-bit geohash, where the latitude's bits are at the even
+    /// positions and the longitude's bits are at the odd positions.
+    pub(super) fn encode(&self) -> u64 {
+        let scale = |v: f64, (min, max): (f64, f64)| {
+            let offset = (v - min) / (max - min) * f64::from(1u32 << STEP);
+            (offset as u64).min((1 << STEP) - 1)
+        };
+        interleave(scale(self.lat, LAT_RANGE), scale(self.lon, LON_RANGE))
+    }
+
+    /// Decode the geohash into the position at the center of its cell.
+    pub(super) fn decode(hash: u64) -> Self {
+        let (lat, lon) = deinterleave(hash);
+        let unscale = |v: u64, (min, max): (f64, f64)| {
+            let cell = (max - min) / f64::from(1u32 << STEP);
+            min + (v as f64 + 0.5) * cell
+        };
+        Self {
+            lon: unscale(lon, LON_RANGE).clamp(LON_RANGE.0, LON_RANGE.1),
+            lat: unscale(lat, LAT_RANGE).clamp(LAT_RANGE.0, LAT_RANGE.1),
+        }
+    }
+
+    /// Get the great-circle distance to the other position in meters.
+    pub(super) fn distance(&self, other: &GeoPosition) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let u = ((lat2 - lat1) / 2.0).sin();
+        let v = ((other.lon - self.lon).to_radians() / 2.0).sin();
+        let a = u * u + lat1.cos() * lat2.cos() * v * v;
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+}
+
+/// Spread the lower 32 bits of `x` to the even bit positions and those of `y` to the odd ones.
+fn interleave(x: u64, y: u64) -> u64 {
+    let spread = |mut v: u64| {
+        v &= 0xFFFF_FFFF;
+        v = (v | (v << 16)) & 0x0000_FFFF_0000_FFFF;
+        v = (v | (v << 8)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    };
+    spread(x) | (spread(y) << 1)
+}
+
+/// Undo [`interleave`].
+fn deinterleave(v: u64) -> (u64, u64) {
+    let squash = |mut v: u64| {
+        v &= 0x5555_5555_5555_5555;
+        v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+        v = (v | (v >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v >> 4)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v >> 8)) & 0x0000_FFFF_0000_FFFF;
+        (v | (v >> 16)) & 0x0000_0000_FFFF_FFFF
+    };
+    (squash(v), squash(v >> 1))
+}
+
+/// The unit of a distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoUnit {
+    /// Meters.
+    M,
+    /// Kilometers.
+    Km,
+    /// Feet.
+    Ft,
+    /// Miles.
+    Mi,
+}
+
+impl GeoUnit {
+    /// Parse a unit from its name, ignoring the case.
+    pub(super) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "m" => Some(Self::M),
+            "km" => Some(Self::Km),
+            "ft" => Some(Self::Ft),
+            "mi" => Some(Self::Mi),
+            _ => None,
+        }
+    }
+
+    /// Get the name of the unit.
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            Self::M => "m",
+            Self::Km => "km",
+            Self::Ft => "ft",
+            Self::Mi => "mi",
+        }
+    }
+
+    /// Get the number of meters in one unit.
+    pub(super) fn meters(&self) -> f64 {
+        match self {
+            Self::M => 1.0,
+            Self::Km => 1000.0,
+            Self::Ft => 0.3048,
+            Self::Mi => 1609.34,
+        }
+    }
+}
+
+/// The area around the center in which members are searched, in the search's unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    /// A circle with the given radius.
+    Radius(f64),
+    /// A rectangle with the given width and height.
+    Box(f64, f64),
+}
+
+/// A member that was found by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Found {
+    pub(super) member: Bytes,
+    pub(super) hash: u64,
+    pub(super) position: GeoPosition,
+    /// The distance to the center in meters.
+    pub(super) distance: f64,
+}
+
+/// Find the members of the geospatial index that are within the shape around the center, in no
+/// particular order.
+pub(super) fn search(
+    index: &SortedSet,
+    center: GeoPosition,
+    shape: GeoShape,
+    unit: GeoUnit,
+) -> Vec<Found> {
+    let mut found = Vec::new();
+    for (score, member) in index.iter() {
+        let hash = score as u64;
+        let position = GeoPosition::decode(hash);
+        let distance = center.distance(&position);
+        let within = match shape {
+            GeoShape::Radius(radius) => distance <= radius * unit.meters(),
+            GeoShape::Box(width, height) => {
+                // The distances along the latitude and along the longitude are checked
+                // separately, the latter is measured at the member's latitude
+                let lat_distance = EARTH_RADIUS * (position.lat - center.lat).to_radians().abs();
+                let lon_distance = GeoPosition {
+                    lon: center.lon,
+                    lat: position.lat,
+                }
+                .distance(&position);
+                lat_distance <= height * unit.meters() / 2.0
+                    && lon_distance <= width * unit.meters() / 2.0
+            }
+        };
+        if within {
+            found.push(Found {
+                member: member.clone(),
+                hash,
+                position,
+                distance,
+            });
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geohash_matches_redis() {
+        // The scores given by Redis to the positions of Palermo and Catania
+        let palermo = GeoPosition::new(13.361389, 38.115556).unwrap();
+        let catania = GeoPosition::new(15.087269, 37.502669).unwrap();
+        assert_eq!(3479099956230698, palermo.encode());
+        assert_eq!(3479447370796909, catania.encode());
+
+        let decoded = GeoPosition::decode(palermo.encode());
+        assert!((decoded.lon - palermo.lon).abs() < 1e-5);
+        assert!((decoded.lat - palermo.lat).abs() < 1e-5);
+
+        // GEODIST Sicily Palermo Catania gives 166274.1516 meters
+        let distance =
+            GeoPosition::decode(palermo.encode()).distance(&GeoPosition::decode(catania.encode()));
+        assert!((distance - 166274.1516).abs() < 0.01, "{distance}");
+    }
+
+    #[test]
+    fn positions_outside_of_the_index_are_rejected() {
+        assert!(GeoPosition::new(180.0, 85.0).is_some());
+        assert!(GeoPosition::new(180.1, 0.0).is_none());
+        assert!(GeoPosition::new(0.0, 86.0).is_none());
+    }
+
+    #[test]
+    fn search_by_radius_and_box() {
+        let mut index = SortedSet::default();
+        for (member, lon, lat) in [
+            ("Palermo", 13.361389, 38.115556),
+            ("Catania", 15.087269, 37.502669),
+            ("edge1", 12.758489, 38.788135),
+            ("edge2", 17.241510, 38.788135),
+        ] {
+            let hash = GeoPosition::new(lon, lat).unwrap().encode();
+            index.insert(member.into(), hash as f64);
+        }
+        let center = GeoPosition::new(15.0, 37.0).unwrap();
+        let names = |found: Vec<Found>| {
+            let mut names: Vec<_> = found.into_iter().map(|f| f.member).collect();
+            names.sort();
+            names
+        };
+
+        let found = search(&index, center, GeoShape::Radius(200.0), GeoUnit::Km);
+        assert_eq!(
+            vec![Bytes::from("Catania"), Bytes::from("Palermo")],
+            names(found)
+        );
+
+        let found = search(&index, center, GeoShape::Box(400.0, 400.0), GeoUnit::Km);
+        assert_eq!(
+            vec![
+                Bytes::from("Catania"),
+                Bytes::from("Palermo"),
+                Bytes::from("edge1"),
+                Bytes::from("edge2"),
+            ],
+            names(found)
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{KeyValueStorage, Update},
+};
+
+use super::{
+    geo::GeoPosition,
+    set::SetCondition,
+    value::{Value, WRONG_TYPE},
+    zset::SortedSet,
+    Utf8Bytes,
+};
+
+/// Arguments for GEOADD command
+#[derive(Debug, PartialEq)]
+pub struct Geoadd {
+    /// The key of the geospatial index
+    key: Utf8Bytes,
+    /// The positions of the members
+    members: Vec<(GeoPosition, Bytes)>,
+    /// The condition under which the members are added or updated
+    condition: Option<SetCondition>,
+    /// Whether the number of changed members is sent back instead of the number of added members
+    changed: bool,
+}
+
+// The positions are never NaN
+impl Eq for Geoadd {}
+
+impl Geoadd {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, members: Vec<(GeoPosition, Bytes)>) -> Self {
+        Self {
+            key,
+            members,
+            condition: None,
+            changed: false,
+        }
+    }
+
+    /// Only add new members with [`SetCondition::Nx`], or only update existing members with
+    /// [`SetCondition::Xx`].
+    pub fn with_condition(mut self, condition: SetCondition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Send back the number of members that were added or moved.
+    pub fn with_changed(mut self) -> Self {
+        self.changed = true;
+        self
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("GEOADD", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Add the members
+        let key = self.key.as_ref().clone();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| {
+                let mut index = match value.map(Value::decode) {
+                    None => SortedSet::default(),
+                    Some(Value::SortedSet(index)) => index,
+                    Some(_) => return (Update::Keep, Err(WRONG_TYPE)),
+                };
+                let mut count = 0;
+                for (position, member) in self.members {
+                    let score = position.encode() as f64;
+                    let prev = index.score(&member);
+                    let allowed = match self.condition {
+                        Some(SetCondition::Nx) => prev.is_none(),
+                        Some(SetCondition::Xx) => prev.is_some(),
+                        None => true,
+                    };
+                    if !allowed || prev == Some(score) {
+                        continue;
+                    }
+                    index.insert(member, score);
+                    if prev.is_none() || self.changed {
+                        count += 1;
+                    }
+                }
+                if index.is_empty() {
+                    return (Update::Keep, Ok(count));
+                }
+                (Update::Set(Value::SortedSet(index).encode()), Ok(count))
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the number of added members
+        let response = match result {
+            Ok(count) => Frame::Integer(count),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Geoadd> for Frame {
+    fn from(cmd: Geoadd) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("GEOADD".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        match cmd.condition {
+            Some(SetCondition::Nx) => cmd_data.push(Self::BulkString("NX".into())),
+            Some(SetCondition::Xx) => cmd_data.push(Self::BulkString("XX".into())),
+            None => {}
+        }
+        if cmd.changed {
+            cmd_data.push(Self::BulkString("CH".into()));
+        }
+        for (position, member) in cmd.members {
+            cmd_data.push(Self::BulkString(position.lon().to_string().into()));
+            cmd_data.push(Self::BulkString(position.lat().to_string().into()));
+            cmd_data.push(Self::BulkString(member));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,242 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    geo::{self, GeoPosition, GeoShape, GeoUnit},
+    value::{Value, WRONG_TYPE},
+    Utf8Bytes,
+};
+
+/// The center of a GEOSEARCH.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoOrigin {
+    /// The position of a member of the index.
+    Member(Bytes),
+    /// The given position.
+    Position(GeoPosition),
+}
+
+/// The order of the members that GEOSEARCH sends back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoOrder {
+    /// From the nearest to the farthest.
+    Asc,
+    /// From the farthest to the nearest.
+    Desc,
+}
+
+/// Arguments for GEOSEARCH command
+#[derive(Debug, PartialEq)]
+pub struct Geosearch {
+    /// The key of the geospatial index
+    key: Utf8Bytes,
+    /// The center of the search
+    origin: GeoOrigin,
+    /// The area around the center that is searched
+    shape: GeoShape,
+    /// The unit of the shape's size and of the sent back distances
+    unit: GeoUnit,
+    /// The order of the sent back members, unordered if not given
+    order: Option<GeoOrder>,
+    /// The max number of members to send back, and whether the search can stop as soon as
+    /// enough members were found
+    count: Option<(u64, bool)>,
+    /// Whether the positions of the members are sent back
+    with_coord: bool,
+    /// Whether the distances to the center are sent back
+    with_dist: bool,
+    /// Whether the geohashes of the members are sent back
+    with_hash: bool,
+}
+
+// The positions and the sizes are never NaN
+impl Eq for Geosearch {}
+
+impl Geosearch {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, origin: GeoOrigin, shape: GeoShape, unit: GeoUnit) -> Self {
+        Self {
+            key,
+            origin,
+            shape,
+            unit,
+            order: None,
+            count: None,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+        }
+    }
+
+    /// Sort the members by their distances to the center.
+    pub fn with_order(mut self, order: GeoOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Send back at most `count` members. When `any` is set, the first members that are found
+    /// are sent back instead of the nearest ones.
+    pub fn with_count(mut self, count: u64, any: bool) -> Self {
+        self.count = Some((count, any));
+        self
+    }
+
+    /// Send back the positions of the members.
+    pub fn with_coord(mut self) -> Self {
+        self.with_coord = true;
+        self
+    }
+
+    /// Send back the distances of the members to the center.
+    pub fn with_dist(mut self) -> Self {
+        self.with_dist = true;
+        self
+    }
+
+    /// Send back the geohashes of the members.
+    pub fn with_hash(mut self) -> Self {
+        self.with_hash = true;
+        self
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the index
+        let key = self.key.as_ref().clone();
+        let value = net::spawn_blocking(move || storage.get(key))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the members that were found
+        let response = match value.map(Value::decode) {
+            None => Frame::Array(Vec::new()),
+            Some(Value::SortedSet(index)) => {
+                let center = match &self.origin {
+                    GeoOrigin::Position(position) => Some(*position),
+                    GeoOrigin::Member(member) => index
+                        .score(member)
+                        .map(|score| GeoPosition::decode(score as u64)),
+                };
+                match center {
+                    Some(center) => {
+                        self.respond(geo::search(&index, center, self.shape, self.unit))
+                    }
+                    None => Frame::Error("ERR could not decode requested zset member".to_string()),
+                }
+            }
+            Some(_) => Frame::Error(WRONG_TYPE.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+
+    /// Order and truncate the found members, then build the response.
+    fn respond(&self, mut found: Vec<geo::Found>) -> Frame {
+        // Without ANY, COUNT gives the nearest members so they are sorted before truncating
+        let order = match self.count {
+            Some((_, false)) => Some(self.order.unwrap_or(GeoOrder::Asc)),
+            _ => self.order,
+        };
+        if let Some((count, true)) = self.count {
+            found.truncate(count as usize);
+        }
+        match order {
+            Some(GeoOrder::Asc) => found.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+            Some(GeoOrder::Desc) => found.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+            None => {}
+        }
+        if let Some((count, false)) = self.count {
+            found.truncate(count as usize);
+        }
+
+        let frames = found.into_iter().map(|f| {
+            if !(self.with_dist || self.with_hash || self.with_coord) {
+                return Frame::BulkString(f.member);
+            }
+            let mut item = vec![Frame::BulkString(f.member)];
+            if self.with_dist {
+                let distance = f.distance / self.unit.meters();
+                item.push(Frame::BulkString(format!("{distance:.4}").into()));
+            }
+            if self.with_hash {
+                item.push(Frame::Integer(f.hash as i64));
+            }
+            if self.with_coord {
+                item.push(Frame::Array(vec![
+                    Frame::BulkString(f.position.lon().to_string().into()),
+                    Frame::BulkString(f.position.lat().to_string().into()),
+                ]));
+            }
+            Frame::Array(item)
+        });
+        Frame::Array(frames.collect())
+    }
+}
+
+impl From<Geosearch> for Frame {
+    fn from(cmd: Geosearch) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("GEOSEARCH".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        match cmd.origin {
+            GeoOrigin::Member(member) => {
+                cmd_data.push(Self::BulkString("FROMMEMBER".into()));
+                cmd_data.push(Self::BulkString(member));
+            }
+            GeoOrigin::Position(position) => {
+                cmd_data.push(Self::BulkString("FROMLONLAT".into()));
+                cmd_data.push(Self::BulkString(position.lon().to_string().into()));
+                cmd_data.push(Self::BulkString(position.lat().to_string().into()));
+            }
+        }
+        match cmd.shape {
+            GeoShape::Radius(radius) => {
+                cmd_data.push(Self::BulkString("BYRADIUS".into()));
+                cmd_data.push(Self::BulkString(radius.to_string().into()));
+            }
+            GeoShape::Box(width, height) => {
+                cmd_data.push(Self::BulkString("BYBOX".into()));
+                cmd_data.push(Self::BulkString(width.to_string().into()));
+                cmd_data.push(Self::BulkString(height.to_string().into()));
+            }
+        }
+        cmd_data.push(Self::BulkString(cmd.unit.name().into()));
+        match cmd.order {
+            Some(GeoOrder::Asc) => cmd_data.push(Self::BulkString("ASC".into())),
+            Some(GeoOrder::Desc) => cmd_data.push(Self::BulkString("DESC".into())),
+            None => {}
+        }
+        if let Some((count, any)) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+            if any {
+                cmd_data.push(Self::BulkString("ANY".into()));
+            }
+        }
+        if cmd.with_coord {
+            cmd_data.push(Self::BulkString("WITHCOORD".into()));
+        }
+        if cmd.with_dist {
+            cmd_data.push(Self::BulkString("WITHDIST".into()));
+        }
+        if cmd.with_hash {
+            cmd_data.push(Self::BulkString("WITHHASH".into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -1,11 +1,36 @@
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
 use tracing::debug;
 
 use crate::{
     net::{self, connection::Connection, frame::Frame},
-    storage::KeyValueStorage,
+    storage::{KeyValueStorage, ValueStream},
+};
+
+use super::{
+    value::{self, Value, WRONG_TYPE},
+    Utf8Bytes,
 };
 
-use super::Utf8Bytes;
+/// Values that are read piece by piece are read as a whole when they're not longer than this.
+const STREAM_THRESHOLD: u64 = 1024 * 1024;
+
+/// The number of bytes in each of the pieces of a value that's sent back piece by piece.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The max number of pieces that are read ahead of the connection, which bounds the memory taken
+/// by a value that's sent back piece by piece.
+const STREAM_CHUNKS_AHEAD: usize = 4;
+
+/// How the value of the key is sent back.
+enum Reply {
+    /// The value is sent back in a single frame.
+    Whole(Option<Bytes>),
+    /// A string value of the given length is sent back piece by piece.
+    Stream(u64, Box<dyn Read + Send>),
+}
 
 /// Arguments for for GET command
 #[derive(Debug, PartialEq, Eq)]
@@ -28,14 +53,22 @@ impl Get {
         KV: KeyValueStorage,
     {
         // Get the key's value
-        let result = tokio::task::spawn_blocking(move || storage.get(self.key.as_ref().clone()))
-            .await?
-            .map_err(|e| net::Error::Storage(e.into()))?;
+        let key = self.key.as_ref().clone();
+        let reply = net::spawn_blocking(move || read_value(storage, key)).await??;
 
         // Responding with the received value
-        let response = match result {
-            Some(val) => Frame::BulkString(val),
-            None => Frame::Null,
+        let response = match reply {
+            Reply::Whole(value) => match value.map(Value::decode) {
+                Some(Value::String(val)) => Frame::BulkString(val),
+                Some(_) => Frame::Error(WRONG_TYPE.to_string()),
+                None => Frame::Null,
+            },
+            Reply::Stream(len, reader) => {
+                debug!(len, "streaming value");
+                let mut chunks = read_chunks(reader);
+                connection.write_bulk_stream(len, &mut chunks).await?;
+                return Ok(());
+            }
         };
         debug!(?response);
 
@@ -45,6 +78,60 @@ impl Get {
     }
 }
 
+/// Read the value of the key, deciding whether it's sent back piece by piece. Only long string
+/// values that the storage gives piece by piece are sent back this way.
+fn read_value<KV>(storage: KV, key: Bytes) -> Result<Reply, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let value = storage
+        .get_stream(key)
+        .map_err(|e| net::Error::Storage(e.into()))?;
+    let (len, mut reader) = match value {
+        None => return Ok(Reply::Whole(None)),
+        Some(ValueStream::Whole(value)) => return Ok(Reply::Whole(Some(value))),
+        Some(ValueStream::Chunked { len, reader }) => (len, reader),
+    };
+    // Reading fails with an I/O error so the connection is closed, as it would be if reading
+    // failed after the reply was started
+    let mut head = Vec::new();
+    if len <= STREAM_THRESHOLD {
+        reader.read_to_end(&mut head)?;
+        return Ok(Reply::Whole(Some(head.into())));
+    }
+    reader
+        .by_ref()
+        .take(value::HEADER_LEN as u64)
+        .read_to_end(&mut head)?;
+    if value::has_header(&head) {
+        reader.read_to_end(&mut head)?;
+        return Ok(Reply::Whole(Some(head.into())));
+    }
+    Ok(Reply::Stream(
+        len,
+        Box::new(io::Cursor::new(head).chain(reader)),
+    ))
+}
+
+/// Read the value in pieces on a blocking thread, and send the pieces through a bounded channel
+/// so reading stays at most a few pieces ahead of the connection.
+fn read_chunks(mut reader: Box<dyn Read + Send>) -> mpsc::Receiver<io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(STREAM_CHUNKS_AHEAD);
+    net::spawn_blocking(move || loop {
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK_SIZE);
+        let result = reader
+            .by_ref()
+            .take(STREAM_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk);
+        let done = !matches!(result, Ok(n) if n > 0);
+        // Stop reading if the connection has gone away
+        if tx.blocking_send(result.map(|_| chunk.into())).is_err() || done {
+            break;
+        }
+    });
+    rx
+}
+
 impl From<Get> for Frame {
     fn from(cmd: Get) -> Self {
         Self::Array(vec![
@@ -53,3 +140,52 @@ impl From<Get> for Frame {
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use crate::storage::bitcask;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn long_chunked_strings_are_streamed() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = bitcask::Config::default()
+            .path(dir.path())
+            .value_chunk_size(NonZeroU32::new(1000).unwrap())
+            .to_owned()
+            .open()
+            .unwrap();
+        let handle = kv.get_handle();
+
+        let long = vec![b'x'; STREAM_THRESHOLD as usize + 1];
+        handle.put_reader("long".into(), long.as_slice()).unwrap();
+        let Reply::Stream(len, reader) = read_value(handle.clone(), "long".into()).unwrap() else {
+            panic!("the value must be streamed");
+        };
+        assert_eq!(long.len() as u64, len);
+        let mut chunks = read_chunks(reader);
+        let mut streamed = Vec::new();
+        while let Some(chunk) = chunks.recv().await {
+            streamed.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(long, streamed);
+
+        handle.put_reader("short".into(), &b"value"[..]).unwrap();
+        assert!(matches!(
+            read_value(handle.clone(), "short".into()).unwrap(),
+            Reply::Whole(Some(v)) if v == "value"
+        ));
+
+        // Non-string values are read as a whole so they can be rejected
+        let mut list = Value::List(Default::default()).encode().to_vec();
+        list.resize(STREAM_THRESHOLD as usize + 1, 0);
+        handle.put_reader("list".into(), list.as_slice()).unwrap();
+        assert!(matches!(
+            read_value(handle, "list".into()).unwrap(),
+            Reply::Whole(Some(_))
+        ));
+    }
+}
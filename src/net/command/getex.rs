@@ -0,0 +1,83 @@
+use std::time::SystemTime;
+
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    expiry::Expiry,
+    value::{Value, WRONG_TYPE},
+    Utf8Bytes,
+};
+
+/// Arguments for GETEX command
+#[derive(Debug, PartialEq, Eq)]
+pub struct GetEx {
+    /// The key to get the value of
+    key: Utf8Bytes,
+    /// The change to the key's expiry
+    expiry: Option<Expiry>,
+}
+
+impl GetEx {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, expiry: Option<Expiry>) -> Self {
+        Self { key, expiry }
+    }
+
+    /// Get the name of the command and the key that it writes to, or `None` if the expiry isn't
+    /// changed and the command only reads.
+    pub(super) fn writes(&self) -> Option<(&'static str, Vec<&Utf8Bytes>)> {
+        self.expiry.map(|_| ("GETEX", vec![&self.key]))
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance. Changing the expiry writes
+    /// the value again with the new expiry.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the key's value and change its expiry within a single atomic operation
+        let now = SystemTime::now();
+        let response = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let key = self.key.as_ref().clone();
+                let value = match txn.get(key.clone())?.map(Value::decode) {
+                    Some(Value::String(value)) => value,
+                    Some(_) => return Ok(Frame::Error(WRONG_TYPE.to_string())),
+                    None => return Ok(Frame::Null),
+                };
+                if let Some(expiry) = self.expiry {
+                    txn.set_with_expiry(key, value.clone(), expiry.expires_at(now))?;
+                }
+                Ok(Frame::BulkString(value))
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<GetEx> for Frame {
+    fn from(cmd: GetEx) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("GETEX".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        if let Some(expiry) = cmd.expiry {
+            cmd_data.extend(expiry.to_args().into_iter().map(Self::BulkString));
+        }
+        Self::Array(cmd_data)
+    }
+}
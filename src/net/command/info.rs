@@ -0,0 +1,115 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::net::{self, connection::Connection, frame::Frame};
+
+use super::{CommandClass, CommandSpec, KeySpec, Utf8Bytes};
+
+/// Arguments for COMMAND command
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandInfo {
+    /// COMMAND, which describes all commands
+    All,
+    /// COMMAND COUNT
+    Count,
+    /// COMMAND LIST
+    List,
+    /// COMMAND INFO [command-name [command-name ...]]
+    Info(Vec<Utf8Bytes>),
+    /// COMMAND GETKEYS command [arg [arg ...]]
+    GetKeys(Vec<Bytes>),
+}
+
+impl CommandInfo {
+    /// Send back the declarations of the commands. Each command is described like in Redis, by
+    /// an array of its name in lower case, its arity, its flags, and the positions of its first
+    /// key, its last key, and the step between its keys.
+    #[tracing::instrument(skip(self, connection))]
+    pub async fn apply(self, connection: &mut Connection) -> Result<(), net::Error> {
+        let response = match self {
+            Self::All => Frame::Array(CommandSpec::all().iter().map(describe).collect()),
+            Self::Count => Frame::Integer(CommandSpec::all().len() as i64),
+            Self::List => Frame::Array(
+                CommandSpec::all()
+                    .iter()
+                    .map(|spec| Frame::BulkString(spec.name().to_ascii_lowercase().into()))
+                    .collect(),
+            ),
+            Self::Info(names) => Frame::Array(
+                names
+                    .iter()
+                    .map(|name| {
+                        CommandSpec::lookup(name.as_ref())
+                            .map(describe)
+                            .unwrap_or(Frame::Null)
+                    })
+                    .collect(),
+            ),
+            Self::GetKeys(args) => {
+                match args.first().and_then(|name| CommandSpec::lookup(&name[..])) {
+                    Some(spec) => Frame::Array(
+                        spec.keys(&args)
+                            .into_iter()
+                            .map(|key| Frame::BulkString(key.clone()))
+                            .collect(),
+                    ),
+                    None => Frame::Error("ERR Invalid command specified".to_string()),
+                }
+            }
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Describe a command like the reply of Redis's COMMAND INFO.
+fn describe(spec: &CommandSpec) -> Frame {
+    let class = match spec.class() {
+        CommandClass::Read => "readonly",
+        CommandClass::Write => "write",
+    };
+    let mut flags = vec![Frame::SimpleString(class.to_string())];
+    let (first, last, step) = match spec.key_spec() {
+        KeySpec::None => (0, 0, 0),
+        KeySpec::Range { first, last, step } => (first as i64, last, step as i64),
+        KeySpec::Counted { .. } | KeySpec::Custom(_) => {
+            flags.push(Frame::SimpleString("movablekeys".to_string()));
+            (0, 0, 0)
+        }
+    };
+    Frame::Array(vec![
+        Frame::BulkString(spec.name().to_ascii_lowercase().into()),
+        Frame::Integer(spec.arity()),
+        Frame::Array(flags),
+        Frame::Integer(first),
+        Frame::Integer(last),
+        Frame::Integer(step),
+    ])
+}
+
+impl From<CommandInfo> for Frame {
+    fn from(cmd: CommandInfo) -> Self {
+        let mut frames = vec![Self::BulkString("COMMAND".into())];
+        match cmd {
+            CommandInfo::All => {}
+            CommandInfo::Count => frames.push(Self::BulkString("COUNT".into())),
+            CommandInfo::List => frames.push(Self::BulkString("LIST".into())),
+            CommandInfo::Info(names) => {
+                frames.push(Self::BulkString("INFO".into()));
+                frames.extend(
+                    names
+                        .into_iter()
+                        .map(|n| Self::BulkString(n.as_ref().clone())),
+                );
+            }
+            CommandInfo::GetKeys(args) => {
+                frames.push(Self::BulkString("GETKEYS".into()));
+                frames.extend(args.into_iter().map(Self::BulkString));
+            }
+        }
+        Self::Array(frames)
+    }
+}
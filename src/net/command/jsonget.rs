@@ -0,0 +1,74 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{jsonpath, Utf8Bytes};
+
+/// Arguments for JSON.GET command
+#[derive(Debug, PartialEq, Eq)]
+pub struct JsonGet {
+    /// The key holding the JSON value
+    key: Utf8Bytes,
+    /// The path to the value within the JSON document
+    path: Utf8Bytes,
+}
+
+impl JsonGet {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, path: Utf8Bytes) -> Self {
+        Self { key, path }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the key's value
+        let result = net::spawn_blocking(move || storage.get(self.key.as_ref().clone()))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the JSON value at the path
+        let response = match result {
+            Some(val) => get_path(&val, self.path.as_str()),
+            None => Frame::Null,
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Get the serialized JSON value at `path` from the stored document.
+fn get_path(document: &[u8], path: &str) -> Frame {
+    let Some(path) = jsonpath::parse(path) else {
+        return Frame::Error("ERR invalid JSON path".to_string());
+    };
+    let Ok(document) = serde_json::from_slice::<serde_json::Value>(document) else {
+        return Frame::Error("WRONGTYPE Operation against a key holding a non-JSON value".into());
+    };
+    match jsonpath::get(&document, &path) {
+        Some(value) => Frame::BulkString(Bytes::from(value.to_string())),
+        None => Frame::Null,
+    }
+}
+
+impl From<JsonGet> for Frame {
+    fn from(cmd: JsonGet) -> Self {
+        Self::Array(vec![
+            Self::BulkString("JSON.GET".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.path.as_ref().clone()),
+        ])
+    }
+}
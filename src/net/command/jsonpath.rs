@@ -0,0 +1,126 @@
+//! A small subset of JSONPath that is used by the JSON commands. A path is made of member names
+//! separated by dots and array indices in square brackets, with an optional `$` for the root,
+//! e.g. `$.users[0].email`, `.users[0].email`, or `users[0].email`.
+
+use serde_json::Value;
+
+/// A single step in a JSON path.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Segment {
+    /// Access an object's member.
+    Member(String),
+    /// Access an array's element.
+    Index(usize),
+}
+
+/// Parse the path into its segments. Returns `None` if the path is malformed.
+pub(super) fn parse(path: &str) -> Option<Vec<Segment>> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    if path.is_empty() || path == "." {
+        return Some(Vec::new());
+    }
+    let mut segments = Vec::new();
+    let mut chars = path.chars().peekable();
+    // A path not starting with '$' or '.' starts with a member name
+    let mut expect_member = !path.starts_with(['.', '[']);
+    loop {
+        if expect_member {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == '.' || c == '[' {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            if name.is_empty() {
+                return None;
+            }
+            segments.push(Segment::Member(name));
+            expect_member = false;
+        }
+        match chars.next() {
+            None => return Some(segments),
+            Some('.') => expect_member = true,
+            Some('[') => {
+                let mut index = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    index.push(c);
+                }
+                segments.push(Segment::Index(index.parse().ok()?));
+            }
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Return a reference to the value at the given path.
+pub(super) fn get<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, segment| match segment {
+        Segment::Member(name) => v.get(name),
+        Segment::Index(i) => v.get(i),
+    })
+}
+
+/// Set the value at the given path. The parent of the path must exist, and members are created
+/// when they don't exist. Returns `false` if the value could not be set.
+pub(super) fn set(value: &mut Value, path: &[Segment], new_value: Value) -> bool {
+    let Some((last, parent)) = path.split_last() else {
+        *value = new_value;
+        return true;
+    };
+    let parent = parent.iter().try_fold(value, |v, segment| match segment {
+        Segment::Member(name) => v.get_mut(name),
+        Segment::Index(i) => v.get_mut(i),
+    });
+    match (parent, last) {
+        (Some(Value::Object(map)), Segment::Member(name)) => {
+            map.insert(name.clone(), new_value);
+            true
+        }
+        (Some(Value::Array(items)), Segment::Index(i)) if *i < items.len() => {
+            items[*i] = new_value;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_paths() {
+        let expected = vec![
+            Segment::Member("a".into()),
+            Segment::Index(0),
+            Segment::Member("b".into()),
+        ];
+        assert_eq!(Some(expected), parse("$.a[0].b"));
+        assert_eq!(Some(vec![]), parse("$"));
+        assert_eq!(Some(vec![]), parse("."));
+        assert_eq!(Some(vec![Segment::Member("a".into())]), parse(".a"));
+        assert_eq!(Some(vec![Segment::Member("a".into())]), parse("a"));
+        assert_eq!(None, parse("$.a[x]"));
+        assert_eq!(None, parse("$..a"));
+    }
+
+    #[test]
+    fn get_and_set_values() {
+        let mut value = json!({"a": [{"b": 1}]});
+        let path = parse("$.a[0].b").unwrap();
+        assert_eq!(Some(&json!(1)), get(&value, &path));
+        assert!(set(&mut value, &path, json!("x")));
+        assert_eq!(json!({"a": [{"b": "x"}]}), value);
+        assert!(set(&mut value, &parse("$.c").unwrap(), json!(2)));
+        assert_eq!(Some(&json!(2)), get(&value, &parse("c").unwrap()));
+        assert!(!set(&mut value, &parse("$.d.e").unwrap(), json!(3)));
+        assert!(!set(&mut value, &parse("$.a[5]").unwrap(), json!(3)));
+    }
+}
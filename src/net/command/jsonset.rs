@@ -0,0 +1,165 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{KeyValueStorage, Update},
+};
+
+use super::{jsonpath, Utf8Bytes};
+
+/// Arguments for JSON.SET command
+#[derive(Debug, PartialEq, Eq)]
+pub struct JsonSet {
+    /// The key holding the JSON value
+    key: Utf8Bytes,
+    /// The path to the value within the JSON document
+    path: Utf8Bytes,
+    /// The serialized JSON value to be set
+    value: Bytes,
+    /// The condition in which the value is set
+    condition: Option<JsonSetCondition>,
+}
+
+/// The condition under which JSON.SET sets the value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JsonSetCondition {
+    /// Only set the value if the path does not exist.
+    Nx,
+    /// Only set the value if the path already exists.
+    Xx,
+}
+
+impl JsonSet {
+    /// Creates a new set of arguments
+    pub fn new(
+        key: Utf8Bytes,
+        path: Utf8Bytes,
+        value: Bytes,
+        condition: Option<JsonSetCondition>,
+    ) -> Self {
+        Self {
+            key,
+            path,
+            value,
+            condition,
+        }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("JSON.SET", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Modify the document and write it back within a single atomic update
+        let key = self.key.as_ref().clone();
+        let response = net::spawn_blocking(move || {
+            storage.update(key, move |document| match self.set_path(document) {
+                Ok(Some(document)) => {
+                    (Update::Set(document), Frame::SimpleString("OK".to_string()))
+                }
+                Ok(None) => (Update::Keep, Frame::Null),
+                Err(e) => (Update::Keep, Frame::Error(e.to_string())),
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+
+    /// Set the value at the path within the given document. Returns the serialized document if it
+    /// was changed, and `None` if the set condition was not met.
+    fn set_path(&self, document: Option<Bytes>) -> Result<Option<Bytes>, &'static str> {
+        let path = jsonpath::parse(self.path.as_str()).ok_or("ERR invalid JSON path")?;
+        let value: serde_json::Value =
+            serde_json::from_slice(&self.value).map_err(|_| "ERR invalid JSON value")?;
+        let mut document = match document {
+            Some(document) => serde_json::from_slice(&document)
+                .map_err(|_| "WRONGTYPE Operation against a key holding a non-JSON value")?,
+            // New documents can only be created at the root
+            None if path.is_empty() => serde_json::Value::Null,
+            None if self.condition == Some(JsonSetCondition::Xx) => return Ok(None),
+            None => return Err("ERR new objects must be created at the root"),
+        };
+        let exists = !document.is_null() && jsonpath::get(&document, &path).is_some();
+        match self.condition {
+            Some(JsonSetCondition::Nx) if exists => return Ok(None),
+            Some(JsonSetCondition::Xx) if !exists => return Ok(None),
+            _ => {}
+        }
+        if !jsonpath::set(&mut document, &path, value) {
+            return Ok(None);
+        }
+        Ok(Some(Bytes::from(document.to_string())))
+    }
+}
+
+impl From<JsonSet> for Frame {
+    fn from(cmd: JsonSet) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("JSON.SET".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.path.as_ref().clone()),
+            Self::BulkString(cmd.value),
+        ];
+        match cmd.condition {
+            Some(JsonSetCondition::Nx) => cmd_data.push(Self::BulkString("NX".into())),
+            Some(JsonSetCondition::Xx) => cmd_data.push(Self::BulkString("XX".into())),
+            None => {}
+        }
+        Self::Array(cmd_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(path: &str, value: &str, condition: Option<JsonSetCondition>) -> JsonSet {
+        JsonSet::new(
+            "key".into(),
+            path.into(),
+            Bytes::from(value.to_string()),
+            condition,
+        )
+    }
+
+    #[test]
+    fn set_path_updates_document() {
+        let document = Some(Bytes::from(r#"{"a":{"b":1}}"#));
+        let cmd = set("$.a.b", "2", None);
+        assert_eq!(
+            Ok(Some(Bytes::from(r#"{"a":{"b":2}}"#))),
+            cmd.set_path(document.clone())
+        );
+        let cmd = set("$.a.b", "2", Some(JsonSetCondition::Nx));
+        assert_eq!(Ok(None), cmd.set_path(document.clone()));
+        let cmd = set("$.a.c", "2", Some(JsonSetCondition::Xx));
+        assert_eq!(Ok(None), cmd.set_path(document));
+    }
+
+    #[test]
+    fn set_path_creates_documents_at_root() {
+        let cmd = set("$", r#"{"a":1}"#, None);
+        assert_eq!(Ok(Some(Bytes::from(r#"{"a":1}"#))), cmd.set_path(None));
+        let cmd = set("$.a", "1", None);
+        assert_eq!(
+            Err("ERR new objects must be created at the root"),
+            cmd.set_path(None)
+        );
+        let cmd = set("$", "{", None);
+        assert_eq!(Err("ERR invalid JSON value"), cmd.set_path(None));
+    }
+}
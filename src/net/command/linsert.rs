@@ -0,0 +1,82 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LINSERT command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Linsert {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// Whether the element is inserted before the pivot, otherwise after it
+    before: bool,
+    /// The element next to which the new element is inserted
+    pivot: Bytes,
+    /// The element to be inserted
+    element: Bytes,
+}
+
+impl Linsert {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, before: bool, pivot: Bytes, element: Bytes) -> Self {
+        Self {
+            key,
+            before,
+            pivot,
+            element,
+        }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("LINSERT", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Insert the element
+        let key = self.key.as_ref().clone();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| {
+                list::insert(value, self.before, &self.pivot, self.element)
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the length of the list
+        let response = match result {
+            Ok(len) => Frame::Integer(len),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Linsert> for Frame {
+    fn from(cmd: Linsert) -> Self {
+        let position = if cmd.before { "BEFORE" } else { "AFTER" };
+        Self::Array(vec![
+            Self::BulkString("LINSERT".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(position.into()),
+            Self::BulkString(cmd.pivot),
+            Self::BulkString(cmd.element),
+        ])
+    }
+}
@@ -0,0 +1,337 @@
+//! Lists are stored as single values that hold all of their elements. Empty lists are never
+//! stored, popping the last element of a list deletes its key.
+
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+use crate::storage::Update;
+
+use super::value::{Value, WRONG_TYPE};
+
+/// The error message that is sent when a command requires the list to exist.
+pub(super) const NO_SUCH_KEY: &str = "ERR no such key";
+
+/// The error message that is sent when an index is outside of the list.
+pub(super) const OUT_OF_RANGE: &str = "ERR index out of range";
+
+/// The end of a list that is pushed to or popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    /// The head of the list.
+    Left,
+    /// The tail of the list.
+    Right,
+}
+
+/// Push the elements to the list that is stored as `value`, creating the list if it does not
+/// exist. Returns the length of the list after the push.
+pub(super) fn push(
+    value: Option<Bytes>,
+    end: ListEnd,
+    elements: Vec<Bytes>,
+) -> (Update, Result<usize, &'static str>) {
+    let mut list = match value.map(Value::decode) {
+        None => VecDeque::new(),
+        Some(Value::List(list)) => list,
+        Some(_) => return (Update::Keep, Err(WRONG_TYPE)),
+    };
+    for element in elements {
+        match end {
+            ListEnd::Left => list.push_front(element),
+            ListEnd::Right => list.push_back(element),
+        }
+    }
+    let len = list.len();
+    (Update::Set(Value::List(list).encode()), Ok(len))
+}
+
+/// Pop at most `count` elements from the list that is stored as `value`. Returns the popped
+/// elements, which is empty if the list does not exist.
+pub(super) fn pop(
+    value: Option<Bytes>,
+    end: ListEnd,
+    count: usize,
+) -> (Update, Result<Vec<Bytes>, &'static str>) {
+    let mut list = match value.map(Value::decode) {
+        None => return (Update::Keep, Ok(Vec::new())),
+        Some(Value::List(list)) => list,
+        Some(_) => return (Update::Keep, Err(WRONG_TYPE)),
+    };
+    let count = count.min(list.len());
+    let elements = match end {
+        ListEnd::Left => list.drain(..count).collect(),
+        ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+    };
+    let update = if list.is_empty() {
+        Update::Delete
+    } else {
+        Update::Set(Value::List(list).encode())
+    };
+    (update, Ok(elements))
+}
+
+/// Get the length of the list that is stored as `value`, which is 0 if the list does not exist.
+pub(super) fn len(value: Option<Bytes>) -> Result<usize, &'static str> {
+    Ok(decode(value)?.map(|list| list.len()).unwrap_or_default())
+}
+
+/// Get the indices of the elements that are equal to `element` in the list that is stored as
+/// `value`. Matches are searched from the head when `rank` is positive, or from the tail when
+/// it's negative, skipping the first `|rank| - 1` matches. At most `count` indices are returned
+/// when it's not 0, and at most `maxlen` elements are compared when it's not 0.
+pub(super) fn positions(
+    value: Option<Bytes>,
+    element: &[u8],
+    rank: i64,
+    count: usize,
+    maxlen: usize,
+) -> Result<Vec<usize>, &'static str> {
+    let Some(list) = decode(value)? else {
+        return Ok(Vec::new());
+    };
+    let count = if count == 0 { usize::MAX } else { count };
+    let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+    let skip = (rank.unsigned_abs() - 1) as usize;
+    let indices: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..list.len())
+    } else {
+        Box::new((0..list.len()).rev())
+    };
+    Ok(indices
+        .take(maxlen)
+        .filter(|&i| list[i] == element)
+        .skip(skip)
+        .take(count)
+        .collect())
+}
+
+/// Insert the element before or after the first element that is equal to `pivot` in the list
+/// that is stored as `value`. Returns the length of the list after the insert, -1 if the pivot
+/// was not found, or 0 if the list does not exist.
+pub(super) fn insert(
+    value: Option<Bytes>,
+    before: bool,
+    pivot: &[u8],
+    element: Bytes,
+) -> (Update, Result<i64, &'static str>) {
+    let mut list = match decode(value) {
+        Ok(Some(list)) => list,
+        Ok(None) => return (Update::Keep, Ok(0)),
+        Err(e) => return (Update::Keep, Err(e)),
+    };
+    let Some(index) = list.iter().position(|e| e == pivot) else {
+        return (Update::Keep, Ok(-1));
+    };
+    list.insert(if before { index } else { index + 1 }, element);
+    let len = list.len() as i64;
+    (Update::Set(Value::List(list).encode()), Ok(len))
+}
+
+/// Remove the elements that are equal to `element` from the list that is stored as `value`. At
+/// most `count` elements are removed from the head when it's positive, or from the tail when
+/// it's negative, all of them are removed when it's 0. Returns the number of removed elements.
+pub(super) fn remove(
+    value: Option<Bytes>,
+    count: i64,
+    element: &[u8],
+) -> (Update, Result<usize, &'static str>) {
+    let mut list = match decode(value) {
+        Ok(Some(list)) => list,
+        Ok(None) => return (Update::Keep, Ok(0)),
+        Err(e) => return (Update::Keep, Err(e)),
+    };
+    let limit = if count == 0 {
+        usize::MAX
+    } else {
+        count.unsigned_abs() as usize
+    };
+    let mut removed = 0;
+    let mut kept = VecDeque::with_capacity(list.len());
+    if count >= 0 {
+        for e in list.drain(..) {
+            if removed < limit && e == element {
+                removed += 1;
+            } else {
+                kept.push_back(e);
+            }
+        }
+    } else {
+        for e in list.drain(..).rev() {
+            if removed < limit && e == element {
+                removed += 1;
+            } else {
+                kept.push_front(e);
+            }
+        }
+    }
+    if removed == 0 {
+        return (Update::Keep, Ok(0));
+    }
+    (store(kept), Ok(removed))
+}
+
+/// Trim the list that is stored as `value` to the elements between the indices `start` and
+/// `stop`, both inclusive. Negative indices count from the tail of the list.
+pub(super) fn trim(
+    value: Option<Bytes>,
+    start: i64,
+    stop: i64,
+) -> (Update, Result<(), &'static str>) {
+    let mut list = match decode(value) {
+        Ok(Some(list)) => list,
+        Ok(None) => return (Update::Keep, Ok(())),
+        Err(e) => return (Update::Keep, Err(e)),
+    };
+    let len = list.len() as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop {
+        return (Update::Delete, Ok(()));
+    }
+    list.truncate(stop as usize + 1);
+    list.drain(..start as usize);
+    (store(list), Ok(()))
+}
+
+/// Set the element at the index of the list that is stored as `value`. Negative indices count
+/// from the tail of the list.
+pub(super) fn set(
+    value: Option<Bytes>,
+    index: i64,
+    element: Bytes,
+) -> (Update, Result<(), &'static str>) {
+    let mut list = match decode(value) {
+        Ok(Some(list)) => list,
+        Ok(None) => return (Update::Keep, Err(NO_SUCH_KEY)),
+        Err(e) => return (Update::Keep, Err(e)),
+    };
+    let len = list.len() as i64;
+    let index = if index < 0 { len + index } else { index };
+    if !(0..len).contains(&index) {
+        return (Update::Keep, Err(OUT_OF_RANGE));
+    }
+    list[index as usize] = element;
+    (Update::Set(Value::List(list).encode()), Ok(()))
+}
+
+/// Decode the list that is stored as `value`, if it exists.
+fn decode(value: Option<Bytes>) -> Result<Option<VecDeque<Bytes>>, &'static str> {
+    match value.map(Value::decode) {
+        None => Ok(None),
+        Some(Value::List(list)) => Ok(Some(list)),
+        Some(_) => Err(WRONG_TYPE),
+    }
+}
+
+/// Store the list, deleting its key if it's empty.
+fn store(list: VecDeque<Bytes>) -> Update {
+    if list.is_empty() {
+        Update::Delete
+    } else {
+        Update::Set(Value::List(list).encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(value: &mut Option<Bytes>, update: Update) {
+        match update {
+            Update::Keep => {}
+            Update::Set(v) => *value = Some(v),
+            Update::Delete => *value = None,
+        }
+    }
+
+    #[test]
+    fn push_and_pop_from_both_ends() {
+        let mut value = None;
+        let (update, len) = push(value.clone(), ListEnd::Left, vec!["b".into(), "a".into()]);
+        apply(&mut value, update);
+        assert_eq!(Ok(2), len);
+        let (update, len) = push(value.clone(), ListEnd::Right, vec!["c".into(), "d".into()]);
+        apply(&mut value, update);
+        assert_eq!(Ok(4), len);
+
+        let (update, popped) = pop(value.clone(), ListEnd::Left, 1);
+        apply(&mut value, update);
+        assert_eq!(Ok(vec![Bytes::from("a")]), popped);
+        let (update, popped) = pop(value.clone(), ListEnd::Right, 2);
+        apply(&mut value, update);
+        assert_eq!(Ok(vec![Bytes::from("d"), Bytes::from("c")]), popped);
+        let (update, popped) = pop(value.clone(), ListEnd::Right, 5);
+        apply(&mut value, update);
+        assert_eq!(Ok(vec![Bytes::from("b")]), popped);
+        assert_eq!(None, value);
+    }
+
+    fn list(elements: &[&'static str]) -> Option<Bytes> {
+        let list = elements.iter().map(|e| Bytes::from(*e)).collect();
+        Some(Value::List(list).encode())
+    }
+
+    #[test]
+    fn positions_by_rank_count_and_maxlen() {
+        let value = list(&["a", "b", "c", "b", "b"]);
+        assert_eq!(Ok(vec![1]), positions(value.clone(), b"b", 1, 1, 0));
+        assert_eq!(Ok(vec![3, 4]), positions(value.clone(), b"b", 2, 0, 0));
+        assert_eq!(Ok(vec![4, 3]), positions(value.clone(), b"b", -1, 2, 0));
+        assert_eq!(Ok(vec![1]), positions(value.clone(), b"b", 1, 0, 3));
+        assert_eq!(Ok(vec![]), positions(value, b"x", 1, 0, 0));
+        assert_eq!(Ok(vec![]), positions(None, b"x", 1, 0, 0));
+    }
+
+    #[test]
+    fn insert_remove_trim_and_set() {
+        let mut value = list(&["a", "b", "a", "c", "a"]);
+
+        let (update, inserted) = insert(value.clone(), true, b"c", "x".into());
+        apply(&mut value, update);
+        assert_eq!(Ok(6), inserted);
+        let (update, inserted) = insert(value.clone(), false, b"missing", "x".into());
+        assert!(matches!(update, Update::Keep));
+        assert_eq!(Ok(-1), inserted);
+
+        let (update, removed) = remove(value.clone(), -2, b"a");
+        apply(&mut value, update);
+        assert_eq!(Ok(2), removed);
+        assert_eq!(list(&["a", "b", "x", "c"]), value);
+
+        let (update, result) = set(value.clone(), -1, "d".into());
+        apply(&mut value, update);
+        assert_eq!(Ok(()), result);
+        let (_, result) = set(value.clone(), 4, "d".into());
+        assert_eq!(Err(OUT_OF_RANGE), result);
+        let (_, result) = set(None, 0, "d".into());
+        assert_eq!(Err(NO_SUCH_KEY), result);
+
+        let (update, result) = trim(value.clone(), 1, -2);
+        apply(&mut value, update);
+        assert_eq!(Ok(()), result);
+        assert_eq!(list(&["b", "x"]), value);
+        assert_eq!(Ok(2), len(value.clone()));
+
+        // Trimming to an empty range deletes the list
+        let (update, _) = trim(value.clone(), 5, 10);
+        apply(&mut value, update);
+        assert_eq!(None, value);
+        assert_eq!(Ok(0), len(value));
+    }
+
+    #[test]
+    fn push_to_non_list_is_rejected() {
+        let (update, len) = push(Some("string".into()), ListEnd::Left, vec!["a".into()]);
+        assert!(matches!(update, Update::Keep));
+        assert_eq!(Err(WRONG_TYPE), len);
+    }
+}
@@ -0,0 +1,56 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LLEN command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Llen {
+    /// The key of the list
+    key: Utf8Bytes,
+}
+
+impl Llen {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes) -> Self {
+        Self { key }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the list
+        let value = net::spawn_blocking(move || storage.get(self.key.as_ref().clone()))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the length of the list
+        let response = match list::len(value) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Llen> for Frame {
+    fn from(cmd: Llen) -> Self {
+        Self::Array(vec![
+            Self::BulkString("LLEN".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ])
+    }
+}
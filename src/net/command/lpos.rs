@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LPOS command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lpos {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The element to search for
+    element: Bytes,
+    /// Which match to start from, negative ranks search from the tail of the list
+    rank: Option<i64>,
+    /// The max number of matches to return, 0 returns every match. When it is given, the
+    /// positions are sent back as an array.
+    count: Option<u64>,
+    /// The max number of elements to compare, 0 compares every element
+    maxlen: Option<u64>,
+}
+
+impl Lpos {
+    /// Creates a new set of arguments.
+    ///
+    /// LPOS requires that the rank must not be 0
+    pub fn new(
+        key: Utf8Bytes,
+        element: Bytes,
+        rank: Option<i64>,
+        count: Option<u64>,
+        maxlen: Option<u64>,
+    ) -> Self {
+        Self {
+            key,
+            element,
+            rank,
+            count,
+            maxlen,
+        }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the list
+        let key = self.key.as_ref().clone();
+        let value = net::spawn_blocking(move || storage.get(key))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the positions of the matches
+        let positions = list::positions(
+            value,
+            &self.element,
+            self.rank.unwrap_or(1),
+            self.count.map(|c| c as usize).unwrap_or(1),
+            self.maxlen.map(|m| m as usize).unwrap_or(0),
+        );
+        let response = match positions {
+            Ok(positions) if self.count.is_some() => Frame::Array(
+                positions
+                    .into_iter()
+                    .map(|i| Frame::Integer(i as i64))
+                    .collect(),
+            ),
+            Ok(positions) => match positions.first() {
+                Some(&i) => Frame::Integer(i as i64),
+                None => Frame::Null,
+            },
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Lpos> for Frame {
+    fn from(cmd: Lpos) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("LPOS".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.element),
+        ];
+        if let Some(rank) = cmd.rank {
+            cmd_data.push(Self::BulkString("RANK".into()));
+            cmd_data.push(Self::BulkString(rank.to_string().into()));
+        }
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        if let Some(maxlen) = cmd.maxlen {
+            cmd_data.push(Self::BulkString("MAXLEN".into()));
+            cmd_data.push(Self::BulkString(maxlen.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LREM command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lrem {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The max number of elements to remove, from the head when positive, from the tail when
+    /// negative, or every match when 0
+    count: i64,
+    /// The element to be removed
+    element: Bytes,
+}
+
+impl Lrem {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, count: i64, element: Bytes) -> Self {
+        Self {
+            key,
+            count,
+            element,
+        }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("LREM", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Remove the elements
+        let key = self.key.as_ref().clone();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| {
+                list::remove(value, self.count, &self.element)
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the number of removed elements
+        let response = match result {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Lrem> for Frame {
+    fn from(cmd: Lrem) -> Self {
+        Self::Array(vec![
+            Self::BulkString("LREM".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.count.to_string().into()),
+            Self::BulkString(cmd.element),
+        ])
+    }
+}
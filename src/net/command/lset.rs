@@ -0,0 +1,75 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LSET command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lset {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The index of the element, negative indices count from the tail
+    index: i64,
+    /// The new element
+    element: Bytes,
+}
+
+impl Lset {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, index: i64, element: Bytes) -> Self {
+        Self {
+            key,
+            index,
+            element,
+        }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("LSET", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Set the element
+        let key = self.key.as_ref().clone();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| list::set(value, self.index, self.element))
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with OK
+        let response = match result {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Lset> for Frame {
+    fn from(cmd: Lset) -> Self {
+        Self::Array(vec![
+            Self::BulkString("LSET".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.index.to_string().into()),
+            Self::BulkString(cmd.element),
+        ])
+    }
+}
@@ -0,0 +1,71 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{list, Utf8Bytes};
+
+/// Arguments for LTRIM command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ltrim {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The index of the first element to keep, negative indices count from the tail
+    start: i64,
+    /// The index of the last element to keep, negative indices count from the tail
+    stop: i64,
+}
+
+impl Ltrim {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, start: i64, stop: i64) -> Self {
+        Self { key, start, stop }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("LTRIM", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Trim the list
+        let key = self.key.as_ref().clone();
+        let (start, stop) = (self.start, self.stop);
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| list::trim(value, start, stop))
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with OK
+        let response = match result {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Ltrim> for Frame {
+    fn from(cmd: Ltrim) -> Self {
+        Self::Array(vec![
+            Self::BulkString("LTRIM".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(cmd.start.to_string().into()),
+            Self::BulkString(cmd.stop.to_string().into()),
+        ])
+    }
+}
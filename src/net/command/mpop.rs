@@ -0,0 +1,204 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{KeyValueStorage, Update},
+};
+
+use super::{
+    list::{self, ListEnd},
+    zset::{self, ZsetEnd},
+    Utf8Bytes,
+};
+
+/// Arguments for LMPOP command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lmpop {
+    /// The keys of the lists, checked in the given order
+    keys: Vec<Utf8Bytes>,
+    /// The end of the list from which the elements are popped
+    end: ListEnd,
+    /// The max number of elements to pop, 1 if not given
+    count: Option<u64>,
+}
+
+impl Lmpop {
+    /// Creates a new set of arguments.
+    ///
+    /// LMPOP requires that the list of keys must have at least 1 element
+    pub fn new(keys: Vec<Utf8Bytes>, end: ListEnd, count: Option<u64>) -> Self {
+        Self { keys, end, count }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("LMPOP", self.keys.iter().collect())
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Pop the elements from the first non-empty list
+        let keys = self.keys.iter().map(|k| k.as_ref().clone()).collect();
+        let count = self.count.map(|c| c as usize).unwrap_or(1);
+        let end = self.end;
+        let response = pop_first(
+            storage,
+            keys,
+            move |value| list::pop(value, end, count),
+            Frame::BulkString,
+        )
+        .await?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Lmpop> for Frame {
+    fn from(cmd: Lmpop) -> Self {
+        let end = match cmd.end {
+            ListEnd::Left => "LEFT",
+            ListEnd::Right => "RIGHT",
+        };
+        mpop_frame("LMPOP", cmd.keys, end, cmd.count)
+    }
+}
+
+/// Arguments for ZMPOP command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Zmpop {
+    /// The keys of the sorted sets, checked in the given order
+    keys: Vec<Utf8Bytes>,
+    /// The end of the sorted set from which the members are popped
+    end: ZsetEnd,
+    /// The max number of members to pop, 1 if not given
+    count: Option<u64>,
+}
+
+impl Zmpop {
+    /// Creates a new set of arguments.
+    ///
+    /// ZMPOP requires that the list of keys must have at least 1 element
+    pub fn new(keys: Vec<Utf8Bytes>, end: ZsetEnd, count: Option<u64>) -> Self {
+        Self { keys, end, count }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("ZMPOP", self.keys.iter().collect())
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Pop the members from the first non-empty sorted set
+        let keys = self.keys.iter().map(|k| k.as_ref().clone()).collect();
+        let count = self.count.map(|c| c as usize).unwrap_or(1);
+        let end = self.end;
+        let response = pop_first(
+            storage,
+            keys,
+            move |value| zset::pop(value, end, count),
+            |(member, score): (Bytes, f64)| {
+                Frame::Array(vec![
+                    Frame::BulkString(member),
+                    Frame::BulkString(score.to_string().into()),
+                ])
+            },
+        )
+        .await?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Zmpop> for Frame {
+    fn from(cmd: Zmpop) -> Self {
+        let end = match cmd.end {
+            ZsetEnd::Min => "MIN",
+            ZsetEnd::Max => "MAX",
+        };
+        mpop_frame("ZMPOP", cmd.keys, end, cmd.count)
+    }
+}
+
+/// Pop the items from the first key whose value is not empty, and respond with the key and the
+/// popped items. Responds with a null if all the values are empty.
+async fn pop_first<KV, T, P>(
+    storage: KV,
+    keys: Vec<Bytes>,
+    pop: P,
+    item_frame: fn(T) -> Frame,
+) -> Result<Frame, net::Error>
+where
+    KV: KeyValueStorage,
+    T: Send + 'static,
+    P: Fn(Option<Bytes>) -> (Update, Result<Vec<T>, &'static str>) + Send + 'static,
+{
+    net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            for key in keys {
+                let value = txn.get(key.clone())?;
+                let (update, result) = pop(value);
+                match update {
+                    Update::Keep => {}
+                    Update::Set(value) => {
+                        let expires_at = txn.get_expiry(key.clone())?;
+                        txn.set_with_expiry(key.clone(), value, expires_at)?;
+                    }
+                    Update::Delete => {
+                        txn.del(key.clone())?;
+                    }
+                }
+                match result {
+                    Ok(items) if !items.is_empty() => {
+                        let items = items.into_iter().map(item_frame).collect();
+                        return Ok(Frame::Array(vec![
+                            Frame::BulkString(key),
+                            Frame::Array(items),
+                        ]));
+                    }
+                    Ok(_) => continue,
+                    Err(e) => return Ok(Frame::Error(e.to_string())),
+                }
+            }
+            Ok(Frame::Null)
+        })
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))
+}
+
+/// Build the frame of an LMPOP or a ZMPOP command.
+fn mpop_frame(name: &str, keys: Vec<Utf8Bytes>, end: &str, count: Option<u64>) -> Frame {
+    let mut cmd_data = vec![
+        Frame::BulkString(name.to_string().into()),
+        Frame::BulkString(keys.len().to_string().into()),
+    ];
+    for key in keys {
+        cmd_data.push(Frame::BulkString(key.as_ref().clone()));
+    }
+    cmd_data.push(Frame::BulkString(end.to_string().into()));
+    if let Some(count) = count {
+        cmd_data.push(Frame::BulkString("COUNT".into()));
+        cmd_data.push(Frame::BulkString(count.to_string().into()));
+    }
+    Frame::Array(cmd_data)
+}
@@ -0,0 +1,107 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::Utf8Bytes;
+
+/// Arguments for OBJECT IDLETIME command
+#[derive(Debug, PartialEq, Eq)]
+pub struct ObjectIdleTime {
+    key: Utf8Bytes,
+}
+
+impl ObjectIdleTime {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes) -> Self {
+        Self { key }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the time since the key was last accessed
+        let idle_time = net::spawn_blocking(move || storage.idle_time(self.key.as_ref().clone()))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the idle time in seconds
+        let response = match idle_time {
+            Some(idle_time) => {
+                Frame::Integer(i64::try_from(idle_time.as_secs()).unwrap_or(i64::MAX))
+            }
+            None => Frame::Null,
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<ObjectIdleTime> for Frame {
+    fn from(cmd: ObjectIdleTime) -> Self {
+        Self::Array(vec![
+            Self::BulkString("OBJECT".into()),
+            Self::BulkString("IDLETIME".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ])
+    }
+}
+
+/// Arguments for OBJECT FREQ command
+#[derive(Debug, PartialEq, Eq)]
+pub struct ObjectFreq {
+    key: Utf8Bytes,
+}
+
+impl ObjectFreq {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes) -> Self {
+        Self { key }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the approximate access frequency of the key
+        let frequency =
+            net::spawn_blocking(move || storage.access_frequency(self.key.as_ref().clone()))
+                .await?
+                .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the logarithmic access counter
+        let response = match frequency {
+            Some(frequency) => Frame::Integer(i64::from(frequency)),
+            None => Frame::Null,
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<ObjectFreq> for Frame {
+    fn from(cmd: ObjectFreq) -> Self {
+        Self::Array(vec![
+            Self::BulkString("OBJECT".into()),
+            Self::BulkString("FREQ".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ])
+    }
+}
@@ -0,0 +1,90 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    list::{self, ListEnd},
+    Utf8Bytes,
+};
+
+/// Arguments for LPOP and RPOP commands
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pop {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The end of the list from which the elements are popped
+    end: ListEnd,
+    /// The max number of elements to pop. When it is given, the popped elements are sent back as
+    /// an array.
+    count: Option<u64>,
+}
+
+impl Pop {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, end: ListEnd, count: Option<u64>) -> Self {
+        Self { key, end, count }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let name = match self.end {
+            ListEnd::Left => "LPOP",
+            ListEnd::Right => "RPOP",
+        };
+        (name, vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Pop the elements
+        let key = self.key.as_ref().clone();
+        let count = self.count.map(|c| c as usize).unwrap_or(1);
+        let end = self.end;
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| list::pop(value, end, count))
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the popped elements
+        let response = match result {
+            Ok(elements) if elements.is_empty() => Frame::Null,
+            Ok(elements) if self.count.is_some() => {
+                Frame::Array(elements.into_iter().map(Frame::BulkString).collect())
+            }
+            Ok(mut elements) => Frame::BulkString(elements.remove(0)),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Pop> for Frame {
+    fn from(cmd: Pop) -> Self {
+        let name = match cmd.end {
+            ListEnd::Left => "LPOP",
+            ListEnd::Right => "RPOP",
+        };
+        let mut cmd_data = vec![
+            Self::BulkString(name.into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
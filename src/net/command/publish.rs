@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::net::{self, connection::Connection, frame::Frame, State};
+
+/// Arguments for PUBLISH command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Publish {
+    channel: Bytes,
+    message: Bytes,
+}
+
+impl Publish {
+    /// Creates a new set of arguments.
+    pub fn new(channel: Bytes, message: Bytes) -> Self {
+        Self { channel, message }
+    }
+
+    /// Send the message to the clients that subscribed to the channel, and reply with the number
+    /// of clients that received it.
+    #[tracing::instrument(skip(self, state, connection))]
+    pub async fn apply(
+        self,
+        state: &Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error> {
+        let receivers = state.pubsub().publish(&self.channel, &self.message);
+        let response = Frame::Integer(receivers as i64);
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Publish> for Frame {
+    fn from(cmd: Publish) -> Self {
+        Self::Array(vec![
+            Self::BulkString("PUBLISH".into()),
+            Self::BulkString(cmd.channel),
+            Self::BulkString(cmd.message),
+        ])
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame, State},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    list::{self, ListEnd},
+    Utf8Bytes,
+};
+
+/// Arguments for LPUSH and RPUSH commands
+#[derive(Debug, PartialEq, Eq)]
+pub struct Push {
+    /// The key of the list
+    key: Utf8Bytes,
+    /// The end of the list to which the elements are pushed
+    end: ListEnd,
+    /// The elements to be pushed
+    elements: Vec<Bytes>,
+}
+
+impl Push {
+    /// Creates a new set of arguments.
+    ///
+    /// LPUSH and RPUSH require that the list of elements must have at least 1 element
+    pub fn new(key: Utf8Bytes, end: ListEnd, elements: Vec<Bytes>) -> Self {
+        Self { key, end, elements }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let name = match self.end {
+            ListEnd::Left => "LPUSH",
+            ListEnd::Right => "RPUSH",
+        };
+        (name, vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance. Clients that are blocked
+    /// on the list are woken up once the elements are pushed.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, state, connection))]
+    pub async fn apply<KV>(
+        self,
+        storage: KV,
+        state: &Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Push the elements
+        let key = self.key.as_ref().clone();
+        let database = storage.selected_database();
+        let pushed = self.elements.len();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| list::push(value, self.end, self.elements))
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the length of the list
+        let response = match result {
+            Ok(len) => {
+                state.wake_waiters(&database, self.key.as_ref(), pushed);
+                Frame::Integer(len as i64)
+            }
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Push> for Frame {
+    fn from(cmd: Push) -> Self {
+        let name = match cmd.end {
+            ListEnd::Left => "LPUSH",
+            ListEnd::Right => "RPUSH",
+        };
+        let mut cmd_data = vec![
+            Self::BulkString(name.into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        for element in cmd.elements {
+            cmd_data.push(Self::BulkString(element));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,467 @@
+//! The table of supported commands. Each command declares its name, its arity, where its keys
+//! are, whether it writes, and how it's parsed, so parsing, introspection, and any check that
+//! needs to know about a command before parsing it are driven by the same declarations.
+
+use bytes::Bytes;
+
+use super::{
+    parse_bpop, parse_channels, parse_client, parse_object, parse_pop, parse_push, parse_rename,
+    parse_session, Command, CommandClass,
+    CommandClass::{Read, Write},
+    Error, ListEnd, Parser, SessionCommand,
+};
+#[cfg(feature = "scripting")]
+use super::{parse_eval, Script};
+
+/// The declaration of a command.
+#[derive(Debug)]
+pub struct CommandSpec {
+    name: &'static str,
+    arity: i64,
+    class: CommandClass,
+    keys: KeySpec,
+    parse: fn(Parser) -> Result<Command, Error>,
+}
+
+/// Where the keys are among the arguments of a command, where the command name is at position 0.
+#[derive(Debug, Clone, Copy)]
+pub enum KeySpec {
+    /// The command doesn't take keys.
+    None,
+    /// The keys are at the positions from `first` to `last` that are `step` apart.
+    Range {
+        /// The position of the first key.
+        first: usize,
+        /// The position of the last key, where a negative position counts from the end of the
+        /// arguments.
+        last: i64,
+        /// The distance between the keys.
+        step: usize,
+    },
+    /// The number of keys is at a position, and the keys directly follow it.
+    Counted {
+        /// The position of the number of keys.
+        index: usize,
+    },
+    /// The positions of the keys depend on the other arguments, and are found by the function.
+    Custom(fn(&[Bytes]) -> Vec<usize>),
+}
+
+impl CommandSpec {
+    /// Find the declaration of the command with the given name, ignoring case.
+    pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+    }
+
+    /// Get the declarations of all the commands.
+    pub fn all() -> &'static [CommandSpec] {
+        COMMANDS
+    }
+
+    /// Get the name of the command in upper case.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Get the number of arguments of the command, including its name. A negative arity means
+    /// that the command takes at least that many arguments, like in Redis.
+    pub fn arity(&self) -> i64 {
+        self.arity
+    }
+
+    /// Get whether the command can write to the storage. Some commands that can write, such as
+    /// GETEX, only write with some of their options.
+    pub fn class(&self) -> CommandClass {
+        self.class
+    }
+
+    /// Get where the keys are among the arguments of the command.
+    pub fn key_spec(&self) -> KeySpec {
+        self.keys
+    }
+
+    /// Find the keys among the arguments of a call to the command, where the command name is the
+    /// first argument.
+    pub fn keys<'a>(&self, args: &'a [Bytes]) -> Vec<&'a Bytes> {
+        let positions: Vec<usize> = match self.keys {
+            KeySpec::None => Vec::new(),
+            KeySpec::Range { first, last, step } => {
+                let last = if last < 0 {
+                    args.len() as i64 + last
+                } else {
+                    last
+                };
+                match usize::try_from(last) {
+                    Ok(last) if first <= last => (first..=last).step_by(step).collect(),
+                    _ => Vec::new(),
+                }
+            }
+            KeySpec::Counted { index } => {
+                let count = args
+                    .get(index)
+                    .and_then(|n| std::str::from_utf8(n).ok())
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(0);
+                (index + 1..).take(count.min(args.len())).collect()
+            }
+            KeySpec::Custom(find) => find(args),
+        };
+        positions.into_iter().filter_map(|i| args.get(i)).collect()
+    }
+
+    /// Parse the arguments that follow the command name.
+    pub(super) fn parse(&self, parser: Parser) -> Result<Command, Error> {
+        (self.parse)(parser)
+    }
+}
+
+/// The keys of BATCH are the arguments after each operation.
+fn batch_keys(args: &[Bytes]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut i = 1;
+    while i + 1 < args.len() {
+        positions.push(i + 1);
+        i += if args[i].eq_ignore_ascii_case(b"SET") {
+            3
+        } else {
+            2
+        };
+    }
+    positions
+}
+
+/// The keys of XREAD are the first half of the arguments after STREAMS.
+fn xread_keys(args: &[Bytes]) -> Vec<usize> {
+    match args
+        .iter()
+        .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+    {
+        Some(streams) => (streams + 1..)
+            .take((args.len() - streams - 1) / 2)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    class: CommandClass,
+    keys: KeySpec,
+    parse: fn(Parser) -> Result<Command, Error>,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        class,
+        keys,
+        parse,
+    }
+}
+
+const fn one_key() -> KeySpec {
+    KeySpec::Range {
+        first: 1,
+        last: 1,
+        step: 1,
+    }
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    spec("AUDIT", -1, Read, KeySpec::None, |p| {
+        Ok(Command::Audit(p.try_into()?))
+    }),
+    spec("BATCH", -3, Write, KeySpec::Custom(batch_keys), |p| {
+        Ok(Command::Batch(p.try_into()?))
+    }),
+    spec("BGPAUSE", -1, Read, KeySpec::None, |p| {
+        Ok(Command::BgPause(p.try_into()?))
+    }),
+    spec(
+        "BLPOP",
+        -3,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+        |p| Ok(Command::BlockingPop(parse_bpop(ListEnd::Left, p)?)),
+    ),
+    spec(
+        "BRPOP",
+        -3,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        },
+        |p| Ok(Command::BlockingPop(parse_bpop(ListEnd::Right, p)?)),
+    ),
+    spec("CLIENT", -2, Read, KeySpec::None, parse_client),
+    spec("COMMAND", -1, Read, KeySpec::None, |p| {
+        Ok(Command::Info(p.try_into()?))
+    }),
+    spec(
+        "COPY",
+        -3,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: 2,
+            step: 1,
+        },
+        |p| Ok(Command::Copy(p.try_into()?)),
+    ),
+    spec("DATAFILES", 1, Read, KeySpec::None, |p| {
+        Ok(Command::DataFiles(p.try_into()?))
+    }),
+    spec(
+        "DEL",
+        -2,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: -1,
+            step: 1,
+        },
+        |p| Ok(Command::Del(p.try_into()?)),
+    ),
+    spec("DELPREFIX", 2, Write, KeySpec::None, |p| {
+        Ok(Command::DelPrefix(p.try_into()?))
+    }),
+    spec("DISCARD", 1, Read, KeySpec::None, |p| {
+        Ok(Command::Session(parse_session(SessionCommand::Discard, p)?))
+    }),
+    #[cfg(feature = "scripting")]
+    spec("EVAL", -3, Write, KeySpec::Counted { index: 2 }, |mut p| {
+        let script = p
+            .get_bytes()?
+            .ok_or(Error::BadArguments("Script is not given"))?;
+        Ok(Command::Eval(parse_eval(Script::Source(script), p)?))
+    }),
+    #[cfg(feature = "scripting")]
+    spec(
+        "EVALSHA",
+        -3,
+        Write,
+        KeySpec::Counted { index: 2 },
+        |mut p| {
+            let sha = p
+                .get_string()?
+                .ok_or(Error::BadArguments("Script is not given"))?;
+            Ok(Command::Eval(parse_eval(Script::Sha(sha), p)?))
+        },
+    ),
+    spec("EXEC", 1, Write, KeySpec::None, |p| {
+        Ok(Command::Session(parse_session(SessionCommand::Exec, p)?))
+    }),
+    spec("GEOADD", -5, Write, one_key(), |p| {
+        Ok(Command::Geoadd(p.try_into()?))
+    }),
+    spec("GEOSEARCH", -7, Read, one_key(), |p| {
+        Ok(Command::Geosearch(p.try_into()?))
+    }),
+    spec("GET", 2, Read, one_key(), |p| {
+        Ok(Command::Get(p.try_into()?))
+    }),
+    spec("GETEX", -2, Write, one_key(), |p| {
+        Ok(Command::GetEx(p.try_into()?))
+    }),
+    spec("INFO", -1, Read, KeySpec::None, |p| {
+        Ok(Command::ServerInfo(p.try_into()?))
+    }),
+    spec("JSON.GET", -2, Read, one_key(), |p| {
+        Ok(Command::JsonGet(p.try_into()?))
+    }),
+    spec("JSON.SET", -4, Write, one_key(), |p| {
+        Ok(Command::JsonSet(p.try_into()?))
+    }),
+    spec("LINSERT", 5, Write, one_key(), |p| {
+        Ok(Command::Linsert(p.try_into()?))
+    }),
+    spec("LLEN", 2, Read, one_key(), |p| {
+        Ok(Command::Llen(p.try_into()?))
+    }),
+    spec("LMPOP", -4, Write, KeySpec::Counted { index: 1 }, |p| {
+        Ok(Command::Lmpop(p.try_into()?))
+    }),
+    spec("LPOP", -2, Write, one_key(), |p| {
+        Ok(Command::Pop(parse_pop(ListEnd::Left, p)?))
+    }),
+    spec("LPOS", -3, Read, one_key(), |p| {
+        Ok(Command::Lpos(p.try_into()?))
+    }),
+    spec("LPUSH", -3, Write, one_key(), |p| {
+        Ok(Command::Push(parse_push(ListEnd::Left, p)?))
+    }),
+    spec("LREM", 4, Write, one_key(), |p| {
+        Ok(Command::Lrem(p.try_into()?))
+    }),
+    spec("LSET", 4, Write, one_key(), |p| {
+        Ok(Command::Lset(p.try_into()?))
+    }),
+    spec("LTRIM", 4, Write, one_key(), |p| {
+        Ok(Command::Ltrim(p.try_into()?))
+    }),
+    spec("MONITOR", 1, Read, KeySpec::None, |p| {
+        Ok(Command::Session(parse_session(SessionCommand::Monitor, p)?))
+    }),
+    spec("MULTI", 1, Read, KeySpec::None, |p| {
+        Ok(Command::Session(parse_session(SessionCommand::Multi, p)?))
+    }),
+    spec(
+        "OBJECT",
+        -2,
+        Read,
+        KeySpec::Range {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+        parse_object,
+    ),
+    spec("PUBLISH", 3, Read, KeySpec::None, |p| {
+        Ok(Command::Publish(p.try_into()?))
+    }),
+    spec(
+        "RENAME",
+        3,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: 2,
+            step: 1,
+        },
+        |p| Ok(Command::Rename(parse_rename(true, p)?)),
+    ),
+    spec(
+        "RENAMENX",
+        3,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: 2,
+            step: 1,
+        },
+        |p| Ok(Command::Rename(parse_rename(false, p)?)),
+    ),
+    spec("RESET", 1, Read, KeySpec::None, |p| {
+        Ok(Command::Session(parse_session(SessionCommand::Reset, p)?))
+    }),
+    spec("RPOP", -2, Write, one_key(), |p| {
+        Ok(Command::Pop(parse_pop(ListEnd::Right, p)?))
+    }),
+    spec("RPUSH", -3, Write, one_key(), |p| {
+        Ok(Command::Push(parse_push(ListEnd::Right, p)?))
+    }),
+    spec("SADD", -3, Write, one_key(), |p| {
+        Ok(Command::Sadd(p.try_into()?))
+    }),
+    spec("SCANRANGE", -3, Read, KeySpec::None, |p| {
+        Ok(Command::ScanRange(p.try_into()?))
+    }),
+    spec("SELECT", 2, Read, KeySpec::None, |p| {
+        Ok(Command::Select(p.try_into()?))
+    }),
+    spec("SET", -3, Write, one_key(), |p| {
+        Ok(Command::Set(p.try_into()?))
+    }),
+    spec("SINTERCARD", -3, Read, KeySpec::Counted { index: 1 }, |p| {
+        Ok(Command::Sintercard(p.try_into()?))
+    }),
+    spec("SUBSCRIBE", -2, Read, KeySpec::None, |p| {
+        let channels = parse_channels(p)?;
+        if channels.is_empty() {
+            return Err(Error::BadArguments("Channels are empty"));
+        }
+        Ok(Command::Session(SessionCommand::Subscribe(channels)))
+    }),
+    spec(
+        "UNLINK",
+        -2,
+        Write,
+        KeySpec::Range {
+            first: 1,
+            last: -1,
+            step: 1,
+        },
+        |p| Ok(Command::Unlink(p.try_into()?)),
+    ),
+    spec("UNSUBSCRIBE", -1, Read, KeySpec::None, |p| {
+        Ok(Command::Session(SessionCommand::Unsubscribe(
+            parse_channels(p)?,
+        )))
+    }),
+    spec("XADD", -5, Write, one_key(), |p| {
+        Ok(Command::Xadd(p.try_into()?))
+    }),
+    spec("XRANGE", -4, Read, one_key(), |p| {
+        Ok(Command::Xrange(p.try_into()?))
+    }),
+    spec("XREAD", -4, Read, KeySpec::Custom(xread_keys), |p| {
+        Ok(Command::Xread(p.try_into()?))
+    }),
+    spec("ZMPOP", -4, Write, KeySpec::Counted { index: 1 }, |p| {
+        Ok(Command::Zmpop(p.try_into()?))
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&'static str]) -> Vec<Bytes> {
+        args.iter().map(|arg| Bytes::from(*arg)).collect()
+    }
+
+    fn keys(name: &str, call: &[&'static str]) -> Vec<Bytes> {
+        let spec = CommandSpec::lookup(name.as_bytes()).unwrap();
+        spec.keys(&args(call)).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn lookup_ignores_case() {
+        assert_eq!("GET", CommandSpec::lookup(b"get").unwrap().name());
+        assert_eq!("JSON.SET", CommandSpec::lookup(b"Json.Set").unwrap().name());
+        assert!(CommandSpec::lookup(b"INVALID").is_none());
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names: Vec<_> = CommandSpec::all().iter().map(|s| s.name()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(CommandSpec::all().len(), names.len());
+    }
+
+    #[test]
+    fn keys_are_extracted() {
+        assert_eq!(args(&["a"]), keys("GET", &["GET", "a"]));
+        assert_eq!(args(&["a", "b"]), keys("DEL", &["DEL", "a", "b"]));
+        assert_eq!(args(&["a", "b"]), keys("BLPOP", &["BLPOP", "a", "b", "0"]));
+        assert_eq!(
+            args(&["a", "b"]),
+            keys("LMPOP", &["LMPOP", "2", "a", "b", "LEFT"])
+        );
+        assert_eq!(
+            args(&["a", "b"]),
+            keys("BATCH", &["BATCH", "SET", "a", "1", "DEL", "b"])
+        );
+        assert_eq!(
+            args(&["a", "b"]),
+            keys(
+                "XREAD",
+                &["XREAD", "COUNT", "1", "STREAMS", "a", "b", "0", "0"]
+            )
+        );
+        assert_eq!(args(&["a"]), keys("OBJECT", &["OBJECT", "IDLETIME", "a"]));
+        assert!(keys("SCANRANGE", &["SCANRANGE", "a", "b"]).is_empty());
+        // Missing keys are skipped
+        assert!(keys("GET", &["GET"]).is_empty());
+        assert!(keys("DEL", &["DEL"]).is_empty());
+    }
+}
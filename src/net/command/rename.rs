@@ -0,0 +1,86 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{KeyValueStorage, Transfer},
+};
+
+use super::{stream, Utf8Bytes};
+
+/// Arguments for RENAME and RENAMENX commands
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rename {
+    /// The key to be renamed
+    src: Utf8Bytes,
+    /// The new name of the key
+    dst: Utf8Bytes,
+    /// Whether the destination key is replaced if it exists. This is `false` for RENAMENX.
+    replace: bool,
+}
+
+impl Rename {
+    /// Creates a new set of arguments
+    pub fn new(src: Utf8Bytes, dst: Utf8Bytes, replace: bool) -> Self {
+        Self { src, dst, replace }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        let name = if self.replace { "RENAME" } else { "RENAMENX" };
+        (name, vec![&self.src, &self.dst])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Rename the key within a single atomic operation
+        let replace = self.replace;
+        let transfer = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let src = self.src.as_ref().clone();
+                let dst = self.dst.as_ref().clone();
+                if txn.get(src.clone())?.is_none() {
+                    return Ok(Transfer::SourceNotFound);
+                }
+                if src != dst {
+                    if !replace && txn.get(dst.clone())?.is_some() {
+                        return Ok(Transfer::DestinationExists);
+                    }
+                    stream::delete_chunks(txn, dst.clone())?;
+                }
+                txn.rename(src, dst, replace)
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the outcome in the format of the command that was run
+        let response = match (transfer, replace) {
+            (Transfer::SourceNotFound, _) => Frame::Error("ERR no such key".into()),
+            (Transfer::Done, true) => Frame::SimpleString("OK".into()),
+            (Transfer::Done, false) => Frame::Integer(1),
+            (Transfer::DestinationExists, _) => Frame::Integer(0),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Rename> for Frame {
+    fn from(cmd: Rename) -> Self {
+        let name = if cmd.replace { "RENAME" } else { "RENAMENX" };
+        Self::Array(vec![
+            Self::BulkString(name.into()),
+            Self::BulkString(cmd.src.as_ref().clone()),
+            Self::BulkString(cmd.dst.as_ref().clone()),
+        ])
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::{KeyValueStorage, Update},
+};
+
+use super::{
+    value::{Value, WRONG_TYPE},
+    Utf8Bytes,
+};
+
+/// Arguments for SADD command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sadd {
+    /// The key of the set
+    key: Utf8Bytes,
+    /// The members to add
+    members: Vec<Bytes>,
+}
+
+impl Sadd {
+    /// Creates a new set of arguments
+    pub fn new(key: Utf8Bytes, members: Vec<Bytes>) -> Self {
+        Self { key, members }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("SADD", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Add the members
+        let key = self.key.as_ref().clone();
+        let result = net::spawn_blocking(move || {
+            storage.update(key, move |value| {
+                let mut set = match value.map(Value::decode) {
+                    None => BTreeSet::new(),
+                    Some(Value::Set(set)) => set,
+                    Some(_) => return (Update::Keep, Err(WRONG_TYPE)),
+                };
+                let len = set.len();
+                set.extend(self.members);
+                let added = (set.len() - len) as i64;
+                if added == 0 {
+                    return (Update::Keep, Ok(0));
+                }
+                (Update::Set(Value::Set(set).encode()), Ok(added))
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the number of added members
+        let response = match result {
+            Ok(count) => Frame::Integer(count),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Sadd> for Frame {
+    fn from(cmd: Sadd) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("SADD".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+        ];
+        cmd_data.extend(cmd.members.into_iter().map(Self::BulkString));
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,97 @@
+use std::ops::Bound;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{stream, Error};
+
+/// Arguments for SCANRANGE command
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScanRange {
+    /// The lower bound of the range
+    start: Bound<Bytes>,
+    /// The upper bound of the range
+    end: Bound<Bytes>,
+    /// The max number of keys to return
+    count: Option<u64>,
+}
+
+impl ScanRange {
+    /// Creates a new set of arguments
+    pub fn new(start: Bound<Bytes>, end: Bound<Bytes>, count: Option<u64>) -> Self {
+        Self { start, end, count }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the keys within the range
+        let count = self.count.map(|c| c as usize).unwrap_or(usize::MAX);
+        let keys = net::spawn_blocking(move || storage.scan_range(self.start, self.end, count))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the list of keys, leaving out the internal keys
+        let response = Frame::Array(
+            keys.into_iter()
+                .filter(|k| !stream::is_chunk_key(k))
+                .map(Frame::BulkString)
+                .collect(),
+        );
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<ScanRange> for Frame {
+    fn from(cmd: ScanRange) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("SCANRANGE".into()),
+            Self::BulkString(encode_bound(cmd.start, b"-")),
+            Self::BulkString(encode_bound(cmd.end, b"+")),
+        ];
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
+
+/// Parse a range item that follows the syntax of Redis's ZRANGEBYLEX. Items starting with `[` are
+/// inclusive, items starting with `(` are exclusive, and `unbounded` (`-` or `+`) is unbounded.
+pub(super) fn parse_bound(item: Bytes, unbounded: &[u8]) -> Result<Bound<Bytes>, Error> {
+    if item == unbounded {
+        return Ok(Bound::Unbounded);
+    }
+    match item.first() {
+        Some(b'[') => Ok(Bound::Included(item.slice(1..))),
+        Some(b'(') => Ok(Bound::Excluded(item.slice(1..))),
+        _ => Err(Error::BadArguments("Range item is not valid")),
+    }
+}
+
+fn encode_bound(bound: Bound<Bytes>, unbounded: &[u8]) -> Bytes {
+    let (prefix, key) = match bound {
+        Bound::Included(key) => (b'[', key),
+        Bound::Excluded(key) => (b'(', key),
+        Bound::Unbounded => return Bytes::copy_from_slice(unbounded),
+    };
+    let mut buf = BytesMut::with_capacity(key.len() + 1);
+    buf.put_u8(prefix);
+    buf.put_slice(&key);
+    buf.freeze()
+}
@@ -0,0 +1,55 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::Utf8Bytes;
+
+/// Arguments for SELECT command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Select {
+    /// The name of the database to switch to
+    name: Utf8Bytes,
+}
+
+impl Select {
+    /// Creates a new set of arguments
+    pub fn new(name: Utf8Bytes) -> Self {
+        Self { name }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Switch the connection to the database, the following commands are applied to it
+        let selected = net::spawn_blocking(move || storage.select(self.name.as_ref()))
+            .await?
+            .map_err(|e| net::Error::Storage(e.into()))?;
+        let response = if selected {
+            Frame::SimpleString("OK".to_string())
+        } else {
+            Frame::Error("ERR no such database".to_string())
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Select> for Frame {
+    fn from(cmd: Select) -> Self {
+        Self::Array(vec![
+            Self::BulkString("SELECT".into()),
+            Self::BulkString(cmd.name.as_ref().clone()),
+        ])
+    }
+}
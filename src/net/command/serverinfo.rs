@@ -0,0 +1,109 @@
+use std::{fmt::Write, sync::Arc};
+
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame, State},
+    storage::KeyValueStorage,
+};
+
+use super::Utf8Bytes;
+
+/// The number of keys listed in the hotkeys section.
+const HOT_KEYS: usize = 10;
+
+/// Arguments for INFO command
+#[derive(Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The sections to report, all of them when empty
+    sections: Vec<Utf8Bytes>,
+}
+
+impl ServerInfo {
+    /// Creates a new set of arguments
+    pub fn new(sections: Vec<Utf8Bytes>) -> Self {
+        Self { sections }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, state, connection))]
+    pub async fn apply<KV>(
+        self,
+        storage: KV,
+        state: &Arc<State>,
+        connection: &mut Connection,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Each section starts with its title and lists its fields as `name:value` lines, like in
+        // Redis. Unknown sections are left out.
+        let mut info = String::new();
+        if self.includes("listener") {
+            info.push_str("# Listener\r\n");
+            write!(
+                info,
+                "accept_failures:{}\r\nrejected_connections:{}\r\nlistener_down:{}\r\n",
+                state.accept_failures(),
+                state.rejected_connections(),
+                u8::from(state.listener_down())
+            )
+            .expect("writing to a string can't fail");
+        }
+        if self.includes("maintenance") {
+            let storage = storage.clone();
+            let paused = net::spawn_blocking(move || storage.maintenance_paused())
+                .await?
+                .map_err(|e| net::Error::Storage(e.into()))?;
+            info.push_str("# Maintenance\r\n");
+            write!(info, "maintenance_paused:{}\r\n", u8::from(paused))
+                .expect("writing to a string can't fail");
+        }
+        if self.includes("hotkeys") {
+            let hot_keys = net::spawn_blocking(move || storage.hot_keys(HOT_KEYS))
+                .await?
+                .map_err(|e| net::Error::Storage(e.into()))?;
+            info.push_str("# Hotkeys\r\n");
+            for (i, (key, count)) in hot_keys.iter().enumerate() {
+                write!(
+                    info,
+                    "hotkey{i}:key={},count={count}\r\n",
+                    key.escape_ascii()
+                )
+                .expect("writing to a string can't fail");
+            }
+        }
+        let response = Frame::BulkString(info.into());
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+
+    /// Return whether the section was requested.
+    fn includes(&self, section: &str) -> bool {
+        self.sections.is_empty()
+            || self.sections.iter().any(|s| {
+                let s = s.as_ref();
+                s.eq_ignore_ascii_case(section.as_bytes())
+                    || s.eq_ignore_ascii_case(b"all")
+                    || s.eq_ignore_ascii_case(b"everything")
+                    || s.eq_ignore_ascii_case(b"default")
+            })
+    }
+}
+
+impl From<ServerInfo> for Frame {
+    fn from(cmd: ServerInfo) -> Self {
+        let mut frames = vec![Self::BulkString("INFO".into())];
+        frames.extend(
+            cmd.sections
+                .into_iter()
+                .map(|s| Self::BulkString(s.as_ref().clone())),
+        );
+        Self::Array(frames)
+    }
+}
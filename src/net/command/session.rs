@@ -0,0 +1,40 @@
+use bytes::Bytes;
+
+use crate::net::frame::Frame;
+
+/// Commands that change the state of the connection instead of the storage. They are handled by
+/// the connection, whose state decides which of them are allowed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionCommand {
+    /// MULTI
+    Multi,
+    /// EXEC
+    Exec,
+    /// DISCARD
+    Discard,
+    /// SUBSCRIBE channel [channel ...]
+    Subscribe(Vec<Bytes>),
+    /// UNSUBSCRIBE [channel [channel ...]]
+    Unsubscribe(Vec<Bytes>),
+    /// MONITOR
+    Monitor,
+    /// RESET
+    Reset,
+}
+
+impl From<SessionCommand> for Frame {
+    fn from(cmd: SessionCommand) -> Self {
+        let (name, channels) = match cmd {
+            SessionCommand::Multi => ("MULTI", Vec::new()),
+            SessionCommand::Exec => ("EXEC", Vec::new()),
+            SessionCommand::Discard => ("DISCARD", Vec::new()),
+            SessionCommand::Subscribe(channels) => ("SUBSCRIBE", channels),
+            SessionCommand::Unsubscribe(channels) => ("UNSUBSCRIBE", channels),
+            SessionCommand::Monitor => ("MONITOR", Vec::new()),
+            SessionCommand::Reset => ("RESET", Vec::new()),
+        };
+        let mut cmd_data = vec![Self::BulkString(name.into())];
+        cmd_data.extend(channels.into_iter().map(Self::BulkString));
+        Self::Array(cmd_data)
+    }
+}
@@ -1,12 +1,19 @@
+use std::time::SystemTime;
+
 use bytes::Bytes;
 use tracing::debug;
 
 use crate::{
     net::{self, connection::Connection, frame::Frame},
-    storage::KeyValueStorage,
+    storage::{Durability, KeyValueStorage},
 };
 
-use super::Utf8Bytes;
+use super::{
+    expiry::Expiry,
+    stream,
+    value::{Value, WRONG_TYPE},
+    Utf8Bytes,
+};
 
 /// Arguments for SET command
 #[derive(Debug, PartialEq, Eq)]
@@ -15,12 +22,65 @@ pub struct Set {
     key: Utf8Bytes,
     /// The value to be set
     value: Bytes,
+    /// The condition under which the value is set
+    condition: Option<SetCondition>,
+    /// The change to the key's expiry
+    expiry: Option<Expiry>,
+    /// Whether the old value is sent back
+    get: bool,
+    /// Whether the reply waits until the write is on disk
+    sync: bool,
+}
+
+/// The condition under which SET sets the value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetCondition {
+    /// Only set the key if it does not already exist.
+    Nx,
+    /// Only set the key if it already exists.
+    Xx,
 }
 
 impl Set {
     /// Creates a new set of arguments
     pub fn new(key: Utf8Bytes, value: Bytes) -> Self {
-        Self { key, value }
+        Self {
+            key,
+            value,
+            condition: None,
+            expiry: None,
+            get: false,
+            sync: false,
+        }
+    }
+
+    /// Only set the value when the given condition is met.
+    pub fn with_condition(mut self, condition: SetCondition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Change the key's expiry with the given option.
+    pub fn with_expiry(mut self, expiry: Expiry) -> Self {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// Send back the old value of the key.
+    pub fn with_get(mut self) -> Self {
+        self.get = true;
+        self
+    }
+
+    /// Wait until the write is on disk before replying.
+    pub fn with_sync(mut self) -> Self {
+        self.sync = true;
+        self
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("SET", vec![&self.key])
     }
 
     /// Apply the command to the specified [`StorageEngine`] instance.
@@ -31,13 +91,61 @@ impl Set {
     where
         KV: KeyValueStorage,
     {
-        // Set the key's value
-        tokio::task::spawn_blocking(move || storage.set(self.key.as_ref().clone(), self.value))
+        let durability = if self.sync {
+            Durability::Batched
+        } else {
+            Durability::None
+        };
+        let response = if self.condition.is_none() && self.expiry.is_none() && !self.get {
+            // Set the key's value, removing the chunks of the stream that it may hold
+            net::spawn_blocking(move || {
+                let key = self.key.as_ref().clone();
+                storage.atomically(move |txn| {
+                    stream::delete_chunks(txn, key.clone())?;
+                    txn.set(key, self.value)
+                })?;
+                storage.make_durable(durability)
+            })
             .await?
             .map_err(|e| net::Error::Storage(e.into()))?;
-
-        // Responding OK
-        let response = Frame::SimpleString("OK".to_string());
+            Frame::SimpleString("OK".to_string())
+        } else {
+            // Check the current value and set the new one within a single atomic operation
+            let now = SystemTime::now();
+            net::spawn_blocking(move || {
+                let reply = storage.atomically(move |txn| {
+                    let key = self.key.as_ref().clone();
+                    let prev = txn.get(key.clone())?.map(Value::decode);
+                    // Sending back the old value is only possible for strings
+                    let reply = match &prev {
+                        Some(Value::String(v)) if self.get => Frame::BulkString(v.clone()),
+                        Some(_) if self.get => return Ok(Frame::Error(WRONG_TYPE.to_string())),
+                        None if self.get => Frame::Null,
+                        _ => Frame::SimpleString("OK".to_string()),
+                    };
+                    let can_set = match self.condition {
+                        Some(SetCondition::Nx) => prev.is_none(),
+                        Some(SetCondition::Xx) => prev.is_some(),
+                        None => true,
+                    };
+                    if !can_set {
+                        return Ok(if self.get { reply } else { Frame::Null });
+                    }
+                    let expires_at = match self.expiry {
+                        Some(Expiry::KeepTtl) => txn.get_expiry(key.clone())?,
+                        Some(expiry) => expiry.expires_at(now),
+                        None => None,
+                    };
+                    stream::delete_chunks(txn, key.clone())?;
+                    txn.set_with_expiry(key, self.value, expires_at)?;
+                    Ok(reply)
+                })?;
+                storage.make_durable(durability)?;
+                Ok(reply)
+            })
+            .await?
+            .map_err(|e: KV::Error| net::Error::Storage(e.into()))?
+        };
         debug!(?response);
 
         // Write the response to the client
@@ -48,10 +156,25 @@ impl Set {
 
 impl From<Set> for Frame {
     fn from(cmd: Set) -> Self {
-        Self::Array(vec![
+        let mut cmd_data = vec![
             Self::BulkString("SET".into()),
             Self::BulkString(cmd.key.as_ref().clone()),
             Self::BulkString(cmd.value),
-        ])
+        ];
+        match cmd.condition {
+            Some(SetCondition::Nx) => cmd_data.push(Self::BulkString("NX".into())),
+            Some(SetCondition::Xx) => cmd_data.push(Self::BulkString("XX".into())),
+            None => {}
+        }
+        if cmd.get {
+            cmd_data.push(Self::BulkString("GET".into()));
+        }
+        if let Some(expiry) = cmd.expiry {
+            cmd_data.extend(expiry.to_args().into_iter().map(Self::BulkString));
+        }
+        if cmd.sync {
+            cmd_data.push(Self::BulkString("SYNC".into()));
+        }
+        Self::Array(cmd_data)
     }
 }
@@ -0,0 +1,131 @@
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    value::{Value, WRONG_TYPE},
+    Utf8Bytes,
+};
+
+/// Arguments for SINTERCARD command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sintercard {
+    /// The keys of the sets
+    keys: Vec<Utf8Bytes>,
+    /// The count at which the intersection stops being counted, no limit if not given or 0
+    limit: Option<u64>,
+}
+
+impl Sintercard {
+    /// Creates a new set of arguments.
+    ///
+    /// SINTERCARD requires that the list of keys must have at least 1 element
+    pub fn new(keys: Vec<Utf8Bytes>, limit: Option<u64>) -> Self {
+        Self { keys, limit }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the sets
+        let keys: Vec<_> = self.keys.iter().map(|k| k.as_ref().clone()).collect();
+        let values = net::spawn_blocking(move || {
+            keys.into_iter()
+                .map(|key| storage.get(key))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+        // Responding with the number of members in all of the sets
+        let limit = self.limit.filter(|&l| l > 0).unwrap_or(u64::MAX) as usize;
+        let response = match intersection_len(values, limit) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => Frame::Error(e.to_string()),
+        };
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Sintercard> for Frame {
+    fn from(cmd: Sintercard) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("SINTERCARD".into()),
+            Self::BulkString(cmd.keys.len().to_string().into()),
+        ];
+        cmd_data.extend(
+            cmd.keys
+                .into_iter()
+                .map(|key| Self::BulkString(key.as_ref().clone())),
+        );
+        if let Some(limit) = cmd.limit {
+            cmd_data.push(Self::BulkString("LIMIT".into()));
+            cmd_data.push(Self::BulkString(limit.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
+
+/// Count the members that are in all of the sets that are stored as `values`, up to `limit`. A
+/// set that doesn't exist is empty.
+fn intersection_len(values: Vec<Option<Bytes>>, limit: usize) -> Result<usize, &'static str> {
+    let mut sets = Vec::with_capacity(values.len());
+    for value in values {
+        match value.map(Value::decode) {
+            None => sets.push(BTreeSet::new()),
+            Some(Value::Set(set)) => sets.push(set),
+            Some(_) => return Err(WRONG_TYPE),
+        }
+    }
+    // Only the members of the smallest set have to be checked
+    sets.sort_unstable_by_key(BTreeSet::len);
+    let Some((smallest, others)) = sets.split_first() else {
+        return Ok(0);
+    };
+    let len = smallest
+        .iter()
+        .filter(|member| others.iter().all(|set| set.contains(*member)))
+        .take(limit)
+        .count();
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(members: &[&'static str]) -> Option<Bytes> {
+        let set = members.iter().map(|m| Bytes::from(*m)).collect();
+        Some(Value::Set(set).encode())
+    }
+
+    #[test]
+    fn intersections_are_counted_up_to_the_limit() {
+        let values = vec![set(&["a", "b", "c", "d"]), set(&["b", "c", "d", "e"])];
+        assert_eq!(Ok(3), intersection_len(values.clone(), usize::MAX));
+        assert_eq!(Ok(2), intersection_len(values.clone(), 2));
+
+        let mut with_missing = values.clone();
+        with_missing.push(None);
+        assert_eq!(Ok(0), intersection_len(with_missing, usize::MAX));
+
+        let mut with_string = values;
+        with_string.push(Some("v".into()));
+        assert_eq!(Err(WRONG_TYPE), intersection_len(with_string, usize::MAX));
+    }
+}
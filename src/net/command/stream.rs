@@ -0,0 +1,490 @@
+//! Streams are append-only sequences of entries that are identified by increasing IDs. The
+//! metadata of a stream is stored under the stream's key, while its entries are grouped into
+//! chunks that are stored under internal keys derived from a random ID given to the stream when
+//! it's created. Appending an entry only rewrites the metadata and the last chunk, and range
+//! queries only read the chunks that overlap with the range. Since the chunk keys don't depend on
+//! the stream's key, renaming a stream only moves its metadata.
+//!
+//! Every command that deletes or overwrites a key removes the chunks of the stream it holds
+//! through [`delete_chunks`]. The chunks are written with the stream's expiry, so they expire
+//! together with the stream.
+
+use std::{fmt, ops::Bound, str::FromStr};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use crate::{net::frame::Frame, storage::Transaction};
+
+use super::value::{Value, WRONG_TYPE};
+
+/// The max number of entries in a chunk.
+const CHUNK_SIZE: u32 = 128;
+
+/// The prefix of the internal keys under which chunks are stored.
+const CHUNK_KEY_PREFIX: &[u8] = b"\x00opal\x00stream\x00";
+
+/// The ID of a stream entry, made of a millisecond timestamp and a sequence number.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct StreamId {
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId {
+    /// The smallest possible ID.
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+
+    /// The largest possible ID.
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Create a new ID.
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parse an ID whose sequence number may be omitted, in which case `default_seq` is used.
+    pub(super) fn parse_incomplete(s: &str, default_seq: u64) -> Option<Self> {
+        match s.split_once('-') {
+            Some(_) => s.parse().ok(),
+            None => Some(Self::new(s.parse().ok()?, default_seq)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.split_once('-').unwrap_or((s, "0"));
+        Ok(Self::new(ms.parse()?, seq.parse()?))
+    }
+}
+
+/// The ID that is given to XADD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XaddId {
+    /// Generate the ID from the current time (`*`).
+    Auto,
+    /// Use the given timestamp and generate the sequence number (`<ms>-*`).
+    AutoSeq(u64),
+    /// Use the given ID (`<ms>-<seq>`).
+    Explicit(StreamId),
+}
+
+/// An entry of a stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct StreamEntry {
+    pub(super) id: StreamId,
+    pub(super) fields: Vec<(Bytes, Bytes)>,
+}
+
+impl From<StreamEntry> for Frame {
+    fn from(entry: StreamEntry) -> Self {
+        let mut fields = Vec::with_capacity(entry.fields.len() * 2);
+        for (field, value) in entry.fields {
+            fields.push(Self::BulkString(field));
+            fields.push(Self::BulkString(value));
+        }
+        Self::Array(vec![
+            Self::BulkString(entry.id.to_string().into()),
+            Self::Array(fields),
+        ])
+    }
+}
+
+/// The metadata of a stream.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Stream {
+    /// The random ID from which the chunk keys are derived.
+    chunks_id: u64,
+    /// The ID of the last entry that was added.
+    last_id: StreamId,
+    /// The number of entries.
+    length: u64,
+    /// The sequence number of the next chunk to be created.
+    next_chunk: u64,
+    /// The chunks in order of their entries.
+    chunks: Vec<Chunk>,
+}
+
+/// The metadata of a chunk of entries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Chunk {
+    seq: u64,
+    first_id: StreamId,
+    last_id: StreamId,
+    len: u32,
+}
+
+impl Stream {
+    /// Get the stream that is stored under the given key. Returns `Ok(None)` if the key does not
+    /// exist, and `Err(WRONG_TYPE)` if the key holds a different type.
+    pub(super) fn load<E, G>(
+        get: &mut G,
+        key: &Bytes,
+    ) -> Result<Result<Option<Self>, &'static str>, E>
+    where
+        G: FnMut(Bytes) -> Result<Option<Bytes>, E>,
+    {
+        Ok(match get(key.clone())?.map(Value::decode) {
+            None => Ok(None),
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WRONG_TYPE),
+        })
+    }
+
+    /// Get the ID of the last entry that was added.
+    pub(super) fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Create an empty stream.
+    fn new() -> Self {
+        Self {
+            chunks_id: rand::random(),
+            ..Default::default()
+        }
+    }
+
+    /// Get the internal keys of the stream's chunks.
+    pub(super) fn chunk_keys(&self) -> Vec<Bytes> {
+        self.chunks
+            .iter()
+            .map(|c| chunk_key(self.chunks_id, c.seq))
+            .collect()
+    }
+
+    /// Copy the stream's chunks to new internal keys and return the metadata of the copy.
+    pub(super) fn duplicate<E>(&self, txn: &mut dyn Transaction<Error = E>) -> Result<Self, E> {
+        let copy = Self {
+            chunks_id: rand::random(),
+            last_id: self.last_id,
+            length: self.length,
+            next_chunk: self.next_chunk,
+            chunks: self.chunks.clone(),
+        };
+        for chunk in &self.chunks {
+            let src = chunk_key(self.chunks_id, chunk.seq);
+            if let Some(entries) = txn.get(src.clone())? {
+                let expires_at = txn.get_expiry(src)?;
+                txn.set_with_expiry(chunk_key(copy.chunks_id, chunk.seq), entries, expires_at)?;
+            }
+        }
+        Ok(copy)
+    }
+
+    /// Get at most `count` entries whose IDs are within the given range.
+    pub(super) fn range<E, G>(
+        &self,
+        get: &mut G,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, E>
+    where
+        G: FnMut(Bytes) -> Result<Option<Bytes>, E>,
+    {
+        let bounds = (start, end);
+        let mut entries = Vec::new();
+        for chunk in &self.chunks {
+            if entries.len() >= count {
+                break;
+            }
+            let overlaps = match start {
+                Bound::Included(id) => chunk.last_id >= id,
+                Bound::Excluded(id) => chunk.last_id > id,
+                Bound::Unbounded => true,
+            } && match end {
+                Bound::Included(id) => chunk.first_id <= id,
+                Bound::Excluded(id) => chunk.first_id < id,
+                Bound::Unbounded => true,
+            };
+            if !overlaps {
+                continue;
+            }
+            // A chunk may be gone if the stream was deleted concurrently
+            let Some(Value::StreamChunk(chunk_entries)) =
+                get(chunk_key(self.chunks_id, chunk.seq))?.map(Value::decode)
+            else {
+                continue;
+            };
+            let remaining = count - entries.len();
+            entries.extend(
+                chunk_entries
+                    .into_iter()
+                    .filter(|e| std::ops::RangeBounds::contains(&bounds, &e.id))
+                    .take(remaining),
+            );
+        }
+        Ok(entries)
+    }
+}
+
+/// Append an entry to the stream under the given key, creating the stream if it does not exist.
+/// Returns the ID of the added entry, or an error message if the entry can't be added.
+pub(super) fn add<E>(
+    txn: &mut dyn Transaction<Error = E>,
+    key: Bytes,
+    id: XaddId,
+    fields: Vec<(Bytes, Bytes)>,
+    now_ms: u64,
+) -> Result<Result<StreamId, &'static str>, E> {
+    let mut get = |k| txn.get(k);
+    let mut stream = match Stream::load(&mut get, &key)? {
+        Ok(stream) => stream.unwrap_or_else(Stream::new),
+        Err(e) => return Ok(Err(e)),
+    };
+    let id = match next_id(stream.last_id, stream.length == 0, id, now_ms) {
+        Ok(id) => id,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    // Append to the last chunk if it has space, otherwise start a new chunk
+    let entry = StreamEntry { id, fields };
+    let (chunk_seq, entries) = match stream.chunks.last_mut() {
+        Some(chunk) if chunk.len < CHUNK_SIZE => {
+            let mut entries = match txn
+                .get(chunk_key(stream.chunks_id, chunk.seq))?
+                .map(Value::decode)
+            {
+                Some(Value::StreamChunk(entries)) => entries,
+                _ => Vec::new(),
+            };
+            entries.push(entry);
+            chunk.last_id = id;
+            chunk.len += 1;
+            (chunk.seq, entries)
+        }
+        _ => {
+            let seq = stream.next_chunk;
+            stream.next_chunk += 1;
+            stream.chunks.push(Chunk {
+                seq,
+                first_id: id,
+                last_id: id,
+                len: 1,
+            });
+            (seq, vec![entry])
+        }
+    };
+    stream.last_id = id;
+    stream.length += 1;
+
+    // The stream keeps its expiry, which is given to its chunks so they expire together
+    let expires_at = txn.get_expiry(key.clone())?;
+    txn.set_with_expiry(
+        chunk_key(stream.chunks_id, chunk_seq),
+        Value::StreamChunk(entries).encode(),
+        expires_at,
+    )?;
+    txn.set_with_expiry(key, Value::Stream(stream).encode(), expires_at)?;
+    Ok(Ok(id))
+}
+
+/// Determine the ID of the entry to be added given the ID of the stream's last entry.
+fn next_id(
+    last_id: StreamId,
+    is_empty: bool,
+    id: XaddId,
+    now_ms: u64,
+) -> Result<StreamId, &'static str> {
+    const ID_TOO_SMALL: &str =
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+    let next_seq = |ms: u64| -> Result<StreamId, &'static str> {
+        if is_empty && ms == 0 {
+            Ok(StreamId::new(0, 1))
+        } else if is_empty || ms > last_id.ms {
+            Ok(StreamId::new(ms, 0))
+        } else if ms == last_id.ms {
+            let seq = last_id.seq.checked_add(1).ok_or(ID_TOO_SMALL)?;
+            Ok(StreamId::new(ms, seq))
+        } else {
+            Err(ID_TOO_SMALL)
+        }
+    };
+    match id {
+        XaddId::Auto => next_seq(now_ms.max(last_id.ms)),
+        XaddId::AutoSeq(ms) => next_seq(ms),
+        XaddId::Explicit(id) if id == StreamId::MIN => {
+            Err("ERR The ID specified in XADD must be greater than 0-0")
+        }
+        XaddId::Explicit(id) if !is_empty && id <= last_id => Err(ID_TOO_SMALL),
+        XaddId::Explicit(id) => Ok(id),
+    }
+}
+
+/// Get the internal key of a chunk given the chunks ID of its stream.
+fn chunk_key(chunks_id: u64, seq: u64) -> Bytes {
+    let mut buf = BytesMut::with_capacity(CHUNK_KEY_PREFIX.len() + 16);
+    buf.put_slice(CHUNK_KEY_PREFIX);
+    buf.put_u64(chunks_id);
+    buf.put_u64(seq);
+    buf.freeze()
+}
+
+/// Delete the chunks of the stream under the given key. Nothing is deleted if the key doesn't hold
+/// a stream. This must be called before a stream is deleted or overwritten, otherwise its chunks
+/// are left behind.
+pub(in crate::net) fn delete_chunks<E>(
+    txn: &mut dyn Transaction<Error = E>,
+    key: Bytes,
+) -> Result<(), E> {
+    if let Some(Value::Stream(stream)) = txn.get(key)?.map(Value::decode) {
+        for chunk_key in stream.chunk_keys() {
+            txn.del(chunk_key)?;
+        }
+    }
+    Ok(())
+}
+
+/// Return `true` if the key is an internal key that holds a chunk.
+pub(in crate::net) fn is_chunk_key(key: &[u8]) -> bool {
+    key.starts_with(CHUNK_KEY_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::SystemTime};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryTransaction(HashMap<Bytes, (Bytes, Option<SystemTime>)>);
+
+    impl Transaction for MemoryTransaction {
+        type Error = std::io::Error;
+
+        fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+            self.set_with_expiry(key, value, None)
+        }
+
+        fn set_with_expiry(
+            &mut self,
+            key: Bytes,
+            value: Bytes,
+            expires_at: Option<SystemTime>,
+        ) -> Result<(), Self::Error> {
+            self.0.insert(key, (value, expires_at));
+            Ok(())
+        }
+
+        fn get(&mut self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+            Ok(self.0.get(&key).map(|(value, _)| value.clone()))
+        }
+
+        fn get_expiry(&mut self, key: Bytes) -> Result<Option<SystemTime>, Self::Error> {
+            Ok(self.0.get(&key).and_then(|(_, expires_at)| *expires_at))
+        }
+
+        fn del(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+            Ok(self.0.remove(&key).is_some())
+        }
+    }
+
+    fn field(i: u64) -> Vec<(Bytes, Bytes)> {
+        vec![(Bytes::from("n"), Bytes::from(i.to_string()))]
+    }
+
+    #[test]
+    fn next_id_is_increasing() {
+        let last = StreamId::new(5, 3);
+        assert_eq!(
+            Ok(StreamId::new(5, 4)),
+            next_id(last, false, XaddId::Auto, 4)
+        );
+        assert_eq!(
+            Ok(StreamId::new(6, 0)),
+            next_id(last, false, XaddId::Auto, 6)
+        );
+        assert_eq!(
+            Ok(StreamId::new(5, 4)),
+            next_id(last, false, XaddId::AutoSeq(5), 0)
+        );
+        assert!(next_id(last, false, XaddId::AutoSeq(4), 0).is_err());
+        assert!(next_id(last, false, XaddId::Explicit(last), 0).is_err());
+        assert!(next_id(last, true, XaddId::Explicit(StreamId::MIN), 0).is_err());
+        assert_eq!(
+            Ok(StreamId::new(0, 1)),
+            next_id(StreamId::MIN, true, XaddId::AutoSeq(0), 0)
+        );
+    }
+
+    #[test]
+    fn entries_are_chunked_and_queried_by_range() {
+        let mut txn = MemoryTransaction::default();
+        let key = Bytes::from("stream");
+        let n = 3 * CHUNK_SIZE as u64;
+        for i in 1..=n {
+            let id = XaddId::Explicit(StreamId::new(i, 0));
+            let added = add(&mut txn, key.clone(), id, field(i), 0).unwrap();
+            assert_eq!(Ok(StreamId::new(i, 0)), added);
+        }
+
+        let mut get = |k| txn.get(k);
+        let stream = Stream::load(&mut get, &key).unwrap().unwrap().unwrap();
+        assert_eq!(n, stream.length);
+        assert_eq!(3, stream.chunk_keys().len());
+
+        let start = Bound::Excluded(StreamId::new(100, 0));
+        let end = Bound::Included(StreamId::new(300, 0));
+        let entries = stream.range(&mut get, start, end, 250).unwrap();
+        assert_eq!(200, entries.len());
+        assert_eq!(StreamId::new(101, 0), entries[0].id);
+        assert_eq!(StreamId::new(300, 0), entries[199].id);
+
+        let entries = stream
+            .range(&mut get, Bound::Unbounded, Bound::Unbounded, 10)
+            .unwrap();
+        assert_eq!(10, entries.len());
+        assert_eq!(field(10), entries[9].fields);
+    }
+
+    #[test]
+    fn chunks_share_the_expiry_of_their_stream() {
+        let mut txn = MemoryTransaction::default();
+        let key = Bytes::from("stream");
+        add(&mut txn, key.clone(), XaddId::Auto, field(0), 0)
+            .unwrap()
+            .unwrap();
+        let expires_at = Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1));
+        let value = txn.get(key.clone()).unwrap().unwrap();
+        txn.set_with_expiry(key.clone(), value, expires_at).unwrap();
+
+        add(&mut txn, key.clone(), XaddId::Auto, field(1), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(expires_at, txn.get_expiry(key.clone()).unwrap());
+        let mut get = |k| txn.get(k);
+        let stream = Stream::load(&mut get, &key).unwrap().unwrap().unwrap();
+        for chunk_key in stream.chunk_keys() {
+            assert_eq!(expires_at, txn.get_expiry(chunk_key).unwrap());
+        }
+
+        let copy = stream.duplicate(&mut txn).unwrap();
+        for chunk_key in copy.chunk_keys() {
+            assert_eq!(expires_at, txn.get_expiry(chunk_key).unwrap());
+        }
+        delete_chunks(&mut txn, key).unwrap();
+        assert_eq!(1 + copy.chunk_keys().len(), txn.0.len());
+    }
+
+    #[test]
+    fn add_to_non_stream_is_rejected() {
+        let mut txn = MemoryTransaction::default();
+        txn.set("key".into(), "value".into()).unwrap();
+        let added = add(&mut txn, "key".into(), XaddId::Auto, field(0), 0).unwrap();
+        assert_eq!(Err(WRONG_TYPE), added);
+    }
+}
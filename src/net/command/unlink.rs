@@ -0,0 +1,73 @@
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{stream, Utf8Bytes};
+
+/// Arguments for UNLINK command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unlink {
+    keys: Vec<Utf8Bytes>,
+}
+
+impl Unlink {
+    /// Creates a new set of arguments.
+    ///
+    /// UNLINK requires that the list of keys must have at least 1 element
+    pub fn new(keys: Vec<Utf8Bytes>) -> Self {
+        Self { keys }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("UNLINK", self.keys.iter().collect())
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Unlink the keys and count the number of deletions. The keys are gone once the command
+        // returns, but their tombstones may be written later together with other unlinked keys.
+        let count = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let mut count = 0;
+                for key in self.keys {
+                    let key = key.as_ref().clone();
+                    stream::delete_chunks(txn, key.clone())?;
+                    if txn.unlink(key)? {
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            })
+        })
+        .await?
+        .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+
+        // Responding with the number of deletions
+        let response = Frame::Integer(count);
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Unlink> for Frame {
+    fn from(cmd: Unlink) -> Self {
+        let mut cmd_data = vec![Self::BulkString("UNLINK".into())];
+        for key in cmd.keys {
+            cmd_data.push(Self::BulkString(key.as_ref().clone()));
+        }
+        Self::Array(cmd_data)
+    }
+}
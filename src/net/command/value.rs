@@ -0,0 +1,99 @@
+//! Values of data types other than strings are stored with a header that marks their types, so
+//! they can share the key space with string values which are stored as is.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    stream::{Stream, StreamEntry},
+    zset::SortedSet,
+};
+
+/// The header that is prepended to the serialized non-string values.
+const HEADER: &[u8] = b"\x00opal\x00";
+
+/// The number of bytes of the header.
+pub(super) const HEADER_LEN: usize = HEADER.len();
+
+/// The error message that is sent when a command is run against a key of a different type.
+pub(super) const WRONG_TYPE: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// A value of one of the supported data types.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) enum Value {
+    /// A string value.
+    String(Bytes),
+    /// The metadata of a stream.
+    Stream(Stream),
+    /// A chunk of entries of a stream. Chunks are stored under internal keys.
+    StreamChunk(Vec<StreamEntry>),
+    /// A list of elements.
+    List(VecDeque<Bytes>),
+    /// A set of members ordered by their scores.
+    SortedSet(SortedSet),
+    /// A set of unique members.
+    Set(BTreeSet<Bytes>),
+}
+
+impl Value {
+    /// Decode the value from its stored representation. Anything without a valid header is
+    /// treated as a string.
+    pub(super) fn decode(raw: Bytes) -> Self {
+        if raw.starts_with(HEADER) {
+            if let Ok(value) = bincode::deserialize(&raw[HEADER.len()..]) {
+                return value;
+            }
+        }
+        Self::String(raw)
+    }
+
+    /// Encode the value into its stored representation.
+    pub(super) fn encode(&self) -> Bytes {
+        if let Self::String(s) = self {
+            return s.clone();
+        }
+        let size = bincode::serialized_size(self).expect("value must be serializable");
+        let mut buf = BytesMut::with_capacity(HEADER.len() + size as usize).writer();
+        buf.get_mut().put_slice(HEADER);
+        bincode::serialize_into(&mut buf, self).expect("value must be serializable");
+        buf.into_inner().freeze()
+    }
+}
+
+/// Check whether the start of a stored value marks it as a non-string value. `raw` must hold at
+/// least [`HEADER_LEN`] bytes unless it's the whole value.
+pub(super) fn has_header(raw: &[u8]) -> bool {
+    raw.starts_with(HEADER)
+}
+
+/// Decode a stored value that is expected to be a string. Returns `None` if the value holds
+/// another data type.
+pub(in crate::net) fn decode_string(raw: Bytes) -> Option<Bytes> {
+    match Value::decode(raw) {
+        Value::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_stored_as_is() {
+        let value = Value::String("hello".into());
+        assert_eq!(Bytes::from("hello"), value.encode());
+        assert_eq!(value, Value::decode("hello".into()));
+    }
+
+    #[test]
+    fn typed_values_roundtrip() {
+        let value = Value::StreamChunk(Vec::new());
+        let raw = value.encode();
+        assert!(raw.starts_with(HEADER));
+        assert_eq!(value, Value::decode(raw));
+    }
+}
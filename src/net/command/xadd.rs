@@ -0,0 +1,85 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    stream::{self, XaddId},
+    Utf8Bytes,
+};
+
+/// Arguments for XADD command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Xadd {
+    /// The key of the stream
+    key: Utf8Bytes,
+    /// The ID of the new entry
+    id: XaddId,
+    /// The field-value pairs of the new entry
+    fields: Vec<(Bytes, Bytes)>,
+}
+
+impl Xadd {
+    /// Creates a new set of arguments.
+    ///
+    /// XADD requires that the list of fields must have at least 1 element
+    pub fn new(key: Utf8Bytes, id: XaddId, fields: Vec<(Bytes, Bytes)>) -> Self {
+        Self { key, id, fields }
+    }
+
+    /// Get the name of the command and the keys that it writes to.
+    pub(super) fn writes(&self) -> (&'static str, Vec<&Utf8Bytes>) {
+        ("XADD", vec![&self.key])
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Append the entry while holding exclusive write access, so IDs are generated in order
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let response = net::spawn_blocking(move || {
+            storage.atomically(move |txn| {
+                let key = self.key.as_ref().clone();
+                Ok(match stream::add(txn, key, self.id, self.fields, now_ms)? {
+                    Ok(id) => Frame::BulkString(id.to_string().into()),
+                    Err(e) => Frame::Error(e.to_string()),
+                })
+            })
+        })
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Xadd> for Frame {
+    fn from(cmd: Xadd) -> Self {
+        let id = match cmd.id {
+            XaddId::Auto => "*".to_string(),
+            XaddId::AutoSeq(ms) => format!("{ms}-*"),
+            XaddId::Explicit(id) => id.to_string(),
+        };
+        let mut cmd_data = vec![
+            Self::BulkString("XADD".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(id.into()),
+        ];
+        for (field, value) in cmd.fields {
+            cmd_data.push(Self::BulkString(field));
+            cmd_data.push(Self::BulkString(value));
+        }
+        Self::Array(cmd_data)
+    }
+}
@@ -0,0 +1,113 @@
+use std::ops::Bound;
+
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    stream::{Stream, StreamId},
+    Utf8Bytes,
+};
+
+/// Arguments for XRANGE command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Xrange {
+    /// The key of the stream
+    key: Utf8Bytes,
+    /// The lower bound of the IDs
+    start: Bound<StreamId>,
+    /// The upper bound of the IDs
+    end: Bound<StreamId>,
+    /// The max number of entries to return
+    count: Option<u64>,
+}
+
+impl Xrange {
+    /// Creates a new set of arguments
+    pub fn new(
+        key: Utf8Bytes,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<u64>,
+    ) -> Self {
+        Self {
+            key,
+            start,
+            end,
+            count,
+        }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the entries within the range
+        let count = self.count.map(|c| c as usize).unwrap_or(usize::MAX);
+        let response = net::spawn_blocking(move || {
+            let key = self.key.as_ref();
+            let mut get = |k| storage.get(k);
+            let stream = match Stream::load(&mut get, key)? {
+                Ok(Some(stream)) => stream,
+                Ok(None) => return Ok(Frame::Array(Vec::new())),
+                Err(e) => return Ok(Frame::Error(e.to_string())),
+            };
+            let entries = stream.range(&mut get, self.start, self.end, count)?;
+            Ok(Frame::Array(entries.into_iter().map(Frame::from).collect()))
+        })
+        .await?
+        .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Xrange> for Frame {
+    fn from(cmd: Xrange) -> Self {
+        let mut cmd_data = vec![
+            Self::BulkString("XRANGE".into()),
+            Self::BulkString(cmd.key.as_ref().clone()),
+            Self::BulkString(encode_bound(cmd.start, "-").into()),
+            Self::BulkString(encode_bound(cmd.end, "+").into()),
+        ];
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        Self::Array(cmd_data)
+    }
+}
+
+/// Parse a range item of XRANGE. Items starting with `(` are exclusive, `unbounded` (`-` or `+`)
+/// is unbounded, and a missing sequence number is replaced by `default_seq`.
+pub(super) fn parse_bound(
+    item: &str,
+    unbounded: &str,
+    default_seq: u64,
+) -> Option<Bound<StreamId>> {
+    if item == unbounded {
+        return Some(Bound::Unbounded);
+    }
+    match item.strip_prefix('(') {
+        Some(id) => StreamId::parse_incomplete(id, default_seq).map(Bound::Excluded),
+        None => StreamId::parse_incomplete(item, default_seq).map(Bound::Included),
+    }
+}
+
+fn encode_bound(bound: Bound<StreamId>, unbounded: &str) -> String {
+    match bound {
+        Bound::Included(id) => id.to_string(),
+        Bound::Excluded(id) => format!("({id}"),
+        Bound::Unbounded => unbounded.to_string(),
+    }
+}
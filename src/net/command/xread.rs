@@ -0,0 +1,96 @@
+use std::ops::Bound;
+
+use tracing::debug;
+
+use crate::{
+    net::{self, connection::Connection, frame::Frame},
+    storage::KeyValueStorage,
+};
+
+use super::{
+    stream::{Stream, StreamId},
+    Utf8Bytes,
+};
+
+/// Arguments for XREAD command
+#[derive(Debug, PartialEq, Eq)]
+pub struct Xread {
+    /// The max number of entries to return per stream
+    count: Option<u64>,
+    /// The keys of the streams and the IDs after which entries are read. An ID of `None` (`$`)
+    /// refers to the last entry of the stream.
+    streams: Vec<(Utf8Bytes, Option<StreamId>)>,
+}
+
+impl Xread {
+    /// Creates a new set of arguments.
+    ///
+    /// XREAD requires that the list of streams must have at least 1 element
+    pub fn new(count: Option<u64>, streams: Vec<(Utf8Bytes, Option<StreamId>)>) -> Self {
+        Self { count, streams }
+    }
+
+    /// Apply the command to the specified [`StorageEngine`] instance.
+    ///
+    /// [`StorageEngine`]: crate::StorageEngine;
+    #[tracing::instrument(skip(self, storage, connection))]
+    pub async fn apply<KV>(self, storage: KV, connection: &mut Connection) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Get the entries that were added after the given IDs
+        let count = self.count.map(|c| c as usize).unwrap_or(usize::MAX);
+        let response = net::spawn_blocking(move || {
+            let mut get = |k| storage.get(k);
+            let mut replies = Vec::new();
+            for (key, id) in self.streams {
+                let key = key.as_ref();
+                let stream = match Stream::load(&mut get, key)? {
+                    Ok(Some(stream)) => stream,
+                    Ok(None) => continue,
+                    Err(e) => return Ok(Frame::Error(e.to_string())),
+                };
+                let start = Bound::Excluded(id.unwrap_or_else(|| stream.last_id()));
+                let entries = stream.range(&mut get, start, Bound::Unbounded, count)?;
+                if !entries.is_empty() {
+                    replies.push(Frame::Array(vec![
+                        Frame::BulkString(key.clone()),
+                        Frame::Array(entries.into_iter().map(Frame::from).collect()),
+                    ]));
+                }
+            }
+            if replies.is_empty() {
+                return Ok(Frame::Null);
+            }
+            Ok(Frame::Array(replies))
+        })
+        .await?
+        .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+        debug!(?response);
+
+        // Write the response to the client
+        connection.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+impl From<Xread> for Frame {
+    fn from(cmd: Xread) -> Self {
+        let mut cmd_data = vec![Self::BulkString("XREAD".into())];
+        if let Some(count) = cmd.count {
+            cmd_data.push(Self::BulkString("COUNT".into()));
+            cmd_data.push(Self::BulkString(count.to_string().into()));
+        }
+        cmd_data.push(Self::BulkString("STREAMS".into()));
+        let mut ids = Vec::with_capacity(cmd.streams.len());
+        for (key, id) in cmd.streams {
+            cmd_data.push(Self::BulkString(key.as_ref().clone()));
+            ids.push(match id {
+                Some(id) => Self::BulkString(id.to_string().into()),
+                None => Self::BulkString("$".into()),
+            });
+        }
+        cmd_data.extend(ids);
+        Self::Array(cmd_data)
+    }
+}
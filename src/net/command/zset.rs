@@ -0,0 +1,158 @@
+//! Sorted sets are stored as single values that hold all of their members ordered by their
+//! scores, and then by the members themselves when the scores are equal. Empty sorted sets are
+//! never stored.
+
+use std::cmp::Ordering;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Update;
+
+use super::value::{Value, WRONG_TYPE};
+
+/// The end of a sorted set from which members are popped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZsetEnd {
+    /// The members with the lowest scores.
+    Min,
+    /// The members with the highest scores.
+    Max,
+}
+
+/// A set of unique members that are ordered by their scores.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct SortedSet {
+    /// The members and their scores, sorted by score and then by member.
+    members: Vec<(f64, Bytes)>,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.members.len() == other.members.len()
+            && self
+                .members
+                .iter()
+                .zip(&other.members)
+                .all(|((s1, m1), (s2, m2))| s1.to_bits() == s2.to_bits() && m1 == m2)
+    }
+}
+
+impl Eq for SortedSet {}
+
+impl SortedSet {
+    /// Get the score of the member, if it's in the set.
+    pub(super) fn score(&self, member: &[u8]) -> Option<f64> {
+        self.members
+            .iter()
+            .find(|(_, m)| m == member)
+            .map(|(score, _)| *score)
+    }
+
+    /// Set the score of the member, adding the member if it's not in the set. Returns the
+    /// previous score of the member.
+    pub(super) fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let prev = match self.members.iter().position(|(_, m)| *m == member) {
+            Some(i) => Some(self.members.remove(i).0),
+            None => None,
+        };
+        let i = self
+            .members
+            .partition_point(|(s, m)| cmp(*s, m, score, &member) == Ordering::Less);
+        self.members.insert(i, (score, member));
+        prev
+    }
+
+    /// Check whether the set has no members.
+    pub(super) fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Iterate over the members and their scores, in order.
+    pub(super) fn iter(&self) -> impl Iterator<Item = (f64, &Bytes)> {
+        self.members.iter().map(|(score, member)| (*score, member))
+    }
+}
+
+/// Members of a sorted set together with their scores.
+type Scored = Vec<(Bytes, f64)>;
+
+/// Pop at most `count` members from the sorted set that is stored as `value`. Returns the popped
+/// members and their scores, which is empty if the sorted set does not exist.
+pub(super) fn pop(
+    value: Option<Bytes>,
+    end: ZsetEnd,
+    count: usize,
+) -> (Update, Result<Scored, &'static str>) {
+    let mut zset = match value.map(Value::decode) {
+        None => return (Update::Keep, Ok(Vec::new())),
+        Some(Value::SortedSet(zset)) => zset,
+        Some(_) => return (Update::Keep, Err(WRONG_TYPE)),
+    };
+    let count = count.min(zset.members.len());
+    let popped: Vec<_> = match end {
+        ZsetEnd::Min => zset.members.drain(..count).collect(),
+        ZsetEnd::Max => zset
+            .members
+            .drain(zset.members.len() - count..)
+            .rev()
+            .collect(),
+    };
+    let members = popped
+        .into_iter()
+        .map(|(score, member)| (member, score))
+        .collect();
+    let update = if zset.is_empty() {
+        Update::Delete
+    } else {
+        Update::Set(Value::SortedSet(zset).encode())
+    };
+    (update, Ok(members))
+}
+
+fn cmp(s1: f64, m1: &Bytes, s2: f64, m2: &Bytes) -> Ordering {
+    s1.total_cmp(&s2).then_with(|| m1.cmp(m2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_are_ordered_by_score_then_member() {
+        let mut zset = SortedSet::default();
+        assert_eq!(None, zset.insert("b".into(), 2.0));
+        assert_eq!(None, zset.insert("a".into(), 2.0));
+        assert_eq!(None, zset.insert("c".into(), 1.0));
+        assert_eq!(Some(1.0), zset.insert("c".into(), 3.0));
+        assert_eq!(Some(3.0), zset.score(b"c"));
+
+        let members: Vec<_> = zset.iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")],
+            members
+        );
+    }
+
+    #[test]
+    fn pop_from_both_ends() {
+        let mut zset = SortedSet::default();
+        zset.insert("a".into(), 1.0);
+        zset.insert("b".into(), 2.0);
+        zset.insert("c".into(), 3.0);
+        let value = Value::SortedSet(zset).encode();
+
+        let (update, popped) = pop(Some(value), ZsetEnd::Max, 2);
+        assert_eq!(
+            Ok(vec![(Bytes::from("c"), 3.0), (Bytes::from("b"), 2.0)]),
+            popped
+        );
+        let Update::Set(value) = update else {
+            panic!("the sorted set must be kept");
+        };
+
+        let (update, popped) = pop(Some(value), ZsetEnd::Min, 5);
+        assert_eq!(Ok(vec![(Bytes::from("a"), 1.0)]), popped);
+        assert_eq!(Update::Delete, update);
+    }
+}
@@ -1,8 +1,17 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroU32,
+    path::PathBuf,
+};
 
 use serde::Deserialize;
 
-use super::Server;
+use super::{
+    protocol::{memcached, ProtocolKind},
+    renames::CommandRenames,
+    RateLimits, Server, State,
+};
 
 /// Network configuration
 #[derive(Debug, Deserialize)]
@@ -17,11 +26,85 @@ pub struct Config {
     /// Min number of milliseconds to wait for when retrying to accept a new connection.
     pub min_backoff_ms: u64,
 
-    /// Max number of milliseconds to wait for when retrying to accept a new connection.
+    /// Max number of milliseconds to wait for when retrying to accept a new connection. The
+    /// backoff doubles after every failure until it reaches this.
     pub max_backoff_ms: u64,
 
+    /// Max number of consecutive failures to accept a new connection, after which the listener
+    /// gives up. The listener never gives up when this is 0.
+    pub max_accept_failures: u32,
+
+    /// The fraction of the backoff that is randomly added to or subtracted from it, so listeners
+    /// that fail at the same time don't retry in lockstep.
+    pub accept_jitter: f64,
+
+    /// What the server does once its listener gives up.
+    pub listener_error_policy: ListenerErrorPolicy,
+
     /// Max number of concurrent connections that can be served by the server.
     pub max_connections: usize,
+
+    /// Max number of new connections that are accepted per second, the rate is not limited when
+    /// this is not set. Connections over the rate wait to be accepted.
+    pub max_accepts_per_sec: Option<NonZeroU32>,
+
+    /// Whether the connections that arrive once `max_connections` is reached are rejected with
+    /// an error and closed, instead of waiting until another connection is closed.
+    pub reject_when_full: bool,
+
+    /// The protocol that clients use to talk to the server.
+    pub protocol: ProtocolKind,
+
+    /// Whether every connection starts with a version 2 PROXY protocol header, which gives the
+    /// address of the client when the server is behind an L4 load balancer. The address is used
+    /// for rate limiting and is recorded in the audit log. Connections without a valid header are
+    /// closed.
+    pub proxy_protocol: bool,
+
+    /// Max number of bytes in a value that is set by a memcached client, larger values are
+    /// rejected with an error.
+    pub memcached_max_item_size: usize,
+
+    /// Max number of read-only commands that run at once.
+    pub max_concurrent_reads: usize,
+
+    /// Max number of write commands that run at once.
+    pub max_concurrent_writes: usize,
+
+    /// The file that the writes made by clients are recorded to, auditing is disabled if this
+    /// is not set. A server that runs alongside another one records to the other's audit log
+    /// instead.
+    pub audit_log: Option<PathBuf>,
+
+    /// Max number of bytes that the audit log grows to before it is rotated.
+    pub audit_log_max_size: u64,
+
+    /// Max number of rotated audit log files that are kept.
+    pub audit_log_max_files: usize,
+
+    /// The limits on the rate of the clients' requests. A server that runs alongside another one
+    /// counts its requests against the other's limits instead.
+    pub rate_limit: RateLimits,
+
+    /// The new names of the RESP commands that are renamed, keyed by their original names. A
+    /// command that is renamed to an empty name is disabled.
+    pub rename_commands: HashMap<String, String>,
+
+    /// Max number of milliseconds that a script run by EVAL or EVALSHA can take, after which the
+    /// script is aborted with an error. Scripts hold exclusive write access to the storage, so a
+    /// runaway script would otherwise block every other write.
+    pub script_time_limit_ms: u64,
+}
+
+/// What a server does once its listener gives up accepting new connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerErrorPolicy {
+    /// Close the existing connections and stop the server.
+    #[default]
+    Shutdown,
+    /// Keep serving the existing connections until the server is shut down.
+    ServeExisting,
 }
 
 impl Config {
@@ -31,8 +114,60 @@ impl Config {
         storage: KV,
         shutdown: S,
     ) -> Result<Server<KV, S>, super::Error> {
+        self.validate()?;
         Server::new(storage, shutdown, self).await
     }
+
+    /// Bind a new listener and create a server that runs alongside the server with the given
+    /// states, e.g. an HTTP gateway next to the RESP server. The clients of both servers are held
+    /// by the same CLIENT PAUSE, count against the same rate limits, and are recorded in the same
+    /// audit log.
+    pub async fn async_server_alongside<KV, S>(
+        self,
+        storage: KV,
+        shutdown: S,
+        other: &State,
+    ) -> Result<Server<KV, S>, super::Error> {
+        self.validate()?;
+        let state = State::alongside(&self, other)?;
+        Server::with_state(storage, shutdown, self, state).await
+    }
+
+    /// Check the settings that can't be checked by the type system.
+    pub fn validate(&self) -> Result<(), super::Error> {
+        if self.min_backoff_ms > self.max_backoff_ms {
+            return Err(super::Error::InvalidConfig(
+                "min_backoff_ms must not exceed max_backoff_ms",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.accept_jitter) {
+            return Err(super::Error::InvalidConfig(
+                "accept_jitter must be within [0, 1]",
+            ));
+        }
+        if self.max_connections == 0 {
+            return Err(super::Error::InvalidConfig(
+                "max_connections must be at least 1",
+            ));
+        }
+        if self.max_concurrent_reads == 0 || self.max_concurrent_writes == 0 {
+            return Err(super::Error::InvalidConfig(
+                "max_concurrent_reads and max_concurrent_writes must be at least 1",
+            ));
+        }
+        CommandRenames::new(&self.rename_commands)?;
+        if self.script_time_limit_ms == 0 {
+            return Err(super::Error::InvalidConfig(
+                "script_time_limit_ms must be at least 1",
+            ));
+        }
+        if self.audit_log_max_size == 0 {
+            return Err(super::Error::InvalidConfig(
+                "audit_log_max_size must be at least 1",
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Default for Config {
@@ -42,7 +177,23 @@ impl Default for Config {
             port: 6379,
             min_backoff_ms: 500,
             max_backoff_ms: 64000,
+            max_accept_failures: 9,
+            accept_jitter: 0.0,
+            listener_error_policy: ListenerErrorPolicy::default(),
             max_connections: 128,
+            max_accepts_per_sec: None,
+            reject_when_full: false,
+            protocol: ProtocolKind::default(),
+            proxy_protocol: false,
+            memcached_max_item_size: memcached::DEFAULT_MAX_ITEM_SIZE,
+            max_concurrent_reads: 256,
+            max_concurrent_writes: 16,
+            audit_log: None,
+            audit_log_max_size: 64 * 1024 * 1024,
+            audit_log_max_files: 8,
+            rate_limit: RateLimits::default(),
+            rename_commands: HashMap::new(),
+            script_time_limit_ms: 5000,
         }
     }
 }
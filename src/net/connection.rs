@@ -2,10 +2,11 @@
 
 use std::io::{self, Cursor, Write};
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt, BufWriter},
     net::TcpStream,
+    sync::mpsc,
 };
 
 use super::frame::{self, Frame};
@@ -62,16 +63,67 @@ where
 
     /// Write a frame to the underlying stream
     pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), super::Error> {
-        if let Frame::Array(items) = frame {
-            self.write_array(items).await?;
-        } else {
-            self.write_single_value(frame).await?;
+        self.write_frames(std::slice::from_ref(frame)).await
+    }
+
+    /// Write the header of an array with the given number of elements, which must be followed by
+    /// that many frames.
+    pub async fn write_array_header(&mut self, len: usize) -> Result<(), super::Error> {
+        self.write_array_len(len).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Write several frames to the underlying stream and flush them at once, so pipelined
+    /// requests or replies don't take a write syscall each.
+    pub async fn write_frames(&mut self, frames: &[Frame]) -> Result<(), super::Error> {
+        for frame in frames {
+            if let Frame::Array(items) = frame {
+                self.write_array(items).await?;
+            } else {
+                self.write_single_value(frame).await?;
+            }
         }
 
         self.stream.flush().await?;
         Ok(())
     }
 
+    /// Write a bulk string of `len` bytes whose content is received in pieces, so the whole
+    /// content doesn't have to be in memory at once. Fails if receiving a piece fails or if the
+    /// pieces don't add up to `len` bytes, in which case the bulk string is left incomplete and
+    /// the connection can't be used anymore.
+    pub async fn write_bulk_stream(
+        &mut self,
+        len: u64,
+        chunks: &mut mpsc::Receiver<io::Result<Bytes>>,
+    ) -> Result<(), super::Error> {
+        self.stream.write_u8(b'$').await?;
+        self.write_decimal(len as i64).await?;
+        self.stream.write_all(b"\r\n").await?;
+
+        let mut written = 0;
+        while let Some(chunk) = chunks.recv().await {
+            let chunk = chunk?;
+            written += chunk.len() as u64;
+            if written > len {
+                break;
+            }
+            self.stream.write_all(&chunk).await?;
+        }
+        if written != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "value length changed while it was sent",
+            )
+            .into());
+        }
+
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
     fn parse_frame(&mut self) -> Result<Option<Frame>, frame::Error> {
         let mut buf = Cursor::new(&self.buffer[..]);
         match Frame::check(&mut buf) {
@@ -96,17 +148,32 @@ where
     }
 
     async fn write_array(&mut self, items: &[Frame]) -> io::Result<()> {
+        // Nested arrays are written with a stack of the arrays that are being written, since an
+        // async function can't call itself without boxing its future
+        self.write_array_len(items.len()).await?;
+        let mut arrays = vec![items.iter()];
+        while let Some(array) = arrays.last_mut() {
+            match array.next() {
+                Some(Frame::Array(items)) => {
+                    self.write_array_len(items.len()).await?;
+                    arrays.push(items.iter());
+                }
+                Some(item) => self.write_single_value(item).await?,
+                None => {
+                    arrays.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_array_len(&mut self, len: usize) -> io::Result<()> {
         // frame init
         self.stream.write_u8(b'*').await?;
 
-        // send frame content
-        let len = items.len();
+        // send array's length as digits
         self.write_decimal(len as i64).await?;
         self.stream.write_all(b"\r\n").await?;
-
-        for item in items {
-            self.write_single_value(item).await?;
-        }
         Ok(())
     }
 
@@ -152,7 +219,7 @@ where
                 self.stream.write_all(bs).await?;
                 self.stream.write_all(b"\r\n").await?;
             }
-            Frame::Array(_) => unimplemented!(),
+            Frame::Array(_) => unreachable!("arrays are written by write_array"),
         }
         Ok(())
     }
@@ -183,6 +250,37 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn write_frames_check_sent_buffer() -> Result<(), Box<dyn std::error::Error>> {
+        let (frames, expected_buffer): (Vec<_>, Vec<_>) = get_test_cases().into_iter().unzip();
+        let mut stream = Cursor::new(Vec::new());
+        let mut conn = Connection::new(&mut stream);
+
+        conn.write_frames(&frames).await?;
+        assert_eq!(stream.get_ref(), &expected_buffer.concat());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_bulk_stream_check_sent_buffer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut stream = Cursor::new(Vec::new());
+        let mut conn = Connection::new(&mut stream);
+        let (tx, mut rx) = mpsc::channel(2);
+        tx.send(Ok(Bytes::from("hello "))).await?;
+        tx.send(Ok(Bytes::from("world"))).await?;
+        drop(tx);
+
+        conn.write_bulk_stream(11, &mut rx).await?;
+        assert_eq!(stream.get_ref(), b"$11\r\nhello world\r\n");
+
+        let mut conn = Connection::new(Cursor::new(Vec::new()));
+        let (tx, mut rx) = mpsc::channel(1);
+        tx.send(Ok(Bytes::from("short"))).await?;
+        drop(tx);
+        assert!(conn.write_bulk_stream(11, &mut rx).await.is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn read_frame_check_received_frame() -> Result<(), Box<dyn std::error::Error>> {
         for test_case in get_test_cases() {
@@ -286,6 +384,13 @@ mod tests {
                 ]),
                 b"*3\r\n$3\r\nfoo\r\n$-1\r\n$3\r\nbar\r\n".as_slice(),
             ),
+            (
+                Frame::Array(vec![
+                    Frame::Array(vec![Frame::Integer(1), Frame::Array(vec![])]),
+                    Frame::BulkString("foo".into()),
+                ]),
+                b"*2\r\n*2\r\n:1\r\n*0\r\n$3\r\nfoo\r\n".as_slice(),
+            ),
             // null
             // NOTE: We use the bulk string representation for null
             (Frame::Null, b"$-1\r\n".as_slice()),
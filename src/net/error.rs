@@ -2,7 +2,8 @@ use std::io;
 
 use thiserror::Error;
 
-use super::{command, frame};
+use super::{command, frame, frame::Frame};
+use crate::storage::bitcask;
 
 /// Error from running the server/client
 #[derive(Error, Debug)]
@@ -19,6 +20,10 @@ pub enum Error {
     #[error("Command error - {0}")]
     Command(#[from] command::Error),
 
+    /// Error from using a configuration with invalid settings.
+    #[error("Invalid configuration - {0}")]
+    InvalidConfig(&'static str),
+
     /// Error from I/O operations.
     #[error("I/O error - {0}")]
     Io(#[from] io::Error),
@@ -27,3 +32,93 @@ pub enum Error {
     #[error("Asynchronous task error - {0}")]
     AsyncTask(#[from] tokio::task::JoinError),
 }
+
+impl Error {
+    /// Return the RESP error reply for the error, if the connection can keep serving commands
+    /// after sending it. Only errors from the storage engine can be reported to the client, the
+    /// other errors leave the connection in an unknown state.
+    pub fn to_frame(&self) -> Option<Frame> {
+        match self {
+            Error::Storage(err) => Some(Frame::Error(format!(
+                "{} {}",
+                storage_error_class(err),
+                err
+            ))),
+            _ => None,
+        }
+    }
+}
+
+/// Get the prefix of the RESP error reply that lets clients tell the different failures of the
+/// storage engine apart.
+fn storage_error_class(err: &anyhow::Error) -> &'static str {
+    match err.downcast_ref::<bitcask::Error>() {
+        Some(bitcask::Error::Recovering) => "LOADING",
+        Some(bitcask::Error::Busy) => "BUSY",
+        // The other failures that go away on their own, such as an interrupted read
+        Some(err) if err.is_retryable() => "TRYAGAIN",
+        Some(bitcask::Error::Io(_) | bitcask::Error::AsyncTask(_)) => "IOERR",
+        Some(bitcask::Error::Corruption { .. } | bitcask::Error::Serialization(_)) => "CORRUPT",
+        // A closed storage can no longer be written to
+        Some(bitcask::Error::Closed) => "READONLY",
+        Some(bitcask::Error::QuotaExceeded(_)) => "OOM",
+        _ => "ERR",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_storage_error_frame(err: bitcask::Error, expected: &str) {
+        let err = Error::Storage(err.into());
+        assert_eq!(Some(Frame::Error(expected.to_string())), err.to_frame());
+    }
+
+    #[test]
+    fn storage_errors_map_to_error_classes() {
+        assert_storage_error_frame(
+            bitcask::Error::Io(io::Error::other("disk failure")),
+            "IOERR I/O error - disk failure",
+        );
+        assert_storage_error_frame(
+            bitcask::Error::corrupted("bad checksum"),
+            "CORRUPT Corrupted data - bad checksum",
+        );
+        assert_storage_error_frame(
+            bitcask::Error::corrupted_at("bad entry", "data/0.bitcask.data".into(), Some(42)),
+            "CORRUPT Corrupted data - bad entry in data/0.bitcask.data at offset 42",
+        );
+        assert_storage_error_frame(
+            bitcask::Error::Io(io::Error::new(io::ErrorKind::Interrupted, "signal")),
+            "TRYAGAIN I/O error - signal",
+        );
+        assert_storage_error_frame(
+            bitcask::Error::SyncFailed,
+            "TRYAGAIN Disk synchronization failed",
+        );
+        assert_storage_error_frame(bitcask::Error::Closed, "READONLY Storage has been closed");
+        assert_storage_error_frame(bitcask::Error::Busy, "BUSY Too many pending writes");
+        assert_storage_error_frame(
+            bitcask::Error::QuotaExceeded("too many keys"),
+            "OOM Quota exceeded - too many keys",
+        );
+        assert_storage_error_frame(bitcask::Error::Recovering, "LOADING Storage is recovering");
+        assert_storage_error_frame(
+            bitcask::Error::IndexNotFound("age".into()),
+            "ERR Index does not exist - age",
+        );
+        assert_storage_error_frame(
+            bitcask::Error::EntryTooLarge { len: 80, max: 64 },
+            "ERR Entry too large - 80 bytes is over the max of 64 bytes",
+        );
+    }
+
+    #[test]
+    fn non_storage_errors_are_not_replied() {
+        let err = Error::InvalidConfig("bad");
+        assert_eq!(None, err.to_frame());
+        let err = Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        assert_eq!(None, err.to_frame());
+    }
+}
@@ -32,7 +32,7 @@ pub enum Error {
 /// they communicate over the network.
 ///
 /// [Redis Serialization Protocol (RESP)]: https://redis.io/topics/protocol
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Frame {
     /// An UTF-8 string that does not contain carriage-return nor line-feed used for sending
     /// general information.
@@ -0,0 +1,173 @@
+//! Separate lanes for read-only commands and write commands. Each lane bounds the number of
+//! commands of its class that run at once, and commands wait in their own lane's queue when it's
+//! full. Since the commands run on the blocking thread pool, a flood of writes that are stuck
+//! behind the writer lock, e.g. during a merge, would otherwise take all the threads and delay
+//! the lightweight reads.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::command::CommandClass;
+
+/// The number of commands that are waiting in a lane and that are running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LaneStats {
+    /// The number of commands waiting to run.
+    pub queued: usize,
+    /// The number of commands running.
+    pub running: usize,
+}
+
+/// The queue depths of the read lane and the write lane.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LanesStats {
+    /// The lane of the read-only commands.
+    pub reads: LaneStats,
+    /// The lane of the write commands.
+    pub writes: LaneStats,
+}
+
+/// A lane that runs a bounded number of commands at once.
+#[derive(Debug)]
+struct Lane {
+    permits: Semaphore,
+    queued: AtomicUsize,
+    running: AtomicUsize,
+}
+
+impl Lane {
+    fn new(concurrency: usize) -> Self {
+        Self {
+            permits: Semaphore::new(concurrency),
+            queued: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    async fn enter(&self) -> LaneGuard<'_> {
+        // The command stops being counted as queued even if it's cancelled while waiting
+        let queued = Counted::new(&self.queued);
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("lane semaphore is never closed");
+        drop(queued);
+        LaneGuard {
+            _permit: permit,
+            _running: Counted::new(&self.running),
+        }
+    }
+
+    fn stats(&self) -> LaneStats {
+        LaneStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            running: self.running.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Lets a command run in its lane until dropped.
+#[derive(Debug)]
+pub(crate) struct LaneGuard<'a> {
+    _permit: SemaphorePermit<'a>,
+    _running: Counted<'a>,
+}
+
+/// Adds one to a counter until dropped.
+#[derive(Debug)]
+struct Counted<'a>(&'a AtomicUsize);
+
+impl<'a> Counted<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The read lane and the write lane of a server.
+#[derive(Debug)]
+pub(crate) struct Lanes {
+    reads: Lane,
+    writes: Lane,
+}
+
+impl Lanes {
+    pub(crate) fn new(max_concurrent_reads: usize, max_concurrent_writes: usize) -> Self {
+        Self {
+            reads: Lane::new(max_concurrent_reads),
+            writes: Lane::new(max_concurrent_writes),
+        }
+    }
+
+    /// Wait until a command of the given class can run.
+    pub(crate) async fn enter(&self, class: CommandClass) -> LaneGuard<'_> {
+        match class {
+            CommandClass::Read => self.reads.enter().await,
+            CommandClass::Write => self.writes.enter().await,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> LanesStats {
+        LanesStats {
+            reads: self.reads.stats(),
+            writes: self.writes.stats(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_are_not_queued_behind_writes() {
+        let lanes = Lanes::new(1, 1);
+        let write = lanes.enter(CommandClass::Write).await;
+
+        // A second write waits for the first one
+        let queued_write = lanes.enter(CommandClass::Write);
+        tokio::pin!(queued_write);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut queued_write)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            LanesStats {
+                reads: LaneStats::default(),
+                writes: LaneStats {
+                    queued: 1,
+                    running: 1
+                },
+            },
+            lanes.stats()
+        );
+
+        // A read runs right away
+        let read = tokio::time::timeout(Duration::from_millis(10), lanes.enter(CommandClass::Read))
+            .await
+            .expect("read must not wait for the writes");
+        assert_eq!(1, lanes.stats().reads.running);
+        drop(read);
+
+        drop(write);
+        let _write = queued_write.await;
+        assert_eq!(
+            LaneStats {
+                queued: 0,
+                running: 1
+            },
+            lanes.stats().writes
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Pausing the processing of the clients' commands, which operators use to stop the writes to a
+//! server while they fail over to a replica.
+
+use tokio::{
+    sync::watch,
+    time::{self, Instant},
+};
+
+use super::command::CommandClass;
+
+/// The commands that are held while the clients are paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PauseMode {
+    /// Only the commands that write to the storage are held.
+    Write,
+    /// All commands are held, except those that pause and unpause the clients.
+    All,
+}
+
+/// The pause of the clients, which commands wait on before they are applied.
+#[derive(Debug)]
+pub(crate) struct ClientPause {
+    /// When the pause ends and which commands it holds, if the clients are paused.
+    paused: watch::Sender<Option<(Instant, PauseMode)>>,
+}
+
+impl Default for ClientPause {
+    fn default() -> Self {
+        Self {
+            paused: watch::channel(None).0,
+        }
+    }
+}
+
+impl ClientPause {
+    /// Hold the commands that the mode covers until the given time. Pausing the clients while
+    /// they are already paused keeps the later end and the mode that holds more commands.
+    pub(crate) fn pause(&self, until: Instant, mode: PauseMode) {
+        self.paused.send_modify(|paused| {
+            *paused = match *paused {
+                Some((end, prev)) if end > Instant::now() => Some((end.max(until), prev.max(mode))),
+                _ => Some((until, mode)),
+            };
+        });
+    }
+
+    /// End the pause, letting the held commands be applied.
+    pub(crate) fn unpause(&self) {
+        self.paused.send_replace(None);
+    }
+
+    /// Wait until a command of the given class isn't held by the pause.
+    pub(crate) async fn wait(&self, class: CommandClass) {
+        let mut paused = self.paused.subscribe();
+        loop {
+            let end = match *paused.borrow_and_update() {
+                Some((end, mode))
+                    if end > Instant::now()
+                        && (mode == PauseMode::All || class == CommandClass::Write) =>
+                {
+                    end
+                }
+                _ => return,
+            };
+            // The pause may be ended early or extended while waiting
+            tokio::select! {
+                _ = time::sleep_until(end) => {}
+                _ = paused.changed() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_pause_holds_only_writes() {
+        let pause = ClientPause::default();
+        pause.pause(Instant::now() + Duration::from_secs(60), PauseMode::Write);
+        time::timeout(Duration::from_millis(100), pause.wait(CommandClass::Read))
+            .await
+            .expect("reads must not be held");
+        assert!(
+            time::timeout(Duration::from_millis(10), pause.wait(CommandClass::Write))
+                .await
+                .is_err()
+        );
+
+        let unpause = async {
+            time::sleep(Duration::from_millis(10)).await;
+            pause.unpause();
+        };
+        time::timeout(Duration::from_millis(100), async {
+            tokio::join!(pause.wait(CommandClass::Write), unpause)
+        })
+        .await
+        .expect("writes must be released by unpausing");
+    }
+}
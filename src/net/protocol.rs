@@ -0,0 +1,55 @@
+//! Request/response protocols that clients can use to talk to the server. Every listener speaks
+//! a single protocol which is chosen by its configuration.
+
+mod http;
+pub(super) mod memcached;
+mod resp;
+
+use std::{fmt::Debug, future::Future, sync::Arc};
+
+use serde::Deserialize;
+
+use super::State;
+use crate::{shutdown::Shutdown, storage::KeyValueStorage};
+
+pub use self::{http::Http, memcached::Memcached, resp::Resp};
+
+/// The protocols that a listener can speak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolKind {
+    /// Redis serialization protocol.
+    #[default]
+    Resp,
+    /// Memcached text protocol.
+    Memcached,
+    /// HTTP/1.1 with JSON bodies.
+    Http,
+}
+
+/// Reads requests from a single client and sends back the responses.
+pub trait Protocol: Send + 'static {
+    /// A request that was read from the client.
+    type Request: Debug + Send;
+
+    /// Read the next request from the client. Returns `None` when the client closed the
+    /// connection after its last request.
+    fn read_request(
+        &mut self,
+    ) -> impl Future<Output = Result<Option<Self::Request>, super::Error>> + Send;
+
+    /// Apply the request to the storage and send back the response. Errors that the client can
+    /// recover from are sent back as responses, any returned error closes the connection.
+    ///
+    /// Passing a `Shutdown` allows the function to finish its execution when the server is
+    /// shutting down.
+    fn apply<KV>(
+        &mut self,
+        request: Self::Request,
+        storage: KV,
+        state: &Arc<State>,
+        shutdown: &mut Shutdown,
+    ) -> impl Future<Output = Result<(), super::Error>> + Send
+    where
+        KV: KeyValueStorage;
+}
@@ -0,0 +1,621 @@
+//! A minimal HTTP/1.1 gateway with JSON bodies, for debugging, health checks, and integrations
+//! where speaking RESP is overkill. The supported routes are:
+//!
+//! + `GET /health`
+//! + `GET /keys/{key}`
+//! + `PUT /keys/{key}` with a body of `{"value": "...", "ttl_ms": 1000}`
+//! + `DELETE /keys/{key}`
+//! + `POST /scan` with a body of `{"start": "a", "end": "z", "count": 100}`
+//!
+//! Keys in paths are percent-decoded. Only UTF-8 values can be read and written.
+
+use std::{
+    net::SocketAddr,
+    ops::Bound,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+use tracing::debug;
+
+use super::Protocol;
+use crate::{
+    net::{
+        self,
+        command::{stream, value, CommandClass},
+        State,
+    },
+    shutdown::Shutdown,
+    storage::KeyValueStorage,
+};
+
+/// Max number of bytes in the request line and the headers.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// Max number of bytes in the body of a request.
+const MAX_BODY_LEN: usize = 8 * 1024 * 1024;
+
+/// Number of keys that are returned by a scan when no count is given.
+const DEFAULT_SCAN_COUNT: usize = 100;
+
+/// A request to the HTTP gateway.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    route: Route,
+    keep_alive: bool,
+}
+
+/// The operation that is requested by the method and the path of a request.
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    /// GET /health
+    Health,
+    /// GET /keys/{key}
+    Get(Bytes),
+    /// PUT /keys/{key}
+    Put(Bytes, Bytes),
+    /// DELETE /keys/{key}
+    Delete(Bytes),
+    /// POST /scan
+    Scan(Bytes),
+    /// A path that doesn't exist.
+    NotFound,
+    /// A method that is not allowed on the path.
+    MethodNotAllowed,
+    /// A request that does not follow the protocol, with the reason why.
+    BadRequest(&'static str),
+    /// A request whose body is longer than `MAX_BODY_LEN`.
+    ContentTooLarge,
+}
+
+impl Route {
+    /// Get whether the route only reads or also writes, or `None` if it isn't applied to the
+    /// storage.
+    fn class(&self) -> Option<CommandClass> {
+        match self {
+            Route::Get(_) | Route::Scan(_) => Some(CommandClass::Read),
+            Route::Put(..) | Route::Delete(_) => Some(CommandClass::Write),
+            Route::Health
+            | Route::NotFound
+            | Route::MethodNotAllowed
+            | Route::BadRequest(_)
+            | Route::ContentTooLarge => None,
+        }
+    }
+
+    /// Get the method and the key that it writes to, if the route writes.
+    fn writes(&self) -> Option<(&'static str, &Bytes)> {
+        match self {
+            Route::Put(key, _) => Some(("PUT", key)),
+            Route::Delete(key) => Some(("DELETE", key)),
+            _ => None,
+        }
+    }
+}
+
+/// Body of PUT /keys/{key}
+#[derive(Debug, Deserialize)]
+struct PutBody {
+    value: String,
+    ttl_ms: Option<u64>,
+}
+
+/// Body of POST /scan
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ScanBody {
+    start: Option<String>,
+    end: Option<String>,
+    count: Option<usize>,
+}
+
+/// A response with a status code and an optional JSON body.
+struct Response {
+    status: u16,
+    body: Option<serde_json::Value>,
+}
+
+impl Response {
+    fn new(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            body: Some(body),
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::new(status, json!({ "error": message.to_string() }))
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: 204,
+            body: None,
+        }
+    }
+}
+
+/// Serves a client using HTTP/1.1.
+pub struct Http<S = TcpStream> {
+    // wraps a stream inside a BufWriter to reduce the number of write syscalls
+    stream: BufWriter<S>,
+    // buffered data from read operation
+    buffer: BytesMut,
+    // set when the connection must be closed after the last response
+    closing: bool,
+    // the address of the client, which is used for rate limiting and recorded in the audit log
+    peer: Option<SocketAddr>,
+}
+
+impl<S> Http<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Create the protocol handler for a client at `peer` connected through the given stream.
+    pub fn new(stream: S, peer: Option<SocketAddr>) -> Self {
+        Self {
+            stream: BufWriter::new(stream),
+            buffer: BytesMut::with_capacity(8 * 1024),
+            closing: false,
+            peer,
+        }
+    }
+
+    async fn write_response(&mut self, response: Response) -> Result<(), net::Error> {
+        let body = match response.body {
+            Some(body) => serde_json::to_vec(&body).expect("JSON values must be serializable"),
+            None => Vec::new(),
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+            response.status,
+            reason_phrase(response.status),
+            body.len()
+        );
+        if !body.is_empty() {
+            head.push_str("Content-Type: application/json\r\n");
+        }
+        if self.closing {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        self.stream.write_all(head.as_bytes()).await?;
+        self.stream.write_all(&body).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+impl<S> Protocol for Http<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Request = Request;
+
+    async fn read_request(&mut self) -> Result<Option<Request>, net::Error> {
+        if self.closing {
+            return Ok(None);
+        }
+        loop {
+            if let Some((request, len)) = parse(&self.buffer[..]) {
+                self.buffer.advance(len);
+                return Ok(Some(request));
+            }
+
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                if self.buffer.is_empty() {
+                    // Peer closed when all data is parsed
+                    return Ok(None);
+                } else {
+                    // The peer closed the socket while sending a request.
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "connection reset by peer",
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    async fn apply<KV>(
+        &mut self,
+        request: Request,
+        storage: KV,
+        state: &Arc<State>,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Requests are rate limited, audited, and held by CLIENT PAUSE like the RESP commands
+        if let Some(class) = request.route.class() {
+            if !state.within_rate_limits(self.peer, class) {
+                self.closing |= !request.keep_alive;
+                return self
+                    .write_response(Response::error(429, "too many requests"))
+                    .await;
+            }
+            if let Some((method, key)) = request.route.writes() {
+                state.audit_write(self.peer, method, [&key[..]])?;
+            }
+            tokio::select! {
+                _ = state.pause().wait(class) => {}
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+
+        let result = match request.route {
+            Route::Health => Ok(Response::new(200, json!({ "status": "ok" }))),
+            Route::Get(key) => get(storage, key).await,
+            Route::Put(key, body) => put(storage, key, body).await,
+            Route::Delete(key) => delete(storage, key).await,
+            Route::Scan(body) => scan(storage, body).await,
+            Route::NotFound => Ok(Response::error(404, "not found")),
+            Route::MethodNotAllowed => Ok(Response::error(405, "method not allowed")),
+            Route::BadRequest(reason) => {
+                // The rest of the buffered data can't be trusted to start at a request
+                self.closing = true;
+                Ok(Response::error(400, reason))
+            }
+            Route::ContentTooLarge => {
+                // The body isn't read, so the rest of the buffered data can't be trusted either
+                self.closing = true;
+                Ok(Response::error(413, "request body is too large"))
+            }
+        };
+
+        // Errors from the storage engine are reported to the client, which can keep sending
+        // requests, other errors close the connection
+        let response = match result {
+            Ok(response) => response,
+            Err(net::Error::Storage(err)) => Response::error(500, err),
+            Err(err) => return Err(err),
+        };
+        debug!(status = response.status, body = ?response.body);
+
+        self.closing |= !request.keep_alive;
+        self.write_response(response).await
+    }
+}
+
+async fn get<KV>(storage: KV, key: Bytes) -> Result<Response, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let raw = net::spawn_blocking(move || storage.get(key))
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+    let response = match raw {
+        None => Response::error(404, "key not found"),
+        Some(raw) => match value::decode_string(raw) {
+            None => Response::error(409, "key holds the wrong kind of value"),
+            Some(value) => match String::from_utf8(value.to_vec()) {
+                Ok(value) => Response::new(200, json!({ "value": value })),
+                Err(_) => Response::error(422, "value is not valid UTF-8"),
+            },
+        },
+    };
+    Ok(response)
+}
+
+async fn put<KV>(storage: KV, key: Bytes, body: Bytes) -> Result<Response, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let body: PutBody = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return Ok(Response::error(400, e)),
+    };
+    let expires_at = body
+        .ttl_ms
+        .map(|ttl| SystemTime::now() + Duration::from_millis(ttl));
+    // Values that are made of multiple entries in the storage are replaced together with their
+    // entries.
+    net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            stream::delete_chunks(txn, key.clone())?;
+            txn.set_with_expiry(key, body.value.into(), expires_at)
+        })
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))?;
+    Ok(Response::no_content())
+}
+
+async fn delete<KV>(storage: KV, key: Bytes) -> Result<Response, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    // Values that are made of multiple entries in the storage are removed together with their
+    // entries.
+    let deleted = net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            stream::delete_chunks(txn, key.clone())?;
+            txn.del(key)
+        })
+    })
+    .await?
+    .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+
+    let response = if deleted {
+        Response::no_content()
+    } else {
+        Response::error(404, "key not found")
+    };
+    Ok(response)
+}
+
+async fn scan<KV>(storage: KV, body: Bytes) -> Result<Response, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    // An empty body scans from the first key
+    let body: ScanBody = if body.is_empty() {
+        ScanBody::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => return Ok(Response::error(400, e)),
+        }
+    };
+    let start = body
+        .start
+        .map_or(Bound::Unbounded, |k| Bound::Included(k.into()));
+    let end = body
+        .end
+        .map_or(Bound::Unbounded, |k| Bound::Excluded(k.into()));
+    let count = body.count.unwrap_or(DEFAULT_SCAN_COUNT);
+    let keys = net::spawn_blocking(move || storage.scan_range(start, end, count))
+        .await?
+        .map_err(|e| net::Error::Storage(e.into()))?;
+
+    // Keys that are not valid UTF-8 are left out
+    let keys: Vec<_> = keys
+        .into_iter()
+        .filter(|k| !stream::is_chunk_key(k))
+        .filter_map(|k| String::from_utf8(k.to_vec()).ok())
+        .collect();
+    Ok(Response::new(200, json!({ "keys": keys })))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// Parse a request from the start of the buffer and return it together with the number of bytes
+/// that it takes. Returns `None` if the buffer doesn't contain a whole request yet.
+fn parse(buf: &[u8]) -> Option<(Request, usize)> {
+    let reject = |route| {
+        let request = Request {
+            route,
+            keep_alive: false,
+        };
+        Some((request, buf.len()))
+    };
+    let bad_request = |reason| reject(Route::BadRequest(reason));
+
+    // Only the first MAX_HEAD_LEN bytes are searched, so a long header is rejected whether or not
+    // its end was received
+    let head = &buf[..buf.len().min(MAX_HEAD_LEN)];
+    let head_len = match head.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 4,
+        None if buf.len() >= MAX_HEAD_LEN => return bad_request("request header is too large"),
+        None => return None,
+    };
+    let head = match std::str::from_utf8(&buf[..head_len - 4]) {
+        Ok(head) => head,
+        Err(_) => return bad_request("request header is not valid UTF-8"),
+    };
+
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, target, version) = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return bad_request("malformed request line"),
+    };
+
+    // HTTP/1.1 keeps the connection open by default, HTTP/1.0 closes it by default
+    let mut keep_alive = version == "HTTP/1.1";
+    let mut content_len = 0;
+    for line in lines {
+        let (name, val) = match line.split_once(':') {
+            Some((name, val)) => (name.trim(), val.trim()),
+            None => return bad_request("malformed header"),
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_len = match val.parse() {
+                Ok(len) => len,
+                Err(_) => return bad_request("invalid content length"),
+            };
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return bad_request("transfer encodings are not supported");
+        } else if name.eq_ignore_ascii_case("connection") {
+            if val.eq_ignore_ascii_case("close") {
+                keep_alive = false;
+            } else if val.eq_ignore_ascii_case("keep-alive") {
+                keep_alive = true;
+            }
+        }
+    }
+
+    let len = match head_len.checked_add(content_len) {
+        Some(len) if content_len <= MAX_BODY_LEN => len,
+        _ => return reject(Route::ContentTooLarge),
+    };
+    if buf.len() < len {
+        return None;
+    }
+    let body = Bytes::copy_from_slice(&buf[head_len..len]);
+
+    // The query string is ignored
+    let path = target.split('?').next().unwrap_or_default();
+    let route = match (method, path) {
+        ("GET", "/health") => Route::Health,
+        (_, "/health") => Route::MethodNotAllowed,
+        ("POST", "/scan") => Route::Scan(body),
+        (_, "/scan") => Route::MethodNotAllowed,
+        (method, path) => match path.strip_prefix("/keys/").map(percent_decode) {
+            Some(Some(key)) if !key.is_empty() => match method {
+                "GET" => Route::Get(key),
+                "PUT" => Route::Put(key, body),
+                "DELETE" => Route::Delete(key),
+                _ => Route::MethodNotAllowed,
+            },
+            Some(_) => Route::BadRequest("invalid key"),
+            None => Route::NotFound,
+        },
+    };
+    Some((Request { route, keep_alive }, len))
+}
+
+/// Decode the percent-encoded bytes of a path segment. Returns `None` if the segment contains a
+/// malformed escape sequence.
+fn percent_decode(segment: &str) -> Option<Bytes> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = [bytes.next()?, bytes.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        decoded.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+    Some(decoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask;
+
+    #[test]
+    fn parse_routes() {
+        let (request, len) = parse(b"GET /keys/a%20b HTTP/1.1\r\nHost: x\r\n\r\nGET").unwrap();
+        assert_eq!(Route::Get("a b".into()), request.route);
+        assert!(request.keep_alive);
+        assert_eq!(37, len);
+
+        let (request, _) = parse(b"PUT /keys/a HTTP/1.0\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+        assert_eq!(Route::Put("a".into(), "{}".into()), request.route);
+        assert!(!request.keep_alive);
+
+        let (request, _) = parse(b"PATCH /keys/a HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Route::MethodNotAllowed, request.route);
+        let (request, _) = parse(b"GET /nope HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Route::NotFound, request.route);
+        let (request, _) = parse(b"GET /keys/%zz HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Route::BadRequest("invalid key"), request.route);
+    }
+
+    #[test]
+    fn parse_waits_for_body() {
+        assert_eq!(None, parse(b"POST /scan HTTP/1.1\r\n"));
+        assert_eq!(
+            None,
+            parse(b"POST /scan HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_large_requests() {
+        let header = format!(
+            "GET /health HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD_LEN)
+        );
+        let (request, len) = parse(header.as_bytes()).unwrap();
+        assert_eq!(
+            Route::BadRequest("request header is too large"),
+            request.route
+        );
+        assert_eq!(header.len(), len);
+
+        let too_large = format!(
+            "PUT /keys/a HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LEN + 1
+        );
+        let overflowing = format!(
+            "PUT /keys/a HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            usize::MAX
+        );
+        for request in [too_large, overflowing] {
+            let (request, _) = parse(request.as_bytes()).unwrap();
+            assert_eq!(Route::ContentTooLarge, request.route);
+            assert!(!request.keep_alive);
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = bitcask::Config::default()
+            .path(dir.path())
+            .to_owned()
+            .open()
+            .unwrap();
+        let state = Arc::default();
+        let (_notify_shutdown, shutdown) = tokio::sync::broadcast::channel(1);
+        let mut shutdown = Shutdown::new(shutdown);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let mut protocol = Http::new(server, None);
+        let mut client = BufWriter::new(client);
+        let body = r#"{"value":"hello"}"#;
+        let requests = format!(
+            "PUT /keys/k HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}\
+             GET /keys/k HTTP/1.1\r\n\r\n\
+             POST /scan HTTP/1.1\r\n\r\n\
+             DELETE /keys/k HTTP/1.1\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        client.write_all(requests.as_bytes()).await.unwrap();
+        client.flush().await.unwrap();
+        while let Some(request) = protocol.read_request().await.unwrap() {
+            protocol
+                .apply(request, kv.get_handle(), &state, &mut shutdown)
+                .await
+                .unwrap();
+        }
+        drop(protocol);
+
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).await.unwrap();
+        assert_eq!(
+            "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 17\r\nContent-Type: application/json\r\n\r\n\
+             {\"value\":\"hello\"}\
+             HTTP/1.1 200 OK\r\nContent-Length: 14\r\nContent-Type: application/json\r\n\r\n\
+             {\"keys\":[\"k\"]}\
+             HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            responses
+        );
+    }
+}
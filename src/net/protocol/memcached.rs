@@ -0,0 +1,692 @@
+//! The [memcached text protocol], for clients that can't be changed to speak RESP. Only `get`,
+//! `set`, `delete`, and `incr` are supported. Flags are accepted but not stored, so values are
+//! always returned with flags set to `0`.
+//!
+//! [memcached text protocol]: https://github.com/memcached/memcached/blob/master/doc/protocol.txt
+
+use std::{
+    io::Write,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+use tracing::debug;
+
+use super::Protocol;
+use crate::{
+    net::{
+        self,
+        command::{stream, value, CommandClass},
+        State,
+    },
+    shutdown::Shutdown,
+    storage::{KeyValueStorage, Update},
+};
+
+/// Max number of bytes in a key.
+const MAX_KEY_LEN: usize = 250;
+
+/// Max number of bytes in a command line, without its "\r\n".
+const MAX_LINE_LEN: usize = 16 * 1024;
+
+/// Max number of bytes in the data block of a set request when no other limit is configured.
+pub(crate) const DEFAULT_MAX_ITEM_SIZE: usize = 1024 * 1024;
+
+/// Expiration times that are larger than this number of seconds are Unix timestamps, smaller
+/// ones are relative to the current time.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// A request in the memcached text protocol.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    /// get <key>*
+    Get { keys: Vec<Bytes> },
+    /// set <key> <flags> <exptime> <bytes> [noreply]
+    Set {
+        key: Bytes,
+        exptime: i64,
+        value: Bytes,
+        noreply: bool,
+    },
+    /// delete <key> [noreply]
+    Delete { key: Bytes, noreply: bool },
+    /// incr <key> <value> [noreply]
+    Incr {
+        key: Bytes,
+        delta: u64,
+        noreply: bool,
+    },
+    /// A command that is not supported.
+    Unknown,
+    /// A request that does not follow the protocol, with the reason why.
+    Malformed(&'static str),
+    /// A set request whose data block is larger than the max item size.
+    TooLarge,
+    /// A command line that is longer than `MAX_LINE_LEN`.
+    LineTooLong,
+}
+
+impl Request {
+    /// Get whether the request only reads or also writes, or `None` if it isn't applied to the
+    /// storage.
+    fn class(&self) -> Option<CommandClass> {
+        match self {
+            Request::Get { .. } => Some(CommandClass::Read),
+            Request::Set { .. } | Request::Delete { .. } | Request::Incr { .. } => {
+                Some(CommandClass::Write)
+            }
+            Request::Unknown | Request::Malformed(_) | Request::TooLarge | Request::LineTooLong => {
+                None
+            }
+        }
+    }
+
+    /// Get the name of the command and the key that it writes to, if the request writes.
+    fn writes(&self) -> Option<(&'static str, &Bytes)> {
+        match self {
+            Request::Set { key, .. } => Some(("set", key)),
+            Request::Delete { key, .. } => Some(("delete", key)),
+            Request::Incr { key, .. } => Some(("incr", key)),
+            _ => None,
+        }
+    }
+
+    /// Get whether the client asked for no reply.
+    fn noreply(&self) -> bool {
+        match self {
+            Request::Set { noreply, .. }
+            | Request::Delete { noreply, .. }
+            | Request::Incr { noreply, .. } => *noreply,
+            _ => false,
+        }
+    }
+}
+
+/// The outcome of incrementing a value.
+enum Incremented {
+    Value(u64),
+    NotFound,
+    NotNumeric,
+}
+
+/// Serves a client using the memcached text protocol.
+pub struct Memcached<S = TcpStream> {
+    // wraps a stream inside a BufWriter to reduce the number of write syscalls
+    stream: BufWriter<S>,
+    // buffered data from read operation
+    buffer: BytesMut,
+    // the address of the client, which is used for rate limiting and recorded in the audit log
+    peer: Option<SocketAddr>,
+    // max number of bytes in the data block of a set request
+    max_item_size: usize,
+    // number of bytes of a rejected data block that haven't been received and discarded yet
+    discarding: usize,
+    // whether the connection is closed once the current request is answered
+    closing: bool,
+}
+
+impl<S> Memcached<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Create the protocol handler for a client at `peer` connected through the given stream.
+    /// Set requests with data blocks larger than `max_item_size` bytes are rejected.
+    pub fn new(stream: S, peer: Option<SocketAddr>, max_item_size: usize) -> Self {
+        Self {
+            stream: BufWriter::new(stream),
+            buffer: BytesMut::with_capacity(8 * 1024),
+            peer,
+            max_item_size,
+            discarding: 0,
+            closing: false,
+        }
+    }
+
+    async fn write_reply(&mut self, reply: &[u8]) -> Result<(), net::Error> {
+        self.stream.write_all(reply).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+impl<S> Protocol for Memcached<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Request = Request;
+
+    async fn read_request(&mut self) -> Result<Option<Request>, net::Error> {
+        if self.closing {
+            return Ok(None);
+        }
+        loop {
+            // The data block of a rejected set request is discarded as it arrives, instead of
+            // being buffered
+            let discarded = self.discarding.min(self.buffer.len());
+            self.buffer.advance(discarded);
+            self.discarding -= discarded;
+            if self.discarding == 0 {
+                if let Some((request, len)) = parse(&self.buffer[..], self.max_item_size) {
+                    let parsed = len.min(self.buffer.len());
+                    self.buffer.advance(parsed);
+                    self.discarding = len - parsed;
+                    return Ok(Some(request));
+                }
+            }
+
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                if self.buffer.is_empty() {
+                    // Peer closed when all data is parsed
+                    return Ok(None);
+                } else {
+                    // The peer closed the socket while sending a request.
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "connection reset by peer",
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    async fn apply<KV>(
+        &mut self,
+        request: Request,
+        storage: KV,
+        state: &Arc<State>,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Requests are rate limited, audited, and held by CLIENT PAUSE like the RESP commands
+        if let Some(class) = request.class() {
+            if !state.within_rate_limits(self.peer, class) {
+                if !request.noreply() {
+                    self.write_reply(b"SERVER_ERROR too many requests\r\n")
+                        .await?;
+                }
+                return Ok(());
+            }
+            if let Some((command, key)) = request.writes() {
+                state.audit_write(self.peer, command, [&key[..]])?;
+            }
+            tokio::select! {
+                _ = state.pause().wait(class) => {}
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+
+        let (result, noreply) = match request {
+            Request::Get { keys } => (get(storage, keys).await, false),
+            Request::Set {
+                key,
+                exptime,
+                value,
+                noreply,
+            } => (set(storage, key, exptime, value).await, noreply),
+            Request::Delete { key, noreply } => (delete(storage, key).await, noreply),
+            Request::Incr {
+                key,
+                delta,
+                noreply,
+            } => (incr(storage, key, delta).await, noreply),
+            Request::Unknown => (Ok(b"ERROR\r\n".to_vec()), false),
+            Request::Malformed(reason) => {
+                (Ok(format!("CLIENT_ERROR {reason}\r\n").into_bytes()), false)
+            }
+            Request::TooLarge => (
+                Ok(b"SERVER_ERROR object too large for cache\r\n".to_vec()),
+                false,
+            ),
+            Request::LineTooLong => {
+                // The rest of the line is still coming, so the next request can't be found
+                self.closing = true;
+                (Ok(b"CLIENT_ERROR line is too long\r\n".to_vec()), false)
+            }
+        };
+
+        // Errors from the storage engine are reported to the client, which can keep sending
+        // requests, other errors close the connection
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(net::Error::Storage(err)) => format!("SERVER_ERROR {err}\r\n").into_bytes(),
+            Err(err) => return Err(err),
+        };
+        debug!(reply = %String::from_utf8_lossy(&reply));
+
+        if !noreply {
+            self.write_reply(&reply).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn get<KV>(storage: KV, keys: Vec<Bytes>) -> Result<Vec<u8>, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let values = net::spawn_blocking(move || {
+        keys.into_iter()
+            .map(|key| Ok((key.clone(), storage.get(key)?)))
+            .collect::<Result<Vec<_>, KV::Error>>()
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))?;
+
+    // Values of other data types are not visible to memcached clients
+    let mut reply = Vec::new();
+    for (key, raw) in values {
+        if let Some(value) = raw.and_then(value::decode_string) {
+            reply.extend_from_slice(b"VALUE ");
+            reply.extend_from_slice(&key);
+            write!(&mut reply, " 0 {}\r\n", value.len())?;
+            reply.extend_from_slice(&value);
+            reply.extend_from_slice(b"\r\n");
+        }
+    }
+    reply.extend_from_slice(b"END\r\n");
+    Ok(reply)
+}
+
+async fn set<KV>(storage: KV, key: Bytes, exptime: i64, value: Bytes) -> Result<Vec<u8>, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let expires_at = expires_at(exptime, SystemTime::now());
+    // Values that are made of multiple entries in the storage are replaced together with their
+    // entries.
+    net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            stream::delete_chunks(txn, key.clone())?;
+            txn.set_with_expiry(key, value, expires_at)
+        })
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))?;
+    Ok(b"STORED\r\n".to_vec())
+}
+
+async fn delete<KV>(storage: KV, key: Bytes) -> Result<Vec<u8>, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    // Values that are made of multiple entries in the storage are removed together with their
+    // entries.
+    let deleted = net::spawn_blocking(move || {
+        storage.atomically(move |txn| {
+            stream::delete_chunks(txn, key.clone())?;
+            txn.del(key)
+        })
+    })
+    .await?
+    .map_err(|e: KV::Error| net::Error::Storage(e.into()))?;
+
+    let reply: &[u8] = if deleted {
+        b"DELETED\r\n"
+    } else {
+        b"NOT_FOUND\r\n"
+    };
+    Ok(reply.to_vec())
+}
+
+async fn incr<KV>(storage: KV, key: Bytes, delta: u64) -> Result<Vec<u8>, net::Error>
+where
+    KV: KeyValueStorage,
+{
+    let incremented = net::spawn_blocking(move || {
+        storage.update(key, move |raw| {
+            let value = match raw {
+                Some(raw) => value::decode_string(raw),
+                None => return (Update::Keep, Incremented::NotFound),
+            };
+            match value.and_then(|v| parse_number::<u64>(&v)) {
+                Some(n) => {
+                    // The value wraps around on overflow
+                    let n = n.wrapping_add(delta);
+                    (Update::Set(n.to_string().into()), Incremented::Value(n))
+                }
+                None => (Update::Keep, Incremented::NotNumeric),
+            }
+        })
+    })
+    .await?
+    .map_err(|e| net::Error::Storage(e.into()))?;
+
+    let reply = match incremented {
+        Incremented::Value(n) => format!("{n}\r\n").into_bytes(),
+        Incremented::NotFound => b"NOT_FOUND\r\n".to_vec(),
+        Incremented::NotNumeric => {
+            b"CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".to_vec()
+        }
+    };
+    Ok(reply)
+}
+
+/// Get the time at which a key expires given its expiration time in the request. A negative
+/// expiration time makes the key expire immediately.
+fn expires_at(exptime: i64, now: SystemTime) -> Option<SystemTime> {
+    match exptime {
+        0 => None,
+        t if t < 0 => Some(SystemTime::UNIX_EPOCH),
+        t if t > MAX_RELATIVE_EXPTIME => {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(t as u64))
+        }
+        t => Some(now + Duration::from_secs(t as u64)),
+    }
+}
+
+/// Parse a request from the start of the buffer and return it together with the number of bytes
+/// that it takes. Returns `None` if the buffer doesn't contain a whole request yet. A set request
+/// whose data block is larger than `max_item_size` is rejected without waiting for the block, so
+/// the returned length can exceed the buffer's.
+fn parse(buf: &[u8], max_item_size: usize) -> Option<(Request, usize)> {
+    let line_len = match buf[..buf.len().min(MAX_LINE_LEN + 2)]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+    {
+        Some(line_len) => line_len,
+        None if buf.len() >= MAX_LINE_LEN + 2 => return Some((Request::LineTooLong, buf.len())),
+        None => return None,
+    };
+    let line = &buf[..line_len];
+    let len = line_len + 2;
+
+    let mut tokens = line.split(|&b| b == b' ').filter(|t| !t.is_empty());
+    let request = match tokens.next() {
+        Some(b"get") => {
+            let keys: Vec<_> = tokens.map(Bytes::copy_from_slice).collect();
+            if keys.is_empty() {
+                Request::Malformed("get requires at least one key")
+            } else if keys.iter().any(|k| k.len() > MAX_KEY_LEN) {
+                Request::Malformed("key is too long")
+            } else {
+                Request::Get { keys }
+            }
+        }
+        Some(b"set") => {
+            let args: Vec<_> = tokens.collect();
+            let (key, exptime, bytes, noreply) = match parse_set_args(&args) {
+                Ok(args) => args,
+                Err(reason) => return Some((Request::Malformed(reason), len)),
+            };
+            // The data block follows the command line and ends with its own "\r\n"
+            let end = len
+                .checked_add(bytes)
+                .and_then(|data_end| data_end.checked_add(2));
+            let (data_end, end) = match end {
+                Some(end) if bytes <= max_item_size => (end - 2, end),
+                _ => {
+                    return Some((
+                        Request::TooLarge,
+                        len.saturating_add(bytes).saturating_add(2),
+                    ))
+                }
+            };
+            if buf.len() < end {
+                return None;
+            }
+            if &buf[data_end..end] != b"\r\n" {
+                return Some((Request::Malformed("bad data chunk"), end));
+            }
+            let request = Request::Set {
+                key,
+                exptime,
+                value: Bytes::copy_from_slice(&buf[len..data_end]),
+                noreply,
+            };
+            return Some((request, end));
+        }
+        Some(b"delete") => match tokens.collect::<Vec<_>>()[..] {
+            [key] => parse_key(key).map_or_else(Request::Malformed, |key| Request::Delete {
+                key,
+                noreply: false,
+            }),
+            [key, b"noreply"] => parse_key(key).map_or_else(Request::Malformed, |key| {
+                Request::Delete { key, noreply: true }
+            }),
+            _ => Request::Malformed("bad command line format"),
+        },
+        Some(b"incr") => {
+            let args: Vec<_> = tokens.collect();
+            let noreply = match args[..] {
+                [_, _] => false,
+                [_, _, b"noreply"] => true,
+                _ => return Some((Request::Malformed("bad command line format"), len)),
+            };
+            match (parse_key(args[0]), parse_number::<u64>(args[1])) {
+                (Ok(key), Some(delta)) => Request::Incr {
+                    key,
+                    delta,
+                    noreply,
+                },
+                (Err(reason), _) => Request::Malformed(reason),
+                (_, None) => Request::Malformed("invalid numeric delta argument"),
+            }
+        }
+        _ => Request::Unknown,
+    };
+    Some((request, len))
+}
+
+/// Parse the arguments of a set request into the key, the expiration time, the length of the
+/// data block, and whether a reply is sent.
+fn parse_set_args(args: &[&[u8]]) -> Result<(Bytes, i64, usize, bool), &'static str> {
+    let noreply = match args {
+        [_, _, _, _] => false,
+        [_, _, _, _, b"noreply"] => true,
+        _ => return Err("bad command line format"),
+    };
+    let key = parse_key(args[0])?;
+    parse_number::<u32>(args[1]).ok_or("bad command line format")?;
+    let exptime = parse_number::<i64>(args[2]).ok_or("bad command line format")?;
+    let bytes = parse_number::<usize>(args[3]).ok_or("bad data chunk")?;
+    Ok((key, exptime, bytes, noreply))
+}
+
+fn parse_key(key: &[u8]) -> Result<Bytes, &'static str> {
+    if key.len() > MAX_KEY_LEN {
+        return Err("key is too long");
+    }
+    Ok(Bytes::copy_from_slice(key))
+}
+
+fn parse_number<T: std::str::FromStr>(token: &[u8]) -> Option<T> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask;
+
+    #[test]
+    fn parse_get() {
+        assert_eq!(
+            Some((
+                Request::Get {
+                    keys: vec!["a".into(), "b".into()]
+                },
+                10
+            )),
+            parse(b"get a  b\r\nget", DEFAULT_MAX_ITEM_SIZE)
+        );
+        assert_eq!(None, parse(b"get a", DEFAULT_MAX_ITEM_SIZE));
+        assert_eq!(
+            Some((Request::Malformed("get requires at least one key"), 5)),
+            parse(b"get\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+    }
+
+    #[test]
+    fn parse_set_waits_for_data_block() {
+        assert_eq!(None, parse(b"set a 0 0 5\r\nhel", DEFAULT_MAX_ITEM_SIZE));
+        assert_eq!(None, parse(b"set a 0 0 5\r\nhello", DEFAULT_MAX_ITEM_SIZE));
+        assert_eq!(
+            Some((
+                Request::Set {
+                    key: "a".into(),
+                    exptime: 60,
+                    value: "hello".into(),
+                    noreply: true,
+                },
+                29
+            )),
+            parse(b"set a 1 60 5 noreply\r\nhello\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+    }
+
+    #[test]
+    fn parse_set_bad_data_chunk() {
+        assert_eq!(
+            Some((Request::Malformed("bad data chunk"), 18)),
+            parse(b"set a 0 0 3\r\nhello\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+    }
+
+    #[test]
+    fn parse_delete_and_incr() {
+        assert_eq!(
+            Some((
+                Request::Delete {
+                    key: "a".into(),
+                    noreply: false
+                },
+                10
+            )),
+            parse(b"delete a\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+        assert_eq!(
+            Some((
+                Request::Incr {
+                    key: "a".into(),
+                    delta: 5,
+                    noreply: true
+                },
+                18
+            )),
+            parse(b"incr a 5 noreply\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+        assert_eq!(
+            Some((Request::Malformed("invalid numeric delta argument"), 11)),
+            parse(b"incr a -1\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+        assert_eq!(
+            Some((Request::Unknown, 11)),
+            parse(b"flush_all\r\n", DEFAULT_MAX_ITEM_SIZE)
+        );
+    }
+
+    #[test]
+    fn relative_and_absolute_expiration_times() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        assert_eq!(None, expires_at(0, now));
+        assert_eq!(Some(SystemTime::UNIX_EPOCH), expires_at(-1, now));
+        assert_eq!(Some(now + Duration::from_secs(10)), expires_at(10, now));
+        assert_eq!(
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000)),
+            expires_at(1_500_000_000, now)
+        );
+    }
+
+    #[test]
+    fn parse_rejects_large_requests() {
+        assert_eq!(
+            Some((Request::TooLarge, 21)),
+            parse(b"set a 0 0 6\r\nhel", 5)
+        );
+        let overflowing = format!("set a 0 0 {}\r\n", usize::MAX);
+        assert_eq!(
+            Some((Request::TooLarge, usize::MAX)),
+            parse(overflowing.as_bytes(), usize::MAX)
+        );
+        let long_line = format!("get {}", "a ".repeat(MAX_LINE_LEN));
+        assert_eq!(
+            Some((Request::LineTooLong, long_line.len())),
+            parse(long_line.as_bytes(), DEFAULT_MAX_ITEM_SIZE)
+        );
+    }
+
+    #[tokio::test]
+    async fn discard_data_blocks_that_are_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = bitcask::Config::default()
+            .path(dir.path())
+            .to_owned()
+            .open()
+            .unwrap();
+        let state = Arc::default();
+        let (_notify_shutdown, shutdown) = tokio::sync::broadcast::channel(1);
+        let mut shutdown = Shutdown::new(shutdown);
+
+        let (client, server) = tokio::io::duplex(1024);
+        let mut protocol = Memcached::new(server, None, 4);
+        let mut client = BufWriter::new(client);
+        client
+            .write_all(b"set a 0 0 5\r\nhello\r\nset a 0 0 2\r\nhi\r\nget a\r\n")
+            .await
+            .unwrap();
+        client.flush().await.unwrap();
+        for _ in 0..3 {
+            let request = protocol.read_request().await.unwrap().unwrap();
+            protocol
+                .apply(request, kv.get_handle(), &state, &mut shutdown)
+                .await
+                .unwrap();
+        }
+        drop(protocol);
+
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).await.unwrap();
+        assert_eq!(
+            "SERVER_ERROR object too large for cache\r\nSTORED\r\nVALUE a 0 2\r\nhi\r\nEND\r\n",
+            replies
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = bitcask::Config::default()
+            .path(dir.path())
+            .to_owned()
+            .open()
+            .unwrap();
+        let state = Arc::default();
+        let (_notify_shutdown, shutdown) = tokio::sync::broadcast::channel(1);
+        let mut shutdown = Shutdown::new(shutdown);
+
+        let (client, server) = tokio::io::duplex(1024);
+        let mut protocol = Memcached::new(server, None, DEFAULT_MAX_ITEM_SIZE);
+        let mut client = BufWriter::new(client);
+        client
+            .write_all(
+                b"set n 0 0 2\r\n41\r\nincr n 1\r\nget n x\r\ndelete n\r\nincr n 1\r\nbogus\r\n",
+            )
+            .await
+            .unwrap();
+        client.flush().await.unwrap();
+        for _ in 0..6 {
+            let request = protocol.read_request().await.unwrap().unwrap();
+            protocol
+                .apply(request, kv.get_handle(), &state, &mut shutdown)
+                .await
+                .unwrap();
+        }
+        drop(protocol);
+
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).await.unwrap();
+        assert_eq!(
+            "STORED\r\n42\r\nVALUE n 0 2\r\n42\r\nEND\r\nDELETED\r\nNOT_FOUND\r\nERROR\r\n",
+            replies
+        );
+    }
+}
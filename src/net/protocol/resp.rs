@@ -0,0 +1,210 @@
+mod session;
+
+use std::{convert::TryFrom, net::SocketAddr, sync::Arc};
+
+use tokio::{net::TcpStream, sync::mpsc};
+use tracing::debug;
+
+use self::session::{Session, Step};
+use super::Protocol;
+use crate::{
+    net::{
+        self,
+        command::{Command, SessionCommand},
+        connection::Connection,
+        frame::Frame,
+        pubsub::{self, Interest},
+        State,
+    },
+    shutdown::Shutdown,
+    storage::{databases::DEFAULT_DATABASE, KeyValueStorage},
+};
+
+/// The number of frames that can wait to be pushed to a subscribed or monitoring client. Frames
+/// that don't fit are dropped.
+const MAX_PENDING_PUSHES: usize = 1024;
+
+/// Serves a client using the Redis serialization protocol (RESP).
+pub struct Resp {
+    connection: Connection,
+    /// The address of the client, which is used for rate limiting and recorded in the audit log.
+    peer: Option<SocketAddr>,
+    /// The states that are shared by all connections.
+    state: Arc<State>,
+    /// The state of the connection, which decides what is done with each command.
+    session: Session,
+    /// The ID of the connection among the connections that listen for pushed frames.
+    id: u64,
+    /// The frames that are pushed to the client when it subscribed to channels or is monitoring.
+    pushes: mpsc::Receiver<Frame>,
+    /// The sender of the pushed frames, which is handed to the connections that push them.
+    pushes_tx: mpsc::Sender<Frame>,
+}
+
+impl Resp {
+    /// Create the protocol handler for a client at `peer` connected through the given socket.
+    pub(crate) fn new(socket: TcpStream, peer: Option<SocketAddr>, state: Arc<State>) -> Self {
+        let (pushes_tx, pushes) = mpsc::channel(MAX_PENDING_PUSHES);
+        Self {
+            connection: Connection::new(socket),
+            peer,
+            id: state.pubsub().next_id(),
+            state,
+            session: Session::default(),
+            pushes,
+            pushes_tx,
+        }
+    }
+
+    /// Apply a single command and send back its reply.
+    async fn apply_command<KV>(
+        &mut self,
+        request: Command,
+        storage: KV,
+        state: &Arc<State>,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // Requests over the rate limits are rejected without being applied
+        if !state.within_rate_limits(self.peer, request.class()) {
+            let response = Frame::Error("RATELIMITED too many requests".to_string());
+            debug!(?response);
+            self.connection.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        // Writes are recorded before they are applied, so failed attempts are also audited
+        if let Some((command, keys)) = request.writes() {
+            let keys = keys.into_iter().map(|k| k.as_str().as_bytes());
+            state.audit_write(self.peer, command, keys)?;
+        }
+
+        // Commands held by CLIENT PAUSE wait for the pause to end, except the commands that
+        // change the pause itself
+        if !matches!(request, Command::Client(_)) {
+            tokio::select! {
+                _ = state.pause().wait(request.class()) => {}
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+
+        // Blocking commands can wait for a long time, so they don't take a place in the lanes
+        let _lane = match request {
+            Command::BlockingPop(_) => None,
+            _ => state.enter_lane(request.class()).await,
+        };
+        let result = request
+            .apply(storage, state, &mut self.connection, shutdown)
+            .await;
+
+        // Errors from the storage engine are reported to the client, which can keep sending
+        // commands, other errors close the connection
+        if let Err(err) = result {
+            let response = err.to_frame().ok_or(err)?;
+            debug!(?response);
+            self.connection.write_frame(&response).await?;
+        }
+        Ok(())
+    }
+
+    /// Update what the connection listens for after its state changed. Frames that were pushed
+    /// but not sent are dropped when the client stops listening.
+    fn listen(&mut self) {
+        let pubsub = self.state.pubsub();
+        match &self.session {
+            Session::Subscribed(channels) => pubsub.listen(
+                self.id,
+                Interest::Channels(channels.clone()),
+                &self.pushes_tx,
+            ),
+            Session::Monitor => pubsub.listen(self.id, Interest::Monitor, &self.pushes_tx),
+            Session::Normal | Session::Multi { .. } => {
+                pubsub.forget(self.id);
+                while self.pushes.try_recv().is_ok() {}
+            }
+        }
+    }
+
+    /// Get the address of the client as it's recorded in logs.
+    fn client(&self) -> String {
+        self.peer.map(|addr| addr.to_string()).unwrap_or_default()
+    }
+}
+
+impl Protocol for Resp {
+    type Request = Command;
+
+    async fn read_request(&mut self) -> Result<Option<Command>, net::Error> {
+        loop {
+            // Pushed frames are sent while waiting for the next request
+            let frame = if self.session.receives_pushes() {
+                tokio::select! {
+                    frame = self.connection.read_frame() => frame?,
+                    Some(push) = self.pushes.recv() => {
+                        self.connection.write_frame(&push).await?;
+                        continue;
+                    }
+                }
+            } else {
+                self.connection.read_frame().await?
+            };
+            let Some(frame) = frame else {
+                return Ok(None);
+            };
+
+            // Try to parse a command out of the frame
+            let frame = self.state.renames().resolve(frame)?;
+            self.state
+                .pubsub()
+                .feed_monitors(|| pubsub::monitor_line(&self.client(), &frame));
+            return Ok(Some(Command::try_from(frame)?));
+        }
+    }
+
+    async fn apply<KV>(
+        &mut self,
+        request: Command,
+        storage: KV,
+        state: &Arc<State>,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), net::Error>
+    where
+        KV: KeyValueStorage,
+    {
+        // RESET also switches the connection back to the default database
+        if matches!(request, Command::Session(SessionCommand::Reset)) {
+            let storage = storage.clone();
+            net::spawn_blocking(move || storage.select(DEFAULT_DATABASE.as_bytes()))
+                .await?
+                .map_err(|e| net::Error::Storage(e.into()))?;
+        }
+        match self.session.step(request) {
+            Step::Apply(request) => self.apply_command(request, storage, state, shutdown).await,
+            Step::Exec(requests) => {
+                // Each command writes its own reply, which together make the elements of the
+                // array
+                self.connection.write_array_header(requests.len()).await?;
+                for request in requests {
+                    self.apply_command(request, storage.clone(), state, shutdown)
+                        .await?;
+                }
+                Ok(())
+            }
+            Step::Reply(responses) => {
+                // The client may act on the replies right away, so it must already be listening
+                self.listen();
+                debug!(?responses);
+                self.connection.write_frames(&responses).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for Resp {
+    fn drop(&mut self) {
+        self.state.pubsub().forget(self.id);
+    }
+}
@@ -0,0 +1,331 @@
+//! The state machine of a RESP connection. The state decides what is done with each command, so
+//! the commands that change the state of a connection are handled in one place.
+
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+
+use crate::net::{
+    command::{Command, SessionCommand},
+    frame::Frame,
+};
+
+/// The state of a connection.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) enum Session {
+    /// Commands are applied as they come.
+    #[default]
+    Normal,
+    /// Commands are queued until EXEC. The transaction is aborted on EXEC if a command couldn't
+    /// be queued.
+    Multi {
+        /// The commands that run on EXEC.
+        queued: Vec<Command>,
+        /// Whether a command was rejected while queuing.
+        aborted: bool,
+    },
+    /// Messages that are published to the channels are pushed to the client, and only the
+    /// commands that change the subscriptions are allowed.
+    Subscribed(BTreeSet<Bytes>),
+    /// The commands of all clients are pushed to the client, which can only leave this state.
+    Monitor,
+}
+
+/// What is done with a command.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Step {
+    /// Apply the command and send back its reply.
+    Apply(Command),
+    /// Apply the commands in order and send back their replies in an array.
+    Exec(Vec<Command>),
+    /// Send back the replies without applying anything.
+    Reply(Vec<Frame>),
+}
+
+impl Session {
+    /// Move to the next state with the given command, and return what to do with the command.
+    pub(super) fn step(&mut self, command: Command) -> Step {
+        let (next, step) = match (std::mem::take(self), command) {
+            // Every state can be left with RESET
+            (_, Command::Session(SessionCommand::Reset)) => (Self::Normal, ok("RESET")),
+            (Self::Normal, Command::Session(cmd)) => Self::step_normal(cmd),
+            (Self::Normal, cmd) => (Self::Normal, Step::Apply(cmd)),
+            (Self::Multi { queued, aborted }, cmd) => Self::step_multi(queued, aborted, cmd),
+            (Self::Subscribed(channels), Command::Session(SessionCommand::Subscribe(new))) => {
+                subscribe(channels, new)
+            }
+            (Self::Subscribed(channels), Command::Session(SessionCommand::Unsubscribe(old))) => {
+                unsubscribe(channels, old)
+            }
+            (state @ Self::Subscribed(_), _) => (
+                state,
+                error("ERR only SUBSCRIBE, UNSUBSCRIBE, and RESET are allowed in this context"),
+            ),
+            (Self::Monitor, _) => (
+                Self::Monitor,
+                error("ERR only RESET is allowed while monitoring"),
+            ),
+        };
+        *self = next;
+        step
+    }
+
+    /// Get whether frames can be pushed to the client while it's waiting for replies.
+    pub(super) fn receives_pushes(&self) -> bool {
+        matches!(self, Self::Subscribed(_) | Self::Monitor)
+    }
+
+    fn step_normal(cmd: SessionCommand) -> (Self, Step) {
+        match cmd {
+            SessionCommand::Multi => (
+                Self::Multi {
+                    queued: Vec::new(),
+                    aborted: false,
+                },
+                ok("OK"),
+            ),
+            SessionCommand::Exec => (Self::Normal, error("ERR EXEC without MULTI")),
+            SessionCommand::Discard => (Self::Normal, error("ERR DISCARD without MULTI")),
+            SessionCommand::Subscribe(channels) => subscribe(BTreeSet::new(), channels),
+            SessionCommand::Unsubscribe(channels) => unsubscribe(BTreeSet::new(), channels),
+            SessionCommand::Monitor => (Self::Monitor, ok("OK")),
+            SessionCommand::Reset => (Self::Normal, ok("RESET")),
+        }
+    }
+
+    fn step_multi(mut queued: Vec<Command>, aborted: bool, cmd: Command) -> (Self, Step) {
+        match cmd {
+            Command::Session(SessionCommand::Exec) if aborted => (
+                Self::Normal,
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+            Command::Session(SessionCommand::Exec) => (Self::Normal, Step::Exec(queued)),
+            Command::Session(SessionCommand::Discard) => (Self::Normal, ok("OK")),
+            Command::Session(SessionCommand::Multi) => (
+                Self::Multi { queued, aborted },
+                error("ERR MULTI calls can not be nested"),
+            ),
+            // Commands that change the state of the connection or that block can't run as a
+            // part of a transaction
+            Command::Session(_) | Command::BlockingPop(_) => (
+                Self::Multi {
+                    queued,
+                    aborted: true,
+                },
+                error("ERR Command not allowed inside a transaction"),
+            ),
+            cmd => {
+                queued.push(cmd);
+                (Self::Multi { queued, aborted }, ok("QUEUED"))
+            }
+        }
+    }
+}
+
+/// Add the channels to the subscriptions, replying with the number of subscriptions after each
+/// channel is added.
+fn subscribe(mut channels: BTreeSet<Bytes>, new: Vec<Bytes>) -> (Session, Step) {
+    let mut replies = Vec::with_capacity(new.len());
+    for channel in new {
+        channels.insert(channel.clone());
+        replies.push(subscription(
+            "subscribe",
+            Frame::BulkString(channel),
+            &channels,
+        ));
+    }
+    (Session::Subscribed(channels), Step::Reply(replies))
+}
+
+/// Remove the channels from the subscriptions, or all the subscriptions when no channel is
+/// given, replying with the number of subscriptions after each channel is removed. The client
+/// leaves the subscribed state once it has no subscription.
+fn unsubscribe(mut channels: BTreeSet<Bytes>, old: Vec<Bytes>) -> (Session, Step) {
+    let old = if old.is_empty() {
+        channels.iter().cloned().collect()
+    } else {
+        old
+    };
+    let mut replies = Vec::with_capacity(old.len().max(1));
+    for channel in old {
+        channels.remove(&channel);
+        replies.push(subscription(
+            "unsubscribe",
+            Frame::BulkString(channel),
+            &channels,
+        ));
+    }
+    if replies.is_empty() {
+        replies.push(subscription("unsubscribe", Frame::Null, &channels));
+    }
+    let next = if channels.is_empty() {
+        Session::Normal
+    } else {
+        Session::Subscribed(channels)
+    };
+    (next, Step::Reply(replies))
+}
+
+fn subscription(kind: &'static str, channel: Frame, channels: &BTreeSet<Bytes>) -> Frame {
+    Frame::Array(vec![
+        Frame::BulkString(kind.into()),
+        channel,
+        Frame::Integer(channels.len() as i64),
+    ])
+}
+
+fn ok(status: &str) -> Step {
+    Step::Reply(vec![Frame::SimpleString(status.to_string())])
+}
+
+fn error(message: &str) -> Step {
+    Step::Reply(vec![Frame::Error(message.to_string())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a command from a frame like the ones sent by clients, and feed it to the session.
+    fn step(session: &mut Session, args: &[&'static str]) -> Step {
+        session.step(command(args))
+    }
+
+    fn command(args: &[&'static str]) -> Command {
+        let frame = Frame::Array(
+            args.iter()
+                .map(|arg| Frame::BulkString(Bytes::from(*arg)))
+                .collect(),
+        );
+        Command::try_from(frame).unwrap()
+    }
+
+    fn subscription_reply(kind: &'static str, channel: &'static str, count: i64) -> Frame {
+        Frame::Array(vec![
+            Frame::BulkString(kind.into()),
+            Frame::BulkString(channel.into()),
+            Frame::Integer(count),
+        ])
+    }
+
+    #[test]
+    fn normal_commands_are_applied() {
+        let mut session = Session::default();
+        assert_eq!(
+            Step::Apply(command(&["GET", "a"])),
+            step(&mut session, &["GET", "a"])
+        );
+        assert_eq!(
+            error("ERR EXEC without MULTI"),
+            step(&mut session, &["EXEC"])
+        );
+        assert_eq!(
+            error("ERR DISCARD without MULTI"),
+            step(&mut session, &["DISCARD"])
+        );
+        assert_eq!(Session::Normal, session);
+    }
+
+    #[test]
+    fn multi_queues_commands_until_exec() {
+        let mut session = Session::default();
+        assert_eq!(ok("OK"), step(&mut session, &["MULTI"]));
+        assert_eq!(ok("QUEUED"), step(&mut session, &["SET", "a", "1"]));
+        assert_eq!(ok("QUEUED"), step(&mut session, &["GET", "a"]));
+        assert_eq!(
+            error("ERR MULTI calls can not be nested"),
+            step(&mut session, &["MULTI"])
+        );
+        assert_eq!(
+            Step::Exec(vec![command(&["SET", "a", "1"]), command(&["GET", "a"])]),
+            step(&mut session, &["EXEC"])
+        );
+        assert_eq!(Session::Normal, session);
+
+        assert_eq!(ok("OK"), step(&mut session, &["MULTI"]));
+        assert_eq!(ok("QUEUED"), step(&mut session, &["DEL", "a"]));
+        assert_eq!(ok("OK"), step(&mut session, &["DISCARD"]));
+        assert_eq!(Session::Normal, session);
+    }
+
+    #[test]
+    fn rejected_commands_abort_the_transaction() {
+        let mut session = Session::default();
+        step(&mut session, &["MULTI"]);
+        assert_eq!(
+            error("ERR Command not allowed inside a transaction"),
+            step(&mut session, &["SUBSCRIBE", "a"])
+        );
+        assert_eq!(
+            error("ERR Command not allowed inside a transaction"),
+            step(&mut session, &["BLPOP", "a", "0"])
+        );
+        assert_eq!(ok("QUEUED"), step(&mut session, &["GET", "a"]));
+        assert_eq!(
+            error("EXECABORT Transaction discarded because of previous errors."),
+            step(&mut session, &["EXEC"])
+        );
+        assert_eq!(Session::Normal, session);
+    }
+
+    #[test]
+    fn subscribed_clients_only_change_subscriptions() {
+        let mut session = Session::default();
+        assert_eq!(
+            Step::Reply(vec![
+                subscription_reply("subscribe", "a", 1),
+                subscription_reply("subscribe", "b", 2),
+            ]),
+            step(&mut session, &["SUBSCRIBE", "a", "b"])
+        );
+        assert!(session.receives_pushes());
+        assert_eq!(
+            error("ERR only SUBSCRIBE, UNSUBSCRIBE, and RESET are allowed in this context"),
+            step(&mut session, &["GET", "a"])
+        );
+        assert_eq!(
+            Step::Reply(vec![subscription_reply("unsubscribe", "a", 1)]),
+            step(&mut session, &["UNSUBSCRIBE", "a"])
+        );
+        assert_eq!(
+            Session::Subscribed(BTreeSet::from([Bytes::from("b")])),
+            session
+        );
+
+        // Unsubscribing from everything goes back to applying commands
+        assert_eq!(
+            Step::Reply(vec![subscription_reply("unsubscribe", "b", 0)]),
+            step(&mut session, &["UNSUBSCRIBE"])
+        );
+        assert_eq!(Session::Normal, session);
+        assert!(!session.receives_pushes());
+        assert_eq!(
+            Step::Reply(vec![Frame::Array(vec![
+                Frame::BulkString("unsubscribe".into()),
+                Frame::Null,
+                Frame::Integer(0),
+            ])]),
+            step(&mut session, &["UNSUBSCRIBE"])
+        );
+    }
+
+    #[test]
+    fn reset_leaves_every_state() {
+        let states: [&[&'static str]; 3] = [&["MULTI"], &["SUBSCRIBE", "a"], &["MONITOR"]];
+        for enter in states {
+            let mut session = Session::default();
+            step(&mut session, enter);
+            assert_ne!(Session::Normal, session);
+            assert_eq!(ok("RESET"), step(&mut session, &["RESET"]));
+            assert_eq!(Session::Normal, session);
+        }
+
+        let mut session = Session::default();
+        step(&mut session, &["MONITOR"]);
+        assert_eq!(
+            error("ERR only RESET is allowed while monitoring"),
+            step(&mut session, &["GET", "a"])
+        );
+        assert_eq!(Session::Monitor, session);
+    }
+}
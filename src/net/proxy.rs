@@ -0,0 +1,151 @@
+//! The header of version 2 of HAProxy's PROXY protocol, which L4 load balancers send at the start
+//! of every connection so the server learns the address of the client instead of the address of
+//! the load balancer.
+//!
+//! See https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{io::AsyncReadExt, net::TcpStream, time};
+
+/// The bytes that every version 2 header starts with.
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The number of bytes of the fixed part of a header, which ends with the length of the rest.
+const FIXED_LEN: usize = 16;
+
+/// The max time a client has to send the header, so clients that never send it can't hold on to
+/// their connection permits.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read the PROXY protocol header that starts the stream, and return the address of the client
+/// that the proxy connected on behalf of. Returns `None` for the connections that the proxy made
+/// on its own, e.g. for health checks, and for addresses that aren't IP addresses.
+pub(crate) async fn read_header(socket: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let read = async {
+        let mut fixed = [0u8; FIXED_LEN];
+        socket.read_exact(&mut fixed).await?;
+        let len = parse_fixed(&fixed)?;
+        let mut rest = vec![0u8; len];
+        socket.read_exact(&mut rest).await?;
+        parse_addresses(&fixed, &rest)
+    };
+    time::timeout(HEADER_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY header timed out"))?
+}
+
+/// Check the fixed part of a header, and return the number of bytes that follow it.
+fn parse_fixed(fixed: &[u8; FIXED_LEN]) -> io::Result<usize> {
+    if fixed[..12] != SIGNATURE {
+        return Err(invalid("missing PROXY protocol v2 signature"));
+    }
+    if fixed[12] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    Ok(u16::from_be_bytes([fixed[14], fixed[15]]) as usize)
+}
+
+/// Get the source address from the part of a header that follows its fixed part. The TLVs that
+/// come after the addresses are ignored.
+fn parse_addresses(fixed: &[u8; FIXED_LEN], rest: &[u8]) -> io::Result<Option<SocketAddr>> {
+    match fixed[12] & 0x0f {
+        // LOCAL
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unsupported PROXY protocol command")),
+    }
+    match fixed[13] >> 4 {
+        // AF_INET
+        0x1 => {
+            let addrs = rest
+                .get(..12)
+                .ok_or_else(|| invalid("truncated IPv4 addresses"))?;
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        0x2 => {
+            let addrs = rest
+                .get(..36)
+                .ok_or_else(|| invalid("truncated IPv6 addresses"))?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addrs[..16]);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // AF_UNSPEC, AF_UNIX
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(command: u8, family: u8, addrs: &[u8]) -> ([u8; FIXED_LEN], Vec<u8>) {
+        let mut fixed = [0u8; FIXED_LEN];
+        fixed[..12].copy_from_slice(&SIGNATURE);
+        fixed[12] = 0x20 | command;
+        fixed[13] = family << 4 | 0x1;
+        fixed[14..].copy_from_slice(&(addrs.len() as u16).to_be_bytes());
+        (fixed, addrs.to_vec())
+    }
+
+    #[test]
+    fn parse_ipv4_and_ipv6_sources() {
+        let mut addrs = vec![10, 0, 0, 1, 127, 0, 0, 1];
+        addrs.extend_from_slice(&4242u16.to_be_bytes());
+        addrs.extend_from_slice(&6379u16.to_be_bytes());
+        let (fixed, rest) = header(0x1, 0x1, &addrs);
+        assert_eq!(rest.len(), parse_fixed(&fixed).unwrap());
+        assert_eq!(
+            Some("10.0.0.1:4242".parse().unwrap()),
+            parse_addresses(&fixed, &rest).unwrap()
+        );
+
+        let mut addrs = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)
+            .octets()
+            .to_vec();
+        addrs.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        addrs.extend_from_slice(&4242u16.to_be_bytes());
+        addrs.extend_from_slice(&6379u16.to_be_bytes());
+        // TLVs after the addresses are skipped
+        addrs.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]);
+        let (fixed, rest) = header(0x1, 0x2, &addrs);
+        assert_eq!(
+            Some("[2001:db8::1]:4242".parse().unwrap()),
+            parse_addresses(&fixed, &rest).unwrap()
+        );
+    }
+
+    #[test]
+    fn local_connections_have_no_source() {
+        let (fixed, rest) = header(0x0, 0x0, &[]);
+        assert_eq!(0, parse_fixed(&fixed).unwrap());
+        assert_eq!(None, parse_addresses(&fixed, &rest).unwrap());
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let (mut fixed, _) = header(0x1, 0x1, &[]);
+        // Truncated addresses
+        assert!(parse_addresses(&fixed, &[10, 0, 0, 1]).is_err());
+        // Version 1 of the protocol is a text header
+        fixed[12] = 0x11;
+        assert!(parse_fixed(&fixed).is_err());
+        let mut fixed = [0u8; FIXED_LEN];
+        fixed[..6].copy_from_slice(b"PROXY ");
+        assert!(parse_fixed(&fixed).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Delivery of published messages and of the monitored commands to the connections that listen
+//! for them.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+
+use super::frame::Frame;
+
+/// What a connection listens for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Interest {
+    /// The messages that are published to any of the channels.
+    Channels(BTreeSet<Bytes>),
+    /// The commands that are run by all clients.
+    Monitor,
+}
+
+/// The connections that listen for messages or commands. Frames are pushed to a connection
+/// through a bounded queue, and a connection that doesn't keep up loses the frames that don't
+/// fit in its queue.
+#[derive(Debug, Default)]
+pub(crate) struct PubSub {
+    listeners: Mutex<HashMap<u64, (Interest, mpsc::Sender<Frame>)>>,
+    next_id: AtomicU64,
+}
+
+impl PubSub {
+    /// Get a new ID for a connection.
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Set what the connection listens for, replacing what it listened for before.
+    pub(crate) fn listen(&self, id: u64, interest: Interest, pushes: &mpsc::Sender<Frame>) {
+        self.listeners.lock().insert(id, (interest, pushes.clone()));
+    }
+
+    /// Stop pushing frames to the connection.
+    pub(crate) fn forget(&self, id: u64) {
+        self.listeners.lock().remove(&id);
+    }
+
+    /// Push a message to the connections that subscribed to the channel, and return the number
+    /// of connections that received it.
+    pub(crate) fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let push = Frame::Array(vec![
+            Frame::BulkString("message".into()),
+            Frame::BulkString(channel.clone()),
+            Frame::BulkString(message.clone()),
+        ]);
+        self.listeners
+            .lock()
+            .values()
+            .filter(|(interest, _)| {
+                matches!(interest, Interest::Channels(channels) if channels.contains(channel))
+            })
+            .filter(|(_, pushes)| pushes.try_send(push.clone()).is_ok())
+            .count()
+    }
+
+    /// Push a line describing a command to the monitoring connections. The line is only made
+    /// when a connection is monitoring.
+    pub(crate) fn feed_monitors<F>(&self, line: F)
+    where
+        F: FnOnce() -> String,
+    {
+        let listeners = self.listeners.lock();
+        let mut monitors = listeners
+            .values()
+            .filter(|(interest, _)| *interest == Interest::Monitor)
+            .peekable();
+        if monitors.peek().is_none() {
+            return;
+        }
+        let push = Frame::SimpleString(line());
+        for (_, pushes) in monitors {
+            // A lagging monitor misses lines instead of slowing down the other clients
+            let _ = pushes.try_send(push.clone());
+        }
+    }
+}
+
+/// Describe a command like the lines that Redis sends to monitoring clients.
+pub(crate) fn monitor_line(client: &str, frame: &Frame) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [0 {}]",
+        now.as_secs(),
+        now.subsec_micros(),
+        client
+    );
+    if let Frame::Array(args) = frame {
+        for arg in args {
+            if let Frame::BulkString(arg) = arg {
+                line.push_str(" \"");
+                line.extend(arg.escape_ascii().map(char::from));
+                line.push('"');
+            }
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_to_subscribed_connections() {
+        let pubsub = PubSub::default();
+        let (tx_a, mut rx_a) = mpsc::channel(1);
+        let (tx_b, mut rx_b) = mpsc::channel(1);
+        let channels = |names: &[&'static str]| {
+            Interest::Channels(names.iter().map(|n| Bytes::from(*n)).collect())
+        };
+        pubsub.listen(pubsub.next_id(), channels(&["news"]), &tx_a);
+        let b = pubsub.next_id();
+        pubsub.listen(b, channels(&["news", "sports"]), &tx_b);
+
+        assert_eq!(2, pubsub.publish(&"news".into(), &"hello".into()));
+        assert_eq!(
+            Frame::Array(vec![
+                Frame::BulkString("message".into()),
+                Frame::BulkString("news".into()),
+                Frame::BulkString("hello".into()),
+            ]),
+            rx_a.try_recv().unwrap()
+        );
+        assert!(rx_b.try_recv().is_ok());
+
+        // A full queue drops the message
+        assert_eq!(1, pubsub.publish(&"sports".into(), &"a".into()));
+        assert_eq!(0, pubsub.publish(&"sports".into(), &"b".into()));
+
+        pubsub.forget(b);
+        assert_eq!(0, pubsub.publish(&"weather".into(), &"c".into()));
+        assert_eq!(1, pubsub.publish(&"news".into(), &"d".into()));
+    }
+
+    #[test]
+    fn monitor_lines_quote_arguments() {
+        let frame = Frame::Array(vec![
+            Frame::BulkString("SET".into()),
+            Frame::BulkString("a \"b\"\n".into()),
+        ]);
+        let line = monitor_line("127.0.0.1:6379", &frame);
+        assert!(line.ends_with(r#" [0 127.0.0.1:6379] "SET" "a \"b\"\n""#));
+    }
+}
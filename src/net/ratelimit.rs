@@ -0,0 +1,259 @@
+//! Token-bucket rate limiting of client requests, so a single misbehaving client can't keep the
+//! storage busy, e.g. by holding the writer with a flood of writes, at the expense of the other
+//! clients. Every bucket holds at most one second worth of requests, which is the largest burst
+//! that it allows.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+use super::command::CommandClass;
+
+/// The number of clients that are tracked before the clients whose buckets have refilled are
+/// forgotten.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// The max number of requests per second, a limit is not enforced when it isn't set.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Max number of requests per second from all clients.
+    pub global: Option<NonZeroU32>,
+
+    /// Max number of requests per second from a single client IP.
+    pub per_client: Option<NonZeroU32>,
+
+    /// Max number of read-only requests per second from a single client IP.
+    pub per_client_reads: Option<NonZeroU32>,
+
+    /// Max number of write requests per second from a single client IP.
+    pub per_client_writes: Option<NonZeroU32>,
+}
+
+impl RateLimits {
+    /// Check whether any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some()
+            || self.per_client.is_some()
+            || self.per_client_reads.is_some()
+            || self.per_client_writes.is_some()
+    }
+}
+
+/// A bucket that is refilled at the rate of the limit and from which each request takes a token.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: f64::INFINITY,
+            refilled_at: now,
+        }
+    }
+
+    /// Add the tokens that accumulated since the last refill, then check whether a token can be
+    /// taken without taking it.
+    fn refill(&mut self, rate: NonZeroU32, now: Instant) -> bool {
+        let rate = f64::from(rate.get());
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled_at = now;
+        self.tokens >= 1.0
+    }
+
+    /// Check whether the bucket would be full if it were refilled now.
+    fn is_full(&self, rate: Option<NonZeroU32>, now: Instant) -> bool {
+        rate.is_none_or(|rate| {
+            let rate = f64::from(rate.get());
+            let elapsed = now
+                .saturating_duration_since(self.refilled_at)
+                .as_secs_f64();
+            self.tokens + elapsed * rate >= rate
+        })
+    }
+}
+
+/// The buckets of a single client.
+#[derive(Debug)]
+struct ClientBuckets {
+    all: TokenBucket,
+    reads: TokenBucket,
+    writes: TokenBucket,
+}
+
+/// Enforces the rate limits on the requests of all clients of a server.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    global: Mutex<TokenBucket>,
+    clients: Mutex<HashMap<IpAddr, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            global: Mutex::new(TokenBucket::new(Instant::now())),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for a request from the client, returning `false` if the request is over any
+    /// of the limits. A rejected request takes no token from any bucket.
+    pub(crate) fn try_acquire(&self, client: IpAddr, class: CommandClass, now: Instant) -> bool {
+        let limits = &self.limits;
+        let class_limit = match class {
+            CommandClass::Read => limits.per_client_reads,
+            CommandClass::Write => limits.per_client_writes,
+        };
+
+        let mut global = self.global.lock();
+        let mut clients = self.clients.lock();
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            clients.retain(|_, buckets| {
+                !(buckets.all.is_full(limits.per_client, now)
+                    && buckets.reads.is_full(limits.per_client_reads, now)
+                    && buckets.writes.is_full(limits.per_client_writes, now))
+            });
+        }
+        let buckets = clients.entry(client).or_insert_with(|| ClientBuckets {
+            all: TokenBucket::new(now),
+            reads: TokenBucket::new(now),
+            writes: TokenBucket::new(now),
+        });
+        let class_bucket = match class {
+            CommandClass::Read => &mut buckets.reads,
+            CommandClass::Write => &mut buckets.writes,
+        };
+
+        let mut limited: Vec<&mut TokenBucket> = Vec::with_capacity(3);
+        for (bucket, limit) in [
+            (&mut *global, limits.global),
+            (&mut buckets.all, limits.per_client),
+            (class_bucket, class_limit),
+        ] {
+            if let Some(limit) = limit {
+                if !bucket.refill(limit, now) {
+                    return false;
+                }
+                limited.push(bucket);
+            }
+        }
+        for bucket in limited {
+            bucket.tokens -= 1.0;
+        }
+        true
+    }
+}
+
+/// Spaces out the connections that a listener accepts, so they don't arrive faster than the limit
+/// once the burst of the bucket is used up.
+#[derive(Debug)]
+pub(crate) struct AcceptThrottle(TokenBucket);
+
+impl AcceptThrottle {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self(TokenBucket::new(now))
+    }
+
+    /// Take a token for the next connection, returning how long to wait before accepting it. The
+    /// bucket goes into debt while waiting, so the connections that wait are accepted in turn.
+    pub(crate) fn delay(&mut self, rate: NonZeroU32, now: Instant) -> Duration {
+        let bucket = &mut self.0;
+        bucket.refill(rate, now);
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / f64::from(rate.get()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const CLIENT_A: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const CLIENT_B: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    fn rate(n: u32) -> Option<NonZeroU32> {
+        NonZeroU32::new(n)
+    }
+
+    #[test]
+    fn requests_over_the_limit_are_rejected_until_refilled() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_client: rate(2),
+            ..RateLimits::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.try_acquire(CLIENT_A, CommandClass::Read, now));
+        assert!(limiter.try_acquire(CLIENT_A, CommandClass::Write, now));
+        assert!(!limiter.try_acquire(CLIENT_A, CommandClass::Read, now));
+        // Other clients have their own buckets
+        assert!(limiter.try_acquire(CLIENT_B, CommandClass::Read, now));
+
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.try_acquire(CLIENT_A, CommandClass::Read, later));
+        assert!(!limiter.try_acquire(CLIENT_A, CommandClass::Read, later));
+    }
+
+    #[test]
+    fn writes_are_limited_separately_from_reads() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_client_writes: rate(1),
+            ..RateLimits::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.try_acquire(CLIENT_A, CommandClass::Write, now));
+        assert!(!limiter.try_acquire(CLIENT_A, CommandClass::Write, now));
+        for _ in 0..100 {
+            assert!(limiter.try_acquire(CLIENT_A, CommandClass::Read, now));
+        }
+    }
+
+    #[test]
+    fn accepts_over_the_limit_are_delayed() {
+        let mut throttle = AcceptThrottle::new(Instant::now());
+        let rate = NonZeroU32::new(2).unwrap();
+        let now = Instant::now();
+        assert_eq!(Duration::ZERO, throttle.delay(rate, now));
+        assert_eq!(Duration::ZERO, throttle.delay(rate, now));
+        assert_eq!(Duration::from_millis(500), throttle.delay(rate, now));
+        assert_eq!(Duration::from_millis(1000), throttle.delay(rate, now));
+
+        // The waiting connections are accepted before the bucket refills
+        let later = now + Duration::from_millis(1000);
+        assert_eq!(Duration::from_millis(500), throttle.delay(rate, later));
+    }
+
+    #[test]
+    fn rejected_requests_take_no_tokens() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: rate(2),
+            per_client_writes: rate(1),
+            ..RateLimits::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.try_acquire(CLIENT_A, CommandClass::Write, now));
+        assert!(!limiter.try_acquire(CLIENT_A, CommandClass::Write, now));
+        // The rejected write didn't use up the global limit
+        assert!(limiter.try_acquire(CLIENT_B, CommandClass::Write, now));
+        assert!(!limiter.try_acquire(CLIENT_B, CommandClass::Read, now));
+    }
+}
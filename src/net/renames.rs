@@ -0,0 +1,112 @@
+//! Renaming and disabling commands, like the `rename-command` setting of Redis. A renamed command
+//! can only be run through its new name, and a disabled command can't be run at all, so
+//! deployments can hide commands that clients shouldn't use behind names that only operators
+//! know.
+
+use std::collections::{HashMap, HashSet};
+
+use bytes::Bytes;
+
+use super::{command, frame::Frame};
+
+/// The commands that were renamed or disabled.
+#[derive(Debug, Default)]
+pub(crate) struct CommandRenames {
+    /// The new names of the renamed commands, mapped to their original names.
+    aliases: HashMap<String, Bytes>,
+    /// The original names of the renamed and the disabled commands.
+    hidden: HashSet<String>,
+}
+
+impl CommandRenames {
+    /// Create the table from the map of original command names to their new names, where an
+    /// empty new name disables the command. Names are case-insensitive.
+    pub(crate) fn new(renames: &HashMap<String, String>) -> Result<Self, super::Error> {
+        let mut table = Self::default();
+        for (name, new_name) in renames {
+            let name = name.to_ascii_uppercase();
+            let new_name = new_name.to_ascii_uppercase();
+            if !new_name.is_empty()
+                && table
+                    .aliases
+                    .insert(new_name, Bytes::from(name.clone()))
+                    .is_some()
+            {
+                return Err(super::Error::InvalidConfig(
+                    "rename_commands must not give two commands the same name",
+                ));
+            }
+            table.hidden.insert(name);
+        }
+        Ok(table)
+    }
+
+    /// Replace the name of the command in the frame with the command's original name. Returns
+    /// an error if the command was disabled or if it's called by its original name after being
+    /// renamed.
+    pub(crate) fn resolve(&self, mut frame: Frame) -> Result<Frame, command::Error> {
+        if let Frame::Array(frames) = &mut frame {
+            if let Some(Frame::BulkString(name)) = frames.first_mut() {
+                let upper = String::from_utf8_lossy(name).to_ascii_uppercase();
+                match self.aliases.get(&upper) {
+                    Some(original) => *name = original.clone(),
+                    None if self.hidden.contains(&upper) => {
+                        return Err(command::Error::BadCommand(
+                            String::from_utf8_lossy(name).into(),
+                        ));
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &str) -> Frame {
+        Frame::Array(vec![
+            Frame::BulkString(Bytes::from(name.to_string())),
+            Frame::BulkString("key".into()),
+        ])
+    }
+
+    #[test]
+    fn renamed_and_disabled_commands_are_hidden() {
+        let renames = CommandRenames::new(&HashMap::from([
+            ("del".to_string(), "del-7f3a".to_string()),
+            ("EVAL".to_string(), String::new()),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            command("DEL"),
+            renames.resolve(command("DEL-7F3A")).unwrap()
+        );
+        assert_eq!(
+            command("DEL"),
+            renames.resolve(command("del-7f3a")).unwrap()
+        );
+        assert_eq!(command("GET"), renames.resolve(command("GET")).unwrap());
+        assert_eq!(
+            Err(command::Error::BadCommand("del".into())),
+            renames.resolve(command("del"))
+        );
+        assert_eq!(
+            Err(command::Error::BadCommand("EVAL".into())),
+            renames.resolve(command("EVAL"))
+        );
+    }
+
+    #[test]
+    fn commands_can_not_share_a_name() {
+        let renames = HashMap::from([
+            ("DEL".to_string(), "X".to_string()),
+            ("SET".to_string(), "x".to_string()),
+        ]);
+        assert!(CommandRenames::new(&renames).is_err());
+    }
+}
@@ -1,15 +1,31 @@
-//! Asynchronous server for the storage engine that communicates with RESP protocol.
-
-use std::{convert::TryFrom, future::Future, sync::Arc, time::Duration};
+//! Asynchronous server for the storage engine that communicates with RESP, memcached, or HTTP.
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use rand::Rng;
 use tokio::{
+    io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
     sync::{broadcast, mpsc, Semaphore},
     time,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use super::{command::Command, connection::Connection};
+use super::{
+    protocol::{Http, Memcached, Protocol, ProtocolKind, Resp},
+    proxy,
+    ratelimit::AcceptThrottle,
+    ListenerErrorPolicy, State,
+};
 use crate::{shutdown::Shutdown, storage::KeyValueStorage};
 
 /// Provide methods and hold states for a Redis server. The server will exist when `shutdown`
@@ -25,14 +41,29 @@ struct Listener<KV> {
     // Database handle
     storage: KV,
 
+    // States that are shared by all connections
+    state: Arc<State>,
+
     // The TCP socket for listening for inbound connection
     listener: TcpListener,
 
-    /// Min number of milliseconds to wait for when retrying to accept a new connection.
-    min_backoff_ms: u64,
+    // The protocol that is spoken by the clients of this listener
+    protocol: ProtocolKind,
 
-    /// Max number of milliseconds to wait for when retrying to accept a new connection.
-    max_backoff_ms: u64,
+    // Whether the connections start with a PROXY protocol header
+    proxy_protocol: bool,
+
+    // Max number of bytes in a value that is set by a memcached client
+    memcached_max_item_size: usize,
+
+    // What the server does once this listener gives up
+    error_policy: ListenerErrorPolicy,
+
+    // The limits that can be changed while the server is running.
+    limits: Arc<Limits>,
+
+    // Spaces out the new connections when their rate is limited.
+    accept_throttle: AcceptThrottle,
 
     // Semaphore with `MAX_CONNECTIONS`.
     //
@@ -63,17 +94,103 @@ struct Listener<KV> {
     shutdown_complete_tx: mpsc::Sender<()>,
 }
 
+/// The limits of a running server that can be changed without dropping connections.
+#[derive(Debug)]
+struct Limits {
+    /// Min number of milliseconds to wait for when retrying to accept a new connection.
+    min_backoff_ms: AtomicU64,
+
+    /// Max number of milliseconds to wait for when retrying to accept a new connection.
+    max_backoff_ms: AtomicU64,
+
+    /// Max number of consecutive failures to accept a new connection, or 0 for no limit.
+    max_accept_failures: AtomicU32,
+
+    /// The fraction of the backoff that is randomly added to or subtracted from it, stored as
+    /// the bits of an `f64`.
+    accept_jitter: AtomicU64,
+
+    /// Max number of concurrent connections that can be served by the server.
+    max_connections: AtomicUsize,
+
+    /// Max number of new connections that are accepted per second, or 0 for no limit.
+    max_accepts_per_sec: AtomicU32,
+
+    /// Whether the connections over the max number of connections are rejected.
+    reject_when_full: AtomicBool,
+}
+
+/// A handle for changing the limits of a running server.
+#[derive(Debug, Clone)]
+pub struct LimitsHandle {
+    limits: Arc<Limits>,
+    limit_connections: Arc<Semaphore>,
+}
+
+impl LimitsHandle {
+    /// Apply the limits from the given configuration. The address that the server listens on
+    /// and its protocol can't be changed while it's running and are ignored. Nothing is applied
+    /// if the configuration is invalid.
+    ///
+    /// Lowering the max number of connections doesn't close any connection, the server stops
+    /// accepting new ones until enough connections have been closed.
+    pub fn reload(&self, conf: &super::Config) -> Result<(), super::Error> {
+        conf.validate()?;
+        info!(?conf, "reloading server limits");
+        self.limits
+            .min_backoff_ms
+            .store(conf.min_backoff_ms, Ordering::Relaxed);
+        self.limits
+            .max_backoff_ms
+            .store(conf.max_backoff_ms, Ordering::Relaxed);
+        self.limits
+            .max_accept_failures
+            .store(conf.max_accept_failures, Ordering::Relaxed);
+        self.limits
+            .accept_jitter
+            .store(conf.accept_jitter.to_bits(), Ordering::Relaxed);
+        self.limits.max_accepts_per_sec.store(
+            conf.max_accepts_per_sec.map_or(0, NonZeroU32::get),
+            Ordering::Relaxed,
+        );
+        self.limits
+            .reject_when_full
+            .store(conf.reject_when_full, Ordering::Relaxed);
+        let prev = self
+            .limits
+            .max_connections
+            .swap(conf.max_connections, Ordering::Relaxed);
+        if conf.max_connections > prev {
+            self.limit_connections
+                .add_permits(conf.max_connections - prev);
+        } else if conf.max_connections < prev {
+            // Take away the extra permits as soon as connections give them back
+            let n = u32::try_from(prev - conf.max_connections).unwrap_or(u32::MAX);
+            let limit_connections = Arc::clone(&self.limit_connections);
+            tokio::spawn(async move {
+                if let Ok(permits) = limit_connections.acquire_many_owned(n).await {
+                    permits.forget();
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
 /// Reads client requests and applies those to the storage.
-struct Handler<KV> {
+struct Handler<KV, P> {
     // Database handle.
     storage: KV,
 
-    // Writes and reads frame.
-    connection: Connection,
+    // States that are shared by all connections.
+    state: Arc<State>,
 
-    // The semaphore that granted the permit for this handler.
-    // The handler is in charge of releasing its permit.
-    limit_connections: Arc<Semaphore>,
+    // Reads requests and writes responses.
+    protocol: P,
+
+    // The permit that was granted for this handler, which is released
+    // when the handler is dropped.
+    _permit: ConnectionPermit,
 
     // Receives shut down signal.
     shutdown: Shutdown,
@@ -85,6 +202,17 @@ struct Handler<KV> {
 impl<KV, S> Server<KV, S> {
     /// Runs the server.
     pub async fn new(storage: KV, shutdown: S, conf: super::Config) -> Result<Self, super::Error> {
+        let state = State::new(&conf)?;
+        Self::with_state(storage, shutdown, conf, state).await
+    }
+
+    /// Create a server whose connections share the given states.
+    pub(crate) async fn with_state(
+        storage: KV,
+        shutdown: S,
+        conf: super::Config,
+        state: State,
+    ) -> Result<Self, super::Error> {
         info!(?conf, "starting server");
         // Ignoring the broadcast received because one can be created by
         // calling `subscribe()` on the `Sender`
@@ -93,9 +221,24 @@ impl<KV, S> Server<KV, S> {
 
         let listener = Listener {
             storage,
+            state: Arc::new(state),
             listener: TcpListener::bind(&format!("{}:{}", conf.host, conf.port)).await?,
-            min_backoff_ms: conf.min_backoff_ms,
-            max_backoff_ms: conf.max_backoff_ms,
+            protocol: conf.protocol,
+            proxy_protocol: conf.proxy_protocol,
+            memcached_max_item_size: conf.memcached_max_item_size,
+            error_policy: conf.listener_error_policy,
+            limits: Arc::new(Limits {
+                min_backoff_ms: AtomicU64::new(conf.min_backoff_ms),
+                max_backoff_ms: AtomicU64::new(conf.max_backoff_ms),
+                max_accept_failures: AtomicU32::new(conf.max_accept_failures),
+                accept_jitter: AtomicU64::new(conf.accept_jitter.to_bits()),
+                max_connections: AtomicUsize::new(conf.max_connections),
+                max_accepts_per_sec: AtomicU32::new(
+                    conf.max_accepts_per_sec.map_or(0, NonZeroU32::get),
+                ),
+                reject_when_full: AtomicBool::new(conf.reject_when_full),
+            }),
+            accept_throttle: AcceptThrottle::new(Instant::now()),
             limit_connections: Arc::new(Semaphore::new(conf.max_connections)),
             notify_shutdown,
             shutdown_complete_rx,
@@ -104,6 +247,26 @@ impl<KV, S> Server<KV, S> {
 
         Ok(Self { listener, shutdown })
     }
+
+    /// Get the address that the server listens on, e.g. to find out the port that was picked
+    /// when the server was configured to listen on port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, super::Error> {
+        Ok(self.listener.listener.local_addr()?)
+    }
+
+    /// Get the states that are shared by the connections, e.g. for reading the queue depths of
+    /// the lanes while the server is running.
+    pub fn state(&self) -> Arc<State> {
+        Arc::clone(&self.listener.state)
+    }
+
+    /// Get a handle for changing the limits of the server while it's running.
+    pub fn limits_handle(&self) -> LimitsHandle {
+        LimitsHandle {
+            limits: Arc::clone(&self.listener.limits),
+            limit_connections: Arc::clone(&self.listener.limit_connections),
+        }
+    }
 }
 
 impl<KV, S> Server<KV, S>
@@ -114,6 +277,9 @@ where
     /// Runs the server that exits when `shutdown` finishes, or when there's
     /// an error.
     pub async fn run(mut self) {
+        let shutdown = self.shutdown;
+        tokio::pin!(shutdown);
+
         // Concurrently run the tasks and blocks the current task until
         // one of the running tasks finishes. The block that is associated
         // with the task gets to run, when the task is the first to finish.
@@ -122,13 +288,18 @@ where
             result = self.listener.listen() => {
                 if let Err(err) = result {
                     // The server has been failing to accept inbound connections
-                    // for multiple times, so it's giving up and shutting down.
-                    // Error occured while handling individual connection don't
-                    // propagate further.
+                    // for multiple times, so it's giving up. Error occured while
+                    // handling individual connection don't propagate further.
                     error!(cause = %err, "failed to accept");
+                    self.listener.state.set_listener_down();
+                    if self.listener.error_policy == ListenerErrorPolicy::ServeExisting {
+                        warn!("no longer accepting connections, serving the existing ones");
+                        (&mut shutdown).await;
+                    }
                 }
+                info!("shutting down");
             }
-            _ = self.shutdown => {
+            _ = &mut shutdown => {
                 info!("shutting down");
             }
         }
@@ -151,27 +322,40 @@ impl<KV> Listener<KV> {
     /// Accepts a new connection.
     ///
     /// Returns the a [`TcpStream`] on success. Retries with an exponential
-    /// backoff strategy when there's an error. If the backoff time passes
-    /// to maximum allowed time, returns an error.
+    /// backoff strategy when there's an error, up to the maximum backoff time.
+    /// If accepting fails too many times in a row, returns an error.
     ///
     /// [`TcpStream`]: tokio::net::TcpStream
     async fn accept(&mut self) -> Result<TcpStream, super::Error> {
-        let mut backoff = self.min_backoff_ms;
+        let mut backoff = self.limits.min_backoff_ms.load(Ordering::Relaxed);
+        let mut failures = 0;
         loop {
             match self.listener.accept().await {
                 Ok((socket, _)) => return Ok(socket),
                 Err(err) => {
-                    if backoff > self.max_backoff_ms {
+                    self.state.record_accept_failure();
+                    failures += 1;
+                    let max_failures = self.limits.max_accept_failures.load(Ordering::Relaxed);
+                    if max_failures != 0 && failures >= max_failures {
                         return Err(err.into());
                     }
+                    warn!(cause = %err, failures, "failed to accept, retrying");
                 }
             }
 
-            // Wait for `backoff` milliseconds
-            time::sleep(Duration::from_millis(backoff)).await;
+            // Wait for `backoff` milliseconds, spread randomly by the jitter
+            backoff = backoff.min(self.limits.max_backoff_ms.load(Ordering::Relaxed));
+            let jitter = f64::from_bits(self.limits.accept_jitter.load(Ordering::Relaxed));
+            let delay = Duration::from_millis(backoff);
+            let delay = if jitter > 0.0 {
+                delay.mul_f64(rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+            } else {
+                delay
+            };
+            time::sleep(delay).await;
 
             // Doubling the backoff time
-            backoff <<= 1;
+            backoff = backoff.saturating_mul(2);
         }
     }
 }
@@ -184,42 +368,135 @@ where
         info!("listening for new connections");
 
         loop {
-            // Wait for a permit to become available.
+            // Wait for a permit to become available, unless the connections over
+            // the limit are rejected.
             //
             // For convenient, the handle is bounded to the semaphore's lifetime
             // and when it gets dropped, it decrements the count. Because we're
             // releasing the permit in a different task from the one we acquired it
             // in, `forget()` is use to drop the semaphore handle without releasing
             // the permit at the end of this scope.
-            self.limit_connections.acquire().await.unwrap().forget();
+            let reject_when_full = self.limits.reject_when_full.load(Ordering::Relaxed);
+            if !reject_when_full {
+                self.limit_connections.acquire().await.unwrap().forget();
+            }
+
+            // Space out the new connections when their rate is limited
+            let max_accepts = self.limits.max_accepts_per_sec.load(Ordering::Relaxed);
+            if let Some(rate) = NonZeroU32::new(max_accepts) {
+                let delay = self.accept_throttle.delay(rate, Instant::now());
+                if !delay.is_zero() {
+                    time::sleep(delay).await;
+                }
+            }
 
             // Accepts a new connection and retries on error. If this function
             // returns an error, it means that the server could not accept any
             // new connection and it is aborting.
             let socket = self.accept().await?;
+            if reject_when_full {
+                match self.limit_connections.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => {
+                        self.reject(socket);
+                        continue;
+                    }
+                }
+            }
+            self.spawn_handler(socket);
+        }
+    }
 
-            // Creating the handler's state for managing the new connection
-            let handler = Handler {
-                storage: self.storage.clone(),
-                connection: Connection::new(socket),
-                limit_connections: Arc::clone(&self.limit_connections),
-                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
-                _shutdown_complete: self.shutdown_complete_tx.clone(),
+    /// Tell a client that the server has too many connections, in the protocol of the listener,
+    /// then close its connection.
+    fn reject(&self, mut socket: TcpStream) {
+        self.state.record_rejected_connection();
+        let reply: &'static [u8] = match self.protocol {
+            ProtocolKind::Resp => b"-ERR max number of clients reached\r\n",
+            ProtocolKind::Memcached => b"SERVER_ERROR max number of clients reached\r\n",
+            ProtocolKind::Http => {
+                b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(err) = socket.write_all(reply).await {
+                debug!(cause = ?err, "failed to reject connection");
+            }
+            let _ = socket.shutdown().await;
+        });
+    }
+
+    /// Handle a new connection in a new task using the protocol of the listener. The PROXY
+    /// protocol header is read in the task, so a slow client doesn't hold up the listener.
+    fn spawn_handler(&self, mut socket: TcpStream) {
+        // Creating the handler's state for managing the new connection
+        let handler = Handler {
+            storage: self.storage.for_client(),
+            state: Arc::clone(&self.state),
+            protocol: (),
+            _permit: ConnectionPermit(Arc::clone(&self.limit_connections)),
+            shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+            _shutdown_complete: self.shutdown_complete_tx.clone(),
+        };
+        let kind = self.protocol;
+        let proxy_protocol = self.proxy_protocol;
+        let max_item_size = self.memcached_max_item_size;
+
+        // Handle the connection in a new task
+        tokio::spawn(async move {
+            let peer = if proxy_protocol {
+                match proxy::read_header(&mut socket).await {
+                    // The proxy's own connections are served as they are
+                    Ok(peer) => peer.or_else(|| socket.peer_addr().ok()),
+                    Err(err) => {
+                        error!(cause=?err, "invalid PROXY protocol header");
+                        return;
+                    }
+                }
+            } else {
+                socket.peer_addr().ok()
             };
 
-            // Handle the connection in a new task
-            tokio::spawn(async move {
-                if let Err(err) = handler.run().await {
-                    error!(cause=?err, "connection error");
+            // Serve the connection with the protocol of the listener
+            let result = match kind {
+                ProtocolKind::Resp => {
+                    let state = Arc::clone(&handler.state);
+                    let protocol = Resp::new(socket, peer, state);
+                    handler.with_protocol(protocol).run().await
                 }
-            });
+                ProtocolKind::Memcached => {
+                    handler
+                        .with_protocol(Memcached::new(socket, peer, max_item_size))
+                        .run()
+                        .await
+                }
+                ProtocolKind::Http => handler.with_protocol(Http::new(socket, peer)).run().await,
+            };
+            if let Err(err) = result {
+                error!(cause=?err, "connection error");
+            }
+        });
+    }
+}
+
+impl<KV> Handler<KV, ()> {
+    /// Serve the connection of the handler with the given protocol.
+    fn with_protocol<P>(self, protocol: P) -> Handler<KV, P> {
+        Handler {
+            storage: self.storage,
+            state: self.state,
+            protocol,
+            _permit: self._permit,
+            shutdown: self.shutdown,
+            _shutdown_complete: self._shutdown_complete,
         }
     }
 }
 
-impl<KV> Handler<KV>
+impl<KV, P> Handler<KV, P>
 where
     KV: KeyValueStorage,
+    P: Protocol,
 {
     /// Process a single connection.
     ///
@@ -230,40 +507,42 @@ where
     /// it reaches a safe state, at which point it is terminated.
     #[tracing::instrument(skip(self))]
     async fn run(mut self) -> Result<(), super::Error> {
-        // Keeps ingesting frames when the server is still running
+        // Keeps ingesting requests when the server is still running
         while !self.shutdown.is_shutdown() {
-            // Awaiting for a shutdown event or a new frame
-            let maybe_frame = tokio::select! {
-                res = self.connection.read_frame() => res?,
+            // Awaiting for a shutdown event or a new request
+            let maybe_request = tokio::select! {
+                res = self.protocol.read_request() => res?,
                 _ = self.shutdown.recv() => {
                     return Ok(());
                 }
             };
 
-            // No frame left means the client closed the connection, so we can
+            // No request left means the client closed the connection, so we can
             // return with no error
-            let frame = match maybe_frame {
-                Some(frame) => frame,
+            let request = match maybe_request {
+                Some(request) => request,
                 None => return Ok(()),
             };
-
-            // Try to parse a command out of the frame
-            let cmd = Command::try_from(frame)?;
-            debug!(?cmd);
+            debug!(?request);
 
             let storage = self.storage.clone();
-            cmd.apply(storage, &mut self.connection, &mut self.shutdown)
+            self.protocol
+                .apply(request, storage, &self.state, &mut self.shutdown)
                 .await?;
         }
         Ok(())
     }
 }
 
-impl<KV> Drop for Handler<KV> {
+/// A permit of the semaphore that limits the number of connections, which was
+/// taken with `forget()` when the connection was accepted.
+struct ConnectionPermit(Arc<Semaphore>);
+
+impl Drop for ConnectionPermit {
     fn drop(&mut self) {
         // Releases the permit that was granted for this handler. Performing this
         // in the `Drop` implementation ensures that the permit is always
         // automatically returned when the handler finishes
-        self.limit_connections.add_permits(1);
+        self.0.add_permits(1);
     }
 }
@@ -0,0 +1,368 @@
+//! States that are shared by all connections of a server.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use super::{
+    audit::{AuditLog, AuditRecord},
+    command::CommandClass,
+    lanes::{LaneGuard, Lanes, LanesStats},
+    pause::ClientPause,
+    pubsub::PubSub,
+    ratelimit::RateLimiter,
+    renames::CommandRenames,
+    Config,
+};
+
+/// The IDs and wake-up handles of the clients that are waiting on a key.
+type WaitQueue = VecDeque<(u64, Arc<Notify>)>;
+
+/// Holds the server's states that must outlive a single connection, such as caches that are
+/// populated by one client and used by another.
+#[derive(Debug, Default)]
+pub struct State {
+    /// Scripts that were run by EVAL, keyed by their SHA1 digests.
+    #[cfg(feature = "scripting")]
+    scripts: Mutex<HashMap<String, Bytes>>,
+
+    /// How long a script can run before it's aborted.
+    #[cfg(feature = "scripting")]
+    script_time_limit: std::time::Duration,
+
+    /// Clients that are blocked waiting for data on a key, in the order they started waiting,
+    /// keyed by the names of the database and the key.
+    waiters: Mutex<HashMap<(Bytes, Bytes), WaitQueue>>,
+
+    /// The ID that is given to the next waiter.
+    next_waiter_id: AtomicU64,
+
+    /// The log of the writes made by clients, if auditing is enabled. Servers that run alongside
+    /// each other share it.
+    audit_log: Option<Arc<AuditLog>>,
+
+    /// The limits on the rate of the clients' requests, if any limit is set. Servers that run
+    /// alongside each other share it.
+    rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// The lanes that bound the number of reads and writes that run at once. Commands aren't
+    /// bounded when this isn't set.
+    lanes: Option<Lanes>,
+
+    /// The commands that were renamed or disabled.
+    renames: Arc<CommandRenames>,
+
+    /// The connections that subscribed to channels or that are monitoring commands.
+    pubsub: PubSub,
+
+    /// The pause of the clients' commands, which is set by CLIENT PAUSE. Servers that run
+    /// alongside each other share it.
+    pause: Arc<ClientPause>,
+
+    /// The number of times the listener failed to accept a new connection.
+    accept_failures: AtomicU64,
+
+    /// Whether the listener gave up accepting new connections.
+    listener_down: AtomicBool,
+
+    /// The number of connections that were rejected because the server had too many.
+    rejected_connections: AtomicU64,
+}
+
+#[cfg(feature = "scripting")]
+impl State {
+    /// Cache the script and return its SHA1 digest.
+    pub(crate) fn cache_script(&self, script: Bytes) -> String {
+        let sha = sha1_smol::Sha1::from(&script).digest().to_string();
+        self.scripts.lock().insert(sha.clone(), script);
+        sha
+    }
+
+    /// Get the script with the given SHA1 digest, if it was cached.
+    pub(crate) fn get_script(&self, sha: &str) -> Option<Bytes> {
+        self.scripts.lock().get(&sha.to_lowercase()).cloned()
+    }
+
+    /// Get how long a script can run before it's aborted.
+    pub(crate) fn script_time_limit(&self) -> std::time::Duration {
+        self.script_time_limit
+    }
+}
+
+impl State {
+    /// Create the states of a server with the given configuration, opening its audit log if
+    /// auditing is enabled.
+    pub(crate) fn new(conf: &Config) -> Result<Self, super::Error> {
+        let audit_log = match &conf.audit_log {
+            Some(path) => Some(Arc::new(AuditLog::open(
+                path,
+                conf.audit_log_max_size,
+                conf.audit_log_max_files,
+            )?)),
+            None => None,
+        };
+        let rate_limiter = conf
+            .rate_limit
+            .is_enabled()
+            .then(|| Arc::new(RateLimiter::new(conf.rate_limit)));
+        Ok(Self {
+            audit_log,
+            rate_limiter,
+            ..Self::unshared(conf)?
+        })
+    }
+
+    /// Create the states of a server that runs alongside the server with the given states. The
+    /// clients of both servers are held by the same pause, count against the same rate limits,
+    /// and are recorded in the same audit log, so they can't get around any of them by
+    /// connecting to the other server.
+    pub(crate) fn alongside(conf: &Config, other: &State) -> Result<Self, super::Error> {
+        Ok(Self {
+            audit_log: other.audit_log.clone(),
+            rate_limiter: other.rate_limiter.clone(),
+            pause: Arc::clone(&other.pause),
+            ..Self::unshared(conf)?
+        })
+    }
+
+    /// Create the states that belong to a single server.
+    fn unshared(conf: &Config) -> Result<Self, super::Error> {
+        Ok(Self {
+            lanes: Some(Lanes::new(
+                conf.max_concurrent_reads,
+                conf.max_concurrent_writes,
+            )),
+            renames: Arc::new(CommandRenames::new(&conf.rename_commands)?),
+            #[cfg(feature = "scripting")]
+            script_time_limit: std::time::Duration::from_millis(conf.script_time_limit_ms),
+            ..Self::default()
+        })
+    }
+
+    /// Get the audit log, if auditing is enabled.
+    pub(crate) fn audit_log(&self) -> Option<&Arc<AuditLog>> {
+        self.audit_log.as_ref()
+    }
+
+    /// Get the commands that were renamed or disabled.
+    pub(crate) fn renames(&self) -> &Arc<CommandRenames> {
+        &self.renames
+    }
+
+    /// Get the connections that listen for published messages or monitored commands.
+    pub(crate) fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    /// Get the pause of the clients' commands.
+    pub(crate) fn pause(&self) -> &ClientPause {
+        &self.pause
+    }
+
+    /// Take a token for a request of the given class from the client at `peer`, returning
+    /// `false` if the request is over the rate limits. Clients without an address aren't
+    /// limited.
+    pub(crate) fn within_rate_limits(&self, peer: Option<SocketAddr>, class: CommandClass) -> bool {
+        match (&self.rate_limiter, peer) {
+            (Some(rate_limiter), Some(peer)) => {
+                rate_limiter.try_acquire(peer.ip(), class, Instant::now())
+            }
+            _ => true,
+        }
+    }
+
+    /// Record a write of the client at `peer` to the given keys in the audit log, if auditing is
+    /// enabled.
+    pub(crate) fn audit_write<'a, K>(
+        &self,
+        peer: Option<SocketAddr>,
+        command: &str,
+        keys: K,
+    ) -> io::Result<()>
+    where
+        K: IntoIterator<Item = &'a [u8]>,
+    {
+        match &self.audit_log {
+            Some(audit_log) => {
+                let client = peer.map(|addr| addr.to_string()).unwrap_or_default();
+                audit_log.append(&AuditRecord::new(&client, command, keys))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until a command of the given class can run in its lane, returning a guard that must
+    /// be kept while the command runs.
+    pub(crate) async fn enter_lane(&self, class: CommandClass) -> Option<LaneGuard<'_>> {
+        match &self.lanes {
+            Some(lanes) => Some(lanes.enter(class).await),
+            None => None,
+        }
+    }
+
+    /// Count a failure of the listener to accept a new connection.
+    pub(crate) fn record_accept_failure(&self) {
+        self.accept_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection that was rejected because the server had too many.
+    pub(crate) fn record_rejected_connection(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Mark the listener as having given up accepting new connections.
+    pub(crate) fn set_listener_down(&self) {
+        self.listener_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Get the number of times the listener failed to accept a new connection.
+    pub fn accept_failures(&self) -> u64 {
+        self.accept_failures.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of connections that were rejected because the server had too many.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// Return whether the listener gave up accepting new connections, which leaves the server
+    /// serving only its existing connections.
+    pub fn listener_down(&self) -> bool {
+        self.listener_down.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of commands that are waiting and running in the read lane and in the write
+    /// lane.
+    pub fn lanes_stats(&self) -> LanesStats {
+        self.lanes.as_ref().map(Lanes::stats).unwrap_or_default()
+    }
+
+    /// Register a waiter for the given keys of a database. The waiter is placed behind the
+    /// waiters that were registered before it, and is unregistered when dropped.
+    pub(crate) fn register_waiter(self: &Arc<Self>, database: &Bytes, keys: &[Bytes]) -> Waiter {
+        let id = self.next_waiter_id.fetch_add(1, Ordering::Relaxed);
+        let notify = Arc::new(Notify::new());
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| (database.clone(), key.clone()))
+            .collect();
+        let mut waiters = self.waiters.lock();
+        for key in &keys {
+            waiters
+                .entry(key.clone())
+                .or_default()
+                .push_back((id, Arc::clone(&notify)));
+        }
+        Waiter {
+            id,
+            keys,
+            notify,
+            state: Arc::clone(self),
+        }
+    }
+
+    /// Wake at most `n` of the longest-waiting clients that are blocked on the key of a database.
+    /// Woken waiters are removed from the key's queue.
+    pub(crate) fn wake_waiters(&self, database: &Bytes, key: &Bytes, n: usize) {
+        let key = (database.clone(), key.clone());
+        let mut waiters = self.waiters.lock();
+        let Some(queue) = waiters.get_mut(&key) else {
+            return;
+        };
+        for (_, notify) in queue.drain(..n.min(queue.len())) {
+            notify.notify_one();
+        }
+        if queue.is_empty() {
+            waiters.remove(&key);
+        }
+    }
+}
+
+/// A client that is blocked waiting for data on a set of keys.
+#[derive(Debug)]
+pub(crate) struct Waiter {
+    id: u64,
+    keys: Vec<(Bytes, Bytes)>,
+    notify: Arc<Notify>,
+    state: Arc<State>,
+}
+
+impl Waiter {
+    /// Wait until the waiter is woken up by a write to one of its keys. This returns immediately
+    /// if the waiter was woken before being awaited.
+    pub(crate) async fn wait(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let mut waiters = self.state.waiters.lock();
+        for key in &self.keys {
+            if let Some(queue) = waiters.get_mut(key) {
+                queue.retain(|(id, _)| *id != self.id);
+                if queue.is_empty() {
+                    waiters.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wake_longest_waiting_client() {
+        let state = Arc::new(State::default());
+        let (db, key) = (Bytes::from("default"), Bytes::from("key"));
+        let first = state.register_waiter(&db, std::slice::from_ref(&key));
+        let second = state.register_waiter(&db, std::slice::from_ref(&key));
+
+        state.wake_waiters(&db, &key, 1);
+        tokio::time::timeout(Duration::from_millis(100), first.wait())
+            .await
+            .expect("first waiter must be woken");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), second.wait())
+                .await
+                .is_err()
+        );
+
+        drop(second);
+        assert!(state.waiters.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn wake_only_clients_waiting_in_the_same_database() {
+        let state = Arc::new(State::default());
+        let (db1, db2, key) = (Bytes::from("db1"), Bytes::from("db2"), Bytes::from("key"));
+        let waiter1 = state.register_waiter(&db1, std::slice::from_ref(&key));
+        let waiter2 = state.register_waiter(&db2, std::slice::from_ref(&key));
+
+        state.wake_waiters(&db2, &key, 1);
+        tokio::time::timeout(Duration::from_millis(100), waiter2.wait())
+            .await
+            .expect("waiter in the database of the key must be woken");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), waiter1.wait())
+                .await
+                .is_err()
+        );
+        assert_eq!(1, state.waiters.lock().len());
+    }
+}
@@ -1,21 +1,289 @@
 //! Define the interface for a storage engine and different implementations of that interface.
 
 pub mod bitcask;
+pub mod databases;
+pub mod mirror;
+
+use std::{
+    fmt, io,
+    ops::Bound,
+    time::{Duration, SystemTime},
+};
 
 use bytes::Bytes;
 
+/// The change that a read-modify-write operation makes to the value of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// Leave the key unchanged.
+    Keep,
+    /// Set the key to the given value.
+    Set(Bytes),
+    /// Delete the key.
+    Delete,
+}
+
+/// The value of a key that's given by [`KeyValueStorage::get_stream`].
+pub enum ValueStream {
+    /// The whole value, which was read at once.
+    Whole(Bytes),
+    /// A value that is read piece by piece.
+    Chunked {
+        /// The number of bytes of the value.
+        len: u64,
+        /// The reader over the bytes of the value.
+        reader: Box<dyn io::Read + Send>,
+    },
+}
+
+impl fmt::Debug for ValueStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Whole(value) => f.debug_tuple("Whole").field(value).finish(),
+            Self::Chunked { len, .. } => f.debug_struct("Chunked").field("len", len).finish(),
+        }
+    }
+}
+
+/// The outcome of copying or renaming a key.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transfer {
+    /// The key was copied or renamed.
+    Done,
+    /// The source key does not exist.
+    SourceNotFound,
+    /// The destination key exists and was not allowed to be replaced.
+    DestinationExists,
+}
+
+/// The usage of one of the data files of a storage, for planning its capacity and tuning its
+/// merges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStats {
+    /// The ID of the data file.
+    pub fileid: u64,
+    /// The number of bytes of the data file on disk.
+    pub size: u64,
+    /// The number of keys whose latest entries are in the data file.
+    pub live_keys: u64,
+    /// The number of entries in the data file that were overwritten or deleted.
+    pub dead_keys: u64,
+    /// The number of bytes taken by the dead entries.
+    pub dead_bytes: u64,
+    /// The fraction of dead keys to all keys in the data file.
+    pub fragmentation: f64,
+    /// The time at which the data file was last written.
+    pub modified: SystemTime,
+    /// Whether the data file has a hint file, which is only written by merges.
+    pub has_hintfile: bool,
+}
+
+/// How long a write waits for its data to reach the disk.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Durability {
+    /// Return once the data is written, leaving it to the engine to synchronize it to disk.
+    #[default]
+    None,
+    /// Wait for the next disk synchronization, which is shared with the other writes that are
+    /// waiting at the same time.
+    Batched,
+    /// Synchronize the data to disk before returning.
+    Immediate,
+}
+
+/// Operations that can be made on a storage while holding exclusive write access to it, so
+/// that no other write can happen in between them.
+pub trait Transaction {
+    /// Error type of the underlying engine
+    type Error;
+
+    /// Set the value of a key and overwrite any existing value at that key.
+    fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error>;
+
+    /// Set the value of a key that expires at the given time, and overwrite any existing value
+    /// at that key. The key never expires if `expires_at` is `None`.
+    fn set_with_expiry(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the value of a key, if it exists. Otherwise, return `None`.
+    fn get(&mut self, key: Bytes) -> Result<Option<Bytes>, Self::Error>;
+
+    /// Get the time at which a key expires. Returns `None` if the key does not exist or has no
+    /// expiry.
+    fn get_expiry(&mut self, key: Bytes) -> Result<Option<SystemTime>, Self::Error>;
+
+    /// Delete a key and return `true`, if it exists. Otherwise, return `false`.
+    fn del(&mut self, key: Bytes) -> Result<bool, Self::Error>;
+
+    /// Delete a key and return `true`, if it exists. Otherwise, return `false`. Implementations
+    /// can hide the key right away and write the deletion later, so a crash may bring the key
+    /// back. Default to [`Transaction::del`].
+    fn unlink(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+        self.del(key)
+    }
+
+    /// Copy the value and the expiry of `src` to `dst`. The value at `dst` is only overwritten
+    /// when `replace` is `true`.
+    fn copy(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        let Some(value) = self.get(src.clone())? else {
+            return Ok(Transfer::SourceNotFound);
+        };
+        if !replace && self.get(dst.clone())?.is_some() {
+            return Ok(Transfer::DestinationExists);
+        }
+        let expires_at = self.get_expiry(src)?;
+        self.set_with_expiry(dst, value, expires_at)?;
+        Ok(Transfer::Done)
+    }
+
+    /// Rename `src` to `dst`, keeping its expiry. The value at `dst` is only overwritten when
+    /// `replace` is `true`. The new key and the deletion of the old key are made through the
+    /// transaction, so they're only written together by transactions that stage their writes.
+    fn rename(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        if src == dst {
+            return Ok(match self.get(src)? {
+                None => Transfer::SourceNotFound,
+                Some(_) if replace => Transfer::Done,
+                Some(_) => Transfer::DestinationExists,
+            });
+        }
+        let transfer = self.copy(src.clone(), dst, replace)?;
+        if transfer == Transfer::Done {
+            self.del(src)?;
+        }
+        Ok(transfer)
+    }
+}
+
 /// A basic interface for a thread-safe key-value store that ensure consistent access to shared
 /// data from multiple different threads.
 pub trait KeyValueStorage: Clone + Send + 'static {
     /// Error type of the underlying engine
     type Error: std::error::Error + Send + Sync;
 
+    /// Return a copy of the storage for a single client that uses it sequentially, such as a
+    /// network connection. Implementations can use this to give out resources that don't have to
+    /// be shared with other clients. Default to a clone of the storage.
+    fn for_client(&self) -> Self {
+        self.clone()
+    }
+
+    /// Switch the client to the database with the given name, for storages that host several
+    /// databases. Returns `false` if there's no such database. Default to hosting only the
+    /// default database.
+    fn select(&self, name: &[u8]) -> Result<bool, Self::Error> {
+        Ok(databases::is_default(name))
+    }
+
+    /// Get the name of the database that the client selected, which tells apart the keys that
+    /// have the same name in different databases. Default to the default database.
+    fn selected_database(&self) -> Bytes {
+        Bytes::from(databases::DEFAULT_DATABASE)
+    }
+
     /// Set the value of a key and overwrite any existing value at that key.
     fn set(&self, key: Bytes, value: Bytes) -> Result<(), Self::Error>;
 
+    /// Set the value of a key that expires at the given time, and overwrite any existing value
+    /// at that key. The key never expires if `expires_at` is `None`.
+    fn set_with_expiry(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error>;
+
     /// Get the value of a key, if it exists. Otherwise, return `None`.
     fn get(&self, key: Bytes) -> Result<Option<Bytes>, Self::Error>;
 
+    /// Get the value of a key, if it exists. Otherwise, return `None`. Implementations that store
+    /// large values in pieces can give a reader that reads the pieces as they're needed, so the
+    /// whole value is never held in memory. Default to reading the whole value with
+    /// [`KeyValueStorage::get`].
+    fn get_stream(&self, key: Bytes) -> Result<Option<ValueStream>, Self::Error> {
+        Ok(self.get(key)?.map(ValueStream::Whole))
+    }
+
+    /// Get the time at which a key expires. Returns `None` if the key does not exist or has no
+    /// expiry.
+    fn get_expiry(&self, key: Bytes) -> Result<Option<SystemTime>, Self::Error>;
+
     /// Delete a key and return `true`, if it exists. Otherwise, return `false`.
     fn del(&self, key: Bytes) -> Result<bool, Self::Error>;
+
+    /// Wait until the writes made before the call are on disk as required by the given
+    /// durability level.
+    fn make_durable(&self, durability: Durability) -> Result<(), Self::Error>;
+
+    /// Get the approximate time since a key was last read or written. Returns `None` if the key
+    /// does not exist.
+    fn idle_time(&self, key: Bytes) -> Result<Option<Duration>, Self::Error>;
+
+    /// Get the approximate frequency of accesses to a key, as a counter from 0 to 255 that grows
+    /// logarithmically with the accesses and decays while the key is idle. Returns `None` if the
+    /// key does not exist. Fails if the storage doesn't track access frequencies.
+    fn access_frequency(&self, key: Bytes) -> Result<Option<u8>, Self::Error>;
+
+    /// Get up to `n` of the most read and written keys with their estimated number of accesses,
+    /// starting with the most accessed key. Returns nothing if the storage doesn't track them.
+    fn hot_keys(&self, n: usize) -> Result<Vec<(Bytes, u64)>, Self::Error>;
+
+    /// Pause or resume the maintenance that the storage runs in the background, such as merges
+    /// and disk synchronizations. Default to storages without background maintenance.
+    fn pause_maintenance(&self, _paused: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Return whether the background maintenance is paused. Default to storages without
+    /// background maintenance.
+    fn maintenance_paused(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Get the usage of each data file, ordered by file ID. Default to storages without data
+    /// files.
+    fn file_stats(&self) -> Result<Vec<FileStats>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Atomically read the value of a key, if it exists, and apply the change returned by `f`.
+    /// No other writes can happen between the read and the write. The second value returned by
+    /// `f` is given back to the caller. Setting a new value keeps the key's expiry.
+    fn update<F, T>(&self, key: Bytes, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(Option<Bytes>) -> (Update, T) + Send + 'static;
+
+    /// Atomically copy the value and the expiry of `src` to `dst`. The value at `dst` is only
+    /// overwritten when `replace` is `true`.
+    fn copy(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        self.atomically(move |txn| txn.copy(src, dst, replace))
+    }
+
+    /// Atomically rename `src` to `dst`, keeping its expiry. The value at `dst` is only
+    /// overwritten when `replace` is `true`.
+    fn rename(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        self.atomically(move |txn| txn.rename(src, dst, replace))
+    }
+
+    /// Run `f` with exclusive write access to the storage. Operations made through the given
+    /// transaction are not interleaved with writes from other threads. Implementations can stage
+    /// the writes until `f` returns, so none of them are made if `f` or the staged writes fail,
+    /// and a crash keeps either all or none of them.
+    fn atomically<F, T>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&mut dyn Transaction<Error = Self::Error>) -> Result<T, Self::Error>
+            + Send
+            + 'static;
+
+    /// Return at most `count` keys that are within the given range in lexicographic order.
+    fn scan_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Result<Vec<Bytes>, Self::Error>;
 }
@@ -1,37 +1,94 @@
 //! An implementation of [Bitcask](https://riak.com/assets/bitcask-intro.pdf).
 
+mod access;
+mod archive;
+mod backup;
 mod bufio;
+mod changes;
+mod checkpoint;
+mod chunks;
+mod cleanshutdown;
+mod coalesce;
 mod config;
 mod context;
+#[cfg(test)]
+mod crash_tests;
+mod cursor;
+mod durability;
+pub mod entry;
+mod filter;
+mod hotkeys;
+mod index;
+mod keydir;
 mod log;
+mod logarchive;
+mod maintenance;
+mod mergeio;
+mod metrics;
+#[cfg(test)]
+mod model_tests;
 mod reader;
+mod scrub;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+mod tiering;
+mod typed;
 mod utils;
 mod writer;
 
-use std::{cell::RefCell, collections::HashMap, io, path::Path, sync::Arc, time};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    io::{self, Read, Write},
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+    time,
+};
 
 use bytes::Bytes;
 use crossbeam::{queue::ArrayQueue, utils::Backoff};
-use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 use rand::prelude::Distribution;
-use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::{join, sync::broadcast};
-use tracing::{debug, error, info};
-
-pub use self::config::{Config, SyncStrategy};
+use tracing::{debug, error, info, warn};
+
+pub use self::{
+    backup::BackupManifest,
+    changes::{Change, ChangeStream},
+    config::{
+        Config, EvictionPolicy, KeyDirHasher, MergeIo, MmapAdvice, QuotaPolicy, RuntimeMode,
+        ScrubPolicy, SyncStrategy, WriteMode,
+    },
+    context::RecoveryProgress,
+    cursor::{Cursor, CursorToken},
+    filter::{CompactionFilter, Decision, ValueTransform},
+    index::{Extractor, IndexDefinition},
+    keydir::KeyDirStats,
+    maintenance::MaintenanceDriver,
+    metrics::{HistogramSnapshot, Stats, StatsStream},
+    scrub::ScrubReport,
+    tiering::{ColdStore, LocalColdStore},
+    typed::{Codec, TypedStore},
+};
 use self::{
-    context::KeyDirEntry,
-    log::{LogDir, LogIterator, LogStatistics, LogWriter},
+    config::MergeStrategy,
+    entry::{FileHeader, HintFileEntry, HintFileRecord},
+    keydir::{DefaultKeyDir, KeyDir, KeyDirEntry},
+    log::{DataFileIterator, LogIterator, LogStatistics},
+    metrics::TimedGuard,
     reader::Reader,
+    tiering::ColdFiles,
+    utils::Layout,
     writer::Writer,
 };
-use super::KeyValueStorage;
-use crate::{
-    shutdown::Shutdown,
-    storage::bitcask::{config::MergePolicy, context::Context},
-};
+use super::{Durability, FileStats, KeyValueStorage, Transaction, Update, ValueStream};
+use crate::{shutdown::Shutdown, storage::bitcask::context::Context};
+
+/// The number of keys that are deleted each time the writer is locked when deleting keys by
+/// prefix, so other writes aren't held for the whole deletion.
+const DELETE_PREFIX_BATCH: usize = 1024;
 
 /// An implementation of a Bitcask instance whose APIs resemble the one given in [bitcask-intro.pdf]
 /// but with a few methods omitted.
@@ -57,6 +114,11 @@ pub struct Bitcask {
     /// for the signal that is sent when this struct is dropped. We do not send messages directly
     /// through the channel but rely on it's `Drop` implementation to send a closing signal.
     notify_shutdown: broadcast::Sender<()>,
+
+    /// The thread running the background tasks, which is joined when this struct is dropped.
+    /// The thread holds a handle to the storage, so the files aren't touched after the storage
+    /// is dropped, and the storage can be opened again right away.
+    background_tasks: Option<std::thread::JoinHandle<Result<(), Error>>>,
 }
 
 impl Bitcask {
@@ -64,28 +126,60 @@ impl Bitcask {
         info!(?conf, "openning bitcask");
 
         // Reconstruct in-memory data from on-disk data
-        let (keydir, stats, active_fileid) = rebuild_storage(&conf.path)?;
-        debug!(?active_fileid, "got new active file ID");
+        #[cfg(feature = "keydir-spill")]
+        keydir::remove_spill_files(&conf.path)?;
+        utils::arrange_files(&conf.path, conf.layout())?;
+        utils::remove_deleted_files(&conf.path)?;
+        let fileids = utils::stored_fileids(&conf.path)?;
+        let keydir = DefaultKeyDir::open(&conf)?;
+        let stats = rebuild_storage(&conf, &fileids, &keydir, || Ok(()))?;
+
+        let mut bitcask = Self::new(conf, keydir, stats, next_fileid(&fileids))?;
+        bitcask.handle.rebuild_indexes()?;
+        bitcask.spawn_background_tasks(|| Ok(()))?;
+        Ok(bitcask)
+    }
+
+    fn open_background(conf: Config) -> Result<Self, Error> {
+        info!(?conf, "openning bitcask in the background");
+
+        // The KeyDir starts empty and is rebuilt before the background tasks are started
+        #[cfg(feature = "keydir-spill")]
+        keydir::remove_spill_files(&conf.path)?;
+        utils::arrange_files(&conf.path, conf.layout())?;
+        utils::remove_deleted_files(&conf.path)?;
+        let fileids = utils::stored_fileids(&conf.path)?;
+        let active_fileid = next_fileid(&fileids);
+        let keydir = DefaultKeyDir::open(&conf)?;
+        let mut bitcask = Self::new(conf, keydir, HashMap::new(), active_fileid)?;
+        bitcask.handle.ctx.start_recovery(fileids.len() as u64);
+
+        let handle = bitcask.get_handle();
+        bitcask.spawn_background_tasks(move || handle.recover(&fileids))?;
+        Ok(bitcask)
+    }
 
-        let ctx = Arc::new(Context::new(conf, keydir));
+    fn new(
+        conf: Config,
+        keydir: DefaultKeyDir,
+        stats: HashMap<u64, LogStatistics>,
+        active_fileid: u64,
+    ) -> Result<Self, Error> {
+        debug!(?active_fileid, "got new active file ID");
+        let cold_files = ColdFiles::open(&conf)?;
+        let ctx = Arc::new(Context::new(conf, keydir, cold_files));
 
         let readers = Arc::new(ArrayQueue::new(ctx.get_conf().concurrency.get()));
         for _ in 0..readers.capacity() {
             readers
-                .push(Reader::new(
-                    ctx.clone(),
-                    RefCell::new(LogDir::new(ctx.get_conf().readers_cache_size)),
-                ))
+                .push(Reader::new(ctx.clone(), RefCell::new(ctx.new_log_dir())))
                 .expect("unreachable error");
         }
 
         let writer = Arc::new(Mutex::new(Writer::new(
             ctx.clone(),
-            RefCell::new(LogDir::new(ctx.get_conf().readers_cache_size)),
-            LogWriter::new(log::create(utils::datafile_name(
-                &ctx.get_conf().path,
-                active_fileid,
-            ))?)?,
+            RefCell::new(ctx.new_log_dir()),
+            writer::create_active_datafile(ctx.get_conf(), active_fileid)?,
             stats,
             active_fileid,
             0,
@@ -95,36 +189,96 @@ impl Bitcask {
             ctx,
             writer,
             readers,
+            dedicated_reader: None,
         };
 
         // We'll tie the lifetime of this channel to the lifetime of our `Bitcask` struct so
         // the channel is closed when the struct is dropped
         let (notify_shutdown, _) = broadcast::channel(1);
-        let bitcask = Self {
+        Ok(Self {
             handle,
             notify_shutdown,
-        };
+            background_tasks: None,
+        })
+    }
 
-        // We spawn a dedicated thread for the background task. The thread will host a
-        // Tokio runtime to schedule tasks for execution.
-        let handle = bitcask.get_handle();
-        let notify_shutdown = bitcask.notify_shutdown.clone();
-        std::thread::Builder::new()
+    /// Spawn a dedicated thread for the background tasks, which are started once `prepare`
+    /// succeeds. Depending on the runtime mode, the thread either hosts a Tokio runtime to
+    /// schedule tasks for execution, runs the tasks itself, or exits after `prepare`.
+    fn spawn_background_tasks<F>(&mut self, prepare: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error> + Send + 'static,
+    {
+        let handle = self.get_handle();
+        let notify_shutdown = self.notify_shutdown.clone();
+        let background_tasks = std::thread::Builder::new()
             .name("bitcask-background-tasks".into())
-            .spawn(move || background_tasks(handle, notify_shutdown))?;
+            .spawn(move || {
+                if let Err(e) = prepare() {
+                    error!(cause=?e, "could not prepare the storage");
+                    return Err(e);
+                }
+                if handle.ctx.get_conf().merge_on_open {
+                    if let Err(e) = handle.force_merge() {
+                        error!(cause=?e, "merge error");
+                    }
+                }
+                match handle.ctx.get_conf().runtime {
+                    RuntimeMode::Tokio => background_tasks(handle, notify_shutdown),
+                    RuntimeMode::Sync => {
+                        MaintenanceDriver::new(handle).run();
+                        Ok(())
+                    }
+                    RuntimeMode::Manual => Ok(()),
+                }
+            })?;
+        self.background_tasks = Some(background_tasks);
+        Ok(())
+    }
 
-        Ok(bitcask)
+    /// Write a storage holding the state of the storage whose log was archived into
+    /// `archive_dir` at the given time into the directory at `path`, which can then be opened.
+    /// Returns the number of log entries that were restored. Writes that were made after the
+    /// latest copy of the active file was archived can't be restored.
+    pub fn restore_to<P, Q>(archive_dir: P, path: Q, time: time::SystemTime) -> Result<u64, Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let restored = logarchive::restore(archive_dir, &path, utils::to_timestamp(time))?;
+        info!(path = ?path.as_ref(), restored, "restored bitcask from the log archive");
+        Ok(restored)
     }
 
     /// Get the handle to the storage
     pub fn get_handle(&self) -> Handle {
         self.handle.clone()
     }
+
+    /// Get a driver that runs the periodic merges, disk synchronizations, and checkpoints each
+    /// time it's ticked. This is meant for storages opened with `RuntimeMode::Manual`, which
+    /// don't run these tasks on their own.
+    pub fn maintenance_driver(&self) -> MaintenanceDriver {
+        MaintenanceDriver::new(self.get_handle())
+    }
 }
 
 impl Drop for Bitcask {
     fn drop(&mut self) {
         self.handle.close();
+        // Replacing the sender drops it, which notifies the background tasks to stop
+        self.notify_shutdown = broadcast::channel(1).0;
+        if let Some(background_tasks) = self.background_tasks.take() {
+            if background_tasks.join().is_err() {
+                error!("background tasks panicked");
+            }
+        }
+        // The statistics are incomplete if the storage didn't finish recovering
+        if self.handle.recovery_progress().is_none() {
+            if let Err(e) = self.handle.write_shutdown_marker() {
+                error!(cause=?e, "shutdown marker error");
+            }
+        }
     }
 }
 
@@ -142,67 +296,793 @@ pub struct Handle {
     /// a reader is taken from the queue and used for reading the data files. Once we finish
     /// reading, the reader is returned back to the queue.
     readers: Arc<ArrayQueue<Reader>>,
+
+    /// A reader that is used only by this handle and its clones instead of the readers queue.
+    /// This is given to handles of clients that make one request at a time, so reads don't
+    /// contend with other clients.
+    dedicated_reader: Option<Arc<Mutex<Reader>>>,
 }
 
 impl Handle {
+    /// Lock the writer while recording the contention on it.
+    fn lock_writer(&self) -> TimedGuard<'_, Writer> {
+        self.ctx.get_metrics().lock(&self.writer)
+    }
+
+    /// Lock the writer for a write that was requested by a user. The write fails with
+    /// [`Error::Busy`] instead of waiting when too many writes are already waiting for the writer.
+    fn lock_writer_for_write(&self) -> Result<TimedGuard<'_, Writer>, Error> {
+        let _pending = self.ctx.queue_write()?;
+        Ok(self.lock_writer())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
     fn put(&self, key: Bytes, value: Bytes) -> Result<(), Error> {
-        if self.ctx.is_closed() {
-            return Err(Error::Closed);
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        self.lock_writer_for_write()?
+            .put(key, chunks::escape(value))
+    }
+
+    /// Set the value of a key and wait until the value is on disk as required by the given
+    /// durability level.
+    pub fn put_durable(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        durability: Durability,
+    ) -> Result<(), Error> {
+        self.put(key, value)?;
+        self.make_durable(durability)
+    }
+
+    /// Wait until the writes made before the call are on disk as required by the given durability
+    /// level. Batched waits share disk synchronizations with each other.
+    fn make_durable(&self, durability: Durability) -> Result<(), Error> {
+        if let SyncStrategy::Always = self.ctx.get_conf().sync {
+            // Every write is already synchronized before it returns
+            return Ok(());
         }
-        self.writer.lock().put(key, value)
+        match durability {
+            Durability::None => Ok(()),
+            Durability::Batched => self.ctx.get_sync_group().wait(|| self.sync()),
+            Durability::Immediate => self.sync(),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn put_with_expiry(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<time::SystemTime>,
+    ) -> Result<(), Error> {
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        let expiry = expires_at.map(utils::to_timestamp);
+        self.lock_writer_for_write()?
+            .put_with_expiry(key, chunks::escape(value), expiry)
     }
 
+    #[tracing::instrument(level = "debug", skip_all)]
     fn delete(&self, key: Bytes) -> Result<bool, Error> {
-        if self.ctx.is_closed() {
-            return Err(Error::Closed);
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        self.lock_writer_for_write()?.delete(key)
+    }
+
+    fn update<F, T>(&self, key: Bytes, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(Option<Bytes>) -> (Update, T),
+    {
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        let mut writer = self.lock_writer_for_write()?;
+        let value = match writer.get(&key)? {
+            Some(raw) => Some(chunks::resolve(raw, |key| writer.get(&key))?),
+            None => None,
+        };
+        let (update, result) = f(value);
+        match update {
+            Update::Keep => {}
+            Update::Set(value) => {
+                let expiry = writer.get_expiry(&key);
+                writer.put_with_expiry(key, chunks::escape(value), expiry)?
+            }
+            Update::Delete => {
+                writer.delete(key)?;
+            }
         }
-        self.writer.lock().delete(key)
+        Ok(result)
+    }
+
+    fn atomically<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn Transaction<Error = Error>) -> Result<T, Error>,
+    {
+        self.ctx.check_available()?;
+        let mut writer = self.lock_writer_for_write()?;
+        // Nothing is written if the transaction fails, and its writes are kept or lost together.
+        // The batch rejects keys with the reserved prefix
+        let mut batch = writer.batch();
+        let result = f(&mut batch)?;
+        batch.commit()?;
+        Ok(result)
     }
 
+    /// Get the value of a key, joining the chunks of a value that was streamed in.
+    #[tracing::instrument(level = "debug", skip_all)]
     fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
-        if self.ctx.is_closed() {
-            return Err(Error::Closed);
+        self.ctx.check_available()?;
+        self.resolve(self.read(key)?)
+    }
+
+    /// Turn a value as it's stored into the value that was written.
+    fn resolve(&self, raw: Option<Bytes>) -> Result<Option<Bytes>, Error> {
+        raw.map(|raw| chunks::resolve(raw, |key| self.read(key)))
+            .transpose()
+    }
+
+    /// Read the value of a key as it's stored through one of the readers without checking
+    /// whether the storage is available.
+    fn read(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
+        let result = self.with_reader(|reader| reader.get(key.clone()));
+        self.repair_mismatch(&key, result)
+    }
+
+    /// Repair the KeyDir entry of the key if the read failed because the entry points to the
+    /// entry of another key, and return the value that the repaired entry points to.
+    fn repair_mismatch(
+        &self,
+        key: &Bytes,
+        result: Result<Option<Bytes>, Error>,
+    ) -> Result<Option<Bytes>, Error> {
+        match result {
+            Err(Error::Corruption { reason, .. }) if reason == reader::KEYDIR_MISMATCH => {
+                self.lock_writer().repair(key)
+            }
+            result => result,
+        }
+    }
+
+    /// Run `f` with one of the readers, waiting for a reader to become idle if there's none.
+    fn with_reader<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&Reader) -> T,
+    {
+        if let Some(reader) = &self.dedicated_reader {
+            return f(&reader.lock());
         }
+        let metrics = self.ctx.get_metrics();
+        let mut waiting_since: Option<time::Instant> = None;
         let backoff = Backoff::new();
         loop {
             if let Some(reader) = self.readers.pop() {
-                // Make a query with the key and return the context to the queue after we finish so
-                // other threads can make progress
-                let result = reader.get(key);
+                if let Some(start) = waiting_since {
+                    metrics.reader_wait_time.record(start.elapsed());
+                }
+                // Use the reader and return it to the queue after we finish so other threads can
+                // make progress. The files that a merge removed in the meantime are closed first,
+                // so an idle reader doesn't hold them
+                let result = f(&reader);
+                reader.release_merged();
                 self.readers.push(reader).expect("unreachable error");
                 break result;
             }
+            if waiting_since.is_none() {
+                metrics.reader_waits.fetch_add(1, Ordering::Relaxed);
+                waiting_since = Some(time::Instant::now());
+            }
             // Spin until we have access to a reader
             backoff.spin();
         }
     }
 
+    /// Get the values of many keys, in the order of the given keys. The keys are grouped by the
+    /// data files holding their values, and the groups are spread over the idle readers, which
+    /// read them in parallel. Each group is read in the order of the values' positions.
+    pub fn multi_get(&self, keys: &[Bytes]) -> Result<Vec<Option<Bytes>>, Error> {
+        self.ctx.check_available()?;
+        self.multi_read(keys)?
+            .into_iter()
+            .map(|raw| self.resolve(raw))
+            .collect()
+    }
+
+    /// Read the values of many keys as they're stored. See [`Handle::multi_get`].
+    fn multi_read(&self, keys: &[Bytes]) -> Result<Vec<Option<Bytes>>, Error> {
+        let mut groups: BTreeMap<u64, Vec<(u64, usize)>> = BTreeMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(entry) = self.ctx.get_keydir().get(key) {
+                groups
+                    .entry(entry.fileid())
+                    .or_default()
+                    .push((entry.pos(), i));
+            }
+        }
+        let mut values = vec![None; keys.len()];
+
+        // Take as many idle readers as there are groups
+        let mut readers = Vec::new();
+        if self.dedicated_reader.is_none() {
+            while readers.len() < groups.len() {
+                match self.readers.pop() {
+                    Some(reader) => readers.push(reader),
+                    None => break,
+                }
+            }
+        }
+        if readers.len() < 2 {
+            for reader in readers {
+                self.readers.push(reader).expect("unreachable error");
+            }
+            for (_, i) in groups.into_values().flatten() {
+                values[i] = self.read(keys[i].clone())?;
+            }
+            return Ok(values);
+        }
+
+        // Deal the groups out to the readers, so each file is read by one of them
+        let mut batches = vec![Vec::new(); readers.len()];
+        for (n, mut group) in groups.into_values().enumerate() {
+            group.sort_unstable();
+            batches[n % readers.len()].extend(group.into_iter().map(|(_, i)| i));
+        }
+        let results = std::thread::scope(|s| {
+            let workers: Vec<_> = readers
+                .into_iter()
+                .zip(batches)
+                .map(|(reader, batch)| {
+                    s.spawn(move || {
+                        let results: Vec<_> = batch
+                            .into_iter()
+                            .map(|i| (i, reader.get(keys[i].clone())))
+                            .collect();
+                        (reader, results)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    let (reader, result) = worker
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                    self.readers.push(reader).expect("unreachable error");
+                    result
+                })
+                .collect::<Vec<_>>()
+        });
+        for (i, result) in results.into_iter().flatten() {
+            values[i] = self.repair_mismatch(&keys[i], result)?;
+        }
+        Ok(values)
+    }
+
+    /// Load the values of the given keys into the page cache ahead of time, so their first reads
+    /// don't wait for the disk. This is useful after opening the storage or before shifting
+    /// traffic to it. Return the number of values that were prefetched.
+    pub fn warmup(&self, keys: &[Bytes]) -> Result<usize, Error> {
+        self.ctx.check_available()?;
+        self.with_reader(|reader| reader.prefetch(keys))
+    }
+
+    /// Load the values of all keys starting with the given prefix into the page cache ahead of
+    /// time. See [`Handle::warmup`].
+    pub fn warmup_prefix(&self, prefix: &[u8]) -> Result<usize, Error> {
+        let keys = self.scan_range(
+            Bound::Included(Bytes::copy_from_slice(prefix)),
+            utils::prefix_end(prefix),
+            usize::MAX,
+        )?;
+        self.warmup(&keys)
+    }
+
+    /// Delete a key and return `true`, if it exists. Unlike [`Handle::delete`], the key is only
+    /// removed from the KeyDir, and its tombstone is queued and written together with the
+    /// tombstones of other unlinked keys. A crash before the tombstones are written brings the
+    /// unlinked keys back.
+    pub fn unlink(&self, key: Bytes) -> Result<bool, Error> {
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        self.lock_writer_for_write()?.unlink(key)
+    }
+
+    /// Delete all keys starting with the given prefix and return the number of deleted keys. The
+    /// tombstones are written in batches, and other writes can go between the batches, so keys
+    /// that are set with the prefix while the deletion runs may be kept.
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<u64, Error> {
+        let mut start = Bound::Included(Bytes::copy_from_slice(prefix));
+        let end = utils::prefix_end(prefix);
+        let mut count = 0;
+        loop {
+            let keys = self.scan_range(start, end.clone(), DELETE_PREFIX_BATCH)?;
+            let Some(last) = keys.last().cloned() else {
+                return Ok(count);
+            };
+            let mut writer = self.lock_writer_for_write()?;
+            for key in keys {
+                if writer.delete(key)? {
+                    count += 1;
+                }
+            }
+            drop(writer);
+            start = Bound::Excluded(last);
+        }
+    }
+
+    fn get_expiry(&self, key: Bytes) -> Result<Option<time::SystemTime>, Error> {
+        self.ctx.check_available()?;
+        let expiry = self
+            .ctx
+            .get_keydir()
+            .get(&key)
+            .filter(|e| !e.is_expired(utils::timestamp()))
+            .and_then(|e| e.expiry());
+        Ok(expiry.map(utils::from_timestamp))
+    }
+
+    fn idle_time(&self, key: Bytes) -> Result<Option<time::Duration>, Error> {
+        self.ctx.check_available()?;
+        let now = utils::timestamp();
+        let idle_time = self
+            .ctx
+            .get_keydir()
+            .get(&key)
+            .filter(|e| !e.is_expired(now))
+            .map(|e| {
+                // The access statistics can be shared with other keys, so the last write is used
+                // when it's more recent
+                let last_access = self.ctx.get_access().last_access(&key).max(e.tstamp());
+                let idle_nanos = now.saturating_sub(last_access).max(0);
+                time::Duration::from_nanos(idle_nanos as u64)
+            });
+        Ok(idle_time)
+    }
+
+    /// Return the approximate access frequency of a key, which is only tracked when keys are
+    /// evicted to stay within the quotas. Returns [`Error::InvalidConfig`] otherwise.
+    fn access_frequency(&self, key: Bytes) -> Result<Option<u8>, Error> {
+        self.ctx.check_available()?;
+        let access = self.ctx.get_access();
+        if !access.tracks_frequency() {
+            return Err(Error::InvalidConfig(
+                "access frequencies are only tracked when keys are evicted",
+            ));
+        }
+        let now = utils::timestamp();
+        let frequency = self
+            .ctx
+            .get_keydir()
+            .get(&key)
+            .filter(|e| !e.is_expired(now))
+            .map(|_| access.frequency(&key, now));
+        Ok(frequency)
+    }
+
+    /// Return the keys within the given range in lexicographic order.
+    pub fn range<R>(&self, range: R) -> Result<Vec<Bytes>, Error>
+    where
+        R: RangeBounds<Bytes>,
+    {
+        self.scan_range(
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            usize::MAX,
+        )
+    }
+
+    fn scan_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Result<Vec<Bytes>, Error> {
+        self.ctx.check_available()?;
+        Ok(self.ctx.keydir_range(start, end, count))
+    }
+
+    /// Apply the settings that can be changed while the storage is running, which are the merge
+    /// settings. Other settings only take effect when the storage is reopened. Nothing is applied
+    /// if the configuration is invalid.
+    pub fn reload(&self, conf: &Config) -> Result<(), Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        conf.validate()?;
+        info!(merge = ?conf.merge, "reloading merge settings");
+        self.ctx.set_merge_strategy(conf.merge.clone());
+        Ok(())
+    }
+
+    /// Return up to `n` of the most read and written keys with their estimated number of accesses,
+    /// starting with the most accessed key. The estimates are never lower than the actual counts.
+    /// Returns nothing unless the hot keys are tracked with [`Config::hot_keys`].
+    pub fn hot_keys(&self, n: usize) -> Vec<(Bytes, u64)> {
+        self.ctx
+            .get_hot_keys()
+            .map(|hot_keys| hot_keys.top(n))
+            .unwrap_or_default()
+    }
+
+    /// Suspend the background merges and disk synchronizations, e.g. during latency-critical
+    /// windows, until [`Handle::resume_maintenance`] is called. A merge that is running is
+    /// finished, and merges that are requested explicitly still run.
+    pub fn pause_maintenance(&self) {
+        info!("pausing background maintenance");
+        self.ctx.set_maintenance_paused(true);
+    }
+
+    /// Resume the background merges and disk synchronizations that were paused.
+    pub fn resume_maintenance(&self) {
+        info!("resuming background maintenance");
+        self.ctx.set_maintenance_paused(false);
+    }
+
+    /// Return whether the background merges and disk synchronizations are paused.
+    pub fn maintenance_paused(&self) -> bool {
+        self.ctx.is_maintenance_paused()
+    }
+
+    /// Return the statistics of the usage and of the contention on the readers and the writer.
+    pub fn stats(&self) -> Stats {
+        let metrics = self.ctx.get_metrics();
+        let (live_keys, live_bytes) = self.ctx.get_usage();
+        Stats {
+            live_keys,
+            live_bytes,
+            readers: self.readers.capacity(),
+            readers_in_use: self.readers.capacity() - self.readers.len(),
+            open_files: self.ctx.open_files(),
+            evicted_keys: metrics.evicted_keys.load(Ordering::Relaxed),
+            pending_writes: self.ctx.pending_writes(),
+            busy_writes: metrics.busy_writes.load(Ordering::Relaxed),
+            coalesced_writes: metrics.coalesced_writes.load(Ordering::Relaxed),
+            scrubbed_bytes: metrics.scrubbed_bytes.load(Ordering::Relaxed),
+            corrupt_files: metrics.corrupt_files.load(Ordering::Relaxed),
+            repaired_files: metrics.repaired_files.load(Ordering::Relaxed),
+            reader_waits: metrics.reader_waits.load(Ordering::Relaxed),
+            reader_wait_time: metrics.reader_wait_time.snapshot(),
+            verified_reads: metrics.verified_reads.load(Ordering::Relaxed),
+            failed_verifications: metrics.failed_verifications.load(Ordering::Relaxed),
+            repaired_reads: metrics.repaired_reads.load(Ordering::Relaxed),
+            read_retries: metrics.read_retries.load(Ordering::Relaxed),
+            failed_read_retries: metrics.failed_read_retries.load(Ordering::Relaxed),
+            writer_wait_time: metrics.writer_wait_time.snapshot(),
+            writer_hold_time: metrics.writer_hold_time.snapshot(),
+            keydir: self.ctx.get_keydir().stats(),
+            cold_files: self.ctx.get_cold_files().map_or(0, ColdFiles::count),
+            cold_fetches: self.ctx.get_cold_files().map_or(0, ColdFiles::fetches),
+            cold_cache_hits: self.ctx.get_cold_files().map_or(0, ColdFiles::cache_hits),
+        }
+    }
+
+    /// Return the usage of each data file, ordered by file ID, for planning the capacity of the
+    /// storage and tuning its merges. The files that merges remove while they're listed are left
+    /// out.
+    pub fn file_stats(&self) -> Result<Vec<FileStats>, Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        let conf = self.ctx.get_conf();
+        let layout = conf.layout();
+        let stats = self.lock_writer().get_stats().clone();
+        let mut files = Vec::new();
+        for fileid in utils::sorted_fileids(&conf.path)? {
+            let metadata = match std::fs::metadata(utils::datafile_name(&conf.path, layout, fileid))
+            {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let file = stats.get(&fileid).cloned().unwrap_or_default();
+            files.push(FileStats {
+                fileid,
+                size: metadata.len(),
+                live_keys: file.live_keys(),
+                dead_keys: file.dead_keys(),
+                dead_bytes: file.dead_bytes(),
+                fragmentation: file.fragmentation(),
+                modified: metadata.modified()?,
+                has_hintfile: utils::hintfile_name(&conf.path, layout, fileid).exists(),
+            });
+        }
+        Ok(files)
+    }
+
+    /// Return a stream of snapshots of the statistics that are taken every `interval`.
+    pub fn stats_stream(&self, interval: time::Duration) -> StatsStream {
+        StatsStream::new(self.clone(), interval)
+    }
+
+    /// Return a cursor that enumerates all keys from the beginning.
+    pub fn cursor(&self) -> Cursor {
+        Cursor::new(self.clone(), CursorToken::default())
+    }
+
+    /// Return a cursor that continues the scan from the position given by the token.
+    pub fn resume_cursor(&self, token: CursorToken) -> Cursor {
+        Cursor::new(self.clone(), token)
+    }
+
+    /// Subscribe to the writes that are committed after this call.
+    pub fn subscribe_changes(&self) -> ChangeStream {
+        ChangeStream::new(self.ctx.subscribe_changes())
+    }
+
+    /// Return the keys whose values contain the given field in the secondary index with the given
+    /// name.
+    pub fn lookup_index(&self, name: &str, field: &[u8]) -> Result<Vec<Bytes>, Error> {
+        self.ctx.check_available()?;
+        self.ctx
+            .get_indexes()
+            .lookup(name, field)
+            .ok_or_else(|| Error::IndexNotFound(name.to_string()))
+    }
+
+    /// Return the value that the key had at the given time. The value is looked up in the data
+    /// files, including the ones that merges moved to the archive directory, so history that was
+    /// merged away without being archived is lost. Each data file is indexed the first time it's
+    /// consulted, which makes the first lookups slow. A value that was streamed in is only
+    /// returned while its chunks are kept, [`Error::ValueChanged`] is returned after that.
+    pub fn get_as_of(&self, key: Bytes, time: time::SystemTime) -> Result<Option<Bytes>, Error> {
+        self.ctx.check_available()?;
+        let raw = self.ctx.get_history().get_as_of(
+            self.ctx.get_conf(),
+            self.ctx.get_cold_files(),
+            &key,
+            utils::to_timestamp(time),
+        )?;
+        self.resolve(raw)
+    }
+
+    /// Set the value of a key to the bytes read from `reader` until its end, and return the number
+    /// of bytes. The value is written in chunks, so it can be larger than the max entry size and
+    /// the max file size. Values written this way are best read with [`Handle::get_writer`] or
+    /// [`KeyValueStorage::get_stream`], since other reads join the chunks in memory.
+    pub fn put_reader<R>(&self, key: Bytes, reader: R) -> Result<u64, Error>
+    where
+        R: Read,
+    {
+        self.ctx.check_available()?;
+        chunks::check_key(&key)?;
+        chunks::put_reader(self, key, reader)
+    }
+
+    /// Write the value of a key to `writer` and return the number of bytes, or `None` if the key
+    /// doesn't exist. Values written with [`Handle::put_reader`] are written one chunk at a time,
+    /// and [`Error::ValueChanged`] is returned if the value is replaced in the meantime.
+    pub fn get_writer<W>(&self, key: Bytes, writer: W) -> Result<Option<u64>, Error>
+    where
+        W: Write,
+    {
+        self.ctx.check_available()?;
+        chunks::get_writer(self, key, writer)
+    }
+
+    /// Move the merge files that haven't changed for `cold_after_ms` to the cold store, and return
+    /// the number of files that were moved. This also runs after each background merge. Does
+    /// nothing unless a cold store is configured with [`Config::cold_store`] or
+    /// [`Config::cold_dir`].
+    ///
+    /// The files are uploaded without holding the writer, since they never change. A file that a
+    /// merge removes while it's uploaded is removed from the cold store again.
+    pub fn tier(&self) -> Result<usize, Error> {
+        self.ctx.check_available()?;
+        let Some(cold_files) = self.ctx.get_cold_files() else {
+            return Ok(0);
+        };
+        let candidates = self.lock_writer().cold_candidates()?;
+        let mut tiered = 0;
+        for fileid in candidates {
+            match cold_files.upload(self.ctx.get_conf(), fileid) {
+                Ok(()) => {}
+                // The file was merged away
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            if self.lock_writer().finish_tiering(fileid)? {
+                tiered += 1;
+            }
+        }
+        Ok(tiered)
+    }
+
+    /// Write a checkpoint of the KeyDir, so the next time the storage is opened only the data
+    /// files that are written after the checkpoint have to be read.
+    pub fn checkpoint(&self) -> Result<(), Error> {
+        self.ctx.check_available()?;
+        let checkpoint = self.lock_writer().checkpoint()?;
+        debug!(
+            next_fileid = checkpoint.next_fileid,
+            keys = checkpoint.entries.len(),
+            "writing checkpoint"
+        );
+        checkpoint::write(&self.ctx.get_conf().path, &checkpoint)
+    }
+
+    /// Add what has been written to the active data file so far to the log archive, so it can be
+    /// restored without waiting for the file to be closed.
+    pub fn archive_log(&self) -> Result<(), Error> {
+        self.ctx.check_available()?;
+        self.lock_writer().archive_active()
+    }
+
+    /// Read the data files that are no longer written to and their hint files, at no more than
+    /// the configured scrub bandwidth, and check them for corruption. Corrupt data files are
+    /// repaired from the log archive when possible, and corrupt hint files are removed.
+    pub fn scrub(&self) -> Result<ScrubReport, Error> {
+        self.ctx.check_available()?;
+        scrub::scrub(self)
+    }
+
+    /// Back up the data files and the hint files into `dest`, which must hold the backup that
+    /// `prev` was returned for, or be empty when `prev` is `None`. Only the data files that were
+    /// created since the previous backup are copied. Returns the manifest to give to the next
+    /// backup, which is also saved in `dest` and can be read with [`BackupManifest::load`].
+    ///
+    /// Writes and merges wait while the files are copied, so the backup is consistent.
+    pub fn backup_incremental<P>(
+        &self,
+        prev: Option<&BackupManifest>,
+        dest: P,
+    ) -> Result<BackupManifest, Error>
+    where
+        P: AsRef<Path>,
+    {
+        self.ctx.check_available()?;
+        let mut writer = self.lock_writer();
+        let fileids = writer.closed_fileids()?;
+        let conf = self.ctx.get_conf();
+        let manifest = backup::backup(
+            &conf.path,
+            conf.layout(),
+            fileids,
+            self.ctx.get_cold_files(),
+            prev,
+            &dest,
+        )?;
+        drop(writer);
+        info!(dest = ?dest.as_ref(), files = manifest.fileids().count(), "backed up bitcask");
+        Ok(manifest)
+    }
+
+    /// Return the progress of rebuilding the KeyDir when the storage was opened in the
+    /// background. Returns `None` once the storage is available.
+    pub fn recovery_progress(&self) -> Option<RecoveryProgress> {
+        self.ctx.recovery_progress()
+    }
+
+    /// Rebuild the KeyDir, the statistics, and the secondary indexes from the given data files,
+    /// then make the storage available. The storage is closed if it can't be recovered.
+    fn recover(&self, fileids: &[u64]) -> Result<(), Error> {
+        match self.rebuild_keydir(fileids) {
+            Ok(()) => {
+                info!(files = fileids.len(), "finished recovering bitcask");
+                self.ctx.finish_recovery();
+                Ok(())
+            }
+            Err(e) => {
+                self.close();
+                Err(e)
+            }
+        }
+    }
+
+    /// Rebuild the KeyDir, the statistics, and the secondary indexes from the given data files.
+    fn rebuild_keydir(&self, fileids: &[u64]) -> Result<(), Error> {
+        let keydir = DefaultKeyDir::open(self.ctx.get_conf())?;
+        let stats = rebuild_storage(self.ctx.get_conf(), fileids, &keydir, || {
+            // Stop early when the storage is dropped before it finishes recovering
+            if self.ctx.is_closed() {
+                return Err(Error::Closed);
+            }
+            self.ctx.record_recovered_file();
+            Ok(())
+        })?;
+        // Entries are moved through the context so the usage counters are kept up to date
+        for (key, entry) in keydir.iter() {
+            self.ctx.keydir_set(key, entry);
+        }
+        self.lock_writer().restore_stats(stats);
+        self.rebuild_indexes()
+    }
+
+    /// Populate the secondary indexes by reading the values of all keys in the KeyDir.
+    fn rebuild_indexes(&self) -> Result<(), Error> {
+        let indexes = self.ctx.get_indexes();
+        if indexes.is_empty() {
+            return Ok(());
+        }
+        for (key, _) in self.ctx.get_keydir().iter() {
+            if chunks::is_chunk_key(&key) {
+                continue;
+            }
+            if let Some(value) = self.read(key.clone())? {
+                indexes.insert(&key, &value);
+            }
+        }
+        Ok(())
+    }
+
     fn merge(&self) -> Result<(), Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        let mut writer = self.writer.lock();
+        let mut writer = self.lock_writer();
         if writer.can_merge() {
             writer.merge()?;
         }
+        drop(writer);
+        self.release_idle_readers();
+        self.tier()?;
+        Ok(())
+    }
+
+    /// Merge the data files without checking the merge triggers and the merge policy.
+    fn force_merge(&self) -> Result<(), Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        self.lock_writer().merge()?;
+        self.release_idle_readers();
         Ok(())
     }
 
+    /// Close the files that merges removed in the readers that are idle in the queue, so the
+    /// files aren't held until the readers are used again. Readers that are in use close them
+    /// when they're returned.
+    fn release_idle_readers(&self) {
+        for _ in 0..self.readers.len() {
+            let Some(reader) = self.readers.pop() else {
+                break;
+            };
+            reader.release_merged();
+            self.readers.push(reader).expect("unreachable error");
+        }
+    }
+
     fn sync(&self) -> Result<(), Error> {
         if self.ctx.is_closed() {
             return Err(Error::Closed);
         }
-        self.writer.lock().sync()
+        self.lock_writer().sync()
+    }
+
+    /// Write the coalesced overwrites that have waited for the coalescing window.
+    fn flush_coalesced(&self) -> Result<(), Error> {
+        if self.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        self.lock_writer().flush_coalesced_if_due()
     }
 
     fn close(&self) {
         self.ctx.close()
     }
+
+    /// Leave a marker of the clean shutdown with the statistics of the data files, so the next
+    /// open restores them instead of deriving them from the files.
+    fn write_shutdown_marker(&self) -> Result<(), Error> {
+        let marker = self.lock_writer().shutdown_marker()?;
+        cleanshutdown::write(&self.ctx.get_conf().path, &marker)
+    }
 }
 
 impl KeyValueStorage for Handle {
     type Error = Error;
 
+    fn for_client(&self) -> Self {
+        if !self.ctx.get_conf().reader_affinity {
+            return self.clone();
+        }
+        let reader = Reader::new(Arc::clone(&self.ctx), RefCell::new(self.ctx.new_log_dir()));
+        Self {
+            dedicated_reader: Some(Arc::new(Mutex::new(reader))),
+            ..self.clone()
+        }
+    }
+
     fn del(&self, key: Bytes) -> Result<bool, Self::Error> {
         self.delete(key)
     }
@@ -211,9 +1091,85 @@ impl KeyValueStorage for Handle {
         self.get(key)
     }
 
+    fn make_durable(&self, durability: Durability) -> Result<(), Self::Error> {
+        self.make_durable(durability)
+    }
+
+    fn get_stream(&self, key: Bytes) -> Result<Option<ValueStream>, Self::Error> {
+        self.ctx.check_available()?;
+        chunks::get_stream(self, key)
+    }
+
+    fn idle_time(&self, key: Bytes) -> Result<Option<time::Duration>, Self::Error> {
+        self.idle_time(key)
+    }
+
+    fn access_frequency(&self, key: Bytes) -> Result<Option<u8>, Self::Error> {
+        self.access_frequency(key)
+    }
+
+    fn hot_keys(&self, n: usize) -> Result<Vec<(Bytes, u64)>, Self::Error> {
+        Ok(self.hot_keys(n))
+    }
+
+    fn pause_maintenance(&self, paused: bool) -> Result<(), Self::Error> {
+        if paused {
+            self.pause_maintenance();
+        } else {
+            self.resume_maintenance();
+        }
+        Ok(())
+    }
+
+    fn maintenance_paused(&self) -> Result<bool, Self::Error> {
+        Ok(self.maintenance_paused())
+    }
+
+    fn file_stats(&self) -> Result<Vec<FileStats>, Self::Error> {
+        self.file_stats()
+    }
+
     fn set(&self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
         self.put(key, value)
     }
+
+    fn set_with_expiry(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<time::SystemTime>,
+    ) -> Result<(), Self::Error> {
+        self.put_with_expiry(key, value, expires_at)
+    }
+
+    fn get_expiry(&self, key: Bytes) -> Result<Option<time::SystemTime>, Self::Error> {
+        self.get_expiry(key)
+    }
+
+    fn update<F, T>(&self, key: Bytes, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(Option<Bytes>) -> (Update, T) + Send + 'static,
+    {
+        self.update(key, f)
+    }
+
+    fn atomically<F, T>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&mut dyn Transaction<Error = Self::Error>) -> Result<T, Self::Error>
+            + Send
+            + 'static,
+    {
+        self.atomically(f)
+    }
+
+    fn scan_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Result<Vec<Bytes>, Self::Error> {
+        self.scan_range(start, end, count)
+    }
 }
 
 #[tracing::instrument(skip(handle, notify_shutdown))]
@@ -241,49 +1197,130 @@ fn background_tasks(handle: Handle, notify_shutdown: broadcast::Sender<()>) -> R
         })
     };
 
-    // Drop unused handle
-    drop(handle);
-    // We drop this early so there's only 1 channel Sender held by our bitcask instance
-    drop(notify_shutdown);
-    // Block until the async tasks finish
-    let (r1, r2) = rt.block_on(async { join!(merge_join_handle, sync_join_handle) });
-    if let Err(e) = r1 {
-        error!(cause=?e, "merge error");
-    }
-    if let Err(e) = r2 {
-        error!(cause=?e, "sync error");
-    }
-    Ok(())
-}
+    let coalesce_join_handle = {
+        let handle = handle.clone();
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        rt.spawn(async move {
+            if let Err(e) = coalesce_on_interval(handle, shutdown).await {
+                error!(cause=?e, "coalesced writes error");
+            }
+        })
+    };
+
+    let log_archive_join_handle = {
+        let handle = handle.clone();
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        rt.spawn(async move {
+            if let Err(e) = log_archive_on_interval(handle, shutdown).await {
+                error!(cause=?e, "log archive error");
+            }
+        })
+    };
+
+    let scrub_join_handle = {
+        let handle = handle.clone();
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        rt.spawn(async move {
+            if let Err(e) = scrub_on_interval(handle, shutdown).await {
+                error!(cause=?e, "scrub error");
+            }
+        })
+    };
+
+    let checkpoint_join_handle = {
+        let handle = handle.clone();
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        rt.spawn(async move {
+            if let Err(e) = checkpoint_on_interval(handle, shutdown).await {
+                error!(cause=?e, "checkpoint error");
+            }
+        })
+    };
+
+    // Drop unused handle
+    drop(handle);
+    // We drop this early so there's only 1 channel Sender held by our bitcask instance
+    drop(notify_shutdown);
+    // Block until the async tasks finish
+    let (r1, r2, r3, r4, r5, r6) = rt.block_on(async {
+        join!(
+            merge_join_handle,
+            sync_join_handle,
+            checkpoint_join_handle,
+            log_archive_join_handle,
+            coalesce_join_handle,
+            scrub_join_handle
+        )
+    });
+    if let Err(e) = r1 {
+        error!(cause=?e, "merge error");
+    }
+    if let Err(e) = r2 {
+        error!(cause=?e, "sync error");
+    }
+    if let Err(e) = r3 {
+        error!(cause=?e, "checkpoint error");
+    }
+    if let Err(e) = r4 {
+        error!(cause=?e, "log archive error");
+    }
+    if let Err(e) = r5 {
+        error!(cause=?e, "coalesced writes error");
+    }
+    if let Err(e) = r6 {
+        error!(cause=?e, "scrub error");
+    }
+    Ok(())
+}
 
 /// A periodic background task that checks the merge triggers and performs merging when the trigger
 /// conditions are met.
 #[tracing::instrument(skip(handle, shutdown))]
 async fn merge_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
-    // Return early so we don't have to run the task
-    if let MergePolicy::Never = handle.ctx.get_conf().merge.policy {
-        return Ok(());
-    }
-    let interval = time::Duration::from_millis(handle.ctx.get_conf().merge.check_interval_ms);
-    let jitter = interval.mul_f64(handle.ctx.get_conf().merge.check_jitter);
-    let dist = rand::distributions::Uniform::new_inclusive(interval - jitter, interval + jitter);
+    let mut delay = first_merge_delay(&handle);
     while !shutdown.is_shutdown() {
         // Wake up the task when a specific interval has passed or when the storage is shutdown.
         tokio::select! {
-            _ = tokio::time::sleep(dist.sample(&mut rand::thread_rng())) => {},
+            _ = tokio::time::sleep(delay) => {},
             _ = shutdown.recv() => {
                 info!("stopping merge background task");
                 return Ok(());
             },
         };
-        let handle = handle.clone();
-        if let Err(e) = tokio::task::spawn_blocking(move || handle.merge()).await? {
+        // The merge settings are read on every iteration because they can be reloaded
+        delay = merge_delay(&handle.ctx.get_merge_strategy());
+        if handle.maintenance_paused() {
+            continue;
+        }
+        // The merge's spans are nested under the task's span
+        let merge_handle = handle.clone();
+        let span = tracing::Span::current();
+        let merge = move || span.in_scope(|| merge_handle.merge());
+        if let Err(e) = tokio::task::spawn_blocking(merge).await? {
             error!(cause=?e, "merge error");
         }
     }
     Ok(())
 }
 
+/// Get the time until the merge triggers are first checked, which is the configured merge delay
+/// if there's one.
+fn first_merge_delay(handle: &Handle) -> time::Duration {
+    match handle.ctx.get_conf().merge_delay_ms {
+        Some(ms) => time::Duration::from_millis(ms),
+        None => merge_delay(&handle.ctx.get_merge_strategy()),
+    }
+}
+
+/// Get the time until the next check of the merge triggers, which is the check interval spread
+/// randomly by the jitter.
+fn merge_delay(merge: &MergeStrategy) -> time::Duration {
+    let interval = time::Duration::from_millis(merge.check_interval_ms);
+    let jitter = interval.mul_f64(merge.check_jitter);
+    let dist = rand::distributions::Uniform::new_inclusive(interval - jitter, interval + jitter);
+    utils::with_rng(|rng| dist.sample(rng))
+}
+
 /// A periodic background task that forces disk synchronizations.
 #[tracing::instrument(skip(handle, shutdown))]
 async fn sync_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
@@ -299,6 +1336,9 @@ async fn sync_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(),
                     return Ok(());
                 },
             };
+            if handle.maintenance_paused() {
+                continue;
+            }
             let handle = handle.clone();
             if let Err(e) = tokio::task::spawn_blocking(move || handle.sync()).await? {
                 error!(cause=?e, "sync error");
@@ -308,84 +1348,264 @@ async fn sync_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(),
     Ok(())
 }
 
-/// Read the given directory, rebuild the KeyDir, and gather statistics about the Bitcask instance
-/// at that directory.
-#[allow(clippy::type_complexity)]
-fn rebuild_storage<P>(
-    path: P,
-) -> Result<
-    (
-        SkipMap<Bytes, KeyDirEntry>,
-        HashMap<u64, LogStatistics>,
-        u64,
-    ),
-    Error,
->
+/// A periodic background task that writes the coalesced overwrites once they have waited for the
+/// coalescing window.
+#[tracing::instrument(skip(handle, shutdown))]
+async fn coalesce_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
+    // Only run task if overwrites are coalesced. Without a window, the writer writes them out
+    // right away.
+    if let Some(window) = handle.ctx.get_conf().coalesce_window() {
+        if window.is_zero() {
+            return Ok(());
+        }
+        while !shutdown.is_shutdown() {
+            // Wake up the task when a specific interval has passed or when the storage is shutdown.
+            tokio::select! {
+                _ = tokio::time::sleep(window) => {},
+                _ = shutdown.recv() => {
+                    info!("stopping coalesced writes background task");
+                    return Ok(());
+                },
+            };
+            let handle = handle.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || handle.flush_coalesced()).await? {
+                error!(cause=?e, "coalesced writes error");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A periodic background task that writes checkpoints of the KeyDir.
+#[tracing::instrument(skip(handle, shutdown))]
+async fn checkpoint_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
+    // Only run task if checkpoints are enabled
+    if let Some(ms) = handle.ctx.get_conf().checkpoint_interval_ms {
+        let interval = time::Duration::from_millis(ms);
+        while !shutdown.is_shutdown() {
+            // Wake up the task when a specific interval has passed or when the storage is shutdown.
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = shutdown.recv() => {
+                    info!("stopping checkpoint background task");
+                    return Ok(());
+                },
+            };
+            let handle = handle.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || handle.checkpoint()).await? {
+                error!(cause=?e, "checkpoint error");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A periodic background task that scrubs the data files for corruption when the scrub policy
+/// allows it.
+#[tracing::instrument(skip(handle, shutdown))]
+async fn scrub_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
+    let conf = handle.ctx.get_conf();
+    // Only run task if the data files are scrubbed
+    if conf.scrub_policy == ScrubPolicy::Never {
+        return Ok(());
+    }
+    let interval = time::Duration::from_millis(conf.scrub_interval_ms);
+    while !shutdown.is_shutdown() {
+        // Wake up the task when a specific interval has passed or when the storage is shutdown.
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {},
+            _ = shutdown.recv() => {
+                info!("stopping scrub background task");
+                return Ok(());
+            },
+        };
+        if !can_scrub(&handle) {
+            continue;
+        }
+        // The scrub checks whether the storage is closed between entries, so it stops soon
+        // after a shutdown
+        let handle = handle.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || handle.scrub()).await? {
+            error!(cause=?e, "scrub error");
+        }
+    }
+    Ok(())
+}
+
+/// Return `true` if the scrub policy allows scrubbing now and maintenance isn't paused.
+fn can_scrub(handle: &Handle) -> bool {
+    if handle.maintenance_paused() {
+        return false;
+    }
+    match handle.ctx.get_conf().scrub_policy {
+        ScrubPolicy::Never => false,
+        ScrubPolicy::Always => true,
+        ScrubPolicy::Window { start, end } => utils::within_hours(start, end),
+    }
+}
+
+/// A periodic background task that adds copies of the active data file to the log archive.
+#[tracing::instrument(skip(handle, shutdown))]
+async fn log_archive_on_interval(handle: Handle, mut shutdown: Shutdown) -> Result<(), Error> {
+    // Only run task if the active file is archived periodically
+    let conf = handle.ctx.get_conf();
+    if let (Some(_), Some(ms)) = (&conf.log_archive_dir, conf.log_archive_interval_ms) {
+        let interval = time::Duration::from_millis(ms);
+        while !shutdown.is_shutdown() {
+            // Wake up the task when a specific interval has passed or when the storage is shutdown.
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = shutdown.recv() => {
+                    info!("stopping log archive background task");
+                    return Ok(());
+                },
+            };
+            let handle = handle.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || handle.archive_log()).await? {
+                error!(cause=?e, "log archive error");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rebuild the KeyDir from the data files with the given IDs in the storage directory, and gather
+/// statistics about the Bitcask instance. The last checkpoint is loaded first, if it's usable, so
+/// only the files that it doesn't cover are read. The statistics are taken from the marker of a
+/// clean shutdown instead, if the storage was closed cleanly. `progress` is called after each file is
+/// handled, and the rebuild stops if it returns an error.
+fn rebuild_storage<F>(
+    conf: &Config,
+    fileids: &[u64],
+    keydir: &DefaultKeyDir,
+    mut progress: F,
+) -> Result<HashMap<u64, LogStatistics>, Error>
 where
-    P: AsRef<Path>,
+    F: FnMut() -> Result<(), Error>,
 {
-    let keydir = SkipMap::default();
-    let mut stats = HashMap::default();
-    let fileids = utils::sorted_fileids(&path)?;
-
-    let mut active_fileid = None;
-    for fileid in fileids {
-        // Collect the most recent file id.
-        match &mut active_fileid {
-            None => active_fileid = Some(fileid),
-            Some(id) => {
-                if fileid > *id {
-                    *id = fileid;
-                }
+    let (path, layout) = (&conf.path, conf.layout());
+    let marker = match cleanshutdown::take(path, fileids) {
+        Ok(marker) => marker,
+        Err(e) => {
+            warn!(cause=?e, "ignoring shutdown marker");
+            None
+        }
+    };
+    let (mut stats, next_fileid) = match checkpoint::read(path, fileids) {
+        Ok(Some(checkpoint)) => {
+            info!(
+                next_fileid = checkpoint.next_fileid,
+                keys = checkpoint.entries.len(),
+                "loaded checkpoint"
+            );
+            for (key, entry) in checkpoint.entries {
+                keydir.insert(key, entry);
             }
+            (checkpoint.stats, checkpoint.next_fileid)
         }
-        // Read the hint file, if it does not exist, read the data file.
-        if let Err(e) = populate_keydir_with_hintfile(&path, fileid, &keydir, &mut stats) {
-            match e {
-                Error::Io(ref ioe) => match ioe.kind() {
-                    io::ErrorKind::NotFound => {
-                        populate_keydir_with_datafile(&path, fileid, &keydir, &mut stats)?;
-                    }
-                    _ => return Err(e),
-                },
-                _ => return Err(e),
+        Ok(None) => (HashMap::default(), 0),
+        Err(e) => {
+            warn!(cause=?e, "ignoring checkpoint");
+            (HashMap::default(), 0)
+        }
+    };
+
+    for &fileid in fileids {
+        if fileid < next_fileid {
+            progress()?;
+            continue;
+        }
+        // Read the hint file, if it does not exist or can't be trusted, read the data file.
+        match read_hintfile(path, layout, fileid) {
+            Ok(entries) => populate_keydir_with_hints(fileid, entries, keydir, &mut stats)?,
+            Err(Error::Io(ref ioe)) if ioe.kind() == io::ErrorKind::NotFound => {
+                populate_keydir_with_datafile(path, layout, fileid, keydir, &mut stats)?;
+            }
+            Err(Error::Corruption { reason, .. }) => {
+                warn!(fileid, reason, "falling back to the data file");
+                populate_keydir_with_datafile(path, layout, fileid, keydir, &mut stats)?;
             }
+            Err(e) => return Err(e),
         }
+        progress()?;
     }
+    // The statistics that the storage had when it was closed cleanly are exact, while those
+    // derived from hint files leave out the tombstones of the merge files
+    if let Some(marker) = marker {
+        info!(
+            active_fileid = marker.active_fileid,
+            "restored the statistics of a clean shutdown"
+        );
+        return Ok(marker.stats);
+    }
+    Ok(stats)
+}
 
-    let active_fileid = active_fileid.map(|id| id + 1).unwrap_or_default();
-    Ok((keydir, stats, active_fileid))
+/// Return the ID of the active file that comes after the files with the given IDs.
+fn next_fileid(fileids: &[u64]) -> u64 {
+    fileids.iter().max().map(|id| id + 1).unwrap_or_default()
 }
 
-/// Read the hint file with `fileid` in `path` and populate the given maps.
-fn populate_keydir_with_hintfile<P>(
-    path: P,
-    fileid: u64,
-    keydir: &SkipMap<Bytes, KeyDirEntry>,
-    stats: &mut HashMap<u64, LogStatistics>,
-) -> Result<(), Error>
+/// Read all entries of the hint file with `fileid` in `path`. Returns [`Error::Corruption`] if an
+/// entry fails its checksum, or if the trailer is missing or doesn't match the entries.
+fn read_hintfile<P>(path: P, layout: Layout, fileid: u64) -> Result<Vec<HintFileEntry>, Error>
 where
     P: AsRef<Path>,
 {
-    let file = log::open(utils::hintfile_name(&path, fileid))?;
-    let mut hintfile_iter = LogIterator::new(file)?;
-    while let Some((_, entry)) = hintfile_iter.next::<HintFileEntry>()? {
-        let keydir_entry = KeyDirEntry {
-            fileid,
-            len: entry.len,
-            pos: entry.pos,
-            tstamp: entry.tstamp,
-        };
+    let hintfile = utils::hintfile_name(&path, layout, fileid);
+    let file = log::open(&hintfile)?;
+    let mut hintfile_iter = LogIterator::new(file, FileHeader::Hint)?;
+    let mut entries = Vec::new();
+    loop {
+        match hintfile_iter.next::<HintFileRecord>() {
+            Ok(Some((_, HintFileRecord::Entry(entry)))) => entries.push(entry),
+            Ok(Some((_, HintFileRecord::Trailer(trailer)))) => {
+                if trailer.count != entries.len() as u64 {
+                    return Err(Error::corrupted_at(
+                        "hint file entry count mismatch",
+                        hintfile,
+                        None,
+                    ));
+                }
+                return Ok(entries);
+            }
+            Ok(None) => {
+                return Err(Error::corrupted_at(
+                    "hint file has no trailer",
+                    hintfile,
+                    None,
+                ))
+            }
+            Err(Error::Io(ioe)) if ioe.kind() == io::ErrorKind::InvalidData => {
+                return Err(Error::corrupted_at(
+                    "hint file has a malformed entry",
+                    hintfile,
+                    None,
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Populate the given maps with the entries of the hint file with `fileid`.
+fn populate_keydir_with_hints(
+    fileid: u64,
+    entries: Vec<HintFileEntry>,
+    keydir: &DefaultKeyDir,
+    stats: &mut HashMap<u64, LogStatistics>,
+) -> Result<(), Error> {
+    for entry in entries {
+        let keydir_entry =
+            KeyDirEntry::new(fileid, entry.len, entry.pos, entry.tstamp, entry.expiry)?;
         // Hint file always contains live keys
         stats.entry(fileid).or_default().add_live();
         // Overwrite previously written value
-        let prev_entry = keydir.get(&entry.key);
-        keydir.insert(entry.key, keydir_entry);
-        if let Some(prev_entry) = prev_entry {
+        if let Some(prev_entry) = keydir.insert(entry.key, keydir_entry) {
             stats
-                .entry(prev_entry.value().fileid)
+                .entry(prev_entry.fileid())
                 .or_default()
-                .overwrite(prev_entry.value().len);
+                .overwrite(prev_entry.len());
         }
     }
     Ok(())
@@ -393,16 +1613,19 @@ where
 
 fn populate_keydir_with_datafile<P>(
     path: P,
+    layout: Layout,
     fileid: u64,
-    keydir: &SkipMap<Bytes, KeyDirEntry>,
+    keydir: &DefaultKeyDir,
     stats: &mut HashMap<u64, LogStatistics>,
 ) -> Result<(), Error>
 where
     P: AsRef<Path>,
 {
-    let file = log::open(utils::datafile_name(&path, fileid))?;
-    let mut datafile_iter = LogIterator::new(file)?;
-    while let Some((datafile_index, datafile_entry)) = datafile_iter.next::<DataFileEntry>()? {
+    let file = log::open(utils::datafile_name(&path, layout, fileid))?;
+    let mut datafile_iter = DataFileIterator::new(file)?;
+    // The zero-filled space that the memory-mapped writer allocated up front fails the checksum
+    // of an entry, so the iteration stops at the end of the data of a file that wasn't truncated
+    while let Some((datafile_index, datafile_entry)) = datafile_iter.next()? {
         match datafile_entry.value {
             // Tombstone
             None => {
@@ -412,28 +1635,28 @@ where
                     .add_dead(datafile_index.len);
                 if let Some(prev_entry) = keydir.remove(&datafile_entry.key) {
                     stats
-                        .entry(prev_entry.value().fileid)
+                        .entry(prev_entry.fileid())
                         .or_default()
-                        .overwrite(prev_entry.value().len);
+                        .overwrite(prev_entry.len());
                 }
             }
             Some(_) => {
-                let keydir_entry = KeyDirEntry {
+                // Expired entries are kept so they shadow older values of the key
+                let keydir_entry = KeyDirEntry::new(
                     fileid,
-                    len: datafile_index.len,
-                    pos: datafile_index.pos,
-                    tstamp: datafile_entry.tstamp,
-                };
+                    datafile_index.len,
+                    datafile_index.pos,
+                    datafile_entry.tstamp,
+                    datafile_entry.expiry,
+                )?;
                 // Add live keys
                 stats.entry(fileid).or_default().add_live();
                 // Overwrite previous value
-                let prev_entry = keydir.get(&datafile_entry.key);
-                keydir.insert(datafile_entry.key, keydir_entry);
-                if let Some(prev_entry) = prev_entry {
+                if let Some(prev_entry) = keydir.insert(datafile_entry.key, keydir_entry) {
                     stats
-                        .entry(prev_entry.value().fileid)
+                        .entry(prev_entry.fileid())
                         .or_default()
-                        .overwrite(prev_entry.value().len);
+                        .overwrite(prev_entry.len());
                 }
             }
         }
@@ -448,6 +1671,71 @@ pub enum Error {
     #[error("Storage has been closed")]
     Closed,
 
+    /// Error from operating on a storage that is still rebuilding its KeyDir
+    #[error("Storage is recovering")]
+    Recovering,
+
+    /// Error from querying a secondary index that was not configured
+    #[error("Index does not exist - {0}")]
+    IndexNotFound(String),
+
+    /// Error from using a configuration with invalid settings
+    #[error("Invalid configuration - {0}")]
+    InvalidConfig(&'static str),
+
+    /// Error from reading data that fails its integrity checks
+    #[error("Corrupted data - {reason}{}", describe_location(.file.as_deref(), *.offset))]
+    Corruption {
+        /// What the integrity check found.
+        reason: &'static str,
+        /// The file that holds the corrupted data, if it's known.
+        file: Option<PathBuf>,
+        /// The position of the corrupted data within the file, if it's known.
+        offset: Option<u64>,
+    },
+
+    /// Error from a write that found too many writes waiting for the writer
+    #[error("Too many pending writes")]
+    Busy,
+
+    /// Error from a write that would take the storage over one of its quotas
+    #[error("Quota exceeded - {0}")]
+    QuotaExceeded(&'static str),
+
+    /// Error from writing an entry whose encoded length is over the max entry size
+    #[error("Entry too large - {len} bytes is over the max of {max} bytes")]
+    EntryTooLarge {
+        /// The encoded length of the entry.
+        len: u64,
+        /// The max length of an entry.
+        max: u64,
+    },
+
+    /// Error from reading a file that was written in a layout that isn't supported, such as a file
+    /// written by an older version before files had headers
+    #[error("Unsupported file format - {0}")]
+    UnsupportedFormat(&'static str),
+
+    /// Error from a key given by a user that starts with the prefix of the internal keys
+    #[error("Key starts with the reserved prefix")]
+    ReservedKey,
+
+    /// Error from a data file ID that is too large to be indexed
+    #[error("Limit exceeded - {0}")]
+    LimitExceeded(&'static str),
+
+    /// Error from waiting for a disk synchronization that was run by another writer and failed
+    #[error("Disk synchronization failed")]
+    SyncFailed,
+
+    /// Error from a value that is replaced while its chunks are read
+    #[error("Value was changed while it was read")]
+    ValueChanged,
+
+    /// Error from a change subscriber falling behind, carrying the number of missed changes
+    #[error("Change subscriber lagged behind by {0} changes")]
+    ChangesLagged(u64),
+
     /// Error from I/O operations.
     #[error("I/O error - {0}")]
     Io(#[from] io::Error),
@@ -456,33 +1744,73 @@ pub enum Error {
     #[error("Serialization error - {0}")]
     Serialization(#[from] bincode::Error),
 
+    /// Error from serializing or deserializing typed keys and values with a codec other than
+    /// bincode.
+    #[error("Codec error - {0}")]
+    Codec(#[source] Box<dyn std::error::Error + Send + Sync>),
+
     /// Error from running asynchronous tasks.
     #[error("Asynchronous task error - {0}")]
     AsyncTask(#[from] tokio::task::JoinError),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HintFileEntry {
-    tstamp: i64,
-    len: u64,
-    pos: u64,
-    key: Bytes,
+impl Error {
+    /// Create an [`Error::Corruption`] for data whose location isn't known.
+    pub(crate) fn corrupted(reason: &'static str) -> Self {
+        Self::Corruption {
+            reason,
+            file: None,
+            offset: None,
+        }
+    }
+
+    /// Create an [`Error::Corruption`] for data in the given file.
+    pub(crate) fn corrupted_at(reason: &'static str, file: PathBuf, offset: Option<u64>) -> Self {
+        Self::Corruption {
+            reason,
+            file: Some(file),
+            offset,
+        }
+    }
+
+    /// Return whether the operation that failed may succeed when it's retried as is, once the
+    /// storage is less busy or has finished recovering. The other errors need something to
+    /// change first, such as a smaller entry, more quota, or a repair of corrupted data.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Recovering | Self::Busy | Self::SyncFailed | Self::ValueChanged => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct DataFileEntry {
-    tstamp: i64,
-    key: Bytes,
-    value: Option<Bytes>,
+/// Describe where corrupted data was found, to be appended to the reason of the corruption.
+fn describe_location(file: Option<&Path>, offset: Option<u64>) -> String {
+    match (file, offset) {
+        (Some(file), Some(offset)) => format!(" in {} at offset {}", file.display(), offset),
+        (Some(file), None) => format!(" in {}", file.display()),
+        (None, _) => String::new(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::num::{NonZeroU64, NonZeroUsize};
+    use std::{
+        fs,
+        num::{NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize},
+    };
 
     use proptest::{collection, prelude::*};
 
-    use super::*;
+    use super::{
+        entry::{DataFileEntry, Encode},
+        *,
+    };
+    use crate::storage::Transfer;
 
     fn simple_test_config(path: &Path) -> Config {
         Config::default()
@@ -539,103 +1867,2097 @@ mod tests {
     }
 
     #[test]
-    fn bitcask_rebuilt_keydir_correctly() {
+    fn bitcask_range_returns_keys_in_order() {
         let dir = tempfile::tempdir().unwrap();
-        let conf = simple_test_config(dir.path());
-
-        // perform operations within a scope so our data store gets dropped
-        // before we rebuild it.
-        {
-            let kv = conf.clone().open().unwrap();
-            let handle = kv.get_handle();
-            // put 10000 different keys
-            for i in 0..10000 {
-                handle
-                    .put(
-                        Bytes::from(format!("key{i}")),
-                        Bytes::from(format!("value{i}")),
-                    )
-                    .unwrap();
-            }
-            // overwrite 5000 keys
-            for i in 0..5000 {
-                handle
-                    .put(
-                        Bytes::from(format!("key{i}")),
-                        Bytes::from(format!("value{i}")),
-                    )
-                    .unwrap();
-            }
-            // delete first 5000 keys
-            for i in 0..5000 {
-                handle.del(Bytes::from(format!("key{i}"))).unwrap();
-            }
-        }
+        let conf = simple_test_config(dir.path()).ordered_keys(true).to_owned();
 
-        // rebuild bitcask
         let kv = conf.open().unwrap();
         let handle = kv.get_handle();
-        // first 5000 keys should be deleted
-        for i in 0..5000 {
-            let value = handle.get(Bytes::from(format!("key{i}"))).unwrap();
-            assert!(value.is_none())
+        for i in (0..100).rev() {
+            handle
+                .put(Bytes::from(format!("key{i:03}")), Bytes::from("value"))
+                .unwrap();
         }
-        // get last 5000 keys
-        for i in 5000..10000 {
-            let value = handle.get(Bytes::from(format!("key{i}"))).unwrap().unwrap();
-            assert_eq!(Bytes::from(format!("value{i}")), value);
+        for i in 0..10 {
+            handle.del(Bytes::from(format!("key{i:03}"))).unwrap();
         }
+
+        let keys = handle
+            .range(Bytes::from("key005")..Bytes::from("key020"))
+            .unwrap();
+        let expected: Vec<_> = (10..20)
+            .map(|i| Bytes::from(format!("key{i:03}")))
+            .collect();
+        assert_eq!(expected, keys);
+
+        let keys = handle.range(Bytes::from("key095")..).unwrap();
+        assert_eq!(5, keys.len());
+        let keys = handle
+            .range(Bytes::from("key050")..Bytes::from("key040"))
+            .unwrap();
+        assert!(keys.is_empty());
     }
 
     #[test]
-    fn bitcask_rebuilt_stats_correctly() {
+    fn bitcask_deletes_keys_by_prefix() {
         let dir = tempfile::tempdir().unwrap();
-        let conf = simple_test_config(dir.path());
-
-        // perform operations within a scope so our data store gets dropped
-        // before we rebuild it.
-        {
-            let kv = conf.clone().open().unwrap();
-            let handle = kv.get_handle();
-            // put 10000 different keys
-            for i in 0..10000 {
-                handle
-                    .put(
-                        Bytes::from(format!("key{i}")),
-                        Bytes::from(format!("value{i}")),
-                    )
-                    .unwrap();
-            }
-            // overwrite 5000 keys
-            for i in 0..5000 {
-                handle
-                    .put(
-                        Bytes::from(format!("key{i}")),
-                        Bytes::from(format!("value{i}")),
-                    )
-                    .unwrap();
-            }
-            // delete 5000 keys
-            for i in 0..5000 {
-                handle.del(Bytes::from(format!("key{i}"))).unwrap();
-            }
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        // More keys than fit in one batch
+        for i in 0..3000 {
+            handle.put(format!("user:{i}").into(), "v".into()).unwrap();
         }
+        handle.put("users".into(), "v".into()).unwrap();
+        handle.put("user".into(), "v".into()).unwrap();
+
+        assert_eq!(3000, handle.delete_prefix(b"user:").unwrap());
+        assert_eq!(0, handle.delete_prefix(b"user:").unwrap());
+        assert_eq!(
+            vec![Bytes::from("user"), Bytes::from("users")],
+            handle.range(..).unwrap()
+        );
+
+        // The tombstones are kept when the KeyDir is rebuilt
+        drop(handle);
+        drop(kv);
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        assert_eq!(None, kv.get_handle().get("user:42".into()).unwrap());
+    }
 
-        // rebuild bitcask
-        let kv = conf.open().unwrap();
+    #[test]
+    fn bitcask_unlinks_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
         let handle = kv.get_handle();
-        // should get 5000 live keys and 15000 dead keys, in which:
-        // - 5000 live keys that were not overwritten or deleted.
-        // - 5000 dead keys resulted from overwriting.
-        // - 10000 dead keys resulted from deleting.
-        let mut lives = 0;
-        let mut deads = 0;
-        for (_, e) in handle.writer.lock().get_stats().iter() {
-            lives += e.live_keys();
-            deads += e.dead_keys();
-        }
-        assert_eq!(5000, lives);
-        assert_eq!(15000, deads);
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("b".into(), "1".into()).unwrap();
+
+        assert!(handle.unlink("a".into()).unwrap());
+        assert!(!handle.unlink("a".into()).unwrap());
+        assert_eq!(None, handle.get("a".into()).unwrap());
+        // The queued tombstones go before the later writes, so they don't delete the new value
+        assert!(handle.unlink("b".into()).unwrap());
+        handle.put("b".into(), "2".into()).unwrap();
+
+        // The tombstones are kept when the KeyDir is rebuilt
+        drop(handle);
+        drop(kv);
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.get("a".into()).unwrap());
+        assert_eq!(Some(Bytes::from("2")), handle.get("b".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_coalesces_overwrites_of_the_same_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.runtime(RuntimeMode::Manual).coalesce_window_ms(60_000);
+        let datafiles_len = || -> u64 {
+            utils::sorted_fileids(dir.path())
+                .unwrap()
+                .map(|id| {
+                    let name = utils::datafile_name(dir.path(), Layout::Flat, id);
+                    fs::metadata(name).unwrap().len()
+                })
+                .sum()
+        };
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "0".into()).unwrap();
+        let len = datafiles_len();
+        for i in 1..=10 {
+            handle.put("a".into(), i.to_string().into()).unwrap();
+        }
+        // The overwrites are read from the buffer before they're written
+        assert_eq!(len, datafiles_len());
+        assert_eq!(Some(Bytes::from("10")), handle.get("a".into()).unwrap());
+        assert_eq!(9, handle.stats().coalesced_writes);
+
+        // Writes that aren't coalesced write the buffer out first, as a single entry
+        handle.put("b".into(), "0".into()).unwrap();
+        let entries_len: u64 = [("a", "10"), ("b", "0")]
+            .into_iter()
+            .map(|(key, value)| {
+                DataFileEntry {
+                    tstamp: 0,
+                    key: key.into(),
+                    value: Some(value.into()),
+                    expiry: None,
+                }
+                .encoded_len()
+            })
+            .sum();
+        assert_eq!(len + entries_len, datafiles_len());
+        drop(handle);
+        drop(kv);
+        let kv = conf.open().unwrap();
+        assert_eq!(
+            Some(Bytes::from("10")),
+            kv.get_handle().get("a".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bitcask_update_applies_change_to_current_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let key = Bytes::from("counter");
+        for i in 0..10u64 {
+            let prev = handle
+                .update(key.clone(), |v| {
+                    let n: u64 = v.map_or(0, |v| std::str::from_utf8(&v).unwrap().parse().unwrap());
+                    (Update::Set(Bytes::from((n + 1).to_string())), n)
+                })
+                .unwrap();
+            assert_eq!(i, prev);
+        }
+        assert_eq!(Some(Bytes::from("10")), handle.get(key.clone()).unwrap());
+
+        handle.update(key.clone(), |_| (Update::Keep, ())).unwrap();
+        assert_eq!(Some(Bytes::from("10")), handle.get(key.clone()).unwrap());
+        handle
+            .update(key.clone(), |_| (Update::Delete, ()))
+            .unwrap();
+        assert_eq!(None, handle.get(key).unwrap());
+    }
+
+    #[test]
+    fn bitcask_expired_keys_are_hidden_and_merged_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        let now = time::SystemTime::now();
+        let later = now + time::Duration::from_secs(3600);
+        handle
+            .put_with_expiry("expired".into(), "value".into(), Some(now))
+            .unwrap();
+        handle
+            .put_with_expiry("live".into(), "value".into(), Some(later))
+            .unwrap();
+        assert_eq!(None, handle.get("expired".into()).unwrap());
+        assert_eq!(None, handle.get_expiry("expired".into()).unwrap());
+        assert_eq!(vec![Bytes::from("live")], handle.range(..).unwrap());
+
+        // Updating the value keeps the expiry, while setting it clears the expiry
+        handle
+            .update("live".into(), |_| (Update::Set("new".into()), ()))
+            .unwrap();
+        let expiry = handle.get_expiry("live".into()).unwrap().unwrap();
+        assert_eq!(
+            utils::to_timestamp(later),
+            utils::to_timestamp(expiry),
+            "expiry must be kept"
+        );
+        handle.put("live".into(), "new".into()).unwrap();
+        assert_eq!(None, handle.get_expiry("live".into()).unwrap());
+
+        // Merging writes a tombstone for the expired key, so it stays deleted after reopening
+        handle.writer.lock().merge().unwrap();
+        assert!(handle.ctx.get_keydir().get(b"expired").is_none());
+        drop(kv);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert!(handle.ctx.get_keydir().get(b"expired").is_none());
+        assert_eq!(Some(Bytes::from("new")), handle.get("live".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_merges_apply_the_compaction_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.index(IndexDefinition::new("value", |v| {
+            Some(Bytes::copy_from_slice(v))
+        }))
+        .compaction_filter(|key: &[u8], value: &[u8], _| {
+            if key.starts_with(b"tmp:") {
+                Decision::Remove
+            } else if key.starts_with(b"upper:") {
+                Decision::Change(value.to_ascii_uppercase().into())
+            } else {
+                Decision::Keep
+            }
+        });
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for key in ["tmp:a", "upper:a", "keep:a"] {
+            handle.put(key.into(), "value".into()).unwrap();
+        }
+        handle.writer.lock().merge().unwrap();
+        assert_eq!(None, handle.get("tmp:a".into()).unwrap());
+        assert_eq!(
+            Some(Bytes::from("VALUE")),
+            handle.get("upper:a".into()).unwrap()
+        );
+        assert_eq!(
+            vec![Bytes::from("upper:a")],
+            handle.lookup_index("value", b"VALUE").unwrap()
+        );
+        assert_eq!(
+            Some(Bytes::from("value")),
+            handle.get("keep:a".into()).unwrap()
+        );
+
+        // The removed keys stay deleted and the changed values are kept after reopening
+        drop(handle);
+        drop(kv);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.get("tmp:a".into()).unwrap());
+        assert_eq!(
+            Some(Bytes::from("VALUE")),
+            handle.get("upper:a".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bitcask_merges_apply_the_value_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.value_transform(|key: &[u8], value: &[u8]| {
+            if key == b"bad" {
+                return Err("can't transform".into());
+            }
+            if value.starts_with(b"v2:") {
+                return Ok(None);
+            }
+            Ok(Some([b"v2:", value].concat().into()))
+        });
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("b".into(), "v2:2".into()).unwrap();
+        handle.writer.lock().merge().unwrap();
+        assert_eq!(Some(Bytes::from("v2:1")), handle.get("a".into()).unwrap());
+        assert_eq!(Some(Bytes::from("v2:2")), handle.get("b".into()).unwrap());
+
+        // Values that are already transformed are copied as they are
+        handle.writer.lock().merge().unwrap();
+        assert_eq!(Some(Bytes::from("v2:1")), handle.get("a".into()).unwrap());
+
+        // Errors stop the merge without losing any value
+        handle.put("bad".into(), "3".into()).unwrap();
+        assert!(matches!(handle.writer.lock().merge(), Err(Error::Codec(_))));
+        assert_eq!(Some(Bytes::from("3")), handle.get("bad".into()).unwrap());
+        assert_eq!(Some(Bytes::from("v2:1")), handle.get("a".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_cursor_survives_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        let keys: Vec<Bytes> = (0..100).map(|i| format!("key{i:03}").into()).collect();
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for key in &keys {
+            handle.put(key.clone(), "value".into()).unwrap();
+        }
+        let mut cursor = handle.cursor();
+        let mut visited = cursor.next_batch(30).unwrap();
+        let token = cursor.token();
+
+        // Rewrite every key so the merge moves all entries, then reopen the storage
+        for key in &keys {
+            handle.put(key.clone(), "new".into()).unwrap();
+        }
+        handle.writer.lock().merge().unwrap();
+        drop(kv);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+
+        let mut cursor = handle.resume_cursor(token);
+        while !cursor.token().is_done() {
+            visited.extend(cursor.next_batch(30).unwrap());
+        }
+        assert_eq!(keys, visited);
+        assert!(cursor.next_batch(30).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bitcask_changes_are_published_in_commit_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .changes_capacity(NonZeroUsize::new(2).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let mut changes = handle.subscribe_changes();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.delete("a".into()).unwrap();
+
+        let change = changes.recv().await.unwrap();
+        assert_eq!(
+            (Bytes::from("a"), Some(Bytes::from("1"))),
+            (change.key, change.value)
+        );
+        let change = changes.recv().await.unwrap();
+        assert_eq!((Bytes::from("a"), None), (change.key, change.value));
+
+        // Falling behind by more than the capacity drops the oldest changes
+        for i in 0..3 {
+            handle.put("b".into(), i.to_string().into()).unwrap();
+        }
+        assert!(matches!(changes.recv().await, Err(Error::ChangesLagged(1))));
+        assert_eq!(Some(Bytes::from("1")), changes.recv().await.unwrap().value);
+    }
+
+    #[tokio::test]
+    async fn bitcask_stats_are_streamed_until_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        let mut stats = handle.stats_stream(time::Duration::from_millis(10));
+
+        assert_eq!(0, stats.recv().await.unwrap().live_keys);
+        handle.put("a".into(), "1".into()).unwrap();
+        assert_eq!(1, stats.recv().await.unwrap().live_keys);
+
+        drop(kv);
+        assert!(matches!(stats.recv().await, Err(Error::Closed)));
+    }
+
+    #[test]
+    fn bitcask_reload_rejects_invalid_merge_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        conf.merge_check_interval_ms(1000);
+        handle.reload(&conf).unwrap();
+        assert_eq!(1000, handle.ctx.get_merge_strategy().check_interval_ms);
+
+        conf.merge_check_interval_ms(2000);
+        conf.merge.check_jitter = 2.0;
+        assert!(matches!(handle.reload(&conf), Err(Error::InvalidConfig(_))));
+        assert_eq!(1000, handle.ctx.get_merge_strategy().check_interval_ms);
+    }
+
+    #[test]
+    fn bitcask_client_handle_reads_with_dedicated_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let client = handle.for_client();
+        handle.put("key".into(), "value".into()).unwrap();
+
+        // Hold the only shared reader so reads through the queue would spin
+        let reader = handle.readers.pop().unwrap();
+        assert_eq!(
+            Some(Bytes::from("value")),
+            client.get("key".into()).unwrap()
+        );
+        handle.readers.push(reader).unwrap();
+        assert_eq!(0, handle.stats().reader_waits);
+    }
+
+    #[test]
+    fn bitcask_rejects_writes_when_too_many_are_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .max_pending_writes(NonZeroUsize::new(1).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let writer = handle.lock_writer();
+        std::thread::scope(|s| {
+            let pending = s.spawn(|| handle.put("a".into(), "1".into()));
+            while handle.stats().pending_writes == 0 {
+                std::thread::yield_now();
+            }
+            assert!(matches!(
+                handle.put("b".into(), "2".into()),
+                Err(Error::Busy)
+            ));
+            assert!(matches!(handle.delete("a".into()), Err(Error::Busy)));
+            drop(writer);
+            pending.join().unwrap().unwrap();
+        });
+
+        let stats = handle.stats();
+        assert_eq!(0, stats.pending_writes);
+        assert_eq!(2, stats.busy_writes);
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+        handle.put("b".into(), "2".into()).unwrap();
+    }
+
+    #[test]
+    fn bitcask_keeps_open_files_within_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path())
+            .max_open_files(NonZeroUsize::new(2).unwrap())
+            .to_owned()
+            .open()
+            .unwrap();
+        let handle = kv.get_handle();
+        // Spread the values over several data files
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..200 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        assert!(utils::sorted_fileids(dir.path()).unwrap().count() > 2);
+
+        let clients: Vec<_> = (0..3).map(|_| handle.for_client()).collect();
+        for client in &clients {
+            for i in 0..200 {
+                let key = Bytes::from(format!("key{i}"));
+                assert_eq!(Some(value.clone()), client.get(key).unwrap());
+            }
+            assert!(handle.stats().open_files <= 2);
+        }
+        handle.writer.lock().merge().unwrap();
+        assert!(handle.stats().open_files <= 2);
+        assert_eq!(Some(value), handle.get("key42".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_readers_close_merged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        // Spread the values over several data files
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..200 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+
+        let client = handle.for_client();
+        for i in 0..200 {
+            let key = Bytes::from(format!("key{i}"));
+            assert_eq!(Some(value.clone()), client.get(key).unwrap());
+        }
+        assert!(handle.stats().open_files > 1);
+        handle.writer.lock().merge().unwrap();
+        // Only the merge file that holds the key stays open
+        assert_eq!(Some(value), client.get("key42".into()).unwrap());
+        assert_eq!(1, handle.stats().open_files);
+    }
+
+    #[test]
+    fn bitcask_rejects_writes_over_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path()).max_keys(2).to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("b".into(), "2".into()).unwrap();
+        assert!(matches!(
+            handle.put("c".into(), "3".into()),
+            Err(Error::QuotaExceeded("max keys"))
+        ));
+        // Overwriting an existing key does not add a key
+        handle.put("a".into(), "4".into()).unwrap();
+        handle.delete("b".into()).unwrap();
+        handle.put("c".into(), "3".into()).unwrap();
+        assert_eq!(2, handle.stats().live_keys);
+    }
+
+    #[test]
+    fn bitcask_transactions_write_nothing_when_they_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .max_keys(3)
+            .max_entry_size(NonZeroU32::new(1024).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+
+        // The last write is over the quota, which is only found when the writes are committed
+        let res = handle.atomically(|txn| {
+            txn.del("a".into())?;
+            txn.set("b".into(), "2".into())?;
+            txn.set("c".into(), "3".into())?;
+            txn.set("d".into(), "4".into())?;
+            txn.set("e".into(), "5".into())?;
+            // Reads see the staged writes
+            assert_eq!(None, txn.get("a".into())?);
+            assert_eq!(Some(Bytes::from("2")), txn.get("b".into())?);
+            Ok(())
+        });
+        assert!(
+            matches!(res, Err(Error::QuotaExceeded("max keys"))),
+            "{res:?}"
+        );
+
+        let res = handle.atomically(|txn| {
+            txn.set("b".into(), "2".into())?;
+            txn.set("c".into(), vec![0; 2048].into())
+        });
+        assert!(matches!(res, Err(Error::EntryTooLarge { .. })), "{res:?}");
+
+        let res = handle.atomically(|txn| {
+            txn.set("b".into(), "2".into())?;
+            Err::<(), _>(Error::Busy)
+        });
+        assert!(matches!(res, Err(Error::Busy)), "{res:?}");
+
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+        for key in ["b", "c", "d", "e"] {
+            assert_eq!(None, handle.get(key.into()).unwrap());
+        }
+        assert_eq!(1, handle.stats().live_keys);
+
+        // Deleting a key within the batch frees space for the others
+        handle
+            .atomically(|txn| {
+                txn.del("a".into())?;
+                txn.set("b".into(), "2".into())?;
+                txn.set("c".into(), "3".into())?;
+                txn.set("d".into(), "4".into())
+            })
+            .unwrap();
+        assert_eq!(3, handle.stats().live_keys);
+    }
+
+    #[test]
+    fn bitcask_partly_written_batches_are_dropped_on_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle
+            .atomically(|txn| {
+                txn.set("b".into(), "2".into())?;
+                txn.del("a".into())
+            })
+            .unwrap();
+        handle
+            .atomically(|txn| {
+                txn.set("c".into(), "3".into())?;
+                txn.set("d".into(), "4".into())?;
+                txn.del("b".into())
+            })
+            .unwrap();
+        drop(kv);
+
+        // Cut off the end of the last batch, as if the process crashed while it was written
+        let fileid = utils::sorted_fileids(dir.path()).unwrap().last().unwrap();
+        let datafile = utils::datafile_name(dir.path(), Layout::Flat, fileid);
+        let len = fs::metadata(&datafile).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&datafile)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.get("a".into()).unwrap());
+        assert_eq!(Some(Bytes::from("2")), handle.get("b".into()).unwrap());
+        assert_eq!(None, handle.get("c".into()).unwrap());
+        assert_eq!(None, handle.get("d".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_renames_are_written_as_one_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("src".into(), "value".into()).unwrap();
+        let transfer = handle.rename("src".into(), "dst".into(), true).unwrap();
+        assert_eq!(Transfer::Done, transfer);
+        drop(kv);
+
+        // A crash while the rename is written leaves the old key, rather than both keys or neither
+        let fileid = utils::sorted_fileids(dir.path()).unwrap().last().unwrap();
+        let datafile = utils::datafile_name(dir.path(), Layout::Flat, fileid);
+        let len = fs::metadata(&datafile).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&datafile)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(
+            Some(Bytes::from("value")),
+            handle.get("src".into()).unwrap()
+        );
+        assert_eq!(None, handle.get("dst".into()).unwrap());
+    }
+
+    #[test]
+    fn errors_tell_whether_they_are_retryable() {
+        assert!(Error::Busy.is_retryable());
+        assert!(Error::Recovering.is_retryable());
+        assert!(Error::SyncFailed.is_retryable());
+        assert!(Error::Io(io::ErrorKind::Interrupted.into()).is_retryable());
+        assert!(!Error::Io(io::ErrorKind::NotFound.into()).is_retryable());
+        assert!(!Error::Closed.is_retryable());
+        assert!(!Error::corrupted("bad checksum").is_retryable());
+        assert!(!Error::QuotaExceeded("max keys").is_retryable());
+        assert!(!Error::EntryTooLarge { len: 80, max: 64 }.is_retryable());
+    }
+
+    #[test]
+    fn bitcask_rejects_entries_over_max_entry_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .max_entry_size(NonZeroU32::new(64).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("small".into(), "value".into()).unwrap();
+        assert!(matches!(
+            handle.put("large".into(), Bytes::from(vec![0; 64])),
+            Err(Error::EntryTooLarge { max: 64, .. })
+        ));
+        assert!(handle.get("large".into()).unwrap().is_none());
+        assert_eq!(1, handle.stats().live_keys);
+    }
+
+    #[test]
+    fn bitcask_streams_values_larger_than_data_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .value_chunk_size(NonZeroU32::new(1000).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let value: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(
+            value.len() as u64,
+            handle.put_reader("blob".into(), value.as_slice()).unwrap()
+        );
+        let mut buf = Vec::new();
+        assert_eq!(
+            Some(value.len() as u64),
+            handle.get_writer("blob".into(), &mut buf).unwrap()
+        );
+        assert_eq!(value, buf);
+        // The manifest and the 205 chunks, which are not counted as keys
+        assert_eq!(206, handle.ctx.get_keydir().iter().count());
+        assert_eq!(1, handle.stats().live_keys);
+
+        // Replacing the value removes the previous chunks
+        handle.put_reader("blob".into(), &b"small"[..]).unwrap();
+        assert_eq!(2, handle.ctx.get_keydir().iter().count());
+        let mut buf = Vec::new();
+        handle.get_writer("blob".into(), &mut buf).unwrap();
+        assert_eq!(b"small", buf.as_slice());
+
+        // Values that were not streamed in are written as is
+        handle.put("plain".into(), "value".into()).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(
+            Some(5),
+            handle.get_writer("plain".into(), &mut buf).unwrap()
+        );
+        assert_eq!(b"value", buf.as_slice());
+        assert_eq!(None, handle.get_writer("missing".into(), &mut buf).unwrap());
+    }
+
+    #[test]
+    fn bitcask_streamed_values_own_their_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .value_chunk_size(NonZeroU32::new(100).unwrap())
+            .max_keys(3)
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from_iter((0..1000).map(|i| (i % 251) as u8));
+        let stored_keys = || handle.ctx.get_keydir().iter().count();
+        handle.put_reader("blob".into(), &value[..]).unwrap();
+        assert_eq!(11, stored_keys());
+
+        // The chunks are joined by plain reads, and hidden from scans and quotas
+        assert_eq!(Some(value.clone()), handle.get("blob".into()).unwrap());
+        assert_eq!(
+            vec![Some(value.clone()), None],
+            handle
+                .multi_get(&["blob".into(), "missing".into()])
+                .unwrap()
+        );
+        assert_eq!(vec![Bytes::from("blob")], handle.range(..).unwrap());
+        assert_eq!(1, handle.stats().live_keys);
+
+        // Copies get their own chunks, which outlive the original
+        handle
+            .atomically(|txn| txn.copy("blob".into(), "copy".into(), false))
+            .unwrap();
+        assert_eq!(22, stored_keys());
+        handle.put("blob".into(), "plain".into()).unwrap();
+        assert_eq!(12, stored_keys());
+        assert_eq!(Some(value.clone()), handle.get("copy".into()).unwrap());
+
+        // Renames move the chunks with the manifest
+        handle
+            .atomically(|txn| txn.rename("copy".into(), "moved".into(), false))
+            .unwrap();
+        assert_eq!(12, stored_keys());
+        assert_eq!(Some(value.clone()), handle.get("moved".into()).unwrap());
+        assert!(handle.delete("moved".into()).unwrap());
+        assert_eq!(1, stored_keys());
+
+        handle.put_reader("blob".into(), &value[..]).unwrap();
+        assert!(handle.unlink("blob".into()).unwrap());
+        assert_eq!(0, stored_keys());
+    }
+
+    #[test]
+    fn bitcask_rejects_reserved_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .value_chunk_size(NonZeroU32::new(100).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from_static(&[7; 1000]);
+        handle.put_reader("blob".into(), &value[..]).unwrap();
+        let chunk = handle
+            .ctx
+            .get_keydir()
+            .iter()
+            .map(|(k, _)| k)
+            .find(|k| chunks::is_chunk_key(k))
+            .unwrap();
+
+        fn is_rejected<T>(res: Result<T, Error>) -> bool {
+            matches!(res, Err(Error::ReservedKey))
+        }
+        assert!(is_rejected(handle.put(chunk.clone(), "x".into())));
+        assert!(is_rejected(handle.put_with_expiry(
+            chunk.clone(),
+            "x".into(),
+            None
+        )));
+        assert!(is_rejected(handle.delete(chunk.clone())));
+        assert!(is_rejected(handle.unlink(chunk.clone())));
+        assert!(is_rejected(
+            handle.update(chunk.clone(), |_| (Update::Delete, ()))
+        ));
+        assert!(is_rejected(
+            handle.put_reader("\x00bitcask\x00user".into(), &b"x"[..])
+        ));
+        for txn_op in [
+            |txn: &mut dyn Transaction<Error = Error>, key: Bytes| txn.set(key, "x".into()),
+            |txn: &mut dyn Transaction<Error = Error>, key: Bytes| txn.del(key).map(drop),
+            |txn: &mut dyn Transaction<Error = Error>, key: Bytes| txn.get(key).map(drop),
+            |txn: &mut dyn Transaction<Error = Error>, key: Bytes| {
+                txn.rename(key, "stolen".into(), true).map(drop)
+            },
+            |txn: &mut dyn Transaction<Error = Error>, key: Bytes| {
+                txn.copy("blob".into(), key, true).map(drop)
+            },
+        ] {
+            let chunk = chunk.clone();
+            assert!(is_rejected(
+                handle.atomically(move |txn| txn_op(txn, chunk))
+            ));
+        }
+
+        // The chunked value is untouched
+        assert_eq!(Some(value), handle.get("blob".into()).unwrap());
+        assert_eq!(None, handle.get("stolen".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_user_values_are_never_read_as_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .value_chunk_size(NonZeroU32::new(100).unwrap())
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put_reader("blob".into(), &[7; 1000][..]).unwrap();
+        let manifest = handle.read("blob".into()).unwrap().unwrap();
+        let escaped = Bytes::from_static(b"\x00bitcask\x00escaped\x00value");
+        handle.put("user".into(), manifest.clone()).unwrap();
+        handle
+            .atomically({
+                let escaped = escaped.clone();
+                move |txn| txn.set("escaped".into(), escaped)
+            })
+            .unwrap();
+        assert_eq!(Some(manifest), handle.get("user".into()).unwrap());
+        assert_eq!(Some(escaped), handle.get("escaped".into()).unwrap());
+
+        // Overwriting the user value leaves the chunks of the streamed value alone
+        handle.delete("user".into()).unwrap();
+        assert_eq!(
+            Some(Bytes::from_static(&[7; 1000])),
+            handle.get("blob".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bitcask_archives_merged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let conf = simple_test_config(dir.path())
+            .merge_archive_dir(&archive_dir)
+            .to_owned();
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("key".into(), "old".into()).unwrap();
+        handle.put("key".into(), "new".into()).unwrap();
+        let merged: Vec<u64> = utils::sorted_fileids(dir.path()).unwrap().collect();
+        handle.writer.lock().merge().unwrap();
+        drop(kv);
+
+        let archived: Vec<u64> = utils::sorted_fileids(&archive_dir).unwrap().collect();
+        assert_eq!(merged, archived);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(Some(Bytes::from("new")), handle.get("key".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_merges_around_the_page_cache() {
+        for merge_io in [MergeIo::DontNeed, MergeIo::Direct] {
+            let dir = tempfile::tempdir().unwrap();
+            let conf = simple_test_config(dir.path())
+                .mmap_advice(MmapAdvice::Random)
+                .merge_io(merge_io)
+                .to_owned();
+
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            // Spread the values over several data files, with a partial block at the end
+            let value = Bytes::from(vec![b'v'; 1000]);
+            for i in 0..150 {
+                handle.put(format!("key{i}").into(), value.clone()).unwrap();
+            }
+            for i in 0..150 {
+                handle.put(format!("key{i}").into(), value.clone()).unwrap();
+            }
+            handle.put("key0".into(), "new".into()).unwrap();
+            handle.writer.lock().merge().unwrap();
+            assert_eq!(Some(Bytes::from("new")), handle.get("key0".into()).unwrap());
+            assert_eq!(Some(value.clone()), handle.get("key149".into()).unwrap());
+            drop(kv);
+
+            let kv = conf.open().unwrap();
+            let handle = kv.get_handle();
+            assert_eq!(150, handle.stats().live_keys);
+            assert_eq!(Some(value), handle.get("key75".into()).unwrap());
+        }
+    }
+
+    #[test]
+    fn bitcask_merges_keep_retained_tombstones() {
+        let tombstones = |path: &Path| -> Vec<Bytes> {
+            let mut keys = Vec::new();
+            for fileid in utils::sorted_fileids(path).unwrap() {
+                let file = log::open(utils::datafile_name(path, Layout::Flat, fileid)).unwrap();
+                let mut iter = DataFileIterator::new(file).unwrap();
+                while let Some((_, entry)) = iter.next().unwrap() {
+                    if entry.value.is_none() {
+                        keys.push(entry.key);
+                    }
+                }
+            }
+            keys
+        };
+
+        for retention_ms in [None, Some(3_600_000)] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut conf = simple_test_config(dir.path());
+            if let Some(ms) = retention_ms {
+                conf.merge_tombstone_retention_ms(ms);
+            }
+            let kv = conf.open().unwrap();
+            let handle = kv.get_handle();
+            handle.put("deleted".into(), "value".into()).unwrap();
+            handle.delete("deleted".into()).unwrap();
+            handle.put("reset".into(), "old".into()).unwrap();
+            handle.delete("reset".into()).unwrap();
+            handle.put("reset".into(), "new".into()).unwrap();
+            handle.writer.lock().merge().unwrap();
+
+            let expected: Vec<Bytes> = match retention_ms {
+                Some(_) => vec!["deleted".into()],
+                None => vec![],
+            };
+            assert_eq!(expected, tombstones(dir.path()));
+            assert_eq!(None, handle.get("deleted".into()).unwrap());
+            assert_eq!(
+                Some(Bytes::from("new")),
+                handle.get("reset".into()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn bitcask_reads_values_as_of_past_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .merge_archive_dir(dir.path().join("archive"))
+            .to_owned();
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        // Leave some time around each instant, so it's strictly between two writes
+        let instant = || {
+            std::thread::sleep(time::Duration::from_millis(2));
+            let now = time::SystemTime::now();
+            std::thread::sleep(time::Duration::from_millis(2));
+            now
+        };
+
+        let before = instant();
+        handle.put("key".into(), "v1".into()).unwrap();
+        let at_v1 = instant();
+        handle.put("key".into(), "v2".into()).unwrap();
+        handle.writer.lock().merge().unwrap();
+        let at_v2 = instant();
+        handle.delete("key".into()).unwrap();
+        let deleted = instant();
+        handle.writer.lock().merge().unwrap();
+        handle.put("key".into(), "v3".into()).unwrap();
+
+        assert_eq!(None, handle.get_as_of("key".into(), before).unwrap());
+        // The first version is only kept in the archive
+        assert_eq!(
+            Some(Bytes::from("v1")),
+            handle.get_as_of("key".into(), at_v1).unwrap()
+        );
+        assert_eq!(
+            Some(Bytes::from("v2")),
+            handle.get_as_of("key".into(), at_v2).unwrap()
+        );
+        assert_eq!(None, handle.get_as_of("key".into(), deleted).unwrap());
+        assert_eq!(
+            Some(Bytes::from("v3")),
+            handle.get_as_of("key".into(), instant()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bitcask_switches_between_directory_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let flat = simple_test_config(dir.path())
+            .max_file_size(NonZeroU64::new(64).unwrap())
+            .to_owned();
+        let fanout = flat.clone().fanout(NonZeroU8::new(4).unwrap()).to_owned();
+        let keys: Vec<Bytes> = (0..20).map(|i| format!("key{i:02}").into()).collect();
+
+        let kv = fanout.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for key in &keys {
+            handle.put(key.clone(), key.clone()).unwrap();
+        }
+        handle.writer.lock().merge().unwrap();
+        drop(kv);
+        assert!(dir.path().join("data").join("00").is_dir());
+
+        for conf in [flat, fanout] {
+            let kv = conf.open().unwrap();
+            let handle = kv.get_handle();
+            for key in &keys {
+                assert_eq!(Some(key.clone()), handle.get(key.clone()).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn bitcask_evicts_least_recently_used_keys_over_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry_len = DataFileEntry {
+            tstamp: 0,
+            key: Bytes::from("k0"),
+            value: Some(Bytes::from("v0")),
+            expiry: None,
+        }
+        .encoded_len();
+        let conf = simple_test_config(dir.path())
+            .max_live_bytes(3 * entry_len)
+            .quota_policy(QuotaPolicy::Evict)
+            .to_owned();
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..3 {
+            handle
+                .put(format!("k{i}").into(), format!("v{i}").into())
+                .unwrap();
+        }
+        // Reading a key makes it the most recently used
+        handle.get("k0".into()).unwrap();
+        for i in 3..5 {
+            handle
+                .put(format!("k{i}").into(), format!("v{i}").into())
+                .unwrap();
+        }
+        let live_keys = handle.range(..).unwrap();
+        assert_eq!(vec!["k0", "k3", "k4"], live_keys);
+        let stats = handle.stats();
+        assert_eq!(3 * entry_len, stats.live_bytes);
+        assert_eq!(2, stats.evicted_keys);
+
+        // A value that can't fit even after evicting every other key is rejected
+        assert!(matches!(
+            handle.put("big".into(), Bytes::from(vec![0; 4 * entry_len as usize])),
+            Err(Error::QuotaExceeded("max live bytes"))
+        ));
+        handle.writer.lock().sync().unwrap();
+        drop(kv);
+
+        // Usage is recomputed when the storage is reopened
+        let kv = conf.open().unwrap();
+        let stats = kv.get_handle().stats();
+        assert_eq!(3, stats.live_keys);
+        assert_eq!(3 * entry_len, stats.live_bytes);
+    }
+
+    #[test]
+    fn bitcask_evicts_least_frequently_used_keys_over_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .max_keys(3)
+            .quota_policy(QuotaPolicy::Evict)
+            .eviction_policy(EvictionPolicy::Lfu)
+            .to_owned();
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        // The frequencies grow logarithmically, so the reads are far apart to be told apart
+        for (i, reads) in [1000, 1, 100].into_iter().enumerate() {
+            let key = Bytes::from(format!("k{i}"));
+            handle.put(key.clone(), "v".into()).unwrap();
+            for _ in 0..reads {
+                handle.get(key.clone()).unwrap();
+            }
+        }
+        handle.put("k3".into(), "v".into()).unwrap();
+        assert_eq!(vec!["k0", "k2", "k3"], handle.range(..).unwrap());
+    }
+
+    #[test]
+    fn bitcask_tracks_access_frequencies_when_evicting() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("key".into(), "value".into()).unwrap();
+        assert!(matches!(
+            handle.access_frequency("key".into()),
+            Err(Error::InvalidConfig(_))
+        ));
+        drop(kv);
+
+        let conf = simple_test_config(dir.path())
+            .quota_policy(QuotaPolicy::Evict)
+            .to_owned();
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.access_frequency("none".into()).unwrap());
+
+        handle.put("new".into(), "value".into()).unwrap();
+        let created = handle.access_frequency("new".into()).unwrap().unwrap();
+        for _ in 0..100 {
+            handle.get("new".into()).unwrap();
+        }
+        let accessed = handle.access_frequency("new".into()).unwrap().unwrap();
+        assert!(accessed > created);
+    }
+
+    #[test]
+    fn bitcask_reports_hot_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("key".into(), "value".into()).unwrap();
+        assert!(handle.hot_keys(10).is_empty());
+        drop(kv);
+
+        let conf = simple_test_config(dir.path())
+            .hot_keys(NonZeroUsize::new(8).unwrap())
+            .to_owned();
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("hot".into(), "value".into()).unwrap();
+        handle.put("warm".into(), "value".into()).unwrap();
+        for i in 0..20 {
+            handle
+                .put(format!("cold{i}").into(), "value".into())
+                .unwrap();
+            handle.get("hot".into()).unwrap();
+            handle.get("hot".into()).unwrap();
+            handle.get("warm".into()).unwrap();
+        }
+        let hot_keys: Vec<_> = handle.hot_keys(2).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(vec![Bytes::from("hot"), Bytes::from("warm")], hot_keys);
+    }
+
+    #[test]
+    fn bitcask_idle_time_is_reset_by_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.idle_time("key".into()).unwrap());
+
+        handle.put("key".into(), "value".into()).unwrap();
+        std::thread::sleep(time::Duration::from_millis(50));
+        let idle_time = handle.idle_time("key".into()).unwrap().unwrap();
+        assert!(idle_time >= time::Duration::from_millis(50));
+
+        handle.get("key".into()).unwrap();
+        let idle_time = handle.idle_time("key".into()).unwrap().unwrap();
+        assert!(idle_time < time::Duration::from_millis(50));
+    }
+
+    #[test]
+    fn bitcask_mmap_write_mode_recovers_untruncated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .write_mode(WriteMode::Mmap)
+            .to_owned();
+
+        // Leaking the storage skips truncating the active file, as if the process crashed
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..1000 {
+            handle
+                .put(format!("key{i}").into(), "value".into())
+                .unwrap();
+        }
+        handle.delete("key0".into()).unwrap();
+        std::mem::forget(kv);
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(None, handle.get("key0".into()).unwrap());
+        for i in 1..1000 {
+            let value = handle.get(format!("key{i}").into()).unwrap();
+            assert_eq!(Some(Bytes::from("value")), value);
+        }
+        assert_eq!(999, handle.range(..).unwrap().len());
+    }
+
+    #[test]
+    fn bitcask_corrupted_hint_files_fall_back_to_data_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..100 {
+            handle
+                .put(format!("key{i}").into(), "value".into())
+                .unwrap();
+        }
+        handle.lock_writer().merge().unwrap();
+        drop(kv);
+
+        // Flip a byte in the middle of every hint file
+        let mut hintfiles: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "hint"))
+            .collect();
+        hintfiles.sort();
+        assert!(!hintfiles.is_empty());
+        for path in &hintfiles {
+            let mut buf = fs::read(path).unwrap();
+            let mid = buf.len() / 2;
+            buf[mid] ^= 0xff;
+            fs::write(path, buf).unwrap();
+        }
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..100 {
+            let value = handle.get(format!("key{i}").into()).unwrap();
+            assert_eq!(Some(Bytes::from("value")), value);
+        }
+    }
+
+    #[test]
+    fn bitcask_secondary_index_rebuilt_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .index(IndexDefinition::json_field("email", "email"))
+            .to_owned();
+
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            for i in 0..100 {
+                let value = format!(r#"{{"email":"user{}@example.com"}}"#, i % 10);
+                handle
+                    .put(Bytes::from(format!("key{i:03}")), Bytes::from(value))
+                    .unwrap();
+            }
+            handle.del(Bytes::from("key000")).unwrap();
+            let keys = handle.lookup_index("email", b"user0@example.com").unwrap();
+            assert_eq!(9, keys.len());
+        }
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        let keys = handle.lookup_index("email", b"user0@example.com").unwrap();
+        let expected: Vec<_> = (1..10)
+            .map(|i| Bytes::from(format!("key{:03}", i * 10)))
+            .collect();
+        assert_eq!(expected, keys);
+        assert!(matches!(
+            handle.lookup_index("name", b"user0"),
+            Err(Error::IndexNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn bitcask_open_background_rejects_operations_until_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            for i in 0..100 {
+                handle
+                    .put(format!("key{i}").into(), format!("value{i}").into())
+                    .unwrap();
+            }
+        }
+
+        let kv = conf.open_background().unwrap();
+        let handle = kv.get_handle();
+        loop {
+            match handle.get("key0".into()) {
+                Err(Error::Recovering) => {
+                    let progress = handle.recovery_progress();
+                    if let Some(progress) = progress {
+                        assert!(progress.files_recovered <= progress.files_total);
+                    }
+                    std::thread::sleep(time::Duration::from_millis(1));
+                }
+                result => {
+                    assert_eq!(Some(Bytes::from("value0")), result.unwrap());
+                    break;
+                }
+            }
+        }
+        assert_eq!(None, handle.recovery_progress());
+        assert_eq!(100, handle.stats().live_keys);
+        handle.put("key100".into(), "value100".into()).unwrap();
+    }
+
+    #[test]
+    fn bitcask_restores_keydir_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            for i in 0..10 {
+                handle.put(format!("key{i}").into(), "old".into()).unwrap();
+            }
+            handle.checkpoint().unwrap();
+            // Writes after the checkpoint are replayed from the data files
+            handle.put("key0".into(), "new".into()).unwrap();
+            handle.delete("key1".into()).unwrap();
+            handle.put("key10".into(), "new".into()).unwrap();
+        }
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(Some(Bytes::from("new")), handle.get("key0".into()).unwrap());
+        assert_eq!(None, handle.get("key1".into()).unwrap());
+        assert_eq!(Some(Bytes::from("old")), handle.get("key2".into()).unwrap());
+        assert_eq!(
+            Some(Bytes::from("new")),
+            handle.get("key10".into()).unwrap()
+        );
+        assert_eq!(10, handle.stats().live_keys);
+    }
+
+    #[test]
+    fn bitcask_removes_merged_files_once_their_readers_close_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("a".into(), "2".into()).unwrap();
+        let deleted_files = || {
+            fs::read_dir(dir.path())
+                .unwrap()
+                .filter(|entry| {
+                    let path = entry.as_ref().unwrap().path();
+                    path.extension().is_some_and(|ext| ext == "deleted")
+                })
+                .count()
+        };
+
+        // A client keeps the merged file open until its next read
+        let client = handle.for_client();
+        assert_eq!(Some(Bytes::from("2")), client.get("a".into()).unwrap());
+        handle.writer.lock().merge().unwrap();
+        assert_eq!(1, deleted_files());
+        assert_eq!(Some(Bytes::from("2")), client.get("a".into()).unwrap());
+        assert_eq!(0, deleted_files());
+        assert_eq!(0, handle.stats().read_retries);
+    }
+
+    #[test]
+    fn bitcask_idle_readers_close_merged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("a".into(), "2".into()).unwrap();
+        let deleted_files = || {
+            fs::read_dir(dir.path())
+                .unwrap()
+                .filter(|entry| {
+                    let path = entry.as_ref().unwrap().path();
+                    path.extension().is_some_and(|ext| ext == "deleted")
+                })
+                .count()
+        };
+
+        // The pooled reader opens the file, then sits idle in the queue during the merge
+        assert_eq!(Some(Bytes::from("2")), handle.get("a".into()).unwrap());
+        handle.force_merge().unwrap();
+        assert_eq!(0, deleted_files());
+        assert_eq!(Some(Bytes::from("2")), handle.get("a".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_retries_reads_of_removed_files_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+
+        // Point the key at a data file that doesn't exist, as if a merge removed it and the key
+        // was never moved
+        let entry = handle.ctx.get_keydir().get(&Bytes::from("a")).unwrap();
+        let missing = KeyDirEntry::new(99, entry.len(), entry.pos(), entry.tstamp(), None).unwrap();
+        handle.ctx.keydir_set("a".into(), missing);
+        assert!(matches!(
+            handle.get("a".into()),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        let stats = handle.stats();
+        assert_eq!(1, stats.read_retries);
+        assert_eq!(1, stats.failed_read_retries);
+
+        // Reads that succeed aren't retried
+        handle.ctx.keydir_set("a".into(), entry);
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+        assert_eq!(1, handle.stats().read_retries);
+    }
+
+    #[test]
+    fn bitcask_verified_reads_detect_mismatched_keydir_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.read_verification(NonZeroU32::new(1).unwrap());
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.put("b".into(), "2".into()).unwrap();
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+
+        // Point the key at the entry of another key. The read finds the mismatch and repairs
+        // the entry from the data files instead of returning the value of the other key.
+        let a_entry = handle.ctx.get_keydir().get(&Bytes::from("a")).unwrap();
+        let b_entry = handle.ctx.get_keydir().get(&Bytes::from("b")).unwrap();
+        handle.ctx.keydir_set("a".into(), b_entry);
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+        let repaired = handle.ctx.get_keydir().get(&Bytes::from("a")).unwrap();
+        assert_eq!(a_entry.pos(), repaired.pos());
+        assert_eq!(a_entry.tstamp(), repaired.tstamp());
+        let stats = handle.stats();
+        assert_eq!(1, stats.failed_verifications);
+        assert_eq!(1, stats.repaired_reads);
+
+        // Keys that were deleted are removed from KeyDir
+        handle.delete("a".into()).unwrap();
+        handle.ctx.keydir_set("a".into(), b_entry);
+        assert_eq!(
+            None,
+            handle.multi_get(&["a".into(), "b".into()]).unwrap()[0]
+        );
+        assert!(handle.ctx.get_keydir().get(&Bytes::from("a")).is_none());
+        let stats = handle.stats();
+        assert_eq!(2, stats.failed_verifications);
+        assert_eq!(2, stats.repaired_reads);
+    }
+
+    #[test]
+    fn bitcask_multi_get_returns_values_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .concurrency(NonZeroUsize::new(4).unwrap())
+            .to_owned();
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        // Spread the values over several data files
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..200 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        handle.put("key7".into(), "new".into()).unwrap();
+        assert!(utils::sorted_fileids(dir.path()).unwrap().count() > 2);
+
+        let keys: Vec<Bytes> = ["key150", "missing", "key7", "key0", "key199", "key150"]
+            .into_iter()
+            .map(Bytes::from)
+            .collect();
+        let values = handle.multi_get(&keys).unwrap();
+        let expected: Vec<_> = keys
+            .iter()
+            .map(|k| handle.get(k.clone()).unwrap())
+            .collect();
+        assert_eq!(expected, values);
+        assert_eq!(Some(Bytes::from("new")), values[2]);
+        assert_eq!(None, values[1]);
+        assert_eq!(0, handle.stats().readers_in_use);
+    }
+
+    #[test]
+    fn bitcask_warmup_prefetches_live_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..50 {
+            handle
+                .put(format!("user:{i}").into(), value.clone())
+                .unwrap();
+            handle
+                .put(format!("item:{i}").into(), value.clone())
+                .unwrap();
+        }
+        handle.delete("user:0".into()).unwrap();
+
+        let keys = vec![Bytes::from("user:1"), "user:0".into(), "missing".into()];
+        assert_eq!(1, handle.warmup(&keys).unwrap());
+        assert_eq!(49, handle.warmup_prefix(b"user:").unwrap());
+        assert_eq!(0, handle.warmup_prefix(b"order:").unwrap());
+        assert_eq!(Some(value), handle.get("item:7".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_refuses_data_files_without_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        // A data file of an older version, whose entries were serialized with bincode
+        let entry = bincode::serialize(&(1i64, "key", Some("value"))).unwrap();
+        fs::write(utils::datafile_name(dir.path(), Layout::Flat, 0), entry).unwrap();
+        let res = simple_test_config(dir.path()).open();
+        assert!(matches!(res, Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn bitcask_durable_puts_are_visible_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            std::thread::scope(|s| {
+                for i in 0..8 {
+                    let handle = handle.clone();
+                    s.spawn(move || {
+                        let key = Bytes::from(format!("key{i}"));
+                        handle
+                            .put_durable(key, "batched".into(), Durability::Batched)
+                            .unwrap();
+                    });
+                }
+            });
+            handle
+                .put_durable("key0".into(), "immediate".into(), Durability::Immediate)
+                .unwrap();
+            handle
+                .put_durable("key1".into(), "none".into(), Durability::None)
+                .unwrap();
+        }
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(
+            Some(Bytes::from("immediate")),
+            handle.get("key0".into()).unwrap()
+        );
+        assert_eq!(
+            Some(Bytes::from("none")),
+            handle.get("key1".into()).unwrap()
+        );
+        assert_eq!(
+            Some(Bytes::from("batched")),
+            handle.get("key7".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn bitcask_runs_maintenance_when_driver_is_ticked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.runtime(RuntimeMode::Manual).checkpoint_interval_ms(0);
+        let kv = conf.open().unwrap();
+        kv.get_handle().put("key".into(), "value".into()).unwrap();
+
+        let mut driver = kv.maintenance_driver();
+        assert!(!dir.path().join("keydir.checkpoint").exists());
+        driver.tick().unwrap();
+        assert!(dir.path().join("keydir.checkpoint").exists());
+
+        drop(kv);
+        assert!(matches!(driver.tick(), Err(Error::Closed)));
+    }
+
+    #[test]
+    fn bitcask_merged_files_are_rotated_at_the_merge_output_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path())
+            .merge_output_file_size(NonZeroU64::new(1024 * 1024).unwrap())
+            .to_owned()
+            .open()
+            .unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for _ in 0..2 {
+            for i in 0..300 {
+                handle.put(format!("key{i}").into(), value.clone()).unwrap();
+            }
+        }
+        assert!(utils::sorted_fileids(dir.path()).unwrap().count() > 2);
+
+        handle.writer.lock().merge().unwrap();
+        // The live values fit in one merged file besides the active file
+        assert_eq!(2, utils::sorted_fileids(dir.path()).unwrap().count());
+        assert_eq!(Some(value), handle.get("key299".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_interrupted_merges_keep_the_completed_merge_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .merge_output_file_size(NonZeroU64::new(4096).unwrap())
+            .to_owned();
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for _ in 0..2 {
+            for i in 0..200 {
+                handle
+                    .put(format!("key{i:03}").into(), value.clone())
+                    .unwrap();
+            }
+        }
+        let active_fileid = handle
+            .writer
+            .lock()
+            .closed_fileids()
+            .unwrap()
+            .last()
+            .unwrap()
+            + 1;
+
+        // Each merge file holds 4 entries, and the third one can't be created
+        let blocked = utils::hintfile_name(dir.path(), Layout::Flat, active_fileid + 3);
+        std::fs::write(&blocked, b"").unwrap();
+        assert!(handle.writer.lock().merge().is_err());
+        assert!(!blocked.exists());
+        let kept = handle
+            .ctx
+            .get_keydir()
+            .iter()
+            .filter(|(_, e)| e.fileid() > active_fileid && e.fileid() < active_fileid + 3)
+            .count();
+        assert_eq!(8, kept);
+
+        // Later writes go after the kept merge files
+        handle.put("key200".into(), value.clone()).unwrap();
+        let entry = handle.ctx.get_keydir().get(b"key200").unwrap();
+        assert!(entry.fileid() > active_fileid + 3);
+
+        handle.writer.lock().merge().unwrap();
+        drop(kv);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..=200 {
+            let key = Bytes::from(format!("key{i:03}"));
+            assert_eq!(Some(value.clone()), handle.get(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn bitcask_merges_on_open_and_after_the_merge_delay() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conf = simple_test_config(dir.path());
+        conf.runtime(RuntimeMode::Manual)
+            .merge_check_interval_ms(3_600_000);
+        // Overwrite every value, so the older files are only dead bytes
+        let value = Bytes::from(vec![b'v'; 1024]);
+        let overwrite = |kv: &Bitcask| {
+            let handle = kv.get_handle();
+            for _ in 0..4 {
+                for i in 0..100 {
+                    handle.put(format!("key{i}").into(), value.clone()).unwrap();
+                }
+            }
+        };
+        let count_files = || utils::sorted_fileids(dir.path()).unwrap().count();
+
+        let kv = conf.clone().open().unwrap();
+        overwrite(&kv);
+        drop(kv);
+        let files_before_merge = count_files();
+
+        let mut kv = conf.clone().merge_on_open(true).to_owned().open().unwrap();
+        kv.background_tasks.take().unwrap().join().unwrap().unwrap();
+        assert!(count_files() < files_before_merge);
+        assert_eq!(
+            Some(value.clone()),
+            kv.get_handle().get("key42".into()).unwrap()
+        );
+
+        overwrite(&kv);
+        let files_before_merge = count_files();
+        let mut driver = kv.maintenance_driver();
+        driver.tick().unwrap();
+        assert_eq!(files_before_merge, count_files());
+        drop(driver);
+        drop(kv);
+
+        let kv = conf.merge_delay_ms(0).to_owned().open().unwrap();
+        let mut driver = kv.maintenance_driver();
+        driver.tick().unwrap();
+        assert!(count_files() < files_before_merge);
+        assert_eq!(Some(value), kv.get_handle().get("key42".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_skips_paused_maintenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path())
+            .runtime(RuntimeMode::Manual)
+            .merge_delay_ms(0)
+            .merge_check_interval_ms(0)
+            .to_owned()
+            .open()
+            .unwrap();
+        let handle = kv.get_handle();
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for _ in 0..4 {
+            for i in 0..100 {
+                handle.put(format!("key{i}").into(), value.clone()).unwrap();
+            }
+        }
+        let count_files = || utils::sorted_fileids(dir.path()).unwrap().count();
+        let files_before_merge = count_files();
+
+        let mut driver = kv.maintenance_driver();
+        handle.pause_maintenance();
+        assert!(handle.maintenance_paused());
+        driver.tick().unwrap();
+        assert_eq!(files_before_merge, count_files());
+
+        handle.resume_maintenance();
+        driver.tick().unwrap();
+        assert!(count_files() < files_before_merge);
+    }
+
+    #[test]
+    fn bitcask_restores_the_archived_log_to_a_point_in_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let restore_dir = dir.path().join("restore");
+        fs::create_dir(dir.path().join("db")).unwrap();
+        let mut conf = simple_test_config(&dir.path().join("db"));
+        conf.log_archive_dir(&archive_dir);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+
+        // Spread the values over several data files
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..150 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        handle.delete("key0".into()).unwrap();
+        let time = time::SystemTime::now();
+        std::thread::sleep(time::Duration::from_millis(1));
+        handle.put("key1".into(), "new".into()).unwrap();
+        handle.delete("key2".into()).unwrap();
+        handle.writer.lock().merge().unwrap();
+        handle.archive_log().unwrap();
+
+        assert_eq!(
+            151,
+            Bitcask::restore_to(&archive_dir, &restore_dir, time).unwrap()
+        );
+        let restored = Config::default()
+            .path(&restore_dir)
+            .to_owned()
+            .open()
+            .unwrap();
+        let restored = restored.get_handle();
+        assert_eq!(149, restored.stats().live_keys);
+        assert_eq!(None, restored.get("key0".into()).unwrap());
+        assert_eq!(Some(value.clone()), restored.get("key1".into()).unwrap());
+        assert_eq!(Some(value), restored.get("key2".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_scrubs_find_and_repair_corrupt_data_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let db_dir = dir.path().join("db");
+        fs::create_dir(&db_dir).unwrap();
+        let mut conf = simple_test_config(&db_dir);
+        conf.log_archive_dir(&archive_dir);
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+
+        // Spread the values over several data files
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..150 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        let report = handle.scrub().unwrap();
+        assert!(report.files >= 2);
+        assert!(report.corrupt_files.is_empty());
+        assert_eq!(report.bytes, handle.stats().scrubbed_bytes);
+
+        // The archived copies are hard links, so give the first file a copy of its own
+        let datafile = |fileid| utils::datafile_name(&db_dir, Layout::Flat, fileid);
+        let archived = utils::datafile_name(&archive_dir, Layout::Flat, 0);
+        let intact = fs::read(&archived).unwrap();
+        fs::remove_file(&archived).unwrap();
+        fs::write(&archived, &intact).unwrap();
+
+        // Change the timestamp of the first entry of the first file, and the flags of the first
+        // entry of the second file
+        let corrupt = |fileid, offset: usize| {
+            let mut buf = fs::read(datafile(fileid)).unwrap();
+            buf[offset] ^= 0xff;
+            fs::write(datafile(fileid), buf).unwrap();
+        };
+        let first_entry = FileHeader::LEN as usize;
+        corrupt(0, first_entry);
+        corrupt(1, first_entry + 8);
+
+        let report = handle.scrub().unwrap();
+        assert_eq!(vec![0], report.repaired_files);
+        assert_eq!(vec![1], report.corrupt_files);
+        assert_eq!(intact, fs::read(datafile(0)).unwrap());
+        let stats = handle.stats();
+        assert_eq!(2, stats.corrupt_files);
+        assert_eq!(1, stats.repaired_files);
+    }
+
+    #[test]
+    fn bitcask_scrubs_find_flipped_bits_in_values_and_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+
+        // Overwrite the values, so the KeyDir doesn't point to any entry of the first file
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for _ in 0..2 {
+            for i in 0..150 {
+                handle.put(format!("key{i}").into(), value.clone()).unwrap();
+            }
+        }
+        assert!(handle.ctx.get_keydir().iter().all(|(_, e)| e.fileid() != 0));
+        // Flip a bit in the value of the first entry of the first file, and in the magic of the
+        // second file
+        let datafile = |fileid| utils::datafile_name(dir.path(), Layout::Flat, fileid);
+        let mut buf = fs::read(datafile(0)).unwrap();
+        let value_pos = buf.iter().position(|&b| b == b'v').unwrap();
+        buf[value_pos + 100] ^= 0x01;
+        fs::write(datafile(0), buf).unwrap();
+        let mut buf = fs::read(datafile(1)).unwrap();
+        buf[0] ^= 0x01;
+        fs::write(datafile(1), buf).unwrap();
+
+        let report = handle.scrub().unwrap();
+        assert_eq!(vec![0, 1], report.corrupt_files);
+        assert!(report.repaired_files.is_empty());
+    }
+
+    #[test]
+    fn bitcask_incremental_backups_follow_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backup");
+        fs::create_dir(dir.path().join("db")).unwrap();
+        let kv = simple_test_config(&dir.path().join("db")).open().unwrap();
+        let handle = kv.get_handle();
+
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..100 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        let full = handle.backup_incremental(None, &backup_dir).unwrap();
+        for i in 0..50 {
+            handle.delete(format!("key{i}").into()).unwrap();
+        }
+        handle.writer.lock().merge().unwrap();
+        handle.put("key100".into(), value.clone()).unwrap();
+        let incremental = handle.backup_incremental(Some(&full), &backup_dir).unwrap();
+
+        assert_eq!(incremental, BackupManifest::load(&backup_dir).unwrap());
+        let restored = Config::default()
+            .path(&backup_dir)
+            .to_owned()
+            .open()
+            .unwrap();
+        let restored = restored.get_handle();
+        assert_eq!(51, restored.stats().live_keys);
+        assert_eq!(None, restored.get("key0".into()).unwrap());
+        assert_eq!(Some(value), restored.get("key100".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_moves_merged_files_to_the_cold_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let cold_dir = dir.path().join("cold");
+        let backup_dir = dir.path().join("backup");
+        fs::create_dir(dir.path().join("db")).unwrap();
+        let conf = simple_test_config(&dir.path().join("db"))
+            .cold_dir(&cold_dir)
+            .cold_after_ms(0)
+            .to_owned();
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..100 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        for i in 0..50 {
+            handle.delete(format!("key{i}").into()).unwrap();
+        }
+        handle.writer.lock().merge().unwrap();
+        let tiered = handle.tier().unwrap();
+        assert!(tiered > 0);
+        assert_eq!(tiered, handle.stats().cold_files);
+        assert_eq!(0, handle.tier().unwrap());
+
+        assert_eq!(None, handle.get("key0".into()).unwrap());
+        assert_eq!(Some(value.clone()), handle.get("key50".into()).unwrap());
+        assert_eq!(Some(value.clone()), handle.get("key50".into()).unwrap());
+        let stats = handle.stats();
+        assert_eq!(1, stats.cold_fetches);
+        assert_eq!(1, stats.cold_cache_hits);
+
+        handle.backup_incremental(None, &backup_dir).unwrap();
+        drop(handle);
+        drop(kv);
+
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        assert_eq!(50, handle.stats().live_keys);
+        assert_eq!(tiered, handle.stats().cold_files);
+        for i in 50..100 {
+            assert_eq!(
+                Some(value.clone()),
+                handle.get(format!("key{i}").into()).unwrap()
+            );
+        }
+
+        let restored = Config::default()
+            .path(&backup_dir)
+            .to_owned()
+            .open()
+            .unwrap();
+        let restored = restored.get_handle();
+        assert_eq!(50, restored.stats().live_keys);
+        assert_eq!(Some(value), restored.get("key99".into()).unwrap());
+    }
+
+    #[test]
+    fn bitcask_rebuilt_keydir_correctly() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        // perform operations within a scope so our data store gets dropped
+        // before we rebuild it.
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            // put 10000 different keys
+            for i in 0..10000 {
+                handle
+                    .put(
+                        Bytes::from(format!("key{i}")),
+                        Bytes::from(format!("value{i}")),
+                    )
+                    .unwrap();
+            }
+            // overwrite 5000 keys
+            for i in 0..5000 {
+                handle
+                    .put(
+                        Bytes::from(format!("key{i}")),
+                        Bytes::from(format!("value{i}")),
+                    )
+                    .unwrap();
+            }
+            // delete first 5000 keys
+            for i in 0..5000 {
+                handle.del(Bytes::from(format!("key{i}"))).unwrap();
+            }
+        }
+
+        // rebuild bitcask
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        // first 5000 keys should be deleted
+        for i in 0..5000 {
+            let value = handle.get(Bytes::from(format!("key{i}"))).unwrap();
+            assert!(value.is_none())
+        }
+        // get last 5000 keys
+        for i in 5000..10000 {
+            let value = handle.get(Bytes::from(format!("key{i}"))).unwrap().unwrap();
+            assert_eq!(Bytes::from(format!("value{i}")), value);
+        }
+    }
+
+    #[test]
+    fn bitcask_restores_stats_after_a_clean_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path())
+            .merge_tombstone_retention_ms(3_600_000)
+            .to_owned();
+        let snapshot = |handle: &Handle| {
+            let mut stats: Vec<_> = handle
+                .writer
+                .lock()
+                .get_stats()
+                .iter()
+                .map(|(&id, s)| (id, s.live_keys(), s.dead_keys(), s.dead_bytes()))
+                .collect();
+            stats.sort_unstable();
+            stats
+        };
+
+        let kv = conf.clone().open().unwrap();
+        let handle = kv.get_handle();
+        for i in 0..1000 {
+            handle
+                .put(format!("key{i}").into(), "value".into())
+                .unwrap();
+        }
+        for i in 0..500 {
+            handle.del(format!("key{i}").into()).unwrap();
+        }
+        // The retained tombstones are left out of the merge's hint files
+        handle.writer.lock().merge().unwrap();
+        let stats = snapshot(&handle);
+        drop(handle);
+        drop(kv);
+        assert!(dir.path().join("clean.shutdown").exists());
+
+        let kv = conf.open().unwrap();
+        assert_eq!(stats, snapshot(&kv.get_handle()));
+        assert!(!dir.path().join("clean.shutdown").exists());
+    }
+
+    #[test]
+    fn bitcask_rebuilt_stats_correctly() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = simple_test_config(dir.path());
+
+        // perform operations within a scope so our data store gets dropped
+        // before we rebuild it.
+        {
+            let kv = conf.clone().open().unwrap();
+            let handle = kv.get_handle();
+            // put 10000 different keys
+            for i in 0..10000 {
+                handle
+                    .put(
+                        Bytes::from(format!("key{i}")),
+                        Bytes::from(format!("value{i}")),
+                    )
+                    .unwrap();
+            }
+            // overwrite 5000 keys
+            for i in 0..5000 {
+                handle
+                    .put(
+                        Bytes::from(format!("key{i}")),
+                        Bytes::from(format!("value{i}")),
+                    )
+                    .unwrap();
+            }
+            // delete 5000 keys
+            for i in 0..5000 {
+                handle.del(Bytes::from(format!("key{i}"))).unwrap();
+            }
+        }
+
+        // rebuild bitcask
+        let kv = conf.open().unwrap();
+        let handle = kv.get_handle();
+        // should get 5000 live keys and 15000 dead keys, in which:
+        // - 5000 live keys that were not overwritten or deleted.
+        // - 5000 dead keys resulted from overwriting.
+        // - 10000 dead keys resulted from deleting.
+        let mut lives = 0;
+        let mut deads = 0;
+        for (_, e) in handle.writer.lock().get_stats().iter() {
+            lives += e.live_keys();
+            deads += e.dead_keys();
+        }
+        assert_eq!(5000, lives);
+        assert_eq!(15000, deads);
+    }
+
+    #[test]
+    fn bitcask_reports_the_usage_of_each_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv = simple_test_config(dir.path()).open().unwrap();
+        let handle = kv.get_handle();
+
+        let value = Bytes::from(vec![b'v'; 1024]);
+        for i in 0..100 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        for i in 0..50 {
+            handle.put(format!("key{i}").into(), value.clone()).unwrap();
+        }
+        let files = handle.file_stats().unwrap();
+        assert!(files.len() > 1);
+        assert!(files.windows(2).all(|w| w[0].fileid < w[1].fileid));
+        assert_eq!(100, files.iter().map(|f| f.live_keys).sum::<u64>());
+        assert_eq!(50, files.iter().map(|f| f.dead_keys).sum::<u64>());
+        assert!(files[0].fragmentation > 0.0);
+        assert!(files.iter().all(|f| f.size > 0 && !f.has_hintfile));
+
+        // Merges write hint files and leave no dead keys behind
+        handle.writer.lock().merge().unwrap();
+        let files = handle.file_stats().unwrap();
+        assert_eq!(0, files.iter().map(|f| f.dead_keys).sum::<u64>());
+        assert!(files.iter().any(|f| f.has_hintfile));
     }
 
     #[test]
@@ -0,0 +1,186 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    sync::atomic::{AtomicI64, AtomicU8, Ordering},
+};
+
+use rand::Rng;
+
+use super::utils;
+
+/// The number of slots in the table of access statistics.
+const SLOTS: usize = 1 << 16;
+
+/// The access frequency of a key that was just created, so new keys aren't evicted before they
+/// have a chance to be accessed.
+const FREQUENCY_INIT: u8 = 5;
+
+/// How fast the access frequency grows, the higher the factor the more accesses it takes to
+/// increase the frequency.
+const FREQUENCY_LOG_FACTOR: f64 = 10.0;
+
+/// The number of nanoseconds a key has to be idle for its access frequency to be decremented.
+const FREQUENCY_DECAY_PERIOD: i64 = 60 * 1_000_000_000;
+
+/// Approximate access statistics of the keys, used for choosing which keys to evict. The
+/// statistics are kept in a fixed-size table indexed by the hashes of the keys rather than in the
+/// KeyDir, so readers can record accesses without writing to the KeyDir. Keys whose hashes collide
+/// share their statistics.
+///
+/// The access frequency is an 8-bit counter like the one of Redis's LFU policies. It grows
+/// logarithmically with the number of accesses, and decays while the key is idle. The counter is
+/// only maintained when the frequencies are tracked, since it costs a random number per access.
+#[derive(Debug)]
+pub(super) struct AccessTracker {
+    slots: Box<[Slot]>,
+    hasher: RandomState,
+    track_frequency: bool,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// The Unix timestamp in nanoseconds of the last access.
+    last_access: AtomicI64,
+    /// The logarithmic access frequency counter, as of the last access.
+    counter: AtomicU8,
+}
+
+impl AccessTracker {
+    /// Create an empty table of access statistics, which maintains the access frequencies if
+    /// `track_frequency` is set.
+    pub(super) fn new(track_frequency: bool) -> Self {
+        Self {
+            slots: (0..SLOTS).map(|_| Slot::default()).collect(),
+            hasher: RandomState::new(),
+            track_frequency,
+        }
+    }
+
+    /// Return whether the access frequencies are maintained.
+    pub(super) fn tracks_frequency(&self) -> bool {
+        self.track_frequency
+    }
+
+    /// Record an access to the key at the given Unix timestamp in nanoseconds. The access counts
+    /// as `weight` hits, which lets callers that only record a sample of the accesses keep the
+    /// frequencies unbiased.
+    pub(super) fn record(&self, key: &[u8], now: i64, weight: u32) {
+        let slot = self.slot(key);
+        let prev_access = slot.last_access.fetch_max(now, Ordering::Relaxed);
+        if !self.track_frequency {
+            return;
+        }
+        utils::with_rng(|rng| {
+            slot.counter
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |counter| {
+                    let mut counter = decay(counter, now.saturating_sub(prev_access));
+                    for _ in 0..weight {
+                        counter = increment(counter, rng);
+                    }
+                    Some(counter)
+                })
+                .ok()
+        });
+    }
+
+    /// Forget the previous accesses to the slot of a key that was just created and record the
+    /// creation as its first access.
+    pub(super) fn reset(&self, key: &[u8], now: i64) {
+        let slot = self.slot(key);
+        slot.last_access.store(now, Ordering::Relaxed);
+        slot.counter.store(FREQUENCY_INIT, Ordering::Relaxed);
+    }
+
+    /// Return the Unix timestamp in nanoseconds of the last access to the key.
+    pub(super) fn last_access(&self, key: &[u8]) -> i64 {
+        self.slot(key).last_access.load(Ordering::Relaxed)
+    }
+
+    /// Return the access frequency of the key, decremented for every `FREQUENCY_DECAY_PERIOD`
+    /// that the key has been idle for at the given Unix timestamp in nanoseconds.
+    pub(super) fn frequency(&self, key: &[u8], now: i64) -> u8 {
+        let slot = self.slot(key);
+        let idle = now.saturating_sub(slot.last_access.load(Ordering::Relaxed));
+        decay(slot.counter.load(Ordering::Relaxed), idle)
+    }
+
+    fn slot(&self, key: &[u8]) -> &Slot {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.slots[hash % SLOTS]
+    }
+}
+
+/// Decrement the counter once for every `FREQUENCY_DECAY_PERIOD` in the idle time.
+fn decay(counter: u8, idle: i64) -> u8 {
+    let periods = (idle / FREQUENCY_DECAY_PERIOD).clamp(0, i64::from(u8::MAX)) as u8;
+    counter.saturating_sub(periods)
+}
+
+/// Increment the counter with a probability that shrinks as the counter grows past its initial
+/// value, so the counter approximates the logarithm of the number of accesses.
+fn increment<R: Rng + ?Sized>(counter: u8, rng: &mut R) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = f64::from(counter.saturating_sub(FREQUENCY_INIT));
+    let p = 1.0 / (base * FREQUENCY_LOG_FACTOR + 1.0);
+    if rng.gen::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_frequency_decays_while_idle() {
+        let tracker = AccessTracker::new(true);
+        tracker.reset(b"key", 0);
+        assert_eq!(FREQUENCY_INIT, tracker.frequency(b"key", 0));
+
+        // The first accesses after the creation always count
+        tracker.record(b"key", 0, 1);
+        assert_eq!(FREQUENCY_INIT + 1, tracker.frequency(b"key", 0));
+        assert_eq!(
+            FREQUENCY_INIT,
+            tracker.frequency(b"key", FREQUENCY_DECAY_PERIOD)
+        );
+        assert_eq!(0, tracker.frequency(b"key", 100 * FREQUENCY_DECAY_PERIOD));
+
+        // The decay is applied before the access is counted
+        tracker.record(b"key", 3 * FREQUENCY_DECAY_PERIOD, 1);
+        assert_eq!(
+            FREQUENCY_INIT - 1,
+            tracker.frequency(b"key", 3 * FREQUENCY_DECAY_PERIOD)
+        );
+
+        tracker.reset(b"key", 10);
+        assert_eq!(FREQUENCY_INIT, tracker.frequency(b"key", 10));
+        assert_eq!(10, tracker.last_access(b"key"));
+    }
+
+    #[test]
+    fn access_frequency_grows_logarithmically() {
+        let tracker = AccessTracker::new(true);
+        tracker.reset(b"key", 0);
+        tracker.record(b"key", 0, 100);
+        let hundred = tracker.frequency(b"key", 0);
+        tracker.record(b"key", 0, 10_000);
+        let ten_thousand = tracker.frequency(b"key", 0);
+        assert!(hundred > FREQUENCY_INIT + 1 && hundred < 20, "{hundred}");
+        assert!(
+            ten_thousand > hundred && ten_thousand < 100,
+            "{ten_thousand}"
+        );
+
+        // Accesses are not counted when the frequencies are not tracked
+        let tracker = AccessTracker::new(false);
+        tracker.reset(b"key", 0);
+        tracker.record(b"key", 5, 100);
+        assert_eq!(FREQUENCY_INIT, tracker.frequency(b"key", 5));
+        assert_eq!(5, tracker.last_access(b"key"));
+    }
+}
@@ -0,0 +1,315 @@
+//! An archive of the data files and hint files that were replaced by merges. Files are moved to
+//! the archive directory instead of being removed, so operators can recover from a bad merge or
+//! restore the storage to an earlier point in time. Archived files keep their names, which never
+//! collide because file IDs are never reused.
+//!
+//! The archived files also keep the history of the keys, which [`History`] looks up to answer
+//! what value a key had at a given time.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{
+    entry::{DataFileValue, Decode, HintFileEntry},
+    log::{self, DataFileIterator},
+    read_hintfile,
+    tiering::ColdFiles,
+    utils::{self, Layout},
+    Config, Error, KeyDirEntry,
+};
+
+/// Move the data file and the hint file with the given ID from the storage directory into the
+/// archive directory. It's not an error if the files don't exist.
+pub(super) fn archive<P, Q>(path: P, layout: Layout, fileid: u64, archive_dir: Q) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fs::create_dir_all(&archive_dir)?;
+    move_file(
+        utils::hintfile_name(&path, layout, fileid),
+        utils::hintfile_name(&archive_dir, Layout::Flat, fileid),
+    )?;
+    move_file(
+        utils::datafile_name(&path, layout, fileid),
+        utils::datafile_name(&archive_dir, Layout::Flat, fileid),
+    )
+}
+
+/// Move a file, falling back to copying it when the archive is on another file system.
+fn move_file<P, Q>(src: P, dst: Q) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    match fs::rename(&src, &dst) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(_) => {
+            fs::copy(&src, &dst)?;
+            utils::remove_file(src)
+        }
+    }
+}
+
+/// Remove the archived files that were last written more than `retention` ago.
+pub(super) fn prune<P>(archive_dir: P, retention: Duration) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let cutoff = SystemTime::now() - retention;
+    for entry in fs::read_dir(archive_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && metadata.modified()? < cutoff {
+            utils::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// A version of a key that was written to a data file.
+#[derive(Debug, Clone, Copy)]
+struct Version {
+    tstamp: i64,
+    expiry: Option<i64>,
+    deleted: bool,
+    len: u64,
+    pos: u64,
+}
+
+/// The versions of every key in a data file, in the order they were written.
+#[derive(Debug, Default)]
+struct FileIndex(HashMap<Bytes, Vec<Version>>);
+
+impl FileIndex {
+    fn build(file: fs::File) -> Result<Self, Error> {
+        let mut index = Self::default();
+        let mut datafile_iter = DataFileIterator::new(file)?;
+        while let Some((datafile_index, datafile_entry)) = datafile_iter.next()? {
+            // A zero-filled entry marks the end of a file that was allocated up front
+            if datafile_entry.tstamp == 0 {
+                break;
+            }
+            index
+                .0
+                .entry(datafile_entry.key)
+                .or_default()
+                .push(Version {
+                    tstamp: datafile_entry.tstamp,
+                    expiry: datafile_entry.expiry,
+                    deleted: datafile_entry.value.is_none(),
+                    len: datafile_index.len,
+                    pos: datafile_index.pos,
+                });
+        }
+        Ok(index)
+    }
+
+    /// Index the entries of a file in the cold store from its hint file, which only lists the
+    /// live keys of the file. Cold files hold no tombstones, so nothing is left out.
+    fn from_hints(entries: Vec<HintFileEntry>) -> Self {
+        let mut index = Self::default();
+        for entry in entries {
+            index.0.entry(entry.key).or_default().push(Version {
+                tstamp: entry.tstamp,
+                expiry: entry.expiry,
+                deleted: false,
+                len: entry.len,
+                pos: entry.pos,
+            });
+        }
+        index
+    }
+
+    /// Return the last version of the key that was written at or before `tstamp`.
+    fn latest(&self, key: &[u8], tstamp: i64) -> Option<Version> {
+        self.0
+            .get(key)?
+            .iter()
+            .filter(|v| v.tstamp <= tstamp)
+            .max_by_key(|v| v.tstamp)
+            .copied()
+    }
+}
+
+/// Looks up the values that keys had in the past from the data files in the storage directory
+/// and in the archive. The data files are indexed the first time they are consulted, and the
+/// indexes are kept since the files never change once they are no longer active, even when a
+/// merge moves them to the archive.
+#[derive(Debug, Default)]
+pub(super) struct History {
+    indexes: Mutex<HashMap<u64, Arc<FileIndex>>>,
+}
+
+impl History {
+    /// Return the value of the key at the Unix timestamp in nanoseconds, or `None` if the key
+    /// didn't exist, was deleted, or had expired by then. History that was merged away without
+    /// being archived, or that was pruned from the archive, is not known.
+    pub(super) fn get_as_of(
+        &self,
+        conf: &Config,
+        cold_files: Option<&ColdFiles>,
+        key: &[u8],
+        tstamp: i64,
+    ) -> Result<Option<Bytes>, Error> {
+        let Some((fileid, version)) = self.latest(conf, cold_files, key, tstamp, true)? else {
+            return Ok(None);
+        };
+        if version.deleted || version.expiry.is_some_and(|expiry| expiry <= tstamp) {
+            return Ok(None);
+        }
+        if let Some(cold_files) = cold_files.filter(|cold_files| cold_files.contains(fileid)) {
+            let buf = cold_files.fetch(fileid, version.pos, version.len)?;
+            return Ok(DataFileValue::read_from(&mut &buf[..])?.0);
+        }
+        let Some(mut file) = open_datafile(conf, fileid)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0; version.len as usize];
+        file.seek(SeekFrom::Start(version.pos))?;
+        file.read_exact(&mut buf)?;
+        Ok(DataFileValue::read_from(&mut buf.as_slice())?.0)
+    }
+
+    /// Find the newest entry of the key in the data files of the storage directory, and return a
+    /// KeyDir entry pointing to it. Returns `None` if the key was never written or was deleted.
+    /// This is used to repair a KeyDir entry that was found to point to another entry.
+    pub(super) fn locate_latest(
+        &self,
+        conf: &Config,
+        cold_files: Option<&ColdFiles>,
+        key: &[u8],
+    ) -> Result<Option<KeyDirEntry>, Error> {
+        match self.latest(conf, cold_files, key, i64::MAX, false)? {
+            Some((fileid, version)) if !version.deleted => Ok(Some(KeyDirEntry::new(
+                fileid,
+                version.len,
+                version.pos,
+                version.tstamp,
+                version.expiry,
+            )?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Return the last version of the key that was written at or before `tstamp` together with
+    /// the ID of the file holding it, looking in the archive too if `archived` is `true`. The
+    /// files in the cold store are indexed from their hint files.
+    fn latest(
+        &self,
+        conf: &Config,
+        cold_files: Option<&ColdFiles>,
+        key: &[u8],
+        tstamp: i64,
+        archived: bool,
+    ) -> Result<Option<(u64, Version)>, Error> {
+        let live: Vec<u64> = utils::sorted_fileids(&conf.path)?.collect();
+        let cold = cold_files.map(ColdFiles::fileids).unwrap_or_default();
+        let mut fileids: BTreeSet<u64> = live.iter().copied().chain(cold.iter().copied()).collect();
+        if let Some(archive_dir) = conf.merge_archive_dir.as_ref().filter(|_| archived) {
+            match utils::sorted_fileids(archive_dir) {
+                Ok(archived) => fileids.extend(archived),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // The file with the highest ID is the active file, which is still being written
+        let active_fileid = live.last().copied();
+
+        let mut indexes = self.indexes.lock();
+        indexes.retain(|fileid, _| fileids.contains(fileid));
+        let mut latest: Option<(u64, Version)> = None;
+        for fileid in fileids {
+            let index = match indexes.get(&fileid) {
+                Some(index) => Arc::clone(index),
+                None if cold.contains(&fileid) => {
+                    let hints = read_hintfile(&conf.path, conf.layout(), fileid)?;
+                    let index = Arc::new(FileIndex::from_hints(hints));
+                    indexes.insert(fileid, Arc::clone(&index));
+                    index
+                }
+                None => {
+                    let Some(file) = open_datafile(conf, fileid)? else {
+                        continue;
+                    };
+                    let index = Arc::new(FileIndex::build(file)?);
+                    if Some(fileid) != active_fileid {
+                        indexes.insert(fileid, Arc::clone(&index));
+                    }
+                    index
+                }
+            };
+            // Merged files hold copies of older versions, so ties go to the later file
+            if let Some(version) = index.latest(key, tstamp) {
+                if latest.is_none_or(|(_, v)| version.tstamp >= v.tstamp) {
+                    latest = Some((fileid, version));
+                }
+            }
+        }
+        Ok(latest)
+    }
+}
+
+/// Open the data file with the given ID from the storage directory, or from the archive if a
+/// merge has moved it there. Returns `None` if the file is in neither place.
+fn open_datafile(conf: &Config, fileid: u64) -> Result<Option<fs::File>, Error> {
+    match log::open(utils::datafile_name(&conf.path, conf.layout(), fileid)) {
+        Ok(file) => return Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let Some(archive_dir) = &conf.merge_archive_dir else {
+        return Ok(None);
+    };
+    match log::open(utils::datafile_name(archive_dir, Layout::Flat, fileid)) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archived_files_are_moved_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        for fileid in 0..3 {
+            fs::File::create(utils::datafile_name(&dir, Layout::Flat, fileid)).unwrap();
+        }
+        fs::File::create(utils::hintfile_name(&dir, Layout::Flat, 1)).unwrap();
+
+        for fileid in 0..2 {
+            archive(&dir, Layout::Flat, fileid, &archive_dir).unwrap();
+        }
+        let fileids: Vec<u64> = utils::sorted_fileids(&dir).unwrap().collect();
+        assert_eq!(vec![2], fileids);
+        let fileids: Vec<u64> = utils::sorted_fileids(&archive_dir).unwrap().collect();
+        assert_eq!(vec![0, 1], fileids);
+        assert!(utils::hintfile_name(&archive_dir, Layout::Flat, 1).is_file());
+
+        // Only the file that was written before the retention period is removed
+        let old = fs::File::options()
+            .write(true)
+            .open(utils::datafile_name(&archive_dir, Layout::Flat, 0))
+            .unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        drop(old);
+        prune(&archive_dir, Duration::from_secs(60)).unwrap();
+        let fileids: Vec<u64> = utils::sorted_fileids(&archive_dir).unwrap().collect();
+        assert_eq!(vec![1], fileids);
+    }
+}
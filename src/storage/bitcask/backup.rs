@@ -0,0 +1,175 @@
+//! Incremental backups of the data files and the hint files.
+//!
+//! Closed data files never change until merges remove them, so a backup only has to copy the data
+//! files that were created since the previous backup into the backup directory. Hint files are
+//! copied every time, since they describe the files as they are now. The data files and the hint
+//! files that merges removed since the previous backup are removed from the backup directory, so
+//! the keys that were deleted before the merges don't reappear when the backup is opened.
+//!
+//! A manifest in the backup directory lists the data files of the backup. It's replaced only after
+//! the new files were copied, so an interrupted backup leaves the previous backup usable.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    tiering::ColdFiles,
+    utils::{self, Layout},
+    Error,
+};
+
+/// The name of the file that lists the backed up files.
+const MANIFEST_FILE: &str = "backup.manifest";
+
+/// The data files in a backup, which are used to tell what the next incremental backup has to
+/// copy.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    fileids: BTreeSet<u64>,
+}
+
+impl BackupManifest {
+    /// Read the manifest of the backup in the given directory.
+    pub fn load<P>(backup_dir: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest = backup_dir.as_ref().join(MANIFEST_FILE);
+        let buf = fs::read(&manifest)?;
+        serde_json::from_slice(&buf)
+            .map_err(|_| Error::corrupted_at("invalid backup manifest", manifest, None))
+    }
+
+    /// Return the IDs of the data files in the backup.
+    pub fn fileids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.fileids.iter().copied()
+    }
+
+    /// Replace the manifest of the backup. The new manifest is written to a temporary file first,
+    /// so the manifest is never left half written.
+    fn save<P>(&self, backup_dir: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let path = backup_dir.as_ref().join(MANIFEST_FILE);
+        let tmp = path.with_extension("tmp");
+        let buf = serde_json::to_vec(self).expect("manifest must be serializable");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Back up the closed data files with the given IDs into `backup_dir`, which holds the backup
+/// that `prev` describes. Data files in `prev` aren't copied again. Returns the manifest of the
+/// new backup.
+///
+/// The files in the cold store are downloaded, so the backup can be opened without the store.
+pub(super) fn backup<P, Q>(
+    path: P,
+    layout: Layout,
+    fileids: BTreeSet<u64>,
+    cold_files: Option<&ColdFiles>,
+    prev: Option<&BackupManifest>,
+    backup_dir: Q,
+) -> Result<BackupManifest, Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fs::create_dir_all(&backup_dir)?;
+    let empty = BackupManifest::default();
+    let prev = prev.unwrap_or(&empty);
+    for &fileid in &fileids {
+        if !prev.fileids.contains(&fileid) {
+            let dst = utils::datafile_name(&backup_dir, Layout::Flat, fileid);
+            match cold_files.filter(|cold_files| cold_files.contains(fileid)) {
+                Some(cold_files) => cold_files.download(fileid, &dst)?,
+                None => {
+                    fs::copy(utils::datafile_name(&path, layout, fileid), &dst)?;
+                }
+            }
+            fs::File::open(dst)?.sync_all()?;
+        }
+        let src = utils::hintfile_name(&path, layout, fileid);
+        let dst = utils::hintfile_name(&backup_dir, Layout::Flat, fileid);
+        match fs::copy(src, &dst) {
+            Ok(_) => fs::File::open(dst)?.sync_all()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let manifest = BackupManifest { fileids };
+    manifest.save(&backup_dir)?;
+    // The files that merges removed since the previous backup
+    for fileid in prev.fileids.difference(&manifest.fileids) {
+        utils::remove_file(utils::hintfile_name(&backup_dir, Layout::Flat, *fileid))?;
+        utils::remove_file(utils::datafile_name(&backup_dir, Layout::Flat, *fileid))?;
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(path: impl AsRef<Path>, contents: &str) {
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn backups_only_copy_new_data_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backup");
+        write_file(utils::datafile_name(dir.path(), Layout::Flat, 0), "data0");
+        write_file(utils::datafile_name(dir.path(), Layout::Flat, 1), "data1");
+        let full = backup(
+            dir.path(),
+            Layout::Flat,
+            [0, 1].into(),
+            None,
+            None,
+            &backup_dir,
+        )
+        .unwrap();
+        assert_eq!(full, BackupManifest::load(&backup_dir).unwrap());
+
+        // A merge replaced file 1 with file 2, which has a hint file
+        utils::remove_file(utils::datafile_name(dir.path(), Layout::Flat, 1)).unwrap();
+        write_file(utils::datafile_name(dir.path(), Layout::Flat, 2), "data2");
+        write_file(utils::hintfile_name(dir.path(), Layout::Flat, 2), "hint2");
+        // Changing a backed up file shows whether it's copied again
+        write_file(utils::datafile_name(dir.path(), Layout::Flat, 0), "changed");
+        let incremental = backup(
+            dir.path(),
+            Layout::Flat,
+            [0, 2].into(),
+            None,
+            Some(&full),
+            &backup_dir,
+        )
+        .unwrap();
+
+        assert_eq!(vec![0, 2], incremental.fileids().collect::<Vec<_>>());
+        let fileids: Vec<u64> = utils::sorted_fileids(&backup_dir).unwrap().collect();
+        assert_eq!(vec![0, 2], fileids);
+        let read = |name| fs::read_to_string(name).unwrap();
+        assert_eq!(
+            "data0",
+            read(utils::datafile_name(&backup_dir, Layout::Flat, 0))
+        );
+        assert_eq!(
+            "hint2",
+            read(utils::hintfile_name(&backup_dir, Layout::Flat, 2))
+        );
+    }
+}
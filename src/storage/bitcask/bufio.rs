@@ -37,9 +37,8 @@ where
     R: Read,
 {
     fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
-        self.reader.read(b).map(|bytes_read| {
+        self.reader.read(b).inspect(|&bytes_read| {
             self.pos += bytes_read as u64;
-            bytes_read
         })
     }
 }
@@ -49,9 +48,8 @@ where
     R: Read + Seek,
 {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.reader.seek(pos).map(|posn| {
+        self.reader.seek(pos).inspect(|&posn| {
             self.pos = posn;
-            posn
         })
     }
 }
@@ -98,9 +96,8 @@ where
     W: Write,
 {
     fn write(&mut self, b: &[u8]) -> io::Result<usize> {
-        self.writer.write(b).map(|bytes_written| {
+        self.writer.write(b).inspect(|&bytes_written| {
             self.pos += bytes_written as u64;
-            bytes_written
         })
     }
 
@@ -114,9 +111,8 @@ where
     W: Write + Seek,
 {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.writer.seek(pos).map(|posn| {
+        self.writer.seek(pos).inspect(|&posn| {
             self.pos = posn;
-            posn
         })
     }
 }
@@ -0,0 +1,47 @@
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use super::Error;
+
+/// A write that was committed to the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The key that was written.
+    pub key: Bytes,
+    /// The value that was set, or `None` if the key was deleted.
+    pub value: Option<Bytes>,
+    /// The Unix timestamp in nanoseconds at which the write happened.
+    pub tstamp: i64,
+}
+
+/// A subscription to the writes that are committed to the storage, in commit order.
+///
+/// Changes are buffered in a bounded queue that is shared by all subscribers. A subscriber that
+/// falls behind by more than the queue's capacity misses the oldest changes, and is told how many
+/// changes were missed through [`Error::ChangesLagged`].
+#[derive(Debug)]
+pub struct ChangeStream {
+    rx: broadcast::Receiver<Change>,
+}
+
+impl ChangeStream {
+    pub(super) fn new(rx: broadcast::Receiver<Change>) -> Self {
+        Self { rx }
+    }
+
+    /// Wait for the next change.
+    ///
+    /// # Error
+    ///
+    /// Returns [`Error::ChangesLagged`] if changes were missed since the last call, after which
+    /// the stream continues from the oldest change that is still buffered. Returns
+    /// [`Error::Closed`] once the storage and all of its handles were dropped, and all buffered
+    /// changes were received.
+    pub async fn recv(&mut self) -> Result<Change, Error> {
+        match self.rx.recv().await {
+            Ok(change) => Ok(change),
+            Err(broadcast::error::RecvError::Lagged(n)) => Err(Error::ChangesLagged(n)),
+            Err(broadcast::error::RecvError::Closed) => Err(Error::Closed),
+        }
+    }
+}
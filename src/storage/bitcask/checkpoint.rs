@@ -0,0 +1,161 @@
+//! Snapshots of the KeyDir and the statistics of the data files, so a restart only has to read
+//! the data files that were written after the last snapshot.
+//!
+//! A checkpoint covers every data file whose ID is less than its `next_fileid`. It's only used
+//! if all of the files it covers still exist, because a merge replaces the files that the
+//! snapshotted entries point to.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use super::{keydir::KeyDirEntry, log::LogStatistics, Error};
+
+const CHECKPOINT_FILE: &str = "keydir.checkpoint";
+
+/// The version of the checkpoint format, which changes whenever the layout of the snapshotted
+/// structures changes. Checkpoints of other versions are ignored.
+const CHECKPOINT_VERSION: u32 = 2;
+
+/// The KeyDir and the statistics of the data files at the time a checkpoint was taken.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct Checkpoint {
+    /// The data files whose IDs are less than this are covered by the checkpoint.
+    pub(super) next_fileid: u64,
+    /// The IDs of the data files that existed when the checkpoint was taken.
+    pub(super) fileids: Vec<u64>,
+    /// The statistics of the data files.
+    pub(super) stats: HashMap<u64, LogStatistics>,
+    /// The KeyDir entries.
+    pub(super) entries: Vec<(Bytes, KeyDirEntry)>,
+}
+
+/// Return the name of the checkpoint file in the given directory.
+fn checkpoint_name<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    path.as_ref().join(CHECKPOINT_FILE)
+}
+
+/// Write the checkpoint to the given directory, replacing the previous one. The file is
+/// written under a unique temporary name first, so a crash never leaves a partial checkpoint
+/// behind and concurrent writes don't interleave.
+pub(super) fn write<P>(path: P, checkpoint: &Checkpoint) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let tmp = path.as_ref().join(format!(
+        "{CHECKPOINT_FILE}.{:016x}.tmp",
+        rand::random::<u64>()
+    ));
+    let file = fs::File::create(&tmp)?;
+    let mut writer = BufWriter::new(file);
+    let payload = bincode::serialize(checkpoint)?;
+    writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(tmp, checkpoint_name(path))?;
+    Ok(())
+}
+
+/// Read the checkpoint in the given directory. Returns `None` if there's no checkpoint, if it was
+/// written in another format version, or if one of the data files that it covers is not in
+/// `fileids`. Returns [`Error::Corruption`] if the
+/// checkpoint fails its checksum.
+pub(super) fn read<P>(path: P, fileids: &[u64]) -> Result<Option<Checkpoint>, Error>
+where
+    P: AsRef<Path>,
+{
+    let name = checkpoint_name(path);
+    let data = match fs::read(&name) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if data.len() < 8 {
+        return Err(Error::corrupted_at("checkpoint is truncated", name, None));
+    }
+    let (version, data) = data.split_at(4);
+    if version != CHECKPOINT_VERSION.to_le_bytes() {
+        return Ok(None);
+    }
+    let (checksum, payload) = data.split_at(4);
+    if crc32fast::hash(payload).to_le_bytes() != checksum {
+        return Err(Error::corrupted_at(
+            "checkpoint checksum mismatch",
+            name,
+            None,
+        ));
+    }
+    let checkpoint: Checkpoint = bincode::deserialize(payload)?;
+    if !checkpoint.fileids.iter().all(|id| fileids.contains(id)) {
+        return Ok(None);
+    }
+    Ok(Some(checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_checkpoint() -> Checkpoint {
+        let entry = KeyDirEntry::new(1, 10, 0, 0, None).unwrap();
+        Checkpoint {
+            next_fileid: 2,
+            fileids: vec![0, 1],
+            stats: HashMap::default(),
+            entries: vec![(Bytes::from("key"), entry)],
+        }
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &test_checkpoint()).unwrap();
+
+        let checkpoint = read(dir.path(), &[0, 1, 2]).unwrap().unwrap();
+        assert_eq!(2, checkpoint.next_fileid);
+        assert_eq!(1, checkpoint.entries.len());
+        // A covered file was merged away
+        assert!(read(dir.path(), &[1, 2]).unwrap().is_none());
+    }
+
+    #[test]
+    fn checkpoint_of_other_version_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &test_checkpoint()).unwrap();
+
+        let name = checkpoint_name(dir.path());
+        let mut data = fs::read(&name).unwrap();
+        data[..4].copy_from_slice(&(CHECKPOINT_VERSION - 1).to_le_bytes());
+        fs::write(&name, data).unwrap();
+        assert!(read(dir.path(), &[0, 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn checkpoint_corruption_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &test_checkpoint()).unwrap();
+
+        let name = checkpoint_name(dir.path());
+        let mut data = fs::read(&name).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&name, data).unwrap();
+        assert!(matches!(
+            read(dir.path(), &[0, 1]),
+            Err(Error::Corruption { .. })
+        ));
+    }
+}
@@ -0,0 +1,411 @@
+//! Values that are streamed in with [`Handle::put_reader`] are split into chunks, so a value can
+//! be larger than an entry or a data file, and it never has to be held in memory as a whole.
+//!
+//! Each chunk is stored under an internal key that is derived from a random ID given to the value
+//! and the chunk's sequence number. Once all chunks are written, a manifest holding the ID and the
+//! number of chunks is written under the key, so readers see either the previous value or the
+//! whole new value. The writer removes the chunks of a manifest when its key is overwritten,
+//! deleted, or merged away, and copies get their own chunks. A crash in the middle of writing the
+//! chunks, or before the tombstones of removed chunks are written, leaves unreachable chunks
+//! behind.
+//!
+//! The internal keys are hidden from scans and don't count as keys toward the quotas. Keys that
+//! users write or read starting with [`RESERVED_PREFIX`] are rejected with [`Error::ReservedKey`],
+//! so users can't touch the chunks. Values that users write starting with [`RESERVED_PREFIX`] are
+//! escaped, so they can't be mistaken for manifests, and the escape is removed when they are
+//! read.
+
+use std::io::{self, Read, Write};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{
+    entry::{DataFileEntry, Encode},
+    writer::Writer,
+    Config, Error, Handle, KeyDirEntry,
+};
+use crate::storage::ValueStream;
+
+/// The prefix of the internal keys and of the headers that are prepended to internal values.
+const RESERVED_PREFIX: &[u8] = b"\x00bitcask\x00";
+
+/// The header that is prepended to the serialized manifests.
+const MANIFEST_HEADER: &[u8] = b"\x00bitcask\x00chunked\x00";
+
+/// The header that is prepended to the values of users that start with [`RESERVED_PREFIX`].
+const ESCAPE_HEADER: &[u8] = b"\x00bitcask\x00escaped\x00";
+
+/// The prefix of the internal keys under which chunks are stored.
+const CHUNK_KEY_PREFIX: &[u8] = b"\x00bitcask\x00chunk\x00";
+
+/// The number of bytes of an encoded manifest.
+const MANIFEST_LEN: usize = MANIFEST_HEADER.len() + 24;
+
+/// The max number of bytes that an entry holding a chunk takes in addition to the chunk.
+const CHUNK_OVERHEAD: u64 = 64;
+
+/// Describes where the chunks of a value are stored.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Manifest {
+    /// The random ID from which the chunk keys are derived.
+    id: u64,
+    /// The number of bytes of the value.
+    len: u64,
+    /// The number of chunks.
+    chunks: u64,
+}
+
+impl Manifest {
+    /// Decode the manifest from its stored representation. Returns `None` if the value is not a
+    /// manifest.
+    pub(super) fn decode(raw: &[u8]) -> Option<Self> {
+        let raw = raw.strip_prefix(MANIFEST_HEADER)?;
+        bincode::deserialize(raw).ok()
+    }
+
+    /// Return `true` if the entry of the key could hold a manifest, which is told by its length
+    /// without reading its value.
+    pub(super) fn may_be_stored_in(key: &Bytes, keydir_entry: &KeyDirEntry) -> bool {
+        let entry = DataFileEntry {
+            tstamp: 0,
+            key: key.clone(),
+            value: Some(Bytes::from_static(&[0; MANIFEST_LEN])),
+            expiry: keydir_entry.expiry(),
+        };
+        !is_chunk_key(key) && entry.encoded_len() == keydir_entry.len()
+    }
+
+    /// Return the random ID from which the chunk keys are derived.
+    pub(super) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Encode the manifest into its stored representation.
+    fn encode(&self) -> Bytes {
+        let size = bincode::serialized_size(self).expect("manifest must be serializable");
+        let mut buf = BytesMut::with_capacity(MANIFEST_HEADER.len() + size as usize).writer();
+        buf.get_mut().put_slice(MANIFEST_HEADER);
+        bincode::serialize_into(&mut buf, self).expect("manifest must be serializable");
+        buf.into_inner().freeze()
+    }
+
+    /// Get the internal keys of the chunks in order.
+    pub(super) fn chunk_keys(&self) -> impl Iterator<Item = Bytes> {
+        let id = self.id;
+        (0..self.chunks).map(move |seq| chunk_key(id, seq))
+    }
+}
+
+/// Return `true` if the key is an internal key holding a chunk.
+pub(super) fn is_chunk_key(key: &[u8]) -> bool {
+    key.starts_with(CHUNK_KEY_PREFIX)
+}
+
+/// Reject a key that is given by a user if it starts with [`RESERVED_PREFIX`], so it can't be
+/// mistaken for an internal key.
+pub(super) fn check_key(key: &[u8]) -> Result<(), Error> {
+    if key.starts_with(RESERVED_PREFIX) {
+        return Err(Error::ReservedKey);
+    }
+    Ok(())
+}
+
+/// Escape a value that is written by a user, so it's never mistaken for an internal value.
+pub(super) fn escape(value: Bytes) -> Bytes {
+    if !value.starts_with(RESERVED_PREFIX) {
+        return value;
+    }
+    let mut escaped = BytesMut::with_capacity(ESCAPE_HEADER.len() + value.len());
+    escaped.put_slice(ESCAPE_HEADER);
+    escaped.put_slice(&value);
+    escaped.freeze()
+}
+
+/// Remove the escape of a stored value. Manifests are returned as they are.
+pub(super) fn unescape(raw: Bytes) -> Bytes {
+    match raw.strip_prefix(ESCAPE_HEADER) {
+        Some(_) => raw.slice(ESCAPE_HEADER.len()..),
+        None => raw,
+    }
+}
+
+/// A value as it's stored under a key.
+enum Stored {
+    /// A value that is stored as a whole, with its escape removed.
+    Whole(Bytes),
+    /// A value that was streamed in and is stored in chunks.
+    Chunked(Manifest),
+}
+
+impl Stored {
+    fn decode(raw: Bytes) -> Self {
+        match Manifest::decode(&raw) {
+            Some(manifest) => Self::Chunked(manifest),
+            None => Self::Whole(unescape(raw)),
+        }
+    }
+}
+
+/// Turn a stored value into the value that was written. The chunks of a value that was streamed
+/// in are read with `read` and joined.
+pub(super) fn resolve<F>(raw: Bytes, mut read: F) -> Result<Bytes, Error>
+where
+    F: FnMut(Bytes) -> Result<Option<Bytes>, Error>,
+{
+    let manifest = match Stored::decode(raw) {
+        Stored::Whole(value) => return Ok(value),
+        Stored::Chunked(manifest) => manifest,
+    };
+    let mut value = BytesMut::with_capacity(manifest.len as usize);
+    for key in manifest.chunk_keys() {
+        // The chunks are removed when the value is replaced while it's being read
+        let chunk = read(key)?.ok_or(Error::ValueChanged)?;
+        value.put_slice(&chunk);
+    }
+    Ok(value.freeze())
+}
+
+/// Write copies of the chunks of a stored value under a new ID, and return the manifest of the
+/// copy, or `None` if the value wasn't streamed in. The keys of the copied chunks are added to
+/// `copied`, including when the copy fails halfway.
+pub(super) fn copy_chunks(
+    writer: &mut Writer,
+    raw: &[u8],
+    copied: &mut Vec<Bytes>,
+) -> Result<Option<Bytes>, Error> {
+    let Some(manifest) = Manifest::decode(raw) else {
+        return Ok(None);
+    };
+    let copy = Manifest {
+        id: rand::random(),
+        ..manifest
+    };
+    for (src, dst) in manifest.chunk_keys().zip(copy.chunk_keys()) {
+        let chunk = writer.get(&src)?.ok_or(Error::ValueChanged)?;
+        writer.put(dst.clone(), chunk)?;
+        copied.push(dst);
+    }
+    Ok(Some(copy.encode()))
+}
+
+/// Get the internal key of a chunk.
+fn chunk_key(id: u64, seq: u64) -> Bytes {
+    let mut key = BytesMut::with_capacity(CHUNK_KEY_PREFIX.len() + 16);
+    key.put_slice(CHUNK_KEY_PREFIX);
+    key.put_u64(id);
+    key.put_u64(seq);
+    key.freeze()
+}
+
+/// Get the number of bytes in a chunk. The configured size is lowered if needed, so an entry
+/// holding a chunk is within the max entry size and fills at most half of a data file.
+fn chunk_size(conf: &Config) -> usize {
+    let max = u64::from(conf.max_entry_size.get())
+        .min(conf.max_file_size.get() / 2)
+        .saturating_sub(CHUNK_OVERHEAD)
+        .max(1);
+    u64::from(conf.value_chunk_size.get()).min(max) as usize
+}
+
+/// Write the bytes read from `reader` as the value of the key and return the number of bytes.
+/// The key keeps its expiry, and the writer removes the chunks of its previous value.
+pub(super) fn put_reader<R>(handle: &Handle, key: Bytes, mut reader: R) -> Result<u64, Error>
+where
+    R: Read,
+{
+    let chunk_size = chunk_size(handle.ctx.get_conf());
+    let mut manifest = Manifest {
+        id: rand::random(),
+        len: 0,
+        chunks: 0,
+    };
+    let written: Result<(), Error> = loop {
+        let mut chunk = Vec::with_capacity(chunk_size);
+        if let Err(e) = reader
+            .by_ref()
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)
+        {
+            break Err(e.into());
+        }
+        if chunk.is_empty() {
+            break Ok(());
+        }
+        let len = chunk.len() as u64;
+        let chunk_key = chunk_key(manifest.id, manifest.chunks);
+        if let Err(e) = handle
+            .lock_writer_for_write()
+            .and_then(|mut writer| writer.put(chunk_key, chunk.into()))
+        {
+            break Err(e);
+        }
+        manifest.len += len;
+        manifest.chunks += 1;
+    };
+
+    let len = manifest.len;
+    let result = written.and_then(|_| {
+        let mut writer = handle.lock_writer_for_write()?;
+        let expiry = writer.get_expiry(&key);
+        writer.put_with_expiry(key, manifest.encode(), expiry)
+    });
+    if let Err(e) = result {
+        remove_chunks(handle, manifest.chunk_keys());
+        return Err(e);
+    }
+    Ok(len)
+}
+
+/// Get the value of the key, or `None` if the key doesn't exist. The chunks of a value that was
+/// streamed in are read as the reader reaches them.
+pub(super) fn get_stream(handle: &Handle, key: Bytes) -> Result<Option<ValueStream>, Error> {
+    let Some(raw) = handle.read(key)? else {
+        return Ok(None);
+    };
+    let manifest = match Stored::decode(raw) {
+        Stored::Whole(value) => return Ok(Some(ValueStream::Whole(value))),
+        Stored::Chunked(manifest) => manifest,
+    };
+    let reader = ChunkReader {
+        handle: handle.clone(),
+        keys: manifest.chunk_keys(),
+        chunk: Bytes::new(),
+    };
+    Ok(Some(ValueStream::Chunked {
+        len: manifest.len,
+        reader: Box::new(reader),
+    }))
+}
+
+/// Write the value of the key to `writer` and return the number of bytes, or `None` if the key
+/// doesn't exist.
+pub(super) fn get_writer<W>(
+    handle: &Handle,
+    key: Bytes,
+    mut writer: W,
+) -> Result<Option<u64>, Error>
+where
+    W: Write,
+{
+    match get_stream(handle, key)? {
+        None => Ok(None),
+        Some(ValueStream::Whole(value)) => {
+            writer.write_all(&value)?;
+            Ok(Some(value.len() as u64))
+        }
+        Some(ValueStream::Chunked { len, mut reader }) => {
+            io::copy(&mut reader, &mut writer).map_err(from_io)?;
+            Ok(Some(len))
+        }
+    }
+}
+
+/// Reads the chunks of a value one at a time.
+struct ChunkReader<I> {
+    handle: Handle,
+    keys: I,
+    /// The part of the current chunk that hasn't been read.
+    chunk: Bytes,
+}
+
+impl<I> Read for ChunkReader<I>
+where
+    I: Iterator<Item = Bytes>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            let Some(key) = self.keys.next() else {
+                return Ok(0);
+            };
+            // The chunks are removed when the value is replaced while it's being read
+            self.chunk = self
+                .handle
+                .read(key)
+                .map_err(io::Error::other)?
+                .ok_or_else(|| io::Error::other(Error::ValueChanged))?;
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Ok(n)
+    }
+}
+
+/// Unwrap the storage errors that were turned into I/O errors by a [`ChunkReader`].
+fn from_io(e: io::Error) -> Error {
+    if !e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+        return Error::Io(e);
+    }
+    let inner = e.into_inner().expect("the error has an inner error");
+    *inner
+        .downcast::<Error>()
+        .expect("the inner error is a storage error")
+}
+
+/// Remove the chunks with the given keys. Failures are only logged since the chunks are
+/// unreachable.
+pub(super) fn remove_chunks<I>(handle: &Handle, keys: I)
+where
+    I: IntoIterator<Item = Bytes>,
+{
+    let mut writer = handle.lock_writer();
+    for key in keys {
+        if let Err(e) = writer.delete(key.clone()) {
+            warn!(error = ?e, ?key, "failed to remove value chunk");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_round_trip() {
+        let manifest = Manifest {
+            id: 7,
+            len: 3000,
+            chunks: 3,
+        };
+        let encoded = manifest.encode();
+        assert_eq!(Some(manifest), Manifest::decode(&encoded));
+        assert_eq!(None, Manifest::decode(b"plain value"));
+        assert_eq!(MANIFEST_LEN, encoded.len());
+    }
+
+    #[test]
+    fn escaped_values_are_never_read_as_manifests() {
+        let manifest = Manifest {
+            id: 7,
+            len: 3000,
+            chunks: 3,
+        }
+        .encode();
+        let no_chunks = |_| -> Result<Option<Bytes>, Error> { Ok(None) };
+        for value in [
+            manifest.clone(),
+            Bytes::from_static(ESCAPE_HEADER),
+            Bytes::from_static(b"\x00bitcask\x00"),
+            Bytes::from_static(b"plain value"),
+        ] {
+            let escaped = escape(value.clone());
+            assert!(matches!(Stored::decode(escaped.clone()), Stored::Whole(_)));
+            assert_eq!(value, resolve(escaped, no_chunks).unwrap());
+        }
+        assert!(matches!(
+            resolve(manifest, no_chunks),
+            Err(Error::ValueChanged)
+        ));
+    }
+
+    #[test]
+    fn chunks_fit_in_entries_and_files() {
+        let mut conf = Config::default();
+        conf.max_file_size(std::num::NonZeroU64::new(64 * 1024).unwrap());
+        assert_eq!(32 * 1024 - CHUNK_OVERHEAD as usize, chunk_size(&conf));
+        conf.value_chunk_size(std::num::NonZeroU32::new(1000).unwrap());
+        assert_eq!(1000, chunk_size(&conf));
+    }
+}
@@ -0,0 +1,115 @@
+//! A marker that's left behind when the storage is closed cleanly, so the next open can trust the
+//! state that the storage had when it was closed instead of deriving it again.
+//!
+//! The marker holds the ID of the active file, the IDs of all data files, and the statistics of
+//! the data files. It's removed as soon as it's read, so it never outlives the open that follows
+//! the clean shutdown, and it's only used if the data files on disk are the ones it lists.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{log::LogStatistics, Error};
+
+const MARKER_FILE: &str = "clean.shutdown";
+
+/// The version of the marker format. Markers of other versions are ignored.
+const MARKER_VERSION: u32 = 1;
+
+/// The state of the storage when it was closed cleanly.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct ShutdownMarker {
+    /// The ID of the file that was active when the storage was closed.
+    pub(super) active_fileid: u64,
+    /// The IDs of the data files that existed when the storage was closed.
+    pub(super) fileids: Vec<u64>,
+    /// The statistics of the data files.
+    pub(super) stats: HashMap<u64, LogStatistics>,
+}
+
+/// Return the name of the marker file in the given directory.
+fn marker_name<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    path.as_ref().join(MARKER_FILE)
+}
+
+/// Write the marker to the given directory. Like checkpoints, the marker is written under a
+/// temporary name first, so a crash while closing never leaves a partial marker behind.
+pub(super) fn write<P>(path: P, marker: &ShutdownMarker) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let tmp = path.as_ref().join(format!("{MARKER_FILE}.tmp"));
+    let file = fs::File::create(&tmp)?;
+    let mut writer = BufWriter::new(file);
+    let payload = bincode::serialize(marker)?;
+    writer.write_all(&MARKER_VERSION.to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(tmp, marker_name(path))?;
+    Ok(())
+}
+
+/// Read and remove the marker in the given directory. Returns `None` if there's no marker, if it
+/// was written in another format version, if it fails its checksum, or if the data files in
+/// `fileids` are not the ones that existed when the storage was closed.
+pub(super) fn take<P>(path: P, fileids: &[u64]) -> Result<Option<ShutdownMarker>, Error>
+where
+    P: AsRef<Path>,
+{
+    let name = marker_name(path);
+    let data = match fs::read(&name) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    fs::remove_file(&name)?;
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let (version, data) = data.split_at(4);
+    let (checksum, payload) = data.split_at(4);
+    if version != MARKER_VERSION.to_le_bytes() || crc32fast::hash(payload).to_le_bytes() != checksum
+    {
+        return Ok(None);
+    }
+    let marker: ShutdownMarker = bincode::deserialize(payload)?;
+    if marker.fileids != fileids {
+        return Ok(None);
+    }
+    Ok(Some(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_is_only_used_once_and_for_the_same_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = ShutdownMarker {
+            active_fileid: 2,
+            fileids: vec![0, 2],
+            stats: HashMap::default(),
+        };
+        write(dir.path(), &marker).unwrap();
+        assert!(take(dir.path(), &[0, 2]).unwrap().is_some());
+        assert!(take(dir.path(), &[0, 2]).unwrap().is_none());
+
+        // A file was written after the storage was closed
+        write(dir.path(), &marker).unwrap();
+        assert!(take(dir.path(), &[0, 2, 3]).unwrap().is_none());
+        assert!(!marker_name(dir.path()).exists());
+    }
+}
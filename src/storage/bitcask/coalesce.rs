@@ -0,0 +1,115 @@
+//! A small buffer that coalesces consecutive overwrites of the same key, so a key that is updated
+//! many times in a row takes one entry in the data files instead of one entry per write.
+//!
+//! Only overwrites of keys that are in the KeyDir and keep the keys' expiry are buffered, so the
+//! KeyDir still tells which keys exist and when they expire while their values are buffered.
+//! Reads look up the buffer before the data files. The writer writes the buffered values out when
+//! the buffer holds too many keys, when its oldest value has waited for the coalescing window, and
+//! before any write that isn't buffered, so the writes reach the data files in the order they
+//! were made.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{entry::DataFileEntry, utils};
+
+/// The overwrites that are waiting to be written to the active file.
+#[derive(Debug, Default)]
+pub(super) struct WriteBuffer {
+    inner: Mutex<Inner>,
+
+    /// The number of buffered keys, so reads skip the lock when the buffer is empty.
+    len: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The latest buffered entry of each key.
+    entries: HashMap<Bytes, DataFileEntry>,
+
+    /// The time at which the oldest buffered entry was buffered.
+    since: Option<Instant>,
+}
+
+impl WriteBuffer {
+    /// Get the buffered value of a key.
+    pub(super) fn get(&self, key: &Bytes) -> Option<Bytes> {
+        if self.len.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let inner = self.inner.lock();
+        inner.entries.get(key).and_then(|entry| entry.value.clone())
+    }
+
+    /// Buffer an entry, replacing the buffered entry of the same key. Returns `true` if an entry
+    /// was replaced.
+    pub(super) fn insert(&self, entry: DataFileEntry) -> bool {
+        let mut inner = self.inner.lock();
+        inner.since.get_or_insert_with(utils::instant);
+        let replaced = inner.entries.insert(entry.key.clone(), entry).is_some();
+        self.len.store(inner.entries.len(), Ordering::Release);
+        replaced
+    }
+
+    /// Return `true` if the buffer holds at least `max_keys` keys or its oldest entry has waited
+    /// for `window`.
+    pub(super) fn is_due(&self, window: Duration, max_keys: usize) -> bool {
+        let inner = self.inner.lock();
+        match inner.since {
+            Some(since) => inner.entries.len() >= max_keys || utils::instant() >= since + window,
+            None => false,
+        }
+    }
+
+    /// Get any of the buffered entries.
+    pub(super) fn first(&self) -> Option<DataFileEntry> {
+        let inner = self.inner.lock();
+        inner.entries.values().next().cloned()
+    }
+
+    /// Remove the buffered entry of a key once it has been written.
+    pub(super) fn remove(&self, key: &Bytes) {
+        let mut inner = self.inner.lock();
+        inner.entries.remove(key);
+        if inner.entries.is_empty() {
+            inner.since = None;
+        }
+        self.len.store(inner.entries.len(), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &'static str, value: &'static str) -> DataFileEntry {
+        DataFileEntry {
+            tstamp: 0,
+            key: Bytes::from(key),
+            value: Some(Bytes::from(value)),
+            expiry: None,
+        }
+    }
+
+    #[test]
+    fn overwrites_replace_the_buffered_entries() {
+        let buffer = WriteBuffer::default();
+        assert!(!buffer.is_due(Duration::ZERO, 1));
+        assert!(!buffer.insert(entry("a", "1")));
+        assert!(buffer.insert(entry("a", "2")));
+        assert_eq!(Some(Bytes::from("2")), buffer.get(&"a".into()));
+        assert!(buffer.is_due(Duration::from_secs(60), 1));
+        assert!(!buffer.is_due(Duration::from_secs(60), 2));
+
+        buffer.remove(&"a".into());
+        assert_eq!(None, buffer.get(&"a".into()));
+        assert!(buffer.first().is_none());
+        assert!(!buffer.is_due(Duration::ZERO, 1));
+    }
+}
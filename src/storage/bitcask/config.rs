@@ -1,11 +1,18 @@
 use std::{
-    num::{NonZeroU64, NonZeroUsize},
+    num::{NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
 use serde::Deserialize;
 
-use super::{Bitcask, Error};
+use super::{
+    filter::{CompactionFilter, SharedFilter, SharedTransform, ValueTransform},
+    tiering::{ColdStore, LocalColdStore, SharedColdStore},
+    utils::Layout,
+    Bitcask, Error, IndexDefinition,
+};
 
 /// Configuration for a `Bitcask` instance. We try to mirror the configurations
 /// available in [Configuring Bitcask].
@@ -19,17 +26,65 @@ pub struct Config {
 
     pub(super) concurrency: NonZeroUsize,
     pub(super) readers_cache_size: NonZeroUsize,
+    pub(super) max_open_files: Option<NonZeroUsize>,
+    pub(super) reader_affinity: bool,
 
     pub(super) max_file_size: NonZeroU64,
+    pub(super) merge_output_file_size: Option<NonZeroU64>,
+    pub(super) max_entry_size: NonZeroU32,
+    pub(super) value_chunk_size: NonZeroU32,
+    pub(super) fanout: Option<NonZeroU8>,
+    pub(super) ordered_keys: bool,
+    #[serde(skip)]
+    pub(super) indexes: Vec<IndexDefinition>,
     pub(super) sync: SyncStrategy,
+    pub(super) write_mode: WriteMode,
+    pub(super) mmap_advice: MmapAdvice,
+    pub(super) changes_capacity: NonZeroUsize,
+    pub(super) max_keys: Option<u64>,
+    pub(super) max_live_bytes: Option<u64>,
+    pub(super) quota_policy: QuotaPolicy,
+    pub(super) eviction_policy: EvictionPolicy,
+    pub(super) access_sampling: NonZeroU32,
+    pub(super) read_verification: Option<NonZeroU32>,
+    pub(super) hot_keys: Option<NonZeroUsize>,
+    pub(super) max_pending_writes: Option<NonZeroUsize>,
+    pub(super) coalesce_window_ms: Option<u64>,
+    pub(super) coalesce_max_keys: NonZeroUsize,
+    pub(super) checkpoint_interval_ms: Option<u64>,
+    pub(super) log_archive_dir: Option<PathBuf>,
+    pub(super) log_archive_interval_ms: Option<u64>,
+    pub(super) keydir_memory_budget: Option<u64>,
+    pub(super) keydir_shards: Option<NonZeroUsize>,
+    pub(super) keydir_hasher: KeyDirHasher,
+    pub(super) runtime: RuntimeMode,
     pub(super) merge: MergeStrategy,
+    pub(super) merge_delay_ms: Option<u64>,
+    pub(super) merge_on_open: bool,
+    pub(super) merge_archive_dir: Option<PathBuf>,
+    pub(super) merge_archive_retention_ms: Option<u64>,
+    pub(super) merge_tombstone_retention_ms: Option<u64>,
+    pub(super) merge_io: MergeIo,
+    pub(super) scrub_policy: ScrubPolicy,
+    pub(super) scrub_interval_ms: u64,
+    pub(super) scrub_bytes_per_sec: NonZeroU64,
+    pub(super) cold_dir: Option<PathBuf>,
+    #[serde(skip)]
+    pub(super) cold_store: Option<SharedColdStore>,
+    pub(super) cold_after_ms: u64,
+    pub(super) cold_cache_size: NonZeroU64,
+    #[serde(skip)]
+    pub(super) compaction_filter: Option<SharedFilter>,
+    #[serde(skip)]
+    pub(super) value_transform: Option<SharedTransform>,
 }
 
 /// Control how data is synchronized to disk.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum SyncStrategy {
     /// Data is written to disk when the operating system flushes its buffers.
+    #[default]
     None,
     /// Force a synchronization after every write.
     Always,
@@ -37,6 +92,106 @@ pub enum SyncStrategy {
     IntervalMs(u64),
 }
 
+/// Control how entries are appended to the active data file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// Entries are written to the file through a buffer.
+    #[default]
+    Buffered,
+    /// Data files are allocated with the max file size up front and entries are copied into a
+    /// memory map of the file. Data is flushed to disk according to the synchronization strategy.
+    Mmap,
+}
+
+/// Tell the operating system how the memory maps of the data files are read, which decides how
+/// much of a file it reads ahead. The advice is only given on Unix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MmapAdvice {
+    /// No advice is given, so the operating system uses its default readahead.
+    #[default]
+    Normal,
+    /// Pages are read in random order (`MADV_RANDOM`), so no readahead is done. This suits working
+    /// sets that are larger than the memory.
+    Random,
+    /// Pages are read in sequential order (`MADV_SEQUENTIAL`), so readahead is aggressive and
+    /// pages are freed soon after they are read.
+    Sequential,
+}
+
+/// Control how merges read and write data with respect to the page cache. Merges copy a lot of
+/// data that is rarely read right away, which can evict the pages that readers use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeIo {
+    /// Merges read and write through the page cache.
+    #[default]
+    Cached,
+    /// The pages of the merged files are released with `MADV_DONTNEED` once they are copied, and
+    /// the merge files are dropped from the page cache once they are written. Dropping the merge
+    /// files from the page cache is only supported on Linux.
+    DontNeed,
+    /// Like `DontNeed`, but the merge files are written with `O_DIRECT` so they bypass the page
+    /// cache altogether. This falls back to `DontNeed` on platforms and file systems without
+    /// support for direct I/O.
+    Direct,
+}
+
+/// Control what happens to a write that would take the storage over one of its quotas.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPolicy {
+    /// The write is rejected with `Error::QuotaExceeded`.
+    #[default]
+    Reject,
+    /// Other keys are deleted to make room for the write, which turns the storage into a cache.
+    /// Keys that have expired are deleted first, then the keys chosen by the eviction policy.
+    Evict,
+}
+
+/// Control which keys are deleted when the storage evicts keys to stay within its quotas. Keys are
+/// chosen from a small sample of the keys rather than from all of them, so the policies are only
+/// approximated.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Evict the least recently read or written key.
+    #[default]
+    Lru,
+    /// Evict the least frequently read or written key, where the frequency decays while the key
+    /// is not accessed.
+    Lfu,
+    /// Evict a random key.
+    Random,
+}
+
+/// Control how the keys are hashed by a KeyDir that is a hash map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyDirHasher {
+    /// SipHash-1-3 with random keys, which is the default hasher of the standard library.
+    #[default]
+    Sip,
+    /// aHash with random keys, which is much faster for small keys but is not a cryptographic
+    /// hash.
+    Ahash,
+}
+
+/// Control how the periodic merges, disk synchronizations, and checkpoints are run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeMode {
+    /// The tasks are scheduled by a Tokio runtime on a dedicated thread.
+    #[default]
+    Tokio,
+    /// The tasks are run on a dedicated thread without a Tokio runtime.
+    Sync,
+    /// No thread is started, and the tasks are only run when the caller ticks the
+    /// `MaintenanceDriver` returned by `Bitcask::maintenance_driver`.
+    Manual,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct MergeStrategy {
@@ -47,13 +202,36 @@ pub struct MergeStrategy {
     pub check_jitter: f64,
 }
 
+/// Control when the data files are scrubbed for corruption.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubPolicy {
+    /// The data files are never scrubbed in the background.
+    #[default]
+    Never,
+    /// The data files are scrubbed at any time of the day.
+    Always,
+    /// The data files are only scrubbed when a scrub is due within a window of hours in local
+    /// time.
+    Window {
+        /// The first hour of the window.
+        start: u32,
+        /// The last hour of the window, which is included.
+        end: u32,
+    },
+}
+
 /// Control how data files are merged.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MergePolicy {
+    #[default]
     Always,
     Never,
-    Window { start: u32, end: u32 },
+    Window {
+        start: u32,
+        end: u32,
+    },
 }
 
 /// List of conditions that trigger the data files merging process
@@ -77,19 +255,56 @@ impl Default for Config {
             path: std::env::current_dir().unwrap(),
             concurrency: NonZeroUsize::new(num_cpus::get()).unwrap(),
             readers_cache_size: NonZeroUsize::new(256).unwrap(),
+            max_open_files: None,
+            reader_affinity: true,
             max_file_size: NonZeroU64::new(2 * 1024 * 1024 * 1024).unwrap(),
+            merge_output_file_size: None,
+            max_entry_size: NonZeroU32::MAX,
+            value_chunk_size: NonZeroU32::new(4 * 1024 * 1024).unwrap(),
+            fanout: None,
+            ordered_keys: false,
+            indexes: Vec::new(),
             sync: SyncStrategy::default(),
+            write_mode: WriteMode::default(),
+            mmap_advice: MmapAdvice::default(),
+            changes_capacity: NonZeroUsize::new(1024).unwrap(),
+            max_keys: None,
+            max_live_bytes: None,
+            quota_policy: QuotaPolicy::default(),
+            eviction_policy: EvictionPolicy::default(),
+            access_sampling: NonZeroU32::new(1).unwrap(),
+            read_verification: None,
+            hot_keys: None,
+            max_pending_writes: None,
+            coalesce_window_ms: None,
+            coalesce_max_keys: NonZeroUsize::new(1024).unwrap(),
+            checkpoint_interval_ms: None,
+            log_archive_dir: None,
+            log_archive_interval_ms: None,
+            keydir_memory_budget: None,
+            keydir_shards: None,
+            keydir_hasher: KeyDirHasher::default(),
+            runtime: RuntimeMode::default(),
             merge: MergeStrategy::default(),
+            merge_delay_ms: None,
+            merge_on_open: false,
+            merge_archive_dir: None,
+            merge_archive_retention_ms: None,
+            merge_tombstone_retention_ms: None,
+            merge_io: MergeIo::default(),
+            scrub_policy: ScrubPolicy::default(),
+            scrub_interval_ms: 24 * 60 * 60 * 1000,
+            scrub_bytes_per_sec: NonZeroU64::new(8 * 1024 * 1024).unwrap(),
+            cold_dir: None,
+            cold_store: None,
+            cold_after_ms: 24 * 60 * 60 * 1000,
+            cold_cache_size: NonZeroU64::new(64 * 1024 * 1024).unwrap(),
+            compaction_filter: None,
+            value_transform: None,
         }
     }
 }
 
-impl Default for SyncStrategy {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 impl Default for MergeStrategy {
     fn default() -> Self {
         Self {
@@ -101,11 +316,6 @@ impl Default for MergeStrategy {
         }
     }
 }
-impl Default for MergePolicy {
-    fn default() -> Self {
-        Self::Always
-    }
-}
 
 impl Default for MergeTriggers {
     fn default() -> Self {
@@ -131,6 +341,50 @@ impl Config {
         Bitcask::open(self)
     }
 
+    /// Create a `Bitcask` instance at the given path without waiting for its KeyDir to be
+    /// rebuilt. Operations on the instance return `Error::Recovering` until the rebuild finishes,
+    /// and its progress can be checked with `Handle::recovery_progress`.
+    pub fn open_background(self) -> Result<Bitcask, Error> {
+        Bitcask::open_background(self)
+    }
+
+    /// Check the settings that can't be checked by the type system, which is needed for
+    /// configurations that are deserialized rather than built.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let MergePolicy::Window { start, end } = self.merge.policy {
+            if start >= 24 || end >= 24 {
+                return Err(Error::InvalidConfig(
+                    "merge window hours must be within [0, 24)",
+                ));
+            }
+        }
+        if let ScrubPolicy::Window { start, end } = self.scrub_policy {
+            if start >= 24 || end >= 24 {
+                return Err(Error::InvalidConfig(
+                    "scrub window hours must be within [0, 24)",
+                ));
+            }
+        }
+        if let Some(shards) = self.keydir_shards {
+            if shards.get() < 2 || !shards.is_power_of_two() {
+                return Err(Error::InvalidConfig(
+                    "KeyDir shards must be a power of two greater than 1",
+                ));
+            }
+        }
+        let fractions = [
+            self.merge.triggers.fragmentation,
+            self.merge.thresholds.fragmentation,
+            self.merge.check_jitter,
+        ];
+        if fractions.iter().any(|f| !(0.0..=1.0).contains(f)) {
+            return Err(Error::InvalidConfig(
+                "merge fragmentations and jitter must be within [0, 1]",
+            ));
+        }
+        Ok(())
+    }
+
     /// Set path to the storage directory. Default to the current directory.
     pub fn path<P>(&mut self, path: P) -> &mut Self
     where
@@ -153,18 +407,266 @@ impl Config {
         self
     }
 
+    /// Set the max number of data files that are kept open by all the readers caches together,
+    /// including the ones of the writer, the merges, and the dedicated readers of clients. When
+    /// the limit is reached, a cache closes its least recently used files before opening another
+    /// one. Default to no limit other than the size of each cache.
+    pub fn max_open_files(&mut self, max_open_files: NonZeroUsize) -> &mut Self {
+        self.max_open_files = Some(max_open_files);
+        self
+    }
+
+    /// Set whether each client, such as a network connection, gets its own reader instead of
+    /// sharing the readers queue. Each of these readers keeps its own cache of
+    /// `readers_cache_size` files open. Default to `true`.
+    pub fn reader_affinity(&mut self, reader_affinity: bool) -> &mut Self {
+        self.reader_affinity = reader_affinity;
+        self
+    }
+
     /// Set the max file size in byte. Default to `2GiBs`.
     pub fn max_file_size(&mut self, max_file_size: NonZeroU64) -> &mut Self {
         self.max_file_size = max_file_size;
         self
     }
 
+    /// Set the size in bytes at which the files written by merges are rotated, which can be
+    /// larger than the max file size so merged data is kept in fewer files. Default to the max
+    /// file size.
+    pub fn merge_output_file_size(&mut self, merge_output_file_size: NonZeroU64) -> &mut Self {
+        self.merge_output_file_size = Some(merge_output_file_size);
+        self
+    }
+
+    /// Get the size in bytes at which the files written by merges are rotated.
+    pub(super) fn merge_output_limit(&self) -> u64 {
+        self.merge_output_file_size
+            .unwrap_or(self.max_file_size)
+            .get()
+    }
+
+    /// Set the max size in bytes of an entry in the data files, which includes the key, the value,
+    /// and the entry's header. Larger writes are rejected. The KeyDir stores entry sizes as `u32`,
+    /// so this can't be raised above `4GiBs`, which is the default.
+    pub fn max_entry_size(&mut self, max_entry_size: NonZeroU32) -> &mut Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    /// Set the number of bytes in each of the chunks that values written with
+    /// `Handle::put_reader` are split into. The size is lowered if needed so an entry holding a
+    /// chunk stays within the max entry size and fills at most half of a data file. Default to
+    /// `4MiBs`.
+    pub fn value_chunk_size(&mut self, value_chunk_size: NonZeroU32) -> &mut Self {
+        self.value_chunk_size = value_chunk_size;
+        self
+    }
+
+    /// Spread the data files and the hint files over the given number of subdirectories, `data/00`,
+    /// `data/01`, ..., rather than keeping all of them in the storage directory. Existing files
+    /// are moved to match the layout when the storage is opened. Default to no subdirectories.
+    pub fn fanout(&mut self, dirs: NonZeroU8) -> &mut Self {
+        self.fanout = Some(dirs);
+        self
+    }
+
+    /// Return where the data files and the hint files are placed.
+    pub(super) fn layout(&self) -> Layout {
+        self.fanout.map_or(Layout::Flat, Layout::Fanout)
+    }
+
+    /// Set whether to maintain an ordered index over the keys so range queries don't have to sort
+    /// the keys when the KeyDir does not keep them in order. Default to `false`.
+    pub fn ordered_keys(&mut self, ordered_keys: bool) -> &mut Self {
+        self.ordered_keys = ordered_keys;
+        self
+    }
+
+    /// Add a secondary index over the values. Indexes are rebuilt when the storage is opened.
+    pub fn index(&mut self, index: IndexDefinition) -> &mut Self {
+        self.indexes.push(index);
+        self
+    }
+
     /// Set the synchronization strategy. Default to `SyncStrategy::None`.
     pub fn sync(&mut self, sync: SyncStrategy) -> &mut Self {
         self.sync = sync;
         self
     }
 
+    /// Set the number of committed writes that are buffered for change subscribers. Subscribers
+    /// that fall further behind miss the oldest changes. Default to `1024`.
+    pub fn changes_capacity(&mut self, changes_capacity: NonZeroUsize) -> &mut Self {
+        self.changes_capacity = changes_capacity;
+        self
+    }
+
+    /// Set how entries are appended to the active data file. Default to `WriteMode::Buffered`.
+    pub fn write_mode(&mut self, write_mode: WriteMode) -> &mut Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// Set the access pattern that the memory maps of the data files are advised with.
+    /// Default to `MmapAdvice::Normal`.
+    pub fn mmap_advice(&mut self, mmap_advice: MmapAdvice) -> &mut Self {
+        self.mmap_advice = mmap_advice;
+        self
+    }
+
+    /// Set the max number of keys the storage can hold. Default to no limit.
+    pub fn max_keys(&mut self, max_keys: u64) -> &mut Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Set the max number of bytes the live entries in the data files can occupy, which includes
+    /// the keys, the values, and the entries' headers. Default to no limit.
+    pub fn max_live_bytes(&mut self, max_live_bytes: u64) -> &mut Self {
+        self.max_live_bytes = Some(max_live_bytes);
+        self
+    }
+
+    /// Set what happens to writes that exceed the quotas. Default to `QuotaPolicy::Reject`.
+    pub fn quota_policy(&mut self, quota_policy: QuotaPolicy) -> &mut Self {
+        self.quota_policy = quota_policy;
+        self
+    }
+
+    /// Set how keys are chosen when evicting keys. Default to `EvictionPolicy::Lru`.
+    pub fn eviction_policy(&mut self, eviction_policy: EvictionPolicy) -> &mut Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Set the number of reads out of which one read, on average, updates the access statistics
+    /// of its key. Sampling makes reads cheaper when many threads read the same keys, at the cost
+    /// of less accurate idle times and eviction decisions. Writes are always recorded.
+    /// Default to `1`.
+    pub fn access_sampling(&mut self, access_sampling: NonZeroU32) -> &mut Self {
+        self.access_sampling = access_sampling;
+        self
+    }
+
+    /// Cross-check one out of every given number of reads against the data file entry that its
+    /// KeyDir entry points to. Reads whose entry holds another key or was written at another time
+    /// are logged and fail with `Error::Corruption`, which helps with diagnosing a corrupted
+    /// KeyDir. Verified reads decode the whole entry, including the key. Default to no
+    /// verification.
+    pub fn read_verification(&mut self, every: NonZeroU32) -> &mut Self {
+        self.read_verification = Some(every);
+        self
+    }
+
+    /// Track the most read and written keys with the given number of counters, so skewed
+    /// workloads can be diagnosed. The more counters, the more accurate the tracked keys and their
+    /// counts. Reads are sampled like the access statistics. Default to no tracking.
+    pub fn hot_keys(&mut self, counters: NonZeroUsize) -> &mut Self {
+        self.hot_keys = Some(counters);
+        self
+    }
+
+    /// Set the max number of writes that can wait for the writer, so writes fail fast with
+    /// `Error::Busy` instead of piling up when the writer falls behind, such as when the disk is
+    /// slow. Default to no limit.
+    pub fn max_pending_writes(&mut self, max_pending_writes: NonZeroUsize) -> &mut Self {
+        self.max_pending_writes = Some(max_pending_writes);
+        self
+    }
+
+    /// Coalesce the overwrites of a key that are made within the given number of milliseconds
+    /// into one entry in the data files, which cuts the dead bytes of keys that are updated many
+    /// times in a row. Only overwrites of existing keys that keep the keys' expiry are coalesced.
+    /// Reads see the coalesced values right away, but the secondary indexes, the change
+    /// subscribers, and [`Handle::get_as_of`] only see them once they're written. Writes that are
+    /// still coalescing are lost on a crash. This is ignored with `SyncStrategy::Always`. Default
+    /// to no coalescing.
+    ///
+    /// [`Handle::get_as_of`]: super::Handle::get_as_of
+    pub fn coalesce_window_ms(&mut self, window_ms: u64) -> &mut Self {
+        self.coalesce_window_ms = Some(window_ms);
+        self
+    }
+
+    /// Get the window in which overwrites are coalesced, if they're coalesced.
+    pub(super) fn coalesce_window(&self) -> Option<Duration> {
+        match self.sync {
+            SyncStrategy::Always => None,
+            _ => self.coalesce_window_ms.map(Duration::from_millis),
+        }
+    }
+
+    /// Set the max number of keys whose overwrites are coalesced at once. The coalesced values
+    /// are written before the window ends when there are more keys. Default to `1024`.
+    pub fn coalesce_max_keys(&mut self, max_keys: NonZeroUsize) -> &mut Self {
+        self.coalesce_max_keys = max_keys;
+        self
+    }
+
+    /// Set the number of milliseconds between checkpoints of the KeyDir. A restart loads the
+    /// last checkpoint and only reads the data files that were written after it. Default to no
+    /// checkpoints.
+    pub fn checkpoint_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.checkpoint_interval_ms = Some(interval_ms);
+        self
+    }
+
+    /// Add every data file to the given directory once the writer closes it, so the storage can
+    /// be restored to any point in time with [`Bitcask::restore_to`]. Files written by merges
+    /// aren't added. Default to not archiving the data files.
+    ///
+    /// [`Bitcask::restore_to`]: super::Bitcask::restore_to
+    pub fn log_archive_dir<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.log_archive_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the number of milliseconds between the copies of the active data file that are added
+    /// to the log archive, which bounds the writes that can't be restored. Default to only
+    /// archiving the data files once they're closed.
+    pub fn log_archive_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.log_archive_interval_ms = Some(interval_ms);
+        self
+    }
+
+    /// Set the max number of bytes the in-memory KeyDir entries can take before the least
+    /// recently written ones are spilled to an index on disk. This is only used when the crate is
+    /// built with the `keydir-spill` feature. Default to keeping all entries in memory.
+    pub fn keydir_memory_budget(&mut self, budget: u64) -> &mut Self {
+        self.keydir_memory_budget = Some(budget);
+        self
+    }
+
+    /// Set the number of shards of the KeyDir, which must be a power of two greater than 1. More
+    /// shards means less contention between the writer and the readers on the same shard. This
+    /// is only used when the crate is built with the `keydir-dashmap` feature. Default to four
+    /// times the number of logical cores, rounded up to a power of two.
+    ///
+    /// # Panics
+    ///
+    /// If the given number is not a power of two greater than 1 then panics
+    pub fn keydir_shards(&mut self, shards: NonZeroUsize) -> &mut Self {
+        assert!(shards.get() > 1 && shards.is_power_of_two());
+        self.keydir_shards = Some(shards);
+        self
+    }
+
+    /// Set how the keys are hashed by the KeyDir. This is only used when the crate is built with
+    /// the `keydir-dashmap` feature. Default to `KeyDirHasher::Sip`.
+    pub fn keydir_hasher(&mut self, hasher: KeyDirHasher) -> &mut Self {
+        self.keydir_hasher = hasher;
+        self
+    }
+
+    /// Set how the periodic maintenance tasks are run. Default to `RuntimeMode::Tokio`.
+    pub fn runtime(&mut self, runtime: RuntimeMode) -> &mut Self {
+        self.runtime = runtime;
+        self
+    }
+
     /// Set the merge policy. Default to `MergePolicy::Always`.
     pub fn merge_policy(&mut self, policy: MergePolicy) -> &mut Self {
         if let MergePolicy::Window { start, end } = policy {
@@ -238,4 +740,152 @@ impl Config {
         self.merge.check_jitter = check_jitter;
         self
     }
+
+    /// Set the number of milliseconds to wait after the storage is opened, or after it has
+    /// recovered in the background, before the merge triggers are first checked. This leaves
+    /// time for the page cache to warm up before merges compete with the reads. Default to the
+    /// merge check interval.
+    pub fn merge_delay_ms(&mut self, delay_ms: u64) -> &mut Self {
+        self.merge_delay_ms = Some(delay_ms);
+        self
+    }
+
+    /// Set whether to merge the data files right after the storage is opened, or after it has
+    /// recovered in the background, without waiting for the merge triggers or the merge policy.
+    /// The files are still chosen by the merge thresholds. Default to `false`.
+    pub fn merge_on_open(&mut self, merge_on_open: bool) -> &mut Self {
+        self.merge_on_open = merge_on_open;
+        self
+    }
+
+    /// Move the files that are replaced by merges into the given directory instead of removing
+    /// them, so the storage can be restored from them. Default to removing the files.
+    pub fn merge_archive_dir<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.merge_archive_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the number of milliseconds that archived files are kept for, counted from when they
+    /// were last written. Older files are removed after each merge. Default to keeping archived
+    /// files forever.
+    pub fn merge_archive_retention_ms(&mut self, retention_ms: u64) -> &mut Self {
+        self.merge_archive_retention_ms = Some(retention_ms);
+        self
+    }
+
+    /// Set the number of milliseconds that tombstones are kept for by merges, counted from when
+    /// the keys were deleted. Younger tombstones are copied to the merge files, so consumers that
+    /// replay the data files, such as replicas and backups, don't bring deleted keys back.
+    /// Default to dropping tombstones at the first merge.
+    pub fn merge_tombstone_retention_ms(&mut self, retention_ms: u64) -> &mut Self {
+        self.merge_tombstone_retention_ms = Some(retention_ms);
+        self
+    }
+
+    /// Set how merges use the page cache. Values that are read right after a merge are read from
+    /// disk when the merge files are kept out of the page cache. Default to `MergeIo::Cached`.
+    pub fn merge_io(&mut self, merge_io: MergeIo) -> &mut Self {
+        self.merge_io = merge_io;
+        self
+    }
+
+    /// Set when the data files are scrubbed in the background. Scrubs read every data file that
+    /// is no longer written to, check that its entries can be decoded and match the KeyDir and
+    /// that its hint file passes its checksums, and repair corrupt data files from the log
+    /// archive. Default to `ScrubPolicy::Never`.
+    ///
+    /// # Panics
+    ///
+    /// If the window's hours are not in [0, 24) then panics
+    pub fn scrub_policy(&mut self, policy: ScrubPolicy) -> &mut Self {
+        if let ScrubPolicy::Window { start, end } = policy {
+            assert!((0..24).contains(&start));
+            assert!((0..24).contains(&end));
+        }
+        self.scrub_policy = policy;
+        self
+    }
+
+    /// Set the number of milliseconds between the scrubs of the data files. Default to a day.
+    pub fn scrub_interval_ms(&mut self, interval_ms: u64) -> &mut Self {
+        self.scrub_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set the max number of bytes that scrubs read per second, so they don't take the disk
+    /// bandwidth away from the reads and the writes. Default to `8MiBs`.
+    pub fn scrub_bytes_per_sec(&mut self, bytes_per_sec: NonZeroU64) -> &mut Self {
+        self.scrub_bytes_per_sec = bytes_per_sec;
+        self
+    }
+
+    /// Move the merge files that haven't changed for `cold_after_ms` to a [`LocalColdStore`] in
+    /// the given directory, such as a cheaper disk or a mounted bucket. See
+    /// [`Config::cold_store`]. Default to keeping every data file in the storage directory.
+    pub fn cold_dir<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.cold_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Move the merge files that haven't changed for `cold_after_ms` to the given store, e.g. an
+    /// object store, after each merge. Only their hint files are kept in the storage directory,
+    /// and the reads of their keys fetch the entries from the store. This takes precedence over
+    /// [`Config::cold_dir`]. Default to keeping every data file in the storage directory.
+    pub fn cold_store<S>(&mut self, store: S) -> &mut Self
+    where
+        S: ColdStore + 'static,
+    {
+        self.cold_store = Some(SharedColdStore(Arc::new(store)));
+        self
+    }
+
+    /// Get the store that cold data files are moved to, if tiering is enabled.
+    pub(super) fn cold_backend(&self) -> Option<Arc<dyn ColdStore>> {
+        if let Some(store) = &self.cold_store {
+            return Some(Arc::clone(&store.0));
+        }
+        self.cold_dir
+            .as_ref()
+            .map(|dir| Arc::new(LocalColdStore::new(dir)) as Arc<dyn ColdStore>)
+    }
+
+    /// Set the number of milliseconds that a merge file must go unchanged for before it's moved
+    /// to the cold store. Default to a day.
+    pub fn cold_after_ms(&mut self, cold_after_ms: u64) -> &mut Self {
+        self.cold_after_ms = cold_after_ms;
+        self
+    }
+
+    /// Set the max number of bytes of the entries fetched from the cold store that are cached in
+    /// memory. Default to `64MiBs`.
+    pub fn cold_cache_size(&mut self, cold_cache_size: NonZeroU64) -> &mut Self {
+        self.cold_cache_size = cold_cache_size;
+        self
+    }
+
+    /// Set the filter that decides what merges do with each live entry that they copy. Default to
+    /// copying every entry as it is.
+    pub fn compaction_filter<F>(&mut self, filter: F) -> &mut Self
+    where
+        F: CompactionFilter + 'static,
+    {
+        self.compaction_filter = Some(SharedFilter(Arc::new(filter)));
+        self
+    }
+
+    /// Set the transformation that merges apply to the values that they copy, which re-encodes
+    /// the values as their files are merged. Default to copying the values as they are.
+    pub fn value_transform<T>(&mut self, transform: T) -> &mut Self
+    where
+        T: ValueTransform + 'static,
+    {
+        self.value_transform = Some(SharedTransform(Arc::new(transform)));
+        self
+    }
 }
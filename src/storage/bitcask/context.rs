@@ -1,45 +1,418 @@
+use std::{
+    collections::BTreeSet,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
 use bytes::Bytes;
 use crossbeam::atomic::AtomicCell;
-use crossbeam_skiplist::{map::Entry, SkipMap};
+use parking_lot::RwLock;
+use rand::Rng;
+use tokio::sync::broadcast;
+
+use super::{
+    access::AccessTracker,
+    archive::History,
+    changes::Change,
+    chunks,
+    coalesce::WriteBuffer,
+    config::{MergeStrategy, QuotaPolicy},
+    durability::SyncGroup,
+    hotkeys::HotKeys,
+    index::SecondaryIndexes,
+    keydir::{DefaultKeyDir, KeyDir, KeyDirEntry},
+    log::{FileRefs, LogDir, OpenFiles},
+    metrics::Metrics,
+    tiering::ColdFiles,
+    utils, Config, Error,
+};
+
+/// The progress of rebuilding the KeyDir of a storage that was opened in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// The number of data files that have been read.
+    pub files_recovered: u64,
+    /// The number of data files that have to be read.
+    pub files_total: u64,
+}
 
-use super::Config;
+/// A write that is waiting for the writer, which stops being counted once dropped.
+pub(super) struct PendingWrite<'a>(&'a AtomicUsize);
+
+impl Drop for PendingWrite<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
 
 /// The context holds states that are shared across both reads and writes operations.
 #[derive(Debug)]
 pub(super) struct Context {
     /// The mapping from keys to the positions of their values on disk.
-    keydir: SkipMap<Bytes, KeyDirEntry>,
+    keydir: DefaultKeyDir,
+
+    /// A secondary index that keeps the keys in lexicographic order. This is only maintained when
+    /// ordered keys are requested and the KeyDir can not iterate its keys in order by itself.
+    ordered_keys: Option<RwLock<BTreeSet<Bytes>>>,
+
+    /// The user-defined secondary indexes over the values.
+    indexes: SecondaryIndexes,
+
+    /// The overwrites that are coalesced before they're written.
+    write_buffer: WriteBuffer,
+
+    /// The sending half of the queue that carries committed writes to the subscribers.
+    changes: broadcast::Sender<Change>,
+
+    /// The contention metrics of the readers and the writer.
+    metrics: Metrics,
+
+    /// The writers waiting for the next disk synchronization.
+    sync_group: SyncGroup,
+
+    /// The number of writes waiting for the writer.
+    pending_writes: AtomicUsize,
+
+    /// The approximate access statistics of the keys.
+    access: AccessTracker,
+
+    /// The most accessed keys, when they are tracked.
+    hot_keys: Option<HotKeys>,
+
+    /// The limit on the number of data files kept open by all the readers caches.
+    open_files: Arc<OpenFiles>,
+
+    /// The data files that the readers caches have open, so merges defer removing them.
+    file_refs: Arc<FileRefs>,
+
+    /// The indexes of the data files for looking up the past values of keys.
+    history: History,
+
+    /// The data files that were moved to the cold store, if tiering is enabled.
+    cold_files: Option<Arc<ColdFiles>>,
+
+    /// The number of keys in the KeyDir.
+    live_keys: AtomicU64,
+
+    /// The number of bytes occupied by the entries that the KeyDir points to.
+    live_bytes: AtomicU64,
+
+    /// The number of reads that were counted towards the read verification interval.
+    reads: AtomicU64,
+
+    /// The number of merges that have removed data files, which tells the readers when to look
+    /// for the retired files in their caches. The files themselves are tracked by `file_refs`,
+    /// only while readers have them open.
+    merge_epoch: AtomicU64,
 
     /// Mark whether the storage has been closed
     closed: AtomicCell<bool>,
 
-    /// Storage configurations.
+    /// Mark whether the KeyDir is being rebuilt in the background
+    recovering: AtomicCell<bool>,
+
+    /// Mark whether the background merges and disk synchronizations are paused
+    maintenance_paused: AtomicCell<bool>,
+
+    /// The number of data files that have been read while recovering.
+    files_recovered: AtomicU64,
+
+    /// The number of data files that have to be read while recovering.
+    files_to_recover: AtomicU64,
+
+    /// The merge settings, which can be changed while the storage is running.
+    merge: RwLock<MergeStrategy>,
+
+    /// Storage configurations. The merge settings in here are the ones the storage was opened
+    /// with, the current ones are kept in `merge`.
     conf: Config,
 }
 
 impl Context {
     /// Create a new Context for holding shared Bitcask states.
-    pub(super) fn new(conf: Config, keydir: SkipMap<Bytes, KeyDirEntry>) -> Self {
+    pub(super) fn new(
+        conf: Config,
+        keydir: DefaultKeyDir,
+        cold_files: Option<Arc<ColdFiles>>,
+    ) -> Self {
+        let ordered_keys = (conf.ordered_keys && !DefaultKeyDir::ORDERED)
+            .then(|| RwLock::new(keydir.iter().map(|(k, _)| k).collect()));
+        let indexes = SecondaryIndexes::new(&conf.indexes);
+        let (changes, _) = broadcast::channel(conf.changes_capacity.get());
+        // Chunks count toward the live bytes but not the live keys
+        let (live_keys, live_bytes) = keydir.iter().fold((0, 0), |(keys, bytes), (k, e)| {
+            (keys + u64::from(!chunks::is_chunk_key(&k)), bytes + e.len())
+        });
+        // Access frequencies are only used for choosing the keys to evict
+        let access = AccessTracker::new(matches!(conf.quota_policy, QuotaPolicy::Evict));
+        let hot_keys = conf.hot_keys.map(|counters| HotKeys::new(counters.get()));
         Self {
+            merge: RwLock::new(conf.merge.clone()),
+            open_files: Arc::new(OpenFiles::new(conf.max_open_files)),
+            file_refs: Arc::default(),
             conf,
             keydir,
+            ordered_keys,
+            indexes,
+            changes,
+            write_buffer: WriteBuffer::default(),
+            metrics: Metrics::default(),
+            sync_group: SyncGroup::default(),
+            pending_writes: AtomicUsize::new(0),
+            access,
+            hot_keys,
+            history: History::default(),
+            cold_files,
+            live_keys: AtomicU64::new(live_keys),
+            live_bytes: AtomicU64::new(live_bytes),
+            reads: AtomicU64::new(0),
+            merge_epoch: AtomicU64::new(0),
             closed: AtomicCell::new(false),
+            recovering: AtomicCell::new(false),
+            maintenance_paused: AtomicCell::new(false),
+            files_recovered: AtomicU64::new(0),
+            files_to_recover: AtomicU64::new(0),
         }
     }
 
     /// Set the keydir and returns the previous set entry if there's any.
-    pub(super) fn keydir_set(
-        &self,
-        key: Bytes,
-        keydir_entry: KeyDirEntry,
-    ) -> Option<Entry<'_, Bytes, KeyDirEntry>> {
-        let prev_entry = self.keydir.get(&key);
-        self.keydir.insert(key, keydir_entry);
+    pub(super) fn keydir_set(&self, key: Bytes, keydir_entry: KeyDirEntry) -> Option<KeyDirEntry> {
+        if let Some(ordered_keys) = &self.ordered_keys {
+            ordered_keys.write().insert(key.clone());
+        }
+        self.live_bytes
+            .fetch_add(keydir_entry.len(), Ordering::Relaxed);
+        let prev_entry = self.keydir.insert(key.clone(), keydir_entry);
+        match prev_entry {
+            Some(prev_entry) => {
+                self.live_bytes
+                    .fetch_sub(prev_entry.len(), Ordering::Relaxed);
+            }
+            None if chunks::is_chunk_key(&key) => {}
+            None => {
+                self.live_keys.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        prev_entry
+    }
+
+    /// Remove the key from the keydir and returns the removed entry if there's any.
+    pub(super) fn keydir_remove(&self, key: &Bytes) -> Option<KeyDirEntry> {
+        if let Some(ordered_keys) = &self.ordered_keys {
+            ordered_keys.write().remove(key);
+        }
+        let prev_entry = self.keydir.remove(key);
+        if let Some(prev_entry) = prev_entry {
+            if !chunks::is_chunk_key(key) {
+                self.live_keys.fetch_sub(1, Ordering::Relaxed);
+            }
+            self.live_bytes
+                .fetch_sub(prev_entry.len(), Ordering::Relaxed);
+        }
         prev_entry
     }
 
+    /// Return the number of keys in the KeyDir and the number of bytes occupied by their
+    /// entries.
+    pub(super) fn get_usage(&self) -> (u64, u64) {
+        (
+            self.live_keys.load(Ordering::Relaxed),
+            self.live_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Return at most `count` keys within the given range in lexicographic order. Expired keys
+    /// and chunks are skipped.
+    pub(super) fn keydir_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Vec<Bytes> {
+        // `BTreeSet::range` panics on inverted or empty exclusive ranges, so we return early when
+        // the range can't contain any key.
+        let is_empty = match (&start, &end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s), Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e))
+            | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+            _ => false,
+        };
+        if is_empty {
+            return Vec::new();
+        }
+        let now = utils::timestamp();
+        match &self.ordered_keys {
+            Some(ordered_keys) => ordered_keys
+                .read()
+                .range((start, end))
+                .filter(|k| !chunks::is_chunk_key(k))
+                .filter(|k| self.keydir.get(k).is_some_and(|e| !e.is_expired(now)))
+                .take(count)
+                .cloned()
+                .collect(),
+            None => self
+                .keydir
+                .range(start, end)
+                .filter(|(k, e)| !chunks::is_chunk_key(k) && !e.is_expired(now))
+                .take(count)
+                .map(|(k, _)| k)
+                .collect(),
+        }
+    }
+
+    /// Send a committed write to the change subscribers, if there's any.
+    pub(super) fn publish_change(&self, key: Bytes, value: Option<Bytes>, tstamp: i64) {
+        if self.changes.receiver_count() > 0 {
+            // Sending only fails when all subscribers have gone away in the meantime
+            self.changes.send(Change { key, value, tstamp }).ok();
+        }
+    }
+
+    /// Create a new receiver for the committed writes.
+    pub(super) fn subscribe_changes(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+
+    /// Get a copy of the current merge settings.
+    pub(super) fn get_merge_strategy(&self) -> MergeStrategy {
+        self.merge.read().clone()
+    }
+
+    /// Replace the merge settings.
+    pub(super) fn set_merge_strategy(&self, merge: MergeStrategy) {
+        *self.merge.write() = merge;
+    }
+
+    /// Get a reference to the buffer of coalesced overwrites.
+    pub(super) fn get_write_buffer(&self) -> &WriteBuffer {
+        &self.write_buffer
+    }
+
+    /// Get a reference to the contention metrics.
+    pub(super) fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Get the number of data files kept open by all the readers caches.
+    pub(super) fn open_files(&self) -> usize {
+        self.open_files.count()
+    }
+
+    /// Create a new readers cache, whose open files are counted towards the shared limit.
+    pub(super) fn new_log_dir(&self) -> LogDir {
+        LogDir::new(
+            self.conf.readers_cache_size,
+            self.conf.layout(),
+            self.conf.mmap_advice,
+            Arc::clone(&self.open_files),
+            Arc::clone(&self.file_refs),
+            self.cold_files.clone(),
+        )
+    }
+
+    /// Get the data files that were moved to the cold store, if tiering is enabled.
+    pub(super) fn get_cold_files(&self) -> Option<&ColdFiles> {
+        self.cold_files.as_deref()
+    }
+
+    /// Get the data files that the readers caches have open.
+    pub(super) fn get_file_refs(&self) -> &FileRefs {
+        &self.file_refs
+    }
+
+    /// Get the number of merges that have removed data files.
+    pub(super) fn merge_epoch(&self) -> u64 {
+        self.merge_epoch.load(Ordering::Acquire)
+    }
+
+    /// Tell the readers that a merge has removed data files.
+    pub(super) fn finish_merge(&self) {
+        self.merge_epoch.fetch_add(1, Ordering::Release);
+    }
+
+    /// Get a reference to the writers waiting for the next disk synchronization.
+    pub(super) fn get_sync_group(&self) -> &SyncGroup {
+        &self.sync_group
+    }
+
+    /// Count a write as waiting for the writer until the returned guard is dropped. Returns
+    /// [`Error::Busy`] if the max number of writes are already waiting.
+    pub(super) fn queue_write(&self) -> Result<PendingWrite<'_>, Error> {
+        let pending = self.pending_writes.fetch_add(1, Ordering::AcqRel);
+        let guard = PendingWrite(&self.pending_writes);
+        if self
+            .conf
+            .max_pending_writes
+            .is_some_and(|max| pending >= max.get())
+        {
+            self.metrics.busy_writes.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::Busy);
+        }
+        Ok(guard)
+    }
+
+    /// Get the number of writes waiting for the writer.
+    pub(super) fn pending_writes(&self) -> usize {
+        self.pending_writes.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the history of the keys.
+    pub(super) fn get_history(&self) -> &History {
+        &self.history
+    }
+
+    /// Record a read of the key at the given Unix timestamp in nanoseconds, if the read is
+    /// sampled. Each sampled read counts for all the reads that were skipped.
+    pub(super) fn record_read(&self, key: &[u8], now: i64) {
+        // Chunks are read as part of the keys whose values they hold
+        if chunks::is_chunk_key(key) {
+            return;
+        }
+        let sampling = self.conf.access_sampling.get();
+        if sampling == 1 || utils::with_rng(|rng| rng.gen_ratio(1, sampling)) {
+            self.access.record(key, now, sampling);
+            self.record_hot_key(key, sampling);
+        }
+    }
+
+    /// Count accesses to the key among the hot keys, if they are tracked.
+    pub(super) fn record_hot_key(&self, key: &[u8], weight: u32) {
+        if let Some(hot_keys) = &self.hot_keys {
+            hot_keys.record(key, weight);
+        }
+    }
+
+    /// Get the most accessed keys, if they are tracked.
+    pub(super) fn get_hot_keys(&self) -> Option<&HotKeys> {
+        self.hot_keys.as_ref()
+    }
+
+    /// Return `true` if the current read should be cross-checked against the data file entry that
+    /// its KeyDir entry points to.
+    pub(super) fn should_verify_read(&self) -> bool {
+        self.conf.read_verification.is_some_and(|every| {
+            self.reads
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(u64::from(every.get()))
+        })
+    }
+
+    /// Get a reference to the access statistics.
+    pub(super) fn get_access(&self) -> &AccessTracker {
+        &self.access
+    }
+
+    /// Get a reference to the secondary indexes.
+    pub(super) fn get_indexes(&self) -> &SecondaryIndexes {
+        &self.indexes
+    }
+
     /// Get a reference to the keydir.
-    pub(super) fn get_keydir(&self) -> &SkipMap<Bytes, KeyDirEntry> {
+    pub(super) fn get_keydir(&self) -> &DefaultKeyDir {
         &self.keydir
     }
 
@@ -53,17 +426,54 @@ impl Context {
         self.closed.store(true)
     }
 
+    /// Return true if the background merges and disk synchronizations are paused.
+    pub(super) fn is_maintenance_paused(&self) -> bool {
+        self.maintenance_paused.load()
+    }
+
+    /// Pause or resume the background merges and disk synchronizations.
+    pub(super) fn set_maintenance_paused(&self, paused: bool) {
+        self.maintenance_paused.store(paused)
+    }
+
+    /// Return an error if the storage can't serve reads and writes, because it was closed or
+    /// because it's still recovering.
+    pub(super) fn check_available(&self) -> Result<(), Error> {
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        if self.recovering.load() {
+            return Err(Error::Recovering);
+        }
+        Ok(())
+    }
+
+    /// Mark the storage as recovering the given number of data files.
+    pub(super) fn start_recovery(&self, files: u64) {
+        self.files_to_recover.store(files, Ordering::Relaxed);
+        self.recovering.store(true);
+    }
+
+    /// Count a data file that has been read while recovering.
+    pub(super) fn record_recovered_file(&self) {
+        self.files_recovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Mark the storage as available once its KeyDir has been rebuilt.
+    pub(super) fn finish_recovery(&self) {
+        self.recovering.store(false);
+    }
+
+    /// Return the progress of the recovery, if the storage is recovering.
+    pub(super) fn recovery_progress(&self) -> Option<RecoveryProgress> {
+        self.recovering.load().then(|| RecoveryProgress {
+            files_recovered: self.files_recovered.load(Ordering::Relaxed),
+            files_total: self.files_to_recover.load(Ordering::Relaxed),
+        })
+    }
+
     /// Get the Bitcask instance configurations.
     pub(super) fn get_conf(&self) -> &Config {
         &self.conf
     }
 }
-
-/// A structure for the keydir entry pointing the position of the entry on the data file.
-#[derive(Debug)]
-pub(super) struct KeyDirEntry {
-    pub(super) fileid: u64,
-    pub(super) len: u64,
-    pub(super) pos: u64,
-    pub(super) tstamp: i64,
-}
@@ -0,0 +1,143 @@
+//! Crash-recovery tests that run random sequences of operations, cut the log at a random point
+//! to simulate a crash in the middle of a write, and check that the storage recovers to the
+//! state after some prefix of the operations. Every change to the log format must keep these
+//! tests passing.
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    num::{NonZeroU64, NonZeroUsize},
+    path::Path,
+};
+
+use bytes::Bytes;
+use proptest::{collection, prelude::*};
+
+use super::{utils, Config};
+
+/// The number of distinct keys, kept small so keys are overwritten and deleted often.
+const KEYS: u8 = 8;
+
+/// An operation applied to the storage and to the model.
+#[derive(Debug, Clone)]
+enum Op {
+    Put(u8, Vec<u8>),
+    Delete(u8),
+    Merge,
+    Checkpoint,
+}
+
+fn op_strategy() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        6 => (0..KEYS, collection::vec(any::<u8>(), 0..64)).prop_map(|(k, v)| Op::Put(k, v)),
+        3 => (0..KEYS).prop_map(Op::Delete),
+        1 => Just(Op::Merge),
+        1 => Just(Op::Checkpoint),
+    ]
+}
+
+fn key(k: u8) -> Bytes {
+    Bytes::from(format!("key{k}"))
+}
+
+/// Small data files, so the operations are spread over many files, and merges that include
+/// every file.
+fn crash_test_config(path: &Path) -> Config {
+    Config::default()
+        .path(path)
+        .concurrency(NonZeroUsize::new(1).unwrap())
+        .max_file_size(NonZeroU64::new(256).unwrap())
+        .merge_threshold_small_file(u64::MAX)
+        .to_owned()
+}
+
+/// Apply the operations and return the expected state after each prefix of them, starting with
+/// the empty state, along with the ID of the active data file before the storage is closed.
+fn run_ops(conf: &Config, ops: &[Op]) -> (Vec<BTreeMap<Bytes, Bytes>>, u64) {
+    let kv = conf.clone().open().unwrap();
+    let handle = kv.get_handle();
+
+    let mut model = BTreeMap::new();
+    let mut states = vec![model.clone()];
+    for op in ops {
+        match op {
+            Op::Put(k, v) => {
+                let v = Bytes::from(v.clone());
+                handle.put(key(*k), v.clone()).unwrap();
+                model.insert(key(*k), v);
+            }
+            Op::Delete(k) => {
+                handle.delete(key(*k)).unwrap();
+                model.remove(&key(*k));
+            }
+            Op::Merge => handle.lock_writer().merge().unwrap(),
+            Op::Checkpoint => handle.checkpoint().unwrap(),
+        }
+        states.push(model.clone());
+    }
+    handle.lock_writer().sync().unwrap();
+    // The active file has the highest ID while the storage is open
+    let active_fileid = utils::sorted_fileids(&conf.path).unwrap().last().unwrap();
+    (states, active_fileid)
+}
+
+/// Truncate the active data file to the given fraction of its length. Only the active file can
+/// lose data in a crash, since every other file was synced before the storage moved on from it.
+/// Returns whether any bytes were cut, which is never the case when the active file was empty
+/// and removed once the storage was closed.
+fn truncate_active_datafile(conf: &Config, fileid: u64, fraction: f64) -> bool {
+    let path = utils::datafile_name(&conf.path, conf.layout(), fileid);
+    let Ok(file) = OpenOptions::new().write(true).open(path) else {
+        return false;
+    };
+    let len = file.metadata().unwrap().len();
+    let cut = (len as f64 * fraction) as u64;
+    file.set_len(cut).unwrap();
+    cut < len
+}
+
+/// Read the value of every key in the key space.
+fn recovered_state(conf: &Config) -> BTreeMap<Bytes, Bytes> {
+    let kv = conf.clone().open().unwrap();
+    let handle = kv.get_handle();
+    (0..KEYS)
+        .filter_map(|k| handle.get(key(k)).unwrap().map(|v| (key(k), v)))
+        .collect()
+}
+
+#[test]
+fn bitcask_recovers_a_prefix_of_the_operations() {
+    let ops_strat = collection::vec(op_strategy(), 1..64);
+    proptest!(ProptestConfig::with_cases(64), |(ops in ops_strat, fraction in 0.0..1.0f64)| {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = crash_test_config(dir.path());
+
+        let (states, active_fileid) = run_ops(&conf, &ops);
+        let cut = truncate_active_datafile(&conf, active_fileid, fraction);
+        let recovered = recovered_state(&conf);
+
+        if cut {
+            prop_assert!(
+                states.contains(&recovered),
+                "recovered state {:?} is not the state after any prefix of the operations",
+                recovered
+            );
+        } else {
+            prop_assert_eq!(states.last().unwrap(), &recovered);
+        }
+    });
+}
+
+#[test]
+fn bitcask_recovers_every_operation_without_a_crash() {
+    let ops_strat = collection::vec(op_strategy(), 1..64);
+    proptest!(ProptestConfig::with_cases(64), |(ops in ops_strat)| {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = crash_test_config(dir.path());
+
+        let (states, _) = run_ops(&conf, &ops);
+        // Reopening twice makes sure recovery itself doesn't lose any data
+        prop_assert_eq!(states.last().unwrap(), &recovered_state(&conf));
+        prop_assert_eq!(states.last().unwrap(), &recovered_state(&conf));
+    });
+}
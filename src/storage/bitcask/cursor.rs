@@ -0,0 +1,65 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Handle};
+
+/// A resumable position within a scan over all keys of the storage.
+///
+/// The position is the last key that was returned rather than a location in the data files, so
+/// a token stays valid across merges, file rotations, and restarts. It can be persisted by
+/// serializing it and later be given to [`Handle::resume_cursor`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CursorToken {
+    /// The last key that was returned, or `None` if the scan has not started.
+    after: Option<Bytes>,
+    /// Whether the scan has visited all keys.
+    done: bool,
+}
+
+impl CursorToken {
+    /// Return `true` if the scan has visited all keys.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// A cursor that enumerates keys in lexicographic order in batches.
+///
+/// Since keys are visited in order, every key that is present for the entire scan is returned
+/// exactly once. Keys that are added or removed during the scan may or may not be returned.
+#[derive(Debug)]
+pub struct Cursor {
+    handle: Handle,
+    token: CursorToken,
+}
+
+impl Cursor {
+    pub(super) fn new(handle: Handle, token: CursorToken) -> Self {
+        Self { handle, token }
+    }
+
+    /// Return at most `count` keys that come after the cursor's position, and move the cursor
+    /// past them. An empty batch is returned once all keys have been visited.
+    pub fn next_batch(&mut self, count: usize) -> Result<Vec<Bytes>, Error> {
+        if self.token.done || count == 0 {
+            return Ok(Vec::new());
+        }
+        let start = match &self.token.after {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+        let keys = self.handle.scan_range(start, Bound::Unbounded, count)?;
+        self.token.done = keys.len() < count;
+        if let Some(key) = keys.last() {
+            self.token.after = Some(key.clone());
+        }
+        Ok(keys)
+    }
+
+    /// Return the token for resuming the scan from the cursor's current position.
+    pub fn token(&self) -> CursorToken {
+        self.token.clone()
+    }
+}
@@ -0,0 +1,121 @@
+//! Group commit for writes that wait for their data to be synchronized to disk. A waiter starts a
+//! synchronization when none is running. Otherwise, it waits for the running one to finish and
+//! then for the next one. That synchronization is shared by all the writers that arrived in the
+//! meantime, so concurrent writers pay for one disk synchronization rather than one each.
+
+use parking_lot::{Condvar, Mutex};
+
+use super::Error;
+
+/// Coordinates the writers waiting for the next disk synchronization.
+#[derive(Debug, Default)]
+pub(super) struct SyncGroup {
+    state: Mutex<SyncGroupState>,
+    finished: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct SyncGroupState {
+    /// The number of synchronizations that have been started.
+    started: u64,
+    /// The number of synchronizations that have finished.
+    finished: u64,
+    /// The sequence number of the last synchronization that failed, or 0.
+    failed: u64,
+}
+
+impl SyncGroup {
+    /// Wait until a synchronization that starts after this call has finished. If no
+    /// synchronization is running once the caller's turn comes, the caller runs `sync` on behalf of
+    /// the group. A waiter that didn't run `sync` gets `Error::SyncFailed` if the last
+    /// synchronization failed.
+    pub(super) fn wait<F>(&self, sync: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error>,
+    {
+        let mut state = self.state.lock();
+        // A running synchronization might have started before the caller's writes
+        let target = state.started + 1;
+        loop {
+            if state.finished >= target {
+                return if state.failed == state.finished {
+                    Err(Error::SyncFailed)
+                } else {
+                    Ok(())
+                };
+            }
+            if state.started == state.finished {
+                state.started += 1;
+                let seq = state.started;
+                let result = parking_lot::MutexGuard::unlocked(&mut state, sync);
+                state.finished = seq;
+                if result.is_err() {
+                    state.failed = seq;
+                }
+                self.finished.notify_all();
+                return result;
+            }
+            self.finished.wait(&mut state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Barrier,
+    };
+
+    use super::*;
+
+    #[test]
+    fn sequential_waits_run_their_own_sync() {
+        let group = SyncGroup::default();
+        let syncs = AtomicUsize::new(0);
+        for _ in 0..3 {
+            group
+                .wait(|| {
+                    syncs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert_eq!(3, syncs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn concurrent_waits_share_syncs() {
+        const THREADS: usize = 16;
+        let group = Arc::new(SyncGroup::default());
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let (group, syncs, barrier) = (group.clone(), syncs.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    group.wait(|| {
+                        syncs.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap().unwrap();
+        }
+        // Waiters that arrive while a synchronization is running share the next one
+        assert!(syncs.load(Ordering::SeqCst) < THREADS);
+    }
+
+    #[test]
+    fn failed_sync_is_reported_to_waiters() {
+        let group = SyncGroup::default();
+        let result = group.wait(|| Err(Error::Closed));
+        assert!(matches!(result, Err(Error::Closed)));
+        // The next waiter starts its own synchronization
+        group.wait(|| Ok(())).unwrap();
+    }
+}
@@ -0,0 +1,869 @@
+//! The on-disk layout of the entries in data files and hint files.
+//!
+//! The layout is stable and doesn't depend on any serialization library, so files can be read by
+//! other tools. Every file starts with a header, followed by entries that are concatenated without
+//! padding. Every entry starts with a fixed-size part, followed by variable-length integers, and
+//! ends with raw bytes and a checksum. Fixed-size integers are little-endian. Variable-length
+//! integers are unsigned LEB128, where each byte holds 7 bits of the value starting from the least
+//! significant bits, and the high bit is set on every byte but the last one.
+//!
+//! The header of a file tells what the file holds and which version of the layout it uses:
+//!
+//! ```text
+//! +----------+------------+---------+-------------+
+//! | magic: 4 | version: 2 | kind: 1 | reserved: 1 |
+//! +----------+------------+---------+-------------+
+//! ```
+//!
+//! `magic` is the bytes `OPAL`, `version` is 1, and `kind` is `D` for data files and `H` for hint
+//! files. Files that were written before the layout had a header, when entries were serialized
+//! with bincode, are refused with [`Error::UnsupportedFormat`], as are files of newer versions.
+//!
+//! A data file entry sets the value of a key or deletes the key:
+//!
+//! ```text
+//! +-----------+----------+-----------------+----------------------+---------------+-----+-------+--------+
+//! | tstamp: 8 | flags: 1 | key_len: varint | value_len: varint(?) | expiry: 8 (?) | key | value | crc: 4 |
+//! +-----------+----------+-----------------+----------------------+---------------+-----+-------+--------+
+//! ```
+//!
+//! Entries that are written together by a transaction are preceded by the header of their batch,
+//! which holds the number of entries and their total size:
+//!
+//! ```text
+//! +-----------+----------+---------------+-------------+--------+---------+
+//! | tstamp: 8 | flags: 1 | count: varint | len: varint | crc: 4 | entries |
+//! +-----------+----------+---------------+-------------+--------+---------+
+//! ```
+//!
+//! A hint file entry points to the data file entry that holds the value of a key:
+//!
+//! ```text
+//! +-----------+----------+-------------+-------------+-----------------+---------------+-----+--------+
+//! | tstamp: 8 | flags: 1 | len: varint | pos: varint | key_len: varint | expiry: 8 (?) | key | crc: 4 |
+//! +-----------+----------+-------------+-------------+-----------------+---------------+-----+--------+
+//! ```
+//!
+//! A hint file ends with a trailer that holds the number of entries in the file:
+//!
+//! ```text
+//! +-----------+----------+---------------+--------+
+//! | tstamp: 8 | flags: 1 | count: varint | crc: 4 |
+//! +-----------+----------+---------------+--------+
+//! ```
+//!
+//! `crc` is the CRC-32 (IEEE) checksum of all the preceding bytes of the entry or the trailer, or
+//! of the `len` bytes of the entries that follow the header of a batch. When a data file is read
+//! from start to end, an entry or a batch whose checksum doesn't match was cut off by a crash, or
+//! is in the zero-filled space that was allocated up front, and is read as the end of the file.
+//! So either all or none of the entries of a batch are read back. When an entry is read at the
+//! position that the KeyDir points to, a checksum that doesn't match is reported as corrupted
+//! data. Hint files can be left incomplete or stale by a failure during a merge, so a hint file
+//! is only used if all checksums match and its trailer holds the right count.
+//!
+//! `tstamp` and `expiry` are Unix timestamps in nanoseconds. Bit 0 of `flags` is set when a data
+//! file entry holds a value, in which case `value_len` is present. Tombstones have neither
+//! `value_len` nor value bytes. Bit 1 of `flags` is set when the entry has an expiry, in which
+//! case `expiry` is present. Bit 2 of `flags` is set only for the trailer of a hint file, whose
+//! `tstamp` is zero. Bit 3 of `flags` is set only for the header of a batch. Other bits are
+//! reserved and must be zero.
+//!
+//! Since the value comes right before the checksum at the end of a data file entry, it can be read
+//! without keeping the key through [`DataFileValue`].
+
+use std::io::{self, IoSlice, Read, Write};
+
+use bytes::Bytes;
+
+use super::Error;
+
+/// The bytes that every file starts with.
+const MAGIC: &[u8; 4] = b"OPAL";
+
+/// The version of the layout that is written.
+const VERSION: u16 = 1;
+
+/// Set in the flags of a data file entry that holds a value.
+const FLAG_VALUE: u8 = 0b01;
+
+/// Set in the flags of an entry that has an expiry.
+const FLAG_EXPIRY: u8 = 0b10;
+
+/// Set in the flags of the trailer of a hint file.
+const FLAG_TRAILER: u8 = 0b100;
+
+/// Set in the flags of the header of a batch of data file entries.
+const FLAG_BATCH: u8 = 0b1000;
+
+/// The max number of bytes that are allocated before reading the raw bytes of an entry.
+const MAX_PREALLOC_LEN: u64 = 1024 * 1024;
+
+/// The max number of bytes of a variable-length 64-bit integer.
+const MAX_VARINT_LEN: usize = 10;
+
+/// The max size of the part of an entry that comes before its raw bytes, which is reached by a
+/// hint file entry with an expiry.
+const MAX_HEADER_LEN: usize = 8 + 1 + 3 * MAX_VARINT_LEN + 8;
+
+/// A type that can be written in its on-disk layout.
+pub trait Encode {
+    /// Return the number of bytes the value occupies on disk.
+    fn encoded_len(&self) -> u64;
+
+    /// Write the value in its on-disk layout.
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error>;
+}
+
+/// A type that can be read from its on-disk layout.
+pub trait Decode: Sized {
+    /// Read a value that was written in its on-disk layout.
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error>;
+}
+
+/// The header that every data file and hint file starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHeader {
+    /// The header of a data file.
+    Data,
+    /// The header of a hint file.
+    Hint,
+}
+
+impl FileHeader {
+    /// The number of bytes of a file header, which is the position of the first entry of a file.
+    pub const LEN: u64 = 8;
+
+    fn kind(self) -> u8 {
+        match self {
+            Self::Data => b'D',
+            Self::Hint => b'H',
+        }
+    }
+}
+
+impl Encode for FileHeader {
+    fn encoded_len(&self) -> u64 {
+        Self::LEN
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        let mut header = Header::default();
+        header.put(MAGIC);
+        header.put(&VERSION.to_le_bytes());
+        header.put(&[self.kind(), 0]);
+        w.write_all(header.as_slice())?;
+        Ok(())
+    }
+}
+
+impl Decode for FileHeader {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let mut buf = [0u8; Self::LEN as usize];
+        r.read_exact(&mut buf)?;
+        if &buf[..4] != MAGIC {
+            return Err(Error::UnsupportedFormat(
+                "file has no header, it was written by a version that is no longer supported",
+            ));
+        }
+        if u16::from_le_bytes([buf[4], buf[5]]) != VERSION {
+            return Err(Error::UnsupportedFormat(
+                "file was written with an unknown version of the layout",
+            ));
+        }
+        match buf[6..] {
+            [b'D', 0] => Ok(Self::Data),
+            [b'H', 0] => Ok(Self::Hint),
+            _ => Err(Error::corrupted("file header has an unknown kind")),
+        }
+    }
+}
+
+/// An entry in a data file, which either sets the value of a key or deletes the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFileEntry {
+    /// The Unix timestamp in nanoseconds at which the entry was written.
+    pub tstamp: i64,
+    /// The key that is written.
+    pub key: Bytes,
+    /// The value of the key, or `None` if the key is deleted.
+    pub value: Option<Bytes>,
+    /// The Unix timestamp in nanoseconds at which the key expires.
+    pub expiry: Option<i64>,
+}
+
+impl Encode for DataFileEntry {
+    fn encoded_len(&self) -> u64 {
+        let mut len = 8 + 1 + varint_len(self.key.len() as u64) + self.key.len();
+        if let Some(value) = &self.value {
+            len += varint_len(value.len() as u64) + value.len();
+        }
+        if self.expiry.is_some() {
+            len += 8;
+        }
+        (len + 4) as u64
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        let mut header = Header::default();
+        header.put(&self.tstamp.to_le_bytes());
+        header.put(&[flags(self.value.is_some(), self.expiry.is_some())]);
+        header.put_varint(self.key.len() as u64);
+        if let Some(value) = &self.value {
+            header.put_varint(value.len() as u64);
+        }
+        if let Some(expiry) = self.expiry {
+            header.put(&expiry.to_le_bytes());
+        }
+        let value = self.value.as_deref().unwrap_or_default();
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(header.as_slice());
+        hasher.update(&self.key);
+        hasher.update(value);
+        let crc = hasher.finalize().to_le_bytes();
+        // The key and the value are written from their own buffers without being copied
+        let mut bufs = [
+            IoSlice::new(header.as_slice()),
+            IoSlice::new(&self.key),
+            IoSlice::new(value),
+            IoSlice::new(&crc),
+        ];
+        write_all_vectored(w, &mut bufs)?;
+        Ok(())
+    }
+}
+
+impl Decode for DataFileEntry {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let mut r = CrcReader::new(r);
+        let (tstamp, key_len, value_len, expiry) = read_data_header(&mut r)?;
+        let key = read_bytes(&mut r, key_len)?;
+        let value = value_len.map(|len| read_bytes(&mut r, len)).transpose()?;
+        if !r.check()? {
+            return Err(Error::corrupted("data file entry checksum mismatch"));
+        }
+        Ok(Self {
+            tstamp,
+            key,
+            value,
+            expiry,
+        })
+    }
+}
+
+/// The value of a data file entry, which is read without keeping the rest of the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFileValue(pub Option<Bytes>);
+
+impl Decode for DataFileValue {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let mut r = CrcReader::new(r);
+        let (_, key_len, value_len, _) = read_data_header(&mut r)?;
+        // Skip the key, which is only read for the checksum
+        let mut buf = [0u8; 256];
+        let mut remaining = key_len;
+        while remaining > 0 {
+            let n = remaining.min(buf.len() as u64) as usize;
+            r.read_exact(&mut buf[..n])?;
+            remaining -= n as u64;
+        }
+        let value = value_len.map(|len| read_bytes(&mut r, len)).transpose()?;
+        if !r.check()? {
+            return Err(Error::corrupted("data file entry checksum mismatch"));
+        }
+        Ok(Self(value))
+    }
+}
+
+/// Data file entries that are written together, so either all or none of them are read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFileBatch {
+    /// The Unix timestamp in nanoseconds at which the batch was written.
+    pub tstamp: i64,
+    /// The entries of the batch.
+    pub entries: Vec<DataFileEntry>,
+}
+
+impl DataFileBatch {
+    /// Return the number of bytes of the header that comes before the entries.
+    pub fn header_len(&self) -> u64 {
+        let len = self.entries_len();
+        (8 + 1 + varint_len(self.entries.len() as u64) + varint_len(len) + 4) as u64
+    }
+
+    fn entries_len(&self) -> u64 {
+        self.entries.iter().map(Encode::encoded_len).sum()
+    }
+}
+
+impl Encode for DataFileBatch {
+    fn encoded_len(&self) -> u64 {
+        self.header_len() + self.entries_len()
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        // The entries are encoded up front, since the header holds their checksum
+        let mut entries = Vec::with_capacity(self.entries_len() as usize);
+        for entry in &self.entries {
+            entry.write_to(&mut entries)?;
+        }
+        let mut header = Header::default();
+        header.put(&self.tstamp.to_le_bytes());
+        header.put(&[FLAG_BATCH]);
+        header.put_varint(self.entries.len() as u64);
+        header.put_varint(entries.len() as u64);
+        header.put(&crc32fast::hash(&entries).to_le_bytes());
+        let mut bufs = [IoSlice::new(header.as_slice()), IoSlice::new(&entries)];
+        write_all_vectored(w, &mut bufs)?;
+        Ok(())
+    }
+}
+
+/// A record that is read from a data file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataFileRecord {
+    /// An entry that was written on its own.
+    Entry(DataFileEntry),
+    /// A batch of entries that were written together.
+    Batch(DataFileBatch),
+}
+
+impl Decode for DataFileRecord {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let mut r = CrcReader::new(r);
+        let tstamp = read_i64(&mut r)?;
+        let flags = read_flags(&mut r, FLAG_VALUE | FLAG_EXPIRY | FLAG_BATCH)?;
+        if flags & FLAG_BATCH == 0 {
+            let (key_len, value_len, expiry) = read_data_lens(&mut r, flags)?;
+            let key = read_bytes(&mut r, key_len)?;
+            let value = value_len.map(|len| read_bytes(&mut r, len)).transpose()?;
+            // An entry that wasn't completely written is treated like the end of the file
+            if !r.check()? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ChecksumMismatch).into());
+            }
+            return Ok(Self::Entry(DataFileEntry {
+                tstamp,
+                key,
+                value,
+                expiry,
+            }));
+        }
+        if flags != FLAG_BATCH {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "entry has unknown flags").into(),
+            );
+        }
+        let r = r.inner;
+        let count = read_varint(r)?;
+        let len = read_varint(r)?;
+        let mut crc = [0u8; 4];
+        r.read_exact(&mut crc)?;
+        let buf = read_bytes(r, len)?;
+        // A batch whose entries weren't all written is treated like the end of the file
+        if crc32fast::hash(&buf) != u32::from_le_bytes(crc) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ChecksumMismatch).into());
+        }
+        let mut entries = Vec::with_capacity(count.min(MAX_PREALLOC_LEN) as usize);
+        let mut buf = buf.as_ref();
+        for _ in 0..count {
+            entries.push(
+                DataFileEntry::read_from(&mut buf)
+                    .map_err(|_| Error::corrupted("data file batch holds a malformed entry"))?,
+            );
+        }
+        if !buf.is_empty() {
+            return Err(Error::corrupted("data file batch has a wrong length"));
+        }
+        Ok(Self::Batch(DataFileBatch { tstamp, entries }))
+    }
+}
+
+/// An entry in a hint file, which points to the data file entry that holds the value of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintFileEntry {
+    /// The Unix timestamp in nanoseconds at which the data file entry was written.
+    pub tstamp: i64,
+    /// The size of the data file entry.
+    pub len: u64,
+    /// The position of the data file entry within its file.
+    pub pos: u64,
+    /// The key of the data file entry.
+    pub key: Bytes,
+    /// The Unix timestamp in nanoseconds at which the key expires.
+    pub expiry: Option<i64>,
+}
+
+impl Encode for HintFileEntry {
+    fn encoded_len(&self) -> u64 {
+        let mut len = 8 + 1 + varint_len(self.len) + varint_len(self.pos);
+        len += varint_len(self.key.len() as u64) + self.key.len();
+        if self.expiry.is_some() {
+            len += 8;
+        }
+        (len + 4) as u64
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        let mut header = Header::default();
+        header.put(&self.tstamp.to_le_bytes());
+        header.put(&[flags(false, self.expiry.is_some())]);
+        header.put_varint(self.len);
+        header.put_varint(self.pos);
+        header.put_varint(self.key.len() as u64);
+        if let Some(expiry) = self.expiry {
+            header.put(&expiry.to_le_bytes());
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(header.as_slice());
+        hasher.update(&self.key);
+        let crc = hasher.finalize().to_le_bytes();
+        let mut bufs = [
+            IoSlice::new(header.as_slice()),
+            IoSlice::new(&self.key),
+            IoSlice::new(&crc),
+        ];
+        write_all_vectored(w, &mut bufs)?;
+        Ok(())
+    }
+}
+
+/// The trailer that ends a hint file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintFileTrailer {
+    /// The number of entries in the hint file.
+    pub count: u64,
+}
+
+impl Encode for HintFileTrailer {
+    fn encoded_len(&self) -> u64 {
+        (8 + 1 + varint_len(self.count) + 4) as u64
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        let mut header = Header::default();
+        header.put(&0i64.to_le_bytes());
+        header.put(&[FLAG_TRAILER]);
+        header.put_varint(self.count);
+        let crc = crc32fast::hash(header.as_slice()).to_le_bytes();
+        header.put(&crc);
+        w.write_all(header.as_slice())?;
+        Ok(())
+    }
+}
+
+/// A record that is read from a hint file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintFileRecord {
+    /// An entry that points to a data file entry.
+    Entry(HintFileEntry),
+    /// The trailer that ends the file.
+    Trailer(HintFileTrailer),
+}
+
+impl Decode for HintFileRecord {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+        let mut r = CrcReader::new(r);
+        let tstamp = read_i64(&mut r)?;
+        let flags = read_flags(&mut r, FLAG_EXPIRY | FLAG_TRAILER)?;
+        let record = if flags & FLAG_TRAILER != 0 {
+            let count = read_varint(&mut r)?;
+            Self::Trailer(HintFileTrailer { count })
+        } else {
+            let len = read_varint(&mut r)?;
+            let pos = read_varint(&mut r)?;
+            let key_len = read_varint(&mut r)?;
+            let expiry = (flags & FLAG_EXPIRY != 0)
+                .then(|| read_i64(&mut r))
+                .transpose()?;
+            let key = read_bytes(&mut r, key_len)?;
+            Self::Entry(HintFileEntry {
+                tstamp,
+                len,
+                pos,
+                key,
+                expiry,
+            })
+        };
+        if !r.check()? {
+            return Err(Error::corrupted("hint file entry checksum mismatch"));
+        }
+        Ok(record)
+    }
+}
+
+/// The cause of the end of file error that a data file record whose checksum doesn't match is read
+/// as, which tells it apart from a record that is cut off by the end of the file.
+#[derive(Debug)]
+struct ChecksumMismatch;
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "record is incomplete")
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Return `true` if the error was returned for a data file record whose checksum doesn't match.
+pub(super) fn is_checksum_mismatch(e: &io::Error) -> bool {
+    e.get_ref()
+        .is_some_and(|inner| inner.is::<ChecksumMismatch>())
+}
+
+/// A reader that computes the checksum of the bytes that are read through it.
+struct CrcReader<'a, R> {
+    inner: &'a mut R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a, R: Read> CrcReader<'a, R> {
+    fn new(inner: &'a mut R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Read the checksum that follows the bytes that were read, and return `true` if it matches
+    /// them.
+    fn check(self) -> io::Result<bool> {
+        let mut buf = [0u8; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(self.hasher.finalize() == u32::from_le_bytes(buf))
+    }
+}
+
+impl<R: Read> Read for CrcReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// A buffer for the part of an entry that comes before its raw bytes.
+struct Header {
+    buf: [u8; MAX_HEADER_LEN],
+    len: usize,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            buf: [0; MAX_HEADER_LEN],
+            len: 0,
+        }
+    }
+}
+
+impl Header {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn put_varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.put(&[(v as u8) | 0x80]);
+            v >>= 7;
+        }
+        self.put(&[v as u8]);
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn flags(has_value: bool, has_expiry: bool) -> u8 {
+    let mut flags = 0;
+    if has_value {
+        flags |= FLAG_VALUE;
+    }
+    if has_expiry {
+        flags |= FLAG_EXPIRY;
+    }
+    flags
+}
+
+/// Read the part of a data file entry that comes before the key, returning the timestamp, the
+/// key's size, the value's size if there's a value, and the expiry.
+fn read_data_header<R: Read>(r: &mut R) -> Result<(i64, u64, Option<u64>, Option<i64>), Error> {
+    let tstamp = read_i64(r)?;
+    let flags = read_flags(r, FLAG_VALUE | FLAG_EXPIRY)?;
+    let (key_len, value_len, expiry) = read_data_lens(r, flags)?;
+    Ok((tstamp, key_len, value_len, expiry))
+}
+
+/// Read the part of a data file entry that comes after its flags and before the key, returning
+/// the key's size, the value's size if there's a value, and the expiry.
+fn read_data_lens<R: Read>(r: &mut R, flags: u8) -> io::Result<(u64, Option<u64>, Option<i64>)> {
+    let key_len = read_varint(r)?;
+    let value_len = (flags & FLAG_VALUE != 0)
+        .then(|| read_varint(r))
+        .transpose()?;
+    let expiry = (flags & FLAG_EXPIRY != 0)
+        .then(|| read_i64(r))
+        .transpose()?;
+    Ok((key_len, value_len, expiry))
+}
+
+fn read_flags<R: Read>(r: &mut R, allowed: u8) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    if buf[0] & !allowed != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "entry has unknown flags",
+        ));
+    }
+    Ok(buf[0])
+}
+
+fn read_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut v = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let mut buf = [0u8; 1];
+        r.read_exact(&mut buf)?;
+        v |= u64::from(buf[0] & 0x7f) << (7 * i);
+        if buf[0] & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "variable-length integer is too long",
+    ))
+}
+
+fn varint_len(v: u64) -> usize {
+    let bits = (u64::BITS - v.leading_zeros()).max(1) as usize;
+    bits.div_ceil(7)
+}
+
+/// Read exactly `len` bytes into a new buffer.
+fn read_bytes<R: Read>(r: &mut R, len: u64) -> io::Result<Bytes> {
+    // The size comes from the file, so we don't trust it for allocating large buffers up front
+    let mut buf = Vec::with_capacity(len.min(MAX_PREALLOC_LEN) as usize);
+    r.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf.into())
+}
+
+/// Write all the buffers, retrying on partial writes.
+fn write_all_vectored<W: Write>(w: &mut W, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, option, prelude::*};
+
+    use super::*;
+
+    #[test]
+    fn datafile_entry_layout() {
+        let entry = DataFileEntry {
+            tstamp: 1,
+            key: "k".into(),
+            value: Some("v".into()),
+            expiry: None,
+        };
+        let mut buf = Vec::new();
+        entry.write_to(&mut buf).unwrap();
+        let crc = crc32fast::hash(b"\x01\0\0\0\0\0\0\0\x01\x01\x01kv").to_le_bytes();
+        assert_eq!(b"\x01\0\0\0\0\0\0\0\x01\x01\x01kv", &buf[..13]);
+        assert_eq!(&crc, &buf[13..]);
+    }
+
+    #[test]
+    fn file_header_layout() {
+        let mut buf = Vec::new();
+        FileHeader::Data.write_to(&mut buf).unwrap();
+        assert_eq!(b"OPAL\x01\0D\0", buf.as_slice());
+        assert_eq!(FileHeader::LEN, buf.len() as u64);
+        assert_eq!(
+            FileHeader::Data,
+            FileHeader::read_from(&mut buf.as_slice()).unwrap()
+        );
+
+        // Files that were serialized with bincode start with the timestamp of their first entry
+        let mut bincode_file = Vec::new();
+        bincode::serialize_into(&mut bincode_file, &(1i64, "k", Some("v"))).unwrap();
+        assert!(matches!(
+            FileHeader::read_from(&mut bincode_file.as_slice()),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        buf[4] = 2;
+        assert!(matches!(
+            FileHeader::read_from(&mut buf.as_slice()),
+            Err(Error::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn datafile_batch_is_only_read_when_complete() {
+        let entry = |key: &'static str, value: Option<&'static str>| DataFileEntry {
+            tstamp: 1,
+            key: key.into(),
+            value: value.map(Bytes::from),
+            expiry: None,
+        };
+        let batch = DataFileBatch {
+            tstamp: 1,
+            entries: vec![entry("a", Some("1")), entry("b", None)],
+        };
+        let mut buf = Vec::new();
+        batch.write_to(&mut buf).unwrap();
+        assert_eq!(batch.encoded_len(), buf.len() as u64);
+        let header_len = batch.header_len() as usize;
+        let record = DataFileRecord::read_from(&mut buf.as_slice()).unwrap();
+        assert_eq!(DataFileRecord::Batch(batch), record);
+
+        // A batch that is cut off, or followed by the zeros of a preallocated file, is read like
+        // the end of the file
+        let torn = |res: Result<DataFileRecord, Error>| matches!(res, Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof);
+        for len in 0..buf.len() {
+            assert!(torn(DataFileRecord::read_from(&mut &buf[..len])));
+        }
+        for start in header_len..buf.len() {
+            let mut zeroed = buf.clone();
+            zeroed[start..].fill(0);
+            assert!(torn(DataFileRecord::read_from(&mut zeroed.as_slice())));
+        }
+    }
+
+    #[test]
+    fn datafile_entry_is_only_read_when_complete() {
+        let entry = DataFileEntry {
+            tstamp: 1,
+            key: "k".into(),
+            value: Some("value".into()),
+            expiry: None,
+        };
+        let mut buf = Vec::new();
+        entry.write_to(&mut buf).unwrap();
+        assert_eq!(
+            DataFileRecord::Entry(entry),
+            DataFileRecord::read_from(&mut buf.as_slice()).unwrap()
+        );
+
+        // An entry that is cut off, or the zeros of a preallocated file, are read like the end of
+        // the file
+        let torn = |res: Result<DataFileRecord, Error>| matches!(res, Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof);
+        for len in 0..buf.len() {
+            assert!(torn(DataFileRecord::read_from(&mut &buf[..len])));
+        }
+        assert!(torn(DataFileRecord::read_from(&mut [0; 32].as_slice())));
+    }
+
+    proptest! {
+        #[test]
+        fn datafile_entry_roundtrip(
+            tstamp in any::<i64>(),
+            key in vec(any::<u8>(), 0..256),
+            value in option::of(vec(any::<u8>(), 0..256)),
+            expiry in option::of(any::<i64>()),
+        ) {
+            let entry = DataFileEntry {
+                tstamp,
+                key: key.into(),
+                value: value.map(Bytes::from),
+                expiry,
+            };
+            let mut buf = Vec::new();
+            entry.write_to(&mut buf).unwrap();
+            prop_assert_eq!(entry.encoded_len(), buf.len() as u64);
+            let value = DataFileValue::read_from(&mut buf.as_slice()).unwrap();
+            prop_assert_eq!(&entry.value, &value.0);
+            prop_assert_eq!(entry, DataFileEntry::read_from(&mut buf.as_slice()).unwrap());
+        }
+
+        #[test]
+        fn datafile_entry_corruption_detected(
+            key in vec(any::<u8>(), 0..128),
+            value in vec(any::<u8>(), 1..128),
+            flipped in any::<prop::sample::Index>(),
+        ) {
+            let entry = DataFileEntry {
+                tstamp: 1,
+                key: key.into(),
+                value: Some(value.into()),
+                expiry: None,
+            };
+            let mut buf = Vec::new();
+            entry.write_to(&mut buf).unwrap();
+            // Flip a bit in the value or the checksum, which keeps the entry's structure intact
+            let start = buf.len() - 4 - entry.value.as_ref().unwrap().len();
+            let i = start + flipped.index(buf.len() - start);
+            buf[i] ^= 1;
+            let res = DataFileEntry::read_from(&mut buf.as_slice());
+            prop_assert!(matches!(res, Err(Error::Corruption { .. })), "{:?}", res);
+            let res = DataFileValue::read_from(&mut buf.as_slice());
+            prop_assert!(matches!(res, Err(Error::Corruption { .. })), "{:?}", res);
+        }
+
+        #[test]
+        fn hintfile_entry_roundtrip(
+            tstamp in any::<i64>(),
+            len in any::<u64>(),
+            pos in any::<u64>(),
+            key in vec(any::<u8>(), 0..256),
+            expiry in option::of(any::<i64>()),
+        ) {
+            let entry = HintFileEntry {
+                tstamp,
+                len,
+                pos,
+                key: key.into(),
+                expiry,
+            };
+            let mut buf = Vec::new();
+            entry.write_to(&mut buf).unwrap();
+            prop_assert_eq!(entry.encoded_len(), buf.len() as u64);
+            let trailer = HintFileTrailer { count: 1 };
+            trailer.write_to(&mut buf).unwrap();
+
+            let mut r = buf.as_slice();
+            prop_assert_eq!(HintFileRecord::Entry(entry), HintFileRecord::read_from(&mut r).unwrap());
+            prop_assert_eq!(HintFileRecord::Trailer(trailer), HintFileRecord::read_from(&mut r).unwrap());
+            prop_assert!(r.is_empty());
+        }
+
+        #[test]
+        fn hintfile_entry_corruption_detected(
+            key in vec(any::<u8>(), 1..128),
+            flipped in any::<prop::sample::Index>(),
+        ) {
+            let entry = HintFileEntry {
+                tstamp: 1,
+                len: 2,
+                pos: 3,
+                key: key.into(),
+                expiry: None,
+            };
+            let mut buf = Vec::new();
+            entry.write_to(&mut buf).unwrap();
+            // Flip a bit in the key or the checksum, which keeps the entry's structure intact
+            let i = 12 + flipped.index(buf.len() - 12);
+            buf[i] ^= 1;
+            let res = HintFileRecord::read_from(&mut buf.as_slice());
+            prop_assert!(matches!(res, Err(Error::Corruption { .. })), "{:?}", res);
+        }
+    }
+}
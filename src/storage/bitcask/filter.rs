@@ -0,0 +1,89 @@
+use std::{fmt, sync::Arc, time::SystemTime};
+
+use bytes::Bytes;
+
+/// What a merge does with an entry, as decided by a [`CompactionFilter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Copy the entry as it is.
+    Keep,
+    /// Drop the entry and delete its key.
+    Remove,
+    /// Copy the entry with the given value instead of its own.
+    Change(Bytes),
+}
+
+/// A hook that is called for every live entry that a merge copies, so data can be dropped or
+/// transformed as part of the merges instead of with a separate pass over all keys. Merges only
+/// visit the files that they merge, so an entry is seen again whenever its file is merged.
+///
+/// The filter is called while the writer is held, so it should be quick. Removed keys are
+/// deleted like any other key. Changed values update the secondary indexes, but change
+/// subscribers aren't told about them.
+pub trait CompactionFilter: Send + Sync {
+    /// Decide what happens to the value of a key that was written at `tstamp`.
+    fn keep(&self, key: &[u8], value: &[u8], tstamp: SystemTime) -> Decision;
+}
+
+impl<F> CompactionFilter for F
+where
+    F: Fn(&[u8], &[u8], SystemTime) -> Decision + Send + Sync,
+{
+    fn keep(&self, key: &[u8], value: &[u8], tstamp: SystemTime) -> Decision {
+        self(key, value, tstamp)
+    }
+}
+
+/// A transformation of the values that merges apply to every live entry that they copy, one entry
+/// at a time, such as recompressing the values with another codec, encrypting them with a rotated
+/// key, or upgrading their schema. This migrates the values as the files are merged, without a
+/// separate pass that rewrites all keys.
+///
+/// The values in files that haven't been merged yet keep their old encoding, so the readers of
+/// the values must handle both encodings until the migration is done. The transformation runs
+/// after the [`CompactionFilter`], on the value that the filter kept or changed.
+pub trait ValueTransform: Send + Sync {
+    /// Return the new encoding of a value, or `None` to copy the value as it is, e.g. when it's
+    /// already in the new encoding. An error stops the merge, which keeps the merge files that it
+    /// completed.
+    fn transform(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Bytes>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl<F> ValueTransform for F
+where
+    F: Fn(&[u8], &[u8]) -> Result<Option<Bytes>, Box<dyn std::error::Error + Send + Sync>>
+        + Send
+        + Sync,
+{
+    fn transform(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Bytes>, Box<dyn std::error::Error + Send + Sync>> {
+        self(key, value)
+    }
+}
+
+/// A compaction filter that is shared by the copies of a configuration.
+#[derive(Clone)]
+pub(super) struct SharedFilter(pub(super) Arc<dyn CompactionFilter>);
+
+impl fmt::Debug for SharedFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompactionFilter").finish_non_exhaustive()
+    }
+}
+
+/// A value transformation that is shared by the copies of a configuration.
+#[derive(Clone)]
+pub(super) struct SharedTransform(pub(super) Arc<dyn ValueTransform>);
+
+impl fmt::Debug for SharedTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueTransform").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+/// The most accessed keys, approximated with the space-saving algorithm. A fixed number of
+/// counters is kept, and a key that isn't counted takes over the counter with the lowest count,
+/// inheriting that count. Every key accessed more often than `1 / capacity` of all the accesses
+/// is guaranteed to be counted, and the counts are never lower than the actual ones.
+#[derive(Debug)]
+pub(super) struct HotKeys {
+    capacity: usize,
+    counters: Mutex<HashMap<Bytes, u64>>,
+}
+
+impl HotKeys {
+    /// Create a tracker with the given number of counters.
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: Mutex::new(HashMap::with_capacity(capacity)),
+        }
+    }
+
+    /// Record an access to the key, which counts as `weight` accesses, so callers that only
+    /// record a sample of the accesses keep the counts unbiased.
+    pub(super) fn record(&self, key: &[u8], weight: u32) {
+        let weight = u64::from(weight);
+        let mut counters = self.counters.lock();
+        if let Some(count) = counters.get_mut(key) {
+            *count = count.saturating_add(weight);
+            return;
+        }
+        if counters.len() < self.capacity {
+            counters.insert(Bytes::copy_from_slice(key), weight);
+            return;
+        }
+        // Finding the lowest count goes through all counters, which is cheap since the counters
+        // are few, and keys that are already counted are far more common on skewed workloads
+        let Some((coldest, count)) = counters
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(k, count)| (k.clone(), *count))
+        else {
+            return;
+        };
+        counters.remove(&coldest);
+        counters.insert(Bytes::copy_from_slice(key), count.saturating_add(weight));
+    }
+
+    /// Return up to `n` of the most accessed keys with their estimated number of accesses, in
+    /// descending order of the estimates.
+    pub(super) fn top(&self, n: usize) -> Vec<(Bytes, u64)> {
+        let mut keys: Vec<_> = self
+            .counters
+            .lock()
+            .iter()
+            .map(|(k, count)| (k.clone(), *count))
+            .collect();
+        keys.sort_unstable_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+        keys.truncate(n);
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hot_keys_are_counted_on_skewed_accesses() {
+        let hot_keys = HotKeys::new(4);
+        for i in 0..1000u32 {
+            // One key out of every two accesses is the same, the others are all different
+            let key = if i % 2 == 0 {
+                Bytes::from("hot")
+            } else {
+                Bytes::from(format!("cold{i}"))
+            };
+            hot_keys.record(&key, 1);
+        }
+        hot_keys.record(b"hot", 10);
+
+        let top = hot_keys.top(2);
+        assert_eq!(2, top.len());
+        assert_eq!(Bytes::from("hot"), top[0].0);
+        assert!(top[0].1 >= 510);
+        assert!(top[0].1 > top[1].1);
+        assert_eq!(4, hot_keys.top(10).len());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+/// A function that extracts the indexed field from a value. Returning `None` excludes the value
+/// from the index.
+pub type Extractor = Arc<dyn Fn(&[u8]) -> Option<Bytes> + Send + Sync>;
+
+/// The definition of a secondary index which is given to the storage through its configuration.
+#[derive(Clone)]
+pub struct IndexDefinition {
+    name: String,
+    extractor: Extractor,
+}
+
+impl IndexDefinition {
+    /// Create an index with the given name that uses `extractor` to get the indexed field.
+    pub fn new<S, F>(name: S, extractor: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&[u8]) -> Option<Bytes> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            extractor: Arc::new(extractor),
+        }
+    }
+
+    /// Create an index with the given name over a field of values that are JSON objects. The
+    /// field is located by a dot-separated `path` (e.g. `user.email`). String fields are indexed
+    /// by their content, other fields are indexed by their JSON representation.
+    pub fn json_field<S>(name: S, path: &str) -> Self
+    where
+        S: Into<String>,
+    {
+        let path: Vec<String> = path.split('.').map(String::from).collect();
+        Self::new(name, move |value| {
+            let json: serde_json::Value = serde_json::from_slice(value).ok()?;
+            let field = path.iter().try_fold(&json, |v, segment| v.get(segment))?;
+            match field {
+                serde_json::Value::String(s) => Some(Bytes::from(s.clone())),
+                serde_json::Value::Null => None,
+                v => Some(Bytes::from(v.to_string())),
+            }
+        })
+    }
+
+    /// Get the name of the index.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for IndexDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexDefinition")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The set of secondary indexes that is maintained alongside the KeyDir.
+#[derive(Debug, Default)]
+pub(super) struct SecondaryIndexes(Vec<(IndexDefinition, RwLock<Index>)>);
+
+/// The entries of a secondary index.
+#[derive(Debug, Default)]
+struct Index {
+    /// Mapping from an indexed field to the set of keys whose values contain the field.
+    inverted: HashMap<Bytes, BTreeSet<Bytes>>,
+    /// Mapping from a key to the field that was extracted from its value.
+    forward: HashMap<Bytes, Bytes>,
+}
+
+impl Index {
+    fn insert(&mut self, key: &Bytes, field: Option<Bytes>) {
+        self.remove(key);
+        if let Some(field) = field {
+            self.inverted
+                .entry(field.clone())
+                .or_default()
+                .insert(key.clone());
+            self.forward.insert(key.clone(), field);
+        }
+    }
+
+    fn remove(&mut self, key: &Bytes) {
+        if let Some(field) = self.forward.remove(key) {
+            if let Some(keys) = self.inverted.get_mut(&field) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.inverted.remove(&field);
+                }
+            }
+        }
+    }
+}
+
+impl SecondaryIndexes {
+    /// Create empty indexes from the given definitions.
+    pub(super) fn new(definitions: &[IndexDefinition]) -> Self {
+        Self(
+            definitions
+                .iter()
+                .map(|d| (d.clone(), RwLock::default()))
+                .collect(),
+        )
+    }
+
+    /// Return `true` if there's no index.
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Update all indexes with the new value of the key.
+    pub(super) fn insert(&self, key: &Bytes, value: &[u8]) {
+        for (definition, index) in &self.0 {
+            let field = (definition.extractor)(value);
+            index.write().insert(key, field);
+        }
+    }
+
+    /// Remove the key from all indexes.
+    pub(super) fn remove(&self, key: &Bytes) {
+        for (_, index) in &self.0 {
+            index.write().remove(key);
+        }
+    }
+
+    /// Return the keys whose values have the given field in the index with the given name. Returns
+    /// `None` if the index does not exist.
+    pub(super) fn lookup(&self, name: &str, field: &[u8]) -> Option<Vec<Bytes>> {
+        let (_, index) = self.0.iter().find(|(d, _)| d.name == name)?;
+        let keys = index
+            .read()
+            .inverted
+            .get(field)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default();
+        Some(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_tracks_changes_to_the_indexed_field() {
+        let indexes = SecondaryIndexes::new(&[IndexDefinition::json_field("email", "user.email")]);
+        let key = Bytes::from("key");
+        indexes.insert(&key, br#"{"user":{"email":"a@example.com"}}"#);
+        assert_eq!(
+            Some(vec![key.clone()]),
+            indexes.lookup("email", b"a@example.com")
+        );
+
+        indexes.insert(&key, br#"{"user":{"email":"b@example.com"}}"#);
+        assert_eq!(Some(vec![]), indexes.lookup("email", b"a@example.com"));
+        assert_eq!(
+            Some(vec![key.clone()]),
+            indexes.lookup("email", b"b@example.com")
+        );
+
+        indexes.remove(&key);
+        assert_eq!(Some(vec![]), indexes.lookup("email", b"b@example.com"));
+        assert_eq!(None, indexes.lookup("name", b"b@example.com"));
+    }
+
+    #[test]
+    fn index_skips_values_without_the_field() {
+        let indexes = SecondaryIndexes::new(&[IndexDefinition::json_field("email", "email")]);
+        indexes.insert(&Bytes::from("key1"), b"not json");
+        indexes.insert(&Bytes::from("key2"), br#"{"name":"a"}"#);
+        indexes.insert(&Bytes::from("key3"), br#"{"email":"a"}"#);
+        assert_eq!(
+            Some(vec![Bytes::from("key3")]),
+            indexes.lookup("email", b"a")
+        );
+    }
+}
@@ -0,0 +1,504 @@
+//! The KeyDir, the in-memory index that maps every key to the position of its value on disk.
+//!
+//! The index is used through the [`KeyDir`] trait, and its implementation is chosen at compile
+//! time by cargo features: an ordered skip list by default, a sharded hash map with
+//! `keydir-dashmap`, and an index that spills to disk with `keydir-spill`. The trait is internal
+//! to the storage, so embedders pick one of these implementations but can't supply their own.
+
+#[cfg(feature = "keydir-spill")]
+mod spill;
+
+use std::{fmt::Debug, num::NonZeroI64, ops::Bound};
+
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "keydir-dashmap")]
+use super::config::KeyDirHasher;
+use super::{Config, Error};
+
+/// The in-memory KeyDir implementation. By default, this is a lock-free skip list which keeps
+/// the keys in lexicographic order.
+#[cfg(not(feature = "keydir-dashmap"))]
+type MemoryKeyDir = SkipMapKeyDir;
+
+/// The in-memory KeyDir implementation. The `keydir-dashmap` feature selects a sharded hash map
+/// which trades key ordering for cheaper point lookups.
+#[cfg(feature = "keydir-dashmap")]
+type MemoryKeyDir = DashMapKeyDir;
+
+/// The KeyDir implementation that is used by the storage. The other implementations are selected
+/// at compile time through cargo features.
+#[cfg(not(feature = "keydir-spill"))]
+pub(super) type DefaultKeyDir = MemoryKeyDir;
+
+/// The KeyDir implementation that is used by the storage. The `keydir-spill` feature keeps the
+/// in-memory KeyDir within a memory budget by spilling the least recently written entries to an
+/// on-disk index.
+#[cfg(feature = "keydir-spill")]
+pub(super) type DefaultKeyDir = spill::SpillKeyDir<MemoryKeyDir>;
+
+#[cfg(feature = "keydir-spill")]
+pub(super) use spill::remove_spill_files;
+
+/// The interface for an in-memory index that maps keys to the positions of their values on disk.
+///
+/// Implementations must be safe to read concurrently while a single writer is making changes to
+/// the index. Because there's only ever one writer, implementations don't have to make a lookup
+/// and an insertion atomic with respect to each other.
+pub(super) trait KeyDir: Default + Debug + Send + Sync + 'static {
+    /// Whether the implementation can iterate keys in lexicographic order without sorting.
+    const ORDERED: bool;
+
+    /// Create an empty index for the storage with the given configuration.
+    fn open(_conf: &Config) -> Result<Self, Error> {
+        Ok(Self::default())
+    }
+
+    /// Return a copy of the entry for the given key, if there's any.
+    fn get(&self, key: &[u8]) -> Option<KeyDirEntry>;
+
+    /// Set the entry for the given key and returns the previous entry if there's any.
+    fn insert(&self, key: Bytes, entry: KeyDirEntry) -> Option<KeyDirEntry>;
+
+    /// Remove the entry for the given key and returns it if there's any.
+    fn remove(&self, key: &[u8]) -> Option<KeyDirEntry>;
+
+    /// Return an iterator over copies of all keys and their entries. The order in which keys are
+    /// visited is defined by the implementation.
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_>;
+
+    /// Return an iterator over copies of the keys and their entries whose keys are within the
+    /// given range, in lexicographic order. Implementations that are not ordered have to collect
+    /// and sort the matching keys.
+    fn range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+    ) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_>;
+
+    /// Return copies of at most `n` keys and their entries, for choosing among a sample of the
+    /// keys without visiting all of them. Ordered implementations return the keys that come after
+    /// `cursor`, wrapping around to the first keys, so samples that each start after the last key
+    /// of the previous one go through all keys in turn. Unordered implementations can return keys
+    /// from anywhere in the index. Default to taking the keys from [`KeyDir::range`], which only
+    /// bounds the work for ordered implementations.
+    fn sample(&self, cursor: Option<&Bytes>, n: usize) -> Vec<(Bytes, KeyDirEntry)> {
+        let start = cursor.map_or(Bound::Unbounded, |cursor| Bound::Excluded(cursor.clone()));
+        let mut sample: Vec<_> = self.range(start, Bound::Unbounded).take(n).collect();
+        if let Some(cursor) = cursor.filter(|_| sample.len() < n) {
+            let end = Bound::Included(cursor.clone());
+            sample.extend(self.range(Bound::Unbounded, end).take(n - sample.len()));
+        }
+        sample
+    }
+
+    /// Return the counters of the index if it keeps part of its entries on disk.
+    fn stats(&self) -> Option<KeyDirStats> {
+        None
+    }
+}
+
+/// Counters of a KeyDir that keeps part of its entries on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyDirStats {
+    /// The number of lookups that found the key in memory.
+    pub memory_hits: u64,
+    /// The number of lookups that found the key on disk.
+    pub disk_hits: u64,
+    /// The number of lookups that didn't find the key.
+    pub misses: u64,
+    /// The estimated number of bytes taken by the entries in memory.
+    pub memory_bytes: u64,
+    /// The number of keys whose entries are on disk.
+    pub spilled_keys: u64,
+}
+
+impl KeyDirStats {
+    /// Return the fraction of the lookups that found the key in memory, out of the lookups that
+    /// found the key.
+    pub fn memory_hit_rate(&self) -> f64 {
+        let hits = self.memory_hits + self.disk_hits;
+        if hits == 0 {
+            return 1.0;
+        }
+        self.memory_hits as f64 / hits as f64
+    }
+}
+
+/// A structure for the keydir entry pointing the position of the entry on the data file.
+///
+/// The entry is packed into 32 bytes because there's one for every key. File IDs and the lengths
+/// of the data file entries are stored as `u32`, and the expiry uses the niche of `NonZeroI64`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(super) struct KeyDirEntry {
+    fileid: u32,
+    len: u32,
+    pos: u64,
+    tstamp: i64,
+    /// The Unix timestamp in nanoseconds at which the key expires. An expiry at zero is stored as
+    /// one nanosecond earlier, which is just as much in the past.
+    expiry: Option<NonZeroI64>,
+}
+
+impl KeyDirEntry {
+    /// The largest file ID that can be stored in an entry.
+    pub(super) const MAX_FILEID: u64 = u32::MAX as u64;
+
+    /// The largest data file entry length that can be stored in an entry.
+    #[cfg(test)]
+    pub(super) const MAX_LEN: u64 = u32::MAX as u64;
+
+    /// Create an entry for the data file entry at the given position. Returns
+    /// [`Error::LimitExceeded`] if the file ID can't be stored in an entry, or
+    /// [`Error::EntryTooLarge`] if the length can't.
+    pub(super) fn new(
+        fileid: u64,
+        len: u64,
+        pos: u64,
+        tstamp: i64,
+        expiry: Option<i64>,
+    ) -> Result<Self, Error> {
+        let fileid = u32::try_from(fileid).map_err(|_| Error::LimitExceeded("max file ID"))?;
+        let len = u32::try_from(len).map_err(|_| Error::EntryTooLarge {
+            len,
+            max: u64::from(u32::MAX),
+        })?;
+        Ok(Self {
+            fileid,
+            len,
+            pos,
+            tstamp,
+            expiry: expiry.and_then(|e| NonZeroI64::new(e).or(NonZeroI64::new(-1))),
+        })
+    }
+
+    /// Return the ID of the data file containing the entry.
+    pub(super) fn fileid(&self) -> u64 {
+        u64::from(self.fileid)
+    }
+
+    /// Return the length of the entry in the data file.
+    pub(super) fn len(&self) -> u64 {
+        u64::from(self.len)
+    }
+
+    /// Return the position of the entry in the data file.
+    pub(super) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    /// Return the Unix timestamp in nanoseconds at which the entry was written.
+    pub(super) fn tstamp(&self) -> i64 {
+        self.tstamp
+    }
+
+    /// Return the Unix timestamp in nanoseconds at which the key expires.
+    pub(super) fn expiry(&self) -> Option<i64> {
+        self.expiry.map(NonZeroI64::get)
+    }
+
+    /// Return `true` if the key has expired at the given Unix timestamp in nanoseconds.
+    pub(super) fn is_expired(&self, now: i64) -> bool {
+        self.expiry().is_some_and(|expiry| expiry <= now)
+    }
+}
+
+/// A KeyDir backed by a lock-free skip list that keeps keys in lexicographic order.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "keydir-dashmap", allow(dead_code))]
+pub(super) struct SkipMapKeyDir(SkipMap<Bytes, KeyDirEntry>);
+
+impl KeyDir for SkipMapKeyDir {
+    const ORDERED: bool = true;
+
+    fn get(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        self.0.get(key).map(|e| *e.value())
+    }
+
+    fn insert(&self, key: Bytes, entry: KeyDirEntry) -> Option<KeyDirEntry> {
+        let prev_entry = self.get(&key);
+        self.0.insert(key, entry);
+        prev_entry
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        self.0.remove(key).map(|e| *e.value())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        Box::new(self.0.iter().map(|e| (e.key().clone(), *e.value())))
+    }
+
+    fn range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+    ) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        Box::new(
+            self.0
+                .range((start, end))
+                .map(|e| (e.key().clone(), *e.value())),
+        )
+    }
+}
+
+/// A KeyDir backed by a sharded hash map. Keys are not kept in any particular order.
+#[cfg(feature = "keydir-dashmap")]
+#[derive(Debug, Default)]
+pub(super) struct DashMapKeyDir(dashmap::DashMap<Bytes, KeyDirEntry, KeyDirBuildHasher>);
+
+#[cfg(feature = "keydir-dashmap")]
+impl KeyDir for DashMapKeyDir {
+    const ORDERED: bool = false;
+
+    fn open(conf: &Config) -> Result<Self, Error> {
+        let hasher = KeyDirBuildHasher::new(conf.keydir_hasher);
+        let map = match conf.keydir_shards {
+            Some(shards) => {
+                // DashMap panics on shard counts that are not a power of two greater than 1
+                if shards.get() < 2 || !shards.is_power_of_two() {
+                    return Err(Error::InvalidConfig(
+                        "KeyDir shards must be a power of two greater than 1",
+                    ));
+                }
+                dashmap::DashMap::with_hasher_and_shard_amount(hasher, shards.get())
+            }
+            None => dashmap::DashMap::with_hasher(hasher),
+        };
+        Ok(Self(map))
+    }
+
+    fn get(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        self.0.get(key).map(|e| *e.value())
+    }
+
+    fn insert(&self, key: Bytes, entry: KeyDirEntry) -> Option<KeyDirEntry> {
+        self.0.insert(key, entry)
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        self.0.remove(key).map(|(_, e)| e)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        Box::new(self.0.iter().map(|e| (e.key().clone(), *e.value())))
+    }
+
+    fn range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+    ) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        use std::ops::RangeBounds;
+        let range = (start, end);
+        let mut entries: Vec<_> = self
+            .0
+            .iter()
+            .filter(|e| range.contains(e.key()))
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        entries.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        Box::new(entries.into_iter())
+    }
+
+    /// Take the keys from the start of a random shard, and from the shards after it if that's
+    /// not enough. Keys are placed within a shard by their hashes, so the sampled keys have
+    /// nothing to do with how the keys were used.
+    fn sample(&self, _cursor: Option<&Bytes>, n: usize) -> Vec<(Bytes, KeyDirEntry)> {
+        use rand::Rng;
+        let shards = self.0.shards();
+        let first = super::utils::with_rng(|rng| rng.gen_range(0..shards.len()));
+        let mut sample = Vec::with_capacity(n);
+        for shard in shards.iter().cycle().skip(first).take(shards.len()) {
+            if sample.len() == n {
+                break;
+            }
+            let shard = shard.read();
+            let entries = shard.iter().take(n - sample.len());
+            sample.extend(entries.map(|(key, entry)| (key.clone(), *entry.get())));
+        }
+        sample
+    }
+}
+
+/// Builds the hashers that are chosen through [`KeyDirHasher`]. The same hasher picks the shard
+/// of a key and its slot within the shard.
+#[cfg(feature = "keydir-dashmap")]
+#[derive(Debug, Clone)]
+pub(super) enum KeyDirBuildHasher {
+    Sip(std::collections::hash_map::RandomState),
+    Ahash(ahash::RandomState),
+}
+
+#[cfg(feature = "keydir-dashmap")]
+impl KeyDirBuildHasher {
+    fn new(hasher: KeyDirHasher) -> Self {
+        match hasher {
+            KeyDirHasher::Sip => Self::Sip(Default::default()),
+            KeyDirHasher::Ahash => Self::Ahash(Default::default()),
+        }
+    }
+}
+
+#[cfg(feature = "keydir-dashmap")]
+impl Default for KeyDirBuildHasher {
+    fn default() -> Self {
+        Self::new(KeyDirHasher::default())
+    }
+}
+
+#[cfg(feature = "keydir-dashmap")]
+impl std::hash::BuildHasher for KeyDirBuildHasher {
+    type Hasher = KeyDirHasherState;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            Self::Sip(s) => KeyDirHasherState::Sip(s.build_hasher()),
+            Self::Ahash(s) => KeyDirHasherState::Ahash(s.build_hasher()),
+        }
+    }
+}
+
+/// The state of a hasher built by [`KeyDirBuildHasher`].
+#[cfg(feature = "keydir-dashmap")]
+pub(super) enum KeyDirHasherState {
+    Sip(std::collections::hash_map::DefaultHasher),
+    Ahash(ahash::AHasher),
+}
+
+#[cfg(feature = "keydir-dashmap")]
+impl std::hash::Hasher for KeyDirHasherState {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sip(h) => h.write(bytes),
+            Self::Ahash(h) => h.write(bytes),
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        match self {
+            Self::Sip(h) => h.write_usize(i),
+            Self::Ahash(h) => h.write_usize(i),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            Self::Sip(h) => h.finish(),
+            Self::Ahash(h) => h.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(fileid: u64) -> KeyDirEntry {
+        KeyDirEntry::new(fileid, 0, 0, 0, None).unwrap()
+    }
+
+    #[test]
+    fn keydir_entry_is_packed() {
+        assert_eq!(32, std::mem::size_of::<KeyDirEntry>());
+    }
+
+    #[test]
+    fn keydir_entry_limits() {
+        let entry =
+            KeyDirEntry::new(KeyDirEntry::MAX_FILEID, KeyDirEntry::MAX_LEN, 0, 0, Some(0)).unwrap();
+        assert_eq!(KeyDirEntry::MAX_FILEID, entry.fileid());
+        assert_eq!(KeyDirEntry::MAX_LEN, entry.len());
+        assert!(entry.is_expired(0));
+        assert!(matches!(
+            KeyDirEntry::new(KeyDirEntry::MAX_FILEID + 1, 0, 0, 0, None),
+            Err(Error::LimitExceeded("max file ID"))
+        ));
+        assert!(matches!(
+            KeyDirEntry::new(0, KeyDirEntry::MAX_LEN + 1, 0, 0, None),
+            Err(Error::EntryTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn keydir_insert_returns_previous_entry() {
+        let keydir = DefaultKeyDir::default();
+        assert!(keydir.insert(Bytes::from("key"), entry(0)).is_none());
+        let prev = keydir.insert(Bytes::from("key"), entry(1)).unwrap();
+        assert_eq!(0, prev.fileid());
+        assert_eq!(1, keydir.get(b"key").unwrap().fileid());
+    }
+
+    #[test]
+    fn keydir_remove_returns_removed_entry() {
+        let keydir = DefaultKeyDir::default();
+        keydir.insert(Bytes::from("key"), entry(0));
+        assert_eq!(0, keydir.remove(b"key").unwrap().fileid());
+        assert!(keydir.remove(b"key").is_none());
+        assert!(keydir.get(b"key").is_none());
+    }
+
+    #[test]
+    fn keydir_range_returns_sorted_keys() {
+        let keydir = DefaultKeyDir::default();
+        for key in ["d", "a", "c", "e", "b"] {
+            keydir.insert(Bytes::from(key), entry(0));
+        }
+        let keys: Vec<_> = keydir
+            .range(
+                Bound::Included(Bytes::from("b")),
+                Bound::Excluded(Bytes::from("e")),
+            )
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(vec!["b", "c", "d"], keys);
+    }
+
+    #[test]
+    fn keydir_sample_is_bounded() {
+        let keydir = DefaultKeyDir::default();
+        for key in ["d", "a", "c", "e", "b"] {
+            keydir.insert(Bytes::from(key), entry(0));
+        }
+        assert_eq!(3, keydir.sample(None, 3).len());
+        let mut keys: Vec<_> = keydir
+            .sample(None, 10)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        keys.sort();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], keys);
+        if DefaultKeyDir::ORDERED {
+            // Samples start after the cursor and wrap around to the first keys
+            let keys: Vec<_> = keydir
+                .sample(Some(&Bytes::from("c")), 4)
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect();
+            assert_eq!(vec!["d", "e", "a", "b"], keys);
+        }
+    }
+
+    #[cfg(feature = "keydir-dashmap")]
+    #[test]
+    fn dashmap_keydir_uses_configured_shards_and_hasher() {
+        let mut conf = Config::default();
+        conf.keydir_shards(std::num::NonZeroUsize::new(8).unwrap())
+            .keydir_hasher(KeyDirHasher::Ahash);
+        let keydir = DashMapKeyDir::open(&conf).unwrap();
+        for i in 0..100u64 {
+            keydir.insert(Bytes::from(i.to_string()), entry(i));
+        }
+        for i in 0..100u64 {
+            assert_eq!(i, keydir.get(i.to_string().as_bytes()).unwrap().fileid());
+        }
+
+        conf.keydir_shards = std::num::NonZeroUsize::new(6);
+        assert!(matches!(
+            DashMapKeyDir::open(&conf),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,638 @@
+//! A two-level KeyDir that keeps the most recently written entries in memory and spills the
+//! others to an on-disk hash index once the in-memory entries exceed a memory budget.
+//!
+//! The on-disk index is an append-only file of records. Records whose keys have the same hash
+//! are chained together through the offset of the previous record, and only the offset of the
+//! newest record of each chain is kept in memory. A lookup walks a chain from its newest record,
+//! so the first record with a matching key is the current one. Removing a spilled key appends a
+//! tombstone, and the file is rewritten once most of its records are stale.
+//!
+//! Entries are never moved back to memory by a lookup because only the writer can change the
+//! KeyDir. A spilled entry returns to memory when its key is written again.
+//!
+//! The spill file is only a cache, since the KeyDir is rebuilt from the data files when the
+//! storage is opened, so it's removed when the KeyDir is dropped.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Read, Seek, SeekFrom, Write},
+    num::NonZeroI64,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use tracing::error;
+
+use super::{KeyDir, KeyDirEntry, KeyDirStats};
+use crate::storage::bitcask::{Config, Error};
+
+const SPILL_FILE_PREFIX: &str = "keydir.spill";
+
+/// An estimate of the memory taken by an in-memory entry in addition to its key.
+const ENTRY_OVERHEAD: u64 = 128;
+
+/// Spilling stops once the in-memory entries take at most this fraction of the budget, so
+/// entries are spilled in batches instead of one at a time.
+const SPILL_TARGET: f64 = 0.75;
+
+/// The spill file is only compacted once it's larger than this.
+const COMPACTION_MIN_SIZE: u64 = 1 << 20;
+
+/// The offset of the previous record of a chain that has no previous record.
+const NIL: u64 = u64::MAX;
+
+/// The size of a record without its key: the offset of the previous record, the key length, the
+/// flags, and the fields of the entry.
+const HEADER_SIZE: usize = 8 + 4 + 1 + 4 + 4 + 3 * 8;
+
+const FLAG_TOMBSTONE: u8 = 1;
+const FLAG_EXPIRY: u8 = 1 << 1;
+
+/// A KeyDir that spills the least recently written entries of the in-memory KeyDir `K` to disk
+/// when its entries take more memory than the configured budget. Without a budget, all entries
+/// are kept in memory.
+#[derive(Debug, Default)]
+pub(in crate::storage::bitcask) struct SpillKeyDir<K> {
+    memory: K,
+    disk: Option<SpillFile>,
+    budget: u64,
+    memory_bytes: AtomicU64,
+    memory_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K> SpillKeyDir<K>
+where
+    K: KeyDir,
+{
+    /// Move the least recently written entries to disk until the in-memory entries are back
+    /// under the target fraction of the budget.
+    fn spill_if_over_budget(&self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let mut memory_bytes = self.memory_bytes.load(Ordering::Relaxed);
+        if memory_bytes <= self.budget {
+            return;
+        }
+        let target = (self.budget as f64 * SPILL_TARGET) as u64;
+        let mut entries: Vec<_> = self.memory.iter().collect();
+        entries.sort_unstable_by_key(|(_, e)| e.tstamp);
+        let mut count = 0;
+        for (key, _) in &entries {
+            if memory_bytes <= target {
+                break;
+            }
+            memory_bytes = memory_bytes.saturating_sub(entry_size(key));
+            count += 1;
+        }
+        entries.truncate(count);
+
+        // Entries are only removed from memory after they can be found on disk, so concurrent
+        // lookups always find them in one of the two places.
+        if let Err(e) = disk.spill(&entries) {
+            error!(cause=?e, "failed to spill keydir entries");
+            return;
+        }
+        for (key, _) in &entries {
+            if self.memory.remove(key).is_some() {
+                self.memory_bytes
+                    .fetch_sub(entry_size(key), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<K> KeyDir for SpillKeyDir<K>
+where
+    K: KeyDir,
+{
+    // Spilled entries are kept in hash order
+    const ORDERED: bool = false;
+
+    fn open(conf: &Config) -> Result<Self, Error> {
+        let disk = match conf.keydir_memory_budget {
+            Some(_) => Some(SpillFile::create(&conf.path)?),
+            None => None,
+        };
+        Ok(Self {
+            memory: K::open(conf)?,
+            disk,
+            budget: conf.keydir_memory_budget.unwrap_or(u64::MAX),
+            memory_bytes: AtomicU64::new(0),
+            memory_hits: AtomicU64::new(0),
+            disk_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    fn get(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        if let Some(entry) = self.memory.get(key) {
+            self.memory_hits.fetch_add(1, Ordering::Relaxed);
+            return Some(entry);
+        }
+        let entry = self.disk.as_ref().and_then(|disk| log_error(disk.get(key)));
+        match entry {
+            Some(_) => self.disk_hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        entry
+    }
+
+    fn insert(&self, key: Bytes, entry: KeyDirEntry) -> Option<KeyDirEntry> {
+        let size = entry_size(&key);
+        if let Some(prev_entry) = self.memory.insert(key.clone(), entry) {
+            return Some(prev_entry);
+        }
+        self.memory_bytes.fetch_add(size, Ordering::Relaxed);
+        // The new entry is in memory before the spilled one is removed, so concurrent lookups
+        // never miss the key.
+        let prev_entry = self
+            .disk
+            .as_ref()
+            .and_then(|disk| log_error(disk.remove(&key)));
+        self.spill_if_over_budget();
+        prev_entry
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<KeyDirEntry> {
+        match self.memory.remove(key) {
+            Some(prev_entry) => {
+                self.memory_bytes
+                    .fetch_sub(entry_size(key), Ordering::Relaxed);
+                Some(prev_entry)
+            }
+            None => self
+                .disk
+                .as_ref()
+                .and_then(|disk| log_error(disk.remove(key))),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        match &self.disk {
+            Some(disk) => {
+                let spilled = log_error(disk.entries().map(Some)).unwrap_or_default();
+                Box::new(self.memory.iter().chain(spilled))
+            }
+            None => self.memory.iter(),
+        }
+    }
+
+    fn range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+    ) -> Box<dyn Iterator<Item = (Bytes, KeyDirEntry)> + '_> {
+        let Some(disk) = &self.disk else {
+            return self.memory.range(start, end);
+        };
+        let range = (start, end);
+        let mut entries: Vec<_> = self
+            .memory
+            .range(range.0.clone(), range.1.clone())
+            .collect();
+        let spilled = log_error(disk.entries().map(Some)).unwrap_or_default();
+        entries.extend(spilled.into_iter().filter(|(k, _)| range.contains(k)));
+        entries.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        Box::new(entries.into_iter())
+    }
+
+    fn sample(&self, cursor: Option<&Bytes>, n: usize) -> Vec<(Bytes, KeyDirEntry)> {
+        let mut sample = self.memory.sample(cursor, n);
+        // The spilled keys are only sampled when there are too few keys in memory, since finding
+        // them reads all of the spill file
+        if let Some(disk) = self.disk.as_ref().filter(|_| sample.len() < n) {
+            let spilled = log_error(disk.entries().map(Some)).unwrap_or_default();
+            sample.extend(spilled.into_iter().take(n - sample.len()));
+        }
+        sample
+    }
+
+    fn stats(&self) -> Option<KeyDirStats> {
+        self.disk.as_ref().map(|disk| KeyDirStats {
+            memory_hits: self.memory_hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            memory_bytes: self.memory_bytes.load(Ordering::Relaxed),
+            spilled_keys: disk.keys.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// An on-disk hash index of KeyDir entries.
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    /// The offsets of the newest records of the chains, keyed by the hash of their keys. Lookups
+    /// hold the read lock while reading the file so it can't be compacted under them.
+    chains: RwLock<HashMap<u64, u64>>,
+    log: Mutex<SpillLog>,
+    /// The number of keys whose entries are in the file.
+    keys: AtomicU64,
+}
+
+#[derive(Debug)]
+struct SpillLog {
+    file: fs::File,
+    /// The number of bytes that were written to the file.
+    len: u64,
+    /// The number of bytes taken by the records that are the current entries of their keys.
+    live_bytes: u64,
+}
+
+/// A record of the spill file. The entry is `None` for a tombstone.
+struct Record {
+    prev: u64,
+    key: Bytes,
+    entry: Option<KeyDirEntry>,
+}
+
+impl SpillFile {
+    fn create(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(format!(
+            "{SPILL_FILE_PREFIX}.{:016x}",
+            rand::random::<u64>()
+        ));
+        let file = open_file(&path)?;
+        Ok(Self {
+            path,
+            chains: RwLock::default(),
+            log: Mutex::new(SpillLog {
+                file,
+                len: 0,
+                live_bytes: 0,
+            }),
+            keys: AtomicU64::new(0),
+        })
+    }
+
+    fn get(&self, key: &[u8]) -> io::Result<Option<KeyDirEntry>> {
+        let chains = self.chains.read();
+        let Some(&head) = chains.get(&hash(key)) else {
+            return Ok(None);
+        };
+        let mut log = self.log.lock();
+        find(&mut log.file, head, key)
+    }
+
+    /// Append the entries of keys that are not in the file.
+    fn spill(&self, entries: &[(Bytes, KeyDirEntry)]) -> io::Result<()> {
+        let mut heads = HashMap::new();
+        let mut buf = Vec::new();
+        let chains = self.chains.read();
+        let mut log = self.log.lock();
+        for (key, entry) in entries {
+            let hash = hash(key);
+            let prev = heads
+                .get(&hash)
+                .or_else(|| chains.get(&hash))
+                .copied()
+                .unwrap_or(NIL);
+            heads.insert(hash, log.len + buf.len() as u64);
+            encode_record(&mut buf, prev, key, Some(entry));
+        }
+        drop(chains);
+        append(&mut log, &buf)?;
+        log.live_bytes += buf.len() as u64;
+        drop(log);
+
+        self.chains.write().extend(heads);
+        self.keys.fetch_add(entries.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Append a tombstone for the key and return its entry, if the key is in the file.
+    fn remove(&self, key: &[u8]) -> io::Result<Option<KeyDirEntry>> {
+        let hash = hash(key);
+        let Some(head) = self.chains.read().get(&hash).copied() else {
+            return Ok(None);
+        };
+        let mut log = self.log.lock();
+        let Some(entry) = find(&mut log.file, head, key)? else {
+            return Ok(None);
+        };
+        let offset = log.len;
+        let mut buf = Vec::new();
+        encode_record(&mut buf, head, key, None);
+        append(&mut log, &buf)?;
+        log.live_bytes -= (HEADER_SIZE + key.len()) as u64;
+        drop(log);
+
+        self.chains.write().insert(hash, offset);
+        self.keys.fetch_sub(1, Ordering::Relaxed);
+        self.compact_if_stale();
+        Ok(Some(entry))
+    }
+
+    /// Return copies of all keys in the file and their entries.
+    fn entries(&self) -> io::Result<Vec<(Bytes, KeyDirEntry)>> {
+        let chains = self.chains.read();
+        let mut log = self.log.lock();
+        live_entries(&chains, &mut log.file)
+    }
+
+    /// Rewrite the file with only the current entries if most of its records are stale.
+    fn compact_if_stale(&self) {
+        {
+            let log = self.log.lock();
+            if log.len < COMPACTION_MIN_SIZE || log.live_bytes * 2 >= log.len {
+                return;
+            }
+        }
+        if let Err(e) = self.compact() {
+            error!(cause=?e, "failed to compact the spilled keydir");
+        }
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let mut chains = self.chains.write();
+        let mut log = self.log.lock();
+        let entries = live_entries(&chains, &mut log.file)?;
+
+        let mut heads = HashMap::new();
+        let mut buf = Vec::new();
+        for (key, entry) in &entries {
+            let hash = hash(key);
+            let prev = heads.get(&hash).copied().unwrap_or(NIL);
+            heads.insert(hash, buf.len() as u64);
+            encode_record(&mut buf, prev, key, Some(entry));
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".compact");
+        let mut file = open_file(Path::new(&tmp))?;
+        file.write_all(&buf)?;
+
+        // The old file is closed before it's replaced
+        log.file = file;
+        fs::rename(&tmp, &self.path)?;
+        log.len = buf.len() as u64;
+        log.live_bytes = log.len;
+        *chains = heads;
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!(cause=?e, path=?self.path, "failed to remove the spilled keydir");
+        }
+    }
+}
+
+/// Remove the spill files that were left in the given directory by a previous process.
+pub(in crate::storage::bitcask) fn remove_spill_files<P>(dir: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_spill_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SPILL_FILE_PREFIX));
+        if is_spill_file && path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn open_file(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+fn append(log: &mut SpillLog, buf: &[u8]) -> io::Result<()> {
+    // A failed write leaves `len` unchanged, so the partial record is overwritten by the next one
+    log.file.seek(SeekFrom::Start(log.len))?;
+    log.file.write_all(buf)?;
+    log.len += buf.len() as u64;
+    Ok(())
+}
+
+/// Walk the chain starting at the given offset and return the current entry of the key.
+fn find(file: &mut fs::File, mut offset: u64, key: &[u8]) -> io::Result<Option<KeyDirEntry>> {
+    while offset != NIL {
+        let record = read_record(file, offset)?;
+        if record.key == key {
+            return Ok(record.entry);
+        }
+        offset = record.prev;
+    }
+    Ok(None)
+}
+
+fn live_entries(
+    chains: &HashMap<u64, u64>,
+    file: &mut fs::File,
+) -> io::Result<Vec<(Bytes, KeyDirEntry)>> {
+    let mut entries = Vec::new();
+    for &head in chains.values() {
+        // Only the newest record of each key in the chain is current
+        let mut seen = HashSet::new();
+        let mut offset = head;
+        while offset != NIL {
+            let record = read_record(file, offset)?;
+            offset = record.prev;
+            if !seen.insert(record.key.clone()) {
+                continue;
+            }
+            if let Some(entry) = record.entry {
+                entries.push((record.key, entry));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn encode_record(buf: &mut Vec<u8>, prev: u64, key: &[u8], entry: Option<&KeyDirEntry>) {
+    let mut flags = 0;
+    match entry {
+        Some(entry) if entry.expiry.is_some() => flags |= FLAG_EXPIRY,
+        Some(_) => {}
+        None => flags |= FLAG_TOMBSTONE,
+    }
+    let entry = entry.copied().unwrap_or(KeyDirEntry {
+        fileid: 0,
+        len: 0,
+        pos: 0,
+        tstamp: 0,
+        expiry: None,
+    });
+    buf.extend_from_slice(&prev.to_le_bytes());
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.push(flags);
+    buf.extend_from_slice(&entry.fileid.to_le_bytes());
+    buf.extend_from_slice(&entry.len.to_le_bytes());
+    buf.extend_from_slice(&entry.pos.to_le_bytes());
+    buf.extend_from_slice(&entry.tstamp.to_le_bytes());
+    buf.extend_from_slice(&entry.expiry().unwrap_or_default().to_le_bytes());
+    buf.extend_from_slice(key);
+}
+
+fn read_record(file: &mut fs::File, offset: u64) -> io::Result<Record> {
+    let mut header = [0u8; HEADER_SIZE];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    let u32_at = |pos: usize| u32::from_le_bytes(header[pos..pos + 4].try_into().unwrap());
+    let u64_at = |pos: usize| u64::from_le_bytes(header[pos..pos + 8].try_into().unwrap());
+
+    let prev = u64_at(0);
+    let key_len = u32_at(8);
+    let flags = header[12];
+    let mut key = vec![0u8; key_len as usize];
+    file.read_exact(&mut key)?;
+
+    let entry = (flags & FLAG_TOMBSTONE == 0).then(|| KeyDirEntry {
+        fileid: u32_at(13),
+        len: u32_at(17),
+        pos: u64_at(21),
+        tstamp: u64_at(29) as i64,
+        expiry: (flags & FLAG_EXPIRY != 0)
+            .then(|| NonZeroI64::new(u64_at(37) as i64))
+            .flatten(),
+    });
+    Ok(Record {
+        prev,
+        key: Bytes::from(key),
+        entry,
+    })
+}
+
+fn hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn entry_size(key: &[u8]) -> u64 {
+    key.len() as u64 + ENTRY_OVERHEAD
+}
+
+fn log_error<T>(result: io::Result<Option<T>>) -> Option<T> {
+    result.unwrap_or_else(|e| {
+        error!(cause=?e, "failed to access the spilled keydir");
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::keydir::SkipMapKeyDir;
+
+    fn entry(tstamp: i64) -> KeyDirEntry {
+        KeyDirEntry::new(0, 0, 0, tstamp, Some(tstamp + 1)).unwrap()
+    }
+
+    fn open_keydir(dir: &Path, budget: u64) -> SpillKeyDir<SkipMapKeyDir> {
+        let mut conf = Config::default();
+        conf.path(dir).keydir_memory_budget(budget);
+        SpillKeyDir::open(&conf).unwrap()
+    }
+
+    #[test]
+    fn spill_keydir_spills_oldest_entries_over_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let keydir = open_keydir(dir.path(), 10 * (ENTRY_OVERHEAD + 4));
+        for i in 0..100 {
+            keydir.insert(Bytes::from(format!("{i:04}")), entry(i));
+        }
+
+        let stats = keydir.stats().unwrap();
+        assert!(stats.memory_bytes <= 10 * (ENTRY_OVERHEAD + 4));
+        assert_eq!(
+            100,
+            stats.spilled_keys + keydir.memory.iter().count() as u64
+        );
+        // The oldest entries are on disk
+        assert!(keydir.memory.get(b"0000").is_none());
+        assert!(keydir.memory.get(b"0099").is_some());
+
+        for i in 0..100 {
+            let e = keydir.get(format!("{i:04}").as_bytes()).unwrap();
+            assert_eq!(i, e.tstamp());
+            assert_eq!(Some(i + 1), e.expiry());
+        }
+        assert!(keydir.get(b"missing").is_none());
+        let stats = keydir.stats().unwrap();
+        assert_eq!(100, stats.memory_hits + stats.disk_hits);
+        assert_eq!(1, stats.misses);
+        assert!(stats.memory_hit_rate() < 0.5);
+    }
+
+    #[test]
+    fn spill_keydir_updates_and_removes_spilled_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let keydir = open_keydir(dir.path(), 10 * (ENTRY_OVERHEAD + 4));
+        for i in 0..100 {
+            keydir.insert(Bytes::from(format!("{i:04}")), entry(i));
+        }
+
+        let prev = keydir.insert(Bytes::from("0000"), entry(100)).unwrap();
+        assert_eq!(0, prev.tstamp());
+        assert_eq!(100, keydir.get(b"0000").unwrap().tstamp());
+        assert_eq!(1, keydir.remove(b"0001").unwrap().tstamp());
+        assert!(keydir.get(b"0001").is_none());
+        assert!(keydir.remove(b"0001").is_none());
+
+        let mut keys: Vec<_> = keydir.iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(99, keys.len());
+        let range: Vec<_> = keydir
+            .range(
+                Bound::Included(Bytes::from("0000")),
+                Bound::Excluded(Bytes::from("0003")),
+            )
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(vec!["0000", "0002"], range);
+    }
+
+    #[test]
+    fn spill_file_compaction_keeps_current_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = SpillFile::create(dir.path()).unwrap();
+        let entries: Vec<_> = (0..10)
+            .map(|i| (Bytes::from(format!("{i}")), entry(i)))
+            .collect();
+        file.spill(&entries).unwrap();
+        for (key, _) in &entries[..5] {
+            file.remove(key).unwrap();
+        }
+
+        file.compact().unwrap();
+        assert_eq!(5, file.entries().unwrap().len());
+        assert!(file.get(b"0").unwrap().is_none());
+        assert_eq!(9, file.get(b"9").unwrap().unwrap().tstamp());
+        assert_eq!(
+            5 * (HEADER_SIZE as u64 + 1),
+            fs::metadata(&file.path).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn spill_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = SpillFile::create(dir.path()).unwrap();
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+
+        fs::write(dir.path().join(format!("{SPILL_FILE_PREFIX}.stale")), b"").unwrap();
+        remove_spill_files(dir.path()).unwrap();
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
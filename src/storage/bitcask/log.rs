@@ -1,17 +1,28 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs,
-    io::{self, Write},
+    io::{self, Read, Write},
     num::NonZeroUsize,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
 };
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 use lru::LruCache;
-use serde::{de::DeserializeOwned, Serialize};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
 
 use super::{
     bufio::{BufReaderWithPos, BufWriterWithPos},
-    utils, Error,
+    config::MmapAdvice,
+    entry::{self, DataFileEntry, DataFileRecord, Decode, Encode, FileHeader},
+    tiering::ColdFiles,
+    utils::{self, Layout},
+    Error,
 };
 
 /// Position and length of an log entry within a log file.
@@ -22,41 +33,60 @@ pub(super) struct LogIndex {
 }
 
 /// Keeping track of the number of live/dead keys and how much space do the dead keys occupy.
-#[derive(Debug, Default)]
+///
+/// The counters are atomics so they can be updated through a shared reference. The struct is
+/// aligned to the size of a cache line pair so that the counters of different files never share
+/// a cache line.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[repr(align(128))]
 pub(super) struct LogStatistics {
-    live_keys: u64,
-    dead_keys: u64,
-    dead_bytes: u64,
+    live_keys: AtomicU64,
+    dead_keys: AtomicU64,
+    dead_bytes: AtomicU64,
+}
+
+impl Clone for LogStatistics {
+    fn clone(&self) -> Self {
+        Self {
+            live_keys: AtomicU64::new(self.live_keys()),
+            dead_keys: AtomicU64::new(self.dead_keys()),
+            dead_bytes: AtomicU64::new(self.dead_bytes()),
+        }
+    }
 }
 
 impl LogStatistics {
     /// Add a live key to the statistics.
-    pub(super) fn add_live(&mut self) {
-        self.live_keys += 1;
+    pub(super) fn add_live(&self) {
+        self.live_keys.fetch_add(1, Ordering::Relaxed);
     }
 
     /// Add a dead key to the statistics where `nbytes` is the size of the entry on disk.
-    pub(super) fn add_dead(&mut self, nbytes: u64) {
-        self.dead_keys += 1;
-        self.dead_bytes += nbytes;
+    pub(super) fn add_dead(&self, nbytes: u64) {
+        self.dead_keys.fetch_add(1, Ordering::Relaxed);
+        self.dead_bytes.fetch_add(nbytes, Ordering::Relaxed);
     }
 
-    pub(super) fn overwrite(&mut self, nbytes: u64) {
-        self.live_keys -= 1;
-        self.dead_keys += 1;
-        self.dead_bytes += nbytes;
+    /// Turn a live key into a dead key where `nbytes` is the size of the entry on disk.
+    pub(super) fn overwrite(&self, nbytes: u64) {
+        // The number of live keys can already be zero when the statistics were restored from an
+        // outdated checkpoint, so it saturates instead of wrapping around
+        self.live_keys
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .ok();
+        self.add_dead(nbytes);
     }
 
     pub(super) fn live_keys(&self) -> u64 {
-        self.live_keys
+        self.live_keys.load(Ordering::Relaxed)
     }
 
     pub(super) fn dead_keys(&self) -> u64 {
-        self.dead_keys
+        self.dead_keys.load(Ordering::Relaxed)
     }
 
     pub(super) fn dead_bytes(&self) -> u64 {
-        self.dead_bytes
+        self.dead_bytes.load(Ordering::Relaxed)
     }
 
     /// Calculate the fraction of dead keys to total keys
@@ -73,14 +103,201 @@ impl LogStatistics {
     }
 }
 
+/// A limit on the number of data files that are kept open by all the readers caches of a storage,
+/// so the number of file descriptors and memory maps doesn't grow with the number of caches.
+#[derive(Debug)]
+pub(super) struct OpenFiles {
+    open: AtomicUsize,
+    max: usize,
+}
+
+impl OpenFiles {
+    /// Create a limit of `max` open files, or no limit if `max` is `None`.
+    pub(super) fn new(max: Option<NonZeroUsize>) -> Self {
+        Self {
+            open: AtomicUsize::new(0),
+            max: max.map_or(usize::MAX, NonZeroUsize::get),
+        }
+    }
+
+    /// Get the number of data files that are kept open.
+    pub(super) fn count(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Count an open file and return a permit that uncounts it when dropped, or `None` if the
+    /// limit has been reached.
+    fn acquire(self: &Arc<Self>) -> Option<OpenFile> {
+        self.open
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| OpenFile(Arc::clone(self)))
+    }
+}
+
+/// A data file that is counted towards the limit of open files.
+#[derive(Debug)]
+struct OpenFile(Arc<OpenFiles>);
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The data files that the readers caches of a storage have open. A data file that a merge
+/// removes while readers still have it mapped is only renamed, and it's removed once the last
+/// reader closes it, so an unlinked file is never left pinned by a reader that is idle.
+#[derive(Debug, Default)]
+pub(super) struct FileRefs {
+    files: Mutex<HashMap<u64, Weak<FileRef>>>,
+}
+
+impl FileRefs {
+    /// Get the reference to a data file that is shared by all of its readers. The reference must
+    /// be taken before the file is opened, so a file that is removed in between is seen as
+    /// retired.
+    fn acquire(self: &Arc<Self>, fileid: u64) -> Arc<FileRef> {
+        let mut files = self.files.lock();
+        if let Some(file) = files.get(&fileid).and_then(Weak::upgrade) {
+            return file;
+        }
+        let file = Arc::new(FileRef {
+            fileid,
+            refs: Arc::downgrade(self),
+            retired: AtomicBool::new(false),
+            deferred: Mutex::new(None),
+        });
+        files.insert(fileid, Arc::downgrade(&file));
+        file
+    }
+
+    /// Tell the readers of a data file that it was moved away, so they close it.
+    pub(super) fn retire(&self, fileid: u64) {
+        let file = self.files.lock().get(&fileid).and_then(Weak::upgrade);
+        if let Some(file) = file {
+            file.retired.store(true, Ordering::Release);
+        }
+    }
+
+    /// Remove a data file, or rename it to be removed once the last reader closes it if readers
+    /// still have it open. It's not an error if the file doesn't exist.
+    pub(super) fn remove<P>(&self, fileid: u64, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let files = self.files.lock();
+        let Some(file) = files.get(&fileid).and_then(Weak::upgrade) else {
+            return utils::remove_file(path);
+        };
+        file.retired.store(true, Ordering::Release);
+        let deleted = utils::deleted_name(&path);
+        let result = match fs::rename(&path, &deleted) {
+            Ok(()) => {
+                *file.deferred.lock() = Some(deleted);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        };
+        // The reference may be the last one, whose drop takes the lock
+        drop(files);
+        drop(file);
+        result
+    }
+
+    /// Remove the files that couldn't be removed before, except the files that readers still
+    /// have open, which are removed once the last reader closes them.
+    pub(super) fn remove_deleted_files<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let files: Vec<_> = self
+            .files
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let held: HashSet<_> = files
+            .iter()
+            .filter_map(|file| file.deferred.lock().clone())
+            .collect();
+        // The references may be the last ones, whose drops take the lock
+        drop(files);
+        utils::remove_deleted_files_except(path, &held)
+    }
+}
+
+/// A data file that is open by at least one reader.
+#[derive(Debug)]
+struct FileRef {
+    fileid: u64,
+    refs: Weak<FileRefs>,
+    /// Whether the file was removed or moved away, so the readers should close it.
+    retired: AtomicBool,
+    /// The renamed file that is removed when the last reader closes it.
+    deferred: Mutex<Option<PathBuf>>,
+}
+
+impl Drop for FileRef {
+    fn drop(&mut self) {
+        if let Some(refs) = self.refs.upgrade() {
+            let mut files = refs.files.lock();
+            if files
+                .get(&self.fileid)
+                .is_some_and(|file| file.strong_count() == 0)
+            {
+                files.remove(&self.fileid);
+            }
+        }
+        if let Some(deleted) = self.deferred.get_mut().take() {
+            match fs::remove_file(&deleted) {
+                Ok(()) => {}
+                // The file was cleaned up when the storage was reopened
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(cause=?e, path=?deleted, "failed to remove data file"),
+            }
+        }
+    }
+}
+
 /// A wrapper arround a LRU cache of log readers
 #[derive(Debug)]
-pub(super) struct LogDir(LruCache<u64, LogReader>);
+pub(super) struct LogDir {
+    // The reader is dropped before the reference to its file, so the file is no longer mapped
+    // when it's removed
+    readers: LruCache<u64, (LogReader, OpenFile, Arc<FileRef>)>,
+    layout: Layout,
+    advice: MmapAdvice,
+    open_files: Arc<OpenFiles>,
+    file_refs: Arc<FileRefs>,
+    /// The data files that were moved to the cold store, which are read from there.
+    cold_files: Option<Arc<ColdFiles>>,
+}
 
 impl LogDir {
-    /// Create a new LRU readers cache with the specified size.
-    pub(super) fn new(size: NonZeroUsize) -> Self {
-        Self(LruCache::new(size))
+    /// Create a new LRU readers cache with the specified size for data files in the given layout,
+    /// whose memory maps are given the specified advice. The cached files are counted towards the
+    /// given limit of open files, and referenced in the given shared set. The files that aren't
+    /// in the directory are read from the cold files, if they're there.
+    pub(super) fn new(
+        size: NonZeroUsize,
+        layout: Layout,
+        advice: MmapAdvice,
+        open_files: Arc<OpenFiles>,
+        file_refs: Arc<FileRefs>,
+        cold_files: Option<Arc<ColdFiles>>,
+    ) -> Self {
+        Self {
+            readers: LruCache::new(size),
+            layout,
+            advice,
+            open_files,
+            file_refs,
+            cold_files,
+        }
     }
 
     pub(super) unsafe fn read<T, P>(
@@ -91,18 +308,19 @@ impl LogDir {
         pos: u64,
     ) -> Result<T, Error>
     where
-        T: DeserializeOwned,
+        T: Decode,
         P: AsRef<Path>,
     {
-        match self.0.get_mut(&fileid) {
-            Some(reader) => reader.at::<T>(len, pos),
-            None => {
-                let file = open(utils::datafile_name(&path, fileid))?;
-                let mut reader = LogReader::new(file)?;
-                let result = reader.at::<T>(len, pos);
-                self.0.put(fileid, reader);
-                result
-            }
+        match self.readers.get_mut(&fileid) {
+            Some((reader, ..)) => reader.at::<T>(len, pos),
+            None => match self.open(path, fileid) {
+                Ok((mut reader, file)) => {
+                    let result = reader.at::<T>(len, pos);
+                    self.cache(fileid, reader, file);
+                    result
+                }
+                Err(e) => T::read_from(&mut &self.fetch_cold(fileid, len, pos, e)?[..]),
+            },
         }
     }
 
@@ -118,65 +336,274 @@ impl LogDir {
         P: AsRef<Path>,
         W: Write,
     {
-        match self.0.get_mut(&fileid) {
-            Some(reader) => reader.copy_raw(len, pos, writer),
-            None => {
-                let file = open(utils::datafile_name(&path, fileid))?;
-                let mut reader = LogReader::new(file)?;
-                let result = reader.copy_raw(len, pos, writer);
-                self.0.put(fileid, reader);
-                result
+        match self.readers.get_mut(&fileid) {
+            Some((reader, ..)) => reader.copy_raw(len, pos, writer),
+            None => match self.open(path, fileid) {
+                Ok((mut reader, file)) => {
+                    let result = reader.copy_raw(len, pos, writer);
+                    self.cache(fileid, reader, file);
+                    result
+                }
+                Err(e) => {
+                    writer.write_all(&self.fetch_cold(fileid, len, pos, e)?)?;
+                    Ok(len)
+                }
+            },
+        }
+    }
+
+    /// Ask the OS to load the file segment at the given position into the page cache.
+    pub(super) unsafe fn prefetch<P>(
+        &mut self,
+        path: P,
+        fileid: u64,
+        len: u64,
+        pos: u64,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        match self.readers.get_mut(&fileid) {
+            Some((reader, ..)) => reader.prefetch(len, pos),
+            None => match self.open(path, fileid) {
+                Ok((mut reader, file)) => {
+                    let result = reader.prefetch(len, pos);
+                    self.cache(fileid, reader, file);
+                    result
+                }
+                // Fetching the entry of a cold file puts it in the cache of the cold files
+                Err(e) => self.fetch_cold(fileid, len, pos, e).map(drop),
+            },
+        }
+    }
+
+    /// Close the reader of a data file, and release the pages of its memory map.
+    pub(super) fn release(&mut self, fileid: u64) {
+        if let Some((reader, ..)) = self.readers.pop(&fileid) {
+            reader.release();
+        }
+    }
+
+    /// Close the readers of the data files that merges have removed or moved away, since they can
+    /// no longer be read from. The files are removed once all of their readers are closed.
+    pub(super) fn release_retired(&mut self) {
+        let retired: Vec<_> = self
+            .readers
+            .iter()
+            .filter(|(_, (.., file))| file.retired.load(Ordering::Acquire))
+            .map(|(fileid, _)| *fileid)
+            .collect();
+        for fileid in retired {
+            self.release(fileid);
+        }
+    }
+
+    /// Read the entry of a data file from the cold store after the file couldn't be opened, or
+    /// return the error of opening the file if it isn't in the cold store.
+    fn fetch_cold(&self, fileid: u64, len: u64, pos: u64, err: io::Error) -> io::Result<Bytes> {
+        match &self.cold_files {
+            Some(cold_files)
+                if err.kind() == io::ErrorKind::NotFound && cold_files.contains(fileid) =>
+            {
+                cold_files.fetch(fileid, pos, len)
+            }
+            _ => Err(err),
+        }
+    }
+
+    fn open<P>(&self, path: P, fileid: u64) -> io::Result<(LogReader, Arc<FileRef>)>
+    where
+        P: AsRef<Path>,
+    {
+        let file_ref = self.file_refs.acquire(fileid);
+        let file = open(utils::datafile_name(&path, self.layout, fileid))?;
+        Ok((LogReader::with_advice(file, self.advice)?, file_ref))
+    }
+
+    /// Keep the reader of a data file open for later reads. When the limit of open files has been
+    /// reached, the least recently used files of this cache are closed to make room. If there's
+    /// nothing left to close, the reader is closed once the caller is done with it.
+    fn cache(&mut self, fileid: u64, reader: LogReader, file: Arc<FileRef>) {
+        if self.readers.len() == self.readers.cap().get() {
+            self.readers.pop_lru();
+        }
+        loop {
+            if let Some(permit) = self.open_files.acquire() {
+                self.readers.put(fileid, (reader, permit, file));
+                return;
+            }
+            if self.readers.pop_lru().is_none() {
+                return;
             }
         }
     }
 }
 
-/// An append-only file writer that serializes data using `bincode`.
+/// An append-only file writer that encodes entries in their on-disk layout.
 #[derive(Debug)]
-pub(super) struct LogWriter(BufWriterWithPos<fs::File>);
+pub(super) enum LogWriter {
+    /// Appends through a buffer that is flushed after every entry.
+    Buffered(BufWriterWithPos<fs::File>),
+    /// Appends by copying into a memory-mapped file.
+    Mmap(MmapWriter),
+}
 
 impl LogWriter {
-    /// Create a new log writer for writing entries to the given file.
-    pub(super) fn new(file: fs::File) -> io::Result<Self> {
+    /// Create a new log writer for writing entries to the given empty file, which starts with the
+    /// given header.
+    pub(super) fn new(file: fs::File, header: FileHeader) -> Result<Self, Error> {
         let writer = BufWriterWithPos::new(file)?;
-        Ok(Self(writer))
+        let mut writer = Self::Buffered(writer);
+        writer.append(&header)?;
+        Ok(writer)
+    }
+
+    /// Create a new log writer that preallocates `size` bytes for the given empty file and writes
+    /// entries through a memory map of the file, which starts with the given header.
+    pub(super) fn mmap(file: fs::File, size: u64, header: FileHeader) -> Result<Self, Error> {
+        let writer = MmapWriter::new(file, size)?;
+        let mut writer = Self::Mmap(writer);
+        writer.append(&header)?;
+        Ok(writer)
     }
 
     /// Serialize the given entry at EOF and ensure to flush all data to the I/O device.
     pub(super) fn append<T>(&mut self, entry: &T) -> Result<LogIndex, Error>
     where
-        T: Serialize,
+        T: Encode,
     {
-        let pos = self.0.pos();
+        match self {
+            Self::Buffered(writer) => {
+                let pos = writer.pos();
 
-        bincode::serialize_into(&mut self.0, entry)?;
-        self.0.flush()?;
+                entry.write_to(writer)?;
+                writer.flush()?;
 
-        let len = self.0.pos() - pos;
-        Ok(LogIndex { len, pos })
+                let len = writer.pos() - pos;
+                Ok(LogIndex { len, pos })
+            }
+            Self::Mmap(writer) => writer.append(entry),
+        }
     }
 
     /// Synchronize all data to disk.
     pub(super) fn sync(&mut self) -> io::Result<()> {
-        self.0.get_ref().sync_all()
+        #[cfg(feature = "simulation")]
+        super::simulation::sync()?;
+        match self {
+            Self::Buffered(writer) => writer.get_ref().sync_all(),
+            Self::Mmap(writer) => match &writer.mmap {
+                Some(mmap) => mmap.flush(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// An append-only writer that copies entries into a memory-mapped file whose space is allocated
+/// up front, so appending doesn't need a system call. The unwritten space is zero-filled, and the
+/// file is truncated to the written data when the writer is dropped.
+#[derive(Debug)]
+pub(super) struct MmapWriter {
+    /// The mapping is only `None` while the file is resized, since Windows doesn't allow
+    /// resizing a file that is mapped.
+    mmap: Option<memmap2::MmapMut>,
+    file: fs::File,
+    pos: u64,
+}
+
+impl MmapWriter {
+    fn new(file: fs::File, size: u64) -> io::Result<Self> {
+        file.set_len(size)?;
+        // SAFETY: The file is created by us and is only modified through this writer.
+        let mmap = unsafe { memmap2::MmapMut::map_mut(&file)? };
+        Ok(Self {
+            mmap: Some(mmap),
+            file,
+            pos: 0,
+        })
+    }
+
+    fn append<T>(&mut self, entry: &T) -> Result<LogIndex, Error>
+    where
+        T: Encode,
+    {
+        let len = entry.encoded_len();
+        let end = self.pos + len;
+        // The mapping is missing if resizing failed on an earlier append
+        let mut mmap = self
+            .mmap
+            .take()
+            .ok_or_else(|| io::Error::other("file is not mapped"))?;
+        if end > mmap.len() as u64 {
+            // Grow the file for an entry that doesn't fit in the allocated space. The writer is
+            // expected to move to a new file right after this since the file is full.
+            mmap.flush()?;
+            drop(mmap);
+            self.file.set_len(end)?;
+            // SAFETY: Same as when the file was first mapped.
+            mmap = unsafe { memmap2::MmapMut::map_mut(&self.file)? };
+        }
+        let mmap = self.mmap.insert(mmap);
+        entry.write_to(&mut &mut mmap[self.pos as usize..end as usize])?;
+        let index = LogIndex { len, pos: self.pos };
+        self.pos = end;
+        Ok(index)
+    }
+}
+
+impl Drop for MmapWriter {
+    fn drop(&mut self) {
+        // Remove the unused space so the file ends where its data ends
+        if let Some(mmap) = self.mmap.take() {
+            if let Err(e) = mmap.flush() {
+                error!(cause=?e, "failed to flush memory-mapped file");
+            }
+        }
+        if let Err(e) = self.file.set_len(self.pos) {
+            error!(cause=?e, "failed to truncate memory-mapped file");
+        }
     }
 }
 
-/// A random-access file reader that deserializes data using `bincode`.
+/// A random-access file reader that decodes entries from their on-disk layout.
 #[derive(Debug)]
 pub(super) struct LogReader {
     mmap: memmap2::Mmap,
     file: fs::File,
+    advice: MmapAdvice,
 }
 
 impl LogReader {
     /// Create a new log reader for reading entries from the given file.
+    #[cfg(test)]
     pub(super) fn new(file: fs::File) -> io::Result<Self> {
+        Self::with_advice(file, MmapAdvice::Normal)
+    }
+
+    /// Create a new log reader whose memory map of the file is given the specified advice.
+    pub(super) fn with_advice(file: fs::File, advice: MmapAdvice) -> io::Result<Self> {
         // SAFETY: We just create a Mmap without doing any read so there's nothing to worry about.
         // All methods that access `LogReader` MUST be maked unsafe since the caller is responsible
         // for providing a valid file position.
-        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
-        Ok(Self { mmap, file })
+        let mmap = unsafe { map(&file, advice)? };
+        Ok(Self { mmap, file, advice })
+    }
+
+    /// Release the pages of the memory map with `MADV_DONTNEED`, so they are not kept in memory
+    /// because of this reader.
+    pub(super) fn release(self) {
+        #[cfg(unix)]
+        {
+            // SAFETY: The map is dropped right after, so the released pages are never accessed.
+            let result = unsafe {
+                self.mmap
+                    .unchecked_advise(memmap2::UncheckedAdvice::DontNeed)
+            };
+            if let Err(e) = result {
+                error!(cause=?e, "failed to release memory-mapped file");
+            }
+        }
     }
 
     /// Return the entry at the given position by mapping the file segment directly into memory.
@@ -186,16 +613,16 @@ impl LogReader {
     /// The caller must ensure that the file segment given by `len` and `pos` is valid.
     pub(super) unsafe fn at<T>(&mut self, len: u64, pos: u64) -> Result<T, Error>
     where
-        T: DeserializeOwned,
+        T: Decode,
     {
         // We assume that the caller always provide a valid data entry so we can expand the Mmap
         // and try reading with the `len` and `pos`.
         if pos >= self.mmap.len() as u64 {
-            self.mmap = memmap2::MmapOptions::new().map(&self.file)?;
+            self.mmap = map(&self.file, self.advice)?;
         }
         let start = pos as usize;
         let end = start + len as usize;
-        Ok(bincode::deserialize(&self.mmap[start..end])?)
+        T::read_from(&mut &self.mmap[start..end])
     }
 
     /// Copy the raw data at the given position into the writer at `dst` by mapping the file segment
@@ -211,47 +638,189 @@ impl LogReader {
         // We assume that the caller always provide a valid data entry so we can expand the Mmap
         // and try reading with the `len` and `pos`.
         if pos >= self.mmap.len() as u64 {
-            self.mmap = memmap2::MmapOptions::new().map(&self.file)?;
+            self.mmap = map(&self.file, self.advice)?;
         }
         let start = pos as usize;
         let end = start + len as usize;
         io::copy(&mut self.mmap[start..end].reader(), dst)
     }
+    /// Ask the OS to read the file segment at the given position ahead of time, so later reads of
+    /// the segment don't wait for the disk. On platforms without `madvise`, the pages are read
+    /// right away instead.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the file segment given by `len` and `pos` is valid.
+    pub(super) unsafe fn prefetch(&mut self, len: u64, pos: u64) -> io::Result<()> {
+        if pos + len > self.mmap.len() as u64 {
+            self.mmap = map(&self.file, self.advice)?;
+        }
+        let start = pos as usize;
+        let len = len as usize;
+        #[cfg(unix)]
+        {
+            self.mmap
+                .advise_range(memmap2::Advice::WillNeed, start, len)
+        }
+        #[cfg(not(unix))]
+        {
+            const PAGE_SIZE: usize = 4096;
+            let touched = self.mmap[start..start + len]
+                .iter()
+                .step_by(PAGE_SIZE)
+                .fold(0u8, |acc, b| acc ^ b);
+            std::hint::black_box(touched);
+            Ok(())
+        }
+    }
+}
+
+/// Map the file into memory and give the map the specified advice.
+///
+/// # Safety
+///
+/// The caller must ensure that the file segments that are accessed through the map are valid.
+unsafe fn map(file: &fs::File, advice: MmapAdvice) -> io::Result<memmap2::Mmap> {
+    let mmap = memmap2::MmapOptions::new().map(file)?;
+    #[cfg(unix)]
+    match advice {
+        MmapAdvice::Normal => {}
+        MmapAdvice::Random => mmap.advise(memmap2::Advice::Random)?,
+        MmapAdvice::Sequential => mmap.advise(memmap2::Advice::Sequential)?,
+    }
+    #[cfg(not(unix))]
+    let _ = advice;
+    Ok(mmap)
 }
 
-/// A sequential-access file reader that deserializes data using `bincode`.
+/// A sequential-access file reader that decodes the entries of a file.
 #[derive(Debug)]
-pub(super) struct LogIterator(BufReaderWithPos<fs::File>);
+pub(super) struct LogIterator {
+    reader: BufReaderWithPos<fs::File>,
+    /// Whether the file ends before its header, which happens when a crash comes right after the
+    /// file is created.
+    empty: bool,
+    /// Whether the iteration ended at a record whose checksum doesn't match.
+    mismatch: bool,
+}
 
 impl LogIterator {
-    /// Create a new log iterator for iterating through entries from the given file.
-    pub(super) fn new(file: fs::File) -> io::Result<Self> {
-        let reader = BufReaderWithPos::new(file)?;
-        Ok(Self(reader))
+    /// Create a new log iterator for iterating through entries from the given file, which must
+    /// start with the given header.
+    pub(super) fn new(file: fs::File, header: FileHeader) -> Result<Self, Error> {
+        let mut reader = BufReaderWithPos::new(file)?;
+        let empty = match FileHeader::read_from(&mut reader) {
+            Ok(found) if found == header => false,
+            Ok(_) => return Err(Error::corrupted("file header has the wrong kind")),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => true,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            reader,
+            empty,
+            mismatch: false,
+        })
     }
 
     /// Return the entry at the current reader position.
     pub(super) fn next<T>(&mut self) -> Result<Option<(LogIndex, T)>, Error>
     where
-        T: DeserializeOwned,
+        T: Decode,
     {
+        if self.empty {
+            return Ok(None);
+        }
         // get reader current position so we can calculate the number of serialized bytes
-        let pos = self.0.pos();
-        match bincode::deserialize_from(&mut self.0) {
+        let pos = self.reader.pos();
+        match T::read_from(&mut self.reader) {
             Ok(entry) => {
-                let len = self.0.pos() - pos;
+                let len = self.reader.pos() - pos;
                 let index = LogIndex { len, pos };
                 Ok(Some((index, entry)))
             }
-            Err(e) => match e.as_ref() {
-                bincode::ErrorKind::Io(ioe) => match ioe.kind() {
-                    io::ErrorKind::UnexpectedEof => Ok(None), // stop iterating when EOF
-                    _ => Err(e.into()),
-                },
+            // stop iterating when EOF
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.mismatch = entry::is_checksum_mismatch(&e);
+                Ok(None)
+            }
+            Err(Error::Serialization(e)) => match e.as_ref() {
+                bincode::ErrorKind::Io(ioe) if ioe.kind() == io::ErrorKind::UnexpectedEof => {
+                    Ok(None)
+                }
                 _ => Err(e.into()),
             },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Return `true` if the iteration ended at the end of the file, or at a record whose checksum
+    /// doesn't match and that is only followed by zeros, as it is when a write is cut off by a
+    /// crash or when the file was allocated up front. Must only be called once [`Self::next`]
+    /// returned `None`.
+    pub(super) fn ended_cleanly(&mut self) -> io::Result<bool> {
+        if !self.mismatch {
+            return Ok(true);
+        }
+        let mut buf = [0u8; 8192];
+        loop {
+            match self.reader.read(&mut buf)? {
+                0 => return Ok(true),
+                n if buf[..n].iter().any(|&b| b != 0) => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A sequential-access reader over the entries of a data file. The entries of a batch are given
+/// one by one once the whole batch has been read, and a batch that was only partly written ends
+/// the data of the file.
+#[derive(Debug)]
+pub(super) struct DataFileIterator {
+    records: LogIterator,
+    batch: std::vec::IntoIter<(LogIndex, DataFileEntry)>,
+}
+
+impl DataFileIterator {
+    /// Create a new iterator over the entries of the given data file.
+    pub(super) fn new(file: fs::File) -> Result<Self, Error> {
+        Ok(Self {
+            records: LogIterator::new(file, FileHeader::Data)?,
+            batch: Vec::new().into_iter(),
+        })
+    }
+
+    /// Return the next entry and its position.
+    pub(super) fn next(&mut self) -> Result<Option<(LogIndex, DataFileEntry)>, Error> {
+        if let Some(next) = self.batch.next() {
+            return Ok(Some(next));
+        }
+        match self.records.next::<DataFileRecord>()? {
+            None => Ok(None),
+            Some((index, DataFileRecord::Entry(entry))) => Ok(Some((index, entry))),
+            Some((index, DataFileRecord::Batch(batch))) => {
+                let mut pos = index.pos + batch.header_len();
+                let entries: Vec<_> = batch
+                    .entries
+                    .into_iter()
+                    .map(|entry| {
+                        let len = entry.encoded_len();
+                        let index = LogIndex { len, pos };
+                        pos += len;
+                        (index, entry)
+                    })
+                    .collect();
+                self.batch = entries.into_iter();
+                self.next()
+            }
         }
     }
+
+    /// Return `true` if the entries ended where the data of the file ends. See
+    /// [`LogIterator::ended_cleanly`].
+    pub(super) fn ended_cleanly(&mut self) -> io::Result<bool> {
+        self.records.ended_cleanly()
+    }
 }
 
 /// Create a new data file for writing entries to.
@@ -265,6 +834,18 @@ where
         .open(path)
 }
 
+/// Create a new data file that can be both read and written, for mapping it into memory.
+pub(super) fn create_mappable<P>(path: P) -> io::Result<fs::File>
+where
+    P: AsRef<Path>,
+{
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
 /// Open a data file for reading entries from.
 pub(super) fn open<P>(path: P) -> io::Result<fs::File>
 where
@@ -277,29 +858,65 @@ where
 mod tests {
     use proptest::{collection::vec, prelude::*};
 
+    use std::io::Read;
+
     use super::*;
 
+    impl Encode for Vec<u8> {
+        fn encoded_len(&self) -> u64 {
+            bincode::serialized_size(self).unwrap()
+        }
+
+        fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+            Ok(bincode::serialize_into(w, self)?)
+        }
+    }
+
+    impl Decode for Vec<u8> {
+        fn read_from<R: Read>(r: &mut R) -> Result<Self, Error> {
+            Ok(bincode::deserialize_from(r)?)
+        }
+    }
+
     proptest! {
         #[test]
         fn writer_position_updated_after_write(buf in vec(any::<u8>(), 0..2048)) {
             let dir = tempfile::tempdir().unwrap();
             let fpath = dir.as_ref().join("test");
             // write the entry
-            let mut writer = LogWriter::new(create(fpath).unwrap()).unwrap();
+            let mut writer = LogWriter::new(create(fpath).unwrap(), FileHeader::Data).unwrap();
             let idx1 = writer.append(&buf).unwrap();
             let idx2 = writer.append(&buf).unwrap();
             // succeed if we received the correct index
-            prop_assert_eq!(idx1.pos, 0);
-            prop_assert_eq!(idx1.len, idx2.pos);
+            prop_assert_eq!(idx1.pos, FileHeader::LEN);
+            prop_assert_eq!(idx1.pos + idx1.len, idx2.pos);
             prop_assert_eq!(idx1.len, idx2.len);
         }
 
+        #[test]
+        fn mmap_writer_truncates_file_when_dropped(buf in vec(any::<u8>(), 0..2048)) {
+            let dir = tempfile::tempdir().unwrap();
+            let fpath = dir.as_ref().join("test");
+            // write entries past the preallocated space
+            let mut writer = LogWriter::mmap(create_mappable(&fpath).unwrap(), 1024, FileHeader::Data).unwrap();
+            let idx1 = writer.append(&buf).unwrap();
+            let idx2 = writer.append(&buf).unwrap();
+            // read the entries while the writer is alive
+            let mut reader = LogReader::new(open(&fpath).unwrap()).unwrap();
+            let buf2 = unsafe { reader.at::<Vec<u8>>(idx2.len, idx2.pos).unwrap() };
+            prop_assert_eq!(&buf, &buf2);
+            // succeed if the file ends with the last entry
+            drop(writer);
+            prop_assert_eq!(idx1.pos + idx1.len, idx2.pos);
+            prop_assert_eq!(idx2.pos + idx2.len, fs::metadata(&fpath).unwrap().len());
+        }
+
         #[test]
         fn reader_reads_entry_written_by_writer(buf in vec(any::<u8>(), 0..2048)) {
             let dir = tempfile::tempdir().unwrap();
             let fpath = dir.as_ref().join("test");
             // write the entry
-            let mut writer = LogWriter::new(create(&fpath).unwrap()).unwrap();
+            let mut writer = LogWriter::new(create(&fpath).unwrap(), FileHeader::Data).unwrap();
             let idx1 = writer.append(&buf).unwrap();
             let idx2 = writer.append(&buf).unwrap();
             // read the entry
@@ -315,7 +932,7 @@ mod tests {
         fn reader_should_remap_disk_when_file_changed(buf in vec(any::<u8>(), 0..2048)) {
             let dir = tempfile::tempdir().unwrap();
             let fpath = dir.as_ref().join("test");
-            let mut writer = LogWriter::new(create(&fpath).unwrap()).unwrap();
+            let mut writer = LogWriter::new(create(&fpath).unwrap(), FileHeader::Data).unwrap();
             let mut reader = LogReader::new(open(&fpath).unwrap()).unwrap();
 
             // write the entry
@@ -344,11 +961,11 @@ mod tests {
             let dir = tempfile::tempdir().unwrap();
             let fpath = dir.as_ref().join("test");
             // write the entries
-            let mut writer = LogWriter::new(create(&fpath).unwrap()).unwrap();
-            let indices: Vec<LogIndex> = entries.iter().map(|buf| writer.append(&buf).unwrap()).collect();
+            let mut writer = LogWriter::new(create(&fpath).unwrap(), FileHeader::Data).unwrap();
+            let indices: Vec<LogIndex> = entries.iter().map(|buf| writer.append(buf).unwrap()).collect();
             // read the entries
             let mut reader = LogReader::new(open(&fpath).unwrap()).unwrap();
-            let mut iter = LogIterator::new(open(&fpath).unwrap()).unwrap();
+            let mut iter = LogIterator::new(open(&fpath).unwrap(), FileHeader::Data).unwrap();
             for (idx, buf) in indices.iter().zip(entries) {
                 let (idx_from_reader, buf_from_reader) = iter.next::<Vec<u8>>().unwrap().unwrap();
                 prop_assert_eq!(idx, &idx_from_reader);
@@ -358,4 +975,68 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn removed_files_are_kept_until_their_readers_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_refs = Arc::new(FileRefs::default());
+        let mut readers = LogDir::new(
+            NonZeroUsize::new(2).unwrap(),
+            Layout::Flat,
+            MmapAdvice::Normal,
+            Arc::new(OpenFiles::new(None)),
+            Arc::clone(&file_refs),
+            None,
+        );
+        let datafiles: Vec<_> = (0..2)
+            .map(|fileid| utils::datafile_name(dir.path(), Layout::Flat, fileid))
+            .collect();
+        let mut indices = Vec::new();
+        for datafile in &datafiles {
+            let mut writer = LogWriter::new(create(datafile).unwrap(), FileHeader::Data).unwrap();
+            indices.push(writer.append(&vec![1u8, 2, 3]).unwrap());
+        }
+        let buf: Vec<u8> =
+            unsafe { readers.read(dir.path(), 0, indices[0].len, indices[0].pos) }.unwrap();
+        assert_eq!(vec![1, 2, 3], buf);
+
+        // The open file is hidden until its reader is closed
+        file_refs.remove(0, &datafiles[0]).unwrap();
+        let deleted = utils::deleted_name(&datafiles[0]);
+        assert!(!datafiles[0].exists());
+        assert!(deleted.exists());
+        readers.release_retired();
+        assert!(!deleted.exists());
+
+        // Files that aren't open are removed right away
+        file_refs.remove(1, &datafiles[1]).unwrap();
+        assert!(!datafiles[1].exists());
+        assert!(!utils::deleted_name(&datafiles[1]).exists());
+        assert!(file_refs.files.lock().is_empty());
+    }
+
+    #[test]
+    fn overwrite_does_not_underflow_live_keys() {
+        let stats = LogStatistics::default();
+        stats.add_live();
+        stats.overwrite(10);
+        stats.overwrite(20);
+        assert_eq!(0, stats.live_keys());
+        assert_eq!(2, stats.dead_keys());
+        assert_eq!(30, stats.dead_bytes());
+    }
+
+    #[test]
+    fn statistics_are_serialized_as_plain_integers() {
+        // Checkpoints written before the counters became atomics must still be readable
+        let stats = LogStatistics::default();
+        stats.add_live();
+        stats.add_dead(7);
+        let buf = bincode::serialize(&stats).unwrap();
+        assert_eq!(bincode::serialize(&(1u64, 1u64, 7u64)).unwrap(), buf);
+        let stats: LogStatistics = bincode::deserialize(&buf).unwrap();
+        let copy = stats.clone();
+        assert_eq!(1, copy.live_keys());
+        assert_eq!(7, stats.dead_bytes());
+    }
 }
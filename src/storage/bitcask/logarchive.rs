@@ -0,0 +1,245 @@
+//! A continuous archive of the log, for restoring the storage to any point in time.
+//!
+//! Every data file that the writer closes is added to the archive, and the part of the active
+//! file that has been written so far can be added periodically as a partial segment, which is
+//! replaced once the file is closed. Files written by merges are never archived: the archive
+//! only holds the log in the order it was written, so the entries of all the archived files are
+//! in the order of their timestamps. A manifest lists the archived files.
+//!
+//! Restoring replays the archived files in order and stops at the first entry that was written
+//! after the requested time. The writes that happened after the last partial segment was taken
+//! can't be restored.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    log::{self, DataFileIterator},
+    utils::{self, Layout},
+    Error,
+};
+
+/// The name of the file that lists the archived files.
+const MANIFEST_FILE: &str = "log.manifest";
+
+/// The files in the archive.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    files: BTreeMap<u64, ArchivedFile>,
+}
+
+/// A data file in the archive.
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedFile {
+    /// The number of bytes of the file that were written when it was archived.
+    len: u64,
+    /// Whether the file was closed, otherwise it's a partial segment of the active file.
+    closed: bool,
+}
+
+impl Manifest {
+    /// Read the manifest of the archive, which is empty if nothing was archived yet.
+    fn load<P>(archive_dir: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest = archive_dir.as_ref().join(MANIFEST_FILE);
+        match fs::read(&manifest) {
+            Ok(buf) => serde_json::from_slice(&buf)
+                .map_err(|_| Error::corrupted_at("invalid log archive manifest", manifest, None)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the manifest of the archive. The new manifest is written to a temporary file
+    /// first, so the manifest is never left half written.
+    fn save<P>(&self, archive_dir: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let path = archive_dir.as_ref().join(MANIFEST_FILE);
+        let tmp = path.with_extension("tmp");
+        let buf = serde_json::to_vec(self).expect("manifest must be serializable");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Add the closed data file with the given ID to the archive. Closed files never change until
+/// merges remove them, so the archived file is a hard link to the data file when the archive is
+/// on the same file system.
+pub(super) fn archive_closed<P, Q>(
+    path: P,
+    layout: Layout,
+    fileid: u64,
+    archive_dir: Q,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fs::create_dir_all(&archive_dir)?;
+    let src = utils::datafile_name(&path, layout, fileid);
+    let dst = utils::datafile_name(&archive_dir, Layout::Flat, fileid);
+    // A partial segment of the file may have been archived before
+    if let Err(e) = fs::remove_file(&dst) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    if fs::hard_link(&src, &dst).is_err() {
+        fs::copy(&src, &dst)?;
+    }
+    let len = fs::metadata(&dst)?.len();
+
+    let mut manifest = Manifest::load(&archive_dir)?;
+    manifest
+        .files
+        .insert(fileid, ArchivedFile { len, closed: true });
+    manifest.save(&archive_dir)
+}
+
+/// Add the first `len` bytes of the active data file with the given ID to the archive as a
+/// partial segment, replacing the previous segment of the file.
+pub(super) fn archive_active<P, Q>(
+    path: P,
+    layout: Layout,
+    fileid: u64,
+    len: u64,
+    archive_dir: Q,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fs::create_dir_all(&archive_dir)?;
+    let dst = utils::datafile_name(&archive_dir, Layout::Flat, fileid);
+    let tmp = dst.with_extension("partial");
+    let src = log::open(utils::datafile_name(&path, layout, fileid))?;
+    let mut file = fs::File::create(&tmp)?;
+    io::copy(&mut src.take(len), &mut file)?;
+    file.sync_all()?;
+    fs::rename(tmp, dst)?;
+
+    let mut manifest = Manifest::load(&archive_dir)?;
+    manifest
+        .files
+        .insert(fileid, ArchivedFile { len, closed: false });
+    manifest.save(&archive_dir)
+}
+
+/// Get the path of the archived copy of the closed data file with the given ID, if there's one.
+pub(super) fn closed_copy<P>(archive_dir: P, fileid: u64) -> Result<Option<PathBuf>, Error>
+where
+    P: AsRef<Path>,
+{
+    let manifest = Manifest::load(&archive_dir)?;
+    let copy = match manifest.files.get(&fileid) {
+        Some(archived) if archived.closed => {
+            Some(utils::datafile_name(&archive_dir, Layout::Flat, fileid))
+        }
+        _ => None,
+    };
+    Ok(copy)
+}
+
+/// Write the data files of a storage holding the entries that were written at or before the Unix
+/// timestamp in nanoseconds into the directory at `path`. Returns the number of restored entries.
+pub(super) fn restore<P, Q>(archive_dir: P, path: Q, tstamp: i64) -> Result<u64, Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let manifest = Manifest::load(&archive_dir)?;
+    fs::create_dir_all(&path)?;
+    let mut restored = 0;
+    for (&fileid, archived) in &manifest.files {
+        let src = utils::datafile_name(&archive_dir, Layout::Flat, fileid);
+        let mut entries = DataFileIterator::new(log::open(&src)?)?;
+        let mut end = 0;
+        let mut reached = false;
+        while let Some((index, entry)) = entries.next()? {
+            // A zero-filled entry marks the end of a file that was allocated up front
+            if entry.tstamp == 0 || index.pos >= archived.len {
+                break;
+            }
+            if entry.tstamp > tstamp {
+                reached = true;
+                break;
+            }
+            end = index.pos + index.len;
+            restored += 1;
+        }
+        if end > 0 {
+            let mut file = fs::File::create(utils::datafile_name(&path, Layout::Flat, fileid))?;
+            io::copy(&mut log::open(&src)?.take(end), &mut file)?;
+            file.sync_all()?;
+        }
+        // The entries of the later files were all written after the time
+        if reached {
+            break;
+        }
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::storage::bitcask::{
+        entry::{DataFileEntry, FileHeader},
+        log::LogWriter,
+    };
+
+    fn write_datafile(path: &Path, fileid: u64, tstamps: &[i64]) {
+        let file = log::create(utils::datafile_name(path, Layout::Flat, fileid)).unwrap();
+        let mut writer = LogWriter::new(file, FileHeader::Data).unwrap();
+        for &tstamp in tstamps {
+            let entry = DataFileEntry {
+                tstamp,
+                expiry: None,
+                key: Bytes::from(format!("key{tstamp}")),
+                value: Some(Bytes::from("value")),
+            };
+            writer.append(&entry).unwrap();
+        }
+    }
+
+    #[test]
+    fn archived_files_are_restored_up_to_the_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let restore_dir = dir.path().join("restore");
+        write_datafile(dir.path(), 0, &[1, 2, 3]);
+        write_datafile(dir.path(), 1, &[4, 5]);
+        archive_closed(dir.path(), Layout::Flat, 0, &archive_dir).unwrap();
+        let len = fs::metadata(utils::datafile_name(dir.path(), Layout::Flat, 1))
+            .unwrap()
+            .len();
+        archive_active(dir.path(), Layout::Flat, 1, len, &archive_dir).unwrap();
+        // Entries written after the partial segment aren't archived
+        fs::remove_file(utils::datafile_name(dir.path(), Layout::Flat, 1)).unwrap();
+        write_datafile(dir.path(), 1, &[4, 5, 6]);
+
+        assert_eq!(2, restore(&archive_dir, &restore_dir, 2).unwrap());
+        let fileids: Vec<u64> = utils::sorted_fileids(&restore_dir).unwrap().collect();
+        assert_eq!(vec![0], fileids);
+
+        fs::remove_dir_all(&restore_dir).unwrap();
+        assert_eq!(5, restore(&archive_dir, &restore_dir, 10).unwrap());
+        let fileids: Vec<u64> = utils::sorted_fileids(&restore_dir).unwrap().collect();
+        assert_eq!(vec![0, 1], fileids);
+    }
+}
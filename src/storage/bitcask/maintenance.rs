@@ -0,0 +1,158 @@
+//! Periodic maintenance that runs without a Tokio runtime, either on a dedicated thread with
+//! [`RuntimeMode::Sync`] or on the caller's schedule with [`RuntimeMode::Manual`].
+//!
+//! [`RuntimeMode::Sync`]: super::RuntimeMode::Sync
+//! [`RuntimeMode::Manual`]: super::RuntimeMode::Manual
+
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use super::{
+    can_scrub,
+    config::{ScrubPolicy, SyncStrategy},
+    first_merge_delay, merge_delay, utils, Error, Handle,
+};
+
+/// The max time the maintenance thread sleeps before checking whether the storage is closed.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs the merges, the disk synchronizations, the checkpoints, the log archiving, the writes of
+/// the coalesced overwrites, and the scrubs of a storage when they're due, each time it's ticked.
+#[derive(Debug)]
+pub struct MaintenanceDriver {
+    handle: Handle,
+    next_merge: Instant,
+    next_sync: Option<Instant>,
+    next_checkpoint: Option<Instant>,
+    next_log_archive: Option<Instant>,
+    next_coalesce: Option<Instant>,
+    next_scrub: Option<Instant>,
+}
+
+impl MaintenanceDriver {
+    /// Create a driver whose tasks are first due after their intervals.
+    pub(super) fn new(handle: Handle) -> Self {
+        let now = utils::instant();
+        let conf = handle.ctx.get_conf();
+        let next_sync = match conf.sync {
+            SyncStrategy::IntervalMs(ms) => Some(now + Duration::from_millis(ms)),
+            _ => None,
+        };
+        let next_checkpoint = conf
+            .checkpoint_interval_ms
+            .map(|ms| now + Duration::from_millis(ms));
+        let next_log_archive = conf
+            .log_archive_dir
+            .as_ref()
+            .and(conf.log_archive_interval_ms)
+            .map(|ms| now + Duration::from_millis(ms));
+        let next_coalesce = conf.coalesce_window().map(|window| now + window);
+        let next_scrub = (conf.scrub_policy != ScrubPolicy::Never)
+            .then(|| now + Duration::from_millis(conf.scrub_interval_ms));
+        let next_merge = now + first_merge_delay(&handle);
+        Self {
+            handle,
+            next_merge,
+            next_sync,
+            next_checkpoint,
+            next_log_archive,
+            next_coalesce,
+            next_scrub,
+        }
+    }
+
+    /// Run the tasks that are due and return the time until the next task is due. Errors from the
+    /// tasks are logged rather than returned, so a failing task doesn't stop the others. Returns
+    /// [`Error::Closed`] once the storage is closed.
+    pub fn tick(&mut self) -> Result<Duration, Error> {
+        if self.handle.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        let conf = self.handle.ctx.get_conf();
+        let now = utils::instant();
+        // Merges and syncs that are due while paused are skipped rather than deferred
+        let paused = self.handle.maintenance_paused();
+
+        if now >= self.next_merge {
+            if !paused {
+                if let Err(e) = self.handle.merge() {
+                    error!(cause=?e, "merge error");
+                }
+            }
+            // The merge settings are read every time because they can be reloaded
+            self.next_merge = utils::instant() + merge_delay(&self.handle.ctx.get_merge_strategy());
+        }
+        if let (Some(next), SyncStrategy::IntervalMs(ms)) = (self.next_sync, &conf.sync) {
+            if now >= next {
+                if !paused {
+                    if let Err(e) = self.handle.sync() {
+                        error!(cause=?e, "sync error");
+                    }
+                }
+                self.next_sync = Some(utils::instant() + Duration::from_millis(*ms));
+            }
+        }
+        if let (Some(next), Some(ms)) = (self.next_checkpoint, conf.checkpoint_interval_ms) {
+            if now >= next {
+                if let Err(e) = self.handle.checkpoint() {
+                    error!(cause=?e, "checkpoint error");
+                }
+                self.next_checkpoint = Some(utils::instant() + Duration::from_millis(ms));
+            }
+        }
+
+        if let (Some(next), Some(ms)) = (self.next_log_archive, conf.log_archive_interval_ms) {
+            if now >= next {
+                if let Err(e) = self.handle.archive_log() {
+                    error!(cause=?e, "log archive error");
+                }
+                self.next_log_archive = Some(utils::instant() + Duration::from_millis(ms));
+            }
+        }
+
+        if let (Some(next), Some(window)) = (self.next_coalesce, conf.coalesce_window()) {
+            if now >= next {
+                if let Err(e) = self.handle.flush_coalesced() {
+                    error!(cause=?e, "coalesced writes error");
+                }
+                self.next_coalesce = Some(utils::instant() + window);
+            }
+        }
+
+        if let Some(next) = self.next_scrub {
+            if now >= next {
+                if can_scrub(&self.handle) {
+                    if let Err(e) = self.handle.scrub() {
+                        error!(cause=?e, "scrub error");
+                    }
+                }
+                self.next_scrub =
+                    Some(utils::instant() + Duration::from_millis(conf.scrub_interval_ms));
+            }
+        }
+
+        let next = [
+            Some(self.next_merge),
+            self.next_sync,
+            self.next_checkpoint,
+            self.next_log_archive,
+            self.next_coalesce,
+            self.next_scrub,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(self.next_merge);
+        Ok(next.saturating_duration_since(utils::instant()))
+    }
+
+    /// Tick the driver on the current thread until the storage is closed.
+    pub(super) fn run(mut self) {
+        while let Ok(delay) = self.tick() {
+            // Wake up regularly so the thread stops soon after the storage is closed
+            std::thread::sleep(delay.min(SHUTDOWN_POLL_INTERVAL));
+        }
+        info!("stopping maintenance thread");
+    }
+}
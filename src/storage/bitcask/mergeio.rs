@@ -0,0 +1,267 @@
+//! Writers for the data files that are written by merges. Merges copy a lot of data that is rarely
+//! read right away, so the written pages can be kept out of the page cache instead of evicting the
+//! pages that readers use.
+
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{config::MergeIo, log};
+
+/// A writer of a merge data file. The file is only complete once `finish` returns.
+#[derive(Debug)]
+pub(super) enum MergeFileWriter {
+    /// Writes through the page cache.
+    Cached(BufWriter<fs::File>),
+    /// Writes through the page cache and drops the file from it when finished.
+    DontNeed(BufWriter<fs::File>),
+    /// Writes around the page cache with `O_DIRECT`.
+    #[cfg(target_os = "linux")]
+    Direct(direct::DirectWriter),
+}
+
+impl MergeFileWriter {
+    /// Create a new merge data file at the given path.
+    pub(super) fn create<P>(path: P, merge_io: MergeIo) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = log::create(path)?;
+        match merge_io {
+            MergeIo::Cached => Ok(Self::Cached(BufWriter::new(file))),
+            MergeIo::DontNeed => Ok(Self::DontNeed(BufWriter::new(file))),
+            #[cfg(target_os = "linux")]
+            MergeIo::Direct => match direct::enable(&file) {
+                Ok(()) => Ok(Self::Direct(direct::DirectWriter::new(file))),
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                    tracing::warn!(
+                        cause=?e,
+                        "direct I/O is unsupported, writing merge files through the page cache"
+                    );
+                    Ok(Self::DontNeed(BufWriter::new(file)))
+                }
+                Err(e) => Err(e),
+            },
+            #[cfg(not(target_os = "linux"))]
+            MergeIo::Direct => Ok(Self::DontNeed(BufWriter::new(file))),
+        }
+    }
+
+    /// Write all buffered data to the file, and drop the file from the page cache if needed.
+    pub(super) fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Cached(mut writer) => writer.flush(),
+            Self::DontNeed(writer) => {
+                let file = writer.into_inner().map_err(|e| e.into_error())?;
+                drop_cache(&file)
+            }
+            #[cfg(target_os = "linux")]
+            Self::Direct(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for MergeFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Cached(writer) | Self::DontNeed(writer) => writer.write(buf),
+            #[cfg(target_os = "linux")]
+            Self::Direct(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Cached(writer) | Self::DontNeed(writer) => writer.flush(),
+            #[cfg(target_os = "linux")]
+            Self::Direct(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Write the dirty pages of the file to disk and drop all of its pages from the page cache.
+#[cfg(target_os = "linux")]
+fn drop_cache(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Only clean pages are dropped, so the data is written first
+    file.sync_data()?;
+    // SAFETY: The file descriptor is valid while `file` is borrowed.
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+/// Dropping pages from the page cache is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+fn drop_cache(_file: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod direct {
+    use std::{
+        fs,
+        io::{self, Write},
+        os::unix::io::AsRawFd,
+    };
+
+    /// The alignment of the buffers, the file offsets, and the lengths of direct writes, which is
+    /// the logical block size of most devices.
+    const BLOCK_SIZE: usize = 4096;
+
+    /// The number of blocks that are buffered before they are written.
+    const BUFFERED_BLOCKS: usize = 256;
+
+    #[derive(Clone)]
+    #[repr(C, align(4096))]
+    struct Block([u8; BLOCK_SIZE]);
+
+    /// Turn on `O_DIRECT` for the file. This fails with `EINVAL` if the file system doesn't
+    /// support direct I/O.
+    pub(super) fn enable(file: &fs::File) -> io::Result<()> {
+        let fd = file.as_raw_fd();
+        // SAFETY: The file descriptor is valid while `file` is borrowed.
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_DIRECT) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// A writer that buffers data in aligned blocks and only writes whole blocks to a file that
+    /// is opened with `O_DIRECT`. The last block is padded with zeros, and the padding is cut off
+    /// when the writer is finished.
+    pub(in super::super) struct DirectWriter {
+        file: fs::File,
+        blocks: Vec<Block>,
+        /// The number of buffered bytes.
+        buffered: usize,
+        /// The number of bytes that have been written to the file.
+        written: u64,
+    }
+
+    impl std::fmt::Debug for DirectWriter {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("DirectWriter")
+                .field("file", &self.file)
+                .field("buffered", &self.buffered)
+                .field("written", &self.written)
+                .finish()
+        }
+    }
+
+    impl DirectWriter {
+        /// Create a writer for an empty file that has `O_DIRECT` turned on.
+        pub(super) fn new(file: fs::File) -> Self {
+            Self {
+                file,
+                blocks: vec![Block([0; BLOCK_SIZE]); BUFFERED_BLOCKS],
+                buffered: 0,
+                written: 0,
+            }
+        }
+
+        /// Write the buffered data, including the last partial block, and cut the padding off the
+        /// end of the file.
+        pub(super) fn finish(mut self) -> io::Result<()> {
+            let len = self.buffered;
+            let padded = len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            let buf = as_bytes_mut(&mut self.blocks);
+            buf[len..padded].fill(0);
+            self.file.write_all(&buf[..padded])?;
+            self.file.set_len(self.written + len as u64)
+        }
+
+        /// Write the whole blocks that are buffered and move the remaining bytes to the front.
+        fn write_blocks(&mut self) -> io::Result<()> {
+            let whole = self.buffered / BLOCK_SIZE * BLOCK_SIZE;
+            if whole == 0 {
+                return Ok(());
+            }
+            let buffered = self.buffered;
+            let buf = as_bytes_mut(&mut self.blocks);
+            self.file.write_all(&buf[..whole])?;
+            buf.copy_within(whole..buffered, 0);
+            self.buffered -= whole;
+            self.written += whole as u64;
+            Ok(())
+        }
+
+        fn buf_mut(&mut self) -> &mut [u8] {
+            as_bytes_mut(&mut self.blocks)
+        }
+    }
+
+    impl Write for DirectWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            let buffered = self.buffered;
+            let buf = self.buf_mut();
+            let n = data.len().min(buf.len() - buffered);
+            buf[buffered..buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            if self.buffered == BLOCK_SIZE * BUFFERED_BLOCKS {
+                self.write_blocks()?;
+            }
+            Ok(n)
+        }
+
+        /// Write the whole blocks that are buffered. The last partial block is only written when
+        /// the writer is finished.
+        fn flush(&mut self) -> io::Result<()> {
+            self.write_blocks()
+        }
+    }
+
+    fn as_bytes_mut(blocks: &mut [Block]) -> &mut [u8] {
+        // SAFETY: `Block` is a plain byte array without padding, so the blocks are contiguous
+        // bytes that live as long as the borrow.
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                blocks.as_mut_ptr().cast::<u8>(),
+                blocks.len() * BLOCK_SIZE,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn merge_files_hold_written_data_with_each_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        // Cross block boundaries and the buffer size
+        let data: Vec<u8> = (0..2 * 1024 * 1024 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        for (i, merge_io) in [MergeIo::Cached, MergeIo::DontNeed, MergeIo::Direct]
+            .into_iter()
+            .enumerate()
+        {
+            let path = dir.path().join(format!("{i}.data"));
+            let mut writer = MergeFileWriter::create(&path, merge_io).unwrap();
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.flush().unwrap();
+            writer.finish().unwrap();
+
+            let mut written = Vec::new();
+            fs::File::open(&path)
+                .unwrap()
+                .read_to_end(&mut written)
+                .unwrap();
+            assert_eq!(data, written);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, MutexGuard};
+
+use super::{keydir::KeyDirStats, Error, Handle};
+
+/// The number of histogram buckets. Bucket `i` counts durations below `2^i` microseconds, and the
+/// last bucket counts everything else, so the buckets cover durations up to about 1 second.
+const BUCKETS: usize = 21;
+
+/// Counters and histograms of the evictions and of the contention on the readers queue and the
+/// writer lock.
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    /// Number of keys that were deleted to keep the storage within its quotas.
+    pub(super) evicted_keys: AtomicU64,
+    /// Number of writes that were rejected because too many writes were waiting for the writer.
+    pub(super) busy_writes: AtomicU64,
+    /// Number of writes that replaced a buffered value, so they never reached the data files.
+    pub(super) coalesced_writes: AtomicU64,
+    /// Number of bytes that scrubs read from the data files and the hint files.
+    pub(super) scrubbed_bytes: AtomicU64,
+    /// Number of corrupt data files that scrubs found.
+    pub(super) corrupt_files: AtomicU64,
+    /// Number of corrupt data files that scrubs repaired from the log archive.
+    pub(super) repaired_files: AtomicU64,
+    /// Number of reads that had to wait for a reader to become available.
+    pub(super) reader_waits: AtomicU64,
+    /// Number of reads that were cross-checked against their data file entries.
+    pub(super) verified_reads: AtomicU64,
+    /// Number of cross-checked reads whose KeyDir entry didn't match the data file entry.
+    pub(super) failed_verifications: AtomicU64,
+    /// Number of cross-checked reads whose KeyDir entry was repaired from the data files.
+    pub(super) repaired_reads: AtomicU64,
+    /// Number of reads that were retried after a transient error or a race with a merge.
+    pub(super) read_retries: AtomicU64,
+    /// Number of retried reads that failed again.
+    pub(super) failed_read_retries: AtomicU64,
+    /// Time spent waiting for a reader, recorded only for reads that had to wait.
+    pub(super) reader_wait_time: Histogram,
+    /// Time spent waiting for the writer lock.
+    pub(super) writer_wait_time: Histogram,
+    /// Time the writer lock was held for.
+    pub(super) writer_hold_time: Histogram,
+}
+
+impl Metrics {
+    /// Lock the writer, recording the time spent waiting for it and the time it's held for.
+    pub(super) fn lock<'a, T>(&'a self, mutex: &'a Mutex<T>) -> TimedGuard<'a, T> {
+        let start = Instant::now();
+        let guard = mutex.lock();
+        let acquired = Instant::now();
+        self.writer_wait_time.record(acquired - start);
+        TimedGuard {
+            guard,
+            acquired,
+            hold_time: &self.writer_hold_time,
+        }
+    }
+}
+
+/// A mutex guard that records the time it's held for when dropped.
+pub(super) struct TimedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    acquired: Instant,
+    hold_time: &'a Histogram,
+}
+
+impl<T> Deref for TimedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TimedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for TimedGuard<'_, T> {
+    fn drop(&mut self) {
+        self.hold_time.record(self.acquired.elapsed());
+    }
+}
+
+/// A histogram of durations with exponentially sized buckets.
+#[derive(Debug, Default)]
+pub(super) struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    /// Add a duration to the histogram.
+    pub(super) fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Take a copy of the histogram's current state.
+    pub(super) fn snapshot(&self) -> HistogramSnapshot {
+        let mut buckets = Vec::with_capacity(BUCKETS);
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            let le = (i < BUCKETS - 1).then(|| Duration::from_micros(1 << i));
+            buckets.push((le, bucket.load(Ordering::Relaxed)));
+        }
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// A copy of the state of a histogram of durations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// The number of recorded durations.
+    pub count: u64,
+    /// The sum of the recorded durations.
+    pub sum: Duration,
+    /// The number of recorded durations in each bucket, paired with the bucket's exclusive upper
+    /// bound. The last bucket has no upper bound.
+    pub buckets: Vec<(Option<Duration>, u64)>,
+}
+
+/// The statistics of a storage's usage and of its contention on its readers and its writer, used
+/// for choosing quotas and the number of concurrent readers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of keys, including the ones that have expired but haven't been deleted yet.
+    pub live_keys: u64,
+    /// The number of bytes occupied by the entries of the keys in the data files.
+    pub live_bytes: u64,
+    /// The number of keys that were deleted to keep the storage within its quotas.
+    pub evicted_keys: u64,
+    /// The number of readers.
+    pub readers: usize,
+    /// The number of readers that are currently in use.
+    pub readers_in_use: usize,
+    /// The number of data files kept open by the readers caches.
+    pub open_files: usize,
+    /// The number of writes that are waiting for the writer.
+    pub pending_writes: usize,
+    /// The number of writes that were rejected because too many writes were waiting for the
+    /// writer.
+    pub busy_writes: u64,
+    /// The number of writes that were coalesced with a later write of the same key, so they
+    /// never reached the data files.
+    pub coalesced_writes: u64,
+    /// The number of bytes that scrubs read from the data files and the hint files.
+    pub scrubbed_bytes: u64,
+    /// The number of corrupt data files that scrubs found, including the repaired ones.
+    pub corrupt_files: u64,
+    /// The number of corrupt data files that scrubs repaired from the log archive.
+    pub repaired_files: u64,
+    /// The number of reads that had to wait for a reader to become available.
+    pub reader_waits: u64,
+    /// The time spent waiting for a reader by the reads that had to wait.
+    pub reader_wait_time: HistogramSnapshot,
+    /// The number of reads that were cross-checked against their data file entries.
+    pub verified_reads: u64,
+    /// The number of cross-checked reads whose KeyDir entry didn't match the data file entry.
+    pub failed_verifications: u64,
+    /// The number of cross-checked reads whose KeyDir entry was repaired from the data files.
+    pub repaired_reads: u64,
+    /// The number of reads that were retried after a transient error or a race with a merge.
+    pub read_retries: u64,
+    /// The number of retried reads that failed again.
+    pub failed_read_retries: u64,
+    /// The time spent waiting for the writer lock.
+    pub writer_wait_time: HistogramSnapshot,
+    /// The time the writer lock was held for.
+    pub writer_hold_time: HistogramSnapshot,
+    /// The hit rate and the memory usage of the KeyDir, when it spills entries to disk.
+    pub keydir: Option<KeyDirStats>,
+    /// The number of data files that were moved to the cold store.
+    pub cold_files: usize,
+    /// The number of reads that fetched their entries from the cold store.
+    pub cold_fetches: u64,
+    /// The number of reads of cold files that were served from the cache of fetched entries.
+    pub cold_cache_hits: u64,
+}
+
+/// Snapshots of a storage's statistics that are taken periodically, for feeding dashboards and
+/// telemetry pipelines without running a timer task of one's own.
+#[derive(Debug)]
+pub struct StatsStream {
+    handle: Handle,
+    interval: tokio::time::Interval,
+}
+
+impl StatsStream {
+    pub(super) fn new(handle: Handle, period: Duration) -> Self {
+        let mut interval = tokio::time::interval(period);
+        // A consumer that falls behind gets the current statistics, not a burst of stale ones
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Self { handle, interval }
+    }
+
+    /// Wait for the next snapshot. The first snapshot is taken right away.
+    ///
+    /// # Error
+    ///
+    /// Returns [`Error::Closed`] once the storage was closed.
+    pub async fn recv(&mut self) -> Result<Stats, Error> {
+        self.interval.tick().await;
+        if self.handle.ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        Ok(self.handle.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_exponential() {
+        let histogram = Histogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_secs(10));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(3, snapshot.count);
+        assert_eq!((Some(Duration::from_micros(1)), 1), snapshot.buckets[0]);
+        assert_eq!((Some(Duration::from_micros(4)), 1), snapshot.buckets[2]);
+        assert_eq!((None, 1), snapshot.buckets[BUCKETS - 1]);
+    }
+}
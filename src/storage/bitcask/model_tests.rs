@@ -0,0 +1,462 @@
+//! Model-based concurrency tests for the interactions between the writer, the readers, and
+//! merges. The model mirrors the steps that [`Writer`](super::writer::Writer) and
+//! [`Reader`](super::reader::Reader) take on the shared states, i.e. the KeyDir, the data files,
+//! the references that the readers' caches hold to the files, and the merge epoch, using the
+//! primitives from `shuttle` so its scheduler can explore their interleavings. The model must be
+//! kept in sync with these steps.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Weak},
+};
+
+use shuttle::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    thread,
+};
+
+/// The number of entries after which the active file is rotated.
+const MAX_FILE_ENTRIES: usize = 2;
+
+/// The location of a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    fileid: u64,
+    pos: usize,
+}
+
+/// A data file. Handles that were opened before the file is removed can still read it, like a
+/// memory map on Unix.
+type File = Arc<Mutex<Vec<u64>>>;
+
+/// The error of reading from a data file that was removed.
+#[derive(Debug, PartialEq, Eq)]
+struct NotFound;
+
+/// The data files that the readers' caches have open, like [`FileRefs`](super::log::FileRefs).
+#[derive(Default)]
+struct FileRefs {
+    files: Mutex<HashMap<u64, Weak<FileRef>>>,
+}
+
+/// A data file that is open by at least one reader.
+struct FileRef {
+    fileid: u64,
+    storage: Weak<Storage>,
+    /// Whether the file was removed, so the readers should close it.
+    retired: AtomicBool,
+    /// Whether the file was renamed to be removed when the last reader closes it.
+    deferred: AtomicBool,
+}
+
+impl Drop for FileRef {
+    fn drop(&mut self) {
+        let Some(storage) = self.storage.upgrade() else {
+            return;
+        };
+        {
+            let mut files = storage.file_refs.files.lock().unwrap();
+            if files
+                .get(&self.fileid)
+                .is_some_and(|file| file.strong_count() == 0)
+            {
+                files.remove(&self.fileid);
+            }
+        }
+        if self.deferred.load(Ordering::Acquire) {
+            storage.deleted.lock().unwrap().remove(&self.fileid);
+        }
+    }
+}
+
+struct Storage {
+    keydir: RwLock<HashMap<u8, Entry>>,
+    /// The data files that can be opened by their names.
+    files: Mutex<BTreeMap<u64, File>>,
+    /// The data files that were renamed to be removed later.
+    deleted: Mutex<BTreeSet<u64>>,
+    file_refs: FileRefs,
+    /// The number of merges that have removed data files.
+    merge_epoch: AtomicU64,
+    writer: Mutex<u64>,
+}
+
+impl Storage {
+    fn new() -> Self {
+        Self {
+            keydir: RwLock::new(HashMap::new()),
+            files: Mutex::new(BTreeMap::from([(0, File::default())])),
+            deleted: Mutex::new(BTreeSet::new()),
+            file_refs: FileRefs::default(),
+            merge_epoch: AtomicU64::new(0),
+            writer: Mutex::new(0),
+        }
+    }
+
+    /// Append the value to the active file, then point the KeyDir to it.
+    fn put(&self, key: u8, value: u64) {
+        let mut active_fileid = self.writer.lock().unwrap();
+        let file = Arc::clone(&self.files.lock().unwrap()[&*active_fileid]);
+        let pos = {
+            let mut data = file.lock().unwrap();
+            data.push(value);
+            data.len() - 1
+        };
+        self.keydir.write().unwrap().insert(
+            key,
+            Entry {
+                fileid: *active_fileid,
+                pos,
+            },
+        );
+        if pos + 1 >= MAX_FILE_ENTRIES {
+            *active_fileid += 1;
+            self.files
+                .lock()
+                .unwrap()
+                .insert(*active_fileid, File::default());
+        }
+    }
+
+    /// Copy the live values of every file into a new merge file, point the KeyDir to the merge
+    /// file, remove the merged files, and then tell the readers about the merge.
+    fn merge(&self) {
+        let mut active_fileid = self.writer.lock().unwrap();
+        let fileids: Vec<u64> = self.files.lock().unwrap().keys().copied().collect();
+        let merge_fileid = *active_fileid + 1;
+        let merge_file = File::default();
+
+        let entries: Vec<(u8, Entry)> = self
+            .keydir
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(k, e)| (*k, *e))
+            .collect();
+        let mut new_entries = Vec::new();
+        for (key, entry) in entries {
+            let file = Arc::clone(&self.files.lock().unwrap()[&entry.fileid]);
+            let value = file.lock().unwrap()[entry.pos];
+            let mut data = merge_file.lock().unwrap();
+            data.push(value);
+            let pos = data.len() - 1;
+            new_entries.push((
+                key,
+                Entry {
+                    fileid: merge_fileid,
+                    pos,
+                },
+            ));
+        }
+        self.files.lock().unwrap().insert(merge_fileid, merge_file);
+
+        for (key, entry) in new_entries {
+            self.keydir.write().unwrap().insert(key, entry);
+        }
+        for fileid in fileids {
+            self.remove(fileid);
+        }
+        self.merge_epoch.fetch_add(1, Ordering::Release);
+        self.remove_deleted_files();
+
+        *active_fileid = merge_fileid + 1;
+        self.files
+            .lock()
+            .unwrap()
+            .insert(*active_fileid, File::default());
+    }
+
+    /// Remove a data file, or rename it to be removed once the last reader closes it if readers
+    /// still have it open.
+    fn remove(&self, fileid: u64) {
+        let files = self.file_refs.files.lock().unwrap();
+        let file = files.get(&fileid).and_then(Weak::upgrade);
+        self.files.lock().unwrap().remove(&fileid);
+        if let Some(file) = &file {
+            file.retired.store(true, Ordering::Release);
+            self.deleted.lock().unwrap().insert(fileid);
+            file.deferred.store(true, Ordering::Release);
+        }
+        // The reference may be the last one, whose drop takes the lock
+        drop(files);
+        drop(file);
+    }
+
+    /// Remove the renamed files that no reader has open anymore.
+    fn remove_deleted_files(&self) {
+        let files: Vec<_> = self
+            .file_refs
+            .files
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        let held: BTreeSet<_> = files
+            .iter()
+            .filter(|file| file.deferred.load(Ordering::Acquire))
+            .map(|file| file.fileid)
+            .collect();
+        drop(files);
+        self.deleted
+            .lock()
+            .unwrap()
+            .retain(|fileid| held.contains(fileid));
+    }
+}
+
+type BeforeOpen = Box<dyn FnMut(&Storage) + Send>;
+
+struct Reader {
+    storage: Arc<Storage>,
+    /// The opened files together with the references that keep them from being removed.
+    cache: HashMap<u64, (File, Arc<FileRef>)>,
+    /// The merge epoch when the cache was last cleaned up.
+    merge_epoch: u64,
+    /// Whether to look up the KeyDir again when the file of an entry was removed.
+    retry: bool,
+    /// Runs right before every attempt to open the file of an entry, so a test can pin down
+    /// where a merge lands within a read.
+    before_open: Option<BeforeOpen>,
+}
+
+impl Reader {
+    fn new(storage: Arc<Storage>) -> Self {
+        let merge_epoch = storage.merge_epoch.load(Ordering::Acquire);
+        Self {
+            storage,
+            cache: HashMap::new(),
+            merge_epoch,
+            retry: true,
+            before_open: None,
+        }
+    }
+
+    /// Read the value of a key, looking up the KeyDir once more if the file of its entry was
+    /// removed in the meantime.
+    fn get(&mut self, key: u8) -> Result<Option<u64>, NotFound> {
+        let mut entry = match self.lookup(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.release_merged();
+        let mut retried = false;
+        loop {
+            if let Some(before_open) = &mut self.before_open {
+                before_open(&self.storage);
+            }
+            if let Some(file) = self.open(entry.fileid) {
+                let value = file.lock().unwrap()[entry.pos];
+                return Ok(Some(value));
+            }
+            if retried || !self.retry {
+                return Err(NotFound);
+            }
+            retried = true;
+            self.cache.remove(&entry.fileid);
+            entry = match self.lookup(key) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    fn lookup(&self, key: u8) -> Option<Entry> {
+        self.storage.keydir.read().unwrap().get(&key).copied()
+    }
+
+    /// Close the cached files that merges have removed since the last call.
+    fn release_merged(&mut self) {
+        let epoch = self.storage.merge_epoch.load(Ordering::Acquire);
+        if self.merge_epoch != epoch {
+            self.merge_epoch = epoch;
+            self.cache
+                .retain(|_, (_, file_ref)| !file_ref.retired.load(Ordering::Acquire));
+        }
+    }
+
+    fn open(&mut self, fileid: u64) -> Option<File> {
+        if let Some((file, _)) = self.cache.get(&fileid) {
+            return Some(Arc::clone(file));
+        }
+        // The reference is taken before the file is opened, so a merge that removes the file in
+        // between either sees the reference or makes the open fail
+        let file_ref = self.acquire(fileid);
+        let file = Arc::clone(self.storage.files.lock().unwrap().get(&fileid)?);
+        self.cache.insert(fileid, (Arc::clone(&file), file_ref));
+        Some(file)
+    }
+
+    fn acquire(&self, fileid: u64) -> Arc<FileRef> {
+        let mut files = self.storage.file_refs.files.lock().unwrap();
+        if let Some(file) = files.get(&fileid).and_then(Weak::upgrade) {
+            return file;
+        }
+        let file = Arc::new(FileRef {
+            fileid,
+            storage: Arc::downgrade(&self.storage),
+            retired: AtomicBool::new(false),
+            deferred: AtomicBool::new(false),
+        });
+        files.insert(fileid, Arc::downgrade(&file));
+        file
+    }
+}
+
+/// A writer that updates a key around a merge, and two readers that read the key twice.
+fn writer_and_readers_with_a_merge(retry: bool) {
+    let storage = Arc::new(Storage::new());
+    storage.put(0, 0);
+
+    let writer = {
+        let storage = Arc::clone(&storage);
+        thread::spawn(move || {
+            storage.put(0, 1);
+            storage.merge();
+            storage.put(0, 2);
+        })
+    };
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let mut reader = Reader::new(Arc::clone(&storage));
+            reader.retry = retry;
+            thread::spawn(move || {
+                let first = reader.get(0).unwrap().unwrap();
+                let second = reader.get(0).unwrap().unwrap();
+                // A reader never goes back to an older value
+                assert!(first <= second, "read {first} after {second}");
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    let mut reader = Reader::new(Arc::clone(&storage));
+    assert_eq!(Ok(Some(2)), reader.get(0));
+    // The files that the readers had open were removed when they were dropped
+    drop(reader);
+    assert!(storage.deleted.lock().unwrap().is_empty());
+}
+
+#[test]
+fn readers_see_the_latest_values_during_merges() {
+    shuttle::check_random(|| writer_and_readers_with_a_merge(true), 10000);
+}
+
+#[test]
+fn readers_see_the_latest_values_during_merges_exhaustive() {
+    shuttle::check_dfs(|| writer_and_readers_with_a_merge(true), Some(100000));
+}
+
+/// Read a key while the given number of merges land between looking up its entry and opening
+/// the entry's file, one before each attempt.
+fn read_with_merges_before_opens(retry: bool, merges: usize) -> Result<Option<u64>, NotFound> {
+    let storage = Arc::new(Storage::new());
+    storage.put(0, 0);
+    let mut reader = Reader::new(Arc::clone(&storage));
+    reader.retry = retry;
+    let mut merges = merges;
+    reader.before_open = Some(Box::new(move |storage| {
+        if merges > 0 {
+            merges -= 1;
+            storage.merge();
+        }
+    }));
+    reader.get(0)
+}
+
+#[test]
+fn reader_without_retry_misses_the_merged_file() {
+    shuttle::check_dfs(
+        || assert_eq!(Err(NotFound), read_with_merges_before_opens(false, 1)),
+        None,
+    );
+}
+
+#[test]
+fn reader_retries_once() {
+    shuttle::check_dfs(
+        || {
+            assert_eq!(Ok(Some(0)), read_with_merges_before_opens(true, 1));
+            // A second merge removes the file of the entry that was looked up again, and the
+            // read gives up instead of retrying forever
+            assert_eq!(Err(NotFound), read_with_merges_before_opens(true, 2));
+        },
+        None,
+    );
+}
+
+#[test]
+fn reader_cache_keeps_removed_files_readable() {
+    shuttle::check_random(
+        || {
+            let storage = Arc::new(Storage::new());
+            storage.put(0, 0);
+            storage.put(1, 1);
+            let mut reader = Reader::new(Arc::clone(&storage));
+            // Cache the file before merging
+            assert_eq!(Ok(Some(0)), reader.get(0));
+
+            let merger = {
+                let storage = Arc::clone(&storage);
+                thread::spawn(move || storage.merge())
+            };
+            let handle = thread::spawn(move || {
+                // The entry may still point to the removed file, which is read through the cache
+                let entry = reader.lookup(1).unwrap();
+                let file = reader.open(entry.fileid).unwrap();
+                assert_eq!(1, file.lock().unwrap()[entry.pos]);
+                reader
+            });
+            merger.join().unwrap();
+            let mut reader = handle.join().unwrap();
+
+            // The removed file is kept until the reader closes it after the merge
+            assert_eq!(Ok(Some(1)), reader.get(1));
+            assert!(storage.deleted.lock().unwrap().is_empty());
+            assert!(reader.cache.keys().all(|fileid| *fileid > 1));
+        },
+        1000,
+    );
+}
+
+#[test]
+fn merges_keep_the_files_that_readers_have_open() {
+    shuttle::check_dfs(
+        || {
+            let storage = Arc::new(Storage::new());
+            storage.put(0, 0);
+            storage.put(0, 1);
+            let mut reader = Reader::new(Arc::clone(&storage));
+            assert_eq!(Ok(Some(1)), reader.get(0));
+
+            // Another merge must not remove the file that the idle reader still has open
+            let merger = {
+                let storage = Arc::clone(&storage);
+                thread::spawn(move || {
+                    storage.merge();
+                    storage.merge();
+                })
+            };
+            merger.join().unwrap();
+            assert_eq!(
+                Some(&0),
+                storage.deleted.lock().unwrap().iter().next(),
+                "the open file was removed"
+            );
+            let file = Arc::clone(&reader.cache[&0].0);
+            assert_eq!(1, file.lock().unwrap()[1]);
+
+            // The reader closes it once it's used again
+            reader.release_merged();
+            assert!(storage.deleted.lock().unwrap().is_empty());
+        },
+        Some(100000),
+    );
+}
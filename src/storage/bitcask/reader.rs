@@ -1,8 +1,22 @@
-use std::{cell::RefCell, sync::Arc};
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    sync::{atomic::Ordering, Arc},
+};
 
 use bytes::Bytes;
+use tracing::error;
 
-use super::{log::LogDir, Context, DataFileEntry, Error};
+use super::{
+    entry::{DataFileEntry, DataFileValue},
+    keydir::{KeyDir, KeyDirEntry},
+    log::LogDir,
+    utils, Context, Error,
+};
+
+/// The reason of the corruption that a verified read returns when the KeyDir entry of the key
+/// points to another data file entry.
+pub(super) const KEYDIR_MISMATCH: &str = "KeyDir entry does not match its data file entry";
 
 /// The reader reads log entries from data files given the locations found in KeyDir. Since data files
 /// are immutable (except for the active one), we can safely read them concurrently without any extra
@@ -14,38 +28,185 @@ pub(super) struct Reader {
 
     /// The cache of file descriptors for reading the data files.
     readers: RefCell<LogDir>,
+
+    /// The merge epoch of the context when the cache was last cleaned up.
+    merge_epoch: Cell<u64>,
 }
 
 impl Reader {
     /// Create a new `Reader` for reading Bitcask states.
     pub(super) fn new(ctx: Arc<Context>, readers: RefCell<LogDir>) -> Self {
-        Self { ctx, readers }
+        let merge_epoch = Cell::new(ctx.merge_epoch());
+        Self {
+            ctx,
+            readers,
+            merge_epoch,
+        }
     }
 
-    /// Get the value of a key and return it, if it exists, otherwise return return `None`.
+    /// Get the value of a key and return it, if it exists and has not expired, otherwise return
+    /// return `None`.
     ///
     /// # Error
     ///
     /// Errors from I/O operations and serializations/deserializations will be propagated.
     #[tracing::instrument(level = "debug", skip(self))]
     pub(super) fn get(&self, key: Bytes) -> Result<Option<Bytes>, Error> {
-        match self.ctx.get_keydir().get(&key) {
-            Some(keydir_entry) => {
+        let now = utils::timestamp();
+        // The buffer is looked up before the KeyDir. The writer points the KeyDir to a buffered
+        // value before it removes the value from the buffer, so the value is found in either.
+        let buffered = self.ctx.get_write_buffer().get(&key);
+        let mut keydir_entry = match self.ctx.get_keydir().get(&key) {
+            Some(keydir_entry) if !keydir_entry.is_expired(now) => keydir_entry,
+            _ => return Ok(None),
+        };
+        self.ctx.record_read(&key, now);
+        if buffered.is_some() {
+            return Ok(buffered);
+        }
+        self.release_merged();
+        let verify = self.ctx.should_verify_read();
+        let mut retried = false;
+        loop {
+            let result = if verify {
+                self.read_verified(&key, &keydir_entry)
+            } else {
                 // SAFETY: We have taken `keydir_entry` from KeyDir which is ensured to point to
                 // valid data file positions. Thus we can be confident that the Mmap won't be
                 // mapped to an invalid segment.
-                let datafile_entry = unsafe {
-                    self.readers.borrow_mut().read::<DataFileEntry, _>(
+                unsafe {
+                    self.readers.borrow_mut().read::<DataFileValue, _>(
                         &self.ctx.get_conf().path,
-                        keydir_entry.value().fileid,
-                        keydir_entry.value().len,
-                        keydir_entry.value().pos,
-                    )?
-                };
+                        keydir_entry.fileid(),
+                        keydir_entry.len(),
+                        keydir_entry.pos(),
+                    )
+                }
+                .map(|datafile_value| datafile_value.0)
+            };
+            let err = match result {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let metrics = self.ctx.get_metrics();
+            if retried {
+                metrics.failed_read_retries.fetch_add(1, Ordering::Relaxed);
+                return Err(err);
+            }
+            // A merge removed the data file after we looked up the entry, or the read hit an
+            // error that may go away on its own. The merge updates KeyDir before removing any
+            // file, so the entry that we look up again points to the merged file. The cached
+            // reader of the file is closed, so the file is opened again if it's still there.
+            let removed = matches!(&err, Error::Io(e) if e.kind() == io::ErrorKind::NotFound);
+            if !removed && !err.is_retryable() {
+                return Err(err);
+            }
+            retried = true;
+            metrics.read_retries.fetch_add(1, Ordering::Relaxed);
+            self.readers.borrow_mut().release(keydir_entry.fileid());
+            if let Some(buffered) = self.ctx.get_write_buffer().get(&key) {
+                return Ok(Some(buffered));
+            }
+            keydir_entry = match self.ctx.get_keydir().get(&key) {
+                Some(entry) if !entry.is_expired(now) => entry,
+                _ => return Ok(None),
+            };
+        }
+    }
 
-                Ok(datafile_entry.value)
+    /// Load the data file segments that hold the values of the given keys into the page cache,
+    /// and return the number of values that were prefetched. Keys that don't exist or have expired
+    /// are skipped.
+    ///
+    /// # Error
+    ///
+    /// Errors from I/O operations will be propagated.
+    pub(super) fn prefetch(&self, keys: &[Bytes]) -> Result<usize, Error> {
+        let now = utils::timestamp();
+        let mut entries: Vec<_> = keys
+            .iter()
+            .filter_map(|key| self.ctx.get_keydir().get(key))
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| (entry.fileid(), entry.pos(), entry.len()))
+            .collect();
+        // Visit the files in order so the readahead of each file is requested at once
+        entries.sort_unstable();
+        self.release_merged();
+        let mut readers = self.readers.borrow_mut();
+        let mut prefetched = 0;
+        for (fileid, pos, len) in entries {
+            // SAFETY: The positions are taken from KeyDir, which points to valid data file
+            // positions.
+            match unsafe { readers.prefetch(&self.ctx.get_conf().path, fileid, len, pos) } {
+                Ok(()) => prefetched += 1,
+                // A merge removed the data file after we looked up the entry, which is fine since
+                // prefetching is only a hint
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::Io(e)),
             }
-            None => Ok(None),
+        }
+        Ok(prefetched)
+    }
+
+    /// Close the cached readers of the data files that were removed by merges since the last
+    /// call. Merges are rare, so checking the epoch keeps this cheap on every read.
+    pub(super) fn release_merged(&self) {
+        let epoch = self.ctx.merge_epoch();
+        if self.merge_epoch.replace(epoch) != epoch {
+            self.readers.borrow_mut().release_retired();
         }
     }
+
+    /// Read the whole data file entry that the KeyDir entry points to, and check that it's the
+    /// entry of the key that was indexed. A mismatch means the KeyDir is corrupted, so the read
+    /// fails with [`KEYDIR_MISMATCH`] rather than returning the value of another entry, and the
+    /// handle repairs the KeyDir entry.
+    fn read_verified(
+        &self,
+        key: &Bytes,
+        keydir_entry: &KeyDirEntry,
+    ) -> Result<Option<Bytes>, Error> {
+        // SAFETY: We have taken `keydir_entry` from KeyDir which is ensured to point to valid
+        // data file positions.
+        let datafile_entry = unsafe {
+            self.readers.borrow_mut().read::<DataFileEntry, _>(
+                &self.ctx.get_conf().path,
+                keydir_entry.fileid(),
+                keydir_entry.len(),
+                keydir_entry.pos(),
+            )?
+        };
+        let metrics = self.ctx.get_metrics();
+        metrics.verified_reads.fetch_add(1, Ordering::Relaxed);
+        if entry_matches(key, keydir_entry, &datafile_entry) {
+            return Ok(datafile_entry.value);
+        }
+        metrics.failed_verifications.fetch_add(1, Ordering::Relaxed);
+        error!(
+            ?key,
+            found_key = ?datafile_entry.key,
+            expected_tstamp = keydir_entry.tstamp(),
+            found_tstamp = datafile_entry.tstamp,
+            fileid = keydir_entry.fileid(),
+            pos = keydir_entry.pos(),
+            "KeyDir entry does not match its data file entry"
+        );
+        let conf = self.ctx.get_conf();
+        Err(Error::corrupted_at(
+            KEYDIR_MISMATCH,
+            utils::datafile_name(&conf.path, conf.layout(), keydir_entry.fileid()),
+            Some(keydir_entry.pos()),
+        ))
+    }
+}
+
+/// Check that the data file entry is the entry of the key that the KeyDir entry was made for.
+pub(super) fn entry_matches(
+    key: &Bytes,
+    keydir_entry: &KeyDirEntry,
+    datafile_entry: &DataFileEntry,
+) -> bool {
+    datafile_entry.key == *key
+        && datafile_entry.tstamp == keydir_entry.tstamp()
+        && datafile_entry.value.is_some()
 }
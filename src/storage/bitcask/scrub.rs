@@ -0,0 +1,286 @@
+//! A scrubber that slowly reads the data files that are no longer written to, so corruption is
+//! found before a read or a merge runs into it, and repairs the corrupt files from the log
+//! archive.
+//!
+//! A data file is corrupt when its header or one of its entries can't be decoded, when an entry
+//! fails its checksum and isn't just the last write that was cut off by a crash, or when an entry
+//! that the KeyDir points to doesn't hold the timestamp and the length that the KeyDir has for
+//! it. Hint files also carry checksums, and a hint file that fails them is removed, so the KeyDir
+//! is rebuilt from the data file instead the next time the storage is opened.
+//!
+//! A corrupt data file is replaced with its copy in the log archive if the copy passes the same
+//! checks. The archived copy is a hard link to the data file when they're on the same file
+//! system, in which case it's just as corrupt and is never used. Readers that already have the
+//! corrupt file open keep reading it until they close it.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info, warn};
+
+use super::{
+    context::Context,
+    keydir::KeyDir,
+    log::{self, DataFileIterator},
+    logarchive, read_hintfile, utils, Error, Handle,
+};
+
+/// The outcome of scrubbing the data files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// The number of data files that were scrubbed.
+    pub files: u64,
+    /// The number of bytes that were read from the data files and the hint files.
+    pub bytes: u64,
+    /// The IDs of the corrupt data files that couldn't be repaired.
+    pub corrupt_files: Vec<u64>,
+    /// The IDs of the corrupt data files that were replaced with their copies in the log archive.
+    pub repaired_files: Vec<u64>,
+    /// The IDs of the files whose hint files failed their checksums and were removed.
+    pub removed_hint_files: Vec<u64>,
+}
+
+/// Limits the rate at which a scrub reads, by sleeping whenever it's ahead of the limit.
+#[derive(Debug)]
+struct Throttle {
+    bytes_per_sec: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Account for `nbytes` that were read, and wait until the read rate is within the limit.
+    fn consume(&mut self, nbytes: u64) {
+        self.bytes += nbytes;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.start.elapsed();
+        if due > elapsed {
+            std::thread::sleep(due - elapsed);
+        }
+    }
+}
+
+/// What reading a data file found.
+#[derive(Debug)]
+struct Verdict {
+    /// The number of bytes that were read.
+    bytes: u64,
+    /// The reason the file is corrupt, if it is.
+    corruption: Option<&'static str>,
+}
+
+/// Scrub all data files that are no longer written to. The writer is only held to get the ID of
+/// the active file and to replace the repaired files, so the scrub doesn't block the writes while
+/// it reads.
+pub(super) fn scrub(handle: &Handle) -> Result<ScrubReport, Error> {
+    let ctx = &handle.ctx;
+    let conf = ctx.get_conf();
+    let layout = conf.layout();
+    let active_fileid = handle.lock_writer().active_fileid();
+    let fileids: Vec<u64> = utils::sorted_fileids(&conf.path)?
+        .filter(|&fileid| fileid < active_fileid)
+        .collect();
+    info!(files = fileids.len(), "scrubbing data files");
+
+    // The number of keys that the KeyDir points to in each file. Keys only ever move out of the
+    // files that are no longer written to, so a file holds fewer keys than this while it's read.
+    let mut expected_keys: HashMap<u64, u64> = HashMap::new();
+    for (_, keydir_entry) in ctx.get_keydir().iter() {
+        *expected_keys.entry(keydir_entry.fileid()).or_default() += 1;
+    }
+
+    let metrics = ctx.get_metrics();
+    let mut throttle = Throttle::new(conf.scrub_bytes_per_sec.get());
+    let mut report = ScrubReport::default();
+    for fileid in fileids {
+        let datafile = utils::datafile_name(&conf.path, layout, fileid);
+        let expected = expected_keys.get(&fileid).copied().unwrap_or_default();
+        let verdict = match verify(ctx, &datafile, fileid, expected, &mut throttle) {
+            Ok(verdict) => verdict,
+            // The file was removed by a merge
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        report.files += 1;
+        report.bytes += verdict.bytes;
+        metrics
+            .scrubbed_bytes
+            .fetch_add(verdict.bytes, Ordering::Relaxed);
+
+        if let Some(cause) = verdict.corruption {
+            error!(fileid, cause, "found a corrupt data file");
+            metrics.corrupt_files.fetch_add(1, Ordering::Relaxed);
+            if repair(handle, &datafile, fileid, expected, &mut throttle)? {
+                warn!(fileid, "repaired data file from the log archive");
+                metrics.repaired_files.fetch_add(1, Ordering::Relaxed);
+                report.repaired_files.push(fileid);
+            } else {
+                report.corrupt_files.push(fileid);
+            }
+        }
+
+        let hintfile = utils::hintfile_name(&conf.path, layout, fileid);
+        let hintfile_len = match fs::metadata(&hintfile) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match read_hintfile(&conf.path, layout, fileid) {
+            Ok(_) => {}
+            Err(Error::Corruption { reason: cause, .. }) => {
+                error!(fileid, cause, "removing corrupt hint file");
+                utils::remove_file(&hintfile)?;
+                report.removed_hint_files.push(fileid);
+            }
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        report.bytes += hintfile_len;
+        metrics
+            .scrubbed_bytes
+            .fetch_add(hintfile_len, Ordering::Relaxed);
+        throttle.consume(hintfile_len);
+    }
+    info!(?report, "finished scrubbing data files");
+    Ok(report)
+}
+
+/// Read all entries of a data file, and check them against the KeyDir. The file should hold at
+/// least `expected` of the entries that the KeyDir points to, unless keys were moved out of it
+/// since they were counted.
+fn verify(
+    ctx: &Context,
+    datafile: &Path,
+    fileid: u64,
+    expected: u64,
+    throttle: &mut Throttle,
+) -> Result<Verdict, Error> {
+    let mut entries = match DataFileIterator::new(log::open(datafile)?) {
+        Ok(entries) => entries,
+        Err(Error::UnsupportedFormat(_) | Error::Corruption { .. }) => {
+            return Ok(Verdict {
+                bytes: 0,
+                corruption: Some("data file has a malformed header"),
+            })
+        }
+        Err(e) => return Err(e),
+    };
+    let mut bytes = 0;
+    let mut matched = 0;
+    loop {
+        if ctx.is_closed() {
+            return Err(Error::Closed);
+        }
+        let (index, entry) = match entries.next() {
+            Ok(Some(next)) => next,
+            Ok(None) => break,
+            Err(Error::Io(e)) if e.kind() != io::ErrorKind::InvalidData => return Err(e.into()),
+            Err(_) => {
+                return Ok(Verdict {
+                    bytes,
+                    corruption: Some("data file has a malformed entry"),
+                })
+            }
+        };
+        bytes = index.pos + index.len;
+        throttle.consume(index.len);
+
+        let Some(keydir_entry) = ctx.get_keydir().get(&entry.key) else {
+            continue;
+        };
+        if keydir_entry.fileid() != fileid || keydir_entry.pos() != index.pos {
+            continue;
+        }
+        if keydir_entry.len() != index.len
+            || keydir_entry.tstamp() != entry.tstamp
+            || entry.value.is_none()
+        {
+            return Ok(Verdict {
+                bytes,
+                corruption: Some("data file entry doesn't match the KeyDir"),
+            });
+        }
+        matched += 1;
+    }
+
+    // Only the last entry can fail its checksum, since a crash can only cut off the last write
+    if !entries.ended_cleanly()? {
+        return Ok(Verdict {
+            bytes,
+            corruption: Some("data file entry checksum mismatch"),
+        });
+    }
+
+    // Entries that can't be found hold corrupt keys, or are past a corrupt length. The keys are
+    // counted again, since they may have moved out of the file while it was read.
+    if matched < expected {
+        let keys = ctx
+            .get_keydir()
+            .iter()
+            .filter(|(_, keydir_entry)| keydir_entry.fileid() == fileid)
+            .count() as u64;
+        if matched < keys {
+            return Ok(Verdict {
+                bytes,
+                corruption: Some("data file is missing entries that the KeyDir points to"),
+            });
+        }
+    }
+    Ok(Verdict {
+        bytes,
+        corruption: None,
+    })
+}
+
+/// Replace a corrupt data file with its copy in the log archive, if there's one and it passes the
+/// checks. Returns `true` if the file was replaced.
+fn repair(
+    handle: &Handle,
+    datafile: &Path,
+    fileid: u64,
+    expected: u64,
+    throttle: &mut Throttle,
+) -> Result<bool, Error> {
+    let conf = handle.ctx.get_conf();
+    let Some(archive_dir) = &conf.log_archive_dir else {
+        return Ok(false);
+    };
+    let Some(copy) = logarchive::closed_copy(archive_dir, fileid)? else {
+        return Ok(false);
+    };
+    match verify(&handle.ctx, &copy, fileid, expected, throttle) {
+        Ok(verdict) if verdict.corruption.is_none() => {}
+        Ok(_) => return Ok(false),
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    let tmp = datafile.with_extension("scrub");
+    fs::copy(&copy, &tmp)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    // Merges hold the writer while they remove files, so a file that a merge removed is never
+    // brought back
+    let writer = handle.lock_writer();
+    let exists = datafile.exists();
+    if exists {
+        fs::rename(&tmp, datafile)?;
+    }
+    drop(writer);
+    if !exists {
+        fs::remove_file(&tmp)?;
+    }
+    Ok(exists)
+}
@@ -0,0 +1,227 @@
+//! A deterministic simulation of the storage's environment, for reproducible tests of merge
+//! timing, expiry, and disk synchronizations.
+//!
+//! While a [`Simulation`] is entered on a thread, the storage calls made from that thread read
+//! the simulated clock instead of the system clock, draw their random numbers (e.g. the merge
+//! jitter, the random evictions, and the sampled reads) from a generator seeded by the
+//! simulation, and fail their disk synchronizations as the simulation decides. The clock only
+//! moves when the test advances it.
+//!
+//! Everything that the simulation drives must run on the thread that entered it, so storages
+//! under simulation are opened with [`RuntimeMode::Manual`] and their maintenance is ticked by
+//! the test through a [`MaintenanceDriver`].
+//!
+//! [`RuntimeMode::Manual`]: super::RuntimeMode::Manual
+//! [`MaintenanceDriver`]: super::MaintenanceDriver
+
+use std::{
+    cell::RefCell,
+    io,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+
+use super::utils;
+
+/// The Unix timestamp in nanoseconds at which simulated clocks start, 2020-01-01T00:00:00Z.
+const START_TIMESTAMP: i64 = 1_577_836_800_000_000_000;
+
+thread_local! {
+    /// The simulation entered on the thread, if any.
+    static CURRENT: RefCell<Option<Rc<RefCell<State>>>> = const { RefCell::new(None) };
+}
+
+/// A seeded simulation of the clock, of the randomness, and of the disk synchronizations.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    state: Rc<RefCell<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The instant that the simulated monotonic clock starts from.
+    start: Instant,
+    /// The simulated time that has passed since the start.
+    elapsed: Duration,
+    /// The generator of all random numbers drawn under the simulation.
+    rng: StdRng,
+    /// The probability that a disk synchronization fails.
+    sync_failure_rate: f64,
+}
+
+impl Simulation {
+    /// Create a simulation whose random numbers are drawn from the given seed. The same seed
+    /// gives the same sequence of merges, evictions, and failed synchronizations.
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+            sync_failure_rate: 0.0,
+        };
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    /// Make each disk synchronization fail with the given probability. Default to 0.
+    pub fn sync_failure_rate(&self, rate: f64) -> &Self {
+        self.state.borrow_mut().sync_failure_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Run the storage calls made from the current thread under the simulation until the returned
+    /// guard is dropped.
+    pub fn enter(&self) -> SimulationGuard {
+        let prev = CURRENT.with(|current| current.replace(Some(Rc::clone(&self.state))));
+        SimulationGuard { prev }
+    }
+
+    /// Move the simulated clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.state.borrow_mut().elapsed += duration;
+    }
+
+    /// Get the simulated time.
+    pub fn now(&self) -> SystemTime {
+        utils::from_timestamp(self.state.borrow().timestamp())
+    }
+}
+
+impl State {
+    fn timestamp(&self) -> i64 {
+        let elapsed = i64::try_from(self.elapsed.as_nanos()).unwrap_or(i64::MAX);
+        START_TIMESTAMP.saturating_add(elapsed)
+    }
+}
+
+/// Restores the simulation that was entered on the thread before, if any, when dropped.
+#[derive(Debug)]
+pub struct SimulationGuard {
+    prev: Option<Rc<RefCell<State>>>,
+}
+
+impl Drop for SimulationGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.prev.take());
+    }
+}
+
+fn with_current<T>(f: impl FnOnce(&mut State) -> T) -> Option<T> {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|state| f(&mut state.borrow_mut()))
+    })
+}
+
+/// Get the simulated Unix timestamp in nanoseconds, if a simulation was entered.
+pub(super) fn timestamp() -> Option<i64> {
+    with_current(|state| state.timestamp())
+}
+
+/// Get the simulated monotonic time, if a simulation was entered.
+pub(super) fn instant() -> Option<Instant> {
+    with_current(|state| state.start + state.elapsed)
+}
+
+/// Draw from the simulation's random number generator if a simulation was entered, otherwise
+/// give back the function.
+pub(super) fn with_rng<T, F>(f: F) -> Result<T, F>
+where
+    F: FnOnce(&mut dyn RngCore) -> T,
+{
+    CURRENT.with(|current| match current.borrow().as_ref() {
+        Some(state) => Ok(f(&mut state.borrow_mut().rng)),
+        None => Err(f),
+    })
+}
+
+/// Decide whether a disk synchronization fails, if a simulation was entered.
+pub(super) fn sync() -> io::Result<()> {
+    let failed = with_current(|state| {
+        let rate = state.sync_failure_rate;
+        rate > 0.0 && state.rng.gen_bool(rate)
+    });
+    match failed {
+        Some(true) => Err(io::Error::other("simulated sync failure")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::storage::{
+        bitcask::{Bitcask, Config, RuntimeMode},
+        KeyValueStorage,
+    };
+
+    fn open(path: &std::path::Path) -> Bitcask {
+        Config::default()
+            .path(path)
+            .runtime(RuntimeMode::Manual)
+            .merge_check_interval_ms(1000)
+            .merge_check_jitter(0.5)
+            .to_owned()
+            .open()
+            .unwrap()
+    }
+
+    fn merge_delays(seed: u64) -> Vec<Duration> {
+        let dir = tempfile::tempdir().unwrap();
+        let sim = Simulation::new(seed);
+        let _guard = sim.enter();
+        let kv = open(dir.path());
+        let mut driver = kv.maintenance_driver();
+        (0..5)
+            .map(|_| {
+                let delay = driver.tick().unwrap();
+                sim.advance(delay);
+                delay
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_timing_is_reproducible() {
+        assert_eq!(merge_delays(7), merge_delays(7));
+        assert_ne!(merge_delays(7), merge_delays(8));
+    }
+
+    #[test]
+    fn keys_expire_on_the_simulated_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let sim = Simulation::new(0);
+        let _guard = sim.enter();
+        let kv = open(dir.path());
+        let handle = kv.get_handle();
+
+        let expires_at = sim.now() + Duration::from_secs(10);
+        KeyValueStorage::set_with_expiry(&handle, "a".into(), "1".into(), Some(expires_at))
+            .unwrap();
+        sim.advance(Duration::from_secs(5));
+        assert_eq!(Some(Bytes::from("1")), handle.get("a".into()).unwrap());
+        sim.advance(Duration::from_secs(6));
+        assert_eq!(None, handle.get("a".into()).unwrap());
+    }
+
+    #[test]
+    fn syncs_fail_as_simulated() {
+        let dir = tempfile::tempdir().unwrap();
+        let sim = Simulation::new(0);
+        let _guard = sim.enter();
+        let kv = open(dir.path());
+        let handle = kv.get_handle();
+
+        handle.put("a".into(), "1".into()).unwrap();
+        handle.sync().unwrap();
+        sim.sync_failure_rate(1.0);
+        assert!(handle.sync().is_err());
+    }
+}
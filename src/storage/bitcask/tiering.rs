@@ -0,0 +1,313 @@
+//! Tiering of cold data files to a cheaper store, such as an object store.
+//!
+//! Merge files that no merge has to reclaim space from are immutable for as long as their keys
+//! stay live, so once they haven't changed for a while they're uploaded to a [`ColdStore`] and
+//! removed from the storage directory. Their hint files stay behind, together with an empty marker
+//! that stands in for the data file, so the KeyDir is rebuilt without touching the cold store. The
+//! reads of the keys in cold files fetch the entries from the cold store, and the fetched ranges
+//! are cached in memory.
+//!
+//! Merges leave the cold files alone until none of their keys are live, and then remove them from
+//! the cold store along with their markers.
+
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
+use tracing::info;
+
+use super::{
+    utils::{self, Layout},
+    Config, Error,
+};
+
+/// A store that holds the data files that were moved out of the storage directory. A file is
+/// only ever uploaded once and never changes afterwards, so stores don't need to handle
+/// concurrent writes to a file. Object stores are plugged in by implementing this for their
+/// clients, with the errors of the clients turned into [`io::Error`]s.
+pub trait ColdStore: Send + Sync {
+    /// Upload the data file at `src` as the file with the given ID. The upload must be complete
+    /// and durable when this returns.
+    fn upload(&self, fileid: u64, src: &Path) -> io::Result<()>;
+
+    /// Download the whole file with the given ID into `dst`.
+    fn download(&self, fileid: u64, dst: &Path) -> io::Result<()>;
+
+    /// Read `len` bytes at `pos` from the file with the given ID.
+    fn fetch(&self, fileid: u64, pos: u64, len: u64) -> io::Result<Bytes>;
+
+    /// Remove the file with the given ID. It's not an error if the file doesn't exist.
+    fn remove(&self, fileid: u64) -> io::Result<()>;
+}
+
+/// A cold store in a local directory, e.g. on a cheaper disk or on a mounted bucket.
+#[derive(Debug, Clone)]
+pub struct LocalColdStore {
+    dir: PathBuf,
+}
+
+impl LocalColdStore {
+    /// Create a store that keeps the files in the given directory, which is created on the first
+    /// upload.
+    pub fn new<P>(dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn file(&self, fileid: u64) -> PathBuf {
+        utils::datafile_name(&self.dir, Layout::Flat, fileid)
+    }
+}
+
+impl ColdStore for LocalColdStore {
+    fn upload(&self, fileid: u64, src: &Path) -> io::Result<()> {
+        // The file is copied under another name first, so a partial upload is never taken for a
+        // complete one
+        fs::create_dir_all(&self.dir)?;
+        let dst = self.file(fileid);
+        let tmp = dst.with_extension("tmp");
+        fs::copy(src, &tmp)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(tmp, dst)
+    }
+
+    fn download(&self, fileid: u64, dst: &Path) -> io::Result<()> {
+        fs::copy(self.file(fileid), dst)?;
+        Ok(())
+    }
+
+    fn fetch(&self, fileid: u64, pos: u64, len: u64) -> io::Result<Bytes> {
+        let mut file = fs::File::open(self.file(fileid))?;
+        let mut buf = vec![0; len as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buf)?;
+        Ok(Bytes::from(buf))
+    }
+
+    fn remove(&self, fileid: u64) -> io::Result<()> {
+        utils::remove_file(self.file(fileid))
+    }
+}
+
+/// A cold store that is shared by the copies of a configuration.
+#[derive(Clone)]
+pub(super) struct SharedColdStore(pub(super) Arc<dyn ColdStore>);
+
+impl fmt::Debug for SharedColdStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColdStore").finish_non_exhaustive()
+    }
+}
+
+/// The ranges of the cold files that were fetched, up to a number of bytes.
+#[derive(Debug)]
+struct RangeCache {
+    ranges: LruCache<(u64, u64), Bytes>,
+    bytes: u64,
+    max_bytes: u64,
+}
+
+impl RangeCache {
+    fn insert(&mut self, fileid: u64, pos: u64, range: Bytes) {
+        self.bytes += range.len() as u64;
+        if let Some(prev) = self.ranges.put((fileid, pos), range) {
+            self.bytes -= prev.len() as u64;
+        }
+        while self.bytes > self.max_bytes {
+            match self.ranges.pop_lru() {
+                Some((_, range)) => self.bytes -= range.len() as u64,
+                None => break,
+            }
+        }
+    }
+
+    fn forget(&mut self, fileid: u64) {
+        let cached: Vec<_> = self
+            .ranges
+            .iter()
+            .filter(|((id, _), _)| *id == fileid)
+            .map(|(k, _)| *k)
+            .collect();
+        for k in cached {
+            if let Some(range) = self.ranges.pop(&k) {
+                self.bytes -= range.len() as u64;
+            }
+        }
+    }
+}
+
+/// The data files that were moved to the cold store, and the cache of the ranges that were
+/// fetched from them.
+pub(super) struct ColdFiles {
+    store: Arc<dyn ColdStore>,
+    fileids: RwLock<BTreeSet<u64>>,
+    cache: Mutex<RangeCache>,
+    /// Number of reads that fetched their range from the cold store.
+    fetches: AtomicU64,
+    /// Number of reads of cold files that were served from the cache.
+    cache_hits: AtomicU64,
+}
+
+impl fmt::Debug for ColdFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColdFiles")
+            .field("fileids", &self.fileids)
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ColdFiles {
+    /// Find the cold files of the storage, or return `None` if tiering isn't configured.
+    pub(super) fn open(conf: &Config) -> Result<Option<Arc<Self>>, Error> {
+        let Some(store) = conf.cold_backend() else {
+            return Ok(None);
+        };
+        let fileids = utils::sorted_cold_fileids(&conf.path)?.collect();
+        let cache = RangeCache {
+            ranges: LruCache::unbounded(),
+            bytes: 0,
+            max_bytes: conf.cold_cache_size.get(),
+        };
+        Ok(Some(Arc::new(Self {
+            store,
+            fileids: RwLock::new(fileids),
+            cache: Mutex::new(cache),
+            fetches: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+        })))
+    }
+
+    /// Get whether the data file with the given ID is in the cold store.
+    pub(super) fn contains(&self, fileid: u64) -> bool {
+        self.fileids.read().contains(&fileid)
+    }
+
+    /// Get the IDs of the data files that are in the cold store.
+    pub(super) fn fileids(&self) -> BTreeSet<u64> {
+        self.fileids.read().clone()
+    }
+
+    /// Read `len` bytes at `pos` from a cold file, from the cache if the range was read before.
+    pub(super) fn fetch(&self, fileid: u64, pos: u64, len: u64) -> io::Result<Bytes> {
+        if let Some(range) = self.cache.lock().ranges.get(&(fileid, pos)) {
+            if range.len() as u64 == len {
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(range.clone());
+            }
+        }
+        // The cache isn't held while fetching, so other reads can go on
+        let range = self.store.fetch(fileid, pos, len)?;
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        self.cache.lock().insert(fileid, pos, range.clone());
+        Ok(range)
+    }
+
+    /// Upload a data file of the storage to the cold store. The file isn't removed yet, see
+    /// [`ColdFiles::commit`].
+    pub(super) fn upload(&self, conf: &Config, fileid: u64) -> io::Result<()> {
+        let datafile = utils::datafile_name(&conf.path, conf.layout(), fileid);
+        self.store.upload(fileid, &datafile)
+    }
+
+    /// Mark an uploaded data file as cold. The marker is written before the data file is removed
+    /// by the caller, so the file is always found in one of the places.
+    pub(super) fn commit(&self, conf: &Config, fileid: u64) -> io::Result<()> {
+        let marker = utils::coldfile_name(&conf.path, conf.layout(), fileid);
+        fs::File::create(marker)?.sync_all()?;
+        self.fileids.write().insert(fileid);
+        info!(fileid, "moved data file to the cold store");
+        Ok(())
+    }
+
+    /// Remove a file from the cold store after it was uploaded, when a merge removed the file
+    /// before it was marked as cold.
+    pub(super) fn abort(&self, fileid: u64) -> io::Result<()> {
+        self.store.remove(fileid)
+    }
+
+    /// Download a cold file into `dst`.
+    pub(super) fn download(&self, fileid: u64, dst: &Path) -> io::Result<()> {
+        self.store.download(fileid, dst)
+    }
+
+    /// Remove a cold file that a merge left no live keys in, together with its marker and its
+    /// hint file.
+    pub(super) fn remove(&self, conf: &Config, fileid: u64) -> io::Result<()> {
+        let layout = conf.layout();
+        self.fileids.write().remove(&fileid);
+        self.cache.lock().forget(fileid);
+        utils::remove_file(utils::hintfile_name(&conf.path, layout, fileid))?;
+        utils::remove_file(utils::coldfile_name(&conf.path, layout, fileid))?;
+        self.store.remove(fileid)
+    }
+
+    /// Get the number of files in the cold store.
+    pub(super) fn count(&self) -> usize {
+        self.fileids.read().len()
+    }
+
+    /// Get the number of reads that fetched their range from the cold store.
+    pub(super) fn fetches(&self) -> u64 {
+        self.fetches.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of reads of cold files that were served from the cache.
+    pub(super) fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_cache_stays_within_its_size() {
+        let mut cache = RangeCache {
+            ranges: LruCache::unbounded(),
+            bytes: 0,
+            max_bytes: 10,
+        };
+        cache.insert(1, 0, Bytes::from(vec![0; 4]));
+        cache.insert(1, 4, Bytes::from(vec![0; 4]));
+        cache.insert(2, 0, Bytes::from(vec![0; 4]));
+        assert_eq!(8, cache.bytes);
+        assert!(cache.ranges.get(&(1, 0)).is_none());
+
+        cache.forget(1);
+        assert_eq!(4, cache.bytes);
+        assert!(cache.ranges.get(&(2, 0)).is_some());
+    }
+
+    #[test]
+    fn local_stores_fetch_ranges_of_uploaded_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::write(&src, b"0123456789").unwrap();
+        let store = LocalColdStore::new(dir.path().join("cold"));
+        store.upload(7, &src).unwrap();
+        assert_eq!(Bytes::from("345"), store.fetch(7, 3, 3).unwrap());
+
+        let dst = dir.path().join("dst");
+        store.download(7, &dst).unwrap();
+        assert_eq!(b"0123456789".to_vec(), fs::read(&dst).unwrap());
+        store.remove(7).unwrap();
+        store.remove(7).unwrap();
+        assert!(store.fetch(7, 0, 1).is_err());
+    }
+}
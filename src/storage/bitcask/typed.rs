@@ -0,0 +1,217 @@
+//! A typed view over a storage, whose keys and values are serialized with serde.
+
+use std::{marker::PhantomData, ops::Bound};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{utils, Error, Handle};
+
+/// The serialization format of the keys and the values of a [`TypedStore`].
+///
+/// Scans return the entries in the order of their serialized keys, which is only the natural
+/// order of the keys for some formats and types. Bincode writes integers in little-endian, so
+/// numeric keys are not scanned in numeric order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// Bincode with its default options.
+    #[default]
+    Bincode,
+    /// JSON.
+    Json,
+    /// MessagePack, which is only available when the crate is built with the `msgpack` feature.
+    #[cfg(feature = "msgpack")]
+    Msgpack,
+}
+
+impl Codec {
+    /// Serialize the value after the given prefix.
+    fn encode<T>(self, prefix: &[u8], value: &T) -> Result<Bytes, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut buf = BytesMut::new().writer();
+        buf.get_mut().put_slice(prefix);
+        match self {
+            Self::Bincode => bincode::serialize_into(&mut buf, value)?,
+            Self::Json => serde_json::to_writer(&mut buf, value).map_err(codec_error)?,
+            #[cfg(feature = "msgpack")]
+            Self::Msgpack => rmp_serde::encode::write(&mut buf, value).map_err(codec_error)?,
+        }
+        Ok(buf.into_inner().freeze())
+    }
+
+    /// Deserialize a value.
+    fn decode<T>(self, raw: &[u8]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Self::Bincode => Ok(bincode::deserialize(raw)?),
+            Self::Json => serde_json::from_slice(raw).map_err(codec_error),
+            #[cfg(feature = "msgpack")]
+            Self::Msgpack => rmp_serde::from_slice(raw).map_err(codec_error),
+        }
+    }
+}
+
+/// A handle to a storage whose keys have type `K` and whose values have type `V`.
+///
+/// The keys can be placed under a prefix, so different types can share a storage and a scan only
+/// visits the keys of its own type.
+#[derive(Debug)]
+pub struct TypedStore<K, V> {
+    handle: Handle,
+    codec: Codec,
+    prefix: Bytes,
+    _types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> Clone for TypedStore<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            codec: self.codec,
+            prefix: self.prefix.clone(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<K, V> TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Create a typed handle to the storage that serializes the keys and the values with the given
+    /// codec.
+    pub fn new(handle: Handle, codec: Codec) -> Self {
+        Self {
+            handle,
+            codec,
+            prefix: Bytes::new(),
+            _types: PhantomData,
+        }
+    }
+
+    /// Place the keys under the given prefix.
+    pub fn with_prefix(mut self, prefix: impl Into<Bytes>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Set the value of a key.
+    pub fn put(&self, key: &K, value: &V) -> Result<(), Error> {
+        let key = self.codec.encode(&self.prefix, key)?;
+        let value = self.codec.encode(&[], value)?;
+        self.handle.put(key, value)
+    }
+
+    /// Get the value of a key, or `None` if the key doesn't exist.
+    pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+        let key = self.codec.encode(&self.prefix, key)?;
+        self.handle
+            .get(key)?
+            .map(|value| self.codec.decode(&value))
+            .transpose()
+    }
+
+    /// Delete a key and return `true`, if it exists. Otherwise, return `false`.
+    pub fn delete(&self, key: &K) -> Result<bool, Error> {
+        let key = self.codec.encode(&self.prefix, key)?;
+        self.handle.delete(key)
+    }
+
+    /// Return at most `count` entries whose keys come after `after`, or from the first key if
+    /// `after` is `None`, in the order of the serialized keys. Keys that are deleted during the
+    /// scan are skipped.
+    pub fn scan(&self, after: Option<&K>, count: usize) -> Result<Vec<(K, V)>, Error> {
+        let start = match after {
+            Some(key) => Bound::Excluded(self.codec.encode(&self.prefix, key)?),
+            None => Bound::Included(self.prefix.clone()),
+        };
+        let keys = self
+            .handle
+            .scan_range(start, utils::prefix_end(&self.prefix), count)?;
+        let mut entries = Vec::with_capacity(keys.len());
+        for raw_key in keys {
+            let Some(value) = self.handle.get(raw_key.clone())? else {
+                continue;
+            };
+            let key = self.codec.decode(&raw_key[self.prefix.len()..])?;
+            entries.push((key, self.codec.decode(&value)?));
+        }
+        Ok(entries)
+    }
+}
+
+/// Wrap an error from a codec other than bincode.
+fn codec_error<E>(e: E) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    Error::Codec(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::Config;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+    }
+
+    #[test]
+    fn typed_operations_with_each_codec() {
+        let codecs = [
+            Codec::Bincode,
+            Codec::Json,
+            #[cfg(feature = "msgpack")]
+            Codec::Msgpack,
+        ];
+        for codec in codecs {
+            let dir = tempfile::tempdir().unwrap();
+            let kv = Config::default()
+                .path(dir.path())
+                .to_owned()
+                .open()
+                .unwrap();
+            let users = TypedStore::<String, User>::new(kv.get_handle(), codec).with_prefix("u:");
+            let counts = TypedStore::<String, u64>::new(kv.get_handle(), codec).with_prefix("c:");
+
+            let alice = User {
+                name: "Alice".to_string(),
+                age: 30,
+            };
+            users.put(&"alice".to_string(), &alice).unwrap();
+            users
+                .put(
+                    &"bob".to_string(),
+                    &User {
+                        name: "Bob".to_string(),
+                        age: 40,
+                    },
+                )
+                .unwrap();
+            counts.put(&"alice".to_string(), &7).unwrap();
+
+            assert_eq!(Some(alice), users.get(&"alice".to_string()).unwrap());
+            assert_eq!(Some(7), counts.get(&"alice".to_string()).unwrap());
+            assert_eq!(None, users.get(&"carol".to_string()).unwrap());
+
+            // Scans only visit the keys under their own prefix
+            let scanned = users.scan(None, 10).unwrap();
+            assert_eq!(2, scanned.len());
+            let rest = users.scan(Some(&scanned[0].0), 10).unwrap();
+            assert_eq!(1, rest.len());
+            assert_eq!(scanned[1].0, rest[0].0);
+
+            assert!(users.delete(&"bob".to_string()).unwrap());
+            assert_eq!(1, users.scan(None, 10).unwrap().len());
+        }
+    }
+}
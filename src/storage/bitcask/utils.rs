@@ -1,64 +1,324 @@
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashSet},
     ffi::OsStr,
     fs, io,
+    num::NonZeroU8,
+    ops::Bound,
     path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use bytes::Bytes;
+use chrono::Timelike;
+use rand::RngCore;
+
 const DATAFILE_EXT: &str = "data";
 
 const HINTFILE_EXT: &str = "hint";
 
+/// The extension of the empty markers that stand in for the data files in the cold store.
+const COLDFILE_EXT: &str = "cold";
+
+/// The extension that is appended to the names of removed files that are still held by readers.
+const DELETED_EXT: &str = "deleted";
+
+/// The subdirectory that holds the data files and the hint files in the fanout layout.
+const FANOUT_DIR: &str = "data";
+
+/// Where the data files and the hint files are placed within the storage directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Layout {
+    /// Every file is placed directly in the storage directory.
+    Flat,
+    /// Files are spread over the subdirectories `data/00`, `data/01`, ... by their IDs, so no
+    /// directory holds too many files.
+    Fanout(NonZeroU8),
+}
+
+impl Layout {
+    /// Return the directory that holds the files with the given ID.
+    fn dir<P>(self, path: P, fileid: u64) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        match self {
+            Self::Flat => path.as_ref().to_path_buf(),
+            Self::Fanout(dirs) => path
+                .as_ref()
+                .join(FANOUT_DIR)
+                .join(format!("{:02x}", fileid % u64::from(dirs.get()))),
+        }
+    }
+}
+
 /// Return the data file name given its ID.
-pub(super) fn datafile_name<P>(path: P, fileid: u64) -> PathBuf
+pub(super) fn datafile_name<P>(path: P, layout: Layout, fileid: u64) -> PathBuf
 where
     P: AsRef<Path>,
 {
-    path.as_ref()
+    layout
+        .dir(path, fileid)
         .join(format!("{fileid}.bitcask.{DATAFILE_EXT}"))
 }
 
 /// Return the hint file name given its ID.
-pub(super) fn hintfile_name<P>(path: P, fileid: u64) -> PathBuf
+pub(super) fn hintfile_name<P>(path: P, layout: Layout, fileid: u64) -> PathBuf
 where
     P: AsRef<Path>,
 {
-    path.as_ref()
+    layout
+        .dir(path, fileid)
         .join(format!("{fileid}.bitcask.{HINTFILE_EXT}"))
 }
 
+/// Return the name of the marker of a data file in the cold store given its ID.
+pub(super) fn coldfile_name<P>(path: P, layout: Layout, fileid: u64) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    layout
+        .dir(path, fileid)
+        .join(format!("{fileid}.bitcask.{COLDFILE_EXT}"))
+}
+
+/// Returns the files in the storage directory and in the subdirectories of the fanout layout,
+/// so files can be found no matter which layout they were written in.
+fn storage_files<P>(path: P) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut dirs = vec![path.as_ref().to_path_buf()];
+    match fs::read_dir(path.as_ref().join(FANOUT_DIR)) {
+        Ok(entries) => dirs.extend(
+            entries
+                .filter_map(std::result::Result::ok)
+                .map(|e| e.path())
+                .filter(|p| p.is_dir()),
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let mut files = Vec::new();
+    for dir in dirs {
+        files.extend(
+            fs::read_dir(dir)?
+                // ignore errors
+                .filter_map(std::result::Result::ok)
+                .map(|e| e.path())
+                .filter(|p| p.is_file()),
+        );
+    }
+    Ok(files)
+}
+
+/// Parse the file ID from the name of a data file or a hint file.
+fn parse_fileid(path: &Path) -> Option<u64> {
+    path.file_stem()
+        .and_then(OsStr::to_str)
+        .and_then(|s| s.split('.').next())
+        .and_then(|s| s.parse::<u64>().ok())
+}
+
 /// Returns a list of sorted file IDs by parsing the data file names in the directory.
 pub(super) fn sorted_fileids<P>(path: P) -> io::Result<impl Iterator<Item = u64>>
 where
     P: AsRef<Path>,
 {
-    // read directory
-    Ok(fs::read_dir(&path)?
-        // ignore errors
-        .filter_map(std::result::Result::ok)
-        // extract paths
-        .map(|e| e.path())
-        // get files with data file extensions
-        .filter(|p| p.is_file() && p.extension() == Some(OsStr::new(DATAFILE_EXT)))
+    sorted_fileids_with_ext(path, DATAFILE_EXT)
+}
+
+/// Returns a list of sorted IDs of the data files in the cold store by parsing the names of
+/// their markers in the directory.
+pub(super) fn sorted_cold_fileids<P>(path: P) -> io::Result<impl Iterator<Item = u64>>
+where
+    P: AsRef<Path>,
+{
+    sorted_fileids_with_ext(path, COLDFILE_EXT)
+}
+
+/// Returns a list of sorted IDs of the data files that are either in the directory or in the
+/// cold store.
+pub(super) fn stored_fileids<P>(path: P) -> io::Result<Vec<u64>>
+where
+    P: AsRef<Path>,
+{
+    let mut fileids: BTreeSet<u64> = sorted_fileids(&path)?.collect();
+    fileids.extend(sorted_cold_fileids(&path)?);
+    Ok(fileids.into_iter().collect())
+}
+
+fn sorted_fileids_with_ext<P>(path: P, ext: &str) -> io::Result<impl Iterator<Item = u64>>
+where
+    P: AsRef<Path>,
+{
+    Ok(storage_files(path)?
+        .into_iter()
+        // get files with the given extension
+        .filter(|p| p.extension() == Some(OsStr::new(ext)))
         // parse the file id as u64
-        .filter_map(|p| {
-            p.file_stem()
-                .and_then(OsStr::to_str)
-                .and_then(|s| s.split('.').next())
-                .map(str::parse::<u64>)
-        })
-        .filter_map(std::result::Result::ok)
+        .filter_map(|p| parse_fileid(&p))
         .collect::<BTreeSet<u64>>()
         .into_iter())
 }
 
+/// Move the data files, the hint files, and the markers of the cold files to where the given layout places them, so a storage
+/// directory can switch between layouts.
+pub(super) fn arrange_files<P>(path: P, layout: Layout) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    if let Layout::Fanout(dirs) = layout {
+        for fileid in 0..u64::from(dirs.get()) {
+            fs::create_dir_all(layout.dir(&path, fileid))?;
+        }
+    }
+    for file in storage_files(&path)? {
+        let ext = file.extension();
+        if ![DATAFILE_EXT, HINTFILE_EXT, COLDFILE_EXT]
+            .iter()
+            .any(|e| ext == Some(OsStr::new(e)))
+        {
+            continue;
+        }
+        let (Some(fileid), Some(name)) = (parse_fileid(&file), file.file_name()) else {
+            continue;
+        };
+        let dest = layout.dir(&path, fileid).join(name);
+        if dest != file {
+            fs::rename(file, dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a data file or a hint file that readers might still have mapped into memory. It's not
+/// an error if the file doesn't exist.
+///
+/// Windows doesn't allow removing a file while it's mapped, but it allows renaming it. So the file
+/// is renamed first, which hides it from [`sorted_fileids`], and if it can't be removed yet, it's
+/// left for [`remove_deleted_files`] to remove once the readers have dropped it.
+pub(super) fn remove_file<P>(path: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    #[cfg(windows)]
+    let result = {
+        let deleted = deleted_name(&path);
+        fs::rename(&path, &deleted).map(|()| {
+            // the file is still mapped by a reader if this fails
+            let _ = fs::remove_file(&deleted);
+        })
+    };
+    #[cfg(not(windows))]
+    let result = fs::remove_file(path);
+
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Return the name that a removed file is renamed to while it's still held by readers, which
+/// hides it from [`sorted_fileids`].
+pub(super) fn deleted_name<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let mut deleted = path.as_ref().as_os_str().to_owned();
+    deleted.push(".");
+    deleted.push(DELETED_EXT);
+    PathBuf::from(deleted)
+}
+
+/// Remove the files that [`remove_file`] couldn't remove because readers still had them mapped,
+/// and the files that were left to be removed by readers when the storage was closed.
+/// Files that are still held are left for the next time.
+pub(super) fn remove_deleted_files<P>(path: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    remove_deleted_files_except(path, &HashSet::new())
+}
+
+/// Remove the files like [`remove_deleted_files`] does, except the given files, which are left
+/// for their readers to remove.
+pub(super) fn remove_deleted_files_except<P>(path: P, held: &HashSet<PathBuf>) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    for file in storage_files(path)? {
+        if file.extension() == Some(OsStr::new(DELETED_EXT)) && !held.contains(&file) {
+            let _ = fs::remove_file(file);
+        }
+    }
+    Ok(())
+}
+
 /// Return system unix nano timestamp
 pub(super) fn timestamp() -> i64 {
+    #[cfg(feature = "simulation")]
+    if let Some(now) = super::simulation::timestamp() {
+        return now;
+    }
     chrono::Local::now()
         .timestamp_nanos_opt()
         .expect("Failed to get timestamp in nanoseconds")
 }
 
+/// Return `true` if the local time is within the hours from `start` to `end`, both inclusive.
+pub(super) fn within_hours(start: u32, end: u32) -> bool {
+    let hour = chrono::Local::now().time().hour();
+    hour >= start && hour <= end
+}
+
+/// Return the current monotonic time, which schedules the maintenance tasks.
+pub(super) fn instant() -> Instant {
+    #[cfg(feature = "simulation")]
+    if let Some(now) = super::simulation::instant() {
+        return now;
+    }
+    Instant::now()
+}
+
+/// Draw random numbers that decide how the storage behaves, such as the merge jitter and the
+/// random evictions.
+pub(super) fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    #[cfg(feature = "simulation")]
+    let f = match super::simulation::with_rng(f) {
+        Ok(drawn) => return drawn,
+        Err(f) => f,
+    };
+    f(&mut rand::thread_rng())
+}
+
+/// Convert a system time into a Unix timestamp in nanoseconds, saturating at the bounds.
+pub(super) fn to_timestamp(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_nanos()).unwrap_or(i64::MAX),
+        Err(e) => i64::try_from(e.duration().as_nanos()).map_or(i64::MIN, |n| -n),
+    }
+}
+
+/// Convert a Unix timestamp in nanoseconds into a system time.
+pub(super) fn from_timestamp(nanos: i64) -> SystemTime {
+    let d = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 {
+        UNIX_EPOCH + d
+    } else {
+        UNIX_EPOCH - d
+    }
+}
+
+/// Return the bound that excludes every key starting with the prefix.
+pub(super) fn prefix_end(prefix: &[u8]) -> Bound<Bytes> {
+    let Some(last) = prefix.iter().rposition(|&b| b != u8::MAX) else {
+        return Bound::Unbounded;
+    };
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Bound::Excluded(end.into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,9 +332,9 @@ mod tests {
             // Create random datafiles and hintfiles in the directory
             let dir = tempfile::tempdir().unwrap();
             for fileid in 0..n {
-                tmps.push(fs::File::create(datafile_name(&dir, fileid)).unwrap());
+                tmps.push(fs::File::create(datafile_name(&dir, Layout::Flat, fileid)).unwrap());
                 if rand::random() {
-                    tmps.push(fs::File::create(hintfile_name(&dir, fileid)).unwrap());
+                    tmps.push(fs::File::create(hintfile_name(&dir, Layout::Flat, fileid)).unwrap());
                 }
             }
             // check if ids are sorted
@@ -82,4 +342,63 @@ mod tests {
             prop_assert!(fileids.enumerate().all(|(i, v)| i as u64 == v))
         }
     }
+
+    #[test]
+    fn prefix_end_excludes_prefixed_keys() {
+        assert_eq!(Bound::Excluded(Bytes::from("u;")), prefix_end(b"u:"));
+        assert_eq!(Bound::Excluded(Bytes::from("b")), prefix_end(b"a\xff\xff"));
+        assert_eq!(Bound::Unbounded, prefix_end(b"\xff"));
+        assert_eq!(Bound::Unbounded, prefix_end(b""));
+    }
+
+    #[test]
+    fn removed_files_are_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        for fileid in 0..3 {
+            fs::File::create(datafile_name(&dir, Layout::Flat, fileid)).unwrap();
+            fs::File::create(hintfile_name(&dir, Layout::Flat, fileid)).unwrap();
+        }
+        // Keep a file open while removing it
+        let _open = fs::File::open(datafile_name(&dir, Layout::Flat, 1)).unwrap();
+        remove_file(datafile_name(&dir, Layout::Flat, 1)).unwrap();
+        remove_file(hintfile_name(&dir, Layout::Flat, 1)).unwrap();
+        // Removing a missing file is not an error
+        remove_file(datafile_name(&dir, Layout::Flat, 1)).unwrap();
+
+        let fileids: Vec<u64> = sorted_fileids(&dir).unwrap().collect();
+        assert_eq!(vec![0, 2], fileids);
+        assert!(!hintfile_name(&dir, Layout::Flat, 1).exists());
+        remove_deleted_files(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_moved_between_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let fanout = Layout::Fanout(NonZeroU8::new(4).unwrap());
+        for fileid in 0..10 {
+            fs::File::create(datafile_name(&dir, Layout::Flat, fileid)).unwrap();
+            fs::File::create(hintfile_name(&dir, Layout::Flat, fileid)).unwrap();
+        }
+
+        arrange_files(&dir, fanout).unwrap();
+        assert_eq!(
+            dir.path().join("data").join("02").join("6.bitcask.data"),
+            datafile_name(&dir, fanout, 6)
+        );
+        for fileid in 0..10 {
+            assert!(datafile_name(&dir, fanout, fileid).is_file());
+            assert!(hintfile_name(&dir, fanout, fileid).is_file());
+            assert!(!datafile_name(&dir, Layout::Flat, fileid).exists());
+        }
+        let fileids: Vec<u64> = sorted_fileids(&dir).unwrap().collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), fileids);
+
+        arrange_files(&dir, Layout::Flat).unwrap();
+        for fileid in 0..10 {
+            assert!(datafile_name(&dir, Layout::Flat, fileid).is_file());
+            assert!(hintfile_name(&dir, Layout::Flat, fileid).is_file());
+        }
+        let fileids: Vec<u64> = sorted_fileids(&dir).unwrap().collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), fileids);
+    }
 }
@@ -1,26 +1,76 @@
-//! An implementation of [Bitcask](https://riak.com/assets/bitcask-intro.pdf).
+//! The single writer of a Bitcask instance, which appends every entry to the active data file.
+//!
+//! All writes are serialized on the writer's mutex, which is the main limit on how many writes a
+//! storage can take. The single writer is what the following rely on, so replacing it with writers
+//! that each append to their own active file, with keys routed to them by hash, would have to
+//! provide the same guarantees some other way:
+//!
+//! + The KeyDir is rebuilt by reading the data files in the order of their IDs, so a later write to
+//!   a key must always land in a file with a higher ID. This holds for sharded writers as long as
+//!   a key always goes to the same writer and file IDs are taken from a shared counter.
+//! + Evicting keys to stay within the quotas deletes keys that would belong to other writers, and
+//!   [`Transaction`]s and updates touch any number of keys under one lock. These would have to
+//!   lock several writers in a fixed order.
+//! + Merges and checkpoints snapshot the KeyDir and the statistics of every data file, so they
+//!   would have to stop all writers, and the statistics would have to be shared by them.
+//! + Syncs, batched durability, and the `always` sync strategy assume a single active file.
 
 use std::{
     cell::RefCell,
     collections::{BTreeSet, HashMap},
     fs,
-    io::{self, BufWriter},
     path::Path,
-    sync::Arc,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime},
 };
 
 use bytes::Bytes;
-use chrono::Timelike;
-use tracing::{debug, error};
+use rand::seq::SliceRandom;
+use tracing::{debug, error, warn};
 
-use crate::storage::bitcask::{config::MergePolicy, log, HintFileEntry};
+use crate::storage::{
+    bitcask::{
+        config::{MergeIo, MergePolicy},
+        entry::{DataFileBatch, HintFileEntry, HintFileTrailer},
+        log,
+    },
+    Transaction, Transfer,
+};
 
 use super::{
-    log::{LogDir, LogStatistics, LogWriter},
-    utils::{self, datafile_name},
-    Context, DataFileEntry, Error, KeyDirEntry, SyncStrategy,
+    archive,
+    checkpoint::Checkpoint,
+    chunks::{self, Manifest},
+    cleanshutdown::ShutdownMarker,
+    entry::{DataFileEntry, DataFileValue, Encode, FileHeader},
+    filter::Decision,
+    keydir::{DefaultKeyDir, KeyDir},
+    log::{DataFileIterator, LogDir, LogStatistics, LogWriter},
+    logarchive,
+    mergeio::MergeFileWriter,
+    reader,
+    utils::{self, datafile_name, Layout},
+    Config, Context, Error, EvictionPolicy, KeyDirEntry, QuotaPolicy, SyncStrategy, WriteMode,
 };
 
+/// The number of unlinked keys whose tombstones are queued before they are written.
+const UNLINK_BATCH: usize = 1024;
+
+/// Create a new data file with the given ID and return a writer for it, using the configured
+/// write mode.
+pub(super) fn create_active_datafile(conf: &Config, fileid: u64) -> Result<LogWriter, Error> {
+    let path = utils::datafile_name(&conf.path, conf.layout(), fileid);
+    let writer = match conf.write_mode {
+        WriteMode::Buffered => LogWriter::new(log::create(path)?, FileHeader::Data)?,
+        WriteMode::Mmap => LogWriter::mmap(
+            log::create_mappable(path)?,
+            conf.max_file_size.get(),
+            FileHeader::Data,
+        )?,
+    };
+    Ok(writer)
+}
+
 /// The writer appends log entries to data files and ensures that indices in KeyDir point to a valid
 /// file locations.
 #[derive(Debug)]
@@ -42,6 +92,26 @@ pub(super) struct Writer {
 
     /// The number of bytes that have been written to the currently active file.
     written_bytes: u64,
+
+    /// The key after which the next sample of keys is taken when evicting keys, so consecutive
+    /// evictions look at different parts of the KeyDir.
+    eviction_cursor: Option<Bytes>,
+
+    /// The keys that were unlinked and whose tombstones are still to be written.
+    unlinked: Vec<Bytes>,
+}
+
+/// What a merge has copied so far, which is kept when the merge is interrupted.
+#[derive(Debug)]
+struct MergeProgress {
+    /// The ID of the merge file that is being written. The merge files before it are complete.
+    fileid: u64,
+
+    /// The KeyDir entries of the keys that were copied to the merge files.
+    keydir_entries: HashMap<Bytes, KeyDirEntry>,
+
+    /// The keys whose values were changed by the compaction filter, with their new values.
+    changed_values: Vec<(Bytes, Bytes)>,
 }
 
 impl Writer {
@@ -61,26 +131,210 @@ impl Writer {
             stats,
             active_fileid,
             written_bytes,
+            eviction_cursor: None,
+            unlinked: Vec::new(),
         }
     }
+
+    /// Replace the statistics of the data files with the ones that were gathered while the
+    /// storage was recovering in the background.
+    pub(super) fn restore_stats(&mut self, stats: HashMap<u64, LogStatistics>) {
+        self.stats = stats;
+    }
     /// Set the value of a key and overwrite any existing value at that key.
     ///
     /// # Error
     ///
     /// Errors from I/O operations and serializations/deserializations will be propagated.
     pub(super) fn put(&mut self, key: Bytes, value: Bytes) -> Result<(), Error> {
+        self.put_with_expiry(key, value, None)
+    }
+
+    /// Set the value of a key that expires at the given Unix timestamp in nanoseconds, and
+    /// overwrite any existing value at that key.
+    ///
+    /// # Error
+    ///
+    /// Errors from I/O operations and serializations/deserializations will be propagated.
+    pub(super) fn put_with_expiry(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expiry: Option<i64>,
+    ) -> Result<(), Error> {
+        let tstamp = utils::timestamp();
+        let datafile_entry = DataFileEntry {
+            tstamp,
+            key: key.clone(),
+            value: Some(value.clone()),
+            expiry,
+        };
+        self.reserve(std::slice::from_ref(&datafile_entry))?;
+        if self.coalesce(&datafile_entry)? {
+            return Ok(());
+        }
         // Write to disk
-        let keydir_entry = self.write(utils::timestamp(), key.clone(), Some(value))?;
+        let keydir_entry = self.write(datafile_entry)?;
+        if let Some(prev_entry) = self.commit(key.clone(), value, tstamp, keydir_entry) {
+            self.remove_replaced_chunks(&key, &prev_entry, &[]);
+        }
+        Ok(())
+    }
+
+    /// Point the KeyDir to a value that was just written and update the states that depend on it.
+    /// Returns the KeyDir entry of the overwritten value, if there's one.
+    fn commit(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        tstamp: i64,
+        keydir_entry: KeyDirEntry,
+    ) -> Option<KeyDirEntry> {
+        // Chunks are internal, so they are only tracked by the KeyDir
+        let internal = chunks::is_chunk_key(&key);
+        if !internal {
+            // Keep the secondary indexes consistent with the entry that was just written
+            self.ctx.get_indexes().insert(&key, &value);
+            self.ctx
+                .publish_change(key.clone(), Some(chunks::unescape(value)), tstamp);
+        }
+        let access = self.ctx.get_access();
         // If we overwrite an existing value, update the storage statistics
-        if let Some(prev_entry) = self.ctx.keydir_set(key, keydir_entry) {
-            self.stats
-                .entry(prev_entry.value().fileid)
-                .or_default()
-                .overwrite(prev_entry.value().len);
+        let prev_entry = self.ctx.keydir_set(key.clone(), keydir_entry);
+        match &prev_entry {
+            Some(prev_entry) => {
+                self.stats
+                    .entry(prev_entry.fileid())
+                    .or_default()
+                    .overwrite(prev_entry.len());
+                if !internal {
+                    access.record(&key, tstamp, 1);
+                }
+            }
+            None if !internal => access.reset(&key, tstamp),
+            None => {}
+        }
+        if !internal {
+            self.ctx.record_hot_key(&key, 1);
+        }
+        prev_entry
+    }
+
+    /// Remove the chunks of the value that a key had before it was overwritten or deleted, if the
+    /// value was streamed in. The chunks are unlinked, so their tombstones are written with the
+    /// next write. `kept` holds the IDs of the manifests that were just written under other keys,
+    /// whose chunks are still in use. Failures are only logged since the chunks are unreachable.
+    fn remove_replaced_chunks(&mut self, key: &Bytes, prev_entry: &KeyDirEntry, kept: &[u64]) {
+        if !Manifest::may_be_stored_in(key, prev_entry) {
+            return;
+        }
+        // SAFETY: The entry was taken from the KeyDir, which points to valid data file positions,
+        // and the data file can't be merged away while the writer is held.
+        let datafile_value = unsafe {
+            self.readers.borrow_mut().read::<DataFileValue, _>(
+                &self.ctx.get_conf().path,
+                prev_entry.fileid(),
+                prev_entry.len(),
+                prev_entry.pos(),
+            )
+        };
+        let manifest = match datafile_value {
+            Ok(DataFileValue(value)) => value.as_deref().and_then(Manifest::decode),
+            Err(e) => {
+                error!(cause=?e, ?key, "can't read the replaced value to remove its chunks");
+                return;
+            }
+        };
+        let Some(manifest) = manifest.filter(|m| !kept.contains(&m.id())) else {
+            return;
+        };
+        for chunk_key in manifest.chunk_keys() {
+            if let Some(chunk_entry) = self.ctx.keydir_remove(&chunk_key) {
+                self.stats
+                    .entry(chunk_entry.fileid())
+                    .or_default()
+                    .overwrite(chunk_entry.len());
+                self.unlinked.push(chunk_key);
+            }
+        }
+    }
+
+    /// Buffer an entry that overwrites a key if overwrites are coalesced, and return `true` if
+    /// it was buffered. The buffer is written out once it's due.
+    fn coalesce(&mut self, datafile_entry: &DataFileEntry) -> Result<bool, Error> {
+        let conf = self.ctx.get_conf();
+        let Some(window) = conf.coalesce_window() else {
+            return Ok(false);
+        };
+        // Entries that would be rejected when written are written right away, so the rejection
+        // is returned to the caller
+        if datafile_entry.encoded_len() > u64::from(conf.max_entry_size.get()) {
+            return Ok(false);
+        }
+        // The chunks of a buffered manifest would never be removed if it were replaced in the
+        // buffer
+        if datafile_entry
+            .value
+            .as_deref()
+            .is_some_and(|value| Manifest::decode(value).is_some())
+        {
+            return Ok(false);
+        }
+        match self.ctx.get_keydir().get(&datafile_entry.key) {
+            Some(prev_entry)
+                if !prev_entry.is_expired(datafile_entry.tstamp)
+                    && prev_entry.expiry() == datafile_entry.expiry => {}
+            _ => return Ok(false),
+        }
+        let buffer = self.ctx.get_write_buffer();
+        if buffer.insert(datafile_entry.clone()) {
+            let metrics = self.ctx.get_metrics();
+            metrics.coalesced_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        if buffer.is_due(window, conf.coalesce_max_keys.get()) {
+            self.flush_coalesced()?;
+        }
+        Ok(true)
+    }
+
+    /// Write the buffered overwrites if they're due.
+    pub(super) fn flush_coalesced_if_due(&mut self) -> Result<(), Error> {
+        let conf = self.ctx.get_conf();
+        match conf.coalesce_window() {
+            Some(window)
+                if self
+                    .ctx
+                    .get_write_buffer()
+                    .is_due(window, conf.coalesce_max_keys.get()) =>
+            {
+                self.flush_coalesced()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the buffered overwrites. Each value stays in the buffer until the KeyDir points to
+    /// its entry, so reads never miss it.
+    fn flush_coalesced(&mut self) -> Result<(), Error> {
+        while let Some(datafile_entry) = self.ctx.get_write_buffer().first() {
+            let key = datafile_entry.key.clone();
+            let value = datafile_entry.value.clone().unwrap_or_default();
+            let tstamp = datafile_entry.tstamp;
+            let keydir_entry = self.append(datafile_entry)?;
+            if let Some(prev_entry) = self.commit(key.clone(), value, tstamp, keydir_entry) {
+                self.remove_replaced_chunks(&key, &prev_entry, &[]);
+            }
+            self.ctx.get_write_buffer().remove(&key);
         }
         Ok(())
     }
 
+    /// Write the buffered overwrites and the queued tombstones of the unlinked keys.
+    fn flush_pending(&mut self) -> Result<(), Error> {
+        self.flush_coalesced()?;
+        self.flush_unlinked()
+    }
+
     /// Delete a key and return `true`, if it exists. Otherwise, return `false`.
     ///
     /// # Error
@@ -88,32 +342,407 @@ impl Writer {
     /// Errors from I/O operations and serializations/deserializations will be propagated.
     pub(super) fn delete(&mut self, key: Bytes) -> Result<bool, Error> {
         // Write to disk
-        self.write(utils::timestamp(), key.clone(), None)?;
-        // If we overwrite an existing value, update the storage statistics
-        match self.ctx.get_keydir().remove(&key) {
+        let tstamp = utils::timestamp();
+        self.write(DataFileEntry {
+            tstamp,
+            key: key.clone(),
+            value: None,
+            expiry: None,
+        })?;
+        match self.commit_delete(key.clone(), tstamp) {
             Some(prev_entry) => {
-                self.stats
-                    .entry(prev_entry.value().fileid)
-                    .or_default()
-                    .overwrite(prev_entry.value().len);
+                self.remove_replaced_chunks(&key, &prev_entry, &[]);
                 Ok(true)
             }
             None => Ok(false),
         }
     }
 
+    /// Remove a key whose tombstone was just written from the KeyDir and update the states that
+    /// depend on it. Returns the KeyDir entry of the deleted value, if the key existed.
+    fn commit_delete(&mut self, key: Bytes, tstamp: i64) -> Option<KeyDirEntry> {
+        if !chunks::is_chunk_key(&key) {
+            self.ctx.get_indexes().remove(&key);
+            self.ctx.publish_change(key.clone(), None, tstamp);
+        }
+        // If we overwrite an existing value, update the storage statistics
+        let prev_entry = self.ctx.keydir_remove(&key)?;
+        self.stats
+            .entry(prev_entry.fileid())
+            .or_default()
+            .overwrite(prev_entry.len());
+        Some(prev_entry)
+    }
+
+    /// Start a transaction whose writes are staged and then written together as one batch, so
+    /// either all or none of them are kept.
+    pub(super) fn batch(&mut self) -> WriteBatch<'_> {
+        WriteBatch {
+            writer: self,
+            writes: Vec::new(),
+            staged: HashMap::new(),
+            copied_chunks: Vec::new(),
+        }
+    }
+
+    /// Write the entries as one batch, which is only read back if it was completely written.
+    /// Every check that can reject an entry is made before anything is written, so a failure
+    /// leaves the storage as it was, except for the keys that were evicted to make space.
+    fn write_batch(&mut self, mut entries: Vec<DataFileEntry>) -> Result<(), Error> {
+        let conf = self.ctx.get_conf();
+        let max = u64::from(conf.max_entry_size.get());
+        if let Some(len) = entries
+            .iter()
+            .map(Encode::encoded_len)
+            .find(|&len| len > max)
+        {
+            return Err(Error::EntryTooLarge { len, max });
+        }
+        if self.active_fileid > KeyDirEntry::MAX_FILEID {
+            return Err(Error::LimitExceeded("max file ID"));
+        }
+        self.reserve(&entries)?;
+        self.flush_pending()?;
+
+        let tstamp = utils::timestamp();
+        for entry in &mut entries {
+            entry.tstamp = tstamp;
+        }
+        let batch = DataFileBatch { tstamp, entries };
+        let index = match self.writer.append(&batch) {
+            Ok(index) => index,
+            Err(e) => {
+                // A partly written batch is read as the end of its file, which would hide the
+                // entries written after it, so they go to a new file
+                if let Err(e) = self.new_active_datafile(self.active_fileid + 1) {
+                    error!(cause=?e, "can't start a new data file after a failed batch");
+                }
+                return Err(e);
+            }
+        };
+        if let SyncStrategy::Always = self.ctx.get_conf().sync {
+            self.writer.sync()?;
+        }
+        self.written_bytes += index.len;
+
+        // The KeyDir entries are all made before the KeyDir is changed, so the batch is applied
+        // as a whole
+        let mut pos = index.pos + batch.header_len();
+        let mut keydir_entries = Vec::with_capacity(batch.entries.len());
+        for entry in &batch.entries {
+            let len = entry.encoded_len();
+            keydir_entries.push(KeyDirEntry::new(
+                self.active_fileid,
+                len,
+                pos,
+                tstamp,
+                entry.expiry,
+            )?);
+            pos += len;
+        }
+        // A manifest can be moved to another key by the batch, so its chunks are kept
+        let kept: Vec<_> = batch
+            .entries
+            .iter()
+            .filter_map(|entry| entry.value.as_deref().and_then(Manifest::decode))
+            .map(|manifest| manifest.id())
+            .collect();
+        for (entry, keydir_entry) in batch.entries.into_iter().zip(keydir_entries) {
+            let stats = self.stats.entry(self.active_fileid).or_default();
+            let prev_entry = match entry.value {
+                Some(value) => {
+                    stats.add_live();
+                    self.commit(entry.key.clone(), value, tstamp, keydir_entry)
+                }
+                None => {
+                    stats.add_dead(keydir_entry.len());
+                    self.commit_delete(entry.key.clone(), tstamp)
+                }
+            };
+            if let Some(prev_entry) = prev_entry {
+                self.remove_replaced_chunks(&entry.key, &prev_entry, &kept);
+            }
+        }
+        debug!(
+            batch_len = %index.len,
+            batch_pos = %index.pos,
+            active_fileid = %self.active_fileid,
+            "appended new batch"
+        );
+        if self.written_bytes > self.ctx.get_conf().max_file_size.get() {
+            self.new_active_datafile(self.active_fileid + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a key from the KeyDir and queue its tombstone, then return `true` if the key
+    /// existed. The queued tombstones are written together before the next write, on syncs,
+    /// merges, and checkpoints, and when the writer is dropped. Until then, a crash brings the
+    /// unlinked keys back.
+    pub(super) fn unlink(&mut self, key: Bytes) -> Result<bool, Error> {
+        // A buffered value of the key must not outlive the key
+        self.flush_coalesced()?;
+        let Some(prev_entry) = self.ctx.keydir_remove(&key) else {
+            return Ok(false);
+        };
+        self.ctx.get_indexes().remove(&key);
+        self.ctx
+            .publish_change(key.clone(), None, utils::timestamp());
+        self.stats
+            .entry(prev_entry.fileid())
+            .or_default()
+            .overwrite(prev_entry.len());
+        self.remove_replaced_chunks(&key, &prev_entry, &[]);
+        self.unlinked.push(key);
+        if self.unlinked.len() >= UNLINK_BATCH {
+            self.flush_unlinked()?;
+        }
+        Ok(true)
+    }
+
+    /// Write the queued tombstones of the unlinked keys. The tombstones that can't be written are
+    /// kept in the queue.
+    fn flush_unlinked(&mut self) -> Result<(), Error> {
+        let mut unlinked = std::mem::take(&mut self.unlinked).into_iter();
+        while let Some(key) = unlinked.next() {
+            let tombstone = DataFileEntry {
+                tstamp: utils::timestamp(),
+                key: key.clone(),
+                value: None,
+                expiry: None,
+            };
+            if let Err(e) = self.append(tombstone) {
+                self.unlinked = std::iter::once(key).chain(unlinked).collect();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the value of a key and return it, if it exists, otherwise return return `None`. Reading
+    /// through the writer ensures that no write can happen in between a read and a subsequent
+    /// write while the writer is held.
+    ///
+    /// # Error
+    ///
+    /// Errors from I/O operations and serializations/deserializations will be propagated.
+    pub(super) fn get(&self, key: &Bytes) -> Result<Option<Bytes>, Error> {
+        let now = utils::timestamp();
+        match self.ctx.get_keydir().get(key) {
+            Some(keydir_entry) if !keydir_entry.is_expired(now) => {
+                if !chunks::is_chunk_key(key) {
+                    self.ctx.get_access().record(key, now, 1);
+                    self.ctx.record_hot_key(key, 1);
+                }
+                if let Some(value) = self.ctx.get_write_buffer().get(key) {
+                    return Ok(Some(value));
+                }
+                // SAFETY: We have taken `keydir_entry` from KeyDir which is ensured to point to
+                // valid data file positions. Thus we can be confident that the Mmap won't be
+                // mapped to an invalid segment.
+                let datafile_value = unsafe {
+                    self.readers.borrow_mut().read::<DataFileValue, _>(
+                        &self.ctx.get_conf().path,
+                        keydir_entry.fileid(),
+                        keydir_entry.len(),
+                        keydir_entry.pos(),
+                    )?
+                };
+                Ok(datafile_value.0)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Repair the KeyDir entry of a key after a verified read found that it points to the entry
+    /// of another key, and return the value of the key. The newest entry of the key is looked up
+    /// in the data files, and the key is removed from KeyDir if it has none. The entry is checked
+    /// again first, since a write may have replaced it before the writer was locked.
+    ///
+    /// # Error
+    ///
+    /// Errors from I/O operations and serializations/deserializations will be propagated.
+    pub(super) fn repair(&mut self, key: &Bytes) -> Result<Option<Bytes>, Error> {
+        // Write out everything that's buffered, so the active file holds every entry of the key
+        self.sync()?;
+        if let Some(keydir_entry) = self.ctx.get_keydir().get(key) {
+            // SAFETY: We have taken `keydir_entry` from KeyDir which is ensured to point to valid
+            // data file positions.
+            let datafile_entry = unsafe {
+                self.readers.borrow_mut().read::<DataFileEntry, _>(
+                    &self.ctx.get_conf().path,
+                    keydir_entry.fileid(),
+                    keydir_entry.len(),
+                    keydir_entry.pos(),
+                )?
+            };
+            if reader::entry_matches(key, &keydir_entry, &datafile_entry) {
+                return self.get(key);
+            }
+        }
+        match self.ctx.get_history().locate_latest(
+            self.ctx.get_conf(),
+            self.ctx.get_cold_files(),
+            key,
+        )? {
+            Some(keydir_entry) => {
+                self.ctx.keydir_set(key.clone(), keydir_entry);
+            }
+            None => {
+                self.ctx.keydir_remove(key);
+            }
+        }
+        self.ctx
+            .get_metrics()
+            .repaired_reads
+            .fetch_add(1, Ordering::Relaxed);
+        warn!(key = ?key, "repaired KeyDir entry from the data files");
+        self.get(key)
+    }
+
+    /// Get the Unix timestamp in nanoseconds at which a key expires. Returns `None` if the key
+    /// does not exist or has no expiry.
+    pub(super) fn get_expiry(&self, key: &Bytes) -> Option<i64> {
+        self.ctx
+            .get_keydir()
+            .get(key)
+            .filter(|e| !e.is_expired(utils::timestamp()))
+            .and_then(|e| e.expiry())
+    }
+
+    /// Make sure that writing the given entries keeps the storage within its quotas, evicting
+    /// keys that aren't written if the quota policy allows it. The entries must be for different
+    /// keys.
+    fn reserve(&mut self, entries: &[DataFileEntry]) -> Result<(), Error> {
+        let conf = self.ctx.get_conf();
+        let (max_keys, max_live_bytes) = (conf.max_keys, conf.max_live_bytes);
+        if max_keys.is_none() && max_live_bytes.is_none() {
+            return Ok(());
+        }
+        // Count the keys and the bytes that the entries add to and free from the storage
+        let (mut added_keys, mut added_bytes, mut removed_keys, mut freed_bytes) = (0, 0, 0, 0);
+        for entry in entries {
+            let prev_entry = self.ctx.get_keydir().get(&entry.key);
+            // Chunks count toward the bytes but not the keys
+            let keys = u64::from(!chunks::is_chunk_key(&entry.key));
+            match (&entry.value, prev_entry) {
+                (Some(_), Some(prev_entry)) => {
+                    added_bytes += entry.encoded_len();
+                    freed_bytes += prev_entry.len();
+                }
+                (Some(_), None) => {
+                    added_keys += keys;
+                    added_bytes += entry.encoded_len();
+                }
+                (None, Some(prev_entry)) => {
+                    removed_keys += keys;
+                    freed_bytes += prev_entry.len();
+                }
+                (None, None) => {}
+            }
+        }
+        // Don't evict anything for writes that can't fit even in an empty storage
+        if max_keys.is_some_and(|max| added_keys > max) {
+            return Err(Error::QuotaExceeded("max keys"));
+        }
+        if max_live_bytes.is_some_and(|max| added_bytes > max) {
+            return Err(Error::QuotaExceeded("max live bytes"));
+        }
+        loop {
+            let (keys, bytes) = self.ctx.get_usage();
+            let keys = (keys + added_keys).saturating_sub(removed_keys);
+            let bytes = (bytes + added_bytes).saturating_sub(freed_bytes);
+            let exceeded = if max_keys.is_some_and(|max| keys > max) {
+                "max keys"
+            } else if max_live_bytes.is_some_and(|max| bytes > max) {
+                "max live bytes"
+            } else {
+                return Ok(());
+            };
+            match self.ctx.get_conf().quota_policy {
+                QuotaPolicy::Reject => return Err(Error::QuotaExceeded(exceeded)),
+                QuotaPolicy::Evict => match self
+                    .eviction_candidate(|key| entries.iter().any(|entry| entry.key == key))
+                {
+                    Some(victim) => {
+                        debug!(?victim, exceeded, "evicting key");
+                        self.delete(victim)?;
+                        let metrics = self.ctx.get_metrics();
+                        metrics.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => return Err(Error::QuotaExceeded(exceeded)),
+                },
+            }
+        }
+    }
+
+    /// Choose a key that isn't written to be evicted by looking at a sample of the keys, like
+    /// Redis does, so an eviction never visits all keys. Ordered KeyDirs are sampled after the
+    /// eviction cursor. An expired key is chosen if there's one in the sample, otherwise the key
+    /// is chosen by the eviction policy. Chunks are never chosen, they are removed with the keys
+    /// whose values they hold.
+    fn eviction_candidate<F>(&mut self, is_written: F) -> Option<Bytes>
+    where
+        F: Fn(&Bytes) -> bool,
+    {
+        let is_written = |key: &Bytes| is_written(key) || chunks::is_chunk_key(key);
+        const SAMPLES: usize = 16;
+        // Keys that can't be evicted are skipped, but only so many keys are looked at
+        const MAX_SAMPLED: usize = 4 * SAMPLES;
+        let keydir = self.ctx.get_keydir();
+        let cursor = self.eviction_cursor.take();
+        let mut sample = Vec::with_capacity(SAMPLES);
+        for (key, entry) in keydir.sample(cursor.as_ref(), MAX_SAMPLED) {
+            if sample.len() == SAMPLES {
+                break;
+            }
+            self.eviction_cursor = Some(key.clone());
+            if !is_written(&key) {
+                sample.push((key, entry));
+            }
+        }
+        let now = utils::timestamp();
+        if let Some((expired, _)) = sample.iter().find(|(_, e)| e.is_expired(now)) {
+            return Some(expired.clone());
+        }
+        let access = self.ctx.get_access();
+        let (victim, _) = match self.ctx.get_conf().eviction_policy {
+            EvictionPolicy::Lru => sample
+                .into_iter()
+                .min_by_key(|(k, _)| access.last_access(k))?,
+            EvictionPolicy::Lfu => sample
+                .into_iter()
+                .min_by_key(|(k, _)| (access.frequency(k, now), access.last_access(k)))?,
+            EvictionPolicy::Random => utils::with_rng(|rng| sample.choose(rng).cloned())?,
+        };
+        Some(victim)
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
-    fn write(
-        &mut self,
-        tstamp: i64,
-        key: Bytes,
-        value: Option<Bytes>,
-    ) -> Result<KeyDirEntry, Error> {
+    fn write(&mut self, datafile_entry: DataFileEntry) -> Result<KeyDirEntry, Error> {
+        // The buffered writes go before any later write, so the tombstones of the unlinked keys
+        // can't delete a key that was set again
+        self.flush_pending()?;
+        self.append(datafile_entry)
+    }
+
+    /// Append an entry to the active file and return the KeyDir entry that points to it.
+    fn append(&mut self, datafile_entry: DataFileEntry) -> Result<KeyDirEntry, Error> {
+        // Entries that can't be indexed by the KeyDir are rejected before they're written
+        let conf = self.ctx.get_conf();
+        let (len, max) = (
+            datafile_entry.encoded_len(),
+            u64::from(conf.max_entry_size.get()),
+        );
+        if len > max {
+            return Err(Error::EntryTooLarge { len, max });
+        }
+        if self.active_fileid > KeyDirEntry::MAX_FILEID {
+            return Err(Error::LimitExceeded("max file ID"));
+        }
         // Append log entry
-        let datafile_entry = DataFileEntry { tstamp, key, value };
         let index = self.writer.append(&datafile_entry)?;
         // Sync immediately if the strategy is "always"
-        let conf = self.ctx.get_conf();
         if let SyncStrategy::Always = conf.sync {
             self.writer.sync()?;
         }
@@ -144,12 +773,13 @@ impl Writer {
             );
         }
 
-        let keydir_entry = KeyDirEntry {
-            fileid: self.active_fileid,
-            len: index.len,
-            pos: index.pos,
-            tstamp,
-        };
+        let keydir_entry = KeyDirEntry::new(
+            self.active_fileid,
+            index.len,
+            index.pos,
+            datafile_entry.tstamp,
+            datafile_entry.expiry,
+        )?;
 
         // Check if active file size exceeds the max limit. This must be done as the last step of
         // the writing process, otherwise we risk corrupting the storage states.
@@ -162,126 +792,306 @@ impl Writer {
     /// Copy data from files that are included for merging. Once finish, copied files are deleted.
     #[tracing::instrument(level = "debug", skip(self))]
     pub(super) fn merge(&mut self) -> Result<(), Error> {
-        let conf = self.ctx.get_conf();
+        // Hold our own reference to the context, so the configurations can be borrowed while
+        // writing tombstones for expired keys.
+        let ctx = Arc::clone(&self.ctx);
+        let conf = ctx.get_conf();
         let path = conf.path.as_path();
-        let min_merge_fileid = self.active_fileid + 1;
-        let mut merge_fileid = min_merge_fileid;
-        debug!(merge_fileid, "new merge file");
+        let layout = conf.layout();
+        // The values of the unlinked keys are merged away, so their tombstones must be written
+        // for older values in the files that aren't merged to stay deleted
+        self.flush_pending()?;
+        // The active file can be merged away, so it's closed and archived before that
+        if conf.log_archive_dir.is_some() && self.written_bytes != 0 {
+            self.new_active_datafile(self.active_fileid + 1)?;
+        }
+        let mut progress = MergeProgress {
+            fileid: self.active_fileid + 1,
+            keydir_entries: HashMap::new(),
+            changed_values: Vec::new(),
+        };
+        debug!(merge_fileid = progress.fileid, "new merge file");
 
         // Get the set of file ids to be merged
         let fileids_to_merge = self.fileids_to_merge(path)?;
-        // Copy entries to a temporary map so we don't modify the KeyDir while iterating.
-        let mut new_keydir_entries = HashMap::new();
-
-        // NOTE: we use an explicit scope here to control the lifetimes of `readers`,
-        // `merge_datafile_writer` and `merge_hintfile_writer`. We drop the readers
-        // early so we can later mutably borrow `self` and drop the writers early so
-        // they are flushed.
+        // Expired keys and the keys that the compaction filter removes are not copied, they are
+        // deleted once the merge files are written.
+        let mut removed_keys = Vec::new();
+        if let Err(e) =
+            self.copy_to_merge_files(&fileids_to_merge, &mut progress, &mut removed_keys)
         {
-            let mut readers = self.readers.borrow_mut();
-            let mut merge_pos = 0;
-            let mut merge_datafile_writer =
-                BufWriter::new(log::create(utils::datafile_name(path, merge_fileid))?);
-            let mut merge_hintfile_writer =
-                LogWriter::new(log::create(utils::hintfile_name(path, merge_fileid))?)?;
-
-            // Only go through entries whose values are located within the merged files.
-            for entry in self
-                .ctx
-                .get_keydir()
-                .iter()
-                .filter(|e| fileids_to_merge.contains(&e.value().fileid))
-            {
-                // SAFETY: We ensure in `BitcaskWriter` that all log entries given by
-                // KeyDir are written disk, thus the readers can savely use memmap to
-                // access the data file randomly.
-                let nbytes = unsafe {
-                    readers.copy(
-                        path,
-                        entry.value().fileid,
-                        entry.value().len,
-                        entry.value().pos,
-                        &mut merge_datafile_writer,
-                    )?
-                };
-
-                new_keydir_entries.insert(
-                    entry.key().clone(),
-                    KeyDirEntry {
-                        fileid: merge_fileid,
-                        len: nbytes,
-                        pos: merge_pos,
-                        tstamp: entry.value().tstamp,
-                    },
-                );
-
-                // the merge file must only contain live keys
-                let stats = self.stats.entry(merge_fileid).or_default();
-                stats.add_live();
-
-                // write the KeyDir entry to the hint file for fast recovery
-                merge_hintfile_writer.append(&HintFileEntry {
-                    tstamp: entry.value().tstamp,
-                    len: entry.value().len,
-                    pos: entry.value().pos,
-                    key: entry.key().clone(),
-                })?;
-
-                // switch to new merge data file if we exceed the max file size
-                merge_pos += nbytes;
-                if merge_pos > conf.max_file_size.get() {
-                    merge_fileid += 1;
-                    merge_pos = 0;
-                    merge_datafile_writer =
-                        BufWriter::new(log::create(utils::datafile_name(path, merge_fileid))?);
-                    merge_hintfile_writer =
-                        LogWriter::new(log::create(utils::hintfile_name(path, merge_fileid))?)?;
-                    debug!(merge_fileid, "new merge file");
-                }
-            }
+            self.keep_completed_merge_files(progress)?;
+            return Err(e);
         }
+        let merge_fileid = progress.fileid;
 
         // Update keydir so it points to the merge data file
-        for (k, v) in new_keydir_entries {
+        for (k, v) in progress.keydir_entries {
             self.ctx.keydir_set(k, v);
         }
+        for (k, v) in progress.changed_values {
+            self.ctx.get_indexes().insert(&k, &v);
+        }
 
-        // Remove stale files from system and storage statistics
+        // Write tombstones for the removed keys, so their older values in files that are not
+        // merged can't be brought back when the KeyDir is rebuilt.
+        for key in removed_keys {
+            self.delete(key)?;
+        }
+
+        // Remove stale files from system and storage statistics, or move them to the archive
+        let file_refs = self.ctx.get_file_refs();
         for id in &fileids_to_merge {
             self.stats.remove(id);
-            if let Err(e) = fs::remove_file(utils::hintfile_name(path, *id)) {
-                if e.kind() != io::ErrorKind::NotFound {
-                    return Err(e.into());
-                }
+            if let Some(cold_files) = self.ctx.get_cold_files().filter(|c| c.contains(*id)) {
+                cold_files.remove(conf, *id)?;
+                continue;
             }
-            if let Err(e) = fs::remove_file(utils::datafile_name(path, *id)) {
-                if e.kind() != io::ErrorKind::NotFound {
-                    return Err(e.into());
+            if let Some(archive_dir) = &conf.merge_archive_dir {
+                archive::archive(path, layout, *id, archive_dir)?;
+                file_refs.retire(*id);
+                continue;
+            }
+            utils::remove_file(utils::hintfile_name(path, layout, *id))?;
+            file_refs.remove(*id, utils::datafile_name(path, layout, *id))?;
+        }
+        if !fileids_to_merge.is_empty() {
+            self.ctx.finish_merge();
+        }
+        if let (Some(archive_dir), Some(ms)) =
+            (&conf.merge_archive_dir, conf.merge_archive_retention_ms)
+        {
+            archive::prune(archive_dir, Duration::from_millis(ms))?;
+        }
+        // Retry removing the files of previous merges that couldn't be removed, which readers no
+        // longer hold
+        file_refs.remove_deleted_files(path)?;
+        self.new_active_datafile(merge_fileid + 1)?;
+        Ok(())
+    }
+
+    /// Copy the live entries in the merged files to the merge files, starting with the merge file
+    /// whose ID is given by the progress. The progress is updated as entries are copied, so it
+    /// tells what was written when the copy is interrupted.
+    fn copy_to_merge_files(
+        &mut self,
+        fileids_to_merge: &BTreeSet<u64>,
+        progress: &mut MergeProgress,
+        removed_keys: &mut Vec<Bytes>,
+    ) -> Result<(), Error> {
+        let ctx = Arc::clone(&self.ctx);
+        let conf = ctx.get_conf();
+        let path = conf.path.as_path();
+        let layout = conf.layout();
+        let now = utils::timestamp();
+
+        // The merge reads through its own cache, so it doesn't evict the files that the writer
+        // reads, and the merged files are closed when the cache is dropped.
+        let mut readers = ctx.new_log_dir();
+        let datafile = utils::datafile_name(path, layout, progress.fileid);
+        let hintfile = utils::hintfile_name(path, layout, progress.fileid);
+        let mut merge_datafile_writer = MergeFileWriter::create(datafile, conf.merge_io)?;
+        FileHeader::Data.write_to(&mut merge_datafile_writer)?;
+        let mut merge_pos = FileHeader::LEN;
+        let mut merge_hintfile_writer = LogWriter::new(log::create(hintfile)?, FileHeader::Hint)?;
+        let mut merge_hintfile_count = 0;
+
+        // Carry over the tombstones that are still retained, so the deletes are seen by
+        // consumers that replay the data files. They are not added to the hint files, which
+        // only hold live keys.
+        if let Some(ms) = conf.merge_tombstone_retention_ms {
+            let retention = i64::try_from(Duration::from_millis(ms).as_nanos()).unwrap_or(i64::MAX);
+            // Cold files hold no tombstones, since they're only moved once they have no dead
+            // entries
+            let local: BTreeSet<u64> = fileids_to_merge
+                .iter()
+                .copied()
+                .filter(|&id| !self.is_cold(id))
+                .collect();
+            let tombstones = retained_tombstones(
+                path,
+                layout,
+                &local,
+                now.saturating_sub(retention),
+                ctx.get_keydir(),
+            )?;
+            for tombstone in tombstones {
+                tombstone.write_to(&mut merge_datafile_writer)?;
+                let nbytes = tombstone.encoded_len();
+                self.stats
+                    .entry(progress.fileid)
+                    .or_default()
+                    .add_dead(nbytes);
+                merge_pos += nbytes;
+            }
+        }
+
+        // Only go through entries whose values are located within the merged files.
+        for (key, keydir_entry) in ctx
+            .get_keydir()
+            .iter()
+            .filter(|(_, e)| fileids_to_merge.contains(&e.fileid()))
+        {
+            // Stop early when the storage is closed, the merge files that were completed are
+            // kept for the next merge
+            if ctx.is_closed() {
+                return Err(Error::Closed);
+            }
+            if keydir_entry.is_expired(now) {
+                removed_keys.push(key);
+                continue;
+            }
+            let decision = merge_decision(conf, &mut readers, &key, &keydir_entry)?;
+            let nbytes = match decision {
+                Decision::Keep => {
+                    // SAFETY: We ensure in `BitcaskWriter` that all log entries given by
+                    // KeyDir are written disk, thus the readers can savely use memmap to
+                    // access the data file randomly.
+                    unsafe {
+                        readers.copy(
+                            path,
+                            keydir_entry.fileid(),
+                            keydir_entry.len(),
+                            keydir_entry.pos(),
+                            &mut merge_datafile_writer,
+                        )?
+                    }
                 }
+                Decision::Remove => {
+                    removed_keys.push(key);
+                    continue;
+                }
+                Decision::Change(value) => {
+                    let datafile_entry = DataFileEntry {
+                        tstamp: keydir_entry.tstamp(),
+                        key: key.clone(),
+                        value: Some(value.clone()),
+                        expiry: keydir_entry.expiry(),
+                    };
+                    datafile_entry.write_to(&mut merge_datafile_writer)?;
+                    progress.changed_values.push((key.clone(), value));
+                    datafile_entry.encoded_len()
+                }
+            };
+
+            progress.keydir_entries.insert(
+                key.clone(),
+                KeyDirEntry::new(
+                    progress.fileid,
+                    nbytes,
+                    merge_pos,
+                    keydir_entry.tstamp(),
+                    keydir_entry.expiry(),
+                )?,
+            );
+
+            // the merge file must only contain live keys
+            let stats = self.stats.entry(progress.fileid).or_default();
+            stats.add_live();
+
+            // write the KeyDir entry to the hint file for fast recovery
+            merge_hintfile_writer.append(&HintFileEntry {
+                tstamp: keydir_entry.tstamp(),
+                len: nbytes,
+                pos: merge_pos,
+                key: key.clone(),
+                expiry: keydir_entry.expiry(),
+            })?;
+            merge_hintfile_count += 1;
+
+            // switch to new merge data file if we exceed the merge output file size
+            merge_pos += nbytes;
+            if merge_pos > conf.merge_output_limit() {
+                // the trailer marks the hint file as complete, so it's only written once all
+                // data has been written
+                merge_datafile_writer.finish()?;
+                merge_hintfile_writer.append(&HintFileTrailer {
+                    count: merge_hintfile_count,
+                })?;
+                progress.fileid += 1;
+                let datafile = utils::datafile_name(path, layout, progress.fileid);
+                let hintfile = utils::hintfile_name(path, layout, progress.fileid);
+                merge_datafile_writer = MergeFileWriter::create(datafile, conf.merge_io)?;
+                FileHeader::Data.write_to(&mut merge_datafile_writer)?;
+                merge_pos = FileHeader::LEN;
+                merge_hintfile_writer = LogWriter::new(log::create(hintfile)?, FileHeader::Hint)?;
+                merge_hintfile_count = 0;
+                debug!(merge_fileid = progress.fileid, "new merge file");
             }
         }
+        merge_datafile_writer.finish()?;
+        merge_hintfile_writer.append(&HintFileTrailer {
+            count: merge_hintfile_count,
+        })?;
 
-        self.new_active_datafile(merge_fileid + 1)?;
+        // The merged files are removed next, but readers can keep them mapped for a while
+        if conf.merge_io != MergeIo::Cached {
+            for id in fileids_to_merge {
+                readers.release(*id);
+            }
+        }
         Ok(())
     }
 
+    /// Keep the merge files that an interrupted merge completed, so the next merge doesn't copy
+    /// their entries again. The KeyDir is pointed to the completed files, the copied entries are
+    /// counted as dead in the merged files, and the unfinished merge file is removed.
+    fn keep_completed_merge_files(&mut self, progress: MergeProgress) -> Result<(), Error> {
+        let ctx = Arc::clone(&self.ctx);
+        let conf = ctx.get_conf();
+        let path = conf.path.as_path();
+        let layout = conf.layout();
+
+        self.stats.remove(&progress.fileid);
+        utils::remove_file(utils::hintfile_name(path, layout, progress.fileid))?;
+        utils::remove_file(utils::datafile_name(path, layout, progress.fileid))?;
+        let mut kept = 0;
+        for (k, v) in progress.changed_values {
+            if progress
+                .keydir_entries
+                .get(&k)
+                .is_some_and(|e| e.fileid() != progress.fileid)
+            {
+                ctx.get_indexes().insert(&k, &v);
+            }
+        }
+        for (k, v) in progress.keydir_entries {
+            if v.fileid() == progress.fileid {
+                continue;
+            }
+            if let Some(prev) = ctx.keydir_set(k, v) {
+                self.stats
+                    .entry(prev.fileid())
+                    .or_default()
+                    .overwrite(prev.len());
+            }
+            kept += 1;
+        }
+        debug!(kept, "kept the completed merge files");
+
+        // The merge files hold older values than the writes that come after, so those writes
+        // must go to a later file for the KeyDir to be rebuilt correctly
+        self.new_active_datafile(progress.fileid + 1)
+    }
+
     /// Return `true` if one of the merge trigger conditions is met.
     pub(super) fn can_merge(&self) -> bool {
-        let conf = self.ctx.get_conf();
-        match conf.merge.policy {
+        let merge = self.ctx.get_merge_strategy();
+        match merge.policy {
             MergePolicy::Never => false,
             ref policy => {
                 if let &MergePolicy::Window { start, end } = policy {
-                    let now = chrono::Local::now().time();
-                    let hour = now.hour();
-                    if hour < start || hour > end {
+                    if !utils::within_hours(start, end) {
                         return false;
                     }
                 }
-                for (_, entry) in self.stats.iter() {
+                for (&fileid, entry) in self.stats.iter() {
+                    if self.is_cold(fileid) && entry.live_keys() != 0 {
+                        continue;
+                    }
                     // If any file met one of the trigger conditions, we'll try to merge
-                    if entry.dead_bytes() > conf.merge.triggers.dead_bytes
-                        || entry.fragmentation() > conf.merge.triggers.fragmentation
+                    if entry.dead_bytes() > merge.triggers.dead_bytes
+                        || entry.fragmentation() > merge.triggers.fragmentation
                     {
                         return true;
                     }
@@ -294,12 +1104,136 @@ impl Writer {
     /// Synchronize data to disk. This tells the operating system to flush its internal buffer to
     /// ensure that data is actually persisted.
     pub(super) fn sync(&mut self) -> Result<(), Error> {
+        self.flush_pending()?;
         self.writer.sync()?;
         Ok(())
     }
 
+    /// Start a new active data file and take a snapshot of the KeyDir and the statistics, which
+    /// covers all data files before the new active file. Written data is synced to disk first,
+    /// so the snapshot never points to entries that might be lost.
+    pub(super) fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        self.flush_pending()?;
+        self.writer.sync()?;
+        if self.written_bytes != 0 {
+            self.new_active_datafile(self.active_fileid + 1)?;
+        }
+        let next_fileid = self.active_fileid;
+        let fileids = utils::stored_fileids(&self.ctx.get_conf().path)?
+            .into_iter()
+            .filter(|&id| id < next_fileid)
+            .collect();
+        Ok(Checkpoint {
+            next_fileid,
+            fileids,
+            stats: self.stats.clone(),
+            entries: self.ctx.get_keydir().iter().collect(),
+        })
+    }
+
+    /// Sync the active file and take the state that's left in the marker of a clean shutdown.
+    pub(super) fn shutdown_marker(&mut self) -> Result<ShutdownMarker, Error> {
+        self.flush_pending()?;
+        self.writer.sync()?;
+        // An empty active file is removed when the writer is dropped
+        let active_fileid = self.active_fileid;
+        let written = self.written_bytes != 0;
+        let fileids = utils::stored_fileids(&self.ctx.get_conf().path)?
+            .into_iter()
+            .filter(|&id| written || id != active_fileid)
+            .collect();
+        Ok(ShutdownMarker {
+            active_fileid,
+            fileids,
+            stats: self.stats.clone(),
+        })
+    }
+
+    /// Get the ID of the data file that is written to.
+    pub(super) fn active_fileid(&self) -> u64 {
+        self.active_fileid
+    }
+
+    /// Start a new active data file and return the IDs of the data files before it, which are all
+    /// closed and synced to disk, including the files in the cold store.
+    pub(super) fn closed_fileids(&mut self) -> Result<BTreeSet<u64>, Error> {
+        self.flush_pending()?;
+        self.writer.sync()?;
+        if self.written_bytes != 0 {
+            self.new_active_datafile(self.active_fileid + 1)?;
+        }
+        let active_fileid = self.active_fileid;
+        Ok(utils::stored_fileids(&self.ctx.get_conf().path)?
+            .into_iter()
+            .filter(|&id| id < active_fileid)
+            .collect())
+    }
+
+    /// Get whether the data file with the given ID was moved to the cold store.
+    fn is_cold(&self, fileid: u64) -> bool {
+        self.ctx
+            .get_cold_files()
+            .is_some_and(|cold_files| cold_files.contains(fileid))
+    }
+
+    /// Return the IDs of the merge files that can be moved to the cold store, which are the files
+    /// that no merge has to reclaim space from and that haven't changed for `cold_after_ms`. Also
+    /// remove the data files that were left behind by an earlier move that was interrupted.
+    pub(super) fn cold_candidates(&mut self) -> Result<Vec<u64>, Error> {
+        let Some(cold_files) = self.ctx.get_cold_files() else {
+            return Ok(Vec::new());
+        };
+        let conf = self.ctx.get_conf();
+        let layout = conf.layout();
+        let cold_after = Duration::from_millis(conf.cold_after_ms);
+        let file_refs = self.ctx.get_file_refs();
+        let mut candidates = Vec::new();
+        for fileid in utils::sorted_fileids(&conf.path)? {
+            let datafile = datafile_name(&conf.path, layout, fileid);
+            if cold_files.contains(fileid) {
+                file_refs.remove(fileid, datafile)?;
+                continue;
+            }
+            let Some(stats) = self.stats.get(&fileid) else {
+                continue;
+            };
+            // Only the files written by merges have hint files
+            if fileid >= self.active_fileid
+                || stats.dead_keys() != 0
+                || stats.live_keys() == 0
+                || !utils::hintfile_name(&conf.path, layout, fileid).exists()
+            {
+                continue;
+            }
+            let modified = fs::metadata(&datafile)?.modified()?;
+            if modified.elapsed().unwrap_or_default() >= cold_after {
+                candidates.push(fileid);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Mark a data file that was uploaded to the cold store as cold, and remove it from the
+    /// storage directory. Returns `false` if a merge removed the file while it was uploaded, in
+    /// which case it's removed from the cold store too.
+    pub(super) fn finish_tiering(&mut self, fileid: u64) -> Result<bool, Error> {
+        let Some(cold_files) = self.ctx.get_cold_files() else {
+            return Ok(false);
+        };
+        let conf = self.ctx.get_conf();
+        if !self.stats.contains_key(&fileid) {
+            cold_files.abort(fileid)?;
+            return Ok(false);
+        }
+        cold_files.commit(conf, fileid)?;
+        self.readers.borrow_mut().release(fileid);
+        self.ctx
+            .get_file_refs()
+            .remove(fileid, datafile_name(&conf.path, conf.layout(), fileid))?;
+        Ok(true)
+    }
+
     /// Return the HashMap containing the writer statistics.
-    #[cfg(test)]
     pub(super) fn get_stats(&self) -> &HashMap<u64, LogStatistics> {
         &self.stats
     }
@@ -308,28 +1242,54 @@ impl Writer {
     #[tracing::instrument(level = "debug", skip(self))]
     fn new_active_datafile(&mut self, fileid: u64) -> Result<(), Error> {
         let conf = self.ctx.get_conf();
+        let closed = (self.written_bytes != 0).then_some(self.active_fileid);
         self.active_fileid = fileid;
-        self.writer = LogWriter::new(log::create(utils::datafile_name(
-            conf.path.as_path(),
-            self.active_fileid,
-        ))?)?;
+        self.writer = create_active_datafile(conf, self.active_fileid)?;
         self.written_bytes = 0;
+        if let (Some(closed), Some(archive_dir)) = (closed, &conf.log_archive_dir) {
+            logarchive::archive_closed(&conf.path, conf.layout(), closed, archive_dir)?;
+        }
         Ok(())
     }
 
+    /// Add what has been written to the active file so far to the log archive.
+    pub(super) fn archive_active(&mut self) -> Result<(), Error> {
+        let conf = self.ctx.get_conf();
+        let Some(archive_dir) = &conf.log_archive_dir else {
+            return Err(Error::InvalidConfig("the log archive directory is not set"));
+        };
+        self.writer.sync()?;
+        logarchive::archive_active(
+            &conf.path,
+            conf.layout(),
+            self.active_fileid,
+            self.written_bytes,
+            archive_dir,
+        )
+    }
+
     /// Return the set of file IDs that are included for merging.
     fn fileids_to_merge<P>(&self, path: P) -> Result<BTreeSet<u64>, Error>
     where
         P: AsRef<Path>,
     {
         let mut fileids = BTreeSet::new();
+        let merge = self.ctx.get_merge_strategy();
+        let layout = self.ctx.get_conf().layout();
         for (&fileid, stats) in self.stats.iter() {
-            let metadata = fs::metadata(datafile_name(&path, fileid))?;
+            // Cold files are only merged away once none of their keys are live, which doesn't
+            // read anything from the cold store
+            if self.is_cold(fileid) {
+                if stats.live_keys() == 0 {
+                    fileids.insert(fileid);
+                }
+                continue;
+            }
+            let metadata = fs::metadata(datafile_name(&path, layout, fileid))?;
             // Files that met one of the threshold conditions are included
-            let conf = self.ctx.get_conf();
-            if stats.dead_bytes() > conf.merge.thresholds.dead_bytes
-                || stats.fragmentation() > conf.merge.thresholds.fragmentation
-                || metadata.len() < conf.merge.thresholds.small_file
+            if stats.dead_bytes() > merge.thresholds.dead_bytes
+                || stats.fragmentation() > merge.thresholds.fragmentation
+                || metadata.len() < merge.thresholds.small_file
             {
                 fileids.insert(fileid);
             }
@@ -338,14 +1298,316 @@ impl Writer {
     }
 }
 
+/// Decide what a merge does with the entry of a key, by calling the compaction filter and then the
+/// value transformation on its value. The value is only read when there's one of them.
+fn merge_decision(
+    conf: &Config,
+    readers: &mut LogDir,
+    key: &Bytes,
+    keydir_entry: &KeyDirEntry,
+) -> Result<Decision, Error> {
+    if conf.compaction_filter.is_none() && conf.value_transform.is_none() {
+        return Ok(Decision::Keep);
+    }
+    // SAFETY: The positions are taken from KeyDir, which points to valid data file positions.
+    let datafile_value = unsafe {
+        readers.read::<DataFileValue, _>(
+            &conf.path,
+            keydir_entry.fileid(),
+            keydir_entry.len(),
+            keydir_entry.pos(),
+        )?
+    };
+    let value = datafile_value.0.unwrap_or_default();
+    let decision = match &conf.compaction_filter {
+        Some(filter) => {
+            let tstamp = utils::from_timestamp(keydir_entry.tstamp());
+            filter.0.keep(key, &value, tstamp)
+        }
+        None => Decision::Keep,
+    };
+    let Some(transform) = &conf.value_transform else {
+        return Ok(decision);
+    };
+    let decision = match decision {
+        Decision::Keep => transform
+            .0
+            .transform(key, &value)
+            .map_err(Error::Codec)?
+            .map_or(Decision::Keep, Decision::Change),
+        Decision::Change(value) => {
+            let transformed = transform.0.transform(key, &value).map_err(Error::Codec)?;
+            Decision::Change(transformed.unwrap_or(value))
+        }
+        Decision::Remove => Decision::Remove,
+    };
+    Ok(decision)
+}
+
+/// Collect the newest tombstone of each deleted key from the given data files, if it was written
+/// at or after `min_tstamp`. Tombstones of keys that have been set again are left out, since the
+/// merge files come after the files holding the new values.
+fn retained_tombstones<P>(
+    path: P,
+    layout: Layout,
+    fileids: &BTreeSet<u64>,
+    min_tstamp: i64,
+    keydir: &DefaultKeyDir,
+) -> Result<Vec<DataFileEntry>, Error>
+where
+    P: AsRef<Path>,
+{
+    let mut tombstones: HashMap<Bytes, DataFileEntry> = HashMap::new();
+    for &fileid in fileids {
+        let file = log::open(utils::datafile_name(&path, layout, fileid))?;
+        let mut datafile_iter = DataFileIterator::new(file)?;
+        while let Some((_, entry)) = datafile_iter.next()? {
+            // A zero-filled entry marks the end of the data in a file that was allocated up front
+            if entry.tstamp == 0 {
+                break;
+            }
+            if entry.value.is_some()
+                || entry.tstamp < min_tstamp
+                || keydir.get(&entry.key).is_some()
+            {
+                continue;
+            }
+            match tombstones.get(&entry.key) {
+                Some(newer) if newer.tstamp >= entry.tstamp => {}
+                _ => {
+                    tombstones.insert(entry.key.clone(), entry);
+                }
+            }
+        }
+    }
+    Ok(tombstones.into_values().collect())
+}
+
+/// A transaction that stages its writes while holding the writer, and writes them as one batch
+/// when it's committed. Reads within the transaction see the staged writes.
+#[derive(Debug)]
+pub(super) struct WriteBatch<'a> {
+    writer: &'a mut Writer,
+    /// The staged writes, at most one for each key.
+    writes: Vec<StagedWrite>,
+    /// The position of the staged write of each key.
+    staged: HashMap<Bytes, usize>,
+    /// The chunks that were written for copies of streamed values, which are removed if the
+    /// transaction isn't committed.
+    copied_chunks: Vec<Bytes>,
+}
+
+/// A write that is staged by a [`WriteBatch`].
+#[derive(Debug)]
+struct StagedWrite {
+    entry: DataFileEntry,
+    /// Whether the key was unlinked rather than deleted.
+    unlinked: bool,
+}
+
+impl WriteBatch<'_> {
+    /// Write the staged writes. A single write goes through the writer's usual path, so it can
+    /// be coalesced or queued like any other write.
+    pub(super) fn commit(mut self) -> Result<(), Error> {
+        let result = if self.writes.len() > 1 {
+            let entries = std::mem::take(&mut self.writes)
+                .into_iter()
+                .map(|w| w.entry)
+                .collect();
+            self.writer.write_batch(entries)
+        } else {
+            match self.writes.pop() {
+                None => Ok(()),
+                Some(StagedWrite { entry, unlinked }) => match entry.value {
+                    Some(value) => self.writer.put_with_expiry(entry.key, value, entry.expiry),
+                    None if unlinked => self.writer.unlink(entry.key).map(|_| ()),
+                    None => self.writer.delete(entry.key).map(|_| ()),
+                },
+            }
+        };
+        if result.is_ok() {
+            self.copied_chunks.clear();
+        }
+        result
+    }
+
+    /// Replace the staged write of the key.
+    fn stage(&mut self, key: Bytes, value: Option<Bytes>, expiry: Option<i64>, unlinked: bool) {
+        let write = StagedWrite {
+            entry: DataFileEntry {
+                tstamp: utils::timestamp(),
+                key: key.clone(),
+                value,
+                expiry,
+            },
+            unlinked,
+        };
+        match self.staged.get(&key) {
+            Some(&i) => self.writes[i] = write,
+            None => {
+                self.staged.insert(key, self.writes.len());
+                self.writes.push(write);
+            }
+        }
+    }
+
+    /// Get the value of the key as it's stored, seeing the staged writes.
+    fn get_stored(&mut self, key: &Bytes) -> Result<Option<Bytes>, Error> {
+        match self.staged(key) {
+            Some(entry) if entry.expiry.is_some_and(|e| e <= utils::timestamp()) => Ok(None),
+            Some(entry) => Ok(entry.value.clone()),
+            None => self.writer.get(key),
+        }
+    }
+
+    /// Stage the value of `src` under `dst`, and the deletion of `src` unless `keep_src` is
+    /// `true`. The chunks of a streamed value are copied when the source is kept, so each key
+    /// owns its chunks.
+    fn transfer(
+        &mut self,
+        src: Bytes,
+        dst: Bytes,
+        replace: bool,
+        keep_src: bool,
+    ) -> Result<Transfer, Error> {
+        let Some(raw) = self.get_stored(&src)? else {
+            return Ok(Transfer::SourceNotFound);
+        };
+        if src == dst {
+            return Ok(if replace {
+                Transfer::Done
+            } else {
+                Transfer::DestinationExists
+            });
+        }
+        if !replace && self.get_stored(&dst)?.is_some() {
+            return Ok(Transfer::DestinationExists);
+        }
+        let expiry = self.get_expiry(src.clone())?.map(utils::to_timestamp);
+        let mut raw = raw;
+        if keep_src {
+            if let Some(copy) = chunks::copy_chunks(self.writer, &raw, &mut self.copied_chunks)? {
+                raw = copy;
+            }
+        } else {
+            self.remove(src, false);
+        }
+        self.stage(dst, Some(raw), expiry, false);
+        Ok(Transfer::Done)
+    }
+
+    /// Get the staged entry of the key, if there's one.
+    fn staged(&self, key: &Bytes) -> Option<&DataFileEntry> {
+        self.staged.get(key).map(|&i| &self.writes[i].entry)
+    }
+
+    /// Stage the deletion of the key and return `true` if it exists.
+    fn remove(&mut self, key: Bytes, unlinked: bool) -> bool {
+        let existed = match self.staged(&key) {
+            Some(entry) => entry.value.is_some(),
+            None => self.writer.ctx.get_keydir().get(&key).is_some(),
+        };
+        self.stage(key, None, None, unlinked);
+        existed
+    }
+}
+
+impl Transaction for WriteBatch<'_> {
+    type Error = Error;
+
+    fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        chunks::check_key(&key)?;
+        self.stage(key, Some(chunks::escape(value)), None, false);
+        Ok(())
+    }
+
+    fn set_with_expiry(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error> {
+        chunks::check_key(&key)?;
+        let expiry = expires_at.map(utils::to_timestamp);
+        self.stage(key, Some(chunks::escape(value)), expiry, false);
+        Ok(())
+    }
+
+    fn get_expiry(&mut self, key: Bytes) -> Result<Option<SystemTime>, Self::Error> {
+        chunks::check_key(&key)?;
+        let expiry = match self.staged(&key) {
+            Some(entry) => entry
+                .expiry
+                .filter(|&expiry| entry.value.is_some() && expiry > utils::timestamp()),
+            None => self.writer.get_expiry(&key),
+        };
+        Ok(expiry.map(utils::from_timestamp))
+    }
+
+    fn get(&mut self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+        chunks::check_key(&key)?;
+        let Some(raw) = self.get_stored(&key)? else {
+            return Ok(None);
+        };
+        chunks::resolve(raw, |key| self.writer.get(&key)).map(Some)
+    }
+
+    fn del(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+        chunks::check_key(&key)?;
+        Ok(self.remove(key, false))
+    }
+
+    fn unlink(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+        chunks::check_key(&key)?;
+        Ok(self.remove(key, true))
+    }
+
+    fn copy(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        chunks::check_key(&src)?;
+        chunks::check_key(&dst)?;
+        self.transfer(src, dst, replace, true)
+    }
+
+    fn rename(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        chunks::check_key(&src)?;
+        chunks::check_key(&dst)?;
+        self.transfer(src, dst, replace, false)
+    }
+}
+
+impl Drop for WriteBatch<'_> {
+    fn drop(&mut self) {
+        for key in std::mem::take(&mut self.copied_chunks) {
+            if let Err(e) = self.writer.delete(key.clone()) {
+                error!(cause=?e, ?key, "can't remove the chunks of an uncommitted copy");
+            }
+        }
+    }
+}
+
 impl Drop for Writer {
     fn drop(&mut self) {
+        if let Err(e) = self.flush_pending() {
+            error!(cause=?e, "can't write the buffered writes");
+        }
         if self.written_bytes != 0 {
+            // The active file is never written to again, a new one is created on open
+            let conf = self.ctx.get_conf();
+            if let Some(archive_dir) = &conf.log_archive_dir {
+                if let Err(e) = logarchive::archive_closed(
+                    &conf.path,
+                    conf.layout(),
+                    self.active_fileid,
+                    archive_dir,
+                ) {
+                    error!(cause=?e, fileid=self.active_fileid, "can't archive data file");
+                }
+            }
             return;
         }
         let conf = self.ctx.get_conf();
-        let active_datafile = utils::datafile_name(&conf.path, self.active_fileid);
-        if let Err(e) = fs::remove_file(active_datafile) {
+        let active_datafile = utils::datafile_name(&conf.path, conf.layout(), self.active_fileid);
+        if let Err(e) = utils::remove_file(active_datafile) {
             error!(cause=?e, fileid=self.active_fileid, "can't remove empty data file");
         }
     }
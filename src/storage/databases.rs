@@ -0,0 +1,239 @@
+//! A storage that hosts several named databases, each backed by its own engine, so that tenants
+//! can share a server while their data and their quotas are kept apart.
+//!
+//! Every client starts on the default database and switches to another one with
+//! [`KeyValueStorage::select`]. The default database can also be selected as `0`, for clients
+//! that only know numbered databases. The clones of a client's storage share its selection, so a
+//! database selected while serving one request is used by the next requests of the client.
+
+use std::{
+    collections::HashMap,
+    ops::Bound,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{Durability, FileStats, KeyValueStorage, Transaction, Transfer, Update, ValueStream};
+
+/// The name of the database that clients use until they select another one.
+pub const DEFAULT_DATABASE: &str = "default";
+
+/// The index that clients that only know numbered databases select the default database with.
+pub const DEFAULT_DATABASE_INDEX: &str = "0";
+
+/// Get whether the name refers to the default database.
+pub fn is_default(name: &[u8]) -> bool {
+    name == DEFAULT_DATABASE.as_bytes() || name == DEFAULT_DATABASE_INDEX.as_bytes()
+}
+
+/// A storage that routes the operations of each client to the database that it selected.
+#[derive(Debug, Clone)]
+pub struct Databases<KV> {
+    /// The engines of the databases by name, shared by all clients. The engines are only
+    /// locked to give out copies of them when clients select their databases.
+    databases: Arc<Mutex<HashMap<Bytes, KV>>>,
+    /// The name and the engine of the database that the client selected.
+    selected: Arc<Mutex<(Bytes, KV)>>,
+}
+
+impl<KV> Databases<KV>
+where
+    KV: KeyValueStorage,
+{
+    /// Host the given engine as the default database.
+    pub fn new(default: KV) -> Self {
+        let selected = Arc::new(Mutex::new((Bytes::from(DEFAULT_DATABASE), default.clone())));
+        let databases = HashMap::from([(Bytes::from(DEFAULT_DATABASE), default)]);
+        Self {
+            databases: Arc::new(Mutex::new(databases)),
+            selected,
+        }
+    }
+
+    /// Host the given engine as the database with the given name, replacing the database that
+    /// had the name.
+    pub fn with_database<N>(self, name: N, storage: KV) -> Self
+    where
+        N: Into<Bytes>,
+    {
+        self.databases.lock().insert(name.into(), storage);
+        self
+    }
+
+    /// Get the engine of the database that the client selected.
+    fn current(&self) -> KV {
+        self.selected.lock().1.clone()
+    }
+}
+
+impl<KV> KeyValueStorage for Databases<KV>
+where
+    KV: KeyValueStorage,
+{
+    type Error = KV::Error;
+
+    fn for_client(&self) -> Self {
+        let default = self.databases.lock()[DEFAULT_DATABASE.as_bytes()].for_client();
+        Self {
+            databases: Arc::clone(&self.databases),
+            selected: Arc::new(Mutex::new((Bytes::from(DEFAULT_DATABASE), default))),
+        }
+    }
+
+    fn select(&self, name: &[u8]) -> Result<bool, Self::Error> {
+        let name = if is_default(name) {
+            DEFAULT_DATABASE.as_bytes()
+        } else {
+            name
+        };
+        let Some(storage) = self.databases.lock().get(name).map(KV::for_client) else {
+            return Ok(false);
+        };
+        *self.selected.lock() = (Bytes::copy_from_slice(name), storage);
+        Ok(true)
+    }
+
+    fn selected_database(&self) -> Bytes {
+        self.selected.lock().0.clone()
+    }
+
+    fn set(&self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        self.current().set(key, value)
+    }
+
+    fn set_with_expiry(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error> {
+        self.current().set_with_expiry(key, value, expires_at)
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+        self.current().get(key)
+    }
+
+    fn get_stream(&self, key: Bytes) -> Result<Option<ValueStream>, Self::Error> {
+        self.current().get_stream(key)
+    }
+
+    fn get_expiry(&self, key: Bytes) -> Result<Option<SystemTime>, Self::Error> {
+        self.current().get_expiry(key)
+    }
+
+    fn del(&self, key: Bytes) -> Result<bool, Self::Error> {
+        self.current().del(key)
+    }
+
+    fn make_durable(&self, durability: Durability) -> Result<(), Self::Error> {
+        self.current().make_durable(durability)
+    }
+
+    fn idle_time(&self, key: Bytes) -> Result<Option<Duration>, Self::Error> {
+        self.current().idle_time(key)
+    }
+
+    fn access_frequency(&self, key: Bytes) -> Result<Option<u8>, Self::Error> {
+        self.current().access_frequency(key)
+    }
+
+    fn hot_keys(&self, n: usize) -> Result<Vec<(Bytes, u64)>, Self::Error> {
+        self.current().hot_keys(n)
+    }
+
+    fn pause_maintenance(&self, paused: bool) -> Result<(), Self::Error> {
+        // The maintenance is paused for the whole server, not only for the selected database
+        let databases: Vec<_> = self.databases.lock().values().cloned().collect();
+        for storage in databases {
+            storage.pause_maintenance(paused)?;
+        }
+        Ok(())
+    }
+
+    fn maintenance_paused(&self) -> Result<bool, Self::Error> {
+        self.current().maintenance_paused()
+    }
+
+    fn file_stats(&self) -> Result<Vec<FileStats>, Self::Error> {
+        self.current().file_stats()
+    }
+
+    fn update<F, T>(&self, key: Bytes, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(Option<Bytes>) -> (Update, T) + Send + 'static,
+    {
+        self.current().update(key, f)
+    }
+
+    fn copy(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        self.current().copy(src, dst, replace)
+    }
+
+    fn rename(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<Transfer, Self::Error> {
+        self.current().rename(src, dst, replace)
+    }
+
+    fn atomically<F, T>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&mut dyn Transaction<Error = Self::Error>) -> Result<T, Self::Error>
+            + Send
+            + 'static,
+    {
+        self.current().atomically(f)
+    }
+
+    fn scan_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Result<Vec<Bytes>, Self::Error> {
+        self.current().scan_range(start, end, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::storage::bitcask::{Bitcask, Config};
+
+    fn open(path: &Path) -> Bitcask {
+        Config::default().path(path).to_owned().open().unwrap()
+    }
+
+    #[test]
+    fn clients_select_their_databases() {
+        let (dir1, dir2) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (default, tenant) = (open(dir1.path()), open(dir2.path()));
+        let databases =
+            Databases::new(default.get_handle()).with_database("tenant", tenant.get_handle());
+
+        let client1 = databases.for_client();
+        let client2 = databases.for_client();
+        assert!(client1.select(b"tenant").unwrap());
+        assert!(!client1.select(b"missing").unwrap());
+        // The clones made for the requests of a client share its selection
+        client1.clone().set("a".into(), "1".into()).unwrap();
+        client2.set("a".into(), "2".into()).unwrap();
+
+        assert_eq!(Some(Bytes::from("1")), client1.get("a".into()).unwrap());
+        assert_eq!(Some(Bytes::from("2")), client2.get("a".into()).unwrap());
+        assert!(client1.select(DEFAULT_DATABASE.as_bytes()).unwrap());
+        assert_eq!(Some(Bytes::from("2")), client1.get("a".into()).unwrap());
+        assert!(client1.select(b"tenant").unwrap());
+        assert_eq!(Bytes::from("tenant"), client1.selected_database());
+        assert!(client1.select(b"0").unwrap());
+        assert_eq!(Bytes::from(DEFAULT_DATABASE), client1.selected_database());
+        assert_eq!(Some(Bytes::from("2")), client1.get("a".into()).unwrap());
+        assert_eq!(
+            Some(Bytes::from("1")),
+            KeyValueStorage::get(&tenant.get_handle(), "a".into()).unwrap()
+        );
+    }
+}
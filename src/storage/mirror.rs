@@ -0,0 +1,369 @@
+//! A storage that mirrors the writes made to one engine onto another engine, so data can be moved
+//! to a new engine while the old one keeps serving requests.
+//!
+//! Reads are served by the primary engine. The primary's result is always the one returned, and
+//! the secondary engine only shadows it. Writes that fail on the secondary engine are logged and
+//! counted as divergences rather than returned. Reads can also be checked against the secondary
+//! engine, and the values that differ are counted too. Once no divergence is reported, the
+//! engines can be swapped.
+
+use std::{
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use super::{Durability, FileStats, KeyValueStorage, Transaction, Update, ValueStream};
+
+/// A storage that writes to two engines and reads from the primary one.
+#[derive(Debug, Clone)]
+pub struct MirroredStorage<P, S> {
+    primary: P,
+    secondary: S,
+    /// Whether reads are compared against the secondary engine.
+    verify_reads: bool,
+    /// The number of writes that failed on the secondary engine and reads that returned different
+    /// values, shared by all clones of the storage.
+    divergences: Arc<AtomicU64>,
+}
+
+impl<P, S> MirroredStorage<P, S>
+where
+    P: KeyValueStorage,
+    S: KeyValueStorage,
+{
+    /// Mirror the writes made to `primary` onto `secondary`.
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            verify_reads: false,
+            divergences: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Read each value from the secondary engine as well, and count a divergence when the values
+    /// differ.
+    pub fn with_read_verification(mut self) -> Self {
+        self.verify_reads = true;
+        self
+    }
+
+    /// Get the number of divergences between the engines that have been found so far.
+    pub fn divergences(&self) -> u64 {
+        self.divergences.load(Ordering::Relaxed)
+    }
+
+    /// Get the primary engine.
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Get the secondary engine.
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Record the error of a write that failed on the secondary engine.
+    fn shadow<T>(&self, op: &'static str, result: Result<T, S::Error>) {
+        if let Err(e) = result {
+            self.divergences.fetch_add(1, Ordering::Relaxed);
+            warn!(error = %e, op, "mirrored write failed on the secondary storage");
+        }
+    }
+}
+
+impl<P, S> KeyValueStorage for MirroredStorage<P, S>
+where
+    P: KeyValueStorage,
+    S: KeyValueStorage,
+{
+    type Error = P::Error;
+
+    fn for_client(&self) -> Self {
+        Self {
+            primary: self.primary.for_client(),
+            secondary: self.secondary.for_client(),
+            ..self.clone()
+        }
+    }
+
+    fn set(&self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        self.primary.set(key.clone(), value.clone())?;
+        self.shadow("set", self.secondary.set(key, value));
+        Ok(())
+    }
+
+    fn set_with_expiry(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error> {
+        self.primary
+            .set_with_expiry(key.clone(), value.clone(), expires_at)?;
+        self.shadow(
+            "set_with_expiry",
+            self.secondary.set_with_expiry(key, value, expires_at),
+        );
+        Ok(())
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+        let value = self.primary.get(key.clone())?;
+        if self.verify_reads {
+            match self.secondary.get(key.clone()) {
+                Ok(shadow) if shadow == value => {}
+                Ok(_) => {
+                    self.divergences.fetch_add(1, Ordering::Relaxed);
+                    warn!(?key, "mirrored storages have different values");
+                }
+                Err(e) => {
+                    self.divergences.fetch_add(1, Ordering::Relaxed);
+                    warn!(error = %e, ?key, "mirrored read failed on the secondary storage");
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    fn get_stream(&self, key: Bytes) -> Result<Option<ValueStream>, Self::Error> {
+        if self.verify_reads {
+            return Ok(self.get(key)?.map(ValueStream::Whole));
+        }
+        self.primary.get_stream(key)
+    }
+
+    fn get_expiry(&self, key: Bytes) -> Result<Option<SystemTime>, Self::Error> {
+        self.primary.get_expiry(key)
+    }
+
+    fn del(&self, key: Bytes) -> Result<bool, Self::Error> {
+        let deleted = self.primary.del(key.clone())?;
+        self.shadow("del", self.secondary.del(key));
+        Ok(deleted)
+    }
+
+    fn make_durable(&self, durability: Durability) -> Result<(), Self::Error> {
+        self.primary.make_durable(durability)?;
+        self.shadow("make_durable", self.secondary.make_durable(durability));
+        Ok(())
+    }
+
+    fn idle_time(&self, key: Bytes) -> Result<Option<Duration>, Self::Error> {
+        self.primary.idle_time(key)
+    }
+
+    fn access_frequency(&self, key: Bytes) -> Result<Option<u8>, Self::Error> {
+        self.primary.access_frequency(key)
+    }
+
+    fn hot_keys(&self, n: usize) -> Result<Vec<(Bytes, u64)>, Self::Error> {
+        self.primary.hot_keys(n)
+    }
+
+    fn pause_maintenance(&self, paused: bool) -> Result<(), Self::Error> {
+        self.primary.pause_maintenance(paused)?;
+        self.shadow(
+            "pause_maintenance",
+            self.secondary.pause_maintenance(paused),
+        );
+        Ok(())
+    }
+
+    fn maintenance_paused(&self) -> Result<bool, Self::Error> {
+        self.primary.maintenance_paused()
+    }
+
+    fn file_stats(&self) -> Result<Vec<FileStats>, Self::Error> {
+        self.primary.file_stats()
+    }
+
+    fn update<F, T>(&self, key: Bytes, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(Option<Bytes>) -> (Update, T) + Send + 'static,
+    {
+        // The change is decided by the primary's value and applied to both engines as is
+        let (update, result) = self.primary.update(key.clone(), move |prev| {
+            let (update, result) = f(prev);
+            (update.clone(), (update, result))
+        })?;
+        if update != Update::Keep {
+            self.shadow("update", self.secondary.update(key, move |_| (update, ())));
+        }
+        Ok(result)
+    }
+
+    fn atomically<F, T>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&mut dyn Transaction<Error = Self::Error>) -> Result<T, Self::Error>
+            + Send
+            + 'static,
+    {
+        // The writes are replayed even if `f` fails because the primary keeps the writes that
+        // were made before the failure
+        let (result, writes) = self.primary.atomically(move |txn| {
+            let mut txn = RecordingTransaction {
+                txn,
+                writes: Vec::new(),
+            };
+            let result = f(&mut txn);
+            Ok((result, txn.writes))
+        })?;
+        if !writes.is_empty() {
+            let replayed = self.secondary.atomically(move |txn| {
+                for write in writes {
+                    match write {
+                        Write::Set(key, value) => txn.set(key, value)?,
+                        Write::SetWithExpiry(key, value, expires_at) => {
+                            txn.set_with_expiry(key, value, expires_at)?
+                        }
+                        Write::Del(key) => {
+                            txn.del(key)?;
+                        }
+                    }
+                }
+                Ok(())
+            });
+            self.shadow("atomically", replayed);
+        }
+        result
+    }
+
+    fn scan_range(
+        &self,
+        start: Bound<Bytes>,
+        end: Bound<Bytes>,
+        count: usize,
+    ) -> Result<Vec<Bytes>, Self::Error> {
+        self.primary.scan_range(start, end, count)
+    }
+}
+
+/// A write that was made in a transaction on the primary engine.
+enum Write {
+    Set(Bytes, Bytes),
+    SetWithExpiry(Bytes, Bytes, Option<SystemTime>),
+    Del(Bytes),
+}
+
+/// A transaction on the primary engine that records its writes, so they can be replayed on the
+/// secondary engine.
+struct RecordingTransaction<'a, E> {
+    txn: &'a mut dyn Transaction<Error = E>,
+    writes: Vec<Write>,
+}
+
+impl<E> Transaction for RecordingTransaction<'_, E> {
+    type Error = E;
+
+    fn set(&mut self, key: Bytes, value: Bytes) -> Result<(), Self::Error> {
+        self.txn.set(key.clone(), value.clone())?;
+        self.writes.push(Write::Set(key, value));
+        Ok(())
+    }
+
+    fn set_with_expiry(
+        &mut self,
+        key: Bytes,
+        value: Bytes,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), Self::Error> {
+        self.txn
+            .set_with_expiry(key.clone(), value.clone(), expires_at)?;
+        self.writes
+            .push(Write::SetWithExpiry(key, value, expires_at));
+        Ok(())
+    }
+
+    fn get(&mut self, key: Bytes) -> Result<Option<Bytes>, Self::Error> {
+        self.txn.get(key)
+    }
+
+    fn get_expiry(&mut self, key: Bytes) -> Result<Option<SystemTime>, Self::Error> {
+        self.txn.get_expiry(key)
+    }
+
+    fn del(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+        let deleted = self.txn.del(key.clone())?;
+        if deleted {
+            self.writes.push(Write::Del(key));
+        }
+        Ok(deleted)
+    }
+
+    fn unlink(&mut self, key: Bytes) -> Result<bool, Self::Error> {
+        let deleted = self.txn.unlink(key.clone())?;
+        if deleted {
+            self.writes.push(Write::Del(key));
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::storage::bitcask::{Bitcask, Config, Handle};
+
+    fn open(path: &Path) -> Bitcask {
+        Config::default().path(path).to_owned().open().unwrap()
+    }
+
+    fn mirrored(primary: &Bitcask, secondary: &Bitcask) -> MirroredStorage<Handle, Handle> {
+        MirroredStorage::new(primary.get_handle(), secondary.get_handle())
+    }
+
+    #[test]
+    fn writes_are_mirrored() {
+        let (dir1, dir2) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (primary, secondary) = (open(dir1.path()), open(dir2.path()));
+        let storage = mirrored(&primary, &secondary);
+
+        storage.set("a".into(), "1".into()).unwrap();
+        storage.set("b".into(), "2".into()).unwrap();
+        storage.del("b".into()).unwrap();
+        let prev = storage
+            .update("c".into(), |prev| (Update::Set("3".into()), prev))
+            .unwrap();
+        assert_eq!(None, prev);
+        storage
+            .atomically(|txn| {
+                txn.rename("a".into(), "d".into(), false)?;
+                Ok(())
+            })
+            .unwrap();
+
+        let secondary = secondary.get_handle();
+        for key in ["a", "b", "c", "d"] {
+            assert_eq!(
+                storage.get(key.into()).unwrap(),
+                KeyValueStorage::get(&secondary, key.into()).unwrap(),
+            );
+        }
+        assert_eq!(0, storage.divergences());
+    }
+
+    #[test]
+    fn different_values_are_counted_when_reads_are_verified() {
+        let (dir1, dir2) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (primary, secondary) = (open(dir1.path()), open(dir2.path()));
+        let storage = mirrored(&primary, &secondary).with_read_verification();
+
+        storage.set("a".into(), "1".into()).unwrap();
+        storage.get("a".into()).unwrap();
+        assert_eq!(0, storage.divergences());
+
+        KeyValueStorage::set(&secondary.get_handle(), "a".into(), "2".into()).unwrap();
+        assert_eq!(Some(Bytes::from("1")), storage.get("a".into()).unwrap());
+        assert_eq!(1, storage.divergences());
+    }
+}
@@ -1,9 +1,25 @@
 //! Capture key-value store operations log
 
+use serde::Deserialize;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    reload, EnvFilter, Registry,
+};
+
+/// The format that the logs are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// One Bunyan-style JSON object per line, for log collectors.
+    #[default]
+    Json,
+    /// Human-readable lines, for reading the logs in a terminal.
+    Text,
+}
 
 /// Compose multiple layer into a `tracing` subscriber
 ///
@@ -15,6 +31,7 @@ use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Regis
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
+    format: LogFormat,
     sink: Sink,
 ) -> impl Subscriber + Send + Sync
 where
@@ -23,11 +40,52 @@ where
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let (json_layer, text_layer) = match format {
+        LogFormat::Json => (Some(BunyanFormattingLayer::new(name, sink)), None),
+        LogFormat::Text => (None, Some(fmt::layer().with_writer(sink))),
+    };
     Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(json_layer.is_some().then_some(JsonStorageLayer))
+        .with(json_layer)
+        .with(text_layer)
+}
+
+/// Compose multiple layer into a `tracing` subscriber whose filter can be changed after the
+/// subscriber has been registered through the returned [`FilterHandle`].
+pub fn get_reloadable_subscriber<Sink>(
+    name: String,
+    env_filter: String,
+    format: LogFormat,
+    sink: Sink,
+) -> (impl Subscriber + Send + Sync, FilterHandle)
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let (json_layer, text_layer) = match format {
+        LogFormat::Json => (Some(BunyanFormattingLayer::new(name, sink)), None),
+        LogFormat::Text => (None, Some(fmt::layer().with_writer(sink))),
+    };
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(json_layer.is_some().then_some(JsonStorageLayer))
+        .with(json_layer)
+        .with(text_layer);
+    (subscriber, FilterHandle(handle))
+}
+
+/// A handle for changing the filter of a subscriber created by [`get_reloadable_subscriber`].
+#[derive(Debug, Clone)]
+pub struct FilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl FilterHandle {
+    /// Replace the subscriber's filter.
+    pub fn reload(&self, env_filter: EnvFilter) -> Result<(), reload::Error> {
+        self.0.reload(env_filter)
+    }
 }
 
 /// Register a subscriber as global default to process span data.
@@ -37,3 +95,15 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger.");
     set_global_default(subscriber).expect("Failed to set subscriber.");
 }
+
+/// Write the logs of the application with the given name to stdout in the given format, keeping
+/// the events that pass the filter unless `RUST_LOG` is set. The returned handle changes the
+/// filter later on, e.g. when the configuration is reloaded.
+///
+/// It should only be called once!
+pub fn init(name: &str, env_filter: &str, format: LogFormat) -> FilterHandle {
+    let (subscriber, filter) =
+        get_reloadable_subscriber(name.into(), env_filter.into(), format, std::io::stdout);
+    init_subscriber(subscriber);
+    filter
+}
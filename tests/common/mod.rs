@@ -0,0 +1,138 @@
+//! Helpers that are shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use bitcask::{
+    net::{self, connection::Connection, protocol::ProtocolKind},
+    storage::{
+        bitcask::{Bitcask, Config},
+        databases::Databases,
+    },
+};
+use tempfile::TempDir;
+use tokio::{net::TcpStream, sync::watch, task::JoinHandle};
+
+/// A server that runs in the background until it's shut down.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    /// The address of the HTTP gateway, if the server runs with one.
+    pub gateway_addr: Option<SocketAddr>,
+    shutdown: watch::Sender<()>,
+    task: JoinHandle<()>,
+    _storage: Bitcask,
+    _tenant: Bitcask,
+    _dir: TempDir,
+    _tenant_dir: TempDir,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Start a server whose configuration is changed by `configure` before it's started.
+    pub async fn start_with<F>(configure: F) -> Self
+    where
+        F: FnOnce(&mut net::Config),
+    {
+        Self::start_inner(configure, false).await
+    }
+
+    /// Start a server together with an HTTP gateway that runs alongside it.
+    pub async fn start_with_gateway<F>(configure: F) -> Self
+    where
+        F: FnOnce(&mut net::Config),
+    {
+        Self::start_inner(configure, true).await
+    }
+
+    async fn start_inner<F>(configure: F, with_gateway: bool) -> Self
+    where
+        F: FnOnce(&mut net::Config),
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Config::default()
+            .path(dir.path())
+            .ordered_keys(true)
+            .to_owned()
+            .open()
+            .unwrap();
+        // A database that clients can select, which only has room for a single key
+        let tenant_dir = tempfile::tempdir().unwrap();
+        let tenant = Config::default()
+            .path(tenant_dir.path())
+            .max_keys(1)
+            .to_owned()
+            .open()
+            .unwrap();
+        let databases =
+            Databases::new(storage.get_handle()).with_database("tenant", tenant.get_handle());
+        let mut conf = net::Config {
+            host: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            ..net::Config::default()
+        };
+        configure(&mut conf);
+        let (shutdown, _) = watch::channel(());
+        let signal = |mut signal: watch::Receiver<()>| async move {
+            let _ = signal.changed().await;
+        };
+        let server = conf
+            .async_server(databases.clone(), signal(shutdown.subscribe()))
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let gateway = if with_gateway {
+            let conf = net::Config {
+                host: Ipv4Addr::LOCALHOST.into(),
+                port: 0,
+                protocol: ProtocolKind::Http,
+                ..net::Config::default()
+            };
+            let gateway = conf
+                .async_server_alongside(databases, signal(shutdown.subscribe()), &server.state())
+                .await
+                .unwrap();
+            Some(gateway)
+        } else {
+            None
+        };
+        let gateway_addr = gateway.as_ref().map(|g| g.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            match gateway {
+                Some(gateway) => {
+                    tokio::join!(server.run(), gateway.run());
+                }
+                None => server.run().await,
+            }
+        });
+        Self {
+            addr,
+            gateway_addr,
+            shutdown,
+            task,
+            _storage: storage,
+            _tenant: tenant,
+            _dir: dir,
+            _tenant_dir: tenant_dir,
+        }
+    }
+
+    pub async fn connect(&self) -> Connection {
+        Connection::new(TcpStream::connect(self.addr).await.unwrap())
+    }
+
+    /// Signal the server to shut down and wait until all of its connections are closed.
+    pub async fn shutdown(self) {
+        self.shutdown.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), self.task)
+            .await
+            .expect("the server must shut down")
+            .unwrap();
+    }
+}
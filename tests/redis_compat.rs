@@ -0,0 +1,260 @@
+//! Compatibility tests that run the supported commands through the `redis` client and
+//! `redis-cli`, catching RESP framing and reply-shape regressions that our own client wouldn't
+//! notice since it shares the frame codec with the server.
+//!
+//! Run with `cargo test --features redis-compat --test redis_compat`. The `redis-cli` test is
+//! skipped when the binary can't be found.
+
+#![cfg(feature = "redis-compat")]
+
+mod common;
+
+use std::{io, num::NonZeroUsize};
+
+use redis::{aio::Connection, AsyncCommands, RedisResult, Value};
+
+use common::TestServer;
+
+async fn connect(server: &TestServer) -> Connection {
+    redis::Client::open(format!("redis://{}", server.addr))
+        .unwrap()
+        .get_async_connection()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn string_commands() {
+    let server = TestServer::start().await;
+    let mut conn = connect(&server).await;
+
+    let _: () = conn.set("k", "v").await.unwrap();
+    let value: Option<String> = conn.get("k").await.unwrap();
+    assert_eq!(Some("v".to_string()), value);
+    let value: Option<String> = conn.get("missing").await.unwrap();
+    assert_eq!(None, value);
+
+    let reply: Option<String> = redis::cmd("SET")
+        .arg("k")
+        .arg("w")
+        .arg("NX")
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(None, reply);
+    let reply: Option<String> = redis::cmd("SET")
+        .arg("k")
+        .arg("w")
+        .arg("GET")
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(Some("v".to_string()), reply);
+    let reply: Value = redis::cmd("SET")
+        .arg("t")
+        .arg("1")
+        .arg("EX")
+        .arg(60)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(Value::Okay, reply);
+
+    let copied: bool = redis::cmd("COPY")
+        .arg("k")
+        .arg("k2")
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert!(copied);
+    let _: () = conn.rename("k2", "k3").await.unwrap();
+    let renamed: bool = conn.rename_nx("k", "k3").await.unwrap();
+    assert!(!renamed);
+
+    let removed: i64 = conn.del(&["k", "k3", "t", "missing"]).await.unwrap();
+    assert_eq!(3, removed);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn list_commands() {
+    let server = TestServer::start().await;
+    let mut conn = connect(&server).await;
+
+    let len: usize = conn.rpush("l", &["a", "b", "c", "a"]).await.unwrap();
+    assert_eq!(4, len);
+    let positions: Vec<usize> = redis::cmd("LPOS")
+        .arg("l")
+        .arg("a")
+        .arg("COUNT")
+        .arg(0)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(vec![0, 3], positions);
+    let len: usize = conn.linsert_before("l", "c", "x").await.unwrap();
+    assert_eq!(5, len);
+    let _: () = conn.lset("l", -1, "z").await.unwrap();
+    let removed: usize = conn.lrem("l", 0, "x").await.unwrap();
+    assert_eq!(1, removed);
+    let _: () = conn.ltrim("l", 0, 2).await.unwrap();
+    let len: usize = conn.llen("l").await.unwrap();
+    assert_eq!(3, len);
+
+    let popped: Option<String> = conn.lpop("l", None).await.unwrap();
+    assert_eq!(Some("a".to_string()), popped);
+    let popped: Vec<String> = conn.rpop("l", NonZeroUsize::new(2)).await.unwrap();
+    assert_eq!(vec!["c", "b"], popped);
+    let popped: Option<String> = conn.lpop("l", None).await.unwrap();
+    assert_eq!(None, popped);
+
+    let _: () = conn.lpush("m", &["1", "2"]).await.unwrap();
+    let popped: Option<(String, Vec<String>)> = redis::cmd("LMPOP")
+        .arg(2)
+        .arg(&["l", "m"])
+        .arg("RIGHT")
+        .arg("COUNT")
+        .arg(5)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(("m".to_string(), vec!["1".to_string(), "2".to_string()])),
+        popped
+    );
+    let popped: Option<(String, String)> = redis::cmd("BLPOP")
+        .arg("m")
+        .arg(0.01)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(None, popped);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn stream_commands() {
+    let server = TestServer::start().await;
+    let mut conn = connect(&server).await;
+
+    let id: String = conn.xadd("s", "1-1", &[("f", "v")]).output().await.unwrap();
+    assert_eq!("1-1", id);
+    let id: String = conn.xadd("s", "1-2", &[("g", "w")]).output().await.unwrap();
+    assert_eq!("1-2", id);
+    let entries: Vec<(String, Vec<String>)> = conn.xrange_all("s").await.unwrap();
+    assert_eq!(
+        vec![
+            ("1-1".to_string(), vec!["f".to_string(), "v".to_string()]),
+            ("1-2".to_string(), vec!["g".to_string(), "w".to_string()]),
+        ],
+        entries
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_and_geo_commands() {
+    let server = TestServer::start().await;
+    let mut conn = connect(&server).await;
+
+    let reply: Value = redis::cmd("JSON.SET")
+        .arg("doc")
+        .arg("$")
+        .arg(r#"{"a":[1,2]}"#)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(Value::Okay, reply);
+    let reply: Option<String> = redis::cmd("JSON.GET")
+        .arg("doc")
+        .arg("$.a")
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(Some("[1,2]".to_string()), reply);
+
+    let added: usize = redis::cmd("GEOADD")
+        .arg("Sicily")
+        .arg(&["13.361389", "38.115556", "Palermo"])
+        .arg(&["15.087269", "37.502669", "Catania"])
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(2, added);
+    let members: Vec<String> = redis::cmd("GEOSEARCH")
+        .arg("Sicily")
+        .arg(&["FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "ASC"])
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(vec!["Catania", "Palermo"], members);
+    let members: Vec<(String, String)> = redis::cmd("GEOSEARCH")
+        .arg("Sicily")
+        .arg(&["FROMMEMBER", "Palermo", "BYRADIUS", "100", "km"])
+        .arg("WITHDIST")
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(vec![("Palermo".to_string(), "0.0000".to_string())], members);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn error_replies() {
+    let server = TestServer::start().await;
+    let mut conn = connect(&server).await;
+
+    let _: () = conn.set("s", "v").await.unwrap();
+    let result: RedisResult<usize> = conn.llen("s").await;
+    assert_eq!(Some("WRONGTYPE"), result.unwrap_err().code());
+    let result: RedisResult<()> = conn.lset("missing", 0, "v").await;
+    assert_eq!(Some("ERR"), result.unwrap_err().code());
+
+    // The connection is still usable after an error reply
+    let value: Option<String> = conn.get("s").await.unwrap();
+    assert_eq!(Some("v".to_string()), value);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn redis_cli() {
+    let server = TestServer::start().await;
+    let cli = |args: &[&str]| {
+        let mut cmd = tokio::process::Command::new("redis-cli");
+        cmd.arg("-h")
+            .arg(server.addr.ip().to_string())
+            .arg("-p")
+            .arg(server.addr.port().to_string())
+            .args(args);
+        cmd
+    };
+
+    let output = match cli(&["SET", "k", "v"]).output().await {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("redis-cli was not found, skipping");
+            server.shutdown().await;
+            return;
+        }
+        Err(e) => panic!("could not run redis-cli: {e}"),
+    };
+    assert_eq!("OK\n", String::from_utf8_lossy(&output.stdout));
+    let output = cli(&["GET", "k"]).output().await.unwrap();
+    assert_eq!("v\n", String::from_utf8_lossy(&output.stdout));
+    let output = cli(&["RPUSH", "l", "a", "b"]).output().await.unwrap();
+    assert_eq!("2\n", String::from_utf8_lossy(&output.stdout));
+    let output = cli(&["LPOP", "l", "2"]).output().await.unwrap();
+    assert_eq!("a\nb\n", String::from_utf8_lossy(&output.stdout));
+
+    server.shutdown().await;
+}
@@ -0,0 +1,980 @@
+//! Protocol-level tests that run the supported commands against a server that listens on an
+//! ephemeral port, checking the exact replies that the clients receive.
+
+#![cfg(feature = "server")]
+
+mod common;
+
+use std::{num::NonZeroU32, ops::Bound, time::Duration};
+
+use bitcask::net::{connection::Connection, frame::Frame, Client};
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use common::TestServer;
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Send a command and wait for its reply.
+async fn call(conn: &mut Connection, args: &[&str]) -> Frame {
+    let args = args.iter().copied().map(bulk).collect();
+    conn.write_frame(&Frame::Array(args)).await.unwrap();
+    conn.read_frame()
+        .await
+        .unwrap()
+        .expect("the server must reply")
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+fn bulks(strings: &[&str]) -> Frame {
+    Frame::Array(strings.iter().copied().map(bulk).collect())
+}
+
+fn ok() -> Frame {
+    Frame::SimpleString("OK".to_string())
+}
+
+fn error(message: &str) -> Frame {
+    Frame::Error(message.to_string())
+}
+
+#[tokio::test]
+async fn client_commands() {
+    let server = TestServer::start().await;
+    let mut client = Client::connect(server.addr).await.unwrap();
+
+    client.set("a".into(), "1".into()).await.unwrap();
+    client.set("b".into(), "2".into()).await.unwrap();
+    assert_eq!(
+        Some(Bytes::from("1")),
+        client.get("a".into()).await.unwrap()
+    );
+    assert_eq!(
+        vec![Bytes::from("a"), Bytes::from("b")],
+        client
+            .scan_range(Bound::Unbounded, Bound::Unbounded, None)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        vec![Bytes::from("b")],
+        client
+            .scan_range(Bound::Excluded("a".into()), Bound::Unbounded, Some(1))
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        2,
+        client
+            .del(vec!["a".into(), "b".into(), "c".into()])
+            .await
+            .unwrap()
+    );
+    assert_eq!(None, client.get("a".into()).await.unwrap());
+
+    drop(client);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn string_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "k", "v"]).await);
+    assert_eq!(bulk("v"), call(&mut conn, &["GET", "k"]).await);
+    assert_eq!(Frame::Null, call(&mut conn, &["SET", "k", "w", "NX"]).await);
+    assert_eq!(
+        bulk("v"),
+        call(&mut conn, &["SET", "k", "w", "XX", "GET"]).await
+    );
+    assert_eq!(
+        ok(),
+        call(&mut conn, &["SET", "t", "x", "PX", "100000"]).await
+    );
+    assert_eq!(bulk("x"), call(&mut conn, &["GETEX", "t", "PERSIST"]).await);
+    assert_eq!(Frame::Null, call(&mut conn, &["GETEX", "missing"]).await);
+
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["COPY", "k", "k2"]).await
+    );
+    assert_eq!(
+        Frame::Integer(0),
+        call(&mut conn, &["COPY", "k", "k2"]).await
+    );
+    assert_eq!(
+        error("ERR source and destination objects are the same"),
+        call(&mut conn, &["COPY", "k", "k"]).await
+    );
+    assert_eq!(ok(), call(&mut conn, &["RENAME", "k2", "k3"]).await);
+    assert_eq!(
+        Frame::Integer(0),
+        call(&mut conn, &["RENAMENX", "k", "k3"]).await
+    );
+    assert_eq!(
+        error("ERR no such key"),
+        call(&mut conn, &["RENAME", "k2", "k4"]).await
+    );
+    assert!(matches!(
+        call(&mut conn, &["OBJECT", "IDLETIME", "k3"]).await,
+        Frame::Integer(_)
+    ));
+    assert_eq!(
+        Frame::Null,
+        call(&mut conn, &["OBJECT", "IDLETIME", "k2"]).await
+    );
+    // Access frequencies are only tracked when keys are evicted
+    assert!(matches!(
+        call(&mut conn, &["OBJECT", "FREQ", "k3"]).await,
+        Frame::Error(_)
+    ));
+    // Hot keys are not tracked by default, and unknown sections are left out
+    assert_eq!(
+        bulk("# Hotkeys\r\n"),
+        call(&mut conn, &["INFO", "hotkeys"]).await
+    );
+    assert_eq!(bulk(""), call(&mut conn, &["INFO", "unknown"]).await);
+    assert_eq!(
+        bulk("# Listener\r\naccept_failures:0\r\nrejected_connections:0\r\nlistener_down:0\r\n"),
+        call(&mut conn, &["INFO", "listener"]).await
+    );
+
+    assert_eq!(
+        Frame::Array(vec![ok(), Frame::Integer(1), Frame::Integer(0)]),
+        call(
+            &mut conn,
+            &["BATCH", "SET", "b", "1", "DEL", "k3", "DEL", "k3"]
+        )
+        .await
+    );
+    assert_eq!(
+        bulks(&["b", "k", "t"]),
+        call(&mut conn, &["SCANRANGE", "-", "+"]).await
+    );
+    assert_eq!(
+        Frame::Integer(3),
+        call(&mut conn, &["DEL", "b", "k", "t", "missing"]).await
+    );
+    assert_eq!(
+        error("ERR audit log is disabled"),
+        call(&mut conn, &["AUDIT"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn list_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        Frame::Integer(4),
+        call(&mut conn, &["RPUSH", "l", "a", "b", "c", "a"]).await
+    );
+    assert_eq!(
+        Frame::Array(vec![Frame::Integer(0), Frame::Integer(3)]),
+        call(&mut conn, &["LPOS", "l", "a", "COUNT", "0"]).await
+    );
+    assert_eq!(
+        Frame::Integer(5),
+        call(&mut conn, &["LINSERT", "l", "BEFORE", "c", "x"]).await
+    );
+    assert_eq!(ok(), call(&mut conn, &["LSET", "l", "-1", "z"]).await);
+    assert_eq!(
+        error("ERR index out of range"),
+        call(&mut conn, &["LSET", "l", "9", "z"]).await
+    );
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["LREM", "l", "0", "x"]).await
+    );
+    assert_eq!(ok(), call(&mut conn, &["LTRIM", "l", "0", "2"]).await);
+    assert_eq!(Frame::Integer(3), call(&mut conn, &["LLEN", "l"]).await);
+    assert_eq!(bulk("a"), call(&mut conn, &["LPOP", "l"]).await);
+    assert_eq!(
+        bulks(&["c", "b"]),
+        call(&mut conn, &["RPOP", "l", "2"]).await
+    );
+    assert_eq!(Frame::Integer(0), call(&mut conn, &["LLEN", "l"]).await);
+    assert_eq!(Frame::Null, call(&mut conn, &["LPOP", "l"]).await);
+
+    assert_eq!(
+        Frame::Integer(2),
+        call(&mut conn, &["LPUSH", "m", "1", "2"]).await
+    );
+    assert_eq!(
+        Frame::Array(vec![bulk("m"), bulks(&["1", "2"])]),
+        call(&mut conn, &["LMPOP", "2", "l", "m", "RIGHT", "COUNT", "5"]).await
+    );
+    assert_eq!(
+        Frame::Null,
+        call(&mut conn, &["LMPOP", "2", "l", "m", "LEFT"]).await
+    );
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["RPUSH", "q", "job"]).await
+    );
+    assert_eq!(
+        bulks(&["q", "job"]),
+        call(&mut conn, &["BLPOP", "l", "q", "0"]).await
+    );
+    assert_eq!(Frame::Null, call(&mut conn, &["BRPOP", "q", "0.01"]).await);
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "s", "v"]).await);
+    assert_eq!(error(WRONG_TYPE), call(&mut conn, &["LLEN", "s"]).await);
+    assert_eq!(
+        error(WRONG_TYPE),
+        call(&mut conn, &["RPUSH", "s", "a"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn set_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        Frame::Integer(3),
+        call(&mut conn, &["SADD", "s1", "a", "b", "c", "a"]).await
+    );
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["SADD", "s1", "b", "d"]).await
+    );
+    assert_eq!(
+        Frame::Integer(3),
+        call(&mut conn, &["SADD", "s2", "b", "c", "d"]).await
+    );
+    assert_eq!(
+        Frame::Integer(3),
+        call(&mut conn, &["SINTERCARD", "2", "s1", "s2"]).await
+    );
+    assert_eq!(
+        Frame::Integer(2),
+        call(&mut conn, &["SINTERCARD", "2", "s1", "s2", "LIMIT", "2"]).await
+    );
+    assert_eq!(
+        Frame::Integer(0),
+        call(&mut conn, &["SINTERCARD", "2", "s1", "missing"]).await
+    );
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "str", "v"]).await);
+    assert_eq!(
+        error(WRONG_TYPE),
+        call(&mut conn, &["SADD", "str", "a"]).await
+    );
+    assert_eq!(
+        error(WRONG_TYPE),
+        call(&mut conn, &["SINTERCARD", "2", "s1", "str"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn blocked_pop_is_woken_up_by_a_push() {
+    let server = TestServer::start().await;
+    let mut blocked = server.connect().await;
+    let mut conn = server.connect().await;
+
+    blocked
+        .write_frame(&bulks(&["BLPOP", "q", "0"]))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["RPUSH", "q", "job"]).await
+    );
+    let reply = tokio::time::timeout(Duration::from_secs(5), blocked.read_frame())
+        .await
+        .expect("the pop must be woken up")
+        .unwrap();
+    assert_eq!(Some(bulks(&["q", "job"])), reply);
+
+    drop((blocked, conn));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn stream_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        bulk("1-1"),
+        call(&mut conn, &["XADD", "s", "1-1", "f", "v"]).await
+    );
+    assert_eq!(
+        bulk("1-2"),
+        call(&mut conn, &["XADD", "s", "1-2", "g", "w"]).await
+    );
+    let first = || Frame::Array(vec![bulk("1-1"), bulks(&["f", "v"])]);
+    let second = || Frame::Array(vec![bulk("1-2"), bulks(&["g", "w"])]);
+    assert_eq!(
+        Frame::Array(vec![first(), second()]),
+        call(&mut conn, &["XRANGE", "s", "-", "+"]).await
+    );
+    assert_eq!(
+        Frame::Array(vec![first()]),
+        call(&mut conn, &["XRANGE", "s", "-", "+", "COUNT", "1"]).await
+    );
+    assert_eq!(
+        Frame::Array(vec![Frame::Array(vec![
+            bulk("s"),
+            Frame::Array(vec![second()])
+        ])]),
+        call(&mut conn, &["XREAD", "STREAMS", "s", "1-1"]).await
+    );
+    assert_eq!(
+        Frame::Null,
+        call(&mut conn, &["XREAD", "STREAMS", "s", "1-2"]).await
+    );
+
+    // Overwriting the stream removes its chunks, so only the key is left
+    assert_eq!(ok(), call(&mut conn, &["SET", "s", "v"]).await);
+    let Frame::Array(files) = call(&mut conn, &["DATAFILES"]).await else {
+        panic!("DATAFILES must reply with an array");
+    };
+    let live_keys: i64 = files
+        .iter()
+        .map(|file| match file {
+            Frame::Array(fields) => match fields[5] {
+                Frame::Integer(n) => n,
+                _ => panic!("live_keys must be an integer"),
+            },
+            _ => panic!("DATAFILES must describe each file with an array"),
+        })
+        .sum();
+    assert_eq!(1, live_keys);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        ok(),
+        call(&mut conn, &["JSON.SET", "doc", "$", r#"{"a":[1,2]}"#]).await
+    );
+    assert_eq!(
+        ok(),
+        call(&mut conn, &["JSON.SET", "doc", "$.b", r#""x""#]).await
+    );
+    assert_eq!(
+        Frame::Null,
+        call(&mut conn, &["JSON.SET", "doc", "$.b", "1", "NX"]).await
+    );
+    assert_eq!(
+        bulk("[1,2]"),
+        call(&mut conn, &["JSON.GET", "doc", "$.a"]).await
+    );
+    assert_eq!(
+        bulk("2"),
+        call(&mut conn, &["JSON.GET", "doc", "$.a[1]"]).await
+    );
+    assert_eq!(
+        Frame::Null,
+        call(&mut conn, &["JSON.GET", "doc", "$.c"]).await
+    );
+    assert_eq!(
+        error("ERR invalid JSON path"),
+        call(&mut conn, &["JSON.GET", "doc", "$.a[x]"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn geo_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        Frame::Integer(2),
+        call(
+            &mut conn,
+            &[
+                "GEOADD",
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ]
+        )
+        .await
+    );
+    assert_eq!(
+        bulks(&["Catania", "Palermo"]),
+        call(
+            &mut conn,
+            &[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "200",
+                "km",
+                "ASC"
+            ]
+        )
+        .await
+    );
+    assert_eq!(
+        Frame::Array(vec![
+            Frame::Array(vec![bulk("Palermo"), bulk("190.4424")]),
+            Frame::Array(vec![bulk("Catania"), bulk("56.4413")]),
+        ]),
+        call(
+            &mut conn,
+            &[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYBOX",
+                "400",
+                "400",
+                "km",
+                "DESC",
+                "WITHDIST"
+            ]
+        )
+        .await
+    );
+    assert_eq!(
+        Frame::Array(vec![
+            bulk("Sicily"),
+            Frame::Array(vec![bulks(&["Catania", "3479447370796909"])]),
+        ]),
+        call(&mut conn, &["ZMPOP", "1", "Sicily", "MAX"]).await
+    );
+    assert_eq!(
+        bulks(&["Palermo"]),
+        call(
+            &mut conn,
+            &[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMMEMBER",
+                "Palermo",
+                "BYRADIUS",
+                "1",
+                "m"
+            ]
+        )
+        .await
+    );
+    assert_eq!(
+        error("ERR could not decode requested zset member"),
+        call(
+            &mut conn,
+            &[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMMEMBER",
+                "Catania",
+                "BYRADIUS",
+                "1",
+                "m"
+            ]
+        )
+        .await
+    );
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "s", "v"]).await);
+    assert_eq!(
+        error(WRONG_TYPE),
+        call(&mut conn, &["GEOADD", "s", "15", "37", "m"]).await
+    );
+    assert_eq!(
+        error(WRONG_TYPE),
+        call(&mut conn, &["ZMPOP", "1", "s", "MIN"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[cfg(feature = "scripting")]
+#[tokio::test]
+async fn eval_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        ok(),
+        call(
+            &mut conn,
+            &[
+                "EVAL",
+                "return redis.call('SET', KEYS[1], ARGV[1])",
+                "1",
+                "k",
+                "v"
+            ]
+        )
+        .await
+    );
+    assert_eq!(bulk("v"), call(&mut conn, &["GET", "k"]).await);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn transaction_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(ok(), call(&mut conn, &["MULTI"]).await);
+    assert_eq!(
+        Frame::SimpleString("QUEUED".to_string()),
+        call(&mut conn, &["SET", "k", "v"]).await
+    );
+    assert_eq!(
+        Frame::SimpleString("QUEUED".to_string()),
+        call(&mut conn, &["GET", "k"]).await
+    );
+    assert_eq!(
+        Frame::Array(vec![ok(), bulk("v")]),
+        call(&mut conn, &["EXEC"]).await
+    );
+    assert_eq!(
+        error("ERR EXEC without MULTI"),
+        call(&mut conn, &["EXEC"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn select_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+    let mut other = server.connect().await;
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "a", "default"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SELECT", "tenant"]).await);
+    assert_eq!(Frame::Null, call(&mut conn, &["GET", "a"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SET", "a", "tenant"]).await);
+    assert_eq!(bulk("tenant"), call(&mut conn, &["GET", "a"]).await);
+    // The quotas of the selected database apply
+    assert_eq!(
+        error("OOM Quota exceeded - max keys"),
+        call(&mut conn, &["SET", "b", "tenant"]).await
+    );
+    assert_eq!(
+        error("ERR no such database"),
+        call(&mut conn, &["SELECT", "missing"]).await
+    );
+    assert_eq!(bulk("tenant"), call(&mut conn, &["GET", "a"]).await);
+
+    // Other connections keep using the default database
+    assert_eq!(bulk("default"), call(&mut other, &["GET", "a"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SELECT", "default"]).await);
+    assert_eq!(bulk("default"), call(&mut conn, &["GET", "a"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SELECT", "tenant"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SELECT", "0"]).await);
+    assert_eq!(bulk("default"), call(&mut conn, &["GET", "a"]).await);
+
+    // RESET switches the connection back to the default database
+    assert_eq!(ok(), call(&mut conn, &["SELECT", "tenant"]).await);
+    assert_eq!(
+        Frame::SimpleString("RESET".to_string()),
+        call(&mut conn, &["RESET"]).await
+    );
+    assert_eq!(bulk("default"), call(&mut conn, &["GET", "a"]).await);
+
+    drop((conn, other));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn client_pause_commands() {
+    let server = TestServer::start().await;
+    let mut admin = server.connect().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        ok(),
+        call(&mut admin, &["CLIENT", "PAUSE", "60000", "WRITE"]).await
+    );
+    // Reads are still served while the writes are held
+    assert_eq!(Frame::Null, call(&mut conn, &["GET", "a"]).await);
+    conn.write_frame(&bulks(&["SET", "a", "1"])).await.unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), conn.read_frame())
+            .await
+            .is_err()
+    );
+
+    assert_eq!(ok(), call(&mut admin, &["CLIENT", "UNPAUSE"]).await);
+    assert_eq!(Some(ok()), conn.read_frame().await.unwrap());
+    assert_eq!(bulk("1"), call(&mut conn, &["GET", "a"]).await);
+
+    drop((admin, conn));
+    server.shutdown().await;
+}
+
+/// Send a PUT request to the gateway without waiting for the response.
+async fn gateway_put(gateway: &mut TcpStream, key: &str, value: &str) {
+    let body = format!(r#"{{"value":"{value}"}}"#);
+    let request = format!(
+        "PUT /keys/{key} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    gateway.write_all(request.as_bytes()).await.unwrap();
+}
+
+/// Read the status line of the next response from the gateway.
+async fn gateway_status(gateway: &mut TcpStream) -> String {
+    let mut buf = [0; 1024];
+    let n = gateway.read(&mut buf).await.unwrap();
+    let response = String::from_utf8_lossy(&buf[..n]);
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn client_pause_holds_the_gateway_writes() {
+    let server = TestServer::start_with_gateway(|_| {}).await;
+    let mut admin = server.connect().await;
+    let mut gateway = TcpStream::connect(server.gateway_addr.unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ok(),
+        call(&mut admin, &["CLIENT", "PAUSE", "60000", "WRITE"]).await
+    );
+    gateway_put(&mut gateway, "a", "1").await;
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), gateway_status(&mut gateway))
+            .await
+            .is_err()
+    );
+    assert_eq!(Frame::Null, call(&mut admin, &["GET", "a"]).await);
+
+    assert_eq!(ok(), call(&mut admin, &["CLIENT", "UNPAUSE"]).await);
+    assert_eq!(
+        "HTTP/1.1 204 No Content",
+        gateway_status(&mut gateway).await
+    );
+    assert_eq!(bulk("1"), call(&mut admin, &["GET", "a"]).await);
+
+    drop((admin, gateway));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn gateway_requests_share_the_rate_limits_and_the_audit_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let audit_log = dir.path().join("audit.log");
+    let server = TestServer::start_with_gateway(|conf| {
+        conf.audit_log = Some(audit_log);
+        conf.rate_limit.per_client_writes = NonZeroU32::new(1);
+    })
+    .await;
+    let mut conn = server.connect().await;
+    let mut gateway = TcpStream::connect(server.gateway_addr.unwrap())
+        .await
+        .unwrap();
+
+    // The write through the gateway takes the only token of the client
+    gateway_put(&mut gateway, "a", "1").await;
+    assert_eq!(
+        "HTTP/1.1 204 No Content",
+        gateway_status(&mut gateway).await
+    );
+    assert_eq!(
+        error("RATELIMITED too many requests"),
+        call(&mut conn, &["SET", "b", "1"]).await
+    );
+    gateway_put(&mut gateway, "c", "1").await;
+    assert_eq!(
+        "HTTP/1.1 429 Too Many Requests",
+        gateway_status(&mut gateway).await
+    );
+
+    let Frame::Array(records) = call(&mut conn, &["AUDIT"]).await else {
+        panic!("AUDIT must reply with an array");
+    };
+    assert_eq!(1, records.len());
+    let Frame::Array(fields) = &records[0] else {
+        panic!("audit records must be arrays");
+    };
+    assert_eq!(bulk("PUT"), fields[2]);
+    assert_eq!(bulks(&["a"]), fields[3]);
+
+    drop((conn, gateway));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn delprefix_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    for key in ["user:1", "user:2", "users"] {
+        assert_eq!(ok(), call(&mut conn, &["SET", key, "1"]).await);
+    }
+    assert_eq!(
+        Frame::Integer(2),
+        call(&mut conn, &["DELPREFIX", "user:"]).await
+    );
+    assert_eq!(Frame::Null, call(&mut conn, &["GET", "user:1"]).await);
+    assert_eq!(bulk("1"), call(&mut conn, &["GET", "users"]).await);
+    assert_eq!(
+        Frame::Error("ERR prefix must not be empty".to_string()),
+        call(&mut conn, &["DELPREFIX", ""]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn unlink_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    for key in ["a", "b"] {
+        assert_eq!(ok(), call(&mut conn, &["SET", key, "1"]).await);
+    }
+    assert_eq!(
+        Frame::Integer(2),
+        call(&mut conn, &["UNLINK", "a", "b", "c"]).await
+    );
+    assert_eq!(Frame::Null, call(&mut conn, &["GET", "a"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SET", "a", "2"]).await);
+    assert_eq!(bulk("2"), call(&mut conn, &["GET", "a"]).await);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn datafiles_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(ok(), call(&mut conn, &["SET", "a", "1"]).await);
+    assert_eq!(ok(), call(&mut conn, &["SET", "a", "2"]).await);
+    let Frame::Array(files) = call(&mut conn, &["DATAFILES"]).await else {
+        panic!("DATAFILES must reply with an array");
+    };
+    let Some(Frame::Array(fields)) = files.last() else {
+        panic!("DATAFILES must list the active file");
+    };
+    assert_eq!(16, fields.len());
+    assert_eq!(bulk("live_keys"), fields[4]);
+    assert_eq!(Frame::Integer(1), fields[5]);
+    assert_eq!(Frame::Integer(1), fields[7]);
+    assert_eq!(bulk("0.5000"), fields[11]);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn proxy_protocol_gives_the_client_address() {
+    let server = TestServer::start_with(|conf| {
+        conf.proxy_protocol = true;
+        conf.rate_limit.per_client = NonZeroU32::new(1);
+    })
+    .await;
+    let connect_as = |client: [u8; 4]| async move {
+        let mut socket = TcpStream::connect(server.addr).await.unwrap();
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+        header.extend_from_slice(&client);
+        header.extend_from_slice(&[127, 0, 0, 1, 0x10, 0x92, 0x18, 0xeb]);
+        socket.write_all(&header).await.unwrap();
+        Connection::new(socket)
+    };
+
+    // Each proxied client gets its own rate limit
+    let mut first = connect_as([10, 0, 0, 1]).await;
+    let mut second = connect_as([10, 0, 0, 2]).await;
+    assert_eq!(ok(), call(&mut first, &["SET", "a", "1"]).await);
+    assert_eq!(
+        error("RATELIMITED too many requests"),
+        call(&mut first, &["SET", "a", "2"]).await
+    );
+    assert_eq!(ok(), call(&mut second, &["SET", "b", "1"]).await);
+
+    // Connections without a header are closed
+    let mut direct = server.connect().await;
+    direct
+        .write_frame(&Frame::Array(vec![bulk("GET"), bulk("a")]))
+        .await
+        .unwrap();
+    assert!(!matches!(direct.read_frame().await, Ok(Some(_))));
+
+    drop((first, second, direct));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_rejected() {
+    let server = TestServer::start_with(|conf| {
+        conf.max_connections = 1;
+        conf.reject_when_full = true;
+    })
+    .await;
+    let mut first = server.connect().await;
+    assert_eq!(ok(), call(&mut first, &["SET", "a", "1"]).await);
+
+    // The second client is told why it can't connect, then closed
+    let mut second = server.connect().await;
+    assert_eq!(
+        Some(error("ERR max number of clients reached")),
+        second.read_frame().await.unwrap()
+    );
+    assert!(!matches!(second.read_frame().await, Ok(Some(_))));
+
+    let info = call(&mut first, &["INFO", "listener"]).await;
+    assert_eq!(
+        bulk("# Listener\r\naccept_failures:0\r\nrejected_connections:1\r\nlistener_down:0\r\n"),
+        info
+    );
+
+    // The permit is given back once the first client leaves
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut third = server.connect().await;
+    assert_eq!(bulk("1"), call(&mut third, &["GET", "a"]).await);
+    assert_eq!(ok(), call(&mut third, &["SET", "b", "1"]).await);
+
+    drop((second, third));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn bgpause_commands() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(ok(), call(&mut conn, &["BGPAUSE"]).await);
+    assert_eq!(
+        bulk("# Maintenance\r\nmaintenance_paused:1\r\n"),
+        call(&mut conn, &["INFO", "maintenance"]).await
+    );
+    assert_eq!(ok(), call(&mut conn, &["BGPAUSE", "OFF"]).await);
+    assert_eq!(
+        bulk("# Maintenance\r\nmaintenance_paused:0\r\n"),
+        call(&mut conn, &["INFO", "maintenance"]).await
+    );
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn pubsub_commands() {
+    let server = TestServer::start().await;
+    let mut subscriber = server.connect().await;
+    let mut monitor = server.connect().await;
+    let mut conn = server.connect().await;
+
+    assert_eq!(
+        Frame::Array(vec![bulk("subscribe"), bulk("news"), Frame::Integer(1)]),
+        call(&mut subscriber, &["SUBSCRIBE", "news"]).await
+    );
+    assert_eq!(ok(), call(&mut monitor, &["MONITOR"]).await);
+    assert_eq!(
+        Frame::Integer(1),
+        call(&mut conn, &["PUBLISH", "news", "hello"]).await
+    );
+    assert_eq!(
+        Frame::Integer(0),
+        call(&mut conn, &["PUBLISH", "sports", "hello"]).await
+    );
+
+    let message = tokio::time::timeout(Duration::from_secs(5), subscriber.read_frame())
+        .await
+        .expect("the message must be pushed")
+        .unwrap();
+    assert_eq!(Some(bulks(&["message", "news", "hello"])), message);
+    let line = tokio::time::timeout(Duration::from_secs(5), monitor.read_frame())
+        .await
+        .expect("the command must be monitored")
+        .unwrap();
+    match line {
+        Some(Frame::SimpleString(line)) => {
+            assert!(line.ends_with(r#" "PUBLISH" "news" "hello""#), "{line}")
+        }
+        other => panic!("unexpected monitor line {other:?}"),
+    }
+
+    // Subscribed clients can only change their subscriptions until they reset
+    assert_eq!(
+        error("ERR only SUBSCRIBE, UNSUBSCRIBE, and RESET are allowed in this context"),
+        call(&mut subscriber, &["GET", "k"]).await
+    );
+    assert_eq!(
+        Frame::SimpleString("RESET".to_string()),
+        call(&mut subscriber, &["RESET"]).await
+    );
+    assert_eq!(Frame::Null, call(&mut subscriber, &["GET", "k"]).await);
+
+    drop((subscriber, monitor, conn));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn invalid_commands_close_the_connection() {
+    let server = TestServer::start().await;
+
+    for args in [&["NOPE"][..], &["GET"], &["SET", "k", "v", "BOGUS"]] {
+        let mut conn = server.connect().await;
+        conn.write_frame(&bulks(args)).await.unwrap();
+        assert_eq!(None, conn.read_frame().await.unwrap(), "{args:?}");
+    }
+
+    // Other connections are still served
+    let mut conn = server.connect().await;
+    assert_eq!(ok(), call(&mut conn, &["SET", "k", "v"]).await);
+
+    drop(conn);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_during_blocking_pop() {
+    let server = TestServer::start().await;
+    let mut conn = server.connect().await;
+
+    conn.write_frame(&bulks(&["BLPOP", "q", "0"]))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    server.shutdown().await;
+
+    // The blocked command is abandoned and the connection is closed without a reply
+    assert_eq!(None, conn.read_frame().await.unwrap());
+}